@@ -0,0 +1,22 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE scheduled_submits (
+    id SERIAL PRIMARY KEY NOT NULL,
+    uuid UUID NOT NULL UNIQUE,
+    package_name VARCHAR NOT NULL,
+    package_version VARCHAR NOT NULL,
+    image VARCHAR NOT NULL,
+    repo_hash VARCHAR NOT NULL,
+    requested_by VARCHAR NOT NULL,
+    requested_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    scheduled_for TIMESTAMP WITH TIME ZONE NOT NULL
+)
@@ -0,0 +1,22 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE dag_cache (
+    id SERIAL PRIMARY KEY NOT NULL,
+    root_name VARCHAR NOT NULL,
+    root_version VARCHAR NOT NULL,
+    image VARCHAR NOT NULL,
+    env_hash VARCHAR NOT NULL,
+    repo_hash VARCHAR NOT NULL,
+    resolution TEXT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL,
+    UNIQUE(root_name, root_version, image, env_hash, repo_hash)
+)
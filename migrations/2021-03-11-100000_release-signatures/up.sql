@@ -0,0 +1,19 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE release_signatures (
+    id SERIAL PRIMARY KEY NOT NULL,
+    release_id INTEGER REFERENCES releases(id) NOT NULL UNIQUE,
+    tool VARCHAR NOT NULL,
+    signing_key VARCHAR NOT NULL,
+    signature TEXT NOT NULL,
+    signed_at TIMESTAMP WITH TIME ZONE NOT NULL
+)
@@ -0,0 +1,20 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE artifact_metadata (
+    id SERIAL PRIMARY KEY NOT NULL,
+    artifact_id INTEGER REFERENCES artifacts(id) NOT NULL,
+    kind VARCHAR NOT NULL,
+    key VARCHAR NOT NULL,
+    value TEXT NOT NULL,
+
+    UNIQUE(artifact_id, key)
+)
@@ -0,0 +1,20 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE checkpoints (
+    id SERIAL PRIMARY KEY NOT NULL,
+    endpoint_id INTEGER REFERENCES endpoints(id) NOT NULL,
+    container_id VARCHAR NOT NULL,
+    job_uuid UUID,
+    name VARCHAR NOT NULL,
+    state VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
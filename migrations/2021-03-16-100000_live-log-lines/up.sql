@@ -0,0 +1,20 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE live_log_lines (
+    id SERIAL PRIMARY KEY NOT NULL,
+    job_uuid UUID NOT NULL,
+    line_number INTEGER NOT NULL,
+    stream VARCHAR NOT NULL,
+    line TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    CONSTRAINT UC_job_uuid_line_number UNIQUE (job_uuid, line_number)
+);
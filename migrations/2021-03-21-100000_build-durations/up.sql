@@ -0,0 +1,31 @@
+--
+-- Copyright (c) 2020-2021 science+computing ag and other contributors
+--
+-- This program and the accompanying materials are made
+-- available under the terms of the Eclipse Public License 2.0
+-- which is available at https://www.eclipse.org/legal/epl-2.0/
+--
+-- SPDX-License-Identifier: EPL-2.0
+--
+
+-- Your SQL goes here
+CREATE TABLE build_durations (
+    id SERIAL PRIMARY KEY NOT NULL,
+    package_id INTEGER REFERENCES packages(id) NOT NULL,
+    image_id INTEGER REFERENCES images(id) NOT NULL,
+    average_seconds DOUBLE PRECISION NOT NULL,
+    samples INTEGER NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    CONSTRAINT UC_build_durations_package_image UNIQUE (package_id, image_id)
+);
+
+-- Start with the plain average of the jobs that were already run
+INSERT INTO build_durations (package_id, image_id, average_seconds, samples, updated_at)
+    SELECT package_id,
+           image_id,
+           AVG(EXTRACT(EPOCH FROM (finished_at - started_at))),
+           COUNT(*),
+           MAX(finished_at)
+    FROM jobs
+    WHERE started_at IS NOT NULL AND finished_at IS NOT NULL
+    GROUP BY package_id, image_id;
@@ -14,6 +14,7 @@ use std::str::FromStr;
 use clap::crate_authors;
 use clap::crate_version;
 use clap::App;
+use clap::AppSettings;
 use clap::Arg;
 use clap::ArgGroup;
 
@@ -26,11 +27,15 @@ pub fn cli<'a>() -> App<'a> {
         .author(crate_authors!())
         .version(crate_version!())
         .about("Generic Build Orchestration System for building linux packages with docker")
+        .setting(AppSettings::AllowExternalSubcommands)
 
         .after_help(indoc::indoc!(r#"
             The following environment variables can be passed to butido:
 
                 RUST_LOG - to enable logging, for exact usage see the rust cookbook
+
+            Other subcommands are run as plugins: the executable "butido-<subcommand>" is searched on
+            PATH, see "butido plugins".
         "#))
 
         .arg(Arg::new("hide_bars")
@@ -107,6 +112,58 @@ pub fn cli<'a>() -> App<'a> {
                 Can also be overriden via environment 'BUTIDO_DATABASE_CONNECTION_TIMEOUT', but this setting has precedence.
             "#))
         )
+        .arg(Arg::new("database_pool_size")
+            .required(false)
+            .multiple(false)
+            .long("db-pool-size")
+            .value_name("SIZE")
+            .about("Override the maximum number of database connections")
+            .long_about(indoc::indoc!(r#"
+                Override the maximum number of database connections set via configuration.
+                Can also be overriden via environment 'BUTIDO_DATABASE_POOL_SIZE', but this setting has precedence.
+            "#))
+        )
+        .arg(Arg::new("log_format")
+            .required(false)
+            .multiple(false)
+            .long("log-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .about("Format of the streamed logs of jobs")
+            .long_about(indoc::indoc!(r#"
+                Format of the logs of jobs that are streamed to stdout while building.
+
+                "text" prints the log lines prefixed with the job they belong to.
+                "json" prints one JSON object per log line (newline-delimited JSON), with the fields "job",
+                "package", "version", "endpoint", "timestamp", "level", "stream" (stdout or stderr) and
+                "line".
+                "json" implies -vv for builds.
+            "#))
+        )
+
+        .subcommand(App::new("config")
+            .version(crate_version!())
+            .about("Inspect the configuration")
+            .subcommand(App::new("show-defaults")
+                .version(crate_version!())
+                .about("Show the default command line arguments from the configuration")
+                .long_about(indoc::indoc!(r#"
+                    Show the default command line arguments from the "defaults" section of the configuration.
+
+                    The defaults of a subcommand are inserted right after its name, unless the flag is given on
+                    the command line already. Nested subcommands are listed with their full path, e.g. "db.jobs".
+                "#))
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+            )
+        )
 
         .subcommand(App::new("generate-completions")
             .version(crate_version!())
@@ -120,6 +177,26 @@ pub fn cli<'a>() -> App<'a> {
             )
         )
 
+        .subcommand(App::new("init")
+            .version(crate_version!())
+            .about("Interactively create a starter configuration and package repository")
+            .long_about(indoc::indoc!(r#"
+                Interactively create a starter configuration and an example package repository.
+
+                The database connection is tested, the store directories are created (with mode 0750) and the docker
+                endpoint is probed before the configuration is written.
+                If the directory is not in a git repository yet, a new one is initialized.
+            "#))
+            .arg(Arg::new("directory")
+                .required(false)
+                .multiple(false)
+                .index(1)
+                .default_value(".")
+                .value_name("DIR")
+                .about("The directory to create the package repository in")
+            )
+        )
+
         .subcommand(App::new("db")
             .version(crate_version!())
             .about("Database CLI interface")
@@ -156,6 +233,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("artifacts")
                 .version(crate_version!())
                 .about("List artifacts from the DB")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -177,6 +255,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("envvars")
                 .version(crate_version!())
                 .about("List envvars from the DB")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -189,6 +268,20 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("images")
                 .version(crate_version!())
                 .about("List images from the DB")
+                .args(output_args())
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+            )
+
+            .subcommand(App::new("queue")
+                .version(crate_version!())
+                .about("List submits which wait for a build window to be started")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -201,6 +294,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("submit")
                 .version(crate_version!())
                 .about("Show details about one specific submit")
+                .args(output_args())
                 .arg(Arg::new("submit")
                     .required(true)
                     .multiple(false)
@@ -209,11 +303,26 @@ pub fn cli<'a>() -> App<'a> {
                     .value_name("SUBMIT")
                     .about("The Submit to show details about")
                 )
+                .arg(Arg::new("drift")
+                    .required(false)
+                    .multiple(false)
+                    .long("drift")
+                    .about("Compare the submit against the current state of the repository")
+                    .long_about(indoc::indoc!(r#"
+                        Re-resolve the root package of the submit against the current repository and report which package
+                        versions, scripts, sources or images differ from what the submit used.
+
+                        Scripts are compared against the scripts stored in the database. Sources are compared against the
+                        package definitions of the commit the submit was built from, which must be available in the local
+                        git repository.
+                    "#))
+                )
             )
 
             .subcommand(App::new("submits")
                 .version(crate_version!())
                 .about("List submits from the DB")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -268,6 +377,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("jobs")
                 .version(crate_version!())
                 .about("List jobs from the DB")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -334,6 +444,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("job")
                 .version(crate_version!())
                 .about("Show a specific job from the DB")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -375,6 +486,21 @@ pub fn cli<'a>() -> App<'a> {
                     .about("Show the environment of the job")
                 )
 
+                .arg(Arg::new("env_diff")
+                    .required(false)
+                    .multiple(false)
+                    .long("env-diff")
+                    .conflicts_with_all(&["show_log", "show_script", "show_env"])
+                    .about("Show how the environment of the job differs from the environment the package declares")
+                    .long_about(indoc::indoc!(r#"
+                        Show how the environment of the job differs from the environment the package declares.
+
+                        The package is loaded from the current state of the repository.
+                        Variables that are compared when searching for artifacts to reuse (all variables, or only the
+                        ones listed in "env_sensitivity" of the package) are marked in the "Reuse" column.
+                    "#))
+                )
+
                 .arg(script_arg_line_numbers())
                 .arg(script_arg_no_line_numbers())
                 .arg(script_arg_highlight())
@@ -395,6 +521,7 @@ pub fn cli<'a>() -> App<'a> {
             .subcommand(App::new("releases")
                 .version(crate_version!())
                 .about("List releases")
+                .args(output_args())
                 .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
@@ -425,6 +552,163 @@ pub fn cli<'a>() -> App<'a> {
                     .about("Only list releases for package PKG")
                 )
             )
+            .subcommand(App::new("flaky")
+                .version(crate_version!())
+                .about("Find packages which alternate between success and failure with identical inputs")
+                .long_about(indoc::indoc!(r#"
+                    Analyze the job history for packages that alternate between success and failure although
+                    they were built with identical inputs: the same cache key or, for jobs without a recorded cache key,
+                    the same package, script and image digest.
+
+                    The flakiest packages are listed first. With --endpoints, the endpoints the jobs of
+                    flaky packages ran on are ranked by their number of failures instead.
+                "#))
+                .args(output_args())
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+
+                .arg(Arg::new("limit")
+                    .required(false)
+                    .multiple(false)
+                    .long("limit")
+                    .short('L')
+                    .takes_value(true)
+                    .value_name("LIMIT")
+                    .validator(parse_usize)
+                    .about("Only list the LIMIT flakiest entries")
+                )
+
+                .arg(Arg::new("package")
+                    .required(false)
+                    .multiple(false)
+                    .long("package")
+                    .short('p')
+                    .takes_value(true)
+                    .value_name("PKG")
+                    .about("Only analyze jobs for package PKG")
+                )
+
+                .arg(Arg::new("endpoints")
+                    .required(false)
+                    .multiple(false)
+                    .long("endpoints")
+                    .takes_value(false)
+                    .about("Rank endpoints instead of packages")
+                )
+            )
+
+            .subcommand(App::new("stats")
+                .version(crate_version!())
+                .about("Show job failure rates per package or per maintainer")
+                .long_about(indoc::indoc!(r#"
+                    Aggregate the job history to the number of jobs, the number of failed jobs and the
+                    failure rate per package.
+
+                    With --by-maintainer, the jobs are aggregated per team maintaining the package when the
+                    job ran instead. Jobs of packages with several maintainers count for each of them.
+                "#))
+                .args(output_args())
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+
+                .arg(Arg::new("by_maintainer")
+                    .required(false)
+                    .multiple(false)
+                    .long("by-maintainer")
+                    .takes_value(false)
+                    .about("Aggregate per maintaining team instead of per package")
+                )
+            )
+
+            .subcommand(App::new("build-times")
+                .version(crate_version!())
+                .about("Show how long the jobs of each package take")
+                .long_about(indoc::indoc!(r#"
+                    Show the average, median, 90th and 99th percentile and maximum duration of the successful jobs
+                    per package, over the last N submits.
+
+                    The slowest packages (by average duration) are listed first.
+                "#))
+                .args(output_args())
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+
+                .arg(Arg::new("submits")
+                    .required(false)
+                    .multiple(false)
+                    .long("submits")
+                    .short('n')
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("50")
+                    .validator(parse_usize)
+                    .about("Consider the jobs of the last N submits")
+                )
+
+                .arg(Arg::new("package")
+                    .required(false)
+                    .multiple(false)
+                    .long("package")
+                    .short('p')
+                    .takes_value(true)
+                    .value_name("PKG")
+                    .about("Only show package PKG")
+                )
+            )
+
+            .subcommand(App::new("durations")
+                .version(crate_version!())
+                .about("Show the build durations the submit estimates are based on")
+                .long_about(indoc::indoc!(r#"
+                    Show the rolling average duration of the successful jobs per package version and image, which
+                    is used to estimate how long a submit takes.
+
+                    The average mostly reflects the last 10 jobs of the package version in the image.
+                "#))
+                .args(output_args())
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+
+                .arg(Arg::new("package")
+                    .required(false)
+                    .multiple(false)
+                    .long("package")
+                    .short('p')
+                    .takes_value(true)
+                    .value_name("PKG")
+                    .about("Only show package PKG")
+                )
+
+                .arg(Arg::new("image")
+                    .required(false)
+                    .multiple(false)
+                    .long("image")
+                    .short('I')
+                    .takes_value(true)
+                    .value_name("IMAGE")
+                    .about("Only show the durations in image IMAGE")
+                )
+            )
         )
 
         .subcommand(App::new("build")
@@ -432,7 +716,7 @@ pub fn cli<'a>() -> App<'a> {
             .about("Build packages in containers")
 
             .arg(Arg::new("package_name")
-                .required(true)
+                .required_unless_present("resume")
                 .multiple(false)
                 .index(1)
                 .value_name("NAME")
@@ -445,6 +729,71 @@ pub fn cli<'a>() -> App<'a> {
                 .about("Exact package version to build (string match)")
             )
 
+            .arg(Arg::new("resume")
+                .required(false)
+                .multiple(false)
+                .long("resume")
+                .takes_value(true)
+                .value_name("SUBMIT")
+                .conflicts_with_all(&["package_name", "package_version", "image", "staging_dir", "env"])
+                .about("Resume a failed submit")
+                .long_about(indoc::indoc!(r#"
+                    Resume a submit that failed.
+
+                    The package, the image, the environment and the staging directory are taken from the submit.
+                    Jobs that already produced artifacts in the staging directory of the submit are not run again, only
+                    the failed and unbuilt jobs are. The new jobs are recorded as part of the resumed submit.
+
+                    The repository must be at the commit the submit was started from.
+                "#))
+            )
+
+            .arg(Arg::new("use-artifacts-from")
+                .required(false)
+                .multiple(false)
+                .long("use-artifacts-from")
+                .takes_value(true)
+                .value_name("SUBMIT")
+                .about("Prefer the artifacts of SUBMIT when reusing artifacts")
+                .long_about(indoc::indoc!(r#"
+                    Prefer the artifacts of SUBMIT when reusing artifacts.
+
+                    If SUBMIT built artifacts that could be reused for a job, only these are used for the job, other
+                    matching artifacts are ignored. This allows staged builds, where a later stage must use exactly the
+                    artifacts of an earlier one.
+                    The artifacts of SUBMIT are taken from its staging directory or the release stores.
+                "#))
+            )
+
+            .arg(Arg::new("only")
+                .required(false)
+                .multiple(false)
+                .long("only")
+                .takes_value(true)
+                .value_name("PKG")
+                .conflicts_with("up_to")
+                .about("Only build the package PKG of the tree")
+                .long_about(indoc::indoc!(r#"
+                    Only build the package PKG of the tree.
+
+                    The dependencies of PKG are not built, existing artifacts are used for them instead. The submit fails if
+                    there are no artifacts for one of the dependencies.
+                "#))
+            )
+
+            .arg(Arg::new("up_to")
+                .required(false)
+                .multiple(false)
+                .long("up-to")
+                .takes_value(true)
+                .value_name("PKG")
+                .about("Only build the part of the tree with the package PKG as root")
+                .long_about(indoc::indoc!(r#"
+                    Only build the part of the tree with the package PKG as root, i.e. PKG and all packages it (transitively)
+                    depends on. The packages depending on PKG are not built.
+                "#))
+            )
+
             .arg(Arg::new("no_verification")
                 .required(false)
                 .multiple(false)
@@ -499,7 +848,7 @@ pub fn cli<'a>() -> App<'a> {
             )
 
             .arg(Arg::new("image")
-                .required(true)
+                .required_unless_present("resume")
                 .multiple(false)
                 .takes_value(true)
                 .value_name("IMAGE NAME")
@@ -521,214 +870,397 @@ pub fn cli<'a>() -> App<'a> {
                     The log of a build is written to `<log_dir>/<build id>.log`.
                 "#))
             )
-        )
 
-        .subcommand(App::new("what-depends")
-            .version(crate_version!())
-            .about("List all packages that depend on a specific package")
-            .arg(Arg::new("package_name")
-                .required(true)
-                .multiple(false)
-                .index(1)
-                .about("The name of the package")
-            )
-            .arg(Arg::new("dependency_type")
+            .arg(Arg::new("arch")
                 .required(false)
                 .multiple(true)
-                .takes_value(true)
-                .short('t')
-                .long("type")
-                .value_name("DEPENDENCY_TYPE")
-                .possible_values(&[
-                    IDENT_DEPENDENCY_TYPE_BUILD,
-                    IDENT_DEPENDENCY_TYPE_RUNTIME,
-                ])
-                .default_values(&[
-                    IDENT_DEPENDENCY_TYPE_BUILD,
-                    IDENT_DEPENDENCY_TYPE_RUNTIME,
-                ])
-                .about("Specify which dependency types are to be checked. By default, all are checked")
-            )
-        )
-        .subcommand(App::new("dependencies-of")
-            .version(crate_version!())
-            .alias("depsof")
-            .about("List the depenendcies of a package")
-            .arg(Arg::new("package_name")
-                .required(true)
-                .multiple(false)
-                .index(1)
-                .value_name("PACKAGE_NAME")
-                .about("The name of the package")
+                .long("arch")
+                .value_name("ARCH")
+                .about("Build the tree for these architectures")
+                .long_about(indoc::indoc!(r#"
+                    Build the tree once for each of these architectures, in one submit.
+
+                    The jobs for an architecture are only scheduled to endpoints which list the architecture in
+                    their `architectures` setting, and only artifacts built for the same architecture are reused.
+                    The artifacts of each architecture are written to a directory named after the architecture
+                    in the staging store.
+                "#))
             )
-            .arg(Arg::new("package_version_constraint")
+
+            .arg(Arg::new("hermetic")
                 .required(false)
                 .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .long("hermetic")
+                .about("Build without network access in the containers")
+                .long_about(indoc::indoc!(r#"
+                    Build all packages in containers without network access (network mode "none").
+
+                    Fails if a package in the tree declares that it needs network (`needs_network = true`).
+                    Only artifacts that were built hermetic are reused, and the resulting artifacts are marked as
+                    hermetic in the database.
+                "#))
             )
-            .arg(Arg::new("dependency_type")
+
+            .arg(Arg::new("offline")
+                .required(false)
+                .multiple(false)
+                .long("offline")
+                .about("Build without network access, only with the sources in the source cache")
+                .long_about(indoc::indoc!(r#"
+                    Build without any network access, e.g. to show that the packages can be built offline.
+
+                    Fails right away if a source of a package in the tree is missing in the source cache, all
+                    missing sources are listed. Implies --hermetic, so the containers do not have network access
+                    either.
+                "#))
+            )
+
+            .arg(Arg::new("reuse-any")
+                .required(false)
+                .multiple(false)
+                .long("reuse-any")
+                .conflicts_with_all(&["reuse-release-only", "reuse-signed-only", "reuse-hermetic-only", "reuse-submitted-by"])
+                .about("Ignore the configured reuse policy for this build")
+                .long_about(indoc::indoc!(r#"
+                    Ignore the `reuse_policy` from the configuration for this build, so that every artifact
+                    matching a job is reused.
+                "#))
+            )
+
+            .arg(Arg::new("force-rebuild")
                 .required(false)
                 .multiple(true)
+                .long("force-rebuild")
                 .takes_value(true)
-                .short('t')
-                .long("type")
-                .value_name("DEPENDENCY_TYPE")
-                .possible_values(&[
-                    IDENT_DEPENDENCY_TYPE_BUILD,
-                    IDENT_DEPENDENCY_TYPE_RUNTIME,
-                ])
-                .default_values(&[
-                    IDENT_DEPENDENCY_TYPE_BUILD,
-                    IDENT_DEPENDENCY_TYPE_RUNTIME,
-                ])
-                .about("Specify which dependency types are to be printed. By default, all are checked")
+                .use_delimiter(true)
+                .value_name("PKG")
+                .conflicts_with("force-rebuild-all")
+                .about("Build the packages PKG even if there are artifacts that could be reused")
+                .long_about(indoc::indoc!(r#"
+                    Build the packages PKG (comma separated) even if there are artifacts that could be reused, e.g. from
+                    the staging store or the release stores.
+
+                    The packages depending on PKG are built as well, because one of their dependencies was built.
+                    The packages must be part of the tree and must not be excluded with --only.
+                "#))
             )
-        )
-        .subcommand(App::new("versions-of")
-            .version(crate_version!())
-            .alias("versions")
-            .about("List the versions of a package")
-            .arg(Arg::new("package_name")
-                .required(true)
+
+            .arg(Arg::new("force-rebuild-all")
+                .required(false)
                 .multiple(false)
-                .index(1)
-                .value_name("PACKAGE_NAME")
-                .about("The name of the package")
+                .long("force-rebuild-all")
+                .about("Build all packages, do not reuse any artifacts")
             )
-        )
-        .subcommand(App::new("env-of")
-            .version(crate_version!())
-            .alias("env")
-            .about("Show the ENV configured for a package")
-            .arg(Arg::new("package_name")
-                .required(true)
+
+            .arg(Arg::new("reuse-release-only")
+                .required(false)
                 .multiple(false)
-                .index(1)
-                .value_name("PACKAGE_NAME")
-                .about("The name of the package")
+                .long("reuse-release-only")
+                .about("Only reuse released artifacts")
             )
-            .arg(Arg::new("package_version_constraint")
-                .required(true)
+
+            .arg(Arg::new("reuse-signed-only")
+                .required(false)
                 .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .long("reuse-signed-only")
+                .about("Only reuse artifacts with a signed release")
             )
-        )
 
-        .subcommand(App::new("find-artifact")
-            .version(crate_version!())
-            .about("Find artifacts for packages")
-            .arg(Arg::new("package_name_regex")
-                .required(true)
+            .arg(Arg::new("reuse-hermetic-only")
+                .required(false)
                 .multiple(false)
-                .index(1)
-                .value_name("REGEX")
-                .about("The regex to match the package name against")
+                .long("reuse-hermetic-only")
+                .about("Only reuse artifacts that were built hermetic")
             )
-            .arg(Arg::new("package_version_constraint")
+
+            .arg(Arg::new("reuse-submitted-by")
                 .required(false)
-                .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .multiple(true)
+                .long("reuse-submitted-by")
+                .value_name("USER")
+                .about("Only reuse artifacts from submits by these users")
+                .long_about(indoc::indoc!(r#"
+                    Only reuse artifacts from submits by these users.
+                    Replaces the `submitted_by` setting of the configured reuse policy.
+                "#))
             )
-            .arg(Arg::new("no_script_filter")
-                .long("no-script-filter")
-                .short('S')
+
+            .arg(Arg::new("dry-run")
                 .required(false)
                 .multiple(false)
-                .takes_value(false)
-                .about("Don't check for script equality. Can cause unexact results.")
+                .long("dry-run")
+                .about("Only print what would be built, do not build anything")
+                .long_about(indoc::indoc!(r#"
+                    Resolve the package tree and print the plan for the submit without scheduling any containers.
+
+                    For each job, the plan shows whether it would be built or which artifacts from the staging store or the
+                    release stores would be reused instead, as well as the image and the endpoints that would be used.
+                    No submit is created in the database.
+                "#))
             )
-            .arg(Arg::new("staging_dir")
+
+            .arg(Arg::new("explain-reuse")
                 .required(false)
                 .multiple(false)
-                .long("staging-dir")
+                .long("explain-reuse")
                 .takes_value(true)
-                .value_name("PATH")
-                .validator(dir_exists_validator)
-                .about("Also consider this staging dir when searching for artifacts")
+                .value_name("FILE")
+                .conflicts_with("dry-run")
+                .about("Explain for each job why it would be built or reused, and write the report to FILE as JSON")
+                .long_about(indoc::indoc!(r#"
+                    Like --dry-run, resolve the package tree and plan the submit without building anything, but explain the
+                    decision for each job.
+
+                    For each job, the report shows the environment it would be built with, the artifacts that would be
+                    reused, why it would be built, and the earlier jobs of the package whose artifacts were considered but
+                    not reused, with the criteria they did not match (e.g. which environment variables differed).
+                    The report is printed and written to FILE as JSON.
+                "#))
             )
-            .arg(Arg::new("env_filter")
+
+            .arg(Arg::new("schedule")
                 .required(false)
-                .multiple(true)
-                .long("env")
-                .short('E')
+                .multiple(false)
+                .long("schedule")
+                .conflicts_with("dry-run")
+                .conflicts_with("explain-reuse")
+                .about("Wait for the next build window before starting the submit")
+                .long_about(indoc::indoc!(r#"
+                    If the current time is outside of the configured build windows, wait for the next build window and
+                    start the submit then.
+
+                    The submit is started by this process, so it has to keep running until then. Until the submit is
+                    started, it is listed in 'butido db queue'. If the process is interrupted, the submit is removed from
+                    the queue. Entries of processes which were killed are removed when they are overdue.
+                    Heavy submits (see 'heavy_submit_jobs' in the configuration) are refused outside of the build windows
+                    unless this flag is passed.
+                "#))
+            )
+
+            .arg(Arg::new("timeout")
+                .required(false)
+                .multiple(false)
+                .long("timeout")
                 .takes_value(true)
-                .value_name("KV")
-                .validator(env_pass_validator)
-                .about("Filter for this \"key=value\" environment variable")
+                .value_name("DURATION")
+                .conflicts_with("dry-run")
+                .conflicts_with("explain-reuse")
+                .validator(parse_duration)
+                .about("Kill the jobs which did not finish DURATION after the submit started")
+                .long_about(indoc::indoc!(r#"
+                    Kill the jobs which did not finish DURATION (e.g. "8h") after the submit started.
+
+                    Running jobs are killed and their containers removed, jobs which did not start yet are not started
+                    anymore. These jobs are reported apart from the jobs which failed on their own.
+                    A timeout for single jobs can be configured with `timeout` in the `build` settings of the configuration
+                    or the package.
+                "#))
             )
-            .arg(Arg::new("image")
+
+            .arg(Arg::new("verbose")
+                .required(false)
+                .multiple_occurrences(true)
+                .short('v')
+                .long("verbose")
+                .about("Increase the verbosity of the build, -vv streams the logs of all jobs to stdout")
+                .long_about(indoc::indoc!(r#"
+                    Increase the verbosity of the build, can be passed multiple times.
+
+                    With -vv, the logs of all jobs are streamed to stdout while building.
+                    Each line is prefixed with `[<package>-<version>@<endpoint>]`, colored per job, so that the interleaved
+                    output of several jobs stays readable.
+                    See also the global --log-format option.
+                "#))
+            )
+            .arg(Arg::new("stream-logs")
                 .required(false)
                 .multiple(false)
-                .long("image")
-                .short('I')
+                .long("stream-logs")
+                .hidden(true)
+                .about("Alias for -vv")
+            )
+
+            .arg(Arg::new("log-split-dir")
+                .required(false)
+                .multiple(false)
+                .long("log-split-dir")
                 .takes_value(true)
-                .value_name("IMAGE")
-                .about("Only list artifacts that were built on IMAGE")
+                .value_name("DIR")
+                .validator(dir_exists_validator)
+                .about("Write the log of each job to a separate file in DIR")
+                .long_about(indoc::indoc!(r#"
+                    Write the log of each job to a separate file in DIR.
+
+                    The log of a job is written to `DIR/<package>-<version>@<endpoint>-<job uuid>.log`.
+                "#))
             )
+
+            .args(metrics_args())
         )
 
-        .subcommand(App::new("find-pkg")
+        .subcommand(App::new("canary")
             .version(crate_version!())
-            .about("Find a package by regex")
-            .arg(Arg::new("package_name_regex")
+            .about("Build a sample of the packages affected by changes since a commit")
+            .long_about(indoc::indoc!(r#"
+                Build a representative sample of the packages affected by the changes in the repository since a commit,
+                as a fast smoke test of a big change.
+
+                Packages are affected if their definition changed or if they depend on an affected package. The affected
+                packages are grouped into clusters of packages depending on each other, and for each cluster the package
+                whose build covers the most changed packages is picked. Clusters are sampled before any cluster is sampled
+                on a second image. The clusters which are not covered by the sample are reported.
+            "#))
+            .arg(Arg::new("since")
                 .required(true)
                 .multiple(false)
-                .index(1)
-                .value_name("REGEX")
-                .about("The regex to match the package name against")
+                .long("since")
+                .takes_value(true)
+                .value_name("GIT REF")
+                .about("Consider the changes since GIT REF")
             )
-            .arg(Arg::new("package_version_constraint")
+            .arg(Arg::new("sample")
                 .required(false)
                 .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .long("sample")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("5")
+                .validator(parse_usize)
+                .about("Build at most N packages")
             )
-
-            .arg(Arg::new("terse")
+            .arg(Arg::new("image")
+                .required(false)
+                .multiple(true)
+                .takes_value(true)
+                .value_name("IMAGE NAME")
+                .short('I')
+                .long("image")
+                .about("Build on IMAGE NAME (can be given multiple times), all configured images if not given")
+            )
+            .arg(Arg::new("dry-run")
                 .required(false)
                 .multiple(false)
-                .long("terse")
-                .short('t')
-                .about("Do not use the fancy format, but simply <name> <version>")
+                .long("dry-run")
+                .about("Only print the sample, do not build anything")
             )
+        )
 
-            .arg(Arg::new("show_all")
-                .required(false)
+        .subcommand(App::new("bisect")
+            .version(crate_version!())
+            .about("Find the commit that broke the build of a package")
+            .long_about(indoc::indoc!(r#"
+                Find the commit of the package repository that broke the build of a package, by bisecting the commits
+                between a good and a bad submit of the package.
+
+                The package is built (on the image and with the environment of the bad submit) in a worktree of the
+                repository at the commits in between, like the 'build' subcommand would build it. Artifacts of earlier
+                builds are reused where possible. Commits at which the build cannot be started (for example because the
+                package does not exist there) are skipped.
+            "#))
+            .arg(Arg::new("package")
+                .required(true)
                 .multiple(false)
-                .long("all")
-                .short('A')
-                .about("Same as: -SDpEFPs --denied-images --allowed-images (all flags enabled)")
+                .long("package")
+                .takes_value(true)
+                .value_name("NAME")
+                .about("The package whose build broke")
+            )
+            .arg(Arg::new("good")
+                .required(true)
+                .multiple(false)
+                .long("good")
+                .takes_value(true)
+                .value_name("SUBMIT")
+                .about("The UUID of a submit that built the package successfully")
+            )
+            .arg(Arg::new("bad")
+                .required(true)
+                .multiple(false)
+                .long("bad")
+                .takes_value(true)
+                .value_name("SUBMIT")
+                .about("The UUID of a later submit that failed to build the package")
             )
+        )
 
-            .arg(Arg::new("show_sources")
+        .subcommand(App::new("submit")
+            .version(crate_version!())
+            .about("Run a pre-defined submit from the package repository")
+            .long_about(indoc::indoc!(r#"
+                Run a pre-defined submit ("submit template") from the package repository.
+
+                Submit templates are stored in `.butido/submits/<name>.toml` in the package repository.
+                A template defines the package to build, the image to build on and the environment to pass to the build:
+
+                    package_name = "foo"
+                    package_version = "1.0"          # optional
+                    image = "debian:bullseye"
+                    build_args = ["--write-log"]     # optional, additional arguments for 'build'
+
+                    [env]                            # optional
+                    FOO = "bar"
+
+                Running a template is equivalent to running the 'build' subcommand with these arguments.
+
+                If several templates are passed, their submits run at the same time in this process.
+                They share the connections to the endpoints and their slots: a free slot goes to the submit with the fewest running jobs
+                among the submits with jobs waiting for it, so that a large submit cannot starve the others.
+                Instead of the progress bars of the jobs, one line per submit is shown.
+            "#))
+            .arg(Arg::new("template_name")
+                .required_unless_present("list")
+                .multiple(true)
+                .index(1)
+                .value_name("NAME")
+                .about("The names of the submit templates to run")
+            )
+            .arg(Arg::new("list")
                 .required(false)
                 .multiple(false)
-                .long("source")
-                .alias("sources")
-                .short('S')
-                .about("Show the sources of the package")
+                .long("list")
+                .takes_value(false)
+                .about("List the available submit templates")
+                .conflicts_with("template_name")
             )
+        )
 
-            .arg(Arg::new("show_dependencies")
+        .subcommand(App::new("what-depends")
+            .version(crate_version!())
+            .about("List all packages that depend on a specific package")
+            .arg(Arg::new("package_name")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .about("The name of the package")
+            )
+            .arg(Arg::new("package_version_constraint")
                 .required(false)
                 .multiple(false)
-                .long("dependencies")
-                .alias("deps")
-                .short('D')
-                .about("Show the dependencies of the package")
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("Only list packages depending on a version matching this constraint, E.G. '=1.0.0'")
+            )
+            .arg(Arg::new("transitive")
+                .required(false)
+                .multiple(false)
+                .long("transitive")
+                .about("Also list the packages that depend on the package through other packages")
+                .long_about(indoc::indoc!(r#"
+                    Also list the packages that depend on the package through other packages, i.e. all packages
+                    that would have to be rebuilt if the package changes.
+
+                    With --output, the "depth" column is 1 for direct dependents, 2 for packages depending on those,
+                    and so on, and the "via" column names the package the dependency is on.
+                "#))
             )
+            .args(output_args())
+            .arg(arg_at_revision())
             .arg(Arg::new("dependency_type")
                 .required(false)
                 .multiple(true)
                 .takes_value(true)
-                .long("dependency-type")
+                .short('t')
+                .long("type")
                 .value_name("DEPENDENCY_TYPE")
                 .possible_values(&[
                     IDENT_DEPENDENCY_TYPE_BUILD,
@@ -738,363 +1270,1280 @@ pub fn cli<'a>() -> App<'a> {
                     IDENT_DEPENDENCY_TYPE_BUILD,
                     IDENT_DEPENDENCY_TYPE_RUNTIME,
                 ])
-                .about("Specify which dependency types are to print.")
+                .about("Specify which dependency types are to be checked. By default, all are checked")
             )
-
-            .arg(Arg::new("show_patches")
-                .required(false)
+        )
+        .subcommand(App::new("dependencies-of")
+            .version(crate_version!())
+            .alias("depsof")
+            .about("List the depenendcies of a package")
+            .arg(Arg::new("package_name")
+                .required(true)
                 .multiple(false)
-                .long("patches")
-                .short('p')
-                .about("Show the patches of the package")
+                .index(1)
+                .value_name("PACKAGE_NAME")
+                .about("The name of the package")
             )
-
-            .arg(Arg::new("show_env")
+            .arg(Arg::new("package_version_constraint")
                 .required(false)
                 .multiple(false)
-                .long("env")
-                .short('E')
-                .about("Show the environment of the package")
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
             )
+            .arg(Arg::new("dependency_type")
+                .required(false)
+                .multiple(true)
+                .takes_value(true)
+                .short('t')
+                .long("type")
+                .value_name("DEPENDENCY_TYPE")
+                .possible_values(&[
+                    IDENT_DEPENDENCY_TYPE_BUILD,
+                    IDENT_DEPENDENCY_TYPE_RUNTIME,
+                ])
+                .default_values(&[
+                    IDENT_DEPENDENCY_TYPE_BUILD,
+                    IDENT_DEPENDENCY_TYPE_RUNTIME,
+                ])
+                .about("Specify which dependency types are to be printed. By default, all are checked")
+            )
+        )
+        .subcommand(App::new("versions-of")
+            .version(crate_version!())
+            .alias("versions")
+            .about("List the versions of a package")
+            .arg(Arg::new("package_name")
+                .required_unless_present("stdin")
+                .multiple(false)
+                .index(1)
+                .value_name("PACKAGE_NAME")
+                .about("The name of the package")
+            )
+            .arg(batch_arg_stdin().conflicts_with("package_name"))
+            .arg(batch_arg_csv())
+            .arg(arg_at_revision())
+        )
+        .subcommand(App::new("env-of")
+            .version(crate_version!())
+            .alias("env")
+            .about("Show the ENV configured for a package")
+            .arg(Arg::new("package_name")
+                .required_unless_present("stdin")
+                .multiple(false)
+                .index(1)
+                .value_name("PACKAGE_NAME")
+                .about("The name of the package")
+            )
+            .arg(Arg::new("package_version_constraint")
+                .required_unless_present("stdin")
+                .multiple(false)
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+            )
+            .arg(batch_arg_stdin().conflicts_with_all(&["package_name", "package_version_constraint"]))
+            .arg(batch_arg_csv())
+            .arg(arg_at_revision())
+        )
 
-            .arg(Arg::new("show_flags")
+        .subcommand(App::new("diff-artifacts")
+            .version(crate_version!())
+            .about("Compare the released artifacts of two versions of a package")
+            .long_about(indoc::indoc!(r#"
+                Compare the released artifacts of two versions of a package.
+
+                The artifacts are matched by their file name (with the version removed) and their sizes are compared.
+                For changed tar archives, the files in the archives are compared as well.
+                With --diffoscope, the changed artifacts are compared with diffoscope, which has to be installed.
+            "#))
+            .arg(Arg::new("package_name")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .value_name("NAME")
+                .about("The name of the package")
+            )
+            .arg(Arg::new("version_a")
+                .required(true)
+                .multiple(false)
+                .index(2)
+                .value_name("VERSION")
+                .about("The version to compare")
+            )
+            .arg(Arg::new("version_b")
+                .required(true)
+                .multiple(false)
+                .index(3)
+                .value_name("VERSION")
+                .about("The version to compare to")
+            )
+            .arg(Arg::new("image")
                 .required(false)
                 .multiple(false)
-                .long("flags")
-                .short('F')
-                .about("Show the flags of the package")
+                .long("image")
+                .short('I')
+                .takes_value(true)
+                .value_name("IMAGE")
+                .about("Only consider artifacts that were built on IMAGE")
+            )
+            .arg(Arg::new("no_script_filter")
+                .long("no-script-filter")
+                .short('S')
+                .required(false)
+                .multiple(false)
+                .takes_value(false)
+                .about("Don't check for script equality. Can cause unexact results.")
+            )
+            .arg(Arg::new("diffoscope")
+                .required(false)
+                .multiple(false)
+                .long("diffoscope")
+                .takes_value(false)
+                .about("Show the content differences of changed artifacts with diffoscope")
+            )
+            .arg(Arg::new("csv")
+                .required(false)
+                .multiple(false)
+                .long("csv")
+                .takes_value(false)
+                .about("Format output as CSV")
             )
+        )
 
-            .arg(Arg::new("show_allowed_images")
+        .subcommand(App::new("find-artifact")
+            .version(crate_version!())
+            .about("Find artifacts for packages")
+            .arg(Arg::new("package_name_regex")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .value_name("REGEX")
+                .about("The regex to match the package name against")
+            )
+            .arg(Arg::new("package_version_constraint")
                 .required(false)
                 .multiple(false)
-                .long("allowed-images")
-                .about("Show the images on which the package is only allowed to be built")
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+            )
+            .arg(Arg::new("no_script_filter")
+                .long("no-script-filter")
+                .short('S')
+                .required(false)
+                .multiple(false)
+                .takes_value(false)
+                .about("Don't check for script equality. Can cause unexact results.")
+            )
+            .arg(Arg::new("staging_dir")
+                .required(false)
+                .multiple(false)
+                .long("staging-dir")
+                .takes_value(true)
+                .value_name("PATH")
+                .validator(dir_exists_validator)
+                .about("Also consider this staging dir when searching for artifacts")
+            )
+            .arg(Arg::new("env_filter")
+                .required(false)
+                .multiple(true)
+                .long("env")
+                .short('E')
+                .takes_value(true)
+                .value_name("KV")
+                .validator(env_pass_validator)
+                .about("Filter for this \"key=value\" environment variable")
+            )
+            .arg(Arg::new("image")
+                .required(false)
+                .multiple(false)
+                .long("image")
+                .short('I')
+                .takes_value(true)
+                .value_name("IMAGE")
+                .about("Only list artifacts that were built on IMAGE")
+            )
+            .arg(Arg::new("artifact_type")
+                .required(false)
+                .multiple(false)
+                .long("type")
+                .takes_value(true)
+                .value_name("TYPE")
+                .possible_values(&["rpm", "deb", "tar"])
+                .about("Only list artifacts of type TYPE")
+            )
+            .arg(Arg::new("rpm_name")
+                .required(false)
+                .multiple(false)
+                .long("rpm-name")
+                .takes_value(true)
+                .value_name("NAME")
+                .about("Only list rpm packages with the name NAME in their header")
+            )
+            .arg(Arg::new("rpm_arch")
+                .required(false)
+                .multiple(false)
+                .long("rpm-arch")
+                .takes_value(true)
+                .value_name("ARCH")
+                .about("Only list rpm packages for the architecture ARCH")
+            )
+            .arg(Arg::new("deb_name")
+                .required(false)
+                .multiple(false)
+                .long("deb-name")
+                .takes_value(true)
+                .value_name("NAME")
+                .about("Only list deb packages with the name NAME in their control file")
+            )
+            .arg(Arg::new("deb_arch")
+                .required(false)
+                .multiple(false)
+                .long("deb-arch")
+                .takes_value(true)
+                .value_name("ARCH")
+                .about("Only list deb packages for the architecture ARCH")
+            )
+        )
+
+        .subcommand(App::new("find-pkg")
+            .version(crate_version!())
+            .about("Find a package by regex")
+            .arg(Arg::new("package_name_regex")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .value_name("REGEX")
+                .about("The regex to match the package name against")
+            )
+            .arg(Arg::new("package_version_constraint")
+                .required(false)
+                .multiple(false)
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+            )
+
+            .arg(Arg::new("terse")
+                .required(false)
+                .multiple(false)
+                .long("terse")
+                .short('t')
+                .about("Do not use the fancy format, but simply <name> <version>")
+            )
+
+            .arg(Arg::new("repository")
+                .required(false)
+                .multiple(false)
+                .long("repository")
+                .takes_value(true)
+                .value_name("NAME")
+                .about("Only find packages from this package repository (\"main\" for the repository butido runs in)")
+            )
+
+            .arg(Arg::new("show_all")
+                .required(false)
+                .multiple(false)
+                .long("all")
+                .short('A')
+                .about("Same as: -SDpEFPs --denied-images --allowed-images (all flags enabled)")
+            )
+
+            .arg(Arg::new("show_sources")
+                .required(false)
+                .multiple(false)
+                .long("source")
+                .alias("sources")
+                .short('S')
+                .about("Show the sources of the package")
+            )
+
+            .arg(Arg::new("show_dependencies")
+                .required(false)
+                .multiple(false)
+                .long("dependencies")
+                .alias("deps")
+                .short('D')
+                .about("Show the dependencies of the package")
+            )
+            .arg(Arg::new("dependency_type")
+                .required(false)
+                .multiple(true)
+                .takes_value(true)
+                .long("dependency-type")
+                .value_name("DEPENDENCY_TYPE")
+                .possible_values(&[
+                    IDENT_DEPENDENCY_TYPE_BUILD,
+                    IDENT_DEPENDENCY_TYPE_RUNTIME,
+                ])
+                .default_values(&[
+                    IDENT_DEPENDENCY_TYPE_BUILD,
+                    IDENT_DEPENDENCY_TYPE_RUNTIME,
+                ])
+                .about("Specify which dependency types are to print.")
+            )
+
+            .arg(Arg::new("show_patches")
+                .required(false)
+                .multiple(false)
+                .long("patches")
+                .short('p')
+                .about("Show the patches of the package")
+            )
+
+            .arg(Arg::new("show_env")
+                .required(false)
+                .multiple(false)
+                .long("env")
+                .short('E')
+                .about("Show the environment of the package")
+            )
+
+            .arg(Arg::new("show_flags")
+                .required(false)
+                .multiple(false)
+                .long("flags")
+                .short('F')
+                .about("Show the flags of the package")
+            )
+
+            .arg(Arg::new("show_allowed_images")
+                .required(false)
+                .multiple(false)
+                .long("allowed-images")
+                .about("Show the images on which the package is only allowed to be built")
+            )
+
+            .arg(Arg::new("show_denied_images")
+                .required(false)
+                .multiple(false)
+                .long("denied-images")
+                .about("Show the images on which the package is not allowed to be built")
+            )
+
+            .arg(Arg::new("show_phases")
+                .required(false)
+                .multiple(false)
+                .long("phases")
+                .short('P')
+                .about("Show the phases of the script of the package")
+            )
+
+            .arg(Arg::new("show_script")
+                .required(false)
+                .multiple(false)
+                .long("script")
+                .short('s')
+                .about("Show the script of the package")
+            )
+            .arg(script_arg_line_numbers())
+            .arg(script_arg_no_line_numbers())
+            .arg(script_arg_highlight())
+            .arg(script_arg_no_highlight())
+
+        )
+        .subcommand(App::new("source")
+            .version(crate_version!())
+            .about("Handle package sources")
+            .subcommand(App::new("verify")
+                .version(crate_version!())
+                .about("Hash-check all source files")
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("PKG")
+                    .about("Verify the sources of this package (optional, if left out, all packages are checked)")
+                )
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("VERSION")
+                    .about("Verify the sources of this package version (optional, if left out, all packages are checked)")
+                )
+
+                .arg(Arg::new("matching")
+                    .required(false)
+                    .multiple(false)
+                    .long("matching")
+                    .takes_value(true)
+                    .value_name("REGEX")
+                    .about("Verify all packages where the package name matches REGEX")
+                )
+
+                .arg(Arg::new("all")
+                    .required(false)
+                    .multiple(false)
+                    .long("all")
+                    .takes_value(false)
+                    .about("Verify the sources of all packages")
+                )
+
+                .group(ArgGroup::new("verify-one-or-many")
+                    .args(&["package_name", "matching", "all"])
+                    .required(true)
+                )
+            )
+            .subcommand(App::new("list-missing")
+                .version(crate_version!())
+                .about("List packages where the source is missing")
+            )
+            .subcommand(App::new("url")
+                .version(crate_version!())
+                .about("Show the URL of the source of a package")
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("PKG")
+                    .about("Verify the sources of this package (optional, if left out, all packages are checked)")
+                )
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("VERSION")
+                    .about("Verify the sources of this package version (optional, if left out, all packages are checked)")
+                )
+            )
+            .subcommand(App::new("download")
+                .version(crate_version!())
+                .about("Download the source for one or multiple packages")
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("PKG")
+                    .about("Download the sources of this package")
+                )
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("VERSION_CONSTRAINT")
+                    .about("Download the sources of this package version (optional, if left out, all packages are downloaded)")
+                )
+                .arg(Arg::new("force")
+                    .required(false)
+                    .multiple(false)
+                    .long("force")
+                    .about("Overwrite existing cache entry")
+                )
+
+                .arg(Arg::new("matching")
+                    .required(false)
+                    .multiple(false)
+                    .long("matching")
+                    .takes_value(true)
+                    .value_name("REGEX")
+                    .about("Download all packages matching a regex with their name")
+                )
+
+                .arg(Arg::new("jobs")
+                    .required(false)
+                    .multiple(false)
+                    .long("jobs")
+                    .short('j')
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("4")
+                    .validator(parse_usize)
+                    .about("Download at most N sources at the same time")
+                )
+
+                .group(ArgGroup::new("download-one-or-many")
+                    .args(&["package_name", "matching"])
+                    .required(true)
+                )
+            )
+            .subcommand(App::new("add")
+                .version(crate_version!())
+                .about("Download a source and print its definition with the hash")
+                .long_about(indoc::indoc!(r#"
+                    Download a source and print its definition with the hash.
+
+                    The source is downloaded to a temporary file and hashed. The definition is printed in the
+                    format of a package TOML file, or appended to the file given with --write. The download is
+                    not stored in the source cache, use "butido source download" for the package afterwards.
+                "#))
+                .arg(Arg::new("url")
+                    .required(true)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("URL")
+                    .about("The URL of the source")
+                )
+                .arg(Arg::new("name")
+                    .required(false)
+                    .multiple(false)
+                    .long("name")
+                    .takes_value(true)
+                    .value_name("NAME")
+                    .default_value("src")
+                    .about("The name of the source in the package")
+                )
+                .arg(Arg::new("hash_type")
+                    .required(false)
+                    .multiple(false)
+                    .long("hash-type")
+                    .takes_value(true)
+                    .value_name("TYPE")
+                    .default_value("sha256")
+                    .possible_values(&["sha1", "sha256", "sha512", "blake3"])
+                    .about("The hash algorithm")
+                )
+                .arg(Arg::new("write")
+                    .required(false)
+                    .multiple(false)
+                    .long("write")
+                    .takes_value(true)
+                    .value_name("PKG_TOML")
+                    .about("Append the source to this package TOML file instead of printing it")
+                )
+            )
+            .subcommand(App::new("of")
+                .version(crate_version!())
+                .about("Get the pathes of the sources of a package")
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("PKG")
+                    .about("Get the source file pathes for this package")
+                )
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("VERSION")
+                    .about("Get the source file pathes for the package in this version")
+                )
+                .arg(batch_arg_stdin().conflicts_with_all(&["package_name", "package_version"]))
+                .arg(batch_arg_csv())
+            )
+        )
+
+        .subcommand(App::new("release")
+            .version(crate_version!())
+            .about("Manage artifact releases")
+            .subcommand(App::new("rm")
+                .version(crate_version!())
+                .about("Remove release artifacts")
+                .long_about(indoc::indoc!(r#"
+                    Removes a released artifact from the release store and deletes the according database entry.
+
+                    This command asks interactively whether you want to delete data.
+                    This can't be turned off.
+                "#))
+                .arg(Arg::new("release_store_name")
+                    .required(true)
+                    .multiple(false)
+                    .long("from")
+                    .value_name("RELEASE_STORE_NAME")
+                    .about("Release store name to remove release from")
+                )
+
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("PKG")
+                    .about("The name of the package")
+                    .requires("package_version")
+                )
+
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("VERSION")
+                    .about("The exact version of the package (string match)")
+                    .requires("package_name")
+                )
+            )
+
+            .subcommand(App::new("new")
+                .version(crate_version!())
+                .about("Release artifacts")
+                .arg(Arg::new("submit_uuid")
+                    .required(true)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("SUBMIT")
+                    .about("The submit uuid from which to release a package")
+                )
+                .arg(Arg::new("release_store_name")
+                    .required(true)
+                    .multiple(false)
+                    .long("to")
+                    .value_name("RELEASE_STORE_NAME")
+                    .about("Release store name to release to")
+                    .long_about(indoc::indoc!(r#"
+                        Butido can release to different release stores, based on this CLI flag.
+                        The release stores that are available must be listed in the configuration.
+                    "#))
+                )
+                .arg(Arg::new("package_name")
+                    .required(false)
+                    .multiple(false)
+                    .index(2)
+                    .value_name("PKG")
+                    .about("The name of the package")
+                    .conflicts_with("all-packages")
+                )
+                .arg(Arg::new("all-packages")
+                    .required(false)
+                    .multiple(false)
+                    .long("all")
+                    .about("Release all packages")
+                    .conflicts_with("package_name")
+                )
+                .group(ArgGroup::new("package")
+                    .args(&["package_name", "all-packages"])
+                    .required(true) // one of these is required
+                )
+                .arg(Arg::new("package_version")
+                    .required(false)
+                    .multiple(false)
+                    .index(3)
+                    .value_name("VERSION")
+                    .about("The exact version of the package (string match)")
+                )
+                .arg(Arg::new("package_do_update")
+                    .required(false)
+                    .multiple(false)
+                    .long("update")
+                    .about("Do update a package if it already exists in the release store")
+                )
+                .arg(Arg::new("noninteractive")
+                    .required(false)
+                    .multiple(false)
+                    .long("non-interactive")
+                    .about("Dont be interactive (only with --update at the moment)")
+                    .requires("package_do_update")
+                )
+                .arg(Arg::new("quiet")
+                    .required(false)
+                    .multiple(false)
+                    .long("quiet")
+                    .short('q')
+                    .about("Don't print pathes to released filesfiles  after releases are complete")
+                )
+            )
+
+            .subcommand(App::new("pin")
+                .version(crate_version!())
+                .about("Pin an artifact, so that it is exempt from cleanups")
+                .long_about(indoc::indoc!(r#"
+                    Pin an artifact, so that it is exempt from any garbage collection or retention policy.
+
+                    The reason for the pin and the user who pinned the artifact are recorded in the database.
+                "#))
+                .arg(Arg::new("id")
+                    .required(true)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("ID")
+                    .about("The ID of the release (or of the artifact, if --artifact is passed)")
+                    .validator(parse_i32)
+                )
+                .arg(Arg::new("is_artifact_id")
+                    .required(false)
+                    .multiple(false)
+                    .long("artifact")
+                    .about("Interpret ID as ID of an artifact instead of ID of a release")
+                )
+                .arg(Arg::new("reason")
+                    .required(true)
+                    .multiple(false)
+                    .long("reason")
+                    .takes_value(true)
+                    .value_name("REASON")
+                    .about("Why the artifact is pinned")
+                )
+            )
+
+            .subcommand(App::new("unpin")
+                .version(crate_version!())
+                .about("Remove the pin from an artifact")
+                .arg(Arg::new("id")
+                    .required(true)
+                    .multiple(false)
+                    .index(1)
+                    .value_name("ID")
+                    .about("The ID of the release (or of the artifact, if --artifact is passed)")
+                    .validator(parse_i32)
+                )
+                .arg(Arg::new("is_artifact_id")
+                    .required(false)
+                    .multiple(false)
+                    .long("artifact")
+                    .about("Interpret ID as ID of an artifact instead of ID of a release")
+                )
+            )
+
+        )
+
+        .subcommand(App::new("lint")
+            .version(crate_version!())
+            .about("Check the packages of the repository for problems")
+            .long_about(indoc::indoc!(r#"
+                Check the packages of the repository for problems, grouped per package. If any problem is
+                found, the command exits with a non-zero exit code.
+
+                The checks are:
+
+                    duplicates          Packages that are defined more than once in a package repository, or
+                                        that shadow packages with the same name and version of an additional
+                                        package repository (see package_repositories in the configuration)
+                                        which does not allow shadowing them
+                    dependencies        Dependencies that cannot be parsed or that no package of the
+                                        repository satisfies
+                    conditions          Conditional dependencies whose "in_image" condition lists none of the
+                                        images the package can be built in
+                    outputs             Outputs with invalid patterns and dependencies on outputs the package
+                                        depended on does not declare
+                    sources             Packages without sources that do not declare `sources = []`
+                    phases              Missing, empty or unknown phases
+                    dependency-mapping  System dependencies that are not mapped for an image
+                    script              Scripts that cannot be rendered or that the configured script_linter
+                                        rejects
+            "#))
+            .arg(Arg::new("package_name")
+                .required(false)
+                .multiple(false)
+                .index(1)
+                .value_name("NAME")
+                .about("Package name to lint (if not present, every package will be linted")
+            )
+            .arg(Arg::new("package_version")
+                .required(false)
+                .multiple(false)
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+            )
+            .arg(Arg::new("skip")
+                .required(false)
+                .multiple(true)
+                .takes_value(true)
+                .use_delimiter(true)
+                .long("skip")
+                .value_name("CHECK")
+                .possible_values(&[
+                    "duplicates",
+                    "dependencies",
+                    "conditions",
+                    "outputs",
+                    "sources",
+                    "phases",
+                    "dependency-mapping",
+                    "script",
+                ])
+                .about("Do not run the check CHECK (can be passed multiple times)")
+            )
+        )
+
+        .subcommand(App::new("tree-of")
+            .version(crate_version!())
+            .about("Print the dependency tree of one or multiple packages")
+            .arg(arg_at_revision())
+            .arg(Arg::new("package_name")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .value_name("NAME")
+                .about("Package name to lint (if not present, every package will be linted")
             )
-
-            .arg(Arg::new("show_denied_images")
+            .arg(Arg::new("package_version")
                 .required(false)
                 .multiple(false)
-                .long("denied-images")
-                .about("Show the images on which the package is not allowed to be built")
+                .index(2)
+                .value_name("VERSION_CONSTRAINT")
+                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
             )
-
-            .arg(Arg::new("show_phases")
+            .arg(Arg::new("image")
                 .required(false)
                 .multiple(false)
-                .long("phases")
-                .short('P')
-                .about("Show the phases of the script of the package")
+                .takes_value(true)
+                .value_name("IMAGE NAME")
+                .short('I')
+                .long("image")
+                .about("Name of the docker image to use")
+                .long_about(indoc::indoc!(r#"
+                    Name of the docker image to use.
+
+                    Required because tree might look different on different images because of
+                    conditions on dependencies.
+                "#))
             )
+            .arg(Arg::new("env")
+                .required(false)
+                .multiple(true)
+                .short('E')
+                .long("env")
+                .validator(env_pass_validator)
+                .about("Additional env to be passed when building packages")
+                .long_about(indoc::indoc!(r#"
+                    Additional env to be passed when building packages.
 
-            .arg(Arg::new("show_script")
+                    Required because tree might look different on different images because of
+                    conditions on dependencies.
+                "#))
+            )
+            .arg(Arg::new("format")
                 .required(false)
                 .multiple(false)
-                .long("script")
-                .short('s')
-                .about("Show the script of the package")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(&["tree", "dot", "json", "mermaid"])
+                .default_value("tree")
+                .about("Output format")
+                .long_about(indoc::indoc!(r#"
+                    Output format.
+
+                    "tree" prints the tree for humans, "dot" prints a graph for Graphviz (e.g. pipe into
+                    `dot -Tsvg`), "mermaid" prints a mermaid flowchart. "json" prints the packages of the
+                    trees with their dependencies, after the conditions on the dependencies were checked.
+                "#))
             )
-            .arg(script_arg_line_numbers())
-            .arg(script_arg_no_line_numbers())
-            .arg(script_arg_highlight())
-            .arg(script_arg_no_highlight())
+        )
 
+        .subcommand(App::new("metrics")
+            .version(crate_version!())
+            .about("Print metrics about butido")
         )
-        .subcommand(App::new("source")
+
+        .subcommand(App::new("store")
             .version(crate_version!())
-            .about("Handle package sources")
-            .subcommand(App::new("verify")
+            .about("Browse the artifact stores")
+            .subcommand(App::new("ls")
                 .version(crate_version!())
-                .about("Hash-check all source files")
-                .arg(Arg::new("package_name")
+                .about("List artifacts in the stores")
+                .long_about(indoc::indoc!(r#"
+                    List the artifacts in the stores.
+
+                    By default, all release stores are listed. The package an artifact belongs to is looked up
+                    in the database, artifacts without a database entry are listed as "unknown".
+                "#))
+                .arg(Arg::new("csv")
                     .required(false)
                     .multiple(false)
-                    .index(1)
-                    .value_name("PKG")
-                    .about("Verify the sources of this package (optional, if left out, all packages are checked)")
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
                 )
-                .arg(Arg::new("package_version")
+
+                .arg(Arg::new("staging")
                     .required(false)
                     .multiple(false)
-                    .index(2)
-                    .value_name("VERSION")
-                    .about("Verify the sources of this package version (optional, if left out, all packages are checked)")
+                    .long("staging")
+                    .takes_value(false)
+                    .conflicts_with("release")
+                    .about("List the staging directories of all submits instead of the release stores")
                 )
 
-                .arg(Arg::new("matching")
+                .arg(Arg::new("release")
                     .required(false)
                     .multiple(false)
-                    .long("matching")
+                    .long("release")
                     .takes_value(true)
-                    .value_name("REGEX")
-                    .about("Verify all packages where the package name matches REGEX")
+                    .value_name("STORE")
+                    .about("Only list the release store STORE")
                 )
 
-                .group(ArgGroup::new("verify-one-or-many")
-                    .args(&["package_name", "matching"])
-                    .required(true)
-                )
-            )
-            .subcommand(App::new("list-missing")
-                .version(crate_version!())
-                .about("List packages where the source is missing")
-            )
-            .subcommand(App::new("url")
-                .version(crate_version!())
-                .about("Show the URL of the source of a package")
-                .arg(Arg::new("package_name")
+                .arg(Arg::new("package")
                     .required(false)
                     .multiple(false)
-                    .index(1)
+                    .long("package")
+                    .short('p')
+                    .takes_value(true)
                     .value_name("PKG")
-                    .about("Verify the sources of this package (optional, if left out, all packages are checked)")
+                    .about("Only list artifacts of package PKG")
                 )
-                .arg(Arg::new("package_version")
+
+                .arg(Arg::new("du")
                     .required(false)
                     .multiple(false)
-                    .index(2)
-                    .value_name("VERSION")
-                    .about("Verify the sources of this package version (optional, if left out, all packages are checked)")
+                    .long("du")
+                    .takes_value(false)
+                    .about("Show disk usage per package instead of single artifacts")
                 )
-            )
-            .subcommand(App::new("download")
-                .version(crate_version!())
-                .about("Download the source for one or multiple packages")
-                .arg(Arg::new("package_name")
+
+                .arg(Arg::new("sort")
                     .required(false)
                     .multiple(false)
-                    .index(1)
-                    .value_name("PKG")
-                    .about("Download the sources of this package")
+                    .long("sort")
+                    .takes_value(true)
+                    .value_name("KEY")
+                    .possible_values(&["path", "package", "size"])
+                    .default_value("path")
+                    .about("Sort by KEY (\"path\" sorts by package with --du)")
                 )
-                .arg(Arg::new("package_version")
-                    .required(false)
+            )
+
+            .subcommand(App::new("index")
+                .version(crate_version!())
+                .about("Write an index of a release store for package managers")
+                .long_about(indoc::indoc!(r#"
+                    Write an index of a release store for package managers, so that the store can be served to them
+                    directly.
+
+                    "yum" writes the "repodata" directory for dnf/yum, listing the rpm artifacts. "apt" writes a
+                    "Packages" file for a flat apt repository, listing the deb artifacts. "flat-json" writes an
+                    "index.json" file, listing all artifacts.
+
+                    The indexes are built from the metadata extracted from the artifacts. The dependencies of the
+                    packages are not part of this metadata, so the yum index does not list them.
+                "#))
+                .arg(Arg::new("release")
+                    .required(true)
                     .multiple(false)
-                    .index(2)
-                    .value_name("VERSION_CONSTRAINT")
-                    .about("Download the sources of this package version (optional, if left out, all packages are downloaded)")
+                    .long("release")
+                    .takes_value(true)
+                    .value_name("STORE")
+                    .about("Index the release store STORE")
                 )
-                .arg(Arg::new("force")
-                    .required(false)
+
+                .arg(Arg::new("format")
+                    .required(true)
                     .multiple(false)
-                    .long("force")
-                    .about("Overwrite existing cache entry")
+                    .long("format")
+                    .takes_value(true)
+                    .value_name("FORMAT")
+                    .possible_values(&["yum", "apt", "flat-json"])
+                    .about("The format of the index")
                 )
+            )
 
-                .arg(Arg::new("matching")
+            .subcommand(App::new("push")
+                .version(crate_version!())
+                .about("Upload artifacts to the remote store")
+                .long_about(indoc::indoc!(r#"
+                    Upload the artifacts of a store to the remote object storage configured in "remote_store".
+
+                    Only artifacts which are not in the remote store yet are uploaded, so that other hosts can
+                    pull them with "butido store pull".
+                "#))
+                .arg(Arg::new("release")
                     .required(false)
                     .multiple(false)
-                    .long("matching")
+                    .long("release")
                     .takes_value(true)
-                    .value_name("REGEX")
-                    .about("Download all packages matching a regex with their name")
+                    .value_name("STORE")
+                    .about("Upload the release store STORE")
                 )
 
-                .group(ArgGroup::new("download-one-or-many")
-                    .args(&["package_name", "matching"])
-                    .required(true)
-                )
-            )
-            .subcommand(App::new("of")
-                .version(crate_version!())
-                .about("Get the pathes of the sources of a package")
-                .arg(Arg::new("package_name")
+                .arg(Arg::new("staging")
                     .required(false)
                     .multiple(false)
-                    .index(1)
-                    .value_name("PKG")
-                    .about("Get the source file pathes for this package")
+                    .long("staging")
+                    .takes_value(true)
+                    .value_name("SUBMIT")
+                    .about("Upload the staging directory of submit SUBMIT")
                 )
-                .arg(Arg::new("package_version")
+                .group(ArgGroup::new("store")
+                    .args(&["release", "staging"])
+                    .required(true)
+                )
+
+                .arg(Arg::new("dry-run")
                     .required(false)
                     .multiple(false)
-                    .index(2)
-                    .value_name("VERSION")
-                    .about("Get the source file pathes for the package in this version")
+                    .long("dry-run")
+                    .about("Only list the artifacts that would be copied")
                 )
             )
-        )
 
-        .subcommand(App::new("release")
-            .version(crate_version!())
-            .about("Manage artifact releases")
-            .subcommand(App::new("rm")
+            .subcommand(App::new("pull")
                 .version(crate_version!())
-                .about("Remove release artifacts")
+                .about("Download artifacts from the remote store")
                 .long_about(indoc::indoc!(r#"
-                    Removes a released artifact from the release store and deletes the according database entry.
+                    Download the artifacts of a store from the remote object storage configured in "remote_store".
 
-                    This command asks interactively whether you want to delete data.
-                    This can't be turned off.
+                    Only artifacts which are not in the local store yet are downloaded. The staging directory of
+                    the submit is created if it does not exist.
                 "#))
-                .arg(Arg::new("release_store_name")
-                    .required(true)
+                .arg(Arg::new("release")
+                    .required(false)
                     .multiple(false)
-                    .long("from")
-                    .value_name("RELEASE_STORE_NAME")
-                    .about("Release store name to remove release from")
+                    .long("release")
+                    .takes_value(true)
+                    .value_name("STORE")
+                    .about("Download the release store STORE")
                 )
 
-                .arg(Arg::new("package_name")
+                .arg(Arg::new("staging")
                     .required(false)
                     .multiple(false)
-                    .index(1)
-                    .value_name("PKG")
-                    .about("The name of the package")
-                    .requires("package_version")
+                    .long("staging")
+                    .takes_value(true)
+                    .value_name("SUBMIT")
+                    .about("Download the staging directory of submit SUBMIT")
+                )
+                .group(ArgGroup::new("store")
+                    .args(&["release", "staging"])
+                    .required(true)
                 )
 
-                .arg(Arg::new("package_version")
+                .arg(Arg::new("dry-run")
                     .required(false)
                     .multiple(false)
-                    .index(2)
-                    .value_name("VERSION")
-                    .about("The exact version of the package (string match)")
-                    .requires("package_name")
+                    .long("dry-run")
+                    .about("Only list the artifacts that would be copied")
                 )
             )
+        )
 
-            .subcommand(App::new("new")
+        .subcommand(App::new("gc")
+            .version(crate_version!())
+            .about("Remove old artifacts from the stores")
+            .subcommand(App::new("staging")
                 .version(crate_version!())
-                .about("Release artifacts")
-                .arg(Arg::new("submit_uuid")
-                    .required(true)
-                    .multiple(false)
-                    .index(1)
-                    .value_name("SUBMIT")
-                    .about("The submit uuid from which to release a package")
-                )
-                .arg(Arg::new("release_store_name")
-                    .required(true)
-                    .multiple(false)
-                    .long("to")
-                    .value_name("RELEASE_STORE_NAME")
-                    .about("Release store name to release to")
-                    .long_about(indoc::indoc!(r#"
-                        Butido can release to different release stores, based on this CLI flag.
-                        The release stores that are available must be listed in the configuration.
-                    "#))
-                )
-                .arg(Arg::new("package_name")
+                .about("Remove staging directories and artifacts according to retention policies")
+                .long_about(indoc::indoc!(r#"
+                    Remove staging directories and artifacts according to retention policies.
+
+                    A staging directory is removed if it is older than --older-than and not one of the staging
+                    directories of the --keep-last submits. The age of a staging directory is the time of its submit, or
+                    its modification time if the directory does not belong to a submit in the database.
+
+                    With --orphaned, staging directories which do not belong to a submit in the database and artifacts
+                    which are not recorded in the database for their submit are removed as well.
+
+                    Staging directories which contain pinned artifacts (see `butido release pin --artifact`) and staging
+                    directories which are in use by another butido process are skipped.
+                "#))
+                .arg(arg_older_than_date("Remove staging directories older than DATE"))
+
+                .arg(Arg::new("keep_last")
                     .required(false)
                     .multiple(false)
-                    .index(2)
-                    .value_name("PKG")
-                    .about("The name of the package")
-                    .conflicts_with("all-packages")
+                    .long("keep-last")
+                    .takes_value(true)
+                    .value_name("N")
+                    .validator(parse_usize)
+                    .about("Keep the staging directories of the last N submits")
                 )
-                .arg(Arg::new("all-packages")
+
+                .arg(Arg::new("orphaned")
                     .required(false)
                     .multiple(false)
-                    .long("all")
-                    .about("Release all packages")
-                    .conflicts_with("package_name")
+                    .long("orphaned")
+                    .takes_value(false)
+                    .about("Remove staging directories and artifacts which are not known to the database")
                 )
-                .group(ArgGroup::new("package")
-                    .args(&["package_name", "all-packages"])
-                    .required(true) // one of these is required
+
+                .group(ArgGroup::new("policy")
+                    .args(&["older_than", "keep_last", "orphaned"])
+                    .required(true)
+                    .multiple(true)
                 )
-                .arg(Arg::new("package_version")
+
+                .arg(Arg::new("dry-run")
                     .required(false)
                     .multiple(false)
-                    .index(3)
-                    .value_name("VERSION")
-                    .about("The exact version of the package (string match)")
+                    .long("dry-run")
+                    .about("Only list what would be removed and how much space would be reclaimed")
+                )
+            )
+            .subcommand(App::new("release")
+                .version(crate_version!())
+                .about("Remove superseded and unreferenced artifacts from a release store")
+                .long_about(indoc::indoc!(r#"
+                    Remove superseded and unreferenced artifacts from a release store.
+
+                    With --keep-versions, only the N newest released versions of each package are kept, the releases of
+                    older versions are removed from the database and their files (and signatures) from the store.
+                    With --unreferenced, files in the store which are not released according to the database are removed.
+
+                    Pinned artifacts are never removed.
+                    The database is updated in one transaction before any file is removed. If butido is interrupted
+                    afterwards, the remaining files are removed by the next run with --unreferenced.
+                "#))
+                .arg(Arg::new("release")
+                    .required(true)
+                    .multiple(false)
+                    .long("release")
+                    .takes_value(true)
+                    .value_name("STORE")
+                    .about("Collect garbage in the release store STORE")
                 )
-                .arg(Arg::new("package_do_update")
+
+                .arg(Arg::new("keep_versions")
                     .required(false)
                     .multiple(false)
-                    .long("update")
-                    .about("Do update a package if it already exists in the release store")
+                    .long("keep-versions")
+                    .takes_value(true)
+                    .value_name("N")
+                    .validator(parse_usize)
+                    .about("Keep the N newest released versions of each package")
                 )
-                .arg(Arg::new("noninteractive")
+
+                .arg(Arg::new("unreferenced")
                     .required(false)
                     .multiple(false)
-                    .long("non-interactive")
-                    .about("Dont be interactive (only with --update at the moment)")
-                    .requires("package_do_update")
+                    .long("unreferenced")
+                    .takes_value(false)
+                    .about("Remove files which are not released according to the database")
                 )
-                .arg(Arg::new("quiet")
+
+                .group(ArgGroup::new("policy")
+                    .args(&["keep_versions", "unreferenced"])
+                    .required(true)
+                    .multiple(true)
+                )
+
+                .arg(Arg::new("dry-run")
                     .required(false)
                     .multiple(false)
-                    .long("quiet")
-                    .short('q')
-                    .about("Don't print pathes to released filesfiles  after releases are complete")
+                    .long("dry-run")
+                    .about("Only list what would be removed and how much space would be reclaimed")
                 )
             )
-
         )
 
-        .subcommand(App::new("lint")
+        .subcommand(App::new("verify")
             .version(crate_version!())
-            .about("Lint the package script of one or multiple packages")
-            .arg(Arg::new("package_name")
+            .about("Verify the signatures of released artifacts")
+            .long_about(indoc::indoc!(r#"
+                Verify the signatures of the artifacts in the release stores.
+
+                The newest release of each path in the stores is checked: the signature next to the artifact
+                must match the one recorded in the database when releasing and must be valid for the artifact.
+                Artifacts released without a signature are listed as "unsigned", but do not fail the command.
+                Requires `release_signing` to be configured.
+            "#))
+            .arg(Arg::new("csv")
                 .required(false)
                 .multiple(false)
-                .index(1)
-                .value_name("NAME")
-                .about("Package name to lint (if not present, every package will be linted")
+                .long("csv")
+                .takes_value(false)
+                .about("Format output as CSV")
             )
-            .arg(Arg::new("package_version")
+
+            .arg(Arg::new("release")
                 .required(false)
                 .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .long("release")
+                .takes_value(true)
+                .value_name("STORE")
+                .about("Only verify the release store STORE")
             )
         )
 
-        .subcommand(App::new("tree-of")
+        .subcommand(App::new("doctor")
             .version(crate_version!())
-            .about("Print the dependency tree of one or multiple packages")
-            .arg(Arg::new("package_name")
-                .required(true)
-                .multiple(false)
-                .index(1)
-                .value_name("NAME")
-                .about("Package name to lint (if not present, every package will be linted")
-            )
-            .arg(Arg::new("package_version")
+            .about("Check when the secrets and certificates butido uses expire")
+            .long_about(indoc::indoc!(r#"
+                Check when the secrets and certificates butido uses expire.
+
+                The gpg key released artifacts are signed with and the client certificates in the kubeconfig of
+                Kubernetes endpoints are checked. Items expiring within `expiry_warning_days` (default: 30) are
+                listed as "expires soon". Fails if one of them expired.
+            "#))
+            .arg(Arg::new("csv")
                 .required(false)
                 .multiple(false)
-                .index(2)
-                .value_name("VERSION_CONSTRAINT")
-                .about("A version constraint to search for (optional), E.G. '=1.0.0'")
+                .long("csv")
+                .takes_value(false)
+                .about("Format output as CSV")
             )
-            .arg(Arg::new("image")
-                .required(false)
-                .multiple(false)
-                .takes_value(true)
-                .value_name("IMAGE NAME")
-                .short('I')
-                .long("image")
-                .about("Name of the docker image to use")
+        )
+
+        .subcommand(App::new("log")
+            .version(crate_version!())
+            .about("Functionality for the logs of jobs")
+            .subcommand(App::new("follow")
+                .version(crate_version!())
+                .about("Follow the log of a running job")
                 .long_about(indoc::indoc!(r#"
-                    Name of the docker image to use.
+                    Follow the log of a running job, for example from a second terminal while a submit runs.
 
-                    Required because tree might look different on different images because of
-                    conditions on dependencies.
+                    The log lines of running jobs are recorded in the database while the job runs. This prints the
+                    lines recorded so far and waits for new ones until the job is finished.
+                    If there are no recorded lines for the job, the endpoints are searched for the container of the
+                    job and the command to attach to it is printed.
                 "#))
+                .arg(Arg::new("job_uuid")
+                    .required(true)
+                    .multiple(false)
+                    .index(1)
+                    .takes_value(true)
+                    .value_name("UUID")
+                    .about("The id of the Job")
+                )
             )
-            .arg(Arg::new("env")
+        )
+
+        .subcommand(App::new("plugins")
+            .version(crate_version!())
+            .about("List the plugins found on PATH")
+            .long_about(indoc::indoc!(r#"
+                List the plugins found on PATH.
+
+                A plugin is an executable named "butido-<subcommand>". It is run for "butido <subcommand>"
+                with the remaining arguments, in the root of the repository. The configuration, the path of
+                the repository and the database connection are passed as JSON in a file only readable by
+                the user, whose path is in the environment variable BUTIDO_PLUGIN_CONTEXT_FILE. Plugins
+                named like a builtin subcommand are not listed, they are never run.
+            "#))
+            .arg(Arg::new("csv")
                 .required(false)
-                .multiple(true)
-                .short('E')
-                .long("env")
-                .validator(env_pass_validator)
-                .about("Additional env to be passed when building packages")
-                .long_about(indoc::indoc!(r#"
-                    Additional env to be passed when building packages.
+                .multiple(false)
+                .long("csv")
+                .takes_value(false)
+                .about("Format output as CSV")
+            )
+        )
 
-                    Required because tree might look different on different images because of
-                    conditions on dependencies.
-                "#))
+        .subcommand(App::new("explain-rebuild")
+            .version(crate_version!())
+            .about("Explain why a job was built instead of reusing artifacts")
+            .long_about(indoc::indoc!(r#"
+                Explain why a job was built instead of reusing the artifacts of an earlier job.
+
+                When a job is built, the most recent earlier jobs of the same package in the same version are
+                checked, and the criteria they did not match are recorded: e.g. an environment variable that
+                differed, a different image or script, or an artifact that is missing from the stores.
+                Each line names the earlier job (the candidate) and the criterion it did not match. Lines
+                without candidate apply to the job as a whole, e.g. because a dependency was built.
+            "#))
+            .arg(Arg::new("csv")
+                .required(false)
+                .multiple(false)
+                .long("csv")
+                .takes_value(false)
+                .about("Format output as CSV")
+            )
+            .arg(Arg::new("job_uuid")
+                .required(true)
+                .multiple(false)
+                .index(1)
+                .takes_value(true)
+                .value_name("UUID")
+                .about("The id of the Job")
             )
         )
 
-        .subcommand(App::new("metrics")
+        .subcommand(App::new("audit")
             .version(crate_version!())
-            .about("Print metrics about butido")
+            .about("Functionality for the audit log")
+            .subcommand(App::new("verify")
+                .version(crate_version!())
+                .about("Verify the hash chain of the audit log")
+                .long_about(indoc::indoc!(r#"
+                    Verify the hash chain of the audit log.
+
+                    Each entry of the audit log contains the hash of the previous entry. This command
+                    re-computes all hashes and fails if an entry was modified or removed.
+                    On success, the hash of the newest entry is printed. It can be stored
+                    elsewhere to be able to detect removal of the newest entries later.
+                "#))
+            )
         )
 
         .subcommand(App::new("endpoint")
@@ -1108,6 +2557,17 @@ pub fn cli<'a>() -> App<'a> {
                 .about("Endpoint to talk to, or all if not given")
             )
 
+            .subcommand(App::new("list")
+                .version(crate_version!())
+                .about("List the endpoint(s) with reachability, engine version, running jobs and images")
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+            )
             .subcommand(App::new("ping")
                 .version(crate_version!())
                 .about("Ping the endpoint(s)")
@@ -1322,7 +2782,231 @@ pub fn cli<'a>() -> App<'a> {
                     )
                 )
             )
+            .subcommand(App::new("drain")
+                .version(crate_version!())
+                .about("Drain the endpoint(s) for maintenance")
+                .long_about(indoc::indoc!(r#"
+                    Drain the endpoint(s) for maintenance.
+
+                    No new jobs are scheduled to a drained endpoint. Jobs that are already running on the endpoint are not aborted but finish normally.
+                    The drained state is persisted in the database, so running submits pick it up as well.
+                "#))
+            )
+            .subcommand(App::new("undrain")
+                .version(crate_version!())
+                .about("Undrain the endpoint(s), so that jobs get scheduled to it again")
+            )
+            .subcommand(App::new("checkpoint")
+                .version(crate_version!())
+                .about("Drain the endpoint(s) and abort the running jobs by checkpointing their containers")
+                .long_about(indoc::indoc!(r#"
+                    Drain the endpoint(s) and abort the running jobs by checkpointing their containers, so that the endpoint(s) can
+                    be maintained.
+
+                    The containers are checkpointed to disk with CRIU and stopped. Their jobs are aborted gracefully: the log of each
+                    job ends with a failed state saying that it was aborted for endpoint maintenance, and the job is built again if
+                    its retry policy allows. butido does not restore the containers, they are kept with their checkpoints so that
+                    they can be inspected (or restored by hand) and have to be removed after the maintenance. Undrain the endpoint(s)
+                    with `butido endpoint undrain` afterwards.
+
+                    This requires CRIU on the endpoint(s) and, for docker, a daemon with experimental features enabled. Kubernetes
+                    endpoints are not supported.
+                "#))
+            )
+            .subcommand(App::new("checkpoints")
+                .version(crate_version!())
+                .about("List the checkpoints of the endpoint(s)")
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .about("Format output as CSV")
+                )
+            )
+            .subcommand(App::new("scratch")
+                .version(crate_version!())
+                .about("List the job working directories in the scratch space of the endpoint(s)")
+                .long_about(indoc::indoc!(r#"
+                    List the job working directories in the scratch space of the endpoint(s).
+
+                    The working directory of a job is removed after its artifacts were copied
+                    out of the container, directories of failed jobs are kept as long as
+                    configured with "keep_failed_hours". Directories of jobs which were
+                    interrupted (e.g. because butido was killed) are leaked.
+
+                    With --clean, the directories which are not used by a running container
+                    and are older than "keep_failed_hours" are removed.
+                "#))
+                .arg(Arg::new("clean")
+                    .required(false)
+                    .multiple(false)
+                    .long("clean")
+                    .takes_value(false)
+                    .about("Remove the unused directories (asks for confirmation)")
+                )
+                .arg(Arg::new("csv")
+                    .required(false)
+                    .multiple(false)
+                    .long("csv")
+                    .takes_value(false)
+                    .conflicts_with("clean")
+                    .about("Format output as CSV")
+                )
+            )
         )
+
+        .subcommands(api_subcommands())
+        .subcommands(serve_subcommands())
+}
+
+/// The subcommands of the HTTP API, which are only available if butido is built with the "api"
+/// feature
+fn api_subcommands<'a>() -> Vec<App<'a>> {
+    #[cfg(feature = "api")]
+    {
+        vec![App::new("serve-api")
+            .version(crate_version!())
+            .about("Serve a read-only HTTP API for submits, jobs, artifacts and packages")
+            .long_about(indoc::indoc!(r#"
+                Serve a read-only HTTP API for submits, jobs, artifacts and packages.
+
+                All responses are JSON. The following endpoints are available:
+
+                    /api/v1/submits             List submits
+                    /api/v1/submits/<uuid>      Show a submit and its jobs
+                    /api/v1/jobs/<uuid>         Show a job, including script and log
+                    /api/v1/artifacts           List artifacts (filter with ?package=<name>&version=<version>)
+                    /api/v1/packages            List packages (filter with ?package=<name>&version=<version>)
+                    /api/v1/schemas/<name>      JSON schema of the responses (submits, submit, job, artifacts, packages)
+
+                Lists are paginated with ?limit=<n>&offset=<n>.
+            "#))
+            .arg(Arg::new("listen")
+                .required(false)
+                .multiple(false)
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:8080")
+                .about("The address to listen on")
+            )
+        ]
+    }
+
+    #[cfg(not(feature = "api"))]
+    {
+        vec![]
+    }
+}
+
+/// The subcommand for serving the artifact stores, which is only available if butido is built with
+/// the "serve" feature
+fn serve_subcommands<'a>() -> Vec<App<'a>> {
+    #[cfg(feature = "serve")]
+    {
+        vec![App::new("serve")
+            .version(crate_version!())
+            .about("Serve the artifact stores over HTTP")
+            .long_about(indoc::indoc!(r#"
+                Serve the release stores (and optionally the staging directories) over HTTP, so that the
+                artifacts can be fetched without mounting the stores.
+
+                The following paths are available:
+
+                    /index.json                     Released artifacts per package and version
+                                                    (filter with ?package=<name>&version=<version>)
+                    /releases/<store>/<path>        A released artifact
+                    /staging/<uuid>/index.json      Artifacts of a submit per package and version (with --staging)
+                    /staging/<uuid>/<path>          An artifact of a submit (with --staging)
+            "#))
+            .arg(Arg::new("listen")
+                .required(false)
+                .multiple(false)
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1:8081")
+                .about("The address to listen on")
+            )
+            .arg(Arg::new("release")
+                .required(false)
+                .multiple(true)
+                .long("release")
+                .takes_value(true)
+                .value_name("STORE")
+                .about("Only serve the release store STORE (can be given multiple times, default: all release stores)")
+            )
+            .arg(Arg::new("staging")
+                .required(false)
+                .multiple(false)
+                .long("staging")
+                .takes_value(false)
+                .about("Serve the staging directories of the submits, too")
+            )
+        ]
+    }
+
+    #[cfg(not(feature = "serve"))]
+    {
+        vec![]
+    }
+}
+
+/// The arguments for serving metrics of a submit, which are only available if butido is built with
+/// the "metrics" feature
+fn metrics_args<'a>() -> Vec<Arg<'a>> {
+    #[cfg(feature = "metrics")]
+    {
+        vec![Arg::new("metrics_addr")
+            .required(false)
+            .multiple(false)
+            .long("metrics-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .conflicts_with("dry-run")
+            .about("Serve Prometheus metrics of the submit on ADDR while it runs")
+            .long_about(indoc::indoc!(r#"
+                Serve Prometheus metrics of the submit on ADDR (e.g. "127.0.0.1:9090") below `/metrics` while it runs.
+
+                The metrics are the number of jobs by state, the containers running per endpoint, the jobs which reused
+                artifacts, the latency of database writes and the build durations per package.
+            "#))
+        ]
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    {
+        vec![]
+    }
+}
+
+/// The arguments for selecting the output format of the "db" subcommands
+fn output_args<'a>() -> Vec<Arg<'a>> {
+    vec![
+        Arg::new("output")
+            .required(false)
+            .multiple(false)
+            .long("output")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(&["table", "csv", "json"])
+            .about("Print the output as FORMAT")
+            .long_about(indoc::indoc!(r#"
+                Print the output as FORMAT, one of "table" (the default), "csv" or "json".
+
+                With "json", the output is an array with one object per row, keyed by the lowercased column names, with
+                spaces replaced by underscores (e.g. "package_version"). If there is nothing to list, an empty array is
+                printed.
+            "#)),
+
+        Arg::new("json")
+            .required(false)
+            .multiple(false)
+            .long("json")
+            .conflicts_with("output")
+            .about("Format output as JSON, same as --output json"),
+    ]
 }
 
 fn script_arg_line_numbers<'a>() -> clap::Arg<'a> {
@@ -1343,6 +3027,45 @@ fn script_arg_no_line_numbers<'a>() -> clap::Arg<'a> {
         .conflicts_with("script_line_numbers")
 }
 
+fn arg_at_revision<'a>() -> clap::Arg<'a> {
+    Arg::new("at")
+        .required(false)
+        .multiple(false)
+        .long("at")
+        .takes_value(true)
+        .value_name("REV")
+        .about("Query the repository as it was at the git revision REV")
+        .long_about(indoc::indoc!(r#"
+            Query the repository as it was at the git revision REV (a commit id, branch, tag or e.g. "HEAD~3").
+
+            The packages are read from the commit, the working tree is not touched. The additional package
+            repositories are used as they are now.
+        "#))
+}
+
+fn batch_arg_stdin<'a>() -> clap::Arg<'a> {
+    Arg::new("stdin")
+        .required(false)
+        .multiple(false)
+        .long("stdin")
+        .about("Read the packages to query from stdin and print the results as one JSON document")
+        .long_about(indoc::indoc!(r#"
+            Read the packages to query from stdin, one per line, as the package name optionally followed by
+            a version constraint (e.g. "foo >=1.0.0"). Empty lines and lines starting with '#' are ignored.
+
+            The results for all packages are printed as one JSON document, or as CSV with `--csv`.
+        "#))
+}
+
+fn batch_arg_csv<'a>() -> clap::Arg<'a> {
+    Arg::new("csv")
+        .required(false)
+        .multiple(false)
+        .long("csv")
+        .requires("stdin")
+        .about("Print the results of --stdin as CSV instead of JSON")
+}
+
 fn script_arg_highlight<'a>() -> clap::Arg<'a> {
     Arg::new("script_highlight")
         .required(false)
@@ -1477,6 +3200,10 @@ fn parse_date_from_string(s: &str) -> std::result::Result<(), String> {
         })
 }
 
+fn parse_duration(s: &str) -> std::result::Result<(), String> {
+    humantime::parse_duration(s).map_err(|e| e.to_string()).map(|_| ())
+}
+
 fn parse_usize(s: &str) -> std::result::Result<(), String> {
     usize::from_str(s) .map_err(|e| e.to_string()).map(|_| ())
 }
@@ -1485,8 +3212,13 @@ fn parse_u64(s: &str) -> std::result::Result<(), String> {
     u64::from_str(s).map_err(|e| e.to_string()).map(|_| ())
 }
 
+fn parse_i32(s: &str) -> std::result::Result<(), String> {
+    i32::from_str(s).map_err(|e| e.to_string()).map(|_| ())
+}
+
 #[cfg(test)]
 mod tests {
+    use super::cli;
     use super::env_pass_validator;
 
     #[test]
@@ -1563,4 +3295,19 @@ mod tests {
     fn test_env_pass_validator_15() {
         assert!(env_pass_validator("123").is_err());
     }
+
+    #[test]
+    fn test_build_verbosity() {
+        let verbosity = |args: &[&str]| {
+            let args = args.iter().chain(["-I", "debian:bullseye"].iter());
+            let matches = cli().try_get_matches_from(args).unwrap();
+            let (_, build) = matches.subcommand().unwrap();
+            (build.occurrences_of("verbose"), build.is_present("stream-logs"))
+        };
+
+        assert_eq!(verbosity(&["butido", "build", "foo"]), (0, false));
+        assert_eq!(verbosity(&["butido", "build", "-vv", "foo"]), (2, false));
+        assert_eq!(verbosity(&["butido", "build", "-v", "-v", "foo"]), (2, false));
+        assert_eq!(verbosity(&["butido", "build", "--stream-logs", "foo"]), (0, true));
+    }
 }
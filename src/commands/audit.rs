@@ -0,0 +1,59 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'audit' subcommand
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use log::debug;
+
+use crate::db::models::AuditLogEntry;
+use crate::db::models::AUDIT_LOG_GENESIS_HASH;
+use crate::db::DbConnectionConfig;
+
+/// Implementation of the "audit" subcommand
+pub fn audit(db_connection_config: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("verify", _matches)) => verify(db_connection_config),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// Implementation of the "audit verify" subcommand
+fn verify(db_connection_config: DbConnectionConfig<'_>) -> Result<()> {
+    let conn = db_connection_config.establish_connection()?;
+    let entries = AuditLogEntry::all(&conn)?;
+
+    let head = entries.iter().try_fold(String::from(AUDIT_LOG_GENESIS_HASH), |prev, entry| {
+        debug!("Verifying audit log entry {}", entry.id);
+        if entry.prev_hash != prev {
+            return Err(anyhow!(
+                "Audit log chain broken at entry {}: expected previous hash {}, found {}",
+                entry.id,
+                prev,
+                entry.prev_hash
+            ));
+        }
+
+        if entry.compute_hash() != entry.hash {
+            return Err(anyhow!("Audit log entry {} was modified", entry.id));
+        }
+
+        Ok(entry.hash.clone())
+    })?;
+
+    writeln!(std::io::stdout(), "Verified {} audit log entries, head: {}", entries.len(), head)
+        .map_err(Error::from)
+}
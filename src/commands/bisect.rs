@@ -0,0 +1,388 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'bisect' subcommand
+
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use colored::Colorize;
+use diesel::PgConnection;
+use git2::Oid;
+use log::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+use crate::db::DbPool;
+use crate::db::models as dbmodels;
+use crate::log::LogFormat;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::repository::Repository;
+use crate::util::EnvironmentVariableName;
+use crate::util::progress::ProgressBars;
+
+/// The result of building the package at one commit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Good,
+    Bad,
+
+    /// The build failed before a submit was created, e.g. because the package did not exist at
+    /// the commit
+    Skip,
+}
+
+/// What is built at each commit, taken from the bad submit
+struct BuildSpec {
+    package_name: String,
+    package_version: String,
+    image: String,
+    env: Vec<(EnvironmentVariableName, String)>,
+}
+
+/// Implementation of the "bisect" subcommand
+pub async fn bisect(
+    repo_path: &Path,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    log_format: LogFormat,
+) -> Result<()> {
+    let package_name = matches.value_of("package").unwrap(); // safe by clap
+    let pool = db_connection_config.establish_pool()?;
+
+    let (good_hash, bad_hash, spec) = {
+        let conn = pool.get()?;
+        let (good, good_hash) = load_submit(&conn, matches.value_of("good").unwrap(), package_name)?; // safe by clap
+        let (bad, bad_hash) = load_submit(&conn, matches.value_of("bad").unwrap(), package_name)?; // safe by clap
+
+        if good.requested_image_id != bad.requested_image_id {
+            warn!("Submits {} and {} were built on different images, bisecting on the image of {}", good.uuid, bad.uuid, bad.uuid);
+        }
+
+        let package = dbmodels::Package::fetch_by_id(&conn, bad.requested_package_id)?
+            .ok_or_else(|| anyhow!("Package for submit {} not found", bad.uuid))?;
+        let image = dbmodels::Image::fetch_by_id(&conn, bad.requested_image_id)?
+            .ok_or_else(|| anyhow!("Image for submit {} not found", bad.uuid))?;
+        let env = super::build::submit_env(&conn, &bad)?;
+
+        let spec = BuildSpec {
+            package_name: package.name,
+            package_version: package.version,
+            image: image.name,
+            env,
+        };
+        (good_hash, bad_hash, spec)
+    };
+
+    let git_repo = git2::Repository::open(repo_path)
+        .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))?;
+    let good_oid = Oid::from_str(&good_hash).with_context(|| anyhow!("Parsing commit hash {}", good_hash))?;
+    let bad_oid = Oid::from_str(&bad_hash).with_context(|| anyhow!("Parsing commit hash {}", bad_hash))?;
+    if !git_repo.graph_descendant_of(bad_oid, good_oid)? {
+        return Err(anyhow!(
+            "Commit {} of the bad submit is not a descendant of commit {} of the good submit",
+            bad_hash, good_hash
+        ));
+    }
+
+    let commits = commits_between(&git_repo, good_oid, bad_oid)?;
+    writeln!(std::io::stdout(), "Bisecting {} commits between {} and {}",
+        commits.len().to_string().green(), good_hash, bad_hash)?;
+
+    let mut bisection = Bisection::new(commits);
+    while let Some(idx) = bisection.next() {
+        let commit = bisection.commits[idx];
+        info!("Building {} at {}", spec.package_name, commit);
+        let outcome = build_at(repo_path, commit, &spec, &progressbars, &pool, config, log_format).await?;
+        bisection.record(idx, outcome);
+
+        let outcome = match outcome {
+            Outcome::Good => "good".green(),
+            Outcome::Bad => "bad".red(),
+            Outcome::Skip => "skipped".yellow(),
+        };
+        writeln!(std::io::stdout(), "{}: {} ({} commits left)", commit, outcome, bisection.remaining())?;
+    }
+
+    let (first_bad, skipped) = bisection.result();
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    let first_bad_commit = git_repo.find_commit(first_bad)?;
+    writeln!(outlock, "First bad commit: {} {}",
+        first_bad.to_string().red(),
+        first_bad_commit.summary().unwrap_or(""))?;
+
+    if !skipped.is_empty() {
+        writeln!(outlock, "The package could not be built at these commits before it, one of them might be the first bad commit:")?;
+        for commit in skipped {
+            let summary = git_repo.find_commit(commit)?.summary().map(String::from).unwrap_or_default();
+            writeln!(outlock, "  {} {}", commit, summary)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Load a submit and the hash of the commit it was built at, and check that it built `package_name`
+fn load_submit(conn: &PgConnection, uuid: &str, package_name: &str) -> Result<(dbmodels::Submit, String)> {
+    let uuid = Uuid::parse_str(uuid).context("Parsing submit UUID")?;
+    let submit = dbmodels::Submit::with_id(conn, &uuid)
+        .with_context(|| anyhow!("Loading submit '{}' from DB", uuid))?;
+    let package = dbmodels::Package::fetch_by_id(conn, submit.requested_package_id)?
+        .ok_or_else(|| anyhow!("Package for submit {} not found", uuid))?;
+
+    if package.name != package_name {
+        return Err(anyhow!("Submit {} built {}, not {}", uuid, package.name, package_name));
+    }
+
+    let githash = dbmodels::GitHash::with_id(conn, submit.repo_hash_id)?;
+    Ok((submit, githash.hash))
+}
+
+/// The commits after `good` up to and including `bad`, oldest first
+fn commits_between(git_repo: &git2::Repository, good: Oid, bad: Oid) -> Result<Vec<Oid>> {
+    let mut revwalk = git_repo.revwalk()?;
+    revwalk.push(bad)?;
+    revwalk.hide(good)?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.map(|oid| oid.map_err(Error::from)).collect()
+}
+
+/// Build the package in a worktree of the repository at `commit`
+///
+/// The build runs like a normal submit, so artifacts of earlier builds are reused where possible.
+/// The submit UUID is chosen upfront (via the staging directory), so that a failed build can be
+/// told apart from a build that did not even start.
+async fn build_at(
+    repo_path: &Path,
+    commit: Oid,
+    spec: &BuildSpec,
+    progressbars: &ProgressBars,
+    pool: &DbPool,
+    config: &Configuration,
+    log_format: LogFormat,
+) -> Result<Outcome> {
+    let worktree = Worktree::add(repo_path, commit)?;
+    let repo = {
+        let bar = progressbars.bar();
+        let repo = Repository::load(worktree.path(), worktree.path(), config.package_repositories(), &bar)
+            .with_context(|| anyhow!("Loading the repository at {}", commit))?;
+        bar.finish_with_message("Repository loading finished");
+        repo
+    };
+
+    // The version might not exist at all commits, build the package by name then
+    let name = PackageName::from(spec.package_name.clone());
+    let version = PackageVersion::from(spec.package_version.clone());
+    let with_version = !repo.find(&name, &version).is_empty();
+
+    let submit_id = Uuid::new_v4();
+    let staging_dir = config.staging_directory().join(submit_id.hyphenated().to_string());
+    tokio::fs::create_dir_all(&staging_dir)
+        .await
+        .with_context(|| anyhow!("Creating staging directory {}", staging_dir.display()))?;
+
+    let env = spec.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>();
+    let staging_dir_arg = staging_dir.display().to_string();
+    let mut args = vec!["butido", "build", spec.package_name.as_str()];
+    if with_version {
+        args.push(spec.package_version.as_str());
+    }
+    args.extend(&["--image", spec.image.as_str(), "--staging-dir", staging_dir_arg.as_str()]);
+    for e in env.iter() {
+        args.extend(&["--env", e.as_str()]);
+    }
+    debug!("Running build with {:?}", args);
+
+    let app_matches = crate::cli::cli()
+        .try_get_matches_from(args)
+        .context("Constructing arguments for build")?;
+    let build_matches = app_matches.subcommand_matches("build").unwrap(); // safe by construction
+
+    let result = crate::commands::build(worktree.path(), build_matches, progressbars.clone(), pool.clone(), config, repo, worktree.path(), log_format, None).await;
+    match result {
+        Ok(()) => Ok(Outcome::Good),
+        Err(e) => {
+            if dbmodels::Submit::find_by_uuid(&*pool.get()?, &submit_id)?.is_some() {
+                debug!("Build at {} failed: {:?}", commit, e);
+                Ok(Outcome::Bad)
+            } else {
+                warn!("Build at {} could not be started: {:?}", commit, e);
+                let _ = tokio::fs::remove_dir(&staging_dir).await;
+                Ok(Outcome::Skip)
+            }
+        }
+    }
+}
+
+/// A git worktree with a detached HEAD, removed when dropped
+struct Worktree<'a> {
+    repo_path: &'a Path,
+    path: PathBuf,
+}
+
+impl<'a> Worktree<'a> {
+    fn add(repo_path: &'a Path, commit: Oid) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("butido-bisect-{}-{}", commit, Uuid::new_v4()));
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo_path)
+            .args(&["worktree", "add", "--quiet", "--detach"])
+            .arg(&path)
+            .arg(commit.to_string())
+            .status()
+            .context("Running git worktree")?;
+
+        if status.success() {
+            Ok(Worktree { repo_path, path })
+        } else {
+            Err(anyhow!("Creating a worktree at {} for {} failed: {}", path.display(), commit, status))
+        }
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<'a> Drop for Worktree<'a> {
+    fn drop(&mut self) {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(self.repo_path)
+            .args(&["worktree", "remove", "--force"])
+            .arg(&self.path)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => {},
+            Ok(status) => warn!("Removing worktree {} failed: {}", self.path.display(), status),
+            Err(e) => warn!("Removing worktree {} failed: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// The state of a bisection over commits, ordered from oldest to newest
+///
+/// The commit before the first one is known to be good, the last one is known to be bad.
+struct Bisection<T> {
+    commits: Vec<T>,
+
+    /// The index of the first commit that is not known to be good
+    lo: usize,
+
+    /// The index of the first commit that is known to be bad
+    hi: usize,
+
+    /// The indices of the commits the package could not be built at
+    skipped: BTreeSet<usize>,
+}
+
+impl<T: Copy> Bisection<T> {
+    fn new(commits: Vec<T>) -> Self {
+        let hi = commits.len().saturating_sub(1);
+        Bisection { commits, lo: 0, hi, skipped: BTreeSet::new() }
+    }
+
+    fn candidates(&self) -> Vec<usize> {
+        (self.lo..self.hi).filter(|i| !self.skipped.contains(i)).collect()
+    }
+
+    /// The index of the commit to build next, `None` if the bisection is finished
+    fn next(&self) -> Option<usize> {
+        let candidates = self.candidates();
+        candidates.get(candidates.len() / 2).copied()
+    }
+
+    fn remaining(&self) -> usize {
+        self.candidates().len()
+    }
+
+    fn record(&mut self, idx: usize, outcome: Outcome) {
+        match outcome {
+            Outcome::Good => self.lo = idx + 1,
+            Outcome::Bad => self.hi = idx,
+            Outcome::Skip => {
+                self.skipped.insert(idx);
+            },
+        }
+    }
+
+    /// The first bad commit, and the skipped commits before it which might be the first bad commit
+    /// instead
+    fn result(&self) -> (T, Vec<T>) {
+        let skipped = (self.lo..self.hi).map(|i| self.commits[i]).collect();
+        (self.commits[self.hi], skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(n: usize, outcome: impl Fn(usize) -> Outcome) -> (usize, Vec<usize>, usize) {
+        let mut bisection = Bisection::new((0..n).collect());
+        let mut builds = 0;
+        while let Some(idx) = bisection.next() {
+            bisection.record(idx, outcome(bisection.commits[idx]));
+            builds += 1;
+        }
+        let (first_bad, skipped) = bisection.result();
+        (first_bad, skipped, builds)
+    }
+
+    #[test]
+    fn test_bisection_finds_first_bad_commit() {
+        for first_bad in 0..20 {
+            let (found, skipped, builds) = run(20, |c| if c >= first_bad { Outcome::Bad } else { Outcome::Good });
+            assert_eq!(found, first_bad);
+            assert!(skipped.is_empty());
+            assert!(builds <= 5, "{} builds for 20 commits", builds);
+        }
+    }
+
+    #[test]
+    fn test_bisection_with_skipped_commits() {
+        let (found, skipped, _) = run(10, |c| match c {
+            5 => Outcome::Skip,
+            c if c >= 6 => Outcome::Bad,
+            _ => Outcome::Good,
+        });
+        assert_eq!(found, 6);
+        assert_eq!(skipped, vec![5]);
+
+        let (found, skipped, _) = run(10, |c| match c {
+            5 => Outcome::Skip,
+            c if c >= 3 => Outcome::Bad,
+            _ => Outcome::Good,
+        });
+        assert_eq!(found, 3);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_bisection_single_commit() {
+        let (found, skipped, builds) = run(1, |_| unreachable!());
+        assert_eq!(found, 0);
+        assert!(skipped.is_empty());
+        assert_eq!(builds, 0);
+    }
+}
@@ -10,6 +10,7 @@
 
 //! Implementation of the 'build' subcommand
 
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
@@ -27,19 +28,26 @@ use diesel::PgConnection;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
 use itertools::Itertools;
-use log::{debug, info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use tokio::sync::RwLock;
 use tokio_stream::StreamExt;
 use uuid::Uuid;
 
 use crate::config::*;
+use crate::db::get_connection;
+use crate::db::DbPool;
+use crate::endpoint::EndpointConfiguration;
+use crate::endpoint::SharedEndpoints;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
 use crate::filestore::path::StoreRoot;
 use crate::job::JobResource;
+use crate::log::LogFormat;
 use crate::log::LogItem;
+use crate::orchestrator::ForceRebuild;
 use crate::orchestrator::OrchestratorSetup;
-use crate::package::Dag;
+use crate::orchestrator::PackageFilter;
+use crate::orchestrator::Timeout;
 use crate::package::PackageName;
 use crate::package::PackageVersion;
 use crate::package::Shebang;
@@ -47,8 +55,10 @@ use crate::package::condition::ConditionData;
 use crate::repository::Repository;
 use crate::schema;
 use crate::source::SourceCache;
+use crate::util::Architecture;
 use crate::util::EnvironmentVariableName;
 use crate::util::docker::ImageName;
+use crate::util::metrics::Metrics;
 use crate::util::progress::ProgressBars;
 
 /// Implementation of the "build" subcommand
@@ -57,31 +67,93 @@ pub async fn build(
     repo_root: &Path,
     matches: &ArgMatches,
     progressbars: ProgressBars,
-    database_connection: PgConnection,
+    database_pool: DbPool,
     config: &Configuration,
     repo: Repository,
     repo_path: &Path,
+    log_format: LogFormat,
+    mut shared_endpoints: Option<SharedEndpoints>,
 ) -> Result<()> {
-    use crate::db::models::{EnvVar, GitHash, Image, Job, Package, Submit};
+    use crate::db::models::{AuditLogEntry, EnvVar, GitHash, Image, Job, Package, ScheduledSubmit, Submit, SubmitEnv};
 
+    let database_connection = get_connection(&database_pool).await?;
     let git_repo = git2::Repository::open(repo_path)
         .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))?;
 
     let _ = crate::ui::package_repo_cleanness_check(&git_repo)?;
     let now = chrono::offset::Local::now().naive_local();
 
+    // If a submit is resumed, the package and the image are the ones of that submit
+    let resumed = matches
+        .value_of("resume")
+        .map(|uuid| -> Result<_> {
+            let uuid = Uuid::parse_str(uuid).context("Parsing submit UUID")?;
+            let submit = Submit::with_id(&database_connection, &uuid)
+                .with_context(|| anyhow!("Loading submit '{}' from DB", uuid))?;
+            let package = Package::fetch_by_id(&database_connection, submit.requested_package_id)?
+                .ok_or_else(|| anyhow!("Package for submit {} not found", uuid))?;
+            let image = Image::fetch_by_id(&database_connection, submit.requested_image_id)?
+                .ok_or_else(|| anyhow!("Image for submit {} not found", uuid))?;
+            let githash = GitHash::with_id(&database_connection, submit.repo_hash_id)?;
+            Ok((submit, package, image, githash))
+        })
+        .transpose()?;
+
+    let use_artifacts_from = matches
+        .value_of("use-artifacts-from")
+        .map(|uuid| -> Result<_> {
+            let uuid = Uuid::parse_str(uuid).context("Parsing submit UUID")?;
+            Submit::with_id(&database_connection, &uuid)
+                .with_context(|| anyhow!("Loading submit '{}' from DB", uuid))
+        })
+        .transpose()?;
+
+    let image_name = if let Some((_, _, image, _)) = resumed.as_ref() {
+        ImageName::from(image.name.clone())
+    } else {
+        matches
+            .value_of("image")
+            .map(String::from)
+            .map(ImageName::from)
+            .unwrap() // safe by clap
+    };
+    let image_defaults = config.docker().image_defaults().get(&image_name);
+    // Offline builds are hermetic, the containers do not get network either
+    let offline = matches.is_present("offline");
+    let hermetic = offline || matches.is_present("hermetic");
+    let architectures = matches
+        .values_of("arch")
+        .map(|vals| vals.map(String::from).map(Architecture::from).unique().collect::<Vec<_>>())
+        .unwrap_or_default();
+    let reuse_policy = if matches.is_present("reuse-any") {
+        ReusePolicy::any()
+    } else {
+        config.reuse_policy()
+            .clone()
+            .with_release_only(matches.is_present("reuse-release-only"))
+            .with_signed_only(matches.is_present("reuse-signed-only"))
+            .with_hermetic_only(matches.is_present("reuse-hermetic-only"))
+            .with_submitted_by({
+                matches.values_of("reuse-submitted-by")
+                    .map(|vals| vals.map(String::from).collect())
+                    .unwrap_or_default()
+            })
+    };
+    // Explaining the reuse decisions plans the submit like a dry run
+    let explain_reuse = matches.value_of("explain-reuse").map(PathBuf::from);
+    let dry_run = matches.is_present("dry-run") || explain_reuse.is_some();
+    let timeout = matches
+        .value_of("timeout")
+        .map(humantime::parse_duration)
+        .transpose()?; // validated by clap
+
     let shebang = Shebang::from({
         matches
             .value_of("shebang")
             .map(String::from)
+            .or_else(|| image_defaults.and_then(|d| d.shebang().clone()))
             .unwrap_or_else(|| config.shebang().clone())
     });
-
-    let image_name = matches
-        .value_of("image")
-        .map(String::from)
-        .map(ImageName::from)
-        .unwrap(); // safe by clap
     if config.docker().verify_images_present()
         && !config
             .docker()
@@ -96,52 +168,78 @@ pub async fn build(
         .with_context(|| anyhow!("Image present verification failed"))
         .map_err(Error::from);
     }
+    if !config.docker().endpoints().values().any(|ep| ep.supports_image(&image_name)) {
+        return Err(anyhow!("No endpoint supports the requested build image {}", image_name))
+            .with_context(|| anyhow!("Check the 'images' of the endpoints in the configuration"))
+            .map_err(Error::from);
+    }
 
     debug!("Getting repository HEAD");
     let hash_str = crate::util::git::get_repo_head_commit_hash(&git_repo)?;
     trace!("Repository HEAD = {}", hash_str);
+    if let Some((submit, _, _, githash)) = resumed.as_ref() {
+        if githash.hash != hash_str {
+            return Err(anyhow!(
+                "Cannot resume submit {}: it was started at commit {}, but the repository is at {}",
+                submit.uuid, githash.hash, hash_str
+            ));
+        }
+    }
     let phases = config.available_phases();
 
-    let mut endpoint_configurations = config
-        .docker()
-        .endpoints()
-        .iter()
-        .map(|(ep_name, ep_cfg)| {
-            crate::endpoint::EndpointConfiguration::builder()
-                .endpoint_name(ep_name.clone())
-                .endpoint(ep_cfg.clone())
-                .required_images(config.docker().images().clone())
-                .required_docker_versions(config.docker().docker_versions().clone())
-                .required_docker_api_versions(config.docker().docker_api_versions().clone())
-                .build()
-        })
-        .collect::<Vec<_>>();
-    {
-        // Because we're loading always sequencially, to have a bit more spread over the endpoints,
-        // shuffle the endpoints here. Not a perfect solution, but a working one.
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        rng.shuffle(&mut endpoint_configurations);
-    }
+    // Shared endpoints are already connected
+    let mut endpoint_configurations = if shared_endpoints.is_some() {
+        Vec::new()
+    } else {
+        endpoint_configurations(config)
+    };
     info!("Endpoint config build");
 
-    let pname = matches
-        .value_of("package_name")
-        .map(String::from)
-        .map(PackageName::from)
-        .unwrap(); // safe by clap
+    let (pname, pvers) = if let Some((_, package, _, _)) = resumed.as_ref() {
+        (PackageName::from(package.name.clone()), Some(PackageVersion::from(package.version.clone())))
+    } else {
+        let pname = matches
+            .value_of("package_name")
+            .map(String::from)
+            .map(PackageName::from)
+            .unwrap(); // safe by clap
 
-    let pvers = matches
-        .value_of("package_version")
-        .map(String::from)
-        .map(PackageVersion::from);
+        let pvers = matches
+            .value_of("package_version")
+            .map(String::from)
+            .map(PackageVersion::from);
+
+        (pname, pvers)
+    };
     info!("We want {} ({:?})", pname, pvers);
 
-    let additional_env = matches
-        .values_of("env")
-        .unwrap_or_default()
-        .map(crate::util::env::parse_to_env)
-        .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?;
+    // The variables passed with --env, which are recorded with the submit
+    let cli_env = if let Some((submit, _, _, _)) = resumed.as_ref() {
+        submit_env(&database_connection, submit)?
+    } else {
+        matches
+            .values_of("env")
+            .unwrap_or_default()
+            .map(crate::util::env::parse_to_env)
+            .collect::<Result<Vec<(EnvironmentVariableName, String)>>>()?
+    };
+
+    let additional_env = {
+        let mut env = cli_env.clone();
+
+        // The defaults of the image are only used if not overridden on the commandline
+        if let Some(defaults) = image_defaults {
+            let default_env = defaults.env()
+                .iter()
+                .filter(|(k, _)| !env.iter().any(|(name, _)| name == *k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .sorted()
+                .collect::<Vec<_>>();
+            env.extend(default_env);
+        }
+
+        env
+    };
 
     let packages = if let Some(pvers) = pvers {
         debug!("Searching for package with version: '{}' '{}'", pname, pvers);
@@ -164,28 +262,68 @@ pub async fn build(
         .get(0)
         .ok_or_else(|| anyhow!("Found no package."))?;
 
-    let release_stores = config
-        .release_stores()
-        .iter()
-        .map(|storename| {
-            let bar_release_loading = progressbars.bar();
+    // Loading the stores only needs the filesystem, so they are loaded on the blocking thread pool
+    // while the DAG is resolved below
+    let release_stores_loading = {
+        let mut to_load = config
+            .release_stores()
+            .iter()
+            .map(|storename| {
+                let p = config.releases_directory().join(storename);
+                debug!("Loading release directory: {}", p.display());
+                let loaded = String::from("Loaded releases successfully");
+                let failed = String::from("Failed to load releases");
+                StoreRoot::new(p).map(|root| (root, progressbars.bar(), loaded, failed))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-            let p = config.releases_directory().join(storename);
-            debug!("Loading release directory: {}", p.display());
-            let r = ReleaseStore::load(StoreRoot::new(p)?, &bar_release_loading);
-            if r.is_ok() {
-                bar_release_loading.finish_with_message("Loaded releases successfully");
+        // The artifacts of the submit whose artifacts are used might not be released, so its
+        // staging directory is searched like a release store
+        if let Some(submit) = use_artifacts_from.as_ref() {
+            let p = config.staging_directory().join(submit.uuid.hyphenated().to_string());
+            if p.is_dir() {
+                debug!("Loading staging directory of submit {}: {}", submit.uuid, p.display());
+                let loaded = format!("Loaded staging of submit {} successfully", submit.uuid);
+                let failed = format!("Failed to load staging of submit {}", submit.uuid);
+                to_load.push((StoreRoot::new(p)?, progressbars.bar(), loaded, failed));
             } else {
-                bar_release_loading.finish_with_message("Failed to load releases");
+                warn!("Staging directory of submit {} does not exist, only its released artifacts are used", submit.uuid);
             }
-            r.map(Arc::new)
+        }
+
+        tokio::task::spawn_blocking(move || {
+            use rayon::iter::IntoParallelIterator;
+            use rayon::iter::ParallelIterator;
+
+            to_load
+                .into_par_iter()
+                .map(|(root, bar, loaded, failed)| {
+                    let r = ReleaseStore::load(root, &bar);
+                    bar.finish_with_message(if r.is_ok() { loaded } else { failed });
+                    r.map(Arc::new)
+                })
+                .collect::<Result<Vec<_>>>()
         })
-        .collect::<Result<Vec<_>>>()?;
+    };
 
-    let (staging_store, staging_dir, submit_id) = {
+    let (staging_store_loading, staging_dir, submit_id, created_staging_dir) = {
         let bar_staging_loading = progressbars.bar();
 
-        let (submit_id, p) = if let Some(staging_dir) = matches.value_of("staging_dir").map(PathBuf::from) {
+        let (submit_id, p) = if let Some((submit, _, _, _)) = resumed.as_ref() {
+            let staging_dir = config
+                .staging_directory()
+                .join(submit.uuid.hyphenated().to_string());
+
+            if !staging_dir.is_dir() {
+                return Err(anyhow!(
+                    "Cannot resume submit {}: staging directory {} does not exist",
+                    submit.uuid,
+                    staging_dir.display()
+                ));
+            }
+
+            (submit.uuid, staging_dir)
+        } else if let Some(staging_dir) = matches.value_of("staging_dir").map(PathBuf::from) {
             info!(
                 "Setting staging dir to {} for this run",
                 staging_dir.display()
@@ -211,18 +349,46 @@ pub async fn build(
             (submit_id, staging_dir)
         };
 
-        if !p.is_dir() {
+        let created_staging_dir = !p.is_dir();
+        if created_staging_dir {
             let _ = tokio::fs::create_dir_all(&p).await?;
         }
 
         debug!("Loading staging directory: {}", p.display());
-        let r = StagingStore::load(StoreRoot::new(p.clone())?, &bar_staging_loading);
-        if r.is_ok() {
-            bar_staging_loading.finish_with_message("Loaded staging successfully");
-        } else {
-            bar_staging_loading.finish_with_message("Failed to load staging");
-        }
-        r.map(RwLock::new).map(Arc::new).map(|store| (store, p, submit_id))?
+        let root = StoreRoot::new(p.clone())?;
+        let setuid_whitelist = config.artifact_setuid_whitelist().clone();
+        let artifact_owner = *config.artifact_owner();
+        let loading = tokio::task::spawn_blocking(move || {
+            let r = StagingStore::load(root, &bar_staging_loading)
+                .map(|store| store.with_setuid_whitelist(setuid_whitelist).with_artifact_owner(artifact_owner));
+            if r.is_ok() {
+                bar_staging_loading.finish_with_message("Loaded staging successfully");
+            } else {
+                bar_staging_loading.finish_with_message("Failed to load staging");
+            }
+            r
+        });
+
+        (loading, p, submit_id, created_staging_dir)
+    };
+
+    // Conditions on images need what is on the endpoints, so the endpoints are connected before the
+    // dag is built if a package has such a condition. The jobs are run on these endpoints then.
+    let image_info = if repo.packages().any(crate::package::Package::has_image_info_conditions) {
+        let shared = match shared_endpoints.take() {
+            Some(shared) => shared,
+            None => SharedEndpoints::setup(std::mem::take(&mut endpoint_configurations)).await?,
+        };
+        let image_info = shared
+            .image_info(&image_name, &config.docker().image_reference(&image_name))
+            .await
+            .context("Querying the images on the endpoints for conditions on images")?;
+        debug!("Images for conditions: {:?}", image_info);
+
+        shared_endpoints = Some(shared);
+        Some(image_info)
+    } else {
+        None
     };
 
     let dag = {
@@ -230,15 +396,44 @@ pub async fn build(
         let condition_data = ConditionData {
             image_name: Some(&image_name),
             env: &additional_env,
+            image_info: image_info.as_ref(),
         };
 
-        let dag = Dag::for_root_package(package.clone(), &repo, Some(&bar_tree_building), &condition_data)?;
+        let dag = crate::db::resolve_dag(
+            &database_connection,
+            dry_run,
+            &git_repo,
+            &repo,
+            package.clone(),
+            Some(&bar_tree_building),
+            &condition_data,
+        )?;
         bar_tree_building.finish_with_message("Finished loading Dag");
         dag
     };
 
+    let release_stores = release_stores_loading.await.context("Loading release stores")??;
+    let staging_store = Arc::new(RwLock::new(staging_store_loading.await.context("Loading staging store")??));
+
     let source_cache = SourceCache::new(config.source_cache_root().clone());
 
+    if offline {
+        let missing = dag.all_packages()
+            .into_iter()
+            .flat_map(|p| source_cache.sources_for(p))
+            .filter(|source| !source.path().exists())
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            for source in missing.iter() {
+                error!("Source missing: {} (from {})", source.path().display(), source.url());
+            }
+
+            return Err(anyhow!("{} sources are missing in the source cache and cannot be downloaded offline", missing.len()))
+                .context("Download them with 'butido source download' before building offline");
+        }
+    }
+
     if matches.is_present("no_verification") {
         warn!("No hash verification will be performed");
     } else {
@@ -279,6 +474,26 @@ pub async fn build(
                 }
             }
 
+            // Check the phases now, so that a misconfigured package does not fail only once its
+            // container runs
+            let _ = pkg.phase_order(config.available_phases())?;
+
+            if let Some(limits) = pkg.build().as_ref() {
+                limits.validate()
+                    .with_context(|| anyhow!("Checking build limits of {} {}", pkg.name(), pkg.version()))?;
+            }
+
+            let _ = reuse_policy.max_age_for(pkg.reuse_max_age().as_deref())
+                .with_context(|| anyhow!("Checking reuse_max_age of {} {}", pkg.name(), pkg.version()))?;
+
+            if hermetic && *pkg.needs_network() {
+                return Err(anyhow!(
+                    "Package {} {} needs network and cannot be built hermetic",
+                    pkg.name(),
+                    pkg.version()
+                ));
+            }
+
             if let Some(deniedlist) = pkg.denied_images() {
                 if deniedlist.iter().any(|denied| image_name == *denied) {
                     return Err(anyhow!(
@@ -294,44 +509,132 @@ pub async fn build(
         })
         .collect::<Result<Vec<()>>>()?;
 
-    trace!("Setting up database jobs for Package, GitHash, Image");
-    let db_package = async { Package::create_or_fetch(&database_connection, package) };
-    let db_githash = async { GitHash::create_or_fetch(&database_connection, &hash_str) };
-    let db_image = async { Image::create_or_fetch(&database_connection, &image_name) };
-    let db_envs = async {
-        additional_env
-            .clone()
-            .into_iter()
-            .map(|(k, v)| async {
-                let k: EnvironmentVariableName = k; // hack to work around move semantics
-                let v: String = v; // hack to work around move semantics
-                EnvVar::create_or_fetch(&database_connection, &k, &v)
-            })
-            .collect::<futures::stream::FuturesUnordered<_>>()
-            .collect::<Result<Vec<EnvVar>>>()
-            .await
+    // Heavy submits (and submits which are explicitely scheduled) only start within a build window
+    let now = if dry_run || crate::config::is_in_build_window(config.build_windows(), &now) {
+        now
+    } else {
+        let num_jobs = dag.all_packages().len() * std::cmp::max(1, architectures.len());
+        let is_heavy = config.heavy_submit_jobs().map(|heavy| num_jobs >= heavy).unwrap_or(false);
+        let start = crate::config::next_build_window(config.build_windows(), &now);
+
+        if matches.is_present("schedule") {
+            let stale = ScheduledSubmit::delete_stale(&database_connection, &now)?;
+            if stale > 0 {
+                warn!("Removed {} scheduled submits which were never started", stale);
+            }
+
+            let scheduled = ScheduledSubmit::create(
+                &database_connection,
+                &submit_id,
+                package.name().as_ref(),
+                package.version().as_ref(),
+                image_name.as_ref(),
+                &hash_str,
+                &now,
+                &start,
+            )?;
+
+            writeln!(std::io::stdout(), "Submit {} scheduled for {}", submit_id.to_string().green(), start.to_string().green())?;
+            let wait = (start - chrono::offset::Local::now().naive_local())
+                .to_std()
+                .unwrap_or_else(|_| std::time::Duration::from_secs(0));
+
+            // The submit is only started by this process, so the entry is removed if it is interrupted
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {},
+                _ = tokio::signal::ctrl_c() => {
+                    scheduled.delete(&database_connection)?;
+                    return Err(anyhow!("Scheduled submit {} cancelled", submit_id));
+                }
+            }
+
+            scheduled.delete(&database_connection)?;
+            chrono::offset::Local::now().naive_local()
+        } else if is_heavy {
+            return Err(anyhow!(
+                "Submit has {} jobs and may only be started within a build window, the next one starts at {}",
+                num_jobs, start
+            ))
+            .context("Pass --schedule to wait for the build window");
+        } else {
+            now
+        }
     };
 
-    trace!("Running database jobs for Package, GitHash, Image");
-    let (db_package, db_githash, db_image, db_envs) =
-        tokio::join!(db_package, db_githash, db_image, db_envs);
-
-    let (db_package, db_githash, db_image, _) = (db_package?, db_githash?, db_image?, db_envs?);
-
-    trace!("Database jobs for Package, GitHash, Image finished successfully");
-    trace!("Creating Submit in database");
-    let submit = Submit::create(
-        &database_connection,
-        &now,
-        &submit_id,
-        &db_image,
-        &db_package,
-        &db_githash,
-    )?;
-    trace!(
-        "Creating Submit in database finished successfully: {:?}",
-        submit
-    );
+    // In a dry run, nothing is written to the database, so no submit is created
+    let submit = if dry_run {
+        None
+    } else if let Some((submit, _, _, _)) = resumed.as_ref() {
+        AuditLogEntry::append(&database_connection, "submit-resume", &format!(
+            "{} for {} {} on {} at {}",
+            submit.uuid, package.name(), package.version(), image_name, hash_str
+        ))?;
+
+        Some(submit.clone())
+    } else {
+        trace!("Setting up database jobs for Package, GitHash, Image");
+        let db_package = async { Package::create_or_fetch(&database_connection, package) };
+        let db_githash = async { GitHash::create_or_fetch(&database_connection, &hash_str) };
+        let db_image = async { Image::create_or_fetch(&database_connection, &image_name) };
+        let db_envs = async {
+            cli_env
+                .clone()
+                .into_iter()
+                .map(|(k, v)| async {
+                    let k: EnvironmentVariableName = k; // hack to work around move semantics
+                    let v: String = v; // hack to work around move semantics
+                    EnvVar::create_or_fetch(&database_connection, &k, &v)
+                })
+                .collect::<futures::stream::FuturesUnordered<_>>()
+                .collect::<Result<Vec<EnvVar>>>()
+                .await
+        };
+
+        trace!("Running database jobs for Package, GitHash, Image");
+        let (db_package, db_githash, db_image, db_envs) =
+            tokio::join!(db_package, db_githash, db_image, db_envs);
+
+        let (db_package, db_githash, db_image, db_envs) = (db_package?, db_githash?, db_image?, db_envs?);
+
+        trace!("Database jobs for Package, GitHash, Image finished successfully");
+        trace!("Creating Submit in database");
+        let submit = Submit::create(
+            &database_connection,
+            &now,
+            &submit_id,
+            &db_image,
+            &db_package,
+            &db_githash,
+        )?;
+        trace!(
+            "Creating Submit in database finished successfully: {:?}",
+            submit
+        );
+
+        for env in db_envs.iter() {
+            SubmitEnv::create(&database_connection, &submit, env)?;
+        }
+
+        AuditLogEntry::append(&database_connection, "submit", &format!(
+            "{} for {} {} on {} at {}",
+            submit_id, db_package.name, db_package.version, db_image.name, db_githash.hash
+        ))?;
+        {
+            // Everything that overrides the configuration for this submit is recorded as well
+            let overrides = matches.value_of("shebang")
+                .map(|s| format!("shebang = {}", s))
+                .into_iter()
+                .chain(matches.value_of("staging_dir").map(|s| format!("staging = {}", s)))
+                .chain(matches.value_of("use-artifacts-from").map(|s| format!("artifacts from submit {}", s)))
+                .chain(matches.values_of("env").unwrap_or_default().map(|e| format!("env {}", e)));
+
+            for ovr in overrides {
+                AuditLogEntry::append(&database_connection, "config-override", &format!("{}: {}", submit_id, ovr))?;
+            }
+        }
+
+        Some(submit)
+    };
 
     {
         let out = std::io::stdout();
@@ -342,35 +645,88 @@ pub async fn build(
             t.to_string().green()
         }
 
-        writeln!(outlock, "Starting submit: {}", mkgreen(&submit_id))?;
-        writeln!(outlock, "Started at:      {}", mkgreen(&now))?;
-        writeln!(outlock, "On Image:        {}", mkgreen(&db_image.name))?;
+        if dry_run {
+            writeln!(outlock, "Planning submit (dry run)")?;
+        } else if resumed.is_some() {
+            writeln!(outlock, "Resuming submit: {}", mkgreen(&submit_id))?;
+            writeln!(outlock, "Resumed at:      {}", mkgreen(&now))?;
+        } else {
+            writeln!(outlock, "Starting submit: {}", mkgreen(&submit_id))?;
+            writeln!(outlock, "Started at:      {}", mkgreen(&now))?;
+        }
+        writeln!(outlock, "On Image:        {}", mkgreen(&image_name))?;
+        if !architectures.is_empty() {
+            writeln!(outlock, "For Archs:       {}", mkgreen(&architectures.iter().join(", ")))?;
+        }
         writeln!(outlock, "For Package:     {p} {v}",
-            p = mkgreen(&db_package.name),
-            v = mkgreen(&db_package.version))?;
-        writeln!(outlock, "On repo hash:    {}", mkgreen(&db_githash.hash))?;
+            p = mkgreen(package.name()),
+            v = mkgreen(package.version()))?;
+        writeln!(outlock, "On repo hash:    {}", mkgreen(&hash_str))?;
     }
 
+    let package_filter = matches
+        .value_of("only")
+        .map(|name| PackageFilter::Only(PackageName::from(String::from(name))))
+        .or_else(|| {
+            matches
+                .value_of("up_to")
+                .map(|name| PackageFilter::UpTo(PackageName::from(String::from(name))))
+        });
+
+    let force_rebuild = if matches.is_present("force-rebuild-all") {
+        ForceRebuild::All
+    } else {
+        matches
+            .values_of("force-rebuild")
+            .map(|names| ForceRebuild::Packages(names.map(|name| PackageName::from(String::from(name))).unique().collect()))
+            .unwrap_or_default()
+    };
+
     trace!("Setting up job sets");
     let resources: Vec<JobResource> = additional_env.into_iter().map(JobResource::from).collect();
     let jobdag = crate::job::Dag::from_package_dag(dag, shebang, image_name, phases.clone(), resources);
     trace!("Setting up job sets finished successfully");
 
     trace!("Setting up Orchestrator");
-    let database_connection = Arc::new(database_connection);
+    // The jobs get their connections from the pool
+    drop(database_connection);
+    let metrics = Arc::new(Metrics::default());
+
+    #[cfg(feature = "metrics")]
+    let metrics_server = matches
+        .value_of("metrics_addr")
+        .map(|addr| -> Result<_> {
+            let addr = addr.parse().with_context(|| anyhow!("Parsing address to serve metrics on: {}", addr))?;
+            crate::util::metrics::serve(metrics.clone(), addr)
+        })
+        .transpose()?;
+
     let orch = OrchestratorSetup::builder()
         .progress_generator(progressbars)
         .endpoint_config(endpoint_configurations)
+        .shared_endpoints(shared_endpoints)
         .staging_store(staging_store)
         .release_stores(release_stores)
-        .database(database_connection.clone())
+        .database(database_pool.clone())
         .source_cache(source_cache)
         .submit(submit)
+        .resume(resumed.map(|(submit, _, _, _)| submit))
+        .package_filter(package_filter)
+        .force_rebuild(force_rebuild)
         .log_dir(if matches.is_present("write-log-file") {
             Some(config.log_dir().clone())
         } else {
             None
         })
+        .log_split_dir(matches.value_of("log-split-dir").map(PathBuf::from))
+        .stream_logs(matches.occurrences_of("verbose") >= 2 || matches.is_present("stream-logs") || log_format == LogFormat::Json)
+        .log_format(log_format)
+        .hermetic(hermetic)
+        .reuse_policy(reuse_policy)
+        .architectures(architectures)
+        .timeout(timeout)
+        .use_artifacts_from(use_artifacts_from)
+        .metrics(metrics)
         .jobdag(jobdag)
         .config(config)
         .repository(git_repo)
@@ -378,9 +734,52 @@ pub async fn build(
         .setup()
         .await?;
 
+    if dry_run {
+        let endpoints = orch.endpoints().await?;
+        let plan = if explain_reuse.is_some() {
+            orch.explain_reuse().await?
+        } else {
+            orch.plan().await?
+        };
+        let estimate = orch.estimate_duration(&plan);
+        drop(orch);
+
+        // Do not leave an empty staging directory behind for a submit that never happened
+        if created_staging_dir {
+            tokio::fs::remove_dir(&staging_dir)
+                .await
+                .with_context(|| anyhow!("Removing staging directory {}", staging_dir.display()))?;
+        }
+
+        if let Some(report) = explain_reuse {
+            return print_reuse_explanation(&plan, &report);
+        }
+        return print_plan(plan, &endpoints, estimate);
+    }
+
+    // No jobs are started if all artifacts can be reused, the submit is recorded without jobs
+    if let Some(artifacts) = orch.up_to_date_artifacts().await? {
+        drop(orch);
+
+        #[cfg(feature = "metrics")]
+        if let Some(server) = metrics_server {
+            server.abort();
+        }
+
+        return print_up_to_date(&artifacts);
+    }
+
     info!("Running orchestrator...");
     let mut artifacts = vec![];
-    let errors = orch.run(&mut artifacts).await?;
+    let errors = orch.run(&mut artifacts).await;
+
+    #[cfg(feature = "metrics")]
+    if let Some(server) = metrics_server {
+        server.abort();
+    }
+
+    let errors = errors?;
+    let database_connection = get_connection(&database_pool).await?;
     let out = std::io::stdout();
     let mut outlock = out.lock();
 
@@ -391,7 +790,19 @@ pub async fn build(
         writeln!(outlock, "-> {}", staging_dir.join(artifact_path).display()).map_err(Error::from)
     })?;
 
+    // Jobs which were killed by a timeout did not fail on their own, they are reported without
+    // their logs and are not attributed to the maintainers of the packages
+    let (timed_out, errors): (Vec<_>, Vec<_>) = errors
+        .into_iter()
+        .partition(|(_, error)| error.downcast_ref::<Timeout>().is_some());
+    for (_, error) in timed_out.iter() {
+        for cause in error.chain() {
+            writeln!(outlock, "{}: {}", "[TIMEOUT]".yellow(), cause)?;
+        }
+    }
+
     let mut had_error = false;
+    let mut failed_by_maintainer: BTreeMap<String, Vec<String>> = BTreeMap::new();
     for (job_uuid, error) in errors {
         had_error = true;
         for cause in error.chain() {
@@ -401,7 +812,16 @@ pub async fn build(
         let data = schema::jobs::table
             .filter(schema::jobs::dsl::uuid.eq(job_uuid))
             .inner_join(schema::packages::table)
-            .first::<(Job, Package)>(database_connection.as_ref())?;
+            .first::<(Job, Package)>(&*database_connection)?;
+
+        let failed_package = format!("{} {}", data.1.name, data.1.version);
+        if data.0.maintainers.is_empty() {
+            failed_by_maintainer.entry(String::from("none")).or_default().push(failed_package);
+        } else {
+            for team in data.0.maintainers.iter() {
+                failed_by_maintainer.entry(team.clone()).or_default().push(failed_package.clone());
+            }
+        }
 
         let number_log_lines = *config.build_error_lines();
         writeln!(
@@ -466,9 +886,223 @@ pub async fn build(
         }
     }
 
-    if had_error {
-        Err(anyhow!("One or multiple errors during build"))
-    } else {
-        Ok(())
+    // Route the failures to the teams owning the packages
+    if !failed_by_maintainer.is_empty() {
+        writeln!(outlock, "Failed packages by maintainer:")?;
+        for (team, packages) in failed_by_maintainer {
+            writeln!(outlock, "{}: {}", team.red(), packages.join(", "))?;
+        }
+    }
+
+    match (had_error, timed_out.len()) {
+        (false, 0) => Ok(()),
+        (false, n) => Err(anyhow!("{} job(s) killed by a timeout", n)),
+        (true, 0) => Err(anyhow!("One or multiple errors during build")),
+        (true, n) => Err(anyhow!("One or multiple errors during build, {} job(s) killed by a timeout", n)),
+    }
+}
+
+/// The configurations of all endpoints a build connects to
+pub(super) fn endpoint_configurations(config: &Configuration) -> Vec<EndpointConfiguration> {
+    let mut endpoint_configurations = config
+        .docker()
+        .endpoints()
+        .iter()
+        .map(|(ep_name, ep_cfg)| {
+            EndpointConfiguration::builder()
+                .endpoint_name(ep_name.clone())
+                .endpoint(ep_cfg.clone())
+                .max_jobs(ep_cfg.maxjobs())
+                .required_docker_versions(config.docker().docker_versions().clone())
+                .required_docker_api_versions(config.docker().docker_api_versions().clone())
+                .build()
+        })
+        .collect::<Vec<_>>();
+    {
+        // Because we're loading always sequencially, to have a bit more spread over the endpoints,
+        // shuffle the endpoints here. Not a perfect solution, but a working one.
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        rng.shuffle(&mut endpoint_configurations);
+    }
+    endpoint_configurations
+}
+
+/// Print the plan of a dry run, with the estimated duration of the jobs that would be built
+fn print_plan(plan: Vec<crate::orchestrator::PlannedJob>, endpoints: &[EndpointName], estimate: Option<std::time::Duration>) -> Result<()> {
+    use crate::orchestrator::PlannedAction;
+
+    let format_estimate = |d: Option<&std::time::Duration>| {
+        d.map(|d| humantime::format_duration(std::time::Duration::from_secs(d.as_secs())).to_string())
+            .unwrap_or_else(|| String::from("unknown"))
+    };
+
+    let to_build = plan.iter().filter(|job| job.action().is_build()).count();
+    let header = crate::commands::util::mk_header(["Job", "Package", "Version", "Image", "Action", "Expected", "Artifacts"].to_vec());
+    let data = plan
+        .iter()
+        .map(|job| {
+            let (action, expected, artifacts) = match job.action() {
+                PlannedAction::Build => (String::from("build"), format_estimate(job.expected_duration().as_ref()), String::new()),
+                PlannedAction::Reuse(artifacts) => {
+                    (String::from("reuse"), String::new(), artifacts.iter().map(|a| a.display()).join(", "))
+                }
+            };
+
+            vec![
+                job.uuid().to_string(),
+                job.package_name().to_string(),
+                job.package_version().to_string(),
+                job.image().to_string(),
+                action,
+                expected,
+                artifacts,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    crate::commands::util::display_data(header, data, false)?;
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    writeln!(outlock, "{} of {} jobs would be built, {} reused", to_build, plan.len(), plan.len() - to_build)?;
+    if to_build > 0 {
+        writeln!(outlock, "Endpoints: {}", endpoints.iter().join(", "))?;
+        writeln!(outlock, "Estimated duration: {}", format_estimate(estimate.as_ref()))?;
+    }
+    Ok(())
+}
+
+/// A job in the JSON report of `--explain-reuse`
+#[derive(serde::Serialize)]
+struct ReuseReportJob {
+    job: Uuid,
+    package_name: String,
+    package_version: String,
+    image: String,
+    decision: &'static str,
+    env: BTreeMap<String, Vec<String>>,
+    artifacts: Vec<String>,
+    reasons: Vec<ReuseReportMismatch>,
+    candidates: Vec<ReuseReportCandidate>,
+}
+
+/// An earlier job whose artifacts were not reused, in the JSON report of `--explain-reuse`
+#[derive(serde::Serialize)]
+struct ReuseReportCandidate {
+    job: Uuid,
+    mismatches: Vec<ReuseReportMismatch>,
+}
+
+#[derive(serde::Serialize)]
+struct ReuseReportMismatch {
+    kind: &'static str,
+    message: String,
+}
+
+impl From<&crate::db::ReuseMismatch> for ReuseReportMismatch {
+    fn from(mismatch: &crate::db::ReuseMismatch) -> Self {
+        ReuseReportMismatch {
+            kind: mismatch.kind(),
+            message: mismatch.to_string(),
+        }
     }
 }
+
+/// Print why each job of the plan would be built or reused, and write the report to `report` as
+/// JSON
+fn print_reuse_explanation(plan: &[crate::orchestrator::PlannedJob], report: &Path) -> Result<()> {
+    use crate::orchestrator::PlannedAction;
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    let mut jobs = Vec::with_capacity(plan.len());
+
+    for job in plan {
+        let (decision, artifacts) = match job.action() {
+            PlannedAction::Build => ("build", Vec::new()),
+            PlannedAction::Reuse(artifacts) => ("reuse", artifacts.iter().map(|a| a.display().to_string()).collect()),
+        };
+
+        let decision_colored = if job.action().is_build() { decision.yellow() } else { decision.green() };
+        writeln!(outlock, "{} {} {} ({}): {}",
+            job.uuid(),
+            job.package_name(),
+            job.package_version(),
+            job.image(),
+            decision_colored)?;
+        writeln!(outlock, "  Environment: {}", job.env().iter().map(|(k, v)| format!("{}={}", k, v)).join(", "))?;
+        for artifact in artifacts.iter() {
+            writeln!(outlock, "  Reusing: {}", artifact)?;
+        }
+        for reason in job.reasons() {
+            writeln!(outlock, "  Building: {}", reason)?;
+        }
+        for candidate in job.candidates() {
+            for mismatch in candidate.mismatches.iter() {
+                writeln!(outlock, "  Not reusing job {}: {}", candidate.job.uuid, mismatch)?;
+            }
+        }
+
+        jobs.push(ReuseReportJob {
+            job: *job.uuid(),
+            package_name: job.package_name().to_string(),
+            package_version: job.package_version().to_string(),
+            image: job.image().to_string(),
+            decision,
+            env: job.env()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .into_group_map()
+                .into_iter()
+                .collect(),
+            artifacts,
+            reasons: job.reasons().iter().map(ReuseReportMismatch::from).collect(),
+            candidates: job.candidates()
+                .iter()
+                .map(|candidate| ReuseReportCandidate {
+                    job: candidate.job.uuid,
+                    mismatches: candidate.mismatches.iter().map(ReuseReportMismatch::from).collect(),
+                })
+                .collect(),
+        });
+    }
+
+    let file = std::fs::File::create(report)
+        .with_context(|| anyhow!("Creating reuse report {}", report.display()))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &jobs)
+        .with_context(|| anyhow!("Writing reuse report {}", report.display()))?;
+    writeln!(outlock, "Reuse report written to {}", report.display()).map_err(Error::from)
+}
+
+/// Print the full paths of the artifacts of a submit which had nothing to build
+fn print_up_to_date(artifacts: &[PathBuf]) -> Result<()> {
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    writeln!(outlock, "Nothing to do: {} artifacts up to date", artifacts.len())?;
+    artifacts
+        .iter()
+        .try_for_each(|artifact| writeln!(outlock, "-> {}", artifact.display()).map_err(Error::from))
+}
+
+/// Get the environment variables which were passed to a submit with `--env`
+///
+/// The environment of the packages and the defaults of the image are not part of it, they are
+/// added to the jobs again when the submit is resumed.
+pub(super) fn submit_env(
+    database_connection: &PgConnection,
+    submit: &crate::db::models::Submit,
+) -> Result<Vec<(EnvironmentVariableName, String)>> {
+    schema::submit_envs::table
+        .inner_join(schema::envvars::table)
+        .filter(schema::submit_envs::submit_id.eq(submit.id))
+        .select((schema::envvars::name, schema::envvars::value))
+        .load::<(String, String)>(database_connection)
+        .with_context(|| anyhow!("Loading environment of submit {}", submit.uuid))
+        .map(|envs| {
+            envs.into_iter()
+                .map(|(k, v)| (EnvironmentVariableName::from(k.as_str()), v))
+                .sorted()
+                .collect()
+        })
+}
@@ -0,0 +1,415 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'canary' subcommand
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use colored::Colorize;
+use itertools::Itertools;
+use log::{debug, info, warn};
+
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+use crate::log::LogFormat;
+use crate::package::Package;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::package::ParseDependency;
+use crate::repository::Repository;
+use crate::util::docker::ImageName;
+use crate::util::progress::ProgressBars;
+
+type PackageKey = (PackageName, PackageVersion);
+
+/// A package picked to be built as a canary for a cluster of affected packages
+struct Canary<'a> {
+    cluster: usize,
+    package: &'a Package,
+    image: &'a ImageName,
+
+    /// The number of changed packages of the cluster which are built with the package
+    covers: usize,
+}
+
+/// Implementation of the "canary" subcommand
+pub async fn canary(
+    repo_path: &Path,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    repo: Repository,
+    log_format: LogFormat,
+) -> Result<()> {
+    let since = matches.value_of("since").unwrap(); // safe by clap
+    let n_samples = matches.value_of("sample").map(usize::from_str).transpose()?.unwrap(); // safe by clap
+    let dry_run = matches.is_present("dry-run");
+    let images = match matches.values_of("image") {
+        Some(images) => images.map(String::from).map(ImageName::from).collect::<Vec<_>>(),
+        None => config.docker().images().clone(),
+    };
+
+    let git_repo = git2::Repository::open(repo_path)
+        .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))?;
+    let since_commit = git_repo
+        .revparse_single(since)
+        .and_then(|obj| obj.peel_to_commit())
+        .with_context(|| anyhow!("Resolving {}", since))?;
+    let head_commit = git_repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .context("Resolving HEAD")?;
+
+    // Canonicalized, so that they can be compared with the (canonicalized) pathes of patches
+    let changed_files = {
+        let root = repo_path.canonicalize()?;
+        let diff = git_repo
+            .diff_tree_to_tree(Some(&since_commit.tree()?), Some(&head_commit.tree()?), None)
+            .with_context(|| anyhow!("Computing changes between {} and HEAD", since))?;
+
+        diff.deltas()
+            .flat_map(|delta| vec![delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(|p| root.join(p))
+            .collect::<BTreeSet<PathBuf>>()
+    };
+    debug!("{} files changed since {}", changed_files.len(), since);
+
+    let old_repo = crate::commands::util::load_repository_at(repo_path, &since_commit.id().to_string(), config, &progressbars)
+        .with_context(|| anyhow!("Loading the repository at {}", since))?;
+
+    let changed = repo
+        .packages()
+        .map(|pkg| {
+            let old = old_repo.find(pkg.name(), pkg.version()).into_iter().next();
+            package_changed(old, pkg, &changed_files).map(|b| (b, pkg))
+        })
+        .filter_map(|r| match r {
+            Ok((true, pkg)) => Some(Ok(key(pkg))),
+            Ok((false, _)) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<BTreeSet<PackageKey>>>()?;
+
+    if changed.is_empty() {
+        writeln!(std::io::stdout(), "No packages changed since {}", since)?;
+        return Ok(());
+    }
+
+    let deps = dependency_graph(&repo)?;
+    let affected = affected(&deps, &changed);
+
+    // The clusters with the most changed packages come first, so that they are sampled first
+    let clusters = clusters(&deps, &affected)
+        .into_iter()
+        .sorted_by_key(|cluster| std::cmp::Reverse(cluster.intersection(&changed).count()))
+        .collect::<Vec<_>>();
+    info!("{} packages changed since {}, {} packages affected in {} clusters",
+        changed.len(), since, affected.len(), clusters.len());
+
+    let packages = repo.packages().map(|pkg| (key(pkg), pkg)).collect::<BTreeMap<_, _>>();
+    let sample = sample_order(clusters.len(), images.len())
+        .into_iter()
+        .filter_map(|(cluster, image)| {
+            pick_canary(&deps, &clusters[cluster], &changed, &packages, &images[image])
+                .map(|(package, covers)| Canary { cluster, package, image: &images[image], covers })
+        })
+        .take(n_samples)
+        .collect::<Vec<_>>();
+
+    {
+        let out = std::io::stdout();
+        let mut outlock = out.lock();
+        writeln!(outlock, "{} packages changed since {}, {} packages affected in {} clusters",
+            changed.len().to_string().green(),
+            since,
+            affected.len().to_string().green(),
+            clusters.len().to_string().green())?;
+    }
+
+    let hdr = crate::commands::util::mk_header(["Cluster", "Package", "Version", "Image", "Covers changed"].to_vec());
+    let data = sample
+        .iter()
+        .map(|canary| {
+            vec![
+                canary.cluster.to_string(),
+                canary.package.name().to_string(),
+                canary.package.version().to_string(),
+                canary.image.to_string(),
+                format!("{}/{}", canary.covers, clusters[canary.cluster].intersection(&changed).count()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    crate::commands::util::display_data(hdr, data, false)?;
+
+    let not_covered = clusters
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !sample.iter().any(|canary| canary.cluster == *i))
+        .map(|(i, cluster)| {
+            let changed_packages = cluster
+                .intersection(&changed)
+                .map(|(name, version)| format!("{} {}", name, version))
+                .join(", ");
+            vec![i.to_string(), changed_packages]
+        })
+        .collect::<Vec<_>>();
+
+    if !not_covered.is_empty() {
+        writeln!(std::io::stdout(), "{} clusters are not covered by the sample:", not_covered.len().to_string().red())?;
+        let hdr = crate::commands::util::mk_header(["Cluster", "Changed packages"].to_vec());
+        crate::commands::util::display_data(hdr, not_covered, false)?;
+    }
+
+    if dry_run {
+        return Ok(());
+    }
+
+    let mut results = Vec::with_capacity(sample.len());
+    let pool = db_connection_config.establish_pool()?;
+    for canary in sample.iter() {
+        let name = canary.package.name().to_string();
+        let version = canary.package.version().to_string();
+        let image = canary.image.to_string();
+        info!("Building canary {} {} on {}", name, version, image);
+
+        let app_matches = crate::cli::cli()
+            .try_get_matches_from(vec!["butido", "build", name.as_str(), version.as_str(), "--image", image.as_str()])
+            .context("Constructing arguments for build")?;
+        let build_matches = app_matches.subcommand_matches("build").unwrap(); // safe by construction
+
+        let result = crate::commands::build(repo_path, build_matches, progressbars.clone(), pool.clone(), config, repo.clone(), repo_path, log_format, None).await;
+        if let Err(e) = result.as_ref() {
+            warn!("Canary {} {} on {} failed: {:?}", name, version, image, e);
+        }
+        results.push((canary, result.is_ok()));
+    }
+
+    let hdr = crate::commands::util::mk_header(["Cluster", "Package", "Version", "Image", "Result"].to_vec());
+    let data = results
+        .iter()
+        .map(|(canary, ok)| {
+            vec![
+                canary.cluster.to_string(),
+                canary.package.name().to_string(),
+                canary.package.version().to_string(),
+                canary.image.to_string(),
+                if *ok { String::from("ok") } else { String::from("failed") },
+            ]
+        })
+        .collect::<Vec<_>>();
+    crate::commands::util::display_data(hdr, data, false)?;
+
+    let failed = results.iter().filter(|(_, ok)| !ok).count();
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{} of {} canary builds failed", failed, results.len()))
+    }
+}
+
+fn key(pkg: &Package) -> PackageKey {
+    (pkg.name().clone(), pkg.version().clone())
+}
+
+/// Whether the definition of a package differs from the one at the old commit
+///
+/// Patches are compared by name, and are considered changed if the file was changed.
+fn package_changed(old: Option<&Package>, new: &Package, changed_files: &BTreeSet<PathBuf>) -> Result<bool> {
+    let old = match old {
+        Some(old) => old,
+        None => return Ok(true),
+    };
+
+    // The pathes of the patches differ, because the old repository was loaded from a checkout
+    let definition = |pkg: &Package| -> Result<serde_json::Value> {
+        let mut value = serde_json::to_value(pkg)?;
+        if let Some(obj) = value.as_object_mut() {
+            obj.remove("patches");
+        }
+        Ok(value)
+    };
+    let patch_names = |pkg: &Package| -> Vec<Option<std::ffi::OsString>> {
+        pkg.patches().iter().map(|p| p.file_name().map(ToOwned::to_owned)).collect()
+    };
+
+    if definition(old)? != definition(new)? || patch_names(old) != patch_names(new) {
+        return Ok(true);
+    }
+
+    Ok(new.patches().iter().any(|patch| {
+        patch.canonicalize()
+            .map(|p| changed_files.contains(&p))
+            .unwrap_or(true)
+    }))
+}
+
+/// The packages each package in the repository depends on, build and runtime dependencies
+fn dependency_graph(repo: &Repository) -> Result<BTreeMap<PackageKey, Vec<PackageKey>>> {
+    repo.packages()
+        .map(|pkg| {
+            let deps = pkg.dependencies()
+                .build()
+                .iter()
+                .map(|d| d.parse_as_name_and_version())
+                .chain(pkg.dependencies().runtime().iter().map(|d| d.parse_as_name_and_version()))
+                .map_ok(|(name, constraint)| {
+                    repo.find_with_version(&name, &constraint)
+                        .into_iter()
+                        .map(key)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| anyhow!("Parsing dependencies of {} {}", pkg.name(), pkg.version()))?
+                .into_iter()
+                .flatten()
+                .collect();
+
+            Ok((key(pkg), deps))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()
+}
+
+/// The packages which are changed or (transitively) depend on a changed package
+fn affected<K: Ord + Clone>(deps: &BTreeMap<K, Vec<K>>, changed: &BTreeSet<K>) -> BTreeSet<K> {
+    let mut affected = changed.clone();
+    loop {
+        let new = deps
+            .iter()
+            .filter(|(k, ds)| !affected.contains(*k) && ds.iter().any(|d| affected.contains(d)))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+
+        if new.is_empty() {
+            return affected;
+        }
+        affected.extend(new);
+    }
+}
+
+/// The dependency clusters of `packages`, the groups of packages which are connected by
+/// dependencies among each other
+fn clusters<K: Ord + Clone>(deps: &BTreeMap<K, Vec<K>>, packages: &BTreeSet<K>) -> Vec<BTreeSet<K>> {
+    let mut clusters: Vec<BTreeSet<K>> = Vec::new();
+    for k in packages.iter() {
+        let connected = std::iter::once(k)
+            .chain(deps.get(k).into_iter().flatten().filter(|d| packages.contains(*d)))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        let (joined, rest): (Vec<_>, Vec<_>) = clusters.into_iter().partition(|c| !c.is_disjoint(&connected));
+        clusters = rest;
+        clusters.push(joined.into_iter().fold(connected, |mut acc, c| {
+            acc.extend(c);
+            acc
+        }));
+    }
+    clusters
+}
+
+/// All (transitive) dependencies of `k`, including `k` itself
+fn closure<K: Ord + Clone>(deps: &BTreeMap<K, Vec<K>>, k: &K) -> BTreeSet<K> {
+    let mut closure = BTreeSet::new();
+    let mut stack = vec![k.clone()];
+    while let Some(k) = stack.pop() {
+        if closure.insert(k.clone()) {
+            stack.extend(deps.get(&k).into_iter().flatten().cloned());
+        }
+    }
+    closure
+}
+
+/// Pick the package of `cluster` to build on `image`
+///
+/// This is the package whose build covers the most changed packages, and of those the one with the
+/// least dependencies, as it is the fastest to build.
+fn pick_canary<'a>(
+    deps: &BTreeMap<PackageKey, Vec<PackageKey>>,
+    cluster: &BTreeSet<PackageKey>,
+    changed: &BTreeSet<PackageKey>,
+    packages: &BTreeMap<PackageKey, &'a Package>,
+    image: &ImageName,
+) -> Option<(&'a Package, usize)> {
+    cluster
+        .iter()
+        .filter_map(|k| packages.get(k).copied())
+        .filter(|pkg| {
+            let allowed = pkg.allowed_images().as_ref().map(|l| l.contains(image)).unwrap_or(true);
+            let denied = pkg.denied_images().as_ref().map(|l| l.contains(image)).unwrap_or(false);
+            allowed && !denied
+        })
+        .map(|pkg| {
+            let closure = closure(deps, &key(pkg));
+            let covers = closure.intersection(changed).count();
+            (pkg, covers, closure.len())
+        })
+        .min_by_key(|(pkg, covers, size)| (std::cmp::Reverse(*covers), *size, key(pkg)))
+        .map(|(pkg, covers, _)| (pkg, covers))
+}
+
+/// The order in which (cluster, image) pairs are sampled
+///
+/// Every cluster is sampled once before any cluster is sampled a second time, and the images are
+/// rotated between the clusters, so that a small sample still covers many clusters and images.
+fn sample_order(n_clusters: usize, n_images: usize) -> Vec<(usize, usize)> {
+    (0..n_images)
+        .flat_map(|round| (0..n_clusters).map(move |cluster| (cluster, (cluster + round) % n_images)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&'static str, &[&'static str])]) -> BTreeMap<&'static str, Vec<&'static str>> {
+        edges.iter().map(|(k, ds)| (*k, ds.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_affected_includes_transitive_dependents() {
+        let deps = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        let changed = vec!["a"].into_iter().collect();
+        let affected = affected(&deps, &changed);
+        assert_eq!(affected, vec!["a", "b", "c"].into_iter().collect());
+    }
+
+    #[test]
+    fn test_clusters() {
+        let deps = graph(&[("a", &[]), ("b", &["a"]), ("c", &[]), ("d", &["c", "x"]), ("x", &[]), ("e", &["a"])]);
+        let packages = vec!["a", "b", "c", "d", "e"].into_iter().collect();
+        let clusters = clusters(&deps, &packages);
+        assert_eq!(clusters.len(), 2);
+        assert!(clusters.contains(&vec!["a", "b", "e"].into_iter().collect()));
+        assert!(clusters.contains(&vec!["c", "d"].into_iter().collect()));
+    }
+
+    #[test]
+    fn test_closure() {
+        let deps = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b", "a"])]);
+        assert_eq!(closure(&deps, &"c"), vec!["a", "b", "c"].into_iter().collect());
+        assert_eq!(closure(&deps, &"a"), vec!["a"].into_iter().collect());
+    }
+
+    #[test]
+    fn test_sample_order_covers_clusters_first() {
+        let order = sample_order(3, 2);
+        assert_eq!(order, vec![(0, 0), (1, 1), (2, 0), (0, 1), (1, 0), (2, 1)]);
+    }
+}
@@ -0,0 +1,46 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'config' subcommand
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::ArgMatches;
+use log::info;
+
+use crate::config::Configuration;
+
+/// Implementation of the "config" subcommand
+pub fn config(config: &Configuration, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        Some(("show-defaults", matches)) => show_defaults(config, matches),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// Implementation of the "config show-defaults" subcommand
+fn show_defaults(config: &Configuration, matches: &ArgMatches) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let data = config
+        .defaults()
+        .iter()
+        .filter(|(_, defaults)| !defaults.flags().is_empty())
+        .map(|(subcommand, defaults)| vec![subcommand, defaults.flags().join(" ")])
+        .collect::<Vec<_>>();
+
+    if data.is_empty() {
+        info!("No default command line arguments configured");
+        Ok(())
+    } else {
+        let hdrs = crate::commands::util::mk_header(vec!["Subcommand", "Defaults"]);
+        crate::commands::util::display_data(hdrs, data, csv)
+    }
+}
@@ -10,7 +10,10 @@
 
 //! Implementation of the 'db' subcommand
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
@@ -24,20 +27,32 @@ use colored::Colorize;
 use diesel::BelongingToDsl;
 use diesel::ExpressionMethods;
 use diesel::JoinOnDsl;
+use diesel::PgConnection;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
+use itertools::EitherOrBoth;
 use itertools::Itertools;
 use log::debug;
 use log::info;
 use log::trace;
+use log::warn;
 
+use crate::commands::output::OutputFormat;
 use crate::commands::util::get_date_filter;
 use crate::config::Configuration;
 use crate::db::models;
 use crate::db::DbConnectionConfig;
 use crate::log::JobResult;
+use crate::package::PackageVersion;
 use crate::package::Script;
+use crate::package::ScriptBuilder;
+use crate::package::Shebang;
+use crate::package::condition::ConditionData;
+use crate::repository::Repository;
 use crate::schema;
+use crate::util::EnvironmentVariableName;
+use crate::util::docker::ImageName;
+use crate::util::progress::ProgressBars;
 
 diesel_migrations::embed_migrations!("migrations");
 
@@ -46,6 +61,8 @@ pub fn db(
     db_connection_config: DbConnectionConfig<'_>,
     config: &Configuration,
     matches: &ArgMatches,
+    repo_path: &Path,
+    progressbars: &ProgressBars,
 ) -> Result<()> {
     match matches.subcommand() {
         Some(("cli", matches)) => cli(db_connection_config, matches),
@@ -53,12 +70,17 @@ pub fn db(
         Some(("artifacts", matches)) => artifacts(db_connection_config, matches),
         Some(("envvars", matches)) => envvars(db_connection_config, matches),
         Some(("images", matches)) => images(db_connection_config, matches),
-        Some(("submit", matches)) => submit(db_connection_config, matches),
+        Some(("queue", matches)) => queue(db_connection_config, matches),
+        Some(("submit", matches)) => submit(db_connection_config, config, matches, repo_path, progressbars),
         Some(("submits", matches)) => submits(db_connection_config, matches),
         Some(("jobs", matches)) => jobs(db_connection_config, matches),
-        Some(("job", matches)) => job(db_connection_config, config, matches),
+        Some(("job", matches)) => job(db_connection_config, config, matches, repo_path, progressbars),
         Some(("log-of", matches)) => log_of(db_connection_config, matches),
         Some(("releases", matches)) => releases(db_connection_config, config, matches),
+        Some(("flaky", matches)) => flaky(db_connection_config, matches),
+        Some(("stats", matches)) => stats(db_connection_config, matches),
+        Some(("build-times", matches)) => build_times(db_connection_config, matches),
+        Some(("durations", matches)) => durations(db_connection_config, matches),
         Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
@@ -157,8 +179,8 @@ fn setup(conn_cfg: DbConnectionConfig<'_>) -> Result<()> {
 fn artifacts(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
     use crate::schema::artifacts::dsl;
 
-    let csv = matches.is_present("csv");
-    let hdrs = crate::commands::util::mk_header(vec!["Path", "Released", "Job"]);
+    let output = OutputFormat::from_matches(matches);
+    let hdrs = crate::commands::util::mk_header(vec!["Path", "Output", "Released", "Job"]);
     let conn = conn_cfg.establish_connection()?;
     let data = matches
         .value_of("job_uuid")
@@ -187,6 +209,7 @@ fn artifacts(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<(
                 .unwrap_or_else(|| String::from("no"));
             vec![
                 artifact.path,
+                artifact.output.unwrap_or_else(|| String::from("-")),
                 rel,
                 job.uuid.to_string(),
             ]
@@ -194,9 +217,9 @@ fn artifacts(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<(
         .collect::<Vec<_>>();
 
     if data.is_empty() {
-        info!("No artifacts in database");
+        crate::commands::output::display_none("No artifacts in database", output)?;
     } else {
-        crate::commands::util::display_data(hdrs, data, csv)?;
+        crate::commands::output::display(hdrs, data, output)?;
     }
 
     Ok(())
@@ -206,7 +229,7 @@ fn artifacts(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<(
 fn envvars(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
     use crate::schema::envvars::dsl;
 
-    let csv = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let hdrs = crate::commands::util::mk_header(vec!["Name", "Value"]);
     let conn = conn_cfg.establish_connection()?;
     let data = dsl::envvars
@@ -216,9 +239,9 @@ fn envvars(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
         .collect::<Vec<_>>();
 
     if data.is_empty() {
-        info!("No environment variables in database");
+        crate::commands::output::display_none("No environment variables in database", output)?;
     } else {
-        crate::commands::util::display_data(hdrs, data, csv)?;
+        crate::commands::output::display(hdrs, data, output)?;
     }
 
     Ok(())
@@ -228,7 +251,7 @@ fn envvars(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
 fn images(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
     use crate::schema::images::dsl;
 
-    let csv = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let hdrs = crate::commands::util::mk_header(vec!["Name"]);
     let conn = conn_cfg.establish_connection()?;
     let data = dsl::images
@@ -238,16 +261,68 @@ fn images(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
         .collect::<Vec<_>>();
 
     if data.is_empty() {
-        info!("No images in database");
+        crate::commands::output::display_none("No images in database", output)?;
     } else {
-        crate::commands::util::display_data(hdrs, data, csv)?;
+        crate::commands::output::display(hdrs, data, output)?;
+    }
+
+    Ok(())
+}
+
+/// Implementation of the "db queue" subcommand
+fn queue(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let output = OutputFormat::from_matches(matches);
+    let hdrs = crate::commands::util::mk_header(vec![
+        "Submit",
+        "Package",
+        "Version",
+        "Image",
+        "Commit",
+        "Requested by",
+        "Requested at",
+        "Scheduled for",
+    ]);
+    let conn = conn_cfg.establish_connection()?;
+    let now = chrono::offset::Local::now().naive_local();
+    let stale = models::ScheduledSubmit::delete_stale(&conn, &now)?;
+    if stale > 0 {
+        warn!("Removed {} scheduled submits which were never started", stale);
+    }
+
+    let data = models::ScheduledSubmit::all(&conn)?
+        .into_iter()
+        .map(|s| {
+            vec![
+                s.uuid.to_string(),
+                s.package_name,
+                s.package_version,
+                s.image,
+                s.repo_hash,
+                s.requested_by,
+                s.requested_at.to_string(),
+                s.scheduled_for.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    if data.is_empty() {
+        crate::commands::output::display_none("No scheduled submits in database", output)?;
+    } else {
+        crate::commands::output::display(hdrs, data, output)?;
     }
 
     Ok(())
 }
 
 /// Implementation of the "db submit" subcommand
-fn submit(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+fn submit(
+    conn_cfg: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    repo_path: &Path,
+    progressbars: &ProgressBars,
+) -> Result<()> {
+    let output = OutputFormat::from_matches(matches);
     let conn = conn_cfg.establish_connection()?;
     let submit_id = matches.value_of("submit")
         .map(uuid::Uuid::from_str)
@@ -268,6 +343,10 @@ fn submit(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
         .load::<models::Job>(&conn)
         .with_context(|| anyhow!("Loading jobs for submit = {}", submit_id))?;
 
+    if matches.is_present("drift") {
+        return submit_drift(&conn, config, &submit, &githash, &jobs, repo_path, progressbars, output);
+    }
+
     let n_jobs = jobs.len();
     let (jobs_unknown, jobs_success, jobs_err) = {
         let mut unkn = 0;
@@ -285,27 +364,36 @@ fn submit(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
         (unkn, succ, err)
     };
 
-    let out = std::io::stdout();
-    let mut outlock = out.lock();
-
-    indoc::writedoc!(outlock, r#"
-            Submit   {submit_id}
-            Date:    {submit_dt}
-            Commit:  {submit_commit}
-            Jobs:    {n_jobs}
-            Success: {n_jobs_success}
-            Unknown: {n_jobs_unknown}
-            Errored: {n_jobs_err}
-
-        "#,
-        submit_id = submit.uuid.to_string().cyan(),
-        submit_dt = submit.submit_time.to_string().cyan(),
-        submit_commit = githash.hash.cyan(),
-        n_jobs = n_jobs.to_string().cyan(),
-        n_jobs_success = jobs_success.to_string().green(),
-        n_jobs_unknown = jobs_unknown.to_string().red(),
-        n_jobs_err = jobs_err.to_string().red(),
-    )?;
+    // The summary is only printed for humans, machine-readable output lists the jobs only
+    if output == OutputFormat::Table {
+        let out = std::io::stdout();
+        let mut outlock = out.lock();
+
+        indoc::writedoc!(outlock, r#"
+                Submit     {submit_id}
+                Date:      {submit_dt}
+                Commit:    {submit_commit}
+                Cancelled: {submit_cancelled}
+                Butido:    {submit_butido_version}
+                By:        {submit_submitted_by}
+                Jobs:      {n_jobs}
+                Success:   {n_jobs_success}
+                Unknown:   {n_jobs_unknown}
+                Errored:   {n_jobs_err}
+
+            "#,
+            submit_id = submit.uuid.to_string().cyan(),
+            submit_dt = submit.submit_time.to_string().cyan(),
+            submit_commit = githash.hash.cyan(),
+            submit_cancelled = if submit.cancelled { "yes".red() } else { "no".green() },
+            submit_butido_version = submit.butido_version.as_deref().unwrap_or("unknown").cyan(),
+            submit_submitted_by = submit.submitted_by.as_deref().unwrap_or("unknown").cyan(),
+            n_jobs = n_jobs.to_string().cyan(),
+            n_jobs_success = jobs_success.to_string().green(),
+            n_jobs_unknown = jobs_unknown.to_string().red(),
+            n_jobs_err = jobs_err.to_string().red(),
+        )?;
+    }
 
     let header = crate::commands::util::mk_header(["Job", "Success", "Package", "Version", "Container", "Endpoint", "Image"].to_vec());
     let data = jobs.iter()
@@ -332,12 +420,246 @@ fn submit(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
             ])
         })
         .collect::<Result<Vec<Vec<colored::ColoredString>>>>()?;
-    crate::commands::util::display_data(header, data, false)
+    crate::commands::output::display(header, data, output)
+}
+
+/// Implementation of the "db submit --drift" option
+///
+/// Re-resolves the root package of the submit against the current repository and prints what
+/// changed compared to the jobs of the submit.
+#[allow(clippy::too_many_arguments)]
+fn submit_drift(
+    conn: &PgConnection,
+    config: &Configuration,
+    submit: &models::Submit,
+    githash: &models::GitHash,
+    jobs: &[models::Job],
+    repo_path: &Path,
+    progressbars: &ProgressBars,
+    output: OutputFormat,
+) -> Result<()> {
+    let root = models::Package::fetch_by_id(conn, submit.requested_package_id)?
+        .ok_or_else(|| anyhow!("Package for submit {} not found", submit.uuid))?;
+    let image = models::Image::fetch_by_id(conn, submit.requested_image_id)?
+        .ok_or_else(|| anyhow!("Image for submit {} not found", submit.uuid))?;
+    let image_name = ImageName::from(image.name.clone());
+
+    // The packages the submit built, by name and version
+    let stored = jobs
+        .iter()
+        .map(|job| {
+            let package = models::Package::fetch_for_job(conn, job)?
+                .ok_or_else(|| anyhow!("Package for job {} not found", job.uuid))?;
+            Ok(((package.name.clone(), package.version.clone()), (package, job)))
+        })
+        .collect::<Result<BTreeMap<(String, String), (models::Package, &models::Job)>>>()?;
+
+    // The environment of the job of the root package is what the conditions of the dependencies
+    // were checked against
+    let env = stored
+        .get(&(root.name.clone(), root.version.clone()))
+        .map(|(_, job)| {
+            schema::job_envs::table
+                .inner_join(schema::envvars::table)
+                .filter(schema::job_envs::job_id.eq(job.id))
+                .select(schema::envvars::all_columns)
+                .load::<models::EnvVar>(conn)
+                .with_context(|| anyhow!("Loading environment of job {}", job.uuid))
+        })
+        .transpose()?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ev| (EnvironmentVariableName::from(ev.name.as_str()), ev.value))
+        .collect::<Vec<_>>();
+
+    let repo = {
+        let bar = progressbars.bar();
+        let repo = Repository::load(repo_path, repo_path, config.package_repositories(), &bar).context("Loading the repository")?;
+        bar.finish_with_message("Repository loading finished");
+        repo
+    };
+
+    let root_package = repo
+        .find(&crate::package::PackageName::from(root.name.clone()), &crate::package::PackageVersion::from(root.version.clone()))
+        .into_iter()
+        .next()
+        .cloned()
+        .ok_or_else(|| anyhow!("Package {} {} does not exist in the repository anymore", root.name, root.version))?;
+
+    let dag = {
+        let bar = progressbars.bar();
+        let condition_data = ConditionData {
+            image_name: Some(&image_name),
+            env: &env,
+            image_info: None,
+        };
+        let dag = crate::package::Dag::for_root_package(root_package, &repo, Some(&bar), &condition_data)?;
+        bar.finish_with_message("Finished loading Dag");
+        dag
+    };
+
+    let current = dag
+        .all_packages()
+        .into_iter()
+        .map(|p| ((p.name().to_string(), p.version().to_string()), p))
+        .collect::<BTreeMap<_, _>>();
+
+    // The package definitions at the time of the submit, for comparing the sources
+    let old_repo = match crate::commands::util::load_repository_at(repo_path, &githash.hash, config, progressbars) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("Cannot compare sources, failed to load repository at {}: {:?}", githash.hash, e);
+            None
+        }
+    };
+
+    let mut data: Vec<Vec<String>> = Vec::new();
+    let dash = || String::from("-");
+
+    if !config.docker().images().iter().any(|img| image_name == *img) {
+        data.push(vec![dash(), String::from("image"), image_name.to_string(), String::from("not configured")]);
+    }
+
+    let names = stored.keys().chain(current.keys()).map(|(name, _)| name).unique().cloned().collect::<Vec<_>>();
+    for name in names {
+        let stored_versions = stored.keys().filter(|(n, _)| *n == name).map(|(_, v)| v.clone()).collect::<Vec<_>>();
+        let current_versions = current.keys().filter(|(n, _)| *n == name).map(|(_, v)| v.clone()).collect::<Vec<_>>();
+
+        for drift in match_versions(&stored_versions, &current_versions) {
+            match drift {
+                VersionDrift::Removed(version) => {
+                    data.push(vec![name.clone(), String::from("removed"), version.to_string(), dash()]);
+                }
+
+                VersionDrift::Added(version) => {
+                    data.push(vec![name.clone(), String::from("added"), dash(), version.to_string()]);
+                }
+
+                VersionDrift::Changed(stored_version, current_version) => {
+                    data.push(vec![name.clone(), String::from("version"), stored_version.to_string(), current_version.to_string()]);
+                }
+
+                VersionDrift::Same(version) => {
+                    let key = (name.clone(), version.to_string());
+                    let (job, pkg) = match (stored.get(&key), current.get(&key)) {
+                        (Some((_, job)), Some(pkg)) => (job, pkg),
+                        _ => unreachable!(),
+                    };
+
+                    // Build the script with the shebang the job used, so that only changes in the
+                    // package or the phases show up
+                    let shebang = job.script_text
+                        .lines()
+                        .next()
+                        .filter(|l| l.starts_with("#!"))
+                        .map(String::from)
+                        .unwrap_or_else(|| config.shebang().clone());
+                    let shebang = Shebang::from(shebang);
+                    let script = ScriptBuilder::new(&shebang)
+                        .with_dependency_mapping(config.docker().dependency_mapping().get(&image_name))
+                        .build(pkg, config.available_phases(), *config.strict_script_interpolation())?;
+
+                    if script.as_ref() != job.script_text {
+                        data.push(vec![name.clone(), String::from("script"), String::from("stored"), String::from("changed")]);
+                    }
+
+                    if let Some(old_pkg) = old_repo.as_ref().and_then(|r| r.find(pkg.name(), pkg.version()).into_iter().next()) {
+                        let describe = |src: &crate::package::Source| format!("{} ({})", src.url(), src.hash().value());
+                        for source_name in old_pkg.sources().keys().chain(pkg.sources().keys()).unique().sorted() {
+                            let old_src = old_pkg.sources().get(source_name).map(describe);
+                            let new_src = pkg.sources().get(source_name).map(describe);
+                            if old_src != new_src {
+                                data.push(vec![
+                                    name.clone(),
+                                    format!("source {}", source_name),
+                                    old_src.unwrap_or_else(dash),
+                                    new_src.unwrap_or_else(dash),
+                                ]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        for version in current_versions.iter() {
+            let pkg = &current[&(name.clone(), version.clone())];
+            let allowed = pkg.allowed_images().as_ref().map(|l| l.contains(&image_name)).unwrap_or(true);
+            let denied = pkg.denied_images().as_ref().map(|l| l.contains(&image_name)).unwrap_or(false);
+            if !allowed || denied {
+                data.push(vec![name.clone(), String::from("image"), image_name.to_string(), String::from("not allowed")]);
+            }
+        }
+    }
+
+    if data.is_empty() {
+        let msg = format!("No drift: rebuilding {} {} now would use the same inputs as submit {}", root.name, root.version, submit.uuid);
+        if output != OutputFormat::Table {
+            return crate::commands::output::display_none(&msg, output);
+        }
+
+        let out = std::io::stdout();
+        let mut outlock = out.lock();
+        writeln!(outlock, "{}", msg)?;
+        return Ok(());
+    }
+
+    let header = crate::commands::util::mk_header(["Package", "Change", "Submit", "Now"].to_vec());
+    crate::commands::output::display(header, data, output)
+}
+
+/// How a version of a package built by a submit relates to the versions the package resolves to now
+#[derive(Debug, PartialEq, Eq)]
+enum VersionDrift<'a> {
+    /// The version was built by the submit and is not resolved anymore
+    Removed(&'a str),
+
+    /// The version is resolved now, but was not built by the submit
+    Added(&'a str),
+
+    /// The submit built the first version, the second one is resolved now instead
+    Changed(&'a str, &'a str),
+
+    /// The version was built by the submit and is still resolved
+    Same(&'a str),
+}
+
+/// Match the versions of one package that were built by a submit with the versions it resolves to
+/// now
+///
+/// Versions that are in both lists are the same. The remaining versions are sorted by version
+/// comparison and paired in order as changed versions, the ones left over after that were removed
+/// or added.
+fn match_versions<'a>(stored: &'a [String], current: &'a [String]) -> Vec<VersionDrift<'a>> {
+    let mut drift = stored
+        .iter()
+        .filter(|v| current.contains(v))
+        .map(|v| VersionDrift::Same(v.as_str()))
+        .collect::<Vec<_>>();
+
+    let compare = |a: &&String, b: &&String| {
+        PackageVersion::from(String::clone(a)).compare(&PackageVersion::from(String::clone(b)))
+    };
+    let stored_only = stored.iter().filter(|v| !current.contains(v)).sorted_by(compare).collect::<Vec<_>>();
+    let current_only = current.iter().filter(|v| !stored.contains(v)).sorted_by(compare).collect::<Vec<_>>();
+
+    drift.extend({
+        stored_only
+            .iter()
+            .zip_longest(current_only.iter())
+            .map(|pair| match pair {
+                EitherOrBoth::Both(s, c) => VersionDrift::Changed(s.as_str(), c.as_str()),
+                EitherOrBoth::Left(s) => VersionDrift::Removed(s.as_str()),
+                EitherOrBoth::Right(c) => VersionDrift::Added(c.as_str()),
+            })
+    });
+
+    drift
 }
 
 /// Implementation of the "db submits" subcommand
 fn submits(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
-    let csv = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let limit = matches.value_of("limit").map(i64::from_str).transpose()?;
     let hdrs = crate::commands::util::mk_header(vec!["Time", "UUID", "For Package", "For Package Version"]);
     let conn = conn_cfg.establish_connection()?;
@@ -436,9 +758,9 @@ fn submits(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
     let data = submits.into_iter().rev().map(submit_to_vec).collect::<Vec<_>>();
 
     if data.is_empty() {
-        info!("No submits in database");
+        crate::commands::output::display_none("No submits in database", output)?;
     } else {
-        crate::commands::util::display_data(hdrs, data, csv)?;
+        crate::commands::output::display(hdrs, data, output)?;
     }
 
     Ok(())
@@ -446,7 +768,7 @@ fn submits(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
 
 /// Implementation of the "db jobs" subcommand
 fn jobs(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
-    let csv = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let hdrs = crate::commands::util::mk_header(vec![
         "Submit",
         "Job",
@@ -534,22 +856,28 @@ fn jobs(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
         .collect::<Result<Vec<_>>>()?;
 
     if data.is_empty() {
-        info!("No submits in database");
+        crate::commands::output::display_none("No submits in database", output)?;
     } else {
-        crate::commands::util::display_data(hdrs, data, csv)?;
+        crate::commands::output::display(hdrs, data, output)?;
     }
 
     Ok(())
 }
 
 /// Implementation of the "db job" subcommand
-fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMatches) -> Result<()> {
+fn job(
+    conn_cfg: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    repo_path: &Path,
+    progressbars: &ProgressBars,
+) -> Result<()> {
     let script_highlight = !matches.is_present("no_script_highlight");
     let script_line_numbers = !matches.is_present("no_script_line_numbers");
     let configured_theme = config.script_highlight_theme();
     let show_log = matches.is_present("show_log");
     let show_script = matches.is_present("show_script");
-    let csv = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let conn = conn_cfg.establish_connection()?;
     let job_uuid = matches
         .value_of("job_uuid")
@@ -571,13 +899,17 @@ fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMa
             models::Image,
         )>(&conn)?;
 
+    if matches.is_present("env_diff") {
+        return job_env_diff(&conn, config, &data.0, &data.3, repo_path, progressbars, output);
+    }
+
     trace!("Parsing log");
     let parsed_log = crate::log::ParsedLog::from_str(&data.0.log_text)?;
     trace!("Parsed log = {:?}", parsed_log);
     let success = parsed_log.is_successfull();
     trace!("log successfull = {:?}", success);
 
-    if csv {
+    if output != OutputFormat::Table {
         let hdrs = crate::commands::util::mk_header(vec![
             "UUID",
             "Success",
@@ -601,7 +933,7 @@ fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMa
             data.4.name.to_string(),
             data.0.container_hash,
         ]];
-        crate::commands::util::display_data(hdrs, data, csv)
+        crate::commands::output::display(hdrs, data, output)
     } else {
         let env_vars = if matches.is_present("show_env") {
             Some({
@@ -625,6 +957,7 @@ fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMa
                 Submit:     {submit_uuid}
                 Succeeded:  {succeeded}
                 Package:    {package_name} {package_version}
+                Maintainer: {maintainers}
 
                 Ran on:     {endpoint_name}
                 Image:      {image_name}
@@ -647,6 +980,11 @@ fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMa
             },
             package_name = data.3.name.cyan(),
             package_version = data.3.version.cyan(),
+            maintainers = if data.0.maintainers.is_empty() {
+                String::from("none").cyan()
+            } else {
+                data.0.maintainers.join(", ").cyan()
+            },
             endpoint_name = data.2.name.cyan(),
             image_name = data.4.name.cyan(),
             container_hash = data.0.container_hash.cyan(),
@@ -716,6 +1054,117 @@ fn job(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMa
     }
 }
 
+/// Show how the environment of a job differs from the defaults of its package
+fn job_env_diff(
+    conn: &PgConnection,
+    config: &Configuration,
+    job: &models::Job,
+    package: &models::Package,
+    repo_path: &Path,
+    progressbars: &ProgressBars,
+    output: OutputFormat,
+) -> Result<()> {
+    let job_env = job
+        .env(conn)?
+        .into_iter()
+        .map(|ev| (ev.name, ev.value))
+        .collect::<Vec<_>>();
+
+    let repo = {
+        let bar = progressbars.bar();
+        let repo = Repository::load(repo_path, repo_path, config.package_repositories(), &bar).context("Loading the repository")?;
+        bar.finish_with_message("Repository loading finished");
+        repo
+    };
+
+    let repo_package = repo
+        .find(&crate::package::PackageName::from(package.name.clone()), &crate::package::PackageVersion::from(package.version.clone()))
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Package {} {} does not exist in the repository anymore", package.name, package.version))?;
+
+    let diff = env_diff(&job_env, repo_package.environment().as_ref(), repo_package.env_sensitivity().as_deref());
+    if diff.is_empty() {
+        return crate::commands::output::display_none("Neither the job nor the package has environment variables", output);
+    }
+
+    let data = diff
+        .into_iter()
+        .map(|d| {
+            let name = if output == OutputFormat::Table && d.reuse && d.change != EnvChange::Same {
+                d.name.yellow().to_string()
+            } else {
+                d.name
+            };
+
+            vec![
+                name,
+                d.package_value.unwrap_or_default(),
+                d.job_value.unwrap_or_default(),
+                d.change.to_string(),
+                String::from(if d.reuse { "yes" } else { "no" }),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let hdrs = crate::commands::util::mk_header(vec!["Variable", "Package", "Job", "Change", "Reuse"]);
+    crate::commands::output::display(hdrs, data, output)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, parse_display::Display)]
+#[display(style = "lowercase")]
+enum EnvChange {
+    Same,
+    Changed,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct EnvDiff {
+    name: String,
+    package_value: Option<String>,
+    job_value: Option<String>,
+    change: EnvChange,
+
+    /// Whether the variable is compared when looking for artifacts to reuse
+    reuse: bool,
+}
+
+/// Compare the environment of a job with the environment a package declares
+///
+/// A variable takes part in reuse if the package declares no `env_sensitivity` at all or if it is
+/// listed there.
+fn env_diff(
+    job_env: &[(String, String)],
+    pkg_env: Option<&HashMap<EnvironmentVariableName, String>>,
+    sensitivity: Option<&[EnvironmentVariableName]>,
+) -> Vec<EnvDiff> {
+    let mut vars = BTreeMap::<String, (Option<String>, Option<String>)>::new();
+    for (name, value) in pkg_env.into_iter().flatten() {
+        vars.entry(name.as_ref().to_string()).or_default().0 = Some(value.clone());
+    }
+    for (name, value) in job_env {
+        vars.entry(name.clone()).or_default().1 = Some(value.clone());
+    }
+
+    vars.into_iter()
+        .map(|(name, (package_value, job_value))| {
+            let change = match (package_value.as_ref(), job_value.as_ref()) {
+                (Some(p), Some(j)) if p == j => EnvChange::Same,
+                (Some(_), Some(_)) => EnvChange::Changed,
+                (None, _) => EnvChange::Added,
+                (Some(_), None) => EnvChange::Removed,
+            };
+            let reuse = sensitivity
+                .map(|s| s.iter().any(|n| n.as_ref() == name))
+                .unwrap_or(true);
+
+            EnvDiff { name, package_value, job_value, change, reuse }
+        })
+        .collect()
+}
+
 /// Implementation of the subcommand "db log-of"
 fn log_of(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
     let conn   = conn_cfg.establish_connection()?;
@@ -741,9 +1190,9 @@ fn log_of(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()>
 
 /// Implementation of the "db releases" subcommand
 fn releases(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMatches) -> Result<()> {
-    let csv    = matches.is_present("csv");
+    let output = OutputFormat::from_matches(matches);
     let conn   = conn_cfg.establish_connection()?;
-    let header = crate::commands::util::mk_header(["Package", "Version", "Date", "Path"].to_vec());
+    let header = crate::commands::util::mk_header(["Id", "Package", "Version", "Date", "Path"].to_vec());
     let mut query = schema::jobs::table
         .inner_join(schema::packages::table)
         .inner_join(schema::artifacts::table)
@@ -787,6 +1236,7 @@ fn releases(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &
 
             if p.is_file() {
                 Some(vec![
+                    rel.id.to_string(),
                     pack.name,
                     pack.version,
                     rel.release_date.to_string(),
@@ -799,7 +1249,336 @@ fn releases(conn_cfg: DbConnectionConfig<'_>, config: &Configuration, matches: &
         })
         .collect::<Vec<Vec<_>>>();
 
-    crate::commands::util::display_data(header, data, csv)
+    crate::commands::output::display(header, data, output)
+}
+
+/// Implementation of the "db flaky" subcommand
+fn flaky(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    use std::collections::HashMap;
+
+    let output = OutputFormat::from_matches(matches);
+    let limit = matches.value_of("limit").map(usize::from_str).transpose()?;
+    let conn = conn_cfg.establish_connection()?;
+
+    let mut sel = schema::jobs::table
+        .inner_join(schema::endpoints::table)
+        .inner_join(schema::packages::table)
+        .inner_join(schema::images::table)
+        .into_boxed();
+
+    if let Some(pkg_name) = matches.value_of("package") {
+        sel = sel.filter(schema::packages::name.eq(pkg_name))
+    }
+
+    // Jobs are grouped by their inputs. Within a group, the outcomes are kept in the order the
+    // jobs were run, so that the number of alternations between success and failure can be
+    // counted.
+    // Only the columns needed for that are loaded, neither the logs nor the scripts of the jobs.
+    let mut groups: HashMap<FlakyInputs, (FlakyPackage, Vec<(String, bool)>)> = HashMap::new();
+    sel.order_by(schema::jobs::id.asc())
+        .select((
+            schema::packages::name,
+            schema::packages::version,
+            schema::images::name,
+            schema::jobs::image_digest,
+            schema::jobs::cache_key,
+            diesel::dsl::sql::<diesel::sql_types::Nullable<diesel::sql_types::Text>>(
+                "CASE WHEN jobs.cache_key IS NULL THEN md5(jobs.script_text) END"
+            ),
+            schema::endpoints::name,
+            job_state_sql(),
+        ))
+        .load::<(String, String, String, Option<String>, Option<String>, Option<String>, String, Option<String>)>(&conn)?
+        .into_iter()
+        .filter_map(|(name, version, image, digest, cache_key, script_hash, ep, state)| {
+            let succ = state.map(|state| state == "OK")?;
+            let inputs = match (cache_key, script_hash) {
+                (Some(cache_key), _) => FlakyInputs::CacheKey(cache_key),
+                (None, script_hash) => FlakyInputs::Job {
+                    name: name.clone(),
+                    version: version.clone(),
+                    image: image.clone(),
+                    digest: digest.clone(),
+                    script_hash: script_hash.unwrap_or_default(),
+                },
+            };
+            Some((inputs, (name, version, image, digest), ep, succ))
+        })
+        .for_each(|(inputs, package, ep, succ)| {
+            groups
+                .entry(inputs)
+                .or_insert_with(|| (package, Vec::new()))
+                .1
+                .push((ep, succ));
+        });
+
+    let flaky_groups = groups
+        .into_iter()
+        .filter(|(_, (_, outcomes))| {
+            outcomes.iter().any(|(_, succ)| *succ) && outcomes.iter().any(|(_, succ)| !*succ)
+        })
+        .map(|(_, group)| group)
+        .collect::<Vec<_>>();
+
+    let data = if matches.is_present("endpoints") {
+        let mut by_endpoint: HashMap<String, (usize, usize)> = HashMap::new();
+        flaky_groups
+            .iter()
+            .flat_map(|(_, outcomes)| outcomes.iter())
+            .for_each(|(ep, succ)| {
+                let entry = by_endpoint.entry(ep.clone()).or_insert((0, 0));
+                entry.0 += 1;
+                if !*succ {
+                    entry.1 += 1;
+                }
+            });
+
+        by_endpoint
+            .into_iter()
+            .sorted_by(|(a_name, (a_runs, a_fail)), (b_name, (b_runs, b_fail))| {
+                b_fail.cmp(a_fail)
+                    .then_with(|| a_runs.cmp(b_runs))
+                    .then_with(|| a_name.cmp(b_name))
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(name, (runs, failures))| vec![name, runs.to_string(), failures.to_string()])
+            .collect::<Vec<_>>()
+    } else {
+        flaky_groups
+            .into_iter()
+            .map(|((name, version, image, digest), outcomes)| {
+                let succ = outcomes.iter().map(|(_, succ)| *succ).collect::<Vec<bool>>();
+                let flips = count_outcome_flips(&succ);
+                let failures = succ.iter().filter(|b| !**b).count();
+                (name, version, image, digest, succ.len(), failures, flips)
+            })
+            .sorted_by(|a, b| {
+                b.6.cmp(&a.6)
+                    .then_with(|| b.5.cmp(&a.5))
+                    .then_with(|| a.0.cmp(&b.0))
+                    .then_with(|| a.1.cmp(&b.1))
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(name, version, image, digest, runs, failures, flips)| {
+                vec![
+                    name,
+                    version,
+                    image,
+                    digest.unwrap_or_else(|| String::from("unknown")),
+                    runs.to_string(),
+                    failures.to_string(),
+                    flips.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    if data.is_empty() {
+        return crate::commands::output::display_none("No flaky packages found", output);
+    }
+
+    let hdrs = if matches.is_present("endpoints") {
+        crate::commands::util::mk_header(vec!["Endpoint", "Runs", "Failures"])
+    } else {
+        crate::commands::util::mk_header(vec!["Package", "Version", "Image", "Image Digest", "Runs", "Failures", "Flips"])
+    };
+    crate::commands::output::display(hdrs, data, output)
+}
+
+/// The inputs the jobs are grouped by in the "db flaky" subcommand
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum FlakyInputs {
+    /// The cache key of the job
+    CacheKey(String),
+
+    /// Jobs without a recorded cache key, by package, image and the hash of their script
+    Job {
+        name: String,
+        version: String,
+        image: String,
+        digest: Option<String>,
+        script_hash: String,
+    },
+}
+
+/// Package name, version, image name and image digest of the jobs of a group in "db flaky"
+type FlakyPackage = (String, String, String, Option<String>);
+
+/// The state of a job as SQL expression: "OK" or "ERR" from the last state line of its log, NULL
+/// if there is none
+///
+/// This is what `is_job_successfull()` finds in the log, without loading the log.
+fn job_state_sql() -> diesel::expression::SqlLiteral<diesel::sql_types::Nullable<diesel::sql_types::Text>> {
+    diesel::dsl::sql("substring(chr(10) || jobs.log_text from '.*\\n#BUTIDO:STATE:(OK|ERR)')")
+}
+
+/// Implementation of the "db stats" subcommand
+fn stats(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    use std::collections::HashMap;
+
+    let output = OutputFormat::from_matches(matches);
+    let by_maintainer = matches.is_present("by_maintainer");
+    let conn = conn_cfg.establish_connection()?;
+
+    // Number of jobs and failed jobs, per package or per maintaining team.
+    // Jobs with an undecidable outcome are not counted. Only the columns needed for that are
+    // loaded, not the logs of the jobs.
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    schema::jobs::table
+        .inner_join(schema::packages::table)
+        .select((schema::packages::name, schema::jobs::maintainers, job_state_sql()))
+        .load::<(String, Vec<String>, Option<String>)>(&conn)?
+        .into_iter()
+        .filter_map(|(name, maintainers, state)| state.map(|state| (name, maintainers, state == "OK")))
+        .for_each(|(name, maintainers, succ)| {
+            let keys = if !by_maintainer {
+                vec![name]
+            } else if maintainers.is_empty() {
+                vec![String::from("none")]
+            } else {
+                maintainers
+            };
+
+            for key in keys {
+                let entry = counts.entry(key).or_insert((0, 0));
+                entry.0 += 1;
+                if !succ {
+                    entry.1 += 1;
+                }
+            }
+        });
+
+    if counts.is_empty() {
+        return crate::commands::output::display_none("No jobs found", output);
+    }
+
+    let data = counts
+        .into_iter()
+        .map(|(key, (jobs, failures))| (key, jobs, failures, failures as f64 / jobs as f64))
+        .sorted_by(|a, b| {
+            b.3.partial_cmp(&a.3)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        })
+        .map(|(key, jobs, failures, rate)| {
+            vec![key, jobs.to_string(), failures.to_string(), format!("{:.1}%", rate * 100.0)]
+        })
+        .collect::<Vec<_>>();
+
+    let hdrs = if by_maintainer {
+        crate::commands::util::mk_header(vec!["Maintainer", "Jobs", "Failures", "Failure rate"])
+    } else {
+        crate::commands::util::mk_header(vec!["Package", "Jobs", "Failures", "Failure rate"])
+    };
+    crate::commands::output::display(hdrs, data, output)
+}
+
+/// Implementation of the "db build-times" subcommand
+fn build_times(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    use std::collections::HashMap;
+
+    let output = OutputFormat::from_matches(matches);
+    let n_submits = matches.value_of("submits").map(i64::from_str).transpose()?.unwrap(); // safe by clap default value
+    let conn = conn_cfg.establish_connection()?;
+
+    let submit_ids = schema::submits::table
+        .order_by(schema::submits::submit_time.desc())
+        .limit(n_submits)
+        .select(schema::submits::id)
+        .load::<i32>(&conn)?;
+
+    let mut sel = schema::jobs::table
+        .inner_join(schema::packages::table)
+        .filter(schema::jobs::submit_id.eq_any(submit_ids))
+        .into_boxed();
+
+    if let Some(pkg_name) = matches.value_of("package") {
+        sel = sel.filter(schema::packages::name.eq(pkg_name))
+    }
+
+    // The durations (in seconds) of the successful jobs, per package
+    let mut durations: HashMap<String, Vec<i64>> = HashMap::new();
+    sel.load::<(models::Job, models::Package)>(&conn)?
+        .into_iter()
+        .map(|(job, pkg)| is_job_successfull(&job).map(|succ| (job, pkg, succ)))
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|(_, _, succ)| *succ == Some(true))
+        .filter_map(|(job, pkg, _)| Some((pkg.name, (job.finished_at? - job.started_at?).num_seconds())))
+        .for_each(|(name, secs)| durations.entry(name).or_default().push(secs));
+
+    if durations.is_empty() {
+        return crate::commands::output::display_none("No successful jobs with recorded durations found", output);
+    }
+
+    let data = durations
+        .into_iter()
+        .map(|(name, mut secs)| {
+            secs.sort_unstable();
+            let avg = secs.iter().sum::<i64>() / secs.len() as i64;
+            (name, avg, secs)
+        })
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+        .map(|(name, avg, secs)| {
+            vec![
+                name,
+                secs.len().to_string(),
+                format_seconds(avg),
+                format_seconds(percentile(&secs, 50)),
+                format_seconds(percentile(&secs, 90)),
+                format_seconds(percentile(&secs, 99)),
+                format_seconds(secs[secs.len() - 1]),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let hdrs = crate::commands::util::mk_header(vec!["Package", "Jobs", "Average", "Median", "90th percentile", "99th percentile", "Max"]);
+    crate::commands::output::display(hdrs, data, output)
+}
+
+/// Implementation of the "db durations" subcommand
+fn durations(conn_cfg: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let output = OutputFormat::from_matches(matches);
+    let conn = conn_cfg.establish_connection()?;
+    let package_names = matches.value_of("package").map(|name| vec![name]);
+    let image = matches.value_of("image");
+
+    let data = models::BuildDuration::list(&conn, package_names.as_deref())?
+        .into_iter()
+        .filter(|(_, _, img)| image.map(|i| img.name == i).unwrap_or(true))
+        .map(|(duration, pkg, img)| {
+            vec![
+                pkg.name,
+                pkg.version,
+                img.name,
+                format_seconds(duration.average().as_secs() as i64),
+                duration.samples.to_string(),
+                duration.updated_at.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    if data.is_empty() {
+        return crate::commands::output::display_none("No build durations found", output);
+    }
+
+    let hdrs = crate::commands::util::mk_header(vec!["Package", "Version", "Image", "Average", "Jobs", "Updated"]);
+    crate::commands::output::display(hdrs, data, output)
+}
+
+/// The `p`th percentile (nearest rank) of sorted, non-empty values
+fn percentile(sorted: &[i64], p: usize) -> i64 {
+    let rank = (p * sorted.len() + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+fn format_seconds(secs: i64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+/// Count how often consecutive outcomes switch between success and failure
+fn count_outcome_flips(outcomes: &[bool]) -> usize {
+    outcomes.windows(2).filter(|w| w[0] != w[1]).count()
 }
 
 /// Check if a job is successful
@@ -809,3 +1588,97 @@ fn is_job_successfull(job: &models::Job) -> Result<Option<bool>> {
     crate::log::ParsedLog::from_str(&job.log_text).map(|pl| pl.is_successfull().to_bool())
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::count_outcome_flips;
+    use super::env_diff;
+    use super::match_versions;
+    use super::percentile;
+    use super::EnvChange;
+    use super::VersionDrift;
+    use crate::util::EnvironmentVariableName;
+
+    #[test]
+    fn test_count_outcome_flips() {
+        assert_eq!(count_outcome_flips(&[]), 0);
+        assert_eq!(count_outcome_flips(&[true]), 0);
+        assert_eq!(count_outcome_flips(&[true, true, true]), 0);
+        assert_eq!(count_outcome_flips(&[true, false]), 1);
+        assert_eq!(count_outcome_flips(&[true, false, true, false]), 3);
+        assert_eq!(count_outcome_flips(&[false, false, true, true]), 1);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = (1..=10).collect::<Vec<i64>>();
+        assert_eq!(percentile(&values, 50), 5);
+        assert_eq!(percentile(&values, 90), 9);
+        assert_eq!(percentile(&values, 99), 10);
+        assert_eq!(percentile(&values, 0), 1);
+        assert_eq!(percentile(&[42], 90), 42);
+    }
+
+    #[test]
+    fn test_env_diff() {
+        let pkg_env = vec![("A", "1"), ("B", "2"), ("C", "3")]
+            .into_iter()
+            .map(|(k, v)| (EnvironmentVariableName::from(k), String::from(v)))
+            .collect::<HashMap<_, _>>();
+        let job_env = vec![("A", "1"), ("B", "x"), ("D", "4")]
+            .into_iter()
+            .map(|(k, v)| (String::from(k), String::from(v)))
+            .collect::<Vec<_>>();
+
+        let diff = env_diff(&job_env, Some(&pkg_env), None);
+        let changes = diff.iter().map(|d| (d.name.as_str(), d.change, d.reuse)).collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("A", EnvChange::Same, true),
+            ("B", EnvChange::Changed, true),
+            ("C", EnvChange::Removed, true),
+            ("D", EnvChange::Added, true),
+        ]);
+
+        let sensitivity = vec![EnvironmentVariableName::from("B")];
+        let diff = env_diff(&job_env, Some(&pkg_env), Some(&sensitivity));
+        let reuse = diff.iter().filter(|d| d.reuse).map(|d| d.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(reuse, vec!["B"]);
+
+        let diff = env_diff(&job_env, None, None);
+        assert!(diff.iter().all(|d| d.change == EnvChange::Added));
+    }
+
+    #[test]
+    fn test_match_versions() {
+        let versions = |vs: &[&str]| vs.iter().map(|v| String::from(*v)).collect::<Vec<_>>();
+
+        let stored = versions(&["1.0", "2.0"]);
+        let current = versions(&["1.0", "2.1"]);
+        assert_eq!(match_versions(&stored, &current), vec![
+            VersionDrift::Same("1.0"),
+            VersionDrift::Changed("2.0", "2.1"),
+        ]);
+
+        let stored = versions(&["1.0", "2.0"]);
+        let current = versions(&["2.0"]);
+        assert_eq!(match_versions(&stored, &current), vec![
+            VersionDrift::Same("2.0"),
+            VersionDrift::Removed("1.0"),
+        ]);
+
+        let stored = versions(&["1.0"]);
+        let current = versions(&["1.1", "2.0"]);
+        assert_eq!(match_versions(&stored, &current), vec![
+            VersionDrift::Changed("1.0", "1.1"),
+            VersionDrift::Added("2.0"),
+        ]);
+
+        let stored = versions(&["9.0", "10.0"]);
+        let current = versions(&["11.0", "9.1"]);
+        assert_eq!(match_versions(&stored, &current), vec![
+            VersionDrift::Changed("9.0", "9.1"),
+            VersionDrift::Changed("10.0", "11.0"),
+        ]);
+    }
+}
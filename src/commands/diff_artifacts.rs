@@ -0,0 +1,283 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'diff-artifacts' subcommand
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use clap::ArgMatches;
+use colored::Colorize;
+use diesel::PgConnection;
+use log::debug;
+use log::trace;
+
+use crate::config::Configuration;
+use crate::filestore::ReleaseStore;
+use crate::filestore::path::StoreRoot;
+use crate::package::HashType;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::repository::Repository;
+use crate::util::docker::ImageName;
+use crate::util::progress::ProgressBars;
+
+/// Implementation of the "diff-artifacts" subcommand
+pub async fn diff_artifacts(matches: &ArgMatches, config: &Configuration, progressbars: ProgressBars, repo: Repository, database_connection: PgConnection) -> Result<()> {
+    let package_name = matches.value_of("package_name").map(String::from).map(PackageName::from).unwrap(); // safe by clap
+    let version_a = matches.value_of("version_a").map(String::from).map(PackageVersion::from).unwrap(); // safe by clap
+    let version_b = matches.value_of("version_b").map(String::from).map(PackageVersion::from).unwrap(); // safe by clap
+    let image_name = matches.value_of("image").map(String::from).map(ImageName::from);
+    let csv = matches.is_present("csv");
+
+    let release_stores = config
+        .release_stores()
+        .iter()
+        .map(|storename| {
+            let bar_release_loading = progressbars.bar();
+
+            let p = config.releases_directory().join(storename);
+            debug!("Loading release directory: {}", p.display());
+            let r = ReleaseStore::load(StoreRoot::new(p)?, &bar_release_loading);
+            if r.is_ok() {
+                bar_release_loading.finish_with_message("Loaded releases successfully");
+            } else {
+                bar_release_loading.finish_with_message("Failed to load releases");
+            }
+
+            r.map(Arc::new)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let find_artifacts = |version: &PackageVersion| -> Result<BTreeMap<String, PathBuf>> {
+        let package = repo
+            .packages()
+            .find(|p| *p.name() == package_name && p.version() == version)
+            .ok_or_else(|| anyhow!("Package {} {} not found in repository", package_name, version))?;
+
+        let artifacts = crate::db::FindArtifacts::builder()
+            .config(config)
+            .release_stores(&release_stores)
+            .database_connection(&database_connection)
+            .env_filter(&[])
+            .script_filter(!matches.is_present("no_script_filter"))
+            .image_name(image_name.as_ref())
+            .package(package)
+            .build()
+            .run()?;
+
+        // The artifacts are keyed by their file name without the package version, so that the
+        // artifacts of the two versions can be matched. If a file was released more than once, the
+        // latest release wins.
+        let mut latest: BTreeMap<String, (PathBuf, Option<NaiveDateTime>)> = BTreeMap::new();
+        for (path, released) in artifacts {
+            let file_name = path
+                .artifact_path()
+                .file_name()
+                .and_then(|f| f.to_str())
+                .ok_or_else(|| anyhow!("Artifact path is not valid UTF-8: {}", path.display()))
+                .map(|f| without_version(f, version.as_str()))?;
+            trace!("Found artifact for {} {}: {} ({:?})", package_name, version, path.display(), released);
+
+            let is_newer = latest.get(&file_name).map(|(_, other)| released > *other).unwrap_or(true);
+            if is_newer {
+                latest.insert(file_name, (path.joined(), released));
+            }
+        }
+
+        if latest.is_empty() {
+            return Err(anyhow!("No released artifacts found for {} {}", package_name, version));
+        }
+
+        Ok(latest.into_iter().map(|(name, (path, _))| (name, path)).collect())
+    };
+
+    let artifacts_a = find_artifacts(&version_a)?;
+    let artifacts_b = find_artifacts(&version_b)?;
+
+    let fmt_size = |size: Option<u64>| match size {
+        None => String::from("-"),
+        Some(size) if csv => size.to_string(),
+        Some(size) => bytesize::ByteSize::b(size).to_string(),
+    };
+
+    let mut changed = vec![];
+    let mut data = vec![];
+    for name in artifacts_a.keys().chain(artifacts_b.keys().filter(|k| !artifacts_a.contains_key(*k))) {
+        let a = artifacts_a.get(name);
+        let b = artifacts_b.get(name);
+        let size_a = a.map(PathBuf::as_path).map(file_size).transpose()?;
+        let size_b = b.map(PathBuf::as_path).map(file_size).transpose()?;
+
+        let change = match (a, b) {
+            (Some(_), None) => "removed",
+            (None, Some(_)) => "added",
+            (Some(a), Some(b)) => if size_a == size_b && HashType::Sha256.hash_file(a)? == HashType::Sha256.hash_file(b)? {
+                "unchanged"
+            } else {
+                changed.push((name, a, b));
+                "changed"
+            },
+            (None, None) => unreachable!(), // every name is a key of one of the maps
+        };
+
+        data.push(vec![name.clone(), fmt_size(size_a), fmt_size(size_b), String::from(change)]);
+    }
+
+    let hdrs = crate::commands::util::mk_header(vec!["Artifact", version_a.as_str(), version_b.as_str(), "Change"]);
+    crate::commands::util::display_data(hdrs, data, csv)?;
+
+    // The content of tar archives can be compared without any external tools
+    for (name, a, b) in changed.iter().filter(|(_, a, b)| is_tar(a) && is_tar(b)) {
+        let entries_a = tar_entries(a)?;
+        let entries_b = tar_entries(b)?;
+
+        let data = entries_a
+            .keys()
+            .chain(entries_b.keys().filter(|k| !entries_a.contains_key(*k)))
+            .filter_map(|path| {
+                let entry_a = entries_a.get(path);
+                let entry_b = entries_b.get(path);
+                let change = match (entry_a, entry_b) {
+                    (Some(_), None) => "removed",
+                    (None, Some(_)) => "added",
+                    (Some(a), Some(b)) if a != b => "changed",
+                    _ => return None,
+                };
+                let size_a = entry_a.map(|(size, _)| *size);
+                let size_b = entry_b.map(|(size, _)| *size);
+
+                Some(vec![path.display().to_string(), fmt_size(size_a), fmt_size(size_b), String::from(change)])
+            })
+            .collect::<Vec<_>>();
+
+        if !data.is_empty() {
+            writeln!(std::io::stdout(), "\nFiles in {}:", name.green())?;
+            let hdrs = crate::commands::util::mk_header(vec!["File", version_a.as_str(), version_b.as_str(), "Change"]);
+            crate::commands::util::display_data(hdrs, data, csv)?;
+        }
+    }
+
+    if matches.is_present("diffoscope") {
+        let diffoscope = which::which("diffoscope").context("Finding diffoscope executable")?;
+        for (_, a, b) in changed {
+            diffoscope_files(&diffoscope, a, b)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    std::fs::metadata(path)
+        .map(|meta| meta.len())
+        .with_context(|| anyhow!("Getting metadata of {}", path.display()))
+        .map_err(Error::from)
+}
+
+/// The file name `file_name` with the package version replaced by "{version}"
+///
+/// Only a version which follows a "-" and ends the file name or is followed by one of ".", "-",
+/// "_" or "+" is replaced, so that the version string does not match in other parts of the name.
+fn without_version(file_name: &str, version: &str) -> String {
+    let needle = format!("-{}", version);
+    let mut result = String::with_capacity(file_name.len());
+    let mut rest = file_name;
+    while let Some(pos) = rest.find(&needle) {
+        let end = pos + needle.len();
+        let delimited = rest[end..]
+            .chars()
+            .next()
+            .map(|c| matches!(c, '.' | '-' | '_' | '+'))
+            .unwrap_or(true);
+
+        if delimited {
+            result.push_str(&rest[..pos]);
+            result.push_str("-{version}");
+        } else {
+            result.push_str(&rest[..end]);
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn is_tar(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "tar").unwrap_or(false)
+}
+
+/// The regular files in a tar archive with their sizes and the hashes of their content
+fn tar_entries(path: &Path) -> Result<BTreeMap<PathBuf, (u64, String)>> {
+    use sha2::Digest;
+
+    let file = std::fs::File::open(path).with_context(|| anyhow!("Opening {}", path.display()))?;
+    tar::Archive::new(file)
+        .entries()
+        .with_context(|| anyhow!("Reading archive {}", path.display()))?
+        .filter_map(|entry| match entry {
+            Ok(entry) if entry.header().entry_type() != tar::EntryType::Regular => None,
+            Ok(mut entry) => Some({
+                let mut m = sha2::Sha256::new();
+                entry.path()
+                    .map(|p| p.to_path_buf())
+                    .and_then(|p| std::io::copy(&mut entry, &mut m).map(|_| p))
+                    .map(|p| (p, (entry.size(), format!("{:x}", m.finalize()))))
+                    .map_err(Error::from)
+            }),
+            Err(e) => Some(Err(Error::from(e))),
+        })
+        .collect::<Result<BTreeMap<_, _>>>()
+        .with_context(|| anyhow!("Reading archive {}", path.display()))
+        .map_err(Error::from)
+}
+
+/// Print the differences of two files with diffoscope
+fn diffoscope_files(diffoscope: &Path, a: &Path, b: &Path) -> Result<()> {
+    let status = std::process::Command::new(diffoscope)
+        .arg(a)
+        .arg(b)
+        .status()
+        .with_context(|| anyhow!("Running {}", diffoscope.display()))?;
+
+    // diffoscope exits with 1 if the files differ
+    match status.code() {
+        Some(0) | Some(1) => Ok(()),
+        _ => Err(anyhow!("diffoscope failed for {} and {}: {}", a.display(), b.display(), status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_version() {
+        assert_eq!(without_version("foo-1.0.tar", "1.0"), "foo-{version}.tar");
+        assert_eq!(without_version("foo-1.0-1.x86_64.rpm", "1.0"), "foo-{version}-1.x86_64.rpm");
+        assert_eq!(without_version("foo-1.0", "1.0"), "foo-{version}");
+        assert_eq!(without_version("foo-1.0.1.tar", "1.0.1"), "foo-{version}.tar");
+    }
+
+    #[test]
+    fn test_without_version_only_replaces_delimited_versions() {
+        assert_eq!(without_version("lib1.0-1.0.tar", "1.0"), "lib1.0-{version}.tar");
+        assert_eq!(without_version("foo-10.tar", "1"), "foo-10.tar");
+        assert_eq!(without_version("foo-1.0a.tar", "1.0"), "foo-1.0a.tar");
+    }
+}
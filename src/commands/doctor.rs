@@ -0,0 +1,70 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'doctor' subcommand
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::ArgMatches;
+
+use crate::config::Configuration;
+use crate::util::expiry::ExpiryStatus;
+
+/// Implementation of the "doctor" subcommand
+pub async fn doctor(matches: &ArgMatches, config: &Configuration) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let warning_days = crate::util::expiry::warning_days(config);
+    let now = chrono::offset::Utc::now().naive_utc();
+    let hdr = crate::commands::util::mk_header(["Item", "Expires (UTC)", "Status"].to_vec());
+
+    let checks = crate::util::expiry::check_all(config).await;
+    let n_expired = checks
+        .iter()
+        .filter(|check| check.status(&now, warning_days) == ExpiryStatus::Expired)
+        .count();
+
+    let data = checks
+        .into_iter()
+        .map(|check| {
+            let status = match check.status(&now, warning_days) {
+                ExpiryStatus::Valid => String::from("ok"),
+                ExpiryStatus::ExpiresSoon => String::from("expires soon"),
+                ExpiryStatus::Expired => String::from("expired"),
+                ExpiryStatus::NoExpiry => String::from("does not expire"),
+                ExpiryStatus::Unknown => String::from("unknown"),
+            };
+
+            let expires = match check.expires {
+                Ok(Some(date)) => date.to_string(),
+                Ok(None) => String::from("-"),
+                Err(e) => format!("{:#}", e),
+            };
+
+            vec![check.name, expires, status]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    if data.is_empty() {
+        let out = std::io::stdout();
+        let mut outlock = out.lock();
+        writeln!(outlock, "Nothing to check, no gpg signing key or Kubernetes endpoints configured")?;
+        return Ok(());
+    }
+
+    crate::commands::util::display_data(hdr, data, csv)?;
+
+    if n_expired > 0 {
+        Err(anyhow!("{} secrets or certificates expired", n_expired))
+    } else {
+        Ok(())
+    }
+}
@@ -27,10 +27,17 @@ use tokio_stream::StreamExt;
 
 use crate::config::Configuration;
 use crate::config::EndpointName;
+use crate::db::DbConnectionConfig;
+use crate::db::models as dbmodels;
 use crate::util::progress::ProgressBars;
 use crate::endpoint::Endpoint;
 
-pub async fn endpoint(matches: &ArgMatches, config: &Configuration, progress_generator: ProgressBars) -> Result<()> {
+pub async fn endpoint(
+    matches: &ArgMatches,
+    config: &Configuration,
+    progress_generator: ProgressBars,
+    db_connection_config: DbConnectionConfig<'_>,
+) -> Result<()> {
     let endpoint_names = matches
         .value_of("endpoint_name")
         .map(String::from)
@@ -46,16 +53,117 @@ pub async fn endpoint(matches: &ArgMatches, config: &Configuration, progress_gen
         });
 
     match matches.subcommand() {
+        Some(("list", matches)) => list(endpoint_names, matches, config, progress_generator).await,
         Some(("ping", matches)) => ping(endpoint_names, matches, config, progress_generator).await,
         Some(("stats", matches)) => stats(endpoint_names, matches, config, progress_generator).await,
         Some(("container", matches)) => crate::commands::endpoint_container::container(endpoint_names, matches, config).await,
         Some(("containers", matches)) => containers(endpoint_names, matches, config).await,
         Some(("images", matches)) => images(endpoint_names, matches, config).await,
+        Some(("drain", _)) => set_drained(endpoint_names, config, db_connection_config, true),
+        Some(("undrain", _)) => set_drained(endpoint_names, config, db_connection_config, false),
+        Some(("checkpoint", _)) => checkpoint(endpoint_names, config, db_connection_config).await,
+        Some(("checkpoints", matches)) => checkpoints(endpoint_names, matches, db_connection_config),
+        Some(("scratch", matches)) => scratch(endpoint_names, matches, config).await,
         Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
 }
 
+/// List the endpoints with their reachability, the version of their container engine, the number of
+/// running butido containers and the available images
+///
+/// Unlike the other subcommands, this does not fail if an endpoint is not reachable.
+async fn list(endpoint_names: Vec<EndpointName>,
+    matches: &ArgMatches,
+    config: &Configuration,
+    progress_generator: ProgressBars
+) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let bar = progress_generator.bar();
+    bar.set_length(endpoint_names.len() as u64);
+    bar.set_message("Querying endpoints");
+
+    let hdr = crate::commands::util::mk_header([
+        "Name",
+        "Type",
+        "Reachable",
+        "Version",
+        "Running jobs",
+        "Images",
+        "Missing images",
+    ].to_vec());
+
+    let required_images = config
+        .docker()
+        .images()
+        .iter()
+        .map(|img| (img, config.docker().image_reference(img)))
+        .collect::<Vec<_>>();
+    let required_images = &required_images;
+    let data = config
+        .docker()
+        .endpoints()
+        .iter()
+        .filter(|(ep_name, _)| endpoint_names.contains(ep_name))
+        .map(|(ep_name, ep_cfg)| {
+            let bar = bar.clone();
+            async move {
+                let ty = match (ep_cfg.endpoint_type(), ep_cfg.connection().is_some()) {
+                    (crate::config::EndpointType::Socket, false) => "socket",
+                    (crate::config::EndpointType::Http, false) => "http",
+                    (crate::config::EndpointType::Podman, false) => "podman",
+                    (crate::config::EndpointType::Kubernetes, _) => "kubernetes",
+                    (crate::config::EndpointType::Mock, _) => "mock",
+                    (crate::config::EndpointType::Podman, true) => "podman (ssh)",
+                    (_, true) => "docker (ssh)",
+                };
+
+                let timeout = std::time::Duration::from_secs(ep_cfg.timeout().unwrap_or(10));
+                let info = tokio::time::timeout(timeout, async {
+                    let endpoint = Endpoint::setup_endpoint(ep_name, ep_cfg, ep_cfg.maxjobs())?;
+                    endpoint.ping().await?;
+                    let version = endpoint.engine_version().await?;
+                    let running = endpoint.running_butido_containers().await?;
+                    let images = endpoint.image_names().await?;
+                    Ok::<_, Error>((version, running, images))
+                })
+                .await
+                .map_err(|_| anyhow!("Timeout after {} seconds", timeout.as_secs()))
+                .and_then(|r| r);
+                bar.inc(1);
+
+                match info {
+                    Ok((version, running, Some(images))) => {
+                        let missing = required_images
+                            .iter()
+                            .filter(|(name, _)| ep_cfg.supports_image(name))
+                            .map(|(_, reference)| reference)
+                            .filter(|img| !images.contains(img))
+                            .map(|img| img.as_ref())
+                            .join(", ");
+
+                        // The digest references ("name@digest") of the images are not counted
+                        let n_images = images.iter().filter(|img| !img.as_ref().contains('@')).count();
+                        vec![ep_name.as_ref().to_string(), ty.to_string(), String::from("yes"), version, running.to_string(), n_images.to_string(), missing]
+                    },
+                    Ok((version, running, None)) => {
+                        vec![ep_name.as_ref().to_string(), ty.to_string(), String::from("yes"), version, running.to_string(), String::from("-"), String::from("-")]
+                    },
+                    Err(e) => {
+                        debug!("Endpoint {} not reachable: {:?}", ep_name, e);
+                        vec![ep_name.as_ref().to_string(), ty.to_string(), format!("no: {}", e), String::from("-"), String::from("-"), String::from("-"), String::from("-")]
+                    },
+                }
+            }
+        })
+        .collect::<futures::stream::FuturesOrdered<_>>()
+        .collect::<Vec<Vec<String>>>()
+        .await;
+
+    bar.finish_with_message("Querying endpoints finished");
+    crate::commands::util::display_data(hdr, data, csv)
+}
+
 async fn ping(endpoint_names: Vec<EndpointName>,
     matches: &ArgMatches,
     config: &Configuration,
@@ -509,6 +617,180 @@ async fn images_present(endpoint_names: Vec<EndpointName>,
         })
 }
 
+fn set_drained(endpoint_names: Vec<EndpointName>,
+    config: &Configuration,
+    db_connection_config: DbConnectionConfig<'_>,
+    drained: bool,
+) -> Result<()> {
+    if let Some(unknown) = endpoint_names.iter().find(|name| !config.docker().endpoints().contains_key(*name)) {
+        return Err(anyhow!("Endpoint not configured: {}", unknown))
+    }
+
+    let conn = db_connection_config.establish_connection()?;
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+
+    endpoint_names.iter().try_for_each(|ep_name| {
+        let ep = dbmodels::Endpoint::set_drained(&conn, ep_name, drained)
+            .with_context(|| anyhow!("Setting drained state of endpoint {}", ep_name))?;
+
+        if ep.drained {
+            writeln!(lock, "{} drained", ep.name).map_err(Error::from)
+        } else {
+            writeln!(lock, "{} undrained", ep.name).map_err(Error::from)
+        }
+    })
+}
+
+/// Drain the endpoints and checkpoint all running butido containers on them
+///
+/// The checkpoints are recorded in the database before the containers are checkpointed, so the
+/// jobs running in the containers are aborted for the maintenance instead of failing with an
+/// unexplained error. The containers are not restored by butido.
+async fn checkpoint(endpoint_names: Vec<EndpointName>,
+    config: &Configuration,
+    db_connection_config: DbConnectionConfig<'_>,
+) -> Result<()> {
+    let conn = db_connection_config.establish_connection()?;
+    let endpoints = connect_to_endpoints(config, &endpoint_names).await?;
+    let mut out = std::io::stdout();
+
+    for endpoint in endpoints.iter() {
+        let ep = dbmodels::Endpoint::set_drained(&conn, endpoint.name(), true)
+            .with_context(|| anyhow!("Draining endpoint {}", endpoint.name()))?;
+        writeln!(out, "{} drained", ep.name)?;
+
+        for (container_id, job) in endpoint.butido_containers().await? {
+            let now = chrono::offset::Local::now().naive_local();
+            let name = format!("butido-{}", now.format("%Y%m%d%H%M%S"));
+            let job_uuid = uuid::Uuid::parse_str(&job).ok();
+            let checkpoint = dbmodels::Checkpoint::create(&conn, &ep, &container_id, job_uuid.as_ref(), &name, &now)?;
+
+            if let Err(e) = endpoint.checkpoint_container(&container_id, &name).await {
+                checkpoint.set_state(&conn, dbmodels::CheckpointState::Failed)?;
+                return Err(e)
+            }
+
+            checkpoint.set_state(&conn, dbmodels::CheckpointState::Checkpointed)?;
+            writeln!(out, "Checkpointed container {} (job {}) on {} as {}", container_id, job, ep.name, name)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// List the recorded checkpoints of the endpoints
+fn checkpoints(endpoint_names: Vec<EndpointName>,
+    matches: &ArgMatches,
+    db_connection_config: DbConnectionConfig<'_>,
+) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let conn = db_connection_config.establish_connection()?;
+    let hdr = crate::commands::util::mk_header([
+        "Endpoint",
+        "Container id",
+        "Job",
+        "Checkpoint",
+        "State",
+        "Created",
+    ].to_vec());
+
+    let data = dbmodels::Checkpoint::list(&conn)?
+        .into_iter()
+        .filter(|(_, ep)| endpoint_names.iter().any(|name| name.as_ref() == ep.name))
+        .map(|(checkpoint, ep)| {
+            vec![
+                ep.name,
+                checkpoint.container_id,
+                checkpoint.job_uuid.map(|u| u.to_string()).unwrap_or_else(|| String::from("-")),
+                checkpoint.name,
+                checkpoint.state,
+                checkpoint.created_at.to_string(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+
+    crate::commands::util::display_data(hdr, data, csv)
+}
+
+/// List the working directories in the scratch space of the endpoints and, with `--clean`, remove
+/// the ones which are not used by a running container and are older than the retention for
+/// failed jobs
+async fn scratch(endpoint_names: Vec<EndpointName>,
+    matches: &ArgMatches,
+    config: &Configuration,
+) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let clean = matches.is_present("clean");
+    let endpoint_names = endpoint_names
+        .into_iter()
+        .filter(|name| {
+            let has_scratch = config.docker()
+                .endpoints()
+                .get(name)
+                .map(|ep| ep.scratch().is_some())
+                .unwrap_or(false);
+            if !has_scratch {
+                info!("Endpoint {} has no scratch space, skipping", name);
+            }
+            has_scratch
+        })
+        .collect::<Vec<_>>();
+
+    let now = chrono::offset::Utc::now().naive_utc();
+    let mut leaked = vec![];
+    let mut data = vec![];
+    for ep in connect_to_endpoints(config, &endpoint_names).await? {
+        let running_jobs = ep.butido_containers()
+            .await?
+            .into_iter()
+            .map(|(_, job)| job)
+            .collect::<Vec<_>>();
+        let scratch = ep.scratch()
+            .as_ref()
+            .ok_or_else(|| anyhow!("Endpoint {} has no scratch space", ep.name()))?;
+
+        for dir in ep.scratch_dirs().await? {
+            let in_use = running_jobs.contains(&dir.job().to_string());
+            if !in_use && scratch.may_remove_failed(now - *dir.modified()) {
+                leaked.push((ep.clone(), dir.clone()));
+            }
+
+            data.push(vec![
+                ep.name().to_string(),
+                dir.job().to_string(),
+                bytesize::ByteSize::b(dir.size_bytes()).to_string(),
+                dir.modified().to_string(),
+                if in_use { "yes" } else { "no" }.to_string(),
+            ]);
+        }
+    }
+
+    if !clean {
+        let hdr = crate::commands::util::mk_header(["Endpoint", "Job", "Size", "Modified (UTC)", "In use"].to_vec());
+        return crate::commands::util::display_data(hdr, data, csv)
+    }
+
+    if leaked.is_empty() {
+        info!("No scratch directories to remove");
+        return Ok(())
+    }
+
+    let size = leaked.iter().map(|(_, dir)| dir.size_bytes()).sum::<u64>();
+    let prompt = format!("Really remove {} scratch directories ({})?", leaked.len(), bytesize::ByteSize::b(size));
+    if !dialoguer::Confirm::new().with_prompt(prompt).interact()? {
+        return Ok(())
+    }
+
+    let mut out = std::io::stdout();
+    for (ep, dir) in leaked {
+        ep.remove_scratch_dir(&dir.job()).await?;
+        writeln!(out, "Removed scratch directory of job {} on {}", dir.job(), ep.name())?;
+    }
+
+    Ok(())
+}
+
 /// Helper function to connect to all endpoints from the configuration, that appear (by name) in
 /// the `endpoint_names` list
 pub(super) async fn connect_to_endpoints(config: &Configuration, endpoint_names: &[EndpointName]) -> Result<Vec<Arc<Endpoint>>> {
@@ -521,7 +803,15 @@ pub(super) async fn connect_to_endpoints(config: &Configuration, endpoint_names:
             crate::endpoint::EndpointConfiguration::builder()
                 .endpoint_name(ep_name.clone())
                 .endpoint(ep_cfg.clone())
-                .required_images(config.docker().images().clone())
+                .max_jobs(ep_cfg.maxjobs())
+                .required_images({
+                    config.docker()
+                        .images()
+                        .iter()
+                        .filter(|img| ep_cfg.supports_image(img))
+                        .map(|img| config.docker().image_reference(img))
+                        .collect()
+                })
                 .required_docker_versions(config.docker().docker_versions().clone())
                 .required_docker_api_versions(config.docker().docker_api_versions().clone())
                 .build()
@@ -25,6 +25,10 @@ pub async fn env_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
     use filters::filter::Filter;
     use std::io::Write;
 
+    if matches.is_present("stdin") {
+        return env_of_batch(matches, &repo);
+    }
+
     let package_filter = {
         let name = matches
             .value_of("package_name")
@@ -61,3 +65,35 @@ pub async fn env_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
             Ok(())
         })
 }
+
+/// Implementation of "env_of --stdin"
+fn env_of_batch(matches: &ArgMatches, repo: &Repository) -> Result<()> {
+    let queries = crate::commands::util::read_batch_queries()?;
+
+    let packages = queries
+        .iter()
+        .flat_map(|query| repo.packages().filter(move |pkg| query.matches(pkg)))
+        .inspect(|pkg| trace!("Found package: {:?}", pkg))
+        .collect::<Vec<_>>();
+
+    let json = packages
+        .iter()
+        .map(|pkg| serde_json::json!({
+            "package": pkg.name(),
+            "version": pkg.version(),
+            "env": pkg.environment().clone().unwrap_or_default(),
+        }))
+        .collect::<Vec<_>>();
+
+    let rows = packages
+        .iter()
+        .flat_map(|pkg| {
+            pkg.environment()
+                .iter()
+                .flatten()
+                .map(move |(key, value)| vec![pkg.name().to_string(), pkg.version().to_string(), key.to_string(), value.clone()])
+        })
+        .collect();
+
+    crate::commands::util::display_batch_results(json.into(), &["package", "version", "name", "value"], rows, matches.is_present("csv"))
+}
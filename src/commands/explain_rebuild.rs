@@ -0,0 +1,49 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'explain-rebuild' subcommand
+
+use anyhow::Result;
+use clap::ArgMatches;
+use log::info;
+use uuid::Uuid;
+
+use crate::db::DbConnectionConfig;
+use crate::db::models as dbmodels;
+
+/// Implementation of the "explain-rebuild" subcommand
+pub fn explain_rebuild(db_connection_config: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let job_uuid = matches
+        .value_of("job_uuid")
+        .map(Uuid::parse_str)
+        .transpose()?
+        .unwrap(); // safe by clap
+    let conn = db_connection_config.establish_connection()?;
+
+    let data = dbmodels::RebuildReason::fetch_for_job(&conn, &job_uuid)?
+        .into_iter()
+        .map(|(reason, candidate)| {
+            vec![
+                candidate.map(|job| job.uuid.to_string()).unwrap_or_else(|| String::from("-")),
+                reason.kind,
+                reason.detail,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    if data.is_empty() {
+        info!("No reasons recorded for job {}, it was either reused or not built by this version of butido", job_uuid);
+        Ok(())
+    } else {
+        let hdrs = crate::commands::util::mk_header(vec!["Candidate", "Reason", "Detail"]);
+        crate::commands::util::display_data(hdrs, data, csv)
+    }
+}
@@ -15,6 +15,7 @@ use std::io::Write;
 use std::sync::Arc;
 use std::convert::TryFrom;
 
+use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
@@ -25,6 +26,7 @@ use log::debug;
 use log::trace;
 
 use crate::config::Configuration;
+use crate::db::MetadataFilter;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
 use crate::filestore::path::StoreRoot;
@@ -55,6 +57,28 @@ pub async fn find_artifact(matches: &ArgMatches, config: &Configuration, progres
         .map(String::from)
         .map(ImageName::from);
 
+    let metadata_filter = {
+        let mut filter = vec![];
+        if let Some(kind) = matches.value_of("artifact_type") {
+            let kind = crate::filestore::artifact_type::artifact_kind(kind)
+                .ok_or_else(|| anyhow!("Unknown artifact type: {}", kind))?;
+            filter.push(MetadataFilter { kind, field: None });
+        }
+
+        let fields = [
+            ("rpm_name", "rpm", "name"),
+            ("rpm_arch", "rpm", "arch"),
+            ("deb_name", "deb", "package"),
+            ("deb_arch", "deb", "architecture"),
+        ];
+        for (arg, kind, key) in fields.iter() {
+            if let Some(value) = matches.value_of(*arg) {
+                filter.push(MetadataFilter { kind: *kind, field: Some((*key, value.to_string())) });
+            }
+        }
+        filter
+    };
+
     log::debug!("Finding artifacts for '{:?}' '{:?}'", package_name_regex, package_version_constraint);
 
     let release_stores = config
@@ -84,7 +108,7 @@ pub async fn find_artifact(matches: &ArgMatches, config: &Configuration, progres
         }
 
         debug!("Loading staging directory: {}", p.display());
-        let r = StagingStore::load(StoreRoot::new(p.clone())?, &bar_staging_loading);
+        let r = StagingStore::load_read_only(StoreRoot::new(p.clone())?, &bar_staging_loading);
         if r.is_ok() {
             bar_staging_loading.finish_with_message("Loaded staging successfully");
         } else {
@@ -95,7 +119,6 @@ pub async fn find_artifact(matches: &ArgMatches, config: &Configuration, progres
         None
     };
 
-    let database = Arc::new(database_connection);
     repo.packages()
         .filter(|p| package_name_regex.captures(p.name()).is_some())
         .filter(|p| {
@@ -111,10 +134,11 @@ pub async fn find_artifact(matches: &ArgMatches, config: &Configuration, progres
                 .config(config)
                 .release_stores(&release_stores)
                 .staging_store(staging_store.as_ref())
-                .database_connection(database.clone())
+                .database_connection(&database_connection)
                 .env_filter(&env_filter)
                 .script_filter(script_filter)
                 .image_name(image_name.as_ref())
+                .metadata_filter(&metadata_filter)
                 .package(pkg)
                 .build()
                 .run()?;
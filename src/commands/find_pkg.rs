@@ -43,9 +43,12 @@ pub async fn find_pkg(
         .context("Parsing package version constraint")
         .context("A valid package version constraint looks like this: '=1.0.0'")?;
 
+    let repository = matches.value_of("repository");
+
     let iter = repo
         .packages()
         .filter(|p| package_name_regex.captures(p.name()).is_some())
+        .filter(|p| repository.map(|r| repo.origin_of(p) == Some(r)).unwrap_or(true))
         .filter(|p| {
             package_version_constraint
                 .as_ref()
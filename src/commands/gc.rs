@@ -0,0 +1,654 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'gc' subcommand
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use clap::ArgMatches;
+use diesel::Connection;
+use diesel::ExpressionMethods;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use itertools::Itertools;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::config::Configuration;
+use crate::config::SigningTool;
+use crate::db::models;
+use crate::db::DbConnectionConfig;
+use crate::filestore::path::StoreRoot;
+use crate::filestore::ReleaseStore;
+use crate::filestore::StagingStore;
+use crate::filestore::StoreLock;
+use crate::filestore::LOCK_FILE_NAME;
+use crate::package::PackageVersion;
+use crate::schema;
+use crate::util::progress::ProgressBars;
+
+/// Implementation of the "gc" subcommand
+pub fn gc(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("staging", matches)) => staging(db_connection_config, config, matches, progressbars),
+        Some(("release", matches)) => release(db_connection_config, config, matches, progressbars),
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// A directory in the staging store
+#[derive(Debug)]
+struct StagingDir {
+    path: PathBuf,
+
+    /// The submit the directory belongs to, if it is known to the database
+    submit: Option<uuid::Uuid>,
+
+    /// The time of the submit, or the modification time of the directory if there is no submit
+    time: NaiveDateTime,
+
+    /// Whether the directory contains pinned artifacts, these directories are never removed
+    pinned: bool,
+}
+
+/// Why a staging directory or an artifact is removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reason {
+    Age,
+    NotKept,
+    UnknownSubmit,
+    UnknownArtifact,
+    Superseded,
+    NotReleased,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Age => write!(f, "older than limit"),
+            Reason::NotKept => write!(f, "not one of the last submits"),
+            Reason::UnknownSubmit => write!(f, "submit not in database"),
+            Reason::UnknownArtifact => write!(f, "artifact not in database"),
+            Reason::Superseded => write!(f, "superseded by newer versions"),
+            Reason::NotReleased => write!(f, "not released"),
+        }
+    }
+}
+
+/// The retention policies of the "gc staging" subcommand
+#[derive(Debug)]
+struct Policy {
+    older_than: Option<NaiveDateTime>,
+    keep_last: Option<usize>,
+    orphaned: bool,
+}
+
+impl Policy {
+    /// The staging directories to remove, as indexes into `dirs`, with the reason for removing them
+    ///
+    /// Directories with pinned artifacts are never selected.
+    fn select_dirs(&self, dirs: &[StagingDir]) -> Vec<(usize, Reason)> {
+        // The directories of the last submits are kept regardless of their age
+        let kept = self.keep_last.map(|n| {
+            dirs.iter()
+                .enumerate()
+                .filter(|(_, dir)| dir.submit.is_some())
+                .sorted_by(|(_, a), (_, b)| b.time.cmp(&a.time))
+                .take(n)
+                .map(|(i, _)| i)
+                .collect::<HashSet<_>>()
+        });
+
+        dirs.iter()
+            .enumerate()
+            .filter_map(|(i, dir)| {
+                if dir.pinned {
+                    return None;
+                }
+
+                if self.orphaned && dir.submit.is_none() {
+                    return Some((i, Reason::UnknownSubmit));
+                }
+
+                if kept.as_ref().map(|k| k.contains(&i)).unwrap_or(false) {
+                    return None;
+                }
+
+                match self.older_than {
+                    Some(limit) if dir.time < limit => Some((i, Reason::Age)),
+                    Some(_) => None,
+                    None if self.keep_last.is_some() && dir.submit.is_some() => Some((i, Reason::NotKept)),
+                    None => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Something that was (or would be, with --dry-run) removed
+struct Removal {
+    path: PathBuf,
+    reason: Reason,
+    size: u64,
+}
+
+/// Implementation of the "gc staging" subcommand
+fn staging(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    let dry_run = matches.is_present("dry-run");
+    let policy = Policy {
+        older_than: crate::commands::util::get_date_filter("older_than", matches)?.map(|date| date.naive_utc()),
+        keep_last: matches.value_of("keep_last").map(usize::from_str).transpose()?,
+        orphaned: matches.is_present("orphaned"),
+    };
+    debug!("Retention policy: {:?}", policy);
+
+    let conn = db_connection_config.establish_connection()?;
+    let submit_times = schema::submits::table
+        .select((schema::submits::uuid, schema::submits::submit_time))
+        .load::<(uuid::Uuid, NaiveDateTime)>(&conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    // The pathes of the pinned artifacts, by the submits that produced them
+    let pinned_artifacts = schema::artifact_pins::table
+        .inner_join(schema::artifacts::table.inner_join(schema::jobs::table.inner_join(schema::submits::table)))
+        .select((schema::submits::uuid, schema::artifacts::path))
+        .load::<(uuid::Uuid, String)>(&conn)?
+        .into_iter()
+        .into_group_map()
+        .into_iter()
+        .map(|(submit, pathes)| (submit, pathes.into_iter().collect::<HashSet<_>>()))
+        .collect::<HashMap<_, _>>();
+
+    let dirs = staging_dirs(config.staging_directory(), &submit_times, &pinned_artifacts)?;
+    let selected = policy.select_dirs(&dirs);
+    let mut removals = vec![];
+
+    for (i, reason) in selected.iter() {
+        let dir = &dirs[*i];
+        let lock = match StoreLock::acquire(&dir.path) {
+            Ok(lock) => lock,
+            Err(e) => {
+                warn!("Skipping {}: {}", dir.path.display(), e);
+                continue;
+            }
+        };
+
+        let size = dir_size(&dir.path)?;
+        if !dry_run {
+            remove_staging_dir(&dir.path, lock)?;
+            models::AuditLogEntry::append(&conn, "gc-staging", &dir.path.display().to_string())?;
+        }
+
+        removals.push(Removal {
+            path: dir.path.clone(),
+            reason: *reason,
+            size,
+        });
+    }
+
+    if policy.orphaned {
+        let remaining = dirs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !selected.iter().any(|(s, _)| s == i))
+            .filter_map(|(_, dir)| dir.submit.map(|submit| (dir, submit)));
+
+        for (dir, submit) in remaining {
+            let known = artifact_paths(&conn, &submit)?;
+            let pinned = pinned_artifacts.get(&submit);
+
+            let bar = progressbars.bar();
+            debug!("Loading staging directory: {}", dir.path.display());
+            let store = StagingStore::load(StoreRoot::new(dir.path.clone())?, &bar);
+            bar.finish_with_message(format!("Loaded staging directory {}", dir.path.display()));
+            let store = match store {
+                Ok(store) => store,
+                Err(e) => {
+                    warn!("Skipping {}: {}", dir.path.display(), e);
+                    continue;
+                }
+            };
+
+            let orphans = store.iter().filter(|ap| {
+                let path = ap.display().to_string();
+                !known.contains(&path) && !pinned.map(|p| p.contains(&path)).unwrap_or(false)
+            });
+
+            for ap in orphans {
+                let path = dir.path.join(ap);
+                let size = std::fs::metadata(&path)
+                    .with_context(|| anyhow!("Getting metadata of {}", path.display()))?
+                    .len();
+
+                if !dry_run {
+                    std::fs::remove_file(&path).with_context(|| anyhow!("Removing {}", path.display()))?;
+                    models::AuditLogEntry::append(&conn, "gc-staging", &path.display().to_string())?;
+                }
+
+                removals.push(Removal {
+                    path,
+                    reason: Reason::UnknownArtifact,
+                    size,
+                });
+            }
+        }
+    }
+
+    print_removals(removals, dry_run)
+}
+
+/// Print what was (or would be, with --dry-run) removed and how much space that reclaims
+fn print_removals(removals: Vec<Removal>, dry_run: bool) -> Result<()> {
+    if removals.is_empty() {
+        info!("Nothing to remove");
+        return Ok(());
+    }
+
+    let reclaimed = removals.iter().map(|r| r.size).sum::<u64>();
+    let data = removals
+        .into_iter()
+        .map(|r| vec![r.path.display().to_string(), r.reason.to_string(), bytesize::ByteSize::b(r.size).to_string()])
+        .collect::<Vec<_>>();
+    crate::commands::util::display_data(crate::commands::util::mk_header(vec!["Path", "Reason", "Size"]), data, false)?;
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    if dry_run {
+        writeln!(outlock, "Would reclaim {}", bytesize::ByteSize::b(reclaimed))?;
+    } else {
+        writeln!(outlock, "Reclaimed {}", bytesize::ByteSize::b(reclaimed))?;
+    }
+    Ok(())
+}
+
+/// A release of an artifact in a release store
+#[derive(Debug)]
+struct ReleasedArtifact {
+    release_id: i32,
+    path: String,
+    package: String,
+    version: PackageVersion,
+    pinned: bool,
+}
+
+/// The releases of packages with `keep` newer versions in the store, as indexes into `releases`
+///
+/// Pinned artifacts are never selected.
+fn superseded(releases: &[ReleasedArtifact], keep: usize) -> Vec<usize> {
+    let mut kept_versions: HashMap<&str, Vec<&PackageVersion>> = HashMap::new();
+    for release in releases {
+        let versions = kept_versions.entry(&release.package).or_default();
+        if !versions.contains(&&release.version) {
+            versions.push(&release.version);
+        }
+    }
+
+    for versions in kept_versions.values_mut() {
+        versions.sort_by(|a, b| b.compare(a));
+        versions.truncate(keep);
+    }
+
+    releases
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| !r.pinned && !kept_versions[r.package.as_str()].contains(&&r.version))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Implementation of the "gc release" subcommand
+///
+/// Superseded releases are removed from the database in one transaction before their files are
+/// removed. If butido is interrupted in between, the remaining files are not released anymore and
+/// removed by the next run with --unreferenced.
+fn release(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    let store_name = matches.value_of("release").unwrap(); // safe by clap
+    if !config.release_stores().iter().any(|s| s == store_name) {
+        return Err(anyhow!("Unknown release store: {}", store_name))
+            .with_context(|| anyhow!("Available release stores: {}", config.release_stores().join(", ")));
+    }
+
+    let dry_run = matches.is_present("dry-run");
+    let keep_versions = matches.value_of("keep_versions").map(usize::from_str).transpose()?;
+    let root = config.releases_directory().join(store_name);
+    let conn = db_connection_config.establish_connection()?;
+
+    let pinned_artifacts = schema::artifact_pins::table
+        .select(schema::artifact_pins::artifact_id)
+        .load::<i32>(&conn)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let releases = schema::releases::table
+        .inner_join(schema::release_stores::table)
+        .inner_join(schema::artifacts::table.inner_join(schema::jobs::table.inner_join(schema::packages::table)))
+        .filter(schema::release_stores::store_name.eq(store_name))
+        .select((
+            schema::releases::id,
+            schema::artifacts::id,
+            schema::artifacts::path,
+            schema::packages::name,
+            schema::packages::version,
+        ))
+        .load::<(i32, i32, String, String, String)>(&conn)?
+        .into_iter()
+        .map(|(release_id, artifact_id, path, package, version)| ReleasedArtifact {
+            release_id,
+            path,
+            package,
+            version: PackageVersion::from(version),
+            pinned: pinned_artifacts.contains(&artifact_id),
+        })
+        .collect::<Vec<_>>();
+
+    let mut removals = vec![];
+
+    if let Some(keep) = keep_versions {
+        let selected = superseded(&releases, keep);
+        let removed = selected.iter().map(|i| &releases[*i]).collect::<Vec<_>>();
+        let release_ids = removed.iter().map(|r| r.release_id).collect::<Vec<_>>();
+
+        // A file which is also released as another, newer version stays
+        let kept_pathes = releases
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !selected.contains(i))
+            .map(|(_, r)| r.path.as_str())
+            .collect::<HashSet<_>>();
+        let files = removed
+            .iter()
+            .map(|r| r.path.as_str())
+            .filter(|path| !kept_pathes.contains(path))
+            .unique()
+            .map(|path| root.join(path))
+            .collect::<Vec<_>>();
+
+        let signatures = schema::release_signatures::table
+            .filter(schema::release_signatures::release_id.eq_any(&release_ids))
+            .select(schema::release_signatures::tool)
+            .load::<String>(&conn)?
+            .into_iter()
+            .unique()
+            .map(|tool| SigningTool::from_str(&tool).map_err(Error::from))
+            .collect::<Result<Vec<_>>>()?;
+
+        if !dry_run && !release_ids.is_empty() {
+            conn.transaction::<_, Error, _>(|| {
+                diesel::delete(schema::release_signatures::table.filter(schema::release_signatures::release_id.eq_any(&release_ids)))
+                    .execute(&conn)?;
+                diesel::delete(schema::releases::table.filter(schema::releases::id.eq_any(&release_ids)))
+                    .execute(&conn)?;
+
+                for r in removed.iter() {
+                    models::AuditLogEntry::append(&conn, "gc-release", &root.join(&r.path).display().to_string())?;
+                }
+                Ok(())
+            })
+            .context("Removing superseded releases from the database")?;
+            info!("Removed {} superseded releases from the database", release_ids.len());
+        }
+
+        for path in files {
+            let size = match std::fs::metadata(&path) {
+                Ok(meta) => meta.len(),
+                Err(e) => {
+                    warn!("Released file {} not found: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            if !dry_run {
+                std::fs::remove_file(&path).with_context(|| anyhow!("Removing {}", path.display()))?;
+                for tool in signatures.iter() {
+                    let sig_path = crate::util::signing::signature_path(tool, &path);
+                    if sig_path.exists() {
+                        std::fs::remove_file(&sig_path).with_context(|| anyhow!("Removing {}", sig_path.display()))?;
+                    }
+                }
+            }
+
+            removals.push(Removal {
+                path,
+                reason: Reason::Superseded,
+                size,
+            });
+        }
+    }
+
+    if matches.is_present("unreferenced") {
+        let pinned_pathes = schema::artifacts::table
+            .filter(schema::artifacts::id.eq_any(pinned_artifacts.iter().copied().collect::<Vec<_>>()))
+            .select(schema::artifacts::path)
+            .load::<String>(&conn)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let released_pathes = releases.iter().map(|r| r.path.as_str()).collect::<HashSet<_>>();
+
+        let bar = progressbars.bar();
+        debug!("Loading release directory: {}", root.display());
+        let store = ReleaseStore::load(StoreRoot::new(root.clone())?, &bar);
+        bar.finish_with_message(format!("Loaded release store {}", store_name));
+        let store = store?;
+
+        let unreferenced = store
+            .iter()
+            .map(|ap| ap.display().to_string())
+            .filter(|path| !released_pathes.contains(path.as_str()) && !pinned_pathes.contains(path))
+            .map(|path| root.join(path))
+            .sorted()
+            .collect::<Vec<_>>();
+
+        for path in unreferenced {
+            let size = std::fs::metadata(&path)
+                .with_context(|| anyhow!("Getting metadata of {}", path.display()))?
+                .len();
+
+            if !dry_run {
+                std::fs::remove_file(&path).with_context(|| anyhow!("Removing {}", path.display()))?;
+                models::AuditLogEntry::append(&conn, "gc-release", &path.display().to_string())?;
+            }
+
+            removals.push(Removal {
+                path,
+                reason: Reason::NotReleased,
+                size,
+            });
+        }
+    }
+
+    print_removals(removals, dry_run)
+}
+
+/// The directories in the staging store, sorted by path
+fn staging_dirs(
+    staging_directory: &Path,
+    submit_times: &HashMap<uuid::Uuid, NaiveDateTime>,
+    pinned_artifacts: &HashMap<uuid::Uuid, HashSet<String>>,
+) -> Result<Vec<StagingDir>> {
+    let mut dirs = vec![];
+
+    for entry in std::fs::read_dir(staging_directory)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let submit = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| uuid::Uuid::parse_str(n).ok())
+            .filter(|uuid| submit_times.contains_key(uuid));
+
+        let time = match submit {
+            Some(uuid) => submit_times[&uuid],
+            None => {
+                let modified = std::fs::metadata(&path)
+                    .and_then(|m| m.modified())
+                    .with_context(|| anyhow!("Getting modification time of {}", path.display()))?;
+                chrono::DateTime::<chrono::Utc>::from(modified).naive_utc()
+            }
+        };
+
+        let pinned = submit.map(|uuid| pinned_artifacts.contains_key(&uuid)).unwrap_or(false);
+        dirs.push(StagingDir { path, submit, time, pinned });
+    }
+
+    dirs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(dirs)
+}
+
+/// The pathes of the artifacts of the submit `submit`, as recorded in the database
+fn artifact_paths(conn: &PgConnection, submit: &uuid::Uuid) -> Result<HashSet<String>> {
+    schema::artifacts::table
+        .inner_join(schema::jobs::table.inner_join(schema::submits::table))
+        .filter(schema::submits::uuid.eq(submit))
+        .select(schema::artifacts::path)
+        .load::<String>(conn)
+        .map(|pathes| pathes.into_iter().collect())
+        .with_context(|| anyhow!("Loading artifacts of submit {}", submit))
+        .map_err(Error::from)
+}
+
+/// The size of all files below `path`
+fn dir_size(path: &Path) -> Result<u64> {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .map(|entry| -> Result<u64> {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                Ok(entry.metadata()?.len())
+            } else {
+                Ok(0)
+            }
+        })
+        .sum()
+}
+
+/// Remove the staging directory `path`, which is locked with `lock`
+///
+/// The lock file is removed last, so that no other butido process can use the directory while it
+/// is removed.
+fn remove_staging_dir(path: &Path, lock: StoreLock) -> Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name() == LOCK_FILE_NAME {
+            continue;
+        }
+
+        let entry_path = entry.path();
+        let removed = if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(&entry_path)
+        } else {
+            std::fs::remove_file(&entry_path)
+        };
+        removed.with_context(|| anyhow!("Removing {}", entry_path.display()))?;
+    }
+
+    lock.remove()?;
+    std::fs::remove_dir(path)
+        .with_context(|| anyhow!("Removing {}", path.display()))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str, submit: bool, day: u32) -> StagingDir {
+        StagingDir {
+            path: PathBuf::from(name),
+            submit: if submit { Some(uuid::Uuid::new_v4()) } else { None },
+            time: chrono::NaiveDate::from_ymd(2021, 3, day).and_hms(0, 0, 0),
+            pinned: false,
+        }
+    }
+
+    fn released(release_id: i32, package: &str, version: &str, pinned: bool) -> ReleasedArtifact {
+        ReleasedArtifact {
+            release_id,
+            path: format!("{}-{}.tar.gz", package, version),
+            package: package.to_string(),
+            version: PackageVersion::from(version.to_string()),
+            pinned,
+        }
+    }
+
+    #[test]
+    fn test_superseded() {
+        let releases = vec![
+            released(1, "foo", "1.9", false),
+            released(2, "foo", "1.10", false),
+            released(3, "foo", "1.8", true),
+            released(4, "foo", "1.7", false),
+            released(5, "bar", "2.0", false),
+        ];
+
+        assert_eq!(superseded(&releases, 2), vec![3]);
+        assert_eq!(superseded(&releases, 1), vec![0, 3]);
+        assert!(superseded(&releases, 4).is_empty());
+    }
+
+    #[test]
+    fn test_select_dirs() {
+        let dirs = vec![dir("a", true, 1), dir("b", true, 2), dir("c", true, 3), dir("d", false, 1)];
+        let limit = chrono::NaiveDate::from_ymd(2021, 3, 3).and_hms(0, 0, 0);
+
+        let policy = Policy { older_than: Some(limit), keep_last: None, orphaned: false };
+        assert_eq!(policy.select_dirs(&dirs), vec![(0, Reason::Age), (1, Reason::Age), (3, Reason::Age)]);
+
+        let policy = Policy { older_than: None, keep_last: Some(2), orphaned: false };
+        assert_eq!(policy.select_dirs(&dirs), vec![(0, Reason::NotKept)]);
+
+        let policy = Policy { older_than: Some(limit), keep_last: Some(2), orphaned: true };
+        assert_eq!(policy.select_dirs(&dirs), vec![(0, Reason::Age), (3, Reason::UnknownSubmit)]);
+    }
+
+    #[test]
+    fn test_select_dirs_skips_pinned() {
+        let mut dirs = vec![dir("a", true, 1), dir("b", true, 2), dir("c", true, 3)];
+        dirs[0].pinned = true;
+        let limit = chrono::NaiveDate::from_ymd(2021, 3, 3).and_hms(0, 0, 0);
+
+        let policy = Policy { older_than: Some(limit), keep_last: None, orphaned: true };
+        assert_eq!(policy.select_dirs(&dirs), vec![(1, Reason::Age)]);
+
+        let policy = Policy { older_than: None, keep_last: Some(1), orphaned: false };
+        assert_eq!(policy.select_dirs(&dirs), vec![(1, Reason::NotKept)]);
+    }
+}
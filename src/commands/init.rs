@@ -0,0 +1,376 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'init' subcommand
+
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use colored::Colorize;
+use dialoguer::Confirm;
+use dialoguer::Input;
+use dialoguer::Password;
+use log::debug;
+
+use crate::db::DbConnectionConfig;
+use crate::util::docker::ImageName;
+
+/// Permissions of the directories butido stores artifacts, sources and logs in
+const STORE_DIRECTORY_MODE: u32 = 0o750;
+
+/// The values asked from the user for the starter configuration
+struct Answers {
+    database_host: String,
+    database_port: u16,
+    database_user: String,
+    database_password: String,
+    database_name: String,
+    staging: PathBuf,
+    releases_root: PathBuf,
+    release_store: String,
+    source_cache: PathBuf,
+    log_dir: PathBuf,
+    image: String,
+    endpoint_name: String,
+    endpoint_uri: String,
+    endpoint_type: String,
+    endpoint_maxjobs: usize,
+}
+
+/// Implementation of the "init" subcommand
+pub async fn init(matches: &ArgMatches) -> Result<()> {
+    let repo_dir = matches.value_of("directory").map(PathBuf::from).unwrap(); // safe by clap
+    let config_path = repo_dir.join("config.toml");
+    if config_path.exists() {
+        return Err(anyhow!("Configuration already exists: {}", config_path.display()));
+    }
+
+    let answers = ask()?;
+
+    if !check_database(&answers)? {
+        return Err(anyhow!("Aborted"));
+    }
+
+    for dir in [&answers.staging, &answers.releases_root.join(&answers.release_store), &answers.source_cache, &answers.log_dir].iter() {
+        create_store_directory(dir)?;
+    }
+
+    if !probe_endpoint(&answers).await? {
+        return Err(anyhow!("Aborted"));
+    }
+
+    tokio::fs::create_dir_all(&repo_dir)
+        .await
+        .with_context(|| anyhow!("Creating {}", repo_dir.display()))?;
+    if git2::Repository::discover(&repo_dir).is_err() {
+        git2::Repository::init(&repo_dir)
+            .with_context(|| anyhow!("Initializing git repository in {}", repo_dir.display()))?;
+        writeln!(std::io::stdout(), "Initialized git repository in {}", repo_dir.display())?;
+    }
+
+    write_file(&config_path, &render_config(&answers))?;
+    write_file(&repo_dir.join("pkg.toml"), EXAMPLE_ROOT_PKG_TOML)?;
+    tokio::fs::create_dir_all(repo_dir.join("example")).await?;
+    write_file(&repo_dir.join("example").join("pkg.toml"), EXAMPLE_PKG_TOML)?;
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    indoc::writedoc!(outlock, r#"
+
+        Created a starter configuration in {config}.
+        Next steps:
+
+            butido db setup                               # create the database schema
+            butido build example -I {image}    # build the example package
+
+    "#,
+        config = config_path.display().to_string().green(),
+        image = answers.image,
+    )?;
+
+    Ok(())
+}
+
+fn ask() -> Result<Answers> {
+    fn input<T>(prompt: &str, default: T) -> Result<T>
+    where
+        T: Clone + std::str::FromStr + std::fmt::Display,
+        T::Err: std::fmt::Display + std::fmt::Debug,
+    {
+        Input::new()
+            .with_prompt(prompt)
+            .default(default)
+            .interact_text()
+            .map_err(anyhow::Error::from)
+    }
+
+    Ok(Answers {
+        database_host: input("Database host", String::from("localhost"))?,
+        database_port: input("Database port", 5432)?,
+        database_user: input("Database user", String::from("butido"))?,
+        database_password: Password::new().with_prompt("Database password").interact()?,
+        database_name: input("Database name", String::from("butido"))?,
+        staging: input("Staging directory", String::from("/var/lib/butido/staging"))?.into(),
+        releases_root: input("Releases directory", String::from("/var/lib/butido/releases"))?.into(),
+        release_store: input("Name of the release store", String::from("default"))?,
+        source_cache: input("Source cache directory", String::from("/var/lib/butido/sources"))?.into(),
+        log_dir: input("Log directory", String::from("/var/log/butido"))?.into(),
+        image: input("Image to build in", String::from("debian:bullseye"))?,
+        endpoint_name: input("Name of the docker endpoint", String::from("localhost"))?,
+        endpoint_uri: input("URI of the docker endpoint", String::from("/var/run/docker.sock"))?,
+        endpoint_type: loop {
+            let t: String = input("Type of the docker endpoint (http, socket or podman)", String::from("socket"))?;
+            if t == "http" || t == "socket" || t == "podman" {
+                break t;
+            }
+            writeln!(std::io::stdout(), "{}", "Must be 'http', 'socket' or 'podman'".red())?;
+        },
+        endpoint_maxjobs: input("Maximum number of jobs on the endpoint", 1)?,
+    })
+}
+
+/// Try to connect to the database
+///
+/// Returns whether the initialization should continue.
+fn check_database(answers: &Answers) -> Result<bool> {
+    let dbcc = DbConnectionConfig::new(
+        &answers.database_host,
+        answers.database_port,
+        &answers.database_user,
+        &answers.database_password,
+        &answers.database_name,
+        10,
+    );
+
+    match dbcc.establish_connection() {
+        Ok(_) => {
+            writeln!(std::io::stdout(), "{}", "Database connection successful".green())?;
+            Ok(true)
+        }
+        Err(e) => {
+            writeln!(std::io::stdout(), "{}: {}", "Database connection failed".red(), e)?;
+            Confirm::new()
+                .with_prompt("Continue anyways?")
+                .interact()
+                .map_err(anyhow::Error::from)
+        }
+    }
+}
+
+/// Connect to the docker endpoint and check whether the image is available there
+///
+/// Returns whether the initialization should continue.
+async fn probe_endpoint(answers: &Answers) -> Result<bool> {
+    use std::str::FromStr;
+    use shiplift::ImageListOptions;
+
+    let docker = if answers.endpoint_uri.starts_with("http://") || answers.endpoint_uri.starts_with("https://") {
+        let uri = shiplift::Uri::from_str(&answers.endpoint_uri)
+            .with_context(|| anyhow!("Parsing URI: {}", answers.endpoint_uri))?;
+        shiplift::Docker::host(uri)
+    } else {
+        shiplift::Docker::unix(&answers.endpoint_uri)
+    };
+
+    let configured_image = ImageName::from(answers.image.clone());
+    let probe = async {
+        let version = docker.version().await?;
+        let images = docker.images().list(&ImageListOptions::builder().all().build()).await?;
+        let has_image = images
+            .into_iter()
+            .filter_map(|image| image.repo_tags)
+            .flatten()
+            .any(|tag| ImageName::from_endpoint_tag(tag).is_same_image(&configured_image));
+        Ok::<_, anyhow::Error>((version, has_image))
+    };
+
+    match tokio::time::timeout(std::time::Duration::from_secs(10), probe).await {
+        Ok(Ok((version, has_image))) => {
+            writeln!(std::io::stdout(), "{} (docker {}, API {})", "Endpoint reachable".green(), version.version, version.api_version)?;
+            if !has_image {
+                writeln!(std::io::stdout(), "{}", format!("Image {} is not available on the endpoint, pull it before building", answers.image).yellow())?;
+            }
+            Ok(true)
+        }
+        Ok(Err(e)) => {
+            writeln!(std::io::stdout(), "{}: {}", "Endpoint probe failed".red(), e)?;
+            Confirm::new().with_prompt("Continue anyways?").interact().map_err(anyhow::Error::from)
+        }
+        Err(_) => {
+            writeln!(std::io::stdout(), "{}", "Endpoint probe timed out".red())?;
+            Confirm::new().with_prompt("Continue anyways?").interact().map_err(anyhow::Error::from)
+        }
+    }
+}
+
+fn create_store_directory(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if !path.is_absolute() {
+        return Err(anyhow!("Path is not absolute: {}", path.display()));
+    }
+
+    debug!("Creating {}", path.display());
+    std::fs::create_dir_all(path).with_context(|| anyhow!("Creating {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(STORE_DIRECTORY_MODE))
+        .with_context(|| anyhow!("Setting permissions of {}", path.display()))?;
+    writeln!(std::io::stdout(), "Created {}", path.display())?;
+    Ok(())
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if path.exists() {
+        writeln!(std::io::stdout(), "{}", format!("Not overwriting existing {}", path.display()).yellow())?;
+        return Ok(());
+    }
+
+    std::fs::write(path, content).with_context(|| anyhow!("Writing {}", path.display()))?;
+    writeln!(std::io::stdout(), "Created {}", path.display())?;
+    Ok(())
+}
+
+fn render_config(answers: &Answers) -> String {
+    // The answers are written as TOML strings, so quotes and backslashes in them are escaped
+    let quote = |s: String| toml::Value::String(s).to_string();
+
+    indoc::formatdoc!(
+        r##"
+            # butido configuration, created by 'butido init'
+            #
+            # See the example configuration in the butido repository for all available
+            # settings and their documentation.
+
+            compatibility = "{compatibility}"
+
+            script_shebang = "#!/bin/bash"
+
+            releases_root  = {releases_root}
+            release_stores = [ {release_store} ]
+            staging        = {staging}
+            source_cache   = {source_cache}
+            log_dir        = {log_dir}
+
+            strict_script_interpolation = true
+
+            database_host     = {database_host}
+            database_port     = {database_port}
+            database_user     = {database_user}
+            database_password = {database_password}
+            database_name     = {database_name}
+
+            # The phases of the package scripts, in the order they are executed
+            available_phases = [ "unpack", "build", "pack" ]
+
+            [docker]
+            images = [ {image} ]
+            verify_images_present = true
+
+            [docker.endpoints.{endpoint_name}]
+            uri           = {endpoint_uri}
+            endpoint_type = {endpoint_type}
+            maxjobs       = {endpoint_maxjobs}
+
+            [containers]
+            check_env_names = true
+            allowed_env     = []
+        "##,
+        compatibility = env!("CARGO_PKG_VERSION"),
+        releases_root = quote(answers.releases_root.display().to_string()),
+        release_store = quote(answers.release_store.clone()),
+        staging = quote(answers.staging.display().to_string()),
+        source_cache = quote(answers.source_cache.display().to_string()),
+        log_dir = quote(answers.log_dir.display().to_string()),
+        database_host = quote(answers.database_host.clone()),
+        database_port = answers.database_port,
+        database_user = quote(answers.database_user.clone()),
+        database_password = quote(answers.database_password.clone()),
+        database_name = quote(answers.database_name.clone()),
+        image = quote(answers.image.clone()),
+        endpoint_name = quote(answers.endpoint_name.clone()),
+        endpoint_uri = quote(answers.endpoint_uri.clone()),
+        endpoint_type = quote(answers.endpoint_type.clone()),
+        endpoint_maxjobs = answers.endpoint_maxjobs,
+    )
+}
+
+/// Settings shared by all packages of the repository
+const EXAMPLE_ROOT_PKG_TOML: &str = indoc::indoc!(r#"
+    # Settings in this file are inherited by all packages in subdirectories
+
+    version_is_semver = false
+    patches = []
+
+    # Sources are downloaded with 'butido source download' and verified by their hash
+    [sources]
+
+    [dependencies]
+    build = []
+    runtime = []
+
+    [phases]
+    unpack.script = '''
+        mkdir -p /build
+        cd /build
+    '''
+
+    pack.script = '''
+        mkdir -p /outputs
+        tar czf /outputs/{{this.name}}-{{this.version}}.tar.gz -C /build .
+        {{state "OK"}}
+    '''
+"#);
+
+/// An example package without sources
+const EXAMPLE_PKG_TOML: &str = indoc::indoc!(r#"
+    name = "example"
+    version = "1.0"
+
+    [phases]
+    build.script = '''
+        cd /build
+        echo "Hello from {{this.name}} {{this.version}}" > hello.txt
+    '''
+"#);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_config_escapes_answers() {
+        let answers = Answers {
+            database_host: String::from("localhost"),
+            database_port: 5432,
+            database_user: String::from("butido"),
+            database_password: String::from(r#"se"cr\et"#),
+            database_name: String::from("butido"),
+            staging: PathBuf::from("/var/lib/butido/staging"),
+            releases_root: PathBuf::from("/var/lib/butido/releases"),
+            release_store: String::from("default"),
+            source_cache: PathBuf::from("/var/lib/butido/sources"),
+            log_dir: PathBuf::from("/var/log/butido"),
+            image: String::from("debian:bullseye"),
+            endpoint_name: String::from("build.example.com"),
+            endpoint_uri: String::from("/var/run/docker.sock"),
+            endpoint_type: String::from("socket"),
+            endpoint_maxjobs: 2,
+        };
+
+        let config = render_config(&answers).parse::<toml::Value>().unwrap();
+        assert_eq!(config["database_password"].as_str(), Some(r#"se"cr\et"#));
+        assert_eq!(config["script_shebang"].as_str(), Some("#!/bin/bash"));
+        assert_eq!(config["docker"]["endpoints"]["build.example.com"]["maxjobs"].as_integer(), Some(2));
+    }
+}
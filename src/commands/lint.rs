@@ -11,18 +11,34 @@
 //! Implementation of the 'lint' subcommand
 
 use std::convert::TryFrom;
+use std::io::Write;
 use std::path::Path;
 
 use anyhow::anyhow;
+use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
+use itertools::Itertools;
+use log::warn;
+use regex::Regex;
 
 use crate::config::*;
+use crate::package::condition::ConditionCheckable;
+use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
+use crate::package::ParseDependency;
+use crate::package::ScriptBuilder;
+use crate::package::Shebang;
 use crate::repository::Repository;
+use crate::util::docker::ImageName;
 use crate::util::progress::ProgressBars;
 
+/// The name of the check that runs the linter on the package scripts
+///
+/// This check runs asynchronously and is therefore not one of `checks()`.
+const SCRIPT_CHECK: &str = "script";
+
 /// Implementation of the "lint" subcommand
 pub async fn lint(
     repo_path: &Path,
@@ -31,8 +47,6 @@ pub async fn lint(
     config: &Configuration,
     repo: Repository,
 ) -> Result<()> {
-    let linter = crate::ui::find_linter_command(repo_path, config)?
-        .ok_or_else(|| anyhow!("No linter command found"))?;
     let pname = matches
         .value_of("package_name")
         .map(String::from)
@@ -41,11 +55,12 @@ pub async fn lint(
         .value_of("package_version")
         .map(PackageVersionConstraint::try_from)
         .transpose()?;
+    let skipped = matches
+        .values_of("skip")
+        .map(|vals| vals.collect::<Vec<_>>())
+        .unwrap_or_default();
 
-    let bar = progressbars.bar();
-    bar.set_message("Linting package scripts...");
-
-    let iter = repo
+    let packages = repo
         .packages()
         .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
         .filter(|p| {
@@ -53,7 +68,505 @@ pub async fn lint(
                 .as_ref()
                 .map(|v| v.matches(p.version()))
                 .unwrap_or(true)
+        })
+        .collect::<Vec<_>>();
+
+    let ctx = LintContext {
+        repo: &repo,
+        config,
+        shebang: Shebang::from(config.shebang().clone()),
+    };
+
+    let checks = checks()
+        .into_iter()
+        .filter(|check| !skipped.contains(&check.name()))
+        .collect::<Vec<_>>();
+
+    let mut problems = packages
+        .iter()
+        .map(|pkg| {
+            checks
+                .iter()
+                .flat_map(|check| {
+                    check.check(pkg, &ctx)
+                        .into_iter()
+                        .map(move |problem| (check.name(), problem))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    if !skipped.contains(&SCRIPT_CHECK) {
+        let linter = crate::ui::find_linter_command(repo_path, config)?;
+        if linter.is_none() {
+            warn!("No linter command configured (script_linter), the package scripts are only rendered");
+        }
+
+        let bar = progressbars.bar();
+        bar.set_length(packages.len() as u64);
+        bar.set_message("Linting package scripts...");
+
+        let script_problems = packages
+            .iter()
+            .map(|pkg| {
+                let bar = bar.clone();
+                let linter = linter.as_deref();
+                let shebang = &ctx.shebang;
+                async move {
+                    let problems = check_script(pkg, linter, shebang, config).await;
+                    bar.inc(1);
+                    problems
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let script_problems = futures::future::join_all(script_problems).await;
+        bar.finish_with_message(format!("Finished linting {} package scripts", packages.len()));
+
+        problems
+            .iter_mut()
+            .zip(script_problems)
+            .for_each(|(problems, script_problems)| {
+                problems.extend(script_problems.into_iter().map(|problem| (SCRIPT_CHECK, problem)))
+            });
+    }
+
+    let out = std::io::stdout();
+    let mut outlock = out.lock();
+    let mut n_problems = 0;
+    let mut n_packages = 0;
+
+    for (pkg, problems) in packages.iter().zip(problems.iter()) {
+        if problems.is_empty() {
+            continue;
+        }
+
+        n_packages += 1;
+        n_problems += problems.len();
+        writeln!(outlock, "{} {}", pkg.name(), pkg.version())?;
+        for (check, problem) in problems {
+            // Indent the continuation lines of multi-line problems, e.g. the output of the linter
+            let problem = problem.lines().join("\n      ");
+            writeln!(outlock, "  [{}] {}", check, problem)?;
+        }
+    }
+
+    if n_problems > 0 {
+        return Err(anyhow!(
+            "Found {} problems in {} of {} packages",
+            n_problems,
+            n_packages,
+            packages.len()
+        ));
+    }
+
+    writeln!(outlock, "No problems found in {} packages", packages.len()).map_err(Error::from)
+}
+
+/// The data the checks have access to
+struct LintContext<'a> {
+    repo: &'a Repository,
+    config: &'a Configuration,
+    shebang: Shebang,
+}
+
+/// A check of a package of the repository
+trait Check {
+    /// The name of the check, printed with each problem it finds and used with `--skip`
+    fn name(&self) -> &'static str;
+
+    /// Check `package`, returning a description of each problem found
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String>;
+}
+
+/// All checks, in the order their problems are printed
+///
+/// The names of new checks have to be added to the possible values of `--skip` as well.
+fn checks() -> Vec<Box<dyn Check>> {
+    vec![
+        Box::new(DuplicatesCheck),
+        Box::new(DependenciesCheck),
+        Box::new(ConditionsCheck),
+        Box::new(OutputsCheck),
+        Box::new(SourcesCheck),
+        Box::new(PhasesCheck),
+        Box::new(DependencyMappingCheck),
+    ]
+}
+
+/// Packages that are defined more than once or are shadowed by another package repository
+/// without that package repository allowing it
+struct DuplicatesCheck;
+
+impl Check for DuplicatesCheck {
+    fn name(&self) -> &'static str {
+        "duplicates"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        let duplicates = ctx.repo
+            .duplicates()
+            .iter()
+            .filter(|d| d.name() == package.name() && d.version() == package.version())
+            .map(|d| {
+                format!(
+                    "Defined in {} and {} of package repository '{}', the latter is ignored",
+                    d.path().display(),
+                    d.duplicate_path().display(),
+                    d.repository()
+                )
+            });
+
+        // A package repository has to allow that its packages are shadowed by packages of repositories
+        // with higher precedence, otherwise it is most likely a mistake
+        let shadowed = ctx.repo
+            .shadowed()
+            .iter()
+            .filter(|s| !s.allowed())
+            .filter(|s| s.name() == package.name() && s.version() == package.version())
+            .map(|s| {
+                format!(
+                    "Defined in package repository '{}', shadows the package of package repository '{}' (see allow_shadowing)",
+                    s.shadowed_by(),
+                    s.repository()
+                )
+            });
+
+        duplicates.chain(shadowed).collect()
+    }
+}
+
+/// Dependencies that cannot be parsed or are not defined in the repository
+struct DependenciesCheck;
+
+impl Check for DependenciesCheck {
+    fn name(&self) -> &'static str {
+        "dependencies"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        let build = package.dependencies()
+            .build()
+            .iter()
+            .map(|d| ("build dependency", d.as_ref(), d.parse_as_name_and_version()));
+        let runtime = package.dependencies()
+            .runtime()
+            .iter()
+            .map(|d| ("runtime dependency", d.as_ref(), d.parse_as_name_and_version()));
+
+        build
+            .chain(runtime)
+            .filter_map(|(kind, dependency, parsed)| check_dependency(ctx.repo, kind, dependency, parsed))
+            .collect()
+    }
+}
+
+fn check_dependency(
+    repo: &Repository,
+    kind: &str,
+    dependency: &str,
+    parsed: Result<(PackageName, PackageVersionConstraint)>,
+) -> Option<String> {
+    let (name, constraint) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(format!("Invalid {} '{}': {:#}", kind, dependency, e)),
+    };
+
+    if !repo.find_with_version(&name, &constraint).is_empty() {
+        return None;
+    }
+
+    let available = repo.find_by_name(&name);
+    if available.is_empty() {
+        Some(format!("The {} '{}' is not defined in the repository", kind, dependency))
+    } else {
+        Some(format!(
+            "No version of {} matches the {} '{}' (available: {})",
+            name,
+            kind,
+            dependency,
+            available.iter().map(|p| p.version()).join(", ")
+        ))
+    }
+}
+
+/// Conditional dependencies that are never used, because their `in_image` condition lists none
+/// of the images the package can be built in
+struct ConditionsCheck;
+
+impl Check for ConditionsCheck {
+    fn name(&self) -> &'static str {
+        "conditions"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        let images = buildable_images(package, ctx.config.docker().images());
+        check_conditions(package, &images)
+    }
+}
+
+/// The configured images `package` can be built in, considering its allowed and denied images
+fn buildable_images<'a>(package: &Package, images: &'a [ImageName]) -> Vec<&'a ImageName> {
+    images
+        .iter()
+        .filter(|i| package.allowed_images().as_ref().map(|l| l.contains(*i)).unwrap_or(true))
+        .filter(|i| !package.denied_images().as_ref().map(|l| l.contains(*i)).unwrap_or(false))
+        .collect()
+}
+
+fn check_conditions(package: &Package, images: &[&ImageName]) -> Vec<String> {
+    let build = package.dependencies()
+        .build()
+        .iter()
+        .map(|d| ("build dependency", d.as_ref(), d.condition()));
+    let runtime = package.dependencies()
+        .runtime()
+        .iter()
+        .map(|d| ("runtime dependency", d.as_ref(), d.condition()));
+
+    build
+        .chain(runtime)
+        .filter(|(_, _, condition)| condition.map(|c| !c.can_match_in_images(images)).unwrap_or(false))
+        .map(|(kind, dependency, _)| {
+            format!(
+                "The {} '{}' is never used, its condition lists none of the images the package can be built in",
+                kind,
+                dependency
+            )
+        })
+        .collect()
+}
+
+/// Outputs with invalid patterns and dependencies on outputs that are not declared by the package
+/// depended on
+struct OutputsCheck;
+
+impl Check for OutputsCheck {
+    fn name(&self) -> &'static str {
+        "outputs"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        check_outputs(package, ctx.repo)
+    }
+}
+
+fn check_outputs(package: &Package, repo: &Repository) -> Vec<String> {
+    let patterns = package.outputs()
+        .iter()
+        .filter_map(|(name, pattern)| {
+            Regex::new(pattern)
+                .err()
+                .map(|e| format!("Invalid pattern of output '{}': {}", name, e))
+        });
+
+    let build = package.dependencies()
+        .build()
+        .iter()
+        .map(|d| ("build dependency", d.as_ref(), d.parse_as_name_and_version(), d.parse_output()));
+    let runtime = package.dependencies()
+        .runtime()
+        .iter()
+        .map(|d| ("runtime dependency", d.as_ref(), d.parse_as_name_and_version(), d.parse_output()));
+
+    // Dependencies that cannot be parsed are reported by the "dependencies" check
+    let dependencies = build
+        .chain(runtime)
+        .filter_map(|(kind, dependency, parsed, output)| match (parsed, output) {
+            (Ok((name, constraint)), Ok(Some(output))) => Some((kind, dependency, name, constraint, output)),
+            _ => None,
+        })
+        .filter_map(|(kind, dependency, name, constraint, output)| {
+            let undeclared = repo
+                .find_with_version(&name, &constraint)
+                .into_iter()
+                .filter(|p| !p.outputs().contains_key(&output))
+                .map(|p| p.version())
+                .join(", ");
+
+            if undeclared.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "The {} '{}' is on the output '{}', which {} {} does not declare",
+                    kind,
+                    dependency,
+                    output,
+                    name,
+                    undeclared
+                ))
+            }
         });
 
-    crate::commands::util::lint_packages(iter, &linter, config, bar).await
+    patterns.chain(dependencies).collect()
+}
+
+/// Packages that have no sources without declaring that they do not need any
+struct SourcesCheck;
+
+impl Check for SourcesCheck {
+    fn name(&self) -> &'static str {
+        "sources"
+    }
+
+    fn check(&self, package: &Package, _ctx: &LintContext<'_>) -> Vec<String> {
+        crate::commands::util::sources_declared(package)
+            .err()
+            .map(|e| format!("{:#}", e))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Phases that are missing, empty or neither configured nor an extra phase of the package
+struct PhasesCheck;
+
+impl Check for PhasesCheck {
+    fn name(&self) -> &'static str {
+        "phases"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        crate::commands::util::all_phases_available(package, ctx.config.available_phases())
+            .err()
+            .map(|e| format!("{:#}", e))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// System dependencies used in the script that are not mapped for an image with a dependency
+/// mapping
+struct DependencyMappingCheck;
+
+impl Check for DependencyMappingCheck {
+    fn name(&self) -> &'static str {
+        "dependency-mapping"
+    }
+
+    fn check(&self, package: &Package, ctx: &LintContext<'_>) -> Vec<String> {
+        crate::commands::util::all_dependencies_mapped(package, &ctx.shebang, ctx.config)
+            .err()
+            .map(|e| format!("{:#}", e))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Render the script of `package` and run the linter on it, if one is configured
+async fn check_script(
+    package: &Package,
+    linter: Option<&Path>,
+    shebang: &Shebang,
+    config: &Configuration,
+) -> Vec<String> {
+    let result = match linter {
+        Some(linter) => crate::commands::util::lint_script(package, linter, shebang, config)
+            .await
+            .map(|(status, stdout, stderr)| {
+                if status.success() {
+                    None
+                } else {
+                    Some(format!("Linter failed ({}):\n{}{}", status, stdout, stderr))
+                }
+            }),
+
+        None => ScriptBuilder::new(shebang)
+            .build(package, config.available_phases(), *config.strict_script_interpolation())
+            .map(|_| None),
+    };
+
+    match result {
+        Ok(problem) => problem.into_iter().collect(),
+        Err(e) => vec![format!("{:#}", e)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+
+    fn repo() -> Repository {
+        let mut btree = BTreeMap::new();
+        for vers in &["1", "2"] {
+            let pack = package("foo", vers, "https://rust-lang.org", "123");
+            btree.insert((pname("foo"), pversion(vers)), pack);
+        }
+
+        Repository::from(btree)
+    }
+
+    fn check(dependency: &str) -> Option<String> {
+        let d = Dependency::from(String::from(dependency));
+        check_dependency(&repo(), "runtime dependency", dependency, d.parse_as_name_and_version())
+    }
+
+    #[test]
+    fn test_check_dependency() {
+        assert_eq!(check("foo =1"), None);
+        assert_eq!(check("foo >=1"), None);
+        assert_eq!(
+            check("foo =3"),
+            Some(String::from("No version of foo matches the runtime dependency 'foo =3' (available: 1, 2)"))
+        );
+        assert_eq!(
+            check("bar =1"),
+            Some(String::from("The runtime dependency 'bar =1' is not defined in the repository"))
+        );
+        assert!(check("foo").unwrap().starts_with("Invalid runtime dependency 'foo'"));
+    }
+
+    #[test]
+    fn test_outputs_check() {
+        let mut btree = BTreeMap::new();
+        for (vers, outputs) in &[("1", &["devel"][..]), ("2", &["devel", "debuginfo"][..])] {
+            let mut pack = package("foo", vers, "https://rust-lang.org", "123");
+            pack.set_outputs(outputs.iter().map(|o| (o.to_string(), format!("-{}-", o))).collect());
+            btree.insert((pname("foo"), pversion(vers)), pack);
+        }
+        let repo = Repository::from(btree);
+
+        let mut pack = package("a", "1", "https://rust-lang.org", "123");
+        pack.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("foo:devel >=1")),
+            Dependency::from(String::from("foo:debuginfo >=1")),
+        ]));
+
+        let mut outputs = BTreeMap::new();
+        outputs.insert(String::from("broken"), String::from("("));
+        pack.set_outputs(outputs);
+
+        let problems = check_outputs(&pack, &repo);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("Invalid pattern of output 'broken'"));
+        assert_eq!(
+            problems[1],
+            "The runtime dependency 'foo:debuginfo >=1' is on the output 'debuginfo', which foo 1 does not declare"
+        );
+    }
+
+    #[test]
+    fn test_check_conditions() {
+        let condition: crate::package::condition::Condition = toml::from_str(r#"in_image = "centos:7""#).unwrap();
+        let mut pack = package("a", "1", "https://rust-lang.org", "123");
+        pack.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("foo =1")),
+            Dependency::new_conditional(String::from("bar =1"), condition),
+        ]));
+
+        let centos7 = ImageName::from("centos:7");
+        let debian10 = ImageName::from("debian:10");
+
+        assert!(check_conditions(&pack, &[&centos7, &debian10]).is_empty());
+        assert_eq!(check_conditions(&pack, &[&debian10]).len(), 1);
+    }
 }
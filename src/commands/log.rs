@@ -0,0 +1,138 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'log' subcommand
+
+use std::io::Write;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use diesel::ExpressionMethods;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use log::debug;
+use uuid::Uuid;
+
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+use crate::db::models as dbmodels;
+use crate::schema;
+
+/// How often the database is checked for new log lines of the followed job
+const FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Implementation of the "log" subcommand
+pub async fn log(matches: &ArgMatches, config: &Configuration, db_connection_config: DbConnectionConfig<'_>) -> Result<()> {
+    match matches.subcommand() {
+        Some(("follow", matches)) => follow(matches, config, db_connection_config).await,
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// Print the log of a job and the lines it prints until it is finished
+async fn follow(matches: &ArgMatches, config: &Configuration, db_connection_config: DbConnectionConfig<'_>) -> Result<()> {
+    let job_uuid = matches
+        .value_of("job_uuid")
+        .map(Uuid::parse_str)
+        .transpose()?
+        .unwrap(); // safe by clap
+    let conn = db_connection_config.establish_connection()?;
+    let out = std::io::stdout();
+    let mut last_line = None;
+
+    loop {
+        let lines = dbmodels::LiveLogLine::fetch_after(&conn, &job_uuid, last_line)?;
+
+        if lines.is_empty() && !dbmodels::LiveLogLine::exists_for_job(&conn, &job_uuid)? {
+            // The live log is removed after the job is finished: either its log is recorded with
+            // the job now, or the job failed before that
+            return match finished_log(&conn, &job_uuid)? {
+                Some(log_text) => {
+                    let already_printed = last_line.map(|l| l as usize + 1).unwrap_or(0);
+                    let mut outlock = out.lock();
+                    log_text
+                        .lines()
+                        .skip(already_printed)
+                        .try_for_each(|line| print_line(&mut outlock, line))
+                }
+                None if last_line.is_none() => find_container(&job_uuid, config).await,
+                None => Err(anyhow!("Job {} ended without its log being recorded", job_uuid)),
+            };
+        }
+
+        {
+            let mut outlock = out.lock();
+            for line in lines {
+                print_line(&mut outlock, &line.line)?;
+                last_line = Some(line.line_number);
+            }
+        }
+
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+    }
+}
+
+/// The log of the job, if the job is finished
+fn finished_log(conn: &PgConnection, job_uuid: &Uuid) -> Result<Option<String>> {
+    schema::jobs::table
+        .filter(schema::jobs::dsl::uuid.eq(job_uuid))
+        .select(schema::jobs::dsl::log_text)
+        .first::<String>(conn)
+        .optional()
+        .map_err(Error::from)
+}
+
+fn print_line<W: Write>(out: &mut W, line: &str) -> Result<()> {
+    let item = crate::log::parser().parse(line.as_bytes())?;
+    writeln!(out, "{}", item.display()?).map_err(Error::from)
+}
+
+/// Search the endpoints for the container of a job that does not record its log
+///
+/// This is the case for jobs that are run by a butido without live logs. Their output cannot be
+/// read from another process, but the container can still be attached to.
+async fn find_container(job_uuid: &Uuid, config: &Configuration) -> Result<()> {
+    let endpoint_names = config
+        .docker()
+        .endpoints()
+        .iter()
+        .map(|(ep_name, _)| ep_name.clone())
+        .collect::<Vec<_>>();
+    let job = job_uuid.to_string();
+
+    for endpoint in super::endpoint::connect_to_endpoints(config, &endpoint_names).await? {
+        let containers = match endpoint.butido_containers().await {
+            Ok(containers) => containers,
+            Err(e) => {
+                debug!("Cannot list containers on {}: {:?}", endpoint.name(), e);
+                continue;
+            }
+        };
+
+        if let Some((container_id, _)) = containers.into_iter().find(|(_, container_job)| *container_job == job) {
+            writeln!(
+                std::io::stdout(),
+                "No log is recorded for job {}, it runs in container {} on {}. Attach to it with:\n{}",
+                job,
+                container_id,
+                endpoint.name(),
+                endpoint.debug_command(&container_id)
+            )?;
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!("Job {} is not running (yet) and no log is recorded for it", job))
+}
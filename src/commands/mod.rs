@@ -8,31 +8,65 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+mod audit;
+pub use audit::audit;
+
+mod bisect;
+pub use bisect::bisect;
+
 mod build;
 pub use build::build;
 
+mod canary;
+pub use canary::canary;
+
+mod config;
+pub use self::config::config;
+
 mod db;
 pub use db::db;
 
+mod doctor;
+pub use doctor::doctor;
+
 mod endpoint;
 pub use endpoint::endpoint;
 pub(super) mod endpoint_container;
 
+mod diff_artifacts;
+pub use diff_artifacts::diff_artifacts;
+
 mod env_of;
 pub use env_of::env_of;
 
+mod explain_rebuild;
+pub use explain_rebuild::explain_rebuild;
+
+mod init;
+pub use init::init;
+
 mod find_artifact;
 pub use find_artifact::find_artifact;
 
 mod find_pkg;
 pub use find_pkg::find_pkg;
 
+mod gc;
+pub use gc::gc;
+
 mod dependencies_of;
 pub use dependencies_of::dependencies_of;
 
 mod lint;
 pub use lint::lint;
 
+mod plugin;
+pub use plugin::plugin;
+pub use plugin::plugins;
+
+mod log;
+pub use self::log::log;
+
 mod what_depends;
 pub use what_depends::what_depends;
 
@@ -42,6 +76,15 @@ pub use release::release;
 mod source;
 pub use source::source;
 
+mod store;
+pub use store::store;
+
+mod submit;
+pub use submit::submit;
+
+mod verify;
+pub use verify::verify;
+
 mod versions_of;
 pub use versions_of::versions_of;
 
@@ -51,4 +94,16 @@ pub use tree_of::tree_of;
 mod metrics;
 pub use metrics::metrics;
 
+#[cfg(feature = "api")]
+mod serve_api;
+#[cfg(feature = "api")]
+pub use serve_api::serve_api;
+
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "serve")]
+pub use serve::serve;
+
+mod output;
 mod util;
+pub use util::load_repository_at;
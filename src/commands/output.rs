@@ -0,0 +1,124 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Machine-readable output of the listing subcommands
+
+use std::fmt::Display;
+use std::io::Write;
+
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use log::info;
+
+/// The format the data of a subcommand is printed in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// An ascii table, or plain lines if stdout is not a terminal
+    Table,
+    Csv,
+
+    /// A JSON array with one object per row, keyed by the column names
+    Json,
+}
+
+impl OutputFormat {
+    /// The format selected with `--output`, or with the `--json` and `--csv` shorthands
+    pub fn from_matches(matches: &ArgMatches) -> OutputFormat {
+        match matches.value_of("output") {
+            Some("json") => OutputFormat::Json,
+            Some("csv") => OutputFormat::Csv,
+            Some(_) => OutputFormat::Table,
+            None if matches.is_present("json") => OutputFormat::Json,
+            None if matches.is_present("csv") => OutputFormat::Csv,
+            None => OutputFormat::Table,
+        }
+    }
+}
+
+/// Print the passed data in the format `format`
+///
+/// Table and CSV output are printed with `display_data()`.
+pub fn display<D: Display>(
+    headers: Vec<ascii_table::Column>,
+    data: Vec<Vec<D>>,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Table => crate::commands::util::display_data(headers, data, false),
+        OutputFormat::Csv => crate::commands::util::display_data(headers, data, true),
+        OutputFormat::Json => {
+            let json = json_rows(&headers, data);
+            let out = std::io::stdout();
+            let mut lock = out.lock();
+            serde_json::to_writer_pretty(&mut lock, &json)?;
+            writeln!(lock).map_err(Error::from)
+        }
+    }
+}
+
+/// Report that there is no data to print
+///
+/// With JSON output, an empty array is printed, so that scripts always get a document to parse.
+pub fn display_none(msg: &str, format: OutputFormat) -> Result<()> {
+    info!("{}", msg);
+    if format == OutputFormat::Json {
+        let out = std::io::stdout();
+        let mut lock = out.lock();
+        writeln!(lock, "[]")?;
+    }
+    Ok(())
+}
+
+fn json_rows<D: Display>(headers: &[ascii_table::Column], data: Vec<Vec<D>>) -> serde_json::Value {
+    let keys = headers.iter().map(|h| json_key(&h.header)).collect::<Vec<_>>();
+    let rows = data
+        .into_iter()
+        .map(|row| {
+            let object = keys
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(|d| serde_json::Value::String(d.to_string())))
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::Value::Object(object)
+        })
+        .collect();
+    serde_json::Value::Array(rows)
+}
+
+/// The key of a column in the JSON output, e.g. "package_version" for "Package Version"
+fn json_key(header: &str) -> String {
+    header
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json_key;
+    use super::json_rows;
+
+    #[test]
+    fn test_json_rows() {
+        assert_eq!(json_key("Package Version"), "package_version");
+        assert_eq!(json_key("90th percentile"), "90th_percentile");
+        assert_eq!(json_key("Ran on"), "ran_on");
+
+        let headers = crate::commands::util::mk_header(vec!["UUID", "For Package"]);
+        let json = json_rows(&headers, vec![vec!["1", "a"], vec!["2", "b"]]);
+        assert_eq!(json, serde_json::json!([
+            { "uuid": "1", "for_package": "a" },
+            { "uuid": "2", "for_package": "b" },
+        ]));
+    }
+}
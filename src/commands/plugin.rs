@@ -0,0 +1,163 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of plugins: external "butido-<command>" executables and the 'plugins' subcommand
+
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use log::debug;
+use log::trace;
+use log::warn;
+
+use crate::db::DbConnectionConfig;
+
+/// The prefix of the name of plugin executables
+const PLUGIN_PREFIX: &str = "butido-";
+
+/// The environment variable with the path of the file the context is passed to a plugin in
+///
+/// The context contains the password of the database, so it is not passed in the environment
+/// itself, which other processes of the user and the children of the plugin could read.
+const PLUGIN_CONTEXT_FILE_VAR: &str = "BUTIDO_PLUGIN_CONTEXT_FILE";
+
+/// The version of the format of the plugin context
+///
+/// Only incremented when fields are removed or change their meaning, plugins should ignore
+/// unknown fields.
+const PLUGIN_CONTEXT_VERSION: u64 = 1;
+
+/// Run the plugin for the subcommand `name`
+///
+/// The plugin is searched on PATH as "butido-<name>" and gets the arguments after the subcommand.
+/// The context (configuration, repository path and database connection) is passed as JSON in a
+/// file only readable by the user, whose path is in the BUTIDO_PLUGIN_CONTEXT_FILE environment
+/// variable. The file is removed when the plugin exits.
+pub async fn plugin(
+    name: &str,
+    matches: &ArgMatches,
+    config: ::config::Config,
+    repo_path: &Path,
+    db_connection_config: DbConnectionConfig<'_>,
+) -> Result<()> {
+    let executable = which::which(format!("{}{}", PLUGIN_PREFIX, name))
+        .map_err(|_| anyhow!("Unknown subcommand: {}, no plugin '{}{}' found on PATH", name, PLUGIN_PREFIX, name))?;
+    debug!("Running plugin: {}", executable.display());
+
+    let context = serde_json::json!({
+        "version": PLUGIN_CONTEXT_VERSION,
+        "butido_version": clap::crate_version!(),
+        "repository_path": repo_path,
+        "config": config.try_into::<serde_json::Value>().context("Serializing the configuration")?,
+        "database": {
+            "host": db_connection_config.database_host(),
+            "port": db_connection_config.database_port(),
+            "user": db_connection_config.database_user(),
+            "password": db_connection_config.database_password(),
+            "name": db_connection_config.database_name(),
+            "connection_timeout": db_connection_config.database_connection_timeout(),
+        },
+    });
+
+    // Created with mode 0600
+    let mut context_file = tempfile::Builder::new()
+        .prefix("butido-plugin-context-")
+        .suffix(".json")
+        .tempfile()
+        .context("Creating the context file for the plugin")?;
+    context_file.write_all(serde_json::to_string(&context)?.as_bytes())
+        .and_then(|_| context_file.flush())
+        .context("Writing the context file for the plugin")?;
+
+    let args = matches.values_of_os("").into_iter().flatten().collect::<Vec<&OsStr>>();
+    trace!("Plugin arguments: {:?}", args);
+
+    let status = tokio::process::Command::new(&executable)
+        .args(args)
+        .current_dir(repo_path)
+        .env(PLUGIN_CONTEXT_FILE_VAR, context_file.path())
+        .status()
+        .await
+        .with_context(|| anyhow!("Running plugin: {}", executable.display()))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Plugin {} exited with {}", executable.display(), status))
+    }
+}
+
+/// Implementation of the "plugins" subcommand
+pub fn plugins(matches: &ArgMatches) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let data = find_plugins()?
+        .into_iter()
+        .map(|(name, path)| vec![name, path.display().to_string()])
+        .collect::<Vec<_>>();
+
+    let hdrs = crate::commands::util::mk_header(vec!["Subcommand", "Path"]);
+    crate::commands::util::display_data(hdrs, data, csv)
+}
+
+/// Find all plugins on PATH
+///
+/// Like the shell, the first executable found for a name is used. Plugins named like a builtin
+/// subcommand are never run and therefore skipped.
+fn find_plugins() -> Result<Vec<(String, PathBuf)>> {
+    let cli = crate::cli::cli();
+    let path = match std::env::var_os("PATH") {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut plugins = Vec::<(String, PathBuf)>::new();
+    for dir in std::env::split_paths(&path) {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                trace!("Skipping {}: {}", dir.display(), e);
+                continue
+            }
+        };
+
+        for entry in entries {
+            let entry = entry.with_context(|| anyhow!("Reading directory {}", dir.display()))?;
+            let name = match entry.file_name().to_str().and_then(|n| n.strip_prefix(PLUGIN_PREFIX)) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            };
+
+            let is_executable = std::fs::metadata(entry.path())
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+
+            if !is_executable || plugins.iter().any(|(n, _)| *n == name) {
+                continue
+            }
+
+            if cli.get_subcommands().any(|sub| sub.get_name() == name) {
+                warn!("Plugin {} is shadowed by the builtin subcommand '{}'", entry.path().display(), name);
+                continue
+            }
+
+            plugins.push((name, entry.path()));
+        }
+    }
+
+    plugins.sort();
+    Ok(plugins)
+}
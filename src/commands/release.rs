@@ -12,6 +12,7 @@
 
 use std::io::Write;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -24,6 +25,7 @@ use tokio_stream::StreamExt;
 use resiter::AndThen;
 
 use crate::config::Configuration;
+use crate::config::SigningTool;
 use crate::db::models as dbmodels;
 use crate::db::DbConnectionConfig;
 
@@ -36,6 +38,8 @@ pub async fn release(
     match matches.subcommand() {
         Some(("new", matches))  => new_release(db_connection_config, config, matches).await,
         Some(("rm", matches))   => rm_release(db_connection_config, config, matches).await,
+        Some(("pin", matches))  => pin(db_connection_config, matches),
+        Some(("unpin", matches)) => unpin(db_connection_config, matches),
         Some((other, _matches)) => Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("Missing subcommand")),
     }
@@ -136,6 +140,9 @@ async fn new_release(
 
     let staging_base: &PathBuf = &config.staging_directory().join(submit.uuid.to_string());
 
+    // Make sure no build writes to the staging directory while its artifacts are released
+    let _staging_lock = crate::filestore::StoreLock::acquire(staging_base)?;
+
     let release_store = crate::db::models::ReleaseStore::create(&conn, release_store_name)?;
     let do_update = matches.is_present("package_do_update");
     let interactive = !matches.is_present("noninteractive");
@@ -159,6 +166,12 @@ async fn new_release(
                 );
                 Err(anyhow!("Not a file: {}", art_path.display()))
             } else {
+                // Never release a corrupted artifact
+                if let Some(checksum) = dbmodels::ArtifactChecksum::fetch_for_artifact(&conn, &art)? {
+                    checksum.verify(&art_path)
+                        .with_context(|| anyhow!("Verifying {} before releasing it", art_path.display()))?;
+                }
+
                 if dest_path.exists() && !do_update {
                     return Err(anyhow!("Does already exist: {}", dest_path.display()));
                 } else if dest_path.exists() && do_update {
@@ -173,19 +186,41 @@ async fn new_release(
                     tokio::fs::remove_file(&dest_path)
                         .await
                         .with_context(|| anyhow!("Removing {} before writing new file to this path", dest_path.display()))?;
+
+                    if let Some(signing) = config.release_signing() {
+                        let sig_path = crate::util::signing::signature_path(signing.tool(), &dest_path);
+                        if sig_path.exists() {
+                            debug!("Removing outdated signature {}", sig_path.display());
+                            tokio::fs::remove_file(&sig_path).await?;
+                        }
+                    }
                 }
 
                 // else !dest_path.exists()
                 tokio::fs::copy(&art_path, &dest_path)
                     .await
-                    .with_context(|| anyhow!("Copying {} to {}", art_path.display(), dest_path.display()))
-                    .map_err(Error::from)
-                    .and_then(|_| {
-                        debug!("Updating {:?} to set released = true", art);
-                        let rel = crate::db::models::Release::create(&conn, &art, &now, &release_store)?;
-                        debug!("Release object = {:?}", rel);
-                        Ok(dest_path)
-                    })
+                    .with_context(|| anyhow!("Copying {} to {}", art_path.display(), dest_path.display()))?;
+
+                let signature = match config.release_signing() {
+                    Some(signing) => {
+                        let sig_path = crate::util::signing::sign(signing, &dest_path).await?;
+                        let sig = tokio::fs::read_to_string(&sig_path)
+                            .await
+                            .with_context(|| anyhow!("Reading signature {}", sig_path.display()))?;
+                        Some((signing, sig))
+                    }
+                    None => None,
+                };
+
+                debug!("Updating {:?} to set released = true", art);
+                let rel = crate::db::models::Release::create(&conn, &art, &now, &release_store)?;
+                debug!("Release object = {:?}", rel);
+                if let Some((signing, sig)) = signature {
+                    let rel_sig = dbmodels::ReleaseSignature::create(&conn, &rel, signing, &sig, &now)?;
+                    debug!("Signature object = {:?}", rel_sig);
+                }
+                crate::db::models::AuditLogEntry::append(&conn, "release", &format!("{} -> {}", art.path, dest_path.display()))?;
+                Ok(dest_path)
             }
         })
         .collect::<futures::stream::FuturesUnordered<_>>()
@@ -247,6 +282,17 @@ pub async fn rm_release(
         .select((crate::schema::releases::all_columns, crate::schema::artifacts::all_columns))
         .first::<(crate::db::models::Release, crate::db::models::Artifact)>(&conn)?;
 
+    if let Some(pin) = dbmodels::ArtifactPin::fetch_for_artifact(&conn, &artifact)? {
+        return Err(anyhow!(
+            "Artifact {} is pinned by {} since {}: {}",
+            artifact.path,
+            pin.pinned_by,
+            pin.pinned_at,
+            pin.reason
+        ))
+        .context("Refusing to remove pinned release, unpin it first")
+    }
+
     let artifact_path = config.releases_directory().join(release_store_name).join(&artifact.path);
     if !artifact_path.is_file() {
         return Err(anyhow!("Not a file: {}", artifact_path.display()))
@@ -261,9 +307,79 @@ pub async fn rm_release(
     tokio::fs::remove_file(&artifact_path).await?;
     info!("File removed");
 
+    if let Some(signature) = dbmodels::ReleaseSignature::fetch_for_release(&conn, &release)? {
+        let sig_path = crate::util::signing::signature_path(&SigningTool::from_str(&signature.tool)?, &artifact_path);
+        if sig_path.exists() {
+            tokio::fs::remove_file(&sig_path).await?;
+            info!("Signature file removed");
+        }
+        diesel::delete(&signature).execute(&conn)?;
+    }
+
     diesel::delete(&release).execute(&conn)?;
     info!("Release deleted from database");
+    dbmodels::AuditLogEntry::append(&conn, "release-rm", &artifact_path.display().to_string())?;
 
     Ok(())
 }
 
+/// Find the artifact for the "ID" argument of the pin/unpin commands
+///
+/// The ID is either the ID of a release or (if "--artifact" was passed) the ID of an artifact.
+fn find_artifact_for_pin(conn: &PgConnection, matches: &ArgMatches) -> Result<dbmodels::Artifact> {
+    let id = matches.value_of("id").map(i32::from_str).transpose()?.unwrap(); // safe by clap
+
+    if matches.is_present("is_artifact_id") {
+        crate::schema::artifacts::table
+            .filter(crate::schema::artifacts::id.eq(id))
+            .first::<dbmodels::Artifact>(conn)
+            .optional()?
+            .ok_or_else(|| anyhow!("No artifact with ID {}", id))
+    } else {
+        crate::schema::releases::table
+            .inner_join(crate::schema::artifacts::table)
+            .filter(crate::schema::releases::id.eq(id))
+            .select(crate::schema::artifacts::all_columns)
+            .first::<dbmodels::Artifact>(conn)
+            .optional()?
+            .ok_or_else(|| anyhow!("No release with ID {}", id))
+    }
+}
+
+fn pin(db_connection_config: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let reason = matches.value_of("reason").unwrap(); // safe by clap
+    let conn = db_connection_config.establish_connection()?;
+    let artifact = find_artifact_for_pin(&conn, matches)?;
+
+    if let Some(pin) = dbmodels::ArtifactPin::fetch_for_artifact(&conn, &artifact)? {
+        return Err(anyhow!(
+            "Artifact {} is already pinned by {} since {}: {}",
+            artifact.path,
+            pin.pinned_by,
+            pin.pinned_at,
+            pin.reason
+        ))
+    }
+
+    let user = std::env::var("USER").unwrap_or_else(|_| String::from("unknown"));
+    let now = chrono::offset::Local::now().naive_local();
+    let pin = dbmodels::ArtifactPin::create(&conn, &artifact, reason, &user, &now)?;
+    debug!("Pin object = {:?}", pin);
+    dbmodels::AuditLogEntry::append(&conn, "pin", &format!("{}: {}", artifact.path, reason))?;
+
+    writeln!(std::io::stdout(), "Pinned {}", artifact.path).map_err(Error::from)
+}
+
+fn unpin(db_connection_config: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let conn = db_connection_config.establish_connection()?;
+    let artifact = find_artifact_for_pin(&conn, matches)?;
+
+    let pin = dbmodels::ArtifactPin::fetch_for_artifact(&conn, &artifact)?
+        .ok_or_else(|| anyhow!("Artifact {} is not pinned", artifact.path))?;
+
+    diesel::delete(&pin).execute(&conn)?;
+    info!("Pin removed from database");
+    dbmodels::AuditLogEntry::append(&conn, "unpin", &artifact.path)?;
+
+    writeln!(std::io::stdout(), "Unpinned {}", artifact.path).map_err(Error::from)
+}
@@ -0,0 +1,454 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'serve' subcommand
+//!
+//! Serves the release stores (and optionally the staging directories of the submits) over HTTP, so
+//! that artifacts can be fetched without mounting the stores. The released artifacts are listed
+//! per package and version in `/index.json`, the artifacts of a submit in
+//! `/staging/<uuid>/index.json`.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::ffi::OsStr;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use diesel::ExpressionMethods;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use log::info;
+use log::trace;
+use log::warn;
+use serde::Serialize;
+use tokio::io::AsyncReadExt;
+
+use crate::config::Configuration;
+use crate::db::DbConnection;
+use crate::db::DbConnectionConfig;
+use crate::db::DbPool;
+use crate::schema;
+
+/// The size of the chunks files are sent in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What is served
+struct State {
+    db: DbPool,
+    releases_directory: PathBuf,
+    release_stores: Vec<String>,
+
+    /// The staging directory, if the staging directories of the submits are served
+    staging_directory: Option<PathBuf>,
+
+    /// The extension of the detached signatures of released artifacts, if artifacts are signed
+    signature_extension: Option<&'static str>,
+}
+
+impl State {
+    /// Get a database connection, which blocks until one is free
+    fn conn(&self) -> Result<DbConnection, ServeError> {
+        self.db.get().map_err(|e| ServeError::from(Error::from(e)))
+    }
+}
+
+/// Run `f`, which queries the database or reads from the file system, on a thread where blocking
+/// is allowed
+async fn blocking<T, F>(f: F) -> Result<T, ServeError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ServeError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(ServeError::from(Error::from(e))))
+}
+
+/// Implementation of the "serve" subcommand
+pub async fn serve(db_connection_config: DbConnectionConfig<'_>, config: &Configuration, matches: &ArgMatches) -> Result<()> {
+    let addr = matches
+        .value_of("listen")
+        .unwrap() // safe by clap default value
+        .parse::<SocketAddr>()
+        .context("Parsing address to listen on")?;
+
+    let release_stores = match matches.values_of("release") {
+        Some(names) => names
+            .map(|name| {
+                if config.release_stores().iter().any(|s| s == name) {
+                    Ok(name.to_string())
+                } else {
+                    Err(anyhow!("Unknown release store: {}", name))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => config.release_stores().clone(),
+    };
+
+    let state = Arc::new(State {
+        db: db_connection_config.establish_pool()?,
+        releases_directory: config.releases_directory().clone(),
+        release_stores,
+        staging_directory: if matches.is_present("staging") {
+            Some(config.staging_directory().clone())
+        } else {
+            None
+        },
+        signature_extension: config.release_signing().as_ref().map(|s| s.tool().signature_extension()),
+    });
+
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, request).await) }
+            }))
+        }
+    });
+
+    info!("Serving artifacts on http://{}/index.json", addr);
+    hyper::Server::try_bind(&addr)
+        .with_context(|| anyhow!("Binding to {}", addr))?
+        .serve(make_service)
+        .await
+        .map_err(Error::from)
+}
+
+/// An error response
+struct ServeError(StatusCode, String);
+
+impl From<Error> for ServeError {
+    fn from(e: Error) -> Self {
+        ServeError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+impl From<diesel::result::Error> for ServeError {
+    fn from(e: diesel::result::Error) -> Self {
+        ServeError::from(Error::from(e))
+    }
+}
+
+fn not_found(what: &str) -> ServeError {
+    ServeError(StatusCode::NOT_FOUND, format!("Not found: {}", what))
+}
+
+async fn handle(state: &Arc<State>, request: Request<Body>) -> Response<Body> {
+    trace!("Serve request: {} {}", request.method(), request.uri());
+    let result = if request.method() == Method::GET {
+        // Only the path and query are needed, the request itself is not kept across await points
+        let path = request.uri().path().to_string();
+        let query = request.uri().query().map(String::from);
+        route(state, &path, query.as_deref()).await
+    } else {
+        Err(ServeError(StatusCode::METHOD_NOT_ALLOWED, String::from("Only GET requests are supported")))
+    };
+
+    result.unwrap_or_else(|ServeError(status, msg)| {
+        Response::builder()
+            .status(status)
+            .header(hyper::header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(format!("{}\n", msg)))
+            .unwrap() // safe because status and header are valid
+    })
+}
+
+async fn route(state: &Arc<State>, path: &str, query: Option<&str>) -> Result<Response<Body>, ServeError> {
+    let segments = path
+        .split('/')
+        .skip(1)
+        .map(|s| percent_decode(s).ok_or_else(|| not_found(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match (segments.as_slice(), state.staging_directory.as_ref()) {
+        (["index.json"], _) => {
+            let (package, version) = package_filter(query);
+            let state = state.clone();
+            let index = blocking(move || release_index(&state, package.as_deref(), version.as_deref())).await?;
+            json_response(&index)
+        }
+
+        (["releases", store, artifact @ ..], _) if state.release_stores.iter().any(|s| s == store) => {
+            serve_file(&state.releases_directory.join(store), artifact, path).await
+        }
+
+        (["staging", submit, "index.json"], Some(_)) => {
+            let submit = uuid::Uuid::parse_str(submit).map_err(|_| not_found(path))?;
+            let state = state.clone();
+            json_response(&blocking(move || staging_index(&state, &submit)).await?)
+        }
+
+        (["staging", submit, artifact @ ..], Some(staging)) if uuid::Uuid::parse_str(submit).is_ok() => {
+            serve_file(&staging.join(submit), artifact, path).await
+        }
+
+        _ => Err(not_found(path)),
+    }
+}
+
+/// The artifacts of one version of a package
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    name: String,
+    version: String,
+    artifacts: Vec<IndexArtifact>,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexArtifact {
+    /// The store the artifact is in
+    store: String,
+
+    /// The URL of the artifact, relative to the server
+    url: String,
+    size: u64,
+
+    /// The URL of the detached signature, if there is one
+    signature: Option<String>,
+}
+
+/// The "package" and "version" query parameters
+fn package_filter(query: Option<&str>) -> (Option<String>, Option<String>) {
+    let mut package = None;
+    let mut version = None;
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+        match key.as_ref() {
+            "package" => package = Some(value.into_owned()),
+            "version" => version = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    (package, version)
+}
+
+fn release_index(state: &State, package: Option<&str>, version: Option<&str>) -> Result<Vec<IndexEntry>, ServeError> {
+    let released = {
+        let conn = state.conn()?;
+        let mut query = schema::releases::table
+            .inner_join(schema::release_stores::table)
+            .inner_join(schema::artifacts::table.inner_join(schema::jobs::table.inner_join(schema::packages::table)))
+            .filter(schema::release_stores::store_name.eq_any(&state.release_stores))
+            .into_boxed();
+        if let Some(package) = package {
+            query = query.filter(schema::packages::name.eq(package));
+        }
+        if let Some(version) = version {
+            query = query.filter(schema::packages::version.eq(version));
+        }
+
+        query
+            .select((
+                schema::release_stores::store_name,
+                schema::artifacts::path,
+                schema::packages::name,
+                schema::packages::version,
+            ))
+            .load::<(String, String, String, String)>(&*conn)?
+    };
+
+    let artifacts = released
+        .into_iter()
+        .map(|(store, path, name, version)| {
+            let full = state.releases_directory.join(&store).join(&path);
+            let signature = state
+                .signature_extension
+                .map(|ext| PathBuf::from(format!("{}.{}", path, ext)))
+                .filter(|sig| state.releases_directory.join(&store).join(sig).is_file())
+                .map(|sig| artifact_url("releases", &store, &sig.display().to_string()));
+
+            ((name, version), store, path, full, signature)
+        })
+        .collect::<Vec<_>>();
+
+    Ok(group(artifacts, "releases"))
+}
+
+fn staging_index(state: &State, submit: &uuid::Uuid) -> Result<Vec<IndexEntry>, ServeError> {
+    let staging = state.staging_directory.as_ref().ok_or_else(|| not_found("staging"))?;
+    let artifacts = schema::artifacts::table
+        .inner_join(schema::jobs::table.inner_join(schema::submits::table).inner_join(schema::packages::table))
+        .filter(schema::submits::uuid.eq(submit))
+        .select((schema::artifacts::path, schema::packages::name, schema::packages::version))
+        .load::<(String, String, String)>(&*state.conn()?)?;
+
+    let store = submit.to_string();
+    let artifacts = artifacts
+        .into_iter()
+        .map(|(path, name, version)| {
+            let full = staging.join(&store).join(&path);
+            ((name, version), store.clone(), path, full, None)
+        })
+        .collect::<Vec<_>>();
+
+    Ok(group(artifacts, "staging"))
+}
+
+/// Group artifacts by package name and version, leaving out the ones that do not exist on disk
+/// (anymore)
+#[allow(clippy::type_complexity)]
+fn group(artifacts: Vec<((String, String), String, String, PathBuf, Option<String>)>, kind: &str) -> Vec<IndexEntry> {
+    let mut grouped: BTreeMap<(String, String), Vec<IndexArtifact>> = BTreeMap::new();
+    for (package, store, path, full, signature) in artifacts {
+        let size = match std::fs::metadata(&full) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => {
+                trace!("Not listing {}, it does not exist", full.display());
+                continue;
+            }
+        };
+
+        grouped.entry(package).or_default().push(IndexArtifact {
+            url: artifact_url(kind, &store, &path),
+            store,
+            size,
+            signature,
+        });
+    }
+
+    grouped
+        .into_iter()
+        .map(|((name, version), mut artifacts)| {
+            artifacts.sort_by(|a, b| a.url.cmp(&b.url));
+            IndexEntry { name, version, artifacts }
+        })
+        .collect()
+}
+
+/// The URL of an artifact, relative to the server
+fn artifact_url(kind: &str, store: &str, path: &str) -> String {
+    let mut url = url::Url::parse("http://localhost/").unwrap(); // safe because static
+    url.path_segments_mut()
+        .unwrap() // safe because the URL can be a base
+        .extend([kind, store].iter().copied().chain(path.split('/')));
+    url.path().to_string()
+}
+
+fn json_response<T: Serialize>(t: &T) -> Result<Response<Body>, ServeError> {
+    let body = serde_json::to_string(t).map_err(Error::from)?;
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()) // safe because the header is valid
+}
+
+/// The path of an artifact below the root of a store, from the segments of the request path
+///
+/// Segments which would leave the store or point to files butido uses internally are rejected.
+fn artifact_path(segments: &[&str]) -> Option<PathBuf> {
+    let valid = !segments.is_empty()
+        && segments.iter().all(|s| !s.is_empty() && *s != "." && *s != ".." && !s.contains('/') && !s.contains('\\'))
+        && !crate::filestore::path::is_internal_file(OsStr::new(segments[0]));
+
+    if valid {
+        Some(segments.iter().collect())
+    } else {
+        None
+    }
+}
+
+/// Stream the file at `segments` below `root`
+async fn serve_file(root: &Path, segments: &[&str], request_path: &str) -> Result<Response<Body>, ServeError> {
+    let relative = artifact_path(segments).ok_or_else(|| not_found(request_path))?;
+
+    // The store must not contain symlinks pointing out of it
+    let root = tokio::fs::canonicalize(root).await.map_err(|_| not_found(request_path))?;
+    let path = tokio::fs::canonicalize(root.join(relative)).await.map_err(|_| not_found(request_path))?;
+    if !path.starts_with(&root) {
+        return Err(not_found(request_path));
+    }
+
+    let mut file = tokio::fs::File::open(&path).await.map_err(|_| not_found(request_path))?;
+    let metadata = file.metadata().await.map_err(Error::from)?;
+    if !metadata.is_file() {
+        return Err(not_found(request_path));
+    }
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            match file.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    if sender.send_data(hyper::body::Bytes::copy_from_slice(&buf[..n])).await.is_err() {
+                        trace!("Client went away while sending {}", path.display());
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!("Reading {} failed: {}", path.display(), e);
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/octet-stream")
+        .header(hyper::header::CONTENT_LENGTH, metadata.len())
+        .body(body)
+        .unwrap()) // safe because the headers are valid
+}
+
+/// Decode a percent-encoded segment of a request path
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_path() {
+        assert_eq!(artifact_path(&["foo-1.0.tar.gz"]), Some(PathBuf::from("foo-1.0.tar.gz")));
+        assert_eq!(artifact_path(&["x86_64", "foo.rpm"]), Some(PathBuf::from("x86_64/foo.rpm")));
+        assert_eq!(artifact_path(&[]), None);
+        assert_eq!(artifact_path(&["..", "etc", "passwd"]), None);
+        assert_eq!(artifact_path(&["a", ""]), None);
+        assert_eq!(artifact_path(&["a/../../b"]), None);
+        assert_eq!(artifact_path(&[".butido.lock"]), None);
+    }
+
+    #[test]
+    fn test_percent_decode_and_url() {
+        assert_eq!(percent_decode("foo%20bar%2B1.tar.gz").as_deref(), Some("foo bar+1.tar.gz"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(artifact_url("releases", "stable", "x86_64/foo bar.rpm"), "/releases/stable/x86_64/foo%20bar.rpm");
+    }
+}
@@ -0,0 +1,533 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'serve-api' subcommand
+//!
+//! A read-only HTTP API for the submits, jobs, artifacts and packages in the database.
+//! All responses are JSON. Lists are paginated with the `limit` and `offset` query parameters and
+//! the JSON schemas of the responses are served below `/api/v1/schemas/`.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use clap::ArgMatches;
+use diesel::ExpressionMethods;
+use diesel::JoinOnDsl;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use hyper::Uri;
+use log::info;
+use log::trace;
+use serde::Serialize;
+
+use crate::db::models;
+use crate::db::DbConnectionConfig;
+use crate::db::DbPool;
+use crate::schema;
+
+/// The number of items in a page if the request does not set a limit
+const DEFAULT_LIMIT: i64 = 50;
+
+/// The maximum number of items in a page
+const MAX_LIMIT: i64 = 1000;
+
+/// Implementation of the "serve-api" subcommand
+pub async fn serve_api(db_connection_config: DbConnectionConfig<'_>, matches: &ArgMatches) -> Result<()> {
+    let addr = matches
+        .value_of("listen")
+        .unwrap() // safe by clap default value
+        .parse::<SocketAddr>()
+        .context("Parsing address to listen on")?;
+
+    let pool = db_connection_config.establish_pool()?;
+
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let pool = pool.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |request| {
+                let pool = pool.clone();
+                async move { Ok::<_, Infallible>(handle(pool, request).await) }
+            }))
+        }
+    });
+
+    info!("Serving API on http://{}/api/v1/", addr);
+    hyper::Server::try_bind(&addr)
+        .with_context(|| anyhow!("Binding to {}", addr))?
+        .serve(make_service)
+        .await
+        .map_err(Error::from)
+}
+
+/// An error response
+struct ApiError(StatusCode, String);
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        ApiError::from(Error::from(e))
+    }
+}
+
+fn not_found(what: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("Not found: {}", what))
+}
+
+fn bad_request(msg: String) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, msg)
+}
+
+async fn handle(pool: DbPool, request: Request<Body>) -> Response<Body> {
+    trace!("API request: {} {}", request.method(), request.uri());
+    let result = if request.method() == Method::GET {
+        // Getting a connection from the pool and the queries block, so they are not run on the
+        // threads of the runtime
+        let uri = request.uri().clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = pool.get().map_err(Error::from)?;
+            route(&conn, &uri)
+        })
+        .await
+        .unwrap_or_else(|e| Err(ApiError::from(Error::from(e))))
+    } else {
+        Err(ApiError(StatusCode::METHOD_NOT_ALLOWED, String::from("Only GET requests are supported")))
+    };
+
+    let (status, body) = match result {
+        Ok(value) => (StatusCode::OK, value),
+        Err(ApiError(status, msg)) => (status, serde_json::json!({ "error": msg })),
+    };
+
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap() // safe because status and header are valid
+}
+
+fn route(conn: &PgConnection, uri: &Uri) -> Result<serde_json::Value, ApiError> {
+    let query = Query::parse(uri.query())?;
+    let path = uri
+        .path()
+        .trim_end_matches('/')
+        .strip_prefix("/api/v1")
+        .ok_or_else(|| not_found(uri.path()))?;
+    let segments = path.split('/').skip(1).collect::<Vec<_>>();
+
+    match segments.as_slice() {
+        ["submits"] => to_json(submits(conn, &query)?),
+        ["submits", uuid] => to_json(submit(conn, &parse_uuid(uuid)?)?),
+        ["jobs", uuid] => to_json(job(conn, &parse_uuid(uuid)?)?),
+        ["artifacts"] => to_json(artifacts(conn, &query)?),
+        ["packages"] => to_json(packages(conn, &query)?),
+        ["schemas", name] => json_schema(name).ok_or_else(|| not_found(name)),
+        _ => Err(not_found(uri.path())),
+    }
+}
+
+fn to_json<T: Serialize>(t: T) -> Result<serde_json::Value, ApiError> {
+    serde_json::to_value(t).map_err(|e| ApiError::from(Error::from(e)))
+}
+
+fn parse_uuid(s: &str) -> Result<uuid::Uuid, ApiError> {
+    uuid::Uuid::parse_str(s).map_err(|e| bad_request(format!("Invalid UUID '{}': {}", s, e)))
+}
+
+/// The query parameters of a request
+#[derive(Debug, Default, PartialEq)]
+struct Query {
+    limit: i64,
+    offset: i64,
+    package: Option<String>,
+    version: Option<String>,
+}
+
+impl Query {
+    fn parse(query: Option<&str>) -> Result<Self, ApiError> {
+        let mut q = Query { limit: DEFAULT_LIMIT, ..Query::default() };
+        let parse_number = |key: &str, value: &str| {
+            value.parse::<i64>()
+                .ok()
+                .filter(|n| *n >= 0)
+                .ok_or_else(|| bad_request(format!("Invalid value for '{}': {}", key, value)))
+        };
+
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match key.as_ref() {
+                "limit" => q.limit = std::cmp::min(parse_number("limit", &value)?, MAX_LIMIT),
+                "offset" => q.offset = parse_number("offset", &value)?,
+                "package" => q.package = Some(value.into_owned()),
+                "version" => q.version = Some(value.into_owned()),
+                other => return Err(bad_request(format!("Unknown query parameter: {}", other))),
+            }
+        }
+
+        Ok(q)
+    }
+}
+
+#[derive(Serialize)]
+struct Page<T> {
+    total: i64,
+    limit: i64,
+    offset: i64,
+    items: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct SubmitItem {
+    uuid: uuid::Uuid,
+    submit_time: NaiveDateTime,
+    package_name: String,
+    package_version: String,
+    image: String,
+    commit: String,
+    cancelled: bool,
+}
+
+#[derive(Serialize)]
+struct SubmitDetails {
+    #[serde(flatten)]
+    submit: SubmitItem,
+    jobs: Vec<JobItem>,
+}
+
+#[derive(Serialize)]
+struct JobItem {
+    uuid: uuid::Uuid,
+    package_name: String,
+    package_version: String,
+    endpoint: String,
+    container_hash: String,
+    image_digest: Option<String>,
+    started_at: Option<NaiveDateTime>,
+    finished_at: Option<NaiveDateTime>,
+    maintainers: Vec<String>,
+    success: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct JobDetails {
+    #[serde(flatten)]
+    job: JobItem,
+    submit: uuid::Uuid,
+    script: String,
+    log: String,
+    artifacts: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ArtifactItem {
+    path: String,
+    package_name: String,
+    package_version: String,
+    job: uuid::Uuid,
+    hermetic: bool,
+}
+
+#[derive(Serialize)]
+struct PackageItem {
+    name: String,
+    version: String,
+}
+
+fn submit_item(submit: models::Submit, package: models::Package, image: models::Image, githash: models::GitHash) -> SubmitItem {
+    SubmitItem {
+        uuid: submit.uuid,
+        submit_time: submit.submit_time,
+        package_name: package.name,
+        package_version: package.version,
+        image: image.name,
+        commit: githash.hash,
+        cancelled: submit.cancelled,
+    }
+}
+
+fn job_item(job: &models::Job, package: models::Package, endpoint: models::Endpoint) -> Result<JobItem, ApiError> {
+    let success = crate::log::ParsedLog::from_str(&job.log_text)?.is_successfull().to_bool();
+    Ok(JobItem {
+        uuid: job.uuid,
+        package_name: package.name,
+        package_version: package.version,
+        endpoint: endpoint.name,
+        container_hash: job.container_hash.clone(),
+        image_digest: job.image_digest.clone(),
+        started_at: job.started_at,
+        finished_at: job.finished_at,
+        maintainers: job.maintainers.clone(),
+        success,
+    })
+}
+
+fn submits(conn: &PgConnection, query: &Query) -> Result<Page<SubmitItem>, ApiError> {
+    let total = schema::submits::table.count().get_result::<i64>(conn)?;
+    let items = schema::submits::table
+        .inner_join(schema::packages::table.on(schema::submits::requested_package_id.eq(schema::packages::id)))
+        .inner_join(schema::images::table.on(schema::submits::requested_image_id.eq(schema::images::id)))
+        .inner_join(schema::githashes::table.on(schema::submits::repo_hash_id.eq(schema::githashes::id)))
+        .order_by(schema::submits::id.desc())
+        .limit(query.limit)
+        .offset(query.offset)
+        .select((schema::submits::all_columns, schema::packages::all_columns, schema::images::all_columns, schema::githashes::all_columns))
+        .load::<(models::Submit, models::Package, models::Image, models::GitHash)>(conn)?
+        .into_iter()
+        .map(|(s, p, i, g)| submit_item(s, p, i, g))
+        .collect();
+
+    Ok(Page { total, limit: query.limit, offset: query.offset, items })
+}
+
+fn submit(conn: &PgConnection, uuid: &uuid::Uuid) -> Result<SubmitDetails, ApiError> {
+    let (submit, package, image, githash) = schema::submits::table
+        .inner_join(schema::packages::table.on(schema::submits::requested_package_id.eq(schema::packages::id)))
+        .inner_join(schema::images::table.on(schema::submits::requested_image_id.eq(schema::images::id)))
+        .inner_join(schema::githashes::table.on(schema::submits::repo_hash_id.eq(schema::githashes::id)))
+        .filter(schema::submits::uuid.eq(uuid))
+        .select((schema::submits::all_columns, schema::packages::all_columns, schema::images::all_columns, schema::githashes::all_columns))
+        .first::<(models::Submit, models::Package, models::Image, models::GitHash)>(conn)
+        .optional()?
+        .ok_or_else(|| not_found(&format!("submit {}", uuid)))?;
+
+    let jobs = schema::jobs::table
+        .inner_join(schema::packages::table)
+        .inner_join(schema::endpoints::table)
+        .filter(schema::jobs::submit_id.eq(submit.id))
+        .order_by(schema::jobs::id.asc())
+        .load::<(models::Job, models::Package, models::Endpoint)>(conn)?
+        .into_iter()
+        .map(|(j, p, e)| job_item(&j, p, e))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+
+    Ok(SubmitDetails { submit: submit_item(submit, package, image, githash), jobs })
+}
+
+fn job(conn: &PgConnection, uuid: &uuid::Uuid) -> Result<JobDetails, ApiError> {
+    let (job, package, endpoint, submit) = schema::jobs::table
+        .inner_join(schema::packages::table)
+        .inner_join(schema::endpoints::table)
+        .inner_join(schema::submits::table)
+        .filter(schema::jobs::uuid.eq(uuid))
+        .first::<(models::Job, models::Package, models::Endpoint, models::Submit)>(conn)
+        .optional()?
+        .ok_or_else(|| not_found(&format!("job {}", uuid)))?;
+
+    let artifacts = schema::artifacts::table
+        .filter(schema::artifacts::job_id.eq(job.id))
+        .select(schema::artifacts::path)
+        .load::<String>(conn)?;
+
+    Ok(JobDetails {
+        job: job_item(&job, package, endpoint)?,
+        submit: submit.uuid,
+        script: job.script_text,
+        log: job.log_text,
+        artifacts,
+    })
+}
+
+fn artifacts(conn: &PgConnection, query: &Query) -> Result<Page<ArtifactItem>, ApiError> {
+    let filtered = || {
+        let mut q = schema::artifacts::table
+            .inner_join(schema::jobs::table.inner_join(schema::packages::table))
+            .into_boxed();
+        if let Some(name) = query.package.as_ref() {
+            q = q.filter(schema::packages::name.eq(name));
+        }
+        if let Some(version) = query.version.as_ref() {
+            q = q.filter(schema::packages::version.eq(version));
+        }
+        q
+    };
+
+    let total = filtered().count().get_result::<i64>(conn)?;
+    let items = filtered()
+        .order_by(schema::artifacts::id.desc())
+        .limit(query.limit)
+        .offset(query.offset)
+        .select((schema::artifacts::all_columns, schema::jobs::uuid, schema::packages::all_columns))
+        .load::<(models::Artifact, uuid::Uuid, models::Package)>(conn)?
+        .into_iter()
+        .map(|(artifact, job, package)| ArtifactItem {
+            path: artifact.path,
+            package_name: package.name,
+            package_version: package.version,
+            job,
+            hermetic: artifact.hermetic,
+        })
+        .collect();
+
+    Ok(Page { total, limit: query.limit, offset: query.offset, items })
+}
+
+fn packages(conn: &PgConnection, query: &Query) -> Result<Page<PackageItem>, ApiError> {
+    let filtered = || {
+        let mut q = schema::packages::table.into_boxed();
+        if let Some(name) = query.package.as_ref() {
+            q = q.filter(schema::packages::name.eq(name));
+        }
+        if let Some(version) = query.version.as_ref() {
+            q = q.filter(schema::packages::version.eq(version));
+        }
+        q
+    };
+
+    let total = filtered().count().get_result::<i64>(conn)?;
+    let items = filtered()
+        .order_by((schema::packages::name.asc(), schema::packages::version.asc()))
+        .limit(query.limit)
+        .offset(query.offset)
+        .load::<models::Package>(conn)?
+        .into_iter()
+        .map(|p| PackageItem { name: p.name, version: p.version })
+        .collect();
+
+    Ok(Page { total, limit: query.limit, offset: query.offset, items })
+}
+
+/// The JSON schema of the response with the passed name
+fn json_schema(name: &str) -> Option<serde_json::Value> {
+    use serde_json::json;
+
+    let string = json!({ "type": "string" });
+    let uuid = json!({ "type": "string", "format": "uuid" });
+    let datetime = json!({ "type": "string", "description": "Timestamp, ISO 8601 without time zone" });
+    let nullable = |t: &str| json!({ "type": [t, "null"] });
+
+    let submit = json!({
+        "type": "object",
+        "properties": {
+            "uuid": uuid,
+            "submit_time": datetime,
+            "package_name": string,
+            "package_version": string,
+            "image": string,
+            "commit": string,
+            "cancelled": { "type": "boolean" },
+        },
+    });
+
+    let job = json!({
+        "type": "object",
+        "properties": {
+            "uuid": uuid,
+            "package_name": string,
+            "package_version": string,
+            "endpoint": string,
+            "container_hash": string,
+            "image_digest": nullable("string"),
+            "started_at": nullable("string"),
+            "finished_at": nullable("string"),
+            "maintainers": { "type": "array", "items": string },
+            "success": nullable("boolean"),
+        },
+    });
+
+    let artifact = json!({
+        "type": "object",
+        "properties": {
+            "path": string,
+            "package_name": string,
+            "package_version": string,
+            "job": uuid,
+            "hermetic": { "type": "boolean" },
+        },
+    });
+
+    let package = json!({
+        "type": "object",
+        "properties": {
+            "name": string,
+            "version": string,
+        },
+    });
+
+    let page = |items: &serde_json::Value| json!({
+        "type": "object",
+        "properties": {
+            "total": { "type": "integer" },
+            "limit": { "type": "integer" },
+            "offset": { "type": "integer" },
+            "items": { "type": "array", "items": items },
+        },
+    });
+
+    let with_properties = |base: &serde_json::Value, extra: serde_json::Value| {
+        let mut schema = base.clone();
+        if let (Some(props), Some(extra)) = (schema["properties"].as_object_mut(), extra.as_object()) {
+            props.extend(extra.clone());
+        }
+        schema
+    };
+
+    let mut schema = match name {
+        "submits" => page(&submit),
+        "submit" => with_properties(&submit, json!({ "jobs": { "type": "array", "items": job } })),
+        "job" => with_properties(&job, json!({
+            "submit": uuid,
+            "script": string,
+            "log": string,
+            "artifacts": { "type": "array", "items": string },
+        })),
+        "artifacts" => page(&artifact),
+        "packages" => page(&package),
+        _ => return None,
+    };
+
+    schema["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    Some(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_defaults() {
+        let q = Query::parse(None).ok().unwrap();
+        assert_eq!(q, Query { limit: DEFAULT_LIMIT, ..Query::default() });
+    }
+
+    #[test]
+    fn test_query_parse() {
+        let q = Query::parse(Some("limit=5000&offset=10&package=foo%20bar")).ok().unwrap();
+        assert_eq!(q.limit, MAX_LIMIT);
+        assert_eq!(q.offset, 10);
+        assert_eq!(q.package.as_deref(), Some("foo bar"));
+
+        assert!(Query::parse(Some("limit=-1")).is_err());
+        assert!(Query::parse(Some("unknown=1")).is_err());
+    }
+
+    #[test]
+    fn test_schemas() {
+        for name in ["submits", "submit", "job", "artifacts", "packages"].iter() {
+            assert!(json_schema(name).is_some(), "No schema for {}", name);
+        }
+        assert!(json_schema("foo").is_none());
+    }
+}
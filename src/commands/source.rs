@@ -13,6 +13,7 @@
 use std::io::Write;
 use std::path::PathBuf;
 use std::convert::TryFrom;
+use std::str::FromStr;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -20,11 +21,14 @@ use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
 use colored::Colorize;
-use log::{info, trace};
+use log::{error, info, trace};
 use tokio::io::AsyncWriteExt;
 use tokio_stream::StreamExt;
+use url::Url;
 
 use crate::config::*;
+use crate::package::HashType;
+use crate::package::HashValue;
 use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersionConstraint;
@@ -45,6 +49,7 @@ pub async fn source(
         Some(("url", matches)) => url(matches, repo).await,
         Some(("download", matches)) => download(matches, config, repo, progressbars).await,
         Some(("of", matches)) => of(matches, config, repo).await,
+        Some(("add", matches)) => add(matches, progressbars).await,
         Some((other, _)) => return Err(anyhow!("Unknown subcommand: {}", other)),
         None => Err(anyhow!("No subcommand")),
     }
@@ -89,6 +94,9 @@ pub async fn verify(
     verify_impl(packages, &sc, &progressbars).await
 }
 
+/// Number of source files that are hashed at the same time
+const VERIFY_JOBS: usize = 8;
+
 pub(in crate::commands) async fn verify_impl<'a, I>(
     packages: I,
     sc: &SourceCache,
@@ -98,6 +106,9 @@ where
     I: Iterator<Item = &'a Package> + 'a,
 {
     let sources = packages
+        .inspect(|p| if p.sources().declared_none() {
+            trace!("{} {} has no sources, nothing to verify", p.name(), p.version());
+        })
         .map(|p| sc.sources_for(p).into_iter())
         .flatten()
         .collect::<Vec<_>>();
@@ -106,9 +117,10 @@ where
     bar.set_message("Verifying sources");
     bar.set_length(sources.len() as u64);
 
+    // Each verification is spawned so the hashing runs in parallel, at most VERIFY_JOBS at a time
     let results = sources.into_iter()
         .map(|src| (bar.clone(), src))
-        .map(|(bar, source)| async move {
+        .map(|(bar, source)| tokio::spawn(async move {
             trace!("Verifying: {}", source.path().display());
             if source.path().exists() {
                 trace!("Exists: {}", source.path().display());
@@ -124,8 +136,10 @@ where
                 bar.inc(1);
                 Err(anyhow!("Source missing: {}", source.path().display()))
             }
-        })
-        .collect::<futures::stream::FuturesUnordered<_>>()
+        }))
+        .map(|handle| async move { handle.await.map_err(Error::from).and_then(|r| r) });
+
+    let results = futures::StreamExt::buffer_unordered(futures::stream::iter(results), VERIFY_JOBS)
         .collect::<Vec<Result<_>>>()
         .await;
 
@@ -223,7 +237,7 @@ pub async fn download(
     repo: Repository,
     progressbars: ProgressBars,
 ) -> Result<()> {
-    async fn perform_download(source: &SourceEntry, bar: &indicatif::ProgressBar) -> Result<()> {
+    async fn perform_download(source: &SourceEntry, fetchers: &SourceFetchers, bar: &indicatif::ProgressBar) -> Result<()> {
         trace!("Creating: {:?}", source);
         let file = source.create().await.with_context(|| {
             anyhow!(
@@ -233,41 +247,10 @@ pub async fn download(
         })?;
 
         let mut file = tokio::io::BufWriter::new(file);
-        let client = reqwest::Client::builder()
-            .redirect(reqwest::redirect::Policy::limited(10))
-            .build()
-            .context("Building HTTP client failed")?;
-
-        let request = client.get(source.url().as_ref())
-            .build()
-            .with_context(|| anyhow!("Building request for {} failed", source.url().as_ref()))?;
-
-        let response = match client.execute(request).await {
-            Ok(resp) => resp,
-            Err(e) => {
-                bar.finish_with_message(format!("Failed: {}", source.url()));
-                return Err(e).with_context(|| anyhow!("Downloading '{}'", source.url()))
-            }
-        };
-
-        if let Some(len) = response.content_length() {
-            bar.set_length(len);
-        }
-
-        let mut stream = reqwest::get(source.url().as_ref()).await?.bytes_stream();
-        let mut bytes_written = 0;
-        while let Some(bytes) = stream.next().await {
-            let bytes = bytes?;
-            file.write_all(bytes.as_ref()).await?;
-            bytes_written += bytes.len();
-
-            bar.inc(bytes.len() as u64);
-            if let Some(len) = response.content_length() {
-                bar.set_message(format!("Downloading {} ({}/{} bytes)", source.url(), bytes_written, len));
-            } else {
-                bar.set_message(format!("Downloading {} ({} bytes)", source.url(), bytes_written));
-            }
-        }
+        fetchers
+            .fetch(source.url(), &mut file, bar)
+            .await
+            .with_context(|| anyhow!("Downloading '{}'", source.url()))?;
 
         file.flush()
             .await
@@ -276,6 +259,16 @@ pub async fn download(
     }
 
     let force = matches.is_present("force");
+    let jobs = matches
+        .value_of("jobs")
+        .map(usize::from_str)
+        .transpose()?
+        .unwrap(); // safe by clap default value
+    if jobs == 0 {
+        return Err(anyhow!("--jobs must be greater than zero"));
+    }
+
+    let fetchers = SourceFetchers::new()?;
     let cache = PathBuf::from(config.source_cache_root());
     let sc = SourceCache::new(cache);
     let pname = matches
@@ -315,8 +308,11 @@ pub async fn download(
         .map(|p| {
             sc.sources_for(p).into_iter().map(|source| {
                 let bar = multi.add(progressbars.spinner());
-                bar.set_message(format!("Downloading {}", source.url()));
-                async move {
+                bar.set_message(format!("Waiting to download {}", source.url()));
+                let fetchers = &fetchers;
+                let url = source.url().to_string();
+                let download = async move {
+                    bar.set_message(format!("Downloading {}", source.url()));
                     let source_path_exists = source.path().exists();
                     if !source_path_exists && source.download_manually() {
                         return Err(anyhow!(
@@ -338,24 +334,49 @@ pub async fn download(
                         }
 
 
-                        if let Err(e) = perform_download(&source, &bar).await {
+                        if let Err(e) = perform_download(&source, fetchers, &bar).await {
                             bar.finish_with_message(format!("Failed: {}", source.url()));
                             Err(e)
+                        } else if let Err(e) = source.verify_hash().await {
+                            bar.finish_with_message(format!("Hash mismatch: {}", source.url()));
+                            // Do not leave a file in the cache that would fail verification later
+                            source.remove_file().await?;
+                            Err(e).context(anyhow!("Downloaded source does not match its hash: {}", source.path().display()))
                         } else {
                             bar.finish_with_message(format!("Finished: {}", source.url()));
                             Ok(())
                         }
                     }
-                }
+                };
+
+                async move { (url, download.await) }
             })
         })
         .flatten()
-        .collect::<futures::stream::FuturesUnordered<_>>()
-        .collect::<Vec<Result<()>>>();
+        .collect::<Vec<_>>();
+
+    // At most `jobs` downloads run at the same time
+    let r = futures::StreamExt::buffer_unordered(futures::stream::iter(r), jobs)
+        .collect::<Vec<(String, Result<()>)>>();
 
     let multibar_block = tokio::task::spawn_blocking(move || multi.join());
     let (r, _) = tokio::join!(r, multibar_block);
-    r.into_iter().collect()
+
+    // All downloads are tried, the failed ones are reported together at the end
+    let total = r.len();
+    let failed = r
+        .into_iter()
+        .filter_map(|(url, result)| result.err().map(|e| (url, e)))
+        .collect::<Vec<_>>();
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        for (url, e) in failed.iter() {
+            error!("Downloading {} failed: {:?}", url, e);
+        }
+        Err(anyhow!("{} of {} downloads failed", failed.len(), total))
+    }
 }
 
 async fn of(
@@ -365,6 +386,11 @@ async fn of(
 ) -> Result<()> {
     let cache = PathBuf::from(config.source_cache_root());
     let sc = SourceCache::new(cache);
+
+    if matches.is_present("stdin") {
+        return of_batch(matches, &sc, &repo);
+    }
+
     let pname = matches
         .value_of("package_name")
         .map(String::from)
@@ -402,3 +428,192 @@ async fn of(
         })
         .map(|_| ())
 }
+
+/// Implementation of "source of --stdin"
+fn of_batch(matches: &ArgMatches, sc: &SourceCache, repo: &Repository) -> Result<()> {
+    let queries = crate::commands::util::read_batch_queries()?;
+
+    let results = queries
+        .iter()
+        .flat_map(|query| repo.packages().filter(move |p| query.matches(p)))
+        .map(|p| {
+            let pathes = sc.sources_for(p)
+                .into_iter()
+                .map(|source| source.path().display().to_string())
+                .collect::<Vec<String>>();
+
+            (p, pathes)
+        })
+        .collect::<Vec<_>>();
+
+    let json = results
+        .iter()
+        .map(|(package, pathes)| serde_json::json!({
+            "package": package.name(),
+            "version": package.version(),
+            "sources": pathes,
+        }))
+        .collect::<Vec<_>>();
+
+    let rows = results
+        .iter()
+        .flat_map(|(package, pathes)| {
+            pathes.iter().map(move |path| vec![package.name().to_string(), package.version().to_string(), path.clone()])
+        })
+        .collect();
+
+    crate::commands::util::display_batch_results(json.into(), &["package", "version", "source"], rows, matches.is_present("csv"))
+}
+
+/// Implementation of the "source add" subcommand
+async fn add(matches: &ArgMatches, progressbars: ProgressBars) -> Result<()> {
+    let url = matches.value_of("url").map(Url::parse).transpose()?.unwrap(); // safe by clap
+    let name = matches.value_of("name").unwrap(); // safe by clap default value
+    let hashtype = matches
+        .value_of("hash_type")
+        .map(HashType::from_str)
+        .transpose()?
+        .unwrap(); // safe by clap default value
+
+    // Downloaded into a temporary file, as the package (and therefore the cache entry) is not known
+    let path = std::env::temp_dir().join(format!("butido-source-{}", uuid::Uuid::new_v4()));
+    let bar = progressbars.spinner();
+    bar.set_message(format!("Downloading {}", url));
+    let hash = {
+        let hash = download_and_hash(&url, &path, &hashtype, &bar).await;
+        if path.exists() {
+            tokio::fs::remove_file(&path)
+                .await
+                .with_context(|| anyhow!("Removing {}", path.display()))?;
+        }
+        hash
+    };
+
+    let hash = match hash {
+        Ok(hash) => {
+            bar.finish_with_message(format!("Finished: {}", url));
+            hash
+        }
+        Err(e) => {
+            bar.finish_with_message(format!("Failed: {}", url));
+            return Err(e)
+        }
+    };
+
+    let stanza = source_stanza(name, &url, &hashtype, &hash);
+    match matches.value_of("write") {
+        None => write!(std::io::stdout(), "{}", stanza).map_err(Error::from),
+        Some(file) => {
+            let content = std::fs::read_to_string(file).with_context(|| anyhow!("Reading {}", file))?;
+            let content = append_source(&content, name, &stanza).with_context(|| anyhow!("Adding source to {}", file))?;
+            std::fs::write(file, content).with_context(|| anyhow!("Writing {}", file))?;
+            info!("Added source '{}' to {}", name, file);
+            Ok(())
+        }
+    }
+}
+
+/// Download `url` to `path` and hash it
+async fn download_and_hash(url: &Url, path: &std::path::Path, hashtype: &HashType, bar: &indicatif::ProgressBar) -> Result<HashValue> {
+    let file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| anyhow!("Creating {}", path.display()))?;
+    let mut file = tokio::io::BufWriter::new(file);
+    SourceFetchers::new()?
+        .fetch(url, &mut file, bar)
+        .await
+        .with_context(|| anyhow!("Downloading '{}'", url))?;
+    file.flush().await?;
+
+    hashtype.hash_file(path)
+}
+
+/// The definition of a source in a package TOML file
+fn source_stanza(name: &str, url: &Url, hashtype: &HashType, hash: &HashValue) -> String {
+    let quote = |s: String| toml::Value::String(s).to_string();
+    let key = if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        name.to_string()
+    } else {
+        quote(name.to_string())
+    };
+
+    indoc::formatdoc!(r#"
+        [sources.{name}]
+        url = {url}
+        hash.type = {hashtype}
+        hash.hash = {hash}
+        download_manually = false
+        "#,
+        name = key,
+        url = quote(url.to_string()),
+        hashtype = quote(hashtype.to_string()),
+        hash = quote(hash.to_string()),
+    )
+}
+
+/// Append the source `stanza` named `name` to the package TOML `content`
+///
+/// Fails if the package already has a source with that name or the result is no valid TOML.
+fn append_source(content: &str, name: &str, stanza: &str) -> Result<String> {
+    let has_source = |content: &str| -> Result<bool> {
+        let value = content.parse::<toml::Value>()?;
+        Ok(value.get("sources").and_then(|sources| sources.get(name)).is_some())
+    };
+
+    if has_source(content)? {
+        return Err(anyhow!("There is already a source named '{}'", name));
+    }
+
+    let separator = if content.is_empty() || content.ends_with("\n\n") {
+        ""
+    } else if content.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    let content = format!("{}{}{}", content, separator, stanza);
+
+    if !has_source(&content)? {
+        return Err(anyhow!("Source '{}' cannot be added, 'sources' is not a table that can be extended", name));
+    }
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::package::Source;
+
+    fn stanza() -> String {
+        let url = Url::parse("https://example.com/foo-1.0.tar.gz").unwrap();
+        let hash = HashValue::from(String::from("abc"));
+        source_stanza("src", &url, &HashType::Sha256, &hash)
+    }
+
+    #[test]
+    fn test_source_stanza() {
+        #[derive(serde::Deserialize)]
+        struct Sources {
+            sources: HashMap<String, Source>,
+        }
+
+        let sources: Sources = toml::from_str(&stanza()).unwrap();
+        let source = sources.sources.get("src").unwrap();
+        assert_eq!(source.url().as_str(), "https://example.com/foo-1.0.tar.gz");
+        assert_eq!(source.hash().value().to_string(), "abc");
+        assert_eq!(source.hash().hashtype().to_string(), "sha256");
+    }
+
+    #[test]
+    fn test_append_source() {
+        let content = append_source("version = \"1.0\"", "src", &stanza()).unwrap();
+        assert!(content.starts_with("version = \"1.0\"\n\n[sources.src]\n"));
+
+        assert!(append_source(&content, "src", &stanza()).is_err());
+        assert!(append_source("sources = { other = { url = \"x\" } }", "src", &stanza()).is_err());
+    }
+}
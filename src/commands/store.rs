@@ -0,0 +1,488 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'store' subcommand
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use diesel::ExpressionMethods;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use itertools::Itertools;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::config::Configuration;
+use crate::db::DbConnectionConfig;
+use crate::db::models;
+use crate::filestore::artifact_type::handler_for;
+use crate::filestore::artifact_type::ArtifactMetadata;
+use crate::filestore::index;
+use crate::filestore::index::IndexEntry;
+use crate::filestore::path::StoreRoot;
+use crate::filestore::remote::S3Store;
+use crate::filestore::ArtifactStorage;
+use crate::filestore::ReleaseStore;
+use crate::filestore::StagingStore;
+use crate::package::HashType;
+use crate::schema;
+use crate::util::progress::ProgressBars;
+
+/// Implementation of the "store" subcommand
+pub async fn store(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    match matches.subcommand() {
+        Some(("ls", matches)) => ls(db_connection_config, config, matches, progressbars),
+        Some(("index", matches)) => index(db_connection_config, config, matches, progressbars),
+        Some(("push", matches)) => sync(db_connection_config, config, matches, progressbars, true).await,
+        Some(("pull", matches)) => sync(db_connection_config, config, matches, progressbars, false).await,
+        Some((other, _)) => Err(anyhow!("Unknown subcommand: {}", other)),
+        None => Err(anyhow!("No subcommand")),
+    }
+}
+
+/// An artifact found in a store
+struct StoreEntry {
+    store: String,
+    path: String,
+
+    /// Name and version of the package, if the artifact is known to the database
+    package: Option<(String, String)>,
+    size: u64,
+}
+
+/// Implementation of the "store ls" subcommand
+fn ls(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let conn = db_connection_config.establish_connection()?;
+
+    let mut entries = if matches.is_present("staging") {
+        staging_entries(&conn, config, &progressbars)?
+    } else {
+        let store_names = match matches.value_of("release") {
+            Some(name) => {
+                check_release_store(config, name)?;
+                vec![name.to_string()]
+            }
+            None => config.release_stores().clone(),
+        };
+
+        store_names
+            .iter()
+            .map(|name| release_entries(&conn, config, name, &progressbars))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+
+    if let Some(pkg) = matches.value_of("package") {
+        entries.retain(|e| e.package.as_ref().map(|(name, _)| name == pkg).unwrap_or(false));
+    }
+
+    let sort = matches.value_of("sort").unwrap(); // safe by clap
+    let fmt_size = |size: u64| if csv {
+        size.to_string()
+    } else {
+        bytesize::ByteSize::b(size).to_string()
+    };
+
+    let (header, data) = if matches.is_present("du") {
+        let mut usage: BTreeMap<(String, String), (usize, u64)> = BTreeMap::new();
+        for entry in entries {
+            let key = entry.package.unwrap_or_else(|| (String::from("unknown"), String::from("unknown")));
+            let u = usage.entry(key).or_insert((0, 0));
+            u.0 += 1;
+            u.1 += entry.size;
+        }
+
+        let data = usage
+            .into_iter()
+            .sorted_by(|(a_pkg, (_, a_size)), (b_pkg, (_, b_size))| {
+                if sort == "size" {
+                    b_size.cmp(a_size).then_with(|| a_pkg.cmp(b_pkg))
+                } else {
+                    a_pkg.cmp(b_pkg)
+                }
+            })
+            .map(|((name, version), (count, size))| vec![name, version, count.to_string(), fmt_size(size)])
+            .collect::<Vec<_>>();
+
+        (crate::commands::util::mk_header(vec!["Package", "Version", "Artifacts", "Size"]), data)
+    } else {
+        let data = entries
+            .into_iter()
+            .sorted_by(|a, b| match sort {
+                "size" => b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)),
+                "package" => a.package.cmp(&b.package).then_with(|| a.path.cmp(&b.path)),
+                _ => a.store.cmp(&b.store).then_with(|| a.path.cmp(&b.path)),
+            })
+            .map(|e| {
+                let (name, version) = e.package.unwrap_or_else(|| (String::from("unknown"), String::from("unknown")));
+                vec![e.store, e.path, name, version, fmt_size(e.size)]
+            })
+            .collect::<Vec<_>>();
+
+        (crate::commands::util::mk_header(vec!["Store", "Path", "Package", "Version", "Size"]), data)
+    };
+
+    if data.is_empty() {
+        info!("No artifacts found");
+        Ok(())
+    } else {
+        crate::commands::util::display_data(header, data, csv)
+    }
+}
+
+/// Implementation of the "store index" subcommand
+fn index(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+) -> Result<()> {
+    let name = matches.value_of("release").unwrap(); // safe by clap
+    let format = matches.value_of("format").unwrap(); // safe by clap
+    check_release_store(config, name)?;
+    let conn = db_connection_config.establish_connection()?;
+
+    // The metadata recorded for the released artifacts, by path
+    let mut recorded: HashMap<String, (String, ArtifactMetadata)> = HashMap::new();
+    schema::releases::table
+        .inner_join(schema::release_stores::table)
+        .inner_join(schema::artifacts::table.inner_join(schema::artifact_metadata::table))
+        .filter(schema::release_stores::store_name.eq(name))
+        .select((
+            schema::artifacts::path,
+            schema::artifact_metadata::kind,
+            schema::artifact_metadata::key,
+            schema::artifact_metadata::value,
+        ))
+        .load::<(String, String, String, String)>(&conn)?
+        .into_iter()
+        .for_each(|(path, kind, key, value)| {
+            recorded
+                .entry(path)
+                .or_insert_with(|| (kind, ArtifactMetadata::new()))
+                .1
+                .insert(key, value);
+        });
+
+    let bar = progressbars.bar();
+    let root = config.releases_directory().join(name);
+    debug!("Loading release directory: {}", root.display());
+    let store = ReleaseStore::load(StoreRoot::new(root.clone())?, &bar);
+    bar.finish_with_message(format!("Loaded release store {}", name));
+    let store = store?;
+
+    let entries = store.iter()
+        .sorted()
+        .map(|ap| {
+            let path = ap.display().to_string();
+            let full = root.join(ap);
+            let size = std::fs::metadata(&full)
+                .with_context(|| anyhow!("Getting metadata of {}", full.display()))?
+                .len();
+
+            // Artifacts released before their metadata was recorded are inspected now
+            let (kind, metadata) = match recorded.remove(&path) {
+                Some((kind, metadata)) => (Some(kind), metadata),
+                None => match handler_for(&full) {
+                    Some(handler) => match handler.metadata(&full) {
+                        Ok(metadata) => (Some(handler.kind().to_string()), metadata),
+                        Err(e) => {
+                            warn!("Failed to extract metadata of {}, not indexing it as {}: {:?}", full.display(), handler.kind(), e);
+                            (None, ArtifactMetadata::new())
+                        }
+                    },
+                    None => (None, ArtifactMetadata::new()),
+                },
+            };
+
+            Ok(IndexEntry {
+                path,
+                size,
+                sha256: HashType::Sha256.hash_file(&full)?.to_string(),
+                kind,
+                metadata,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let written = match format {
+        "yum" => {
+            let dir = root.join(index::YUM_INDEX_DIR);
+            std::fs::create_dir_all(&dir).with_context(|| anyhow!("Creating {}", dir.display()))?;
+
+            let primary = index::yum_primary(&entries);
+            let primary_sha256 = {
+                use sha2::Digest;
+                format!("{:x}", sha2::Sha256::digest(primary.as_bytes()))
+            };
+            let primary_href = format!("{}/primary.xml", index::YUM_INDEX_DIR);
+            let repomd = index::yum_repomd(&primary_href, &primary_sha256, primary.len(), chrono::Utc::now().timestamp());
+
+            write_index(&root.join(&primary_href), primary.as_bytes())?;
+            write_index(&dir.join("repomd.xml"), repomd.as_bytes())?;
+            dir
+        }
+
+        "apt" => {
+            let path = root.join(index::APT_INDEX);
+            write_index(&path, index::apt_packages(&entries).as_bytes())?;
+            path
+        }
+
+        _ => {
+            let path = root.join(index::FLAT_JSON_INDEX);
+            write_index(&path, &index::flat_json(&entries)?)?;
+            path
+        }
+    };
+
+    info!("Indexed {} artifacts of release store {} in {}", entries.len(), name, written.display());
+    Ok(())
+}
+
+/// Implementation of the "store push" and "store pull" subcommands
+async fn sync(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+    push: bool,
+) -> Result<()> {
+    let remote_config = config
+        .remote_store()
+        .as_ref()
+        .ok_or_else(|| anyhow!("No remote store configured, see 'remote_store' in the configuration"))?;
+    let dry_run = matches.is_present("dry-run");
+
+    let bar = progressbars.bar();
+    let (mut local, prefix, root): (Box<dyn ArtifactStorage>, String, PathBuf) = match matches.value_of("release") {
+        Some(name) => {
+            check_release_store(config, name)?;
+            let root = config.releases_directory().join(name);
+            debug!("Loading release directory: {}", root.display());
+            let store = ReleaseStore::load(StoreRoot::new(root.clone())?, &bar)?;
+            (Box::new(store), format!("releases/{}", name), root)
+        }
+
+        None => {
+            let submit = matches
+                .value_of("staging")
+                .map(uuid::Uuid::parse_str)
+                .transpose()?
+                .unwrap(); // safe by clap
+            let root = config.staging_directory().join(submit.to_string());
+            if !push {
+                std::fs::create_dir_all(&root).with_context(|| anyhow!("Creating {}", root.display()))?;
+            }
+
+            debug!("Loading staging directory: {}", root.display());
+            // Pushing only reads from the staging store, which might be in use by a build
+            let store = if push {
+                StagingStore::load_read_only(StoreRoot::new(root.clone())?, &bar)?
+            } else {
+                StagingStore::load(StoreRoot::new(root.clone())?, &bar)?
+            };
+            (Box::new(store), format!("staging/{}", submit), root)
+        }
+    };
+    bar.finish_with_message(format!("Loaded {}", local.describe()));
+
+    let mut remote = S3Store::new(remote_config, &prefix)?;
+    if push {
+        copy_missing(local.as_ref(), &mut remote, None, dry_run).await
+    } else {
+        let conn = if dry_run { None } else { Some(db_connection_config.establish_connection()?) };
+        copy_missing(&remote, local.as_mut(), conn.as_ref().map(|c| (c, root.as_path())), dry_run).await
+    }
+}
+
+/// Copy the artifacts which are missing in `to` from `from`
+///
+/// If `to` is a local store, `audit` is the database connection and the root of the store, so that
+/// each written artifact is recorded in the audit log.
+async fn copy_missing(
+    from: &dyn ArtifactStorage,
+    to: &mut dyn ArtifactStorage,
+    audit: Option<(&PgConnection, &Path)>,
+    dry_run: bool,
+) -> Result<()> {
+    let present = to.artifacts().await?.into_iter().collect::<HashSet<_>>();
+    let missing = from
+        .artifacts()
+        .await?
+        .into_iter()
+        .filter(|ap| !present.contains(ap))
+        .sorted()
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        info!("No artifacts missing in {}", to.describe());
+        return Ok(());
+    }
+
+    for ap in missing.iter() {
+        if dry_run {
+            writeln!(std::io::stdout(), "{}", ap.display())?;
+        } else {
+            let content = from.read(ap).await.with_context(|| anyhow!("Reading {} from {}", ap.display(), from.describe()))?;
+            to.write(ap, content).await.with_context(|| anyhow!("Writing {} to {}", ap.display(), to.describe()))?;
+            if let Some((conn, root)) = audit {
+                models::AuditLogEntry::append(conn, "store-pull", &root.join(ap).display().to_string())?;
+            }
+            info!("Copied {}", ap.display());
+        }
+    }
+
+    if dry_run {
+        info!("Would copy {} artifacts from {} to {}", missing.len(), from.describe(), to.describe());
+    } else {
+        info!("Copied {} artifacts from {} to {}", missing.len(), from.describe(), to.describe());
+    }
+    Ok(())
+}
+
+/// Fail if there is no release store `name`
+fn check_release_store(config: &Configuration, name: &str) -> Result<()> {
+    if config.release_stores().iter().any(|s| s == name) {
+        Ok(())
+    } else {
+        Err(anyhow!("Unknown release store: {}", name))
+            .with_context(|| anyhow!("Available release stores: {}", config.release_stores().join(", ")))
+    }
+}
+
+/// Write an index file, replacing the previous one atomically so that clients never see a partial
+/// index
+fn write_index(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .with_context(|| anyhow!("Writing {}", path.display()))
+        .map_err(Error::from)
+}
+
+/// Load the entries of the release store `name`
+fn release_entries(conn: &PgConnection, config: &Configuration, name: &str, progressbars: &ProgressBars) -> Result<Vec<StoreEntry>> {
+    let index = schema::releases::table
+        .inner_join(schema::release_stores::table)
+        .inner_join(schema::artifacts::table.inner_join(schema::jobs::table.inner_join(schema::packages::table)))
+        .filter(schema::release_stores::store_name.eq(name))
+        .select((schema::artifacts::path, schema::packages::name, schema::packages::version))
+        .load::<(String, String, String)>(conn)?
+        .into_iter()
+        .map(|(path, pkg_name, pkg_version)| (path, (pkg_name, pkg_version)))
+        .collect::<HashMap<_, _>>();
+
+    let bar = progressbars.bar();
+    let p = config.releases_directory().join(name);
+    debug!("Loading release directory: {}", p.display());
+    let store = ReleaseStore::load(StoreRoot::new(p.clone())?, &bar);
+    bar.finish_with_message(format!("Loaded release store {}", name));
+    let store = store?;
+
+    store.iter()
+        .map(|ap| {
+            let path = ap.display().to_string();
+            let full = p.join(ap);
+            let size = std::fs::metadata(&full)
+                .with_context(|| anyhow!("Getting metadata of {}", full.display()))?
+                .len();
+
+            Ok(StoreEntry {
+                store: name.to_string(),
+                package: index.get(&path).cloned(),
+                path,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Load the entries of the staging directories of all submits
+fn staging_entries(conn: &PgConnection, config: &Configuration, progressbars: &ProgressBars) -> Result<Vec<StoreEntry>> {
+    let mut entries = vec![];
+
+    for dir in std::fs::read_dir(config.staging_directory())? {
+        let dir = dir?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let dir_name = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Not a valid directory name: {}", dir.display()))?;
+
+        let index = match uuid::Uuid::parse_str(&dir_name) {
+            Ok(submit_uuid) => schema::artifacts::table
+                .inner_join(schema::jobs::table.inner_join(schema::submits::table).inner_join(schema::packages::table))
+                .filter(schema::submits::uuid.eq(submit_uuid))
+                .select((schema::artifacts::path, schema::packages::name, schema::packages::version))
+                .load::<(String, String, String)>(conn)?
+                .into_iter()
+                .map(|(path, pkg_name, pkg_version)| (path, (pkg_name, pkg_version)))
+                .collect::<HashMap<_, _>>(),
+
+            Err(_) => {
+                debug!("Not a submit directory, cannot look up artifacts in database: {}", dir.display());
+                HashMap::new()
+            }
+        };
+
+        let bar = progressbars.bar();
+        debug!("Loading staging directory: {}", dir.display());
+        let store = StagingStore::load_read_only(StoreRoot::new(dir.clone())?, &bar);
+        bar.finish_with_message(format!("Loaded staging directory {}", dir_name));
+        let store = store?;
+
+        for ap in store.iter() {
+            let path = ap.display().to_string();
+            let full = dir.join(ap);
+            let size = std::fs::metadata(&full)
+                .with_context(|| anyhow!("Getting metadata of {}", full.display()))?
+                .len();
+
+            entries.push(StoreEntry {
+                store: format!("staging/{}", dir_name),
+                package: index.get(&path).cloned(),
+                path,
+                size,
+            });
+        }
+    }
+
+    Ok(entries)
+}
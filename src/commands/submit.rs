@@ -0,0 +1,318 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'submit' subcommand
+//!
+//! Submit templates are pre-defined submits that live in the package repository, in
+//! `.butido/submits/<name>.toml`. Running a template is equivalent to calling the 'build'
+//! subcommand with the arguments from the template.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use clap::ArgMatches;
+use getset::Getters;
+use itertools::Itertools;
+use log::{debug, error, trace};
+use serde::Deserialize;
+
+use crate::config::Configuration;
+use crate::db::DbPool;
+use crate::endpoint::SharedEndpoints;
+use crate::log::LogFormat;
+use crate::repository::Repository;
+use crate::util::progress::ProgressBars;
+
+/// The directory (relative to the repository root) where the submit templates are stored
+const SUBMIT_TEMPLATE_DIR: &str = ".butido/submits";
+
+/// A pre-defined submit
+///
+/// Unknown fields are rejected, so that a misspelled or unsupported setting does not silently
+/// change what is built.
+#[derive(Debug, Getters, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubmitTemplate {
+    /// The name of the package to build
+    #[getset(get = "pub")]
+    package_name: String,
+
+    /// The version of the package to build (string match)
+    #[getset(get = "pub")]
+    package_version: Option<String>,
+
+    /// The image to build on
+    #[getset(get = "pub")]
+    image: String,
+
+    /// Environment variables to pass to all build jobs
+    #[serde(default)]
+    #[getset(get = "pub")]
+    env: BTreeMap<String, String>,
+
+    /// Additional arguments for the 'build' subcommand, e.g. `["--write-log"]`
+    #[serde(default)]
+    #[getset(get = "pub")]
+    build_args: Vec<String>,
+}
+
+impl SubmitTemplate {
+    fn load(path: &Path) -> Result<Self> {
+        let mut config = ::config::Config::default();
+        config
+            .merge(::config::File::from(path).required(true))
+            .with_context(|| anyhow!("Loading submit template {}", path.display()))?;
+
+        config
+            .try_into::<SubmitTemplate>()
+            .with_context(|| anyhow!("Parsing submit template {}", path.display()))
+            .map_err(Error::from)
+    }
+
+    /// Get the commandline for the 'build' subcommand that is equivalent to this template
+    fn build_commandline(&self) -> Vec<String> {
+        let mut args = vec![String::from("build"), self.package_name.clone()];
+
+        if let Some(vers) = self.package_version.as_ref() {
+            args.push(vers.clone());
+        }
+
+        args.push(String::from("--image"));
+        args.push(self.image.clone());
+
+        for (k, v) in self.env.iter() {
+            args.push(String::from("--env"));
+            args.push(format!("{}={}", k, v));
+        }
+
+        args.extend(self.build_args.iter().cloned());
+        args
+    }
+}
+
+/// Find all submit templates in the repository, sorted by name
+fn find_templates(repo_path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let dir = repo_path.join(SUBMIT_TEMPLATE_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new())
+    }
+
+    let mut templates = std::fs::read_dir(&dir)
+        .with_context(|| anyhow!("Reading {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()).map_err(Error::from))
+        .filter(|path| match path {
+            Ok(p) => p.is_file() && p.extension().map(|ext| ext == "toml").unwrap_or(false),
+            Err(_) => true,
+        })
+        .map(|path| {
+            let path = path?;
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(String::from)
+                .ok_or_else(|| anyhow!("Not a valid template name: {}", path.display()))?;
+            Ok((name, path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    templates.sort();
+    Ok(templates)
+}
+
+/// Implementation of the "submit" subcommand
+///
+/// If several templates are passed, their submits run at the same time and share the endpoints.
+/// Only one line per submit is shown then, instead of the progress bars of its jobs.
+pub async fn submit(
+    repo_path: &Path,
+    matches: &ArgMatches,
+    progressbars: ProgressBars,
+    database_pool: DbPool,
+    config: &Configuration,
+    repo: Repository,
+    log_format: LogFormat,
+) -> Result<()> {
+    let templates = find_templates(repo_path)?;
+
+    if matches.is_present("list") {
+        let out = std::io::stdout();
+        let mut outlock = out.lock();
+        return templates
+            .iter()
+            .try_for_each(|(name, _)| writeln!(outlock, "{}", name).map_err(Error::from))
+    }
+
+    let to_run = matches
+        .values_of("template_name")
+        .unwrap() // safe by clap
+        .unique()
+        .map(|name| {
+            let (_, path) = templates
+                .iter()
+                .find(|(tname, _)| tname == name)
+                .ok_or_else(|| anyhow!("No submit template named '{}' in {}", name, repo_path.join(SUBMIT_TEMPLATE_DIR).display()))?;
+
+            let template = SubmitTemplate::load(path)?;
+            debug!("Submit template {} = {:?}", name, template);
+            Ok((name, template))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if let [(name, template)] = to_run.as_slice() {
+        return run_template(name, template, repo_path, progressbars, database_pool, config, repo, log_format, None).await
+    }
+
+    let shared_endpoints = SharedEndpoints::setup(super::build::endpoint_configurations(config)).await?;
+    let multibar = Arc::new({
+        let mp = indicatif::MultiProgress::new();
+        if progressbars.hide() {
+            mp.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+        }
+        mp
+    });
+
+    let submits = to_run
+        .iter()
+        .map(|(name, template)| {
+            let bar = multibar.add(progressbars.spinner());
+            bar.set_message(format!("[{}] Running", name));
+            let progressbars = progressbars.hidden();
+            let database_pool = database_pool.clone();
+            let repo = repo.clone();
+            let shared_endpoints = shared_endpoints.clone();
+
+            async move {
+                let result = run_template(name, template, repo_path, progressbars, database_pool, config, repo, log_format, Some(shared_endpoints)).await;
+                match result.as_ref() {
+                    Ok(()) => bar.finish_with_message(format!("[{}] Finished", name)),
+                    Err(e) => bar.finish_with_message(format!("[{}] Failed: {}", name, e)),
+                }
+                result
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let multibar_block = tokio::task::spawn_blocking(move || multibar.join());
+    let (results, _) = tokio::join!(futures::future::join_all(submits), multibar_block);
+
+    let n_failed = results
+        .into_iter()
+        .filter_map(Result::err)
+        .inspect(|e| error!("{:?}", e))
+        .count();
+
+    if n_failed == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("{} of {} submits failed", n_failed, to_run.len()))
+    }
+}
+
+/// Run the submit template `template` with the name `name`
+#[allow(clippy::too_many_arguments)]
+async fn run_template(
+    name: &str,
+    template: &SubmitTemplate,
+    repo_path: &Path,
+    progressbars: ProgressBars,
+    database_pool: DbPool,
+    config: &Configuration,
+    repo: Repository,
+    log_format: LogFormat,
+    shared_endpoints: Option<SharedEndpoints>,
+) -> Result<()> {
+    let build_commandline = template.build_commandline();
+    trace!("Commandline for template {} = {:?}", name, build_commandline);
+
+    // Let clap parse the commandline for the build subcommand, so that the template is validated
+    // exactly like a build that was started by hand
+    let cli_matches = crate::cli::cli()
+        .try_get_matches_from(std::iter::once(String::from("butido")).chain(build_commandline))
+        .map_err(|e| anyhow!("{}", e))
+        .with_context(|| anyhow!("Submit template {} is not a valid build commandline", name))?;
+
+    let build_matches = cli_matches
+        .subcommand_matches("build")
+        .ok_or_else(|| anyhow!("BUG: build subcommand not found after parsing template"))?;
+
+    crate::commands::build(
+        repo_path,
+        build_matches,
+        progressbars,
+        database_pool,
+        config,
+        repo,
+        repo_path,
+        log_format,
+        shared_endpoints,
+    )
+    .await
+    .with_context(|| anyhow!("Running submit template {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_commandline() {
+        let template: SubmitTemplate = toml::from_str(r#"
+            package_name = "foo"
+            package_version = "1.0"
+            image = "debian:bullseye"
+            build_args = ["--write-log"]
+
+            [env]
+            A = "1"
+            B = "2"
+        "#).unwrap();
+
+        assert_eq!(template.build_commandline(), vec![
+            "build", "foo", "1.0",
+            "--image", "debian:bullseye",
+            "--env", "A=1",
+            "--env", "B=2",
+            "--write-log",
+        ]);
+    }
+
+    #[test]
+    fn test_build_commandline_without_version() {
+        let template: SubmitTemplate = toml::from_str(r#"
+            package_name = "foo"
+            image = "debian:bullseye"
+        "#).unwrap();
+
+        assert_eq!(template.build_commandline(), vec![
+            "build", "foo", "--image", "debian:bullseye",
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_field_is_rejected() {
+        let path = std::env::temp_dir().join(format!("butido-test-submit-template-{}.toml", std::process::id()));
+        std::fs::write(&path, r#"
+            package_name = "foo"
+            image = "debian:bullseye"
+            profile = "release"
+        "#).unwrap();
+
+        let err = SubmitTemplate::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(format!("{:?}", err).contains("unknown field `profile`"), "{:?}", err);
+    }
+}
@@ -11,11 +11,17 @@
 //! Implementation of the 'tree-of' subcommand
 
 use std::convert::TryFrom;
+use std::io::Write;
+use std::path::Path;
 
+use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
-use resiter::AndThen;
+use daggy::NodeIndex;
+use diesel::PgConnection;
+use itertools::Itertools;
 
 use crate::package::Dag;
 use crate::package::PackageName;
@@ -26,9 +32,13 @@ use crate::util::EnvironmentVariableName;
 use crate::util::docker::ImageName;
 
 /// Implementation of the "tree_of" subcommand
+///
+/// If a database connection is available, resolved trees are cached in the database.
 pub async fn tree_of(
     matches: &ArgMatches,
     repo: Repository,
+    repo_path: &Path,
+    database_connection: Option<PgConnection>,
 ) -> Result<()> {
     let pname = matches
         .value_of("package_name")
@@ -53,8 +63,17 @@ pub async fn tree_of(
     let condition_data = ConditionData {
         image_name: image_name.as_ref(),
         env: &additional_env,
+        image_info: None,
     };
 
+    let git_repo = database_connection
+        .as_ref()
+        .map(|_| {
+            git2::Repository::open(repo_path)
+                .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))
+        })
+        .transpose()?;
+
     repo.packages()
         .filter(|p| pname.as_ref().map(|n| p.name() == n).unwrap_or(true))
         .filter(|p| {
@@ -63,12 +82,158 @@ pub async fn tree_of(
                 .map(|v| v.matches(p.version()))
                 .unwrap_or(true)
         })
-        .map(|package| Dag::for_root_package(package.clone(), &repo, None, &condition_data))
-        .and_then_ok(|tree| {
+        .map(|package| match (database_connection.as_ref(), git_repo.as_ref()) {
+            (Some(conn), Some(git_repo)) => {
+                crate::db::resolve_dag(conn, false, git_repo, &repo, package.clone(), None, &condition_data)
+            }
+            _ => Dag::for_root_package(package.clone(), &repo, None, &condition_data),
+        })
+        .collect::<Result<Vec<Dag>>>()
+        .and_then(|trees| {
             let stdout = std::io::stdout();
             let mut outlock = stdout.lock();
 
-            ptree::write_tree(&tree.display(), &mut outlock).map_err(Error::from)
+            match matches.value_of("format") {
+                Some("dot") => trees.iter().try_for_each(|tree| write_dot(tree, &mut outlock)),
+                Some("mermaid") => trees.iter().try_for_each(|tree| write_mermaid(tree, &mut outlock)),
+                Some("json") => {
+                    let json = trees.iter()
+                        .map(|tree| tree_json(tree, image_name.as_ref()))
+                        .collect::<Vec<_>>();
+                    serde_json::to_writer_pretty(&mut outlock, &json)?;
+                    writeln!(outlock).map_err(Error::from)
+                }
+                _ => trees.iter().try_for_each(|tree| {
+                    ptree::write_tree(&tree.display(), &mut outlock).map_err(Error::from)
+                }),
+            }
         })
-        .collect::<Result<()>>()
+}
+
+/// The label of a package in the rendered graphs
+fn node_label(dag: &Dag, idx: NodeIndex) -> String {
+    dag.dag()
+        .node_weight(idx)
+        .map(|p| format!("{} {}", p.name(), p.version()))
+        .unwrap_or_default()
+}
+
+/// Write the dag in the DOT language of Graphviz
+fn write_dot<W: Write>(dag: &Dag, out: &mut W) -> Result<()> {
+    let graph = dag.dag().graph();
+    let quote = |idx| node_label(dag, idx).replace('\\', "\\\\").replace('"', "\\\"");
+
+    writeln!(out, "digraph \"{}\" {{", quote(*dag.root_idx()))?;
+    for idx in graph.node_indices() {
+        writeln!(out, "    \"{}\";", quote(idx))?;
+    }
+    for (from, to) in graph.edge_indices().filter_map(|e| graph.edge_endpoints(e)) {
+        writeln!(out, "    \"{}\" -> \"{}\";", quote(from), quote(to))?;
+    }
+    writeln!(out, "}}").map_err(Error::from)
+}
+
+/// Write the dag as mermaid flowchart
+fn write_mermaid<W: Write>(dag: &Dag, out: &mut W) -> Result<()> {
+    let graph = dag.dag().graph();
+
+    writeln!(out, "graph TD")?;
+    for idx in graph.node_indices() {
+        writeln!(out, "    n{}[\"{}\"]", idx.index(), node_label(dag, idx).replace('"', "#quot;"))?;
+    }
+    for (from, to) in graph.edge_indices().filter_map(|e| graph.edge_endpoints(e)) {
+        writeln!(out, "    n{} --> n{}", from.index(), to.index())?;
+    }
+    Ok(())
+}
+
+/// The dag as JSON object
+///
+/// Contains the root package, the image the tree was computed for and all packages with the
+/// dependencies that were selected after checking their conditions.
+fn tree_json(dag: &Dag, image: Option<&ImageName>) -> serde_json::Value {
+    let graph = dag.dag().graph();
+    let package_json = |idx: NodeIndex| {
+        graph.node_weight(idx)
+            .map(|p| serde_json::json!({ "name": p.name(), "version": p.version() }))
+            .unwrap_or(serde_json::Value::Null)
+    };
+
+    let packages = graph.node_indices()
+        .map(|idx| {
+            let dependencies = graph.neighbors(idx)
+                .sorted()
+                .map(package_json)
+                .collect::<Vec<_>>();
+
+            let mut json = package_json(idx);
+            if let Some(obj) = json.as_object_mut() {
+                obj.insert(String::from("dependencies"), serde_json::Value::Array(dependencies));
+            }
+            json
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "root": package_json(*dag.root_idx()),
+        "image": image,
+        "packages": packages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+
+    fn dag() -> Dag {
+        let mut btree = BTreeMap::new();
+
+        let mut p1 = package("a", "1", "https://rust-lang.org", "123");
+        p1.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(String::from("b =2"))));
+        btree.insert((pname("a"), pversion("1")), p1.clone());
+        btree.insert((pname("b"), pversion("2")), package("b", "2", "https://rust-lang.org", "124"));
+
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+            image_info: None,
+        };
+
+        Dag::for_root_package(p1, &repo, None, &condition_data).unwrap()
+    }
+
+    #[test]
+    fn test_write_dot() {
+        let mut out = Vec::new();
+        write_dot(&dag(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("digraph \"a 1\" {\n"));
+        assert!(out.contains("    \"a 1\" -> \"b 2\";\n"));
+        assert!(out.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_tree_json() {
+        let image = ImageName::from(String::from("debian:bullseye"));
+        let json = tree_json(&dag(), Some(&image));
+
+        assert_eq!(json["root"], serde_json::json!({ "name": "a", "version": "1" }));
+        assert_eq!(json["image"], "debian:bullseye");
+
+        let packages = json["packages"].as_array().unwrap();
+        let a = packages.iter().find(|p| p["name"] == "a").unwrap();
+        assert_eq!(a["dependencies"], serde_json::json!([{ "name": "b", "version": "2" }]));
+        let b = packages.iter().find(|p| p["name"] == "b").unwrap();
+        assert_eq!(b["dependencies"], serde_json::json!([]));
+    }
 }
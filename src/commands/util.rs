@@ -10,6 +10,7 @@
 
 //! Utility module for subcommand implementation helpers
 
+use std::convert::TryFrom;
 use std::io::Write;
 use std::fmt::Display;
 use std::path::Path;
@@ -26,9 +27,14 @@ use tokio_stream::StreamExt;
 
 use crate::config::*;
 use crate::package::Package;
+use crate::package::PackageName;
+use crate::package::PackageVersionConstraint;
+use crate::package::Phase;
 use crate::package::PhaseName;
 use crate::package::ScriptBuilder;
 use crate::package::Shebang;
+use crate::repository::Repository;
+use crate::util::progress::ProgressBars;
 
 /// Helper for getting a boolean value by name form the argument object
 pub fn getbool(m: &ArgMatches, name: &str, cmp: &str) -> bool {
@@ -36,6 +42,22 @@ pub fn getbool(m: &ArgMatches, name: &str, cmp: &str) -> bool {
     m.values_of(name).unwrap().any(|v| v == cmp)
 }
 
+/// Run the linter on the script of `pkg`
+///
+/// Returns the exit status, stdout and stderr of the linter.
+pub async fn lint_script(
+    pkg: &Package,
+    linter: &Path,
+    shebang: &Shebang,
+    config: &Configuration,
+) -> Result<(std::process::ExitStatus, String, String)> {
+    let cmd = tokio::process::Command::new(linter);
+    let script = ScriptBuilder::new(shebang)
+        .build(pkg, config.available_phases(), *config.strict_script_interpolation())?;
+
+    script.lint(cmd).await
+}
+
 /// Helper function to lint all packages in an interator
 pub async fn lint_packages<'a, I>(
     iter: I,
@@ -59,12 +81,10 @@ where
             async move {
                 trace!("Linting script of {} {} with '{}'", pkg.name(), pkg.version(), linter.display());
                 let _ = all_phases_available(pkg, config.available_phases())?;
+                let _ = sources_declared(pkg)?;
+                let _ = all_dependencies_mapped(pkg, &shebang, config)?;
 
-                let cmd = tokio::process::Command::new(linter);
-                let script = ScriptBuilder::new(&shebang)
-                    .build(pkg, config.available_phases(), *config.strict_script_interpolation())?;
-
-                let (status, stdout, stderr) = script.lint(cmd).await?;
+                let (status, stdout, stderr) = lint_script(pkg, linter, &shebang, config).await?;
                 bar.inc(1);
                 Ok((pkg.name().clone(), pkg.version().clone(), status, stdout, stderr))
             }
@@ -116,18 +136,38 @@ where
     }
 }
 
+/// Check whether the package has sources or declares that it does not need any
+///
+/// A package without sources must declare `sources = []`, otherwise its sources were probably
+/// forgotten.
+pub fn sources_declared(pkg: &Package) -> Result<()> {
+    if pkg.sources().is_empty() && !pkg.sources().declared_none() {
+        return Err(anyhow!(
+            "{} {} has no sources, declare `sources = []` if it does not need any",
+            pkg.name(),
+            pkg.version()
+        ));
+    }
+
+    Ok(())
+}
+
 /// Check whether all phases are available in the package,
 /// generate a nice error message if one is not.
-fn all_phases_available(pkg: &Package, available_phases: &[PhaseName]) -> Result<()> {
+///
+/// Also fails if the package has a phase that is neither configured nor an extra phase of the
+/// package, if a phase name is used twice or if a phase has an empty script.
+pub fn all_phases_available(pkg: &Package, available_phases: &[PhaseName]) -> Result<()> {
     let package_phasenames = pkg.phases().keys().collect::<Vec<_>>();
+    let _ = pkg.phase_order(available_phases)?;
 
-    if let Some(phase) = package_phasenames
+    if let Some((name, _)) = pkg.phases()
         .iter()
-        .find(|name| !available_phases.contains(name))
+        .find(|(_, phase)| std::matches!(phase, Phase::Text(text) if text.trim().is_empty()))
     {
         return Err(anyhow!(
-            "Phase '{}' available in {} {}, but not in config",
-            phase.as_str(),
+            "Phase '{}' of {} {} is empty",
+            name.as_str(),
             pkg.name(),
             pkg.version()
         ));
@@ -161,6 +201,23 @@ pub fn mk_package_name_regex(regex: &str) -> Result<Regex> {
         .map_err(Error::from)
 }
 
+/// Check whether all system dependencies used in the script of `pkg` are mapped for every image
+/// that has a dependency mapping configured
+pub fn all_dependencies_mapped(pkg: &Package, shebang: &Shebang, config: &Configuration) -> Result<()> {
+    config
+        .docker()
+        .dependency_mapping()
+        .iter()
+        .try_for_each(|(image, mapping)| {
+            trace!("Checking dependency mapping of {} {} for image {}", pkg.name(), pkg.version(), image);
+            ScriptBuilder::new(shebang)
+                .with_dependency_mapping(Some(mapping))
+                .build(pkg, config.available_phases(), *config.strict_script_interpolation())
+                .with_context(|| anyhow!("Unmapped system dependency in {} {} for image {}", pkg.name(), pkg.version(), image))
+                .map(|_| ())
+        })
+}
+
 /// Make a header column for the ascii_table crate
 pub fn mk_header(vec: Vec<&str>) -> Vec<ascii_table::Column> {
     vec.into_iter()
@@ -225,6 +282,90 @@ pub fn display_data<D: Display>(
     }
 }
 
+/// A package to query, read from stdin with `--stdin`
+#[derive(Debug)]
+pub struct BatchQuery {
+    pub name: PackageName,
+    pub constraint: Option<PackageVersionConstraint>,
+}
+
+impl BatchQuery {
+    /// Parse a line of the package list
+    ///
+    /// A line is a package name, optionally followed by a version constraint. Empty lines and lines
+    /// starting with '#' are skipped.
+    fn parse(line: &str) -> Result<Option<BatchQuery>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (name, constraint) = match line.split_once(char::is_whitespace) {
+            Some((name, constraint)) => (name, Some(constraint.trim())),
+            None => (line, None),
+        };
+
+        let constraint = constraint
+            .map(PackageVersionConstraint::try_from)
+            .transpose()
+            .with_context(|| anyhow!("Parsing version constraint in line '{}'", line))?;
+
+        Ok(Some(BatchQuery {
+            name: PackageName::from(name.to_string()),
+            constraint,
+        }))
+    }
+
+    /// Whether `package` is one of the packages queried
+    pub fn matches(&self, package: &Package) -> bool {
+        package.name() == &self.name
+            && self.constraint.as_ref().map(|c| c.matches(package.version())).unwrap_or(true)
+    }
+}
+
+/// Read the package list for `--stdin` from stdin
+pub fn read_batch_queries() -> Result<Vec<BatchQuery>> {
+    use std::io::BufRead;
+
+    std::io::stdin()
+        .lock()
+        .lines()
+        .filter_map(|line| {
+            line.context("Reading package list from stdin")
+                .and_then(|line| BatchQuery::parse(&line))
+                .transpose()
+        })
+        .collect()
+}
+
+/// Print the results of a `--stdin` query, as one JSON document or, with `csv`, as CSV with a
+/// header line
+pub fn display_batch_results(
+    json: serde_json::Value,
+    header: &[&str],
+    rows: Vec<Vec<String>>,
+    csv: bool,
+) -> Result<()> {
+    let out = std::io::stdout();
+    let mut lock = out.lock();
+
+    if csv {
+        let mut wtr = csv::WriterBuilder::new().from_writer(vec![]);
+        wtr.write_record(header)?;
+        for row in rows {
+            wtr.write_record(&row)?;
+        }
+
+        let text = wtr.into_inner()
+            .map_err(Error::from)
+            .and_then(|t| String::from_utf8(t).map_err(Error::from))?;
+        write!(lock, "{}", text).map_err(Error::from)
+    } else {
+        serde_json::to_writer_pretty(&mut lock, &json)?;
+        writeln!(lock).map_err(Error::from)
+    }
+}
+
 pub fn get_date_filter(name: &str, matches: &ArgMatches) -> Result<Option<chrono::DateTime::<chrono::Local>>> {
     matches.value_of(name)
         .map(|s| {
@@ -258,3 +399,74 @@ pub fn get_date_filter(name: &str, matches: &ArgMatches) -> Result<Option<chrono
         .transpose()
 }
 
+/// Load the repository as it was at the git revision `rev`, e.g. a commit id, branch or tag
+///
+/// The tree of the commit is checked out into a temporary directory, which is removed afterwards.
+pub fn load_repository_at(repo_path: &Path, rev: &str, config: &Configuration, progressbars: &ProgressBars) -> Result<Repository> {
+    let git_repo = git2::Repository::open(repo_path)
+        .with_context(|| anyhow!("Opening repository at {}", repo_path.display()))?;
+    let commit = git_repo
+        .revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| anyhow!("Finding commit {}", rev))?;
+
+    // Removed when dropped, also if the checkout fails
+    let dest = tempfile::Builder::new()
+        .prefix(&format!("butido-{}-", commit.id()))
+        .tempdir()
+        .context("Creating directory for the checkout")?;
+    let mut checkout = git2::build::CheckoutBuilder::new();
+    checkout.target_dir(dest.path()).update_index(false).force();
+    git_repo
+        .checkout_tree(commit.as_object(), Some(&mut checkout))
+        .with_context(|| anyhow!("Checking out {} to {}", rev, dest.path().display()))?;
+
+    let bar = progressbars.bar();
+    let repo = Repository::load(dest.path(), dest.path(), config.package_repositories(), &bar);
+    bar.finish_with_message("Repository loading finished");
+    dest.close().context("Removing the checkout")?;
+    repo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch_query() {
+        assert!(BatchQuery::parse("").unwrap().is_none());
+        assert!(BatchQuery::parse("  # comment").unwrap().is_none());
+
+        let q = BatchQuery::parse("foo").unwrap().unwrap();
+        assert_eq!(q.name, PackageName::from(String::from("foo")));
+        assert!(q.constraint.is_none());
+
+        let q = BatchQuery::parse("foo >=1.0 <2.0").unwrap().unwrap();
+        assert_eq!(q.name, PackageName::from(String::from("foo")));
+        assert!(q.constraint.is_some());
+
+        assert!(BatchQuery::parse("foo bar").is_err());
+    }
+
+    #[test]
+    fn test_sources_declared() {
+        let package = |sources: &str| -> Package {
+            toml::from_str(&format!(r#"
+                name = "foo"
+                version = "1.0"
+                version_is_semver = false
+                patches = []
+                phases = {{}}
+                {}
+
+                [dependencies]
+                build = []
+                runtime = []
+                "#, sources)).unwrap()
+        };
+
+        // A package without sources is loaded, so that lint can report it
+        assert!(sources_declared(&package("")).is_err());
+        assert!(sources_declared(&package("sources = []")).is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Implementation of the 'verify' subcommand
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgMatches;
+use diesel::ExpressionMethods;
+use diesel::NullableExpressionMethods;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use itertools::Itertools;
+use log::debug;
+
+use crate::config::Configuration;
+use crate::config::SigningTool;
+use crate::db::DbConnectionConfig;
+use crate::schema;
+
+/// Implementation of the "verify" subcommand
+pub async fn verify(
+    db_connection_config: DbConnectionConfig<'_>,
+    config: &Configuration,
+    matches: &ArgMatches,
+) -> Result<()> {
+    let csv = matches.is_present("csv");
+    let signing = config.release_signing().as_ref();
+
+    let store_names = match matches.value_of("release") {
+        Some(name) => {
+            if !config.release_stores().iter().any(|s| s == name) {
+                return Err(anyhow!("Unknown release store: {}", name))
+                    .with_context(|| anyhow!("Available release stores: {}", config.release_stores().join(", ")));
+            }
+            vec![name.to_string()]
+        }
+        None => config.release_stores().clone(),
+    };
+
+    let conn = db_connection_config.establish_connection()?;
+
+    // Only the newest release of a path is in the store, older ones were overwritten by it
+    let releases = schema::releases::table
+        .inner_join(schema::artifacts::table)
+        .inner_join(schema::release_stores::table)
+        .left_join(schema::release_signatures::table)
+        .filter(schema::release_stores::store_name.eq_any(&store_names))
+        .order_by(schema::releases::release_date.desc())
+        .select((
+            schema::release_stores::store_name,
+            schema::artifacts::path,
+            schema::release_signatures::tool.nullable(),
+            schema::release_signatures::signature.nullable(),
+        ))
+        .load::<(String, String, Option<String>, Option<String>)>(&conn)?
+        .into_iter()
+        .unique_by(|(store, path, _, _)| (store.clone(), path.clone()))
+        .sorted_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)))
+        .collect::<Vec<_>>();
+
+    let mut failed = 0;
+    let mut data = Vec::with_capacity(releases.len());
+    for (store, path, tool, signature) in releases {
+        let artifact_path = config.releases_directory().join(&store).join(&path);
+
+        let status = if !artifact_path.is_file() {
+            failed += 1;
+            String::from("missing artifact")
+        } else if let (Some(tool), Some(signature)) = (tool, signature) {
+            // Verify with the tool the signature was created with, the configured one may have changed since
+            let tool = SigningTool::from_str(&tool)
+                .with_context(|| anyhow!("Unknown signing tool in database: {}", tool))?;
+            let sig_path = crate::util::signing::signature_path(&tool, &artifact_path);
+            debug!("Verifying {} with {} ({})", artifact_path.display(), sig_path.display(), tool);
+
+            if !sig_path.is_file() {
+                failed += 1;
+                String::from("missing signature")
+            } else if tokio::fs::read_to_string(&sig_path).await? != signature {
+                failed += 1;
+                String::from("signature differs from database")
+            } else if let Err(e) = crate::util::signing::verify(&tool, signing, &artifact_path, &sig_path).await {
+                debug!("Verification failed: {:?}", e);
+                failed += 1;
+                String::from("bad signature")
+            } else {
+                String::from("ok")
+            }
+        } else {
+            String::from("unsigned")
+        };
+
+        data.push(vec![store, path, status]);
+    }
+
+    let header = crate::commands::util::mk_header(vec!["Store", "Path", "Status"]);
+    crate::commands::util::display_data(header, data, csv)?;
+
+    if failed > 0 {
+        Err(anyhow!("{} released artifact(s) failed verification", failed))
+    } else {
+        Ok(())
+    }
+}
@@ -23,6 +23,10 @@ pub async fn versions_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
     use filters::filter::Filter;
     use std::io::Write;
 
+    if matches.is_present("stdin") {
+        return versions_of_batch(matches, &repo);
+    }
+
     let package_filter = {
         let name = matches
             .value_of("package_name")
@@ -42,3 +46,37 @@ pub async fn versions_of(matches: &ArgMatches, repo: Repository) -> Result<()> {
         .collect::<Result<Vec<_>>>()
         .map(|_| ())
 }
+
+/// Implementation of "versions_of --stdin"
+fn versions_of_batch(matches: &ArgMatches, repo: &Repository) -> Result<()> {
+    let queries = crate::commands::util::read_batch_queries()?;
+
+    let results = queries
+        .iter()
+        .map(|query| {
+            let versions = repo.packages()
+                .filter(|pkg| query.matches(pkg))
+                .map(|pkg| pkg.version().to_string())
+                .collect::<Vec<_>>();
+
+            (query, versions)
+        })
+        .collect::<Vec<_>>();
+
+    let json = results
+        .iter()
+        .map(|(query, versions)| serde_json::json!({
+            "package": query.name,
+            "versions": versions,
+        }))
+        .collect::<Vec<_>>();
+
+    let rows = results
+        .iter()
+        .flat_map(|(query, versions)| {
+            versions.iter().map(move |v| vec![query.name.to_string(), v.clone()])
+        })
+        .collect();
+
+    crate::commands::util::display_batch_results(json.into(), &["package", "version"], rows, matches.is_present("csv"))
+}
@@ -10,19 +10,21 @@
 
 //! Implementation of the 'what_depends' subcommand
 
+use std::convert::TryFrom;
 use std::io::Write;
 
+use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
-use futures::stream::StreamExt;
-use futures::stream::TryStreamExt;
 use log::trace;
-use resiter::Filter;
-use resiter::Map;
 
+use crate::commands::output::OutputFormat;
 use crate::commands::util::getbool;
 use crate::config::*;
+use crate::package::Package;
 use crate::package::PackageName;
+use crate::package::PackageVersion;
+use crate::package::PackageVersionConstraint;
 use crate::repository::Repository;
 use crate::ui::*;
 
@@ -32,8 +34,6 @@ pub async fn what_depends(
     config: &Configuration,
     repo: Repository,
 ) -> Result<()> {
-    use filters::failable::filter::FailableFilter;
-
     let print_runtime_deps = getbool(
         matches,
         "dependency_type",
@@ -45,19 +45,47 @@ pub async fn what_depends(
         crate::cli::IDENT_DEPENDENCY_TYPE_BUILD,
     );
 
-    let package_filter = {
-        let name = matches
-            .value_of("package_name")
-            .map(String::from)
-            .map(PackageName::from)
-            .unwrap();
-
-        crate::util::filters::build_package_filter_by_dependency_name(
-            &name,
-            print_build_deps,
-            print_runtime_deps,
-        )
-    };
+    let name = matches
+        .value_of("package_name")
+        .map(String::from)
+        .map(PackageName::from)
+        .unwrap();
+
+    let constraint = matches
+        .value_of("package_version_constraint")
+        .map(PackageVersionConstraint::try_from)
+        .transpose()?;
+
+    let dependents = find_dependents(
+        &repo,
+        &name,
+        constraint.as_ref(),
+        print_build_deps,
+        print_runtime_deps,
+        matches.is_present("transitive"),
+    )?;
+
+    if matches.is_present("output") || matches.is_present("json") {
+        let output = OutputFormat::from_matches(matches);
+        if dependents.is_empty() {
+            return crate::commands::output::display_none("No packages found", output);
+        }
+
+        let hdrs = crate::commands::util::mk_header(vec!["Name", "Version", "Depth", "Via"]);
+        let data = dependents
+            .iter()
+            .map(|d| {
+                vec![
+                    d.package.name().to_string(),
+                    d.package.version().to_string(),
+                    d.depth.to_string(),
+                    d.via.clone(),
+                ]
+            })
+            .collect();
+
+        return crate::commands::output::display(hdrs, data, output);
+    }
 
     let hb = crate::ui::handlebars_for_package_printing(config.package_print_format())?;
     let stdout = std::io::stdout();
@@ -80,23 +108,173 @@ pub async fn what_depends(
         script_highlighting: false,
     };
 
-    let mut i = 0;
-    let iter = repo
-        .packages()
-        .map(|package| package_filter.filter(package).map(|b| (b, package)))
-        .filter_ok(|(b, _)| *b)
-        .map_ok(|tpl| tpl.1)
-        .inspect(|pkg| trace!("Found package: {:?}", pkg))
-        .map_ok(|p| { // poor mans enumerate_ok()
-            i += 1;
-            p.prepare_print(config, &flags, &hb, i)
-        });
-
-    tokio_stream::iter(iter)
-        .map(|pp| pp.and_then(|p| p.into_displayable()))
-        .try_for_each(|p| {
-            let r = writeln!(&mut outlock, "{}", p).map_err(anyhow::Error::from);
-            futures::future::ready(r)
-        })
-        .await
+    dependents
+        .iter()
+        .enumerate()
+        .map(|(i, d)| d.package.prepare_print(config, &flags, &hb, i + 1))
+        .map(|pp| pp.into_displayable())
+        .map(|p| p.and_then(|p| writeln!(&mut outlock, "{}", p).map_err(Error::from)))
+        .collect::<Result<()>>()
+}
+
+/// A package that depends on the queried package
+#[derive(Debug)]
+struct Dependent<'a> {
+    package: &'a Package,
+
+    /// 1 for packages depending on the queried package directly, 2 for packages depending on those, ...
+    depth: usize,
+
+    /// The package the dependency is on, i.e. the queried package or another dependent
+    via: String,
+}
+
+/// Find the packages in `repo` that depend on `name`
+///
+/// If `constraint` is passed, only dependencies that can be satisfied by a package in the repository
+/// matching the constraint are considered. With `transitive`, packages depending on the found
+/// packages are searched as well, until no more packages are found.
+fn find_dependents<'a>(
+    repo: &'a Repository,
+    name: &PackageName,
+    constraint: Option<&PackageVersionConstraint>,
+    check_build_dep: bool,
+    check_runtime_dep: bool,
+    transitive: bool,
+) -> Result<Vec<Dependent<'a>>> {
+    use filters::failable::filter::FailableFilter;
+
+    // The packages to search dependents of, with `None` as version meaning "any version"
+    let mut targets: Vec<(PackageName, Option<PackageVersion>)> = match constraint {
+        None => vec![(name.clone(), None)],
+        Some(constraint) => repo
+            .packages()
+            .filter(|p| p.name() == name && constraint.matches(p.version()))
+            .map(|p| (p.name().clone(), Some(p.version().clone())))
+            .collect(),
+    };
+
+    let mut dependents: Vec<Dependent<'a>> = Vec::new();
+    let mut depth = 0;
+
+    while !targets.is_empty() && (depth == 0 || transitive) {
+        depth += 1;
+        let mut found: Vec<Dependent<'a>> = Vec::new();
+
+        for (target_name, target_version) in targets.iter() {
+            let via = match target_version {
+                Some(v) => format!("{} {}", target_name, v),
+                None => target_name.to_string(),
+            };
+
+            let filter = crate::util::filters::build_package_filter_by_dependency_name(
+                target_name,
+                target_version.as_ref(),
+                check_build_dep,
+                check_runtime_dep,
+            );
+
+            for package in repo.packages() {
+                let known = dependents
+                    .iter()
+                    .chain(found.iter())
+                    .any(|d| d.package.name() == package.name() && d.package.version() == package.version());
+
+                if !known && filter.filter(package)? {
+                    trace!("Found package: {:?} (depth {}, via {})", package, depth, via);
+                    found.push(Dependent {
+                        package,
+                        depth,
+                        via: via.clone(),
+                    });
+                }
+            }
+        }
+
+        targets = found
+            .iter()
+            .map(|d| (d.package.name().clone(), Some(d.package.version().clone())))
+            .collect();
+        dependents.extend(found);
+    }
+
+    Ok(dependents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+
+    fn repo(packages: &[(&str, &str, &[&str])]) -> Repository {
+        let btree = packages
+            .iter()
+            .map(|(name, vers, deps)| {
+                let mut pack = package(name, vers, "https://rust-lang.org", "123");
+                pack.set_dependencies(Dependencies::with_runtime_dependencies(
+                    deps.iter().map(|d| Dependency::from(d.to_string())).collect(),
+                ));
+                ((pname(name), pversion(vers)), pack)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        Repository::from(btree)
+    }
+
+    fn names(dependents: &[Dependent<'_>]) -> Vec<(String, usize, String)> {
+        dependents
+            .iter()
+            .map(|d| (format!("{} {}", d.package.name(), d.package.version()), d.depth, d.via.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_dependents() {
+        let repo = repo(&[
+            ("a", "1", &["foo =1"]),
+            ("b", "1", &["foo =2"]),
+            ("c", "1", &["a =1"]),
+            ("d", "1", &["c =1"]),
+            ("foo", "1", &[]),
+            ("foo", "2", &[]),
+        ]);
+
+        let direct = find_dependents(&repo, &pname("foo"), None, true, true, false).unwrap();
+        assert_eq!(names(&direct), vec![
+            (String::from("a 1"), 1, String::from("foo")),
+            (String::from("b 1"), 1, String::from("foo")),
+        ]);
+
+        let transitive = find_dependents(&repo, &pname("foo"), None, true, true, true).unwrap();
+        assert_eq!(names(&transitive), vec![
+            (String::from("a 1"), 1, String::from("foo")),
+            (String::from("b 1"), 1, String::from("foo")),
+            (String::from("c 1"), 2, String::from("a 1")),
+            (String::from("d 1"), 3, String::from("c 1")),
+        ]);
+
+        let constraint = PackageVersionConstraint::try_from("=2").unwrap();
+        let versioned = find_dependents(&repo, &pname("foo"), Some(&constraint), true, true, true).unwrap();
+        assert_eq!(names(&versioned), vec![
+            (String::from("b 1"), 1, String::from("foo 2")),
+        ]);
+    }
+
+    #[test]
+    fn test_find_dependents_without_runtime_deps() {
+        let repo = repo(&[
+            ("a", "1", &["foo =1"]),
+            ("foo", "1", &[]),
+        ]);
+
+        let found = find_dependents(&repo, &pname("foo"), None, true, false, true).unwrap();
+        assert!(found.is_empty());
+    }
 }
@@ -0,0 +1,29 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use getset::CopyGetters;
+use serde::Deserialize;
+
+/// The owner the files of artifacts are changed to when they are copied out of the container
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, CopyGetters)]
+pub struct ArtifactOwner {
+    #[getset(get_copy = "pub")]
+    uid: u32,
+
+    #[getset(get_copy = "pub")]
+    gid: u32,
+}
+
+impl ArtifactOwner {
+    #[cfg(test)]
+    pub fn new(uid: u32, gid: u32) -> Self {
+        ArtifactOwner { uid, gid }
+    }
+}
@@ -0,0 +1,263 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::package::PhaseName;
+
+/// Resource limits for the containers jobs are run in
+///
+/// Can be configured globally and per package, the settings of the package win.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct BuildLimits {
+    /// The number of CPUs a container may use, fractions are allowed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getset(get_copy = "pub")]
+    cpus: Option<f64>,
+
+    /// The memory a container may use, in bytes or with a suffix "k", "m", "g" or "t"
+    /// (powers of 1024)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    memory: Option<String>,
+
+    /// How long a phase of the script may run (e.g. "30m"), per phase
+    ///
+    /// A phase starts when the script announces it. If it does not end in time, the container is
+    /// killed and the job fails.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    #[getset(get = "pub")]
+    phase_timeouts: HashMap<PhaseName, String>,
+
+    /// How long a job may run (e.g. "6h"), before its container is killed and the job fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    timeout: Option<String>,
+
+    /// How long a job may run without writing to its log (e.g. "30m"), before the
+    /// `inactivity_action` is taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    inactivity_timeout: Option<String>,
+
+    /// What to do with a job which did not write to its log for `inactivity_timeout`, "warn" if
+    /// not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[getset(get_copy = "pub")]
+    inactivity_action: Option<InactivityAction>,
+}
+
+/// What to do with a job which did not write to its log for a while
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InactivityAction {
+    /// Log a warning
+    Warn,
+
+    /// Write the processes running in the container to the log of the job
+    Snapshot,
+
+    /// Kill the container, which fails the job
+    Kill,
+}
+
+impl BuildLimits {
+    /// Check that the limits are valid
+    pub fn validate(&self) -> Result<()> {
+        if let Some(cpus) = self.cpus {
+            if cpus.is_nan() || cpus <= 0.0 {
+                return Err(anyhow!("build.cpus must be greater than zero, is {}", cpus));
+            }
+        }
+
+        self.memory_bytes()?;
+        self.phase_timeout_durations()?;
+        self.timeout_duration()?;
+        self.inactivity().map(|_| ())
+    }
+
+    /// The memory limit in bytes
+    pub fn memory_bytes(&self) -> Result<Option<u64>> {
+        self.memory
+            .as_deref()
+            .map(|m| parse_size(m).context("Invalid build.memory"))
+            .transpose()
+    }
+
+    /// The phase timeouts, by the name of the phase
+    pub fn phase_timeout_durations(&self) -> Result<HashMap<String, Duration>> {
+        self.phase_timeouts
+            .iter()
+            .map(|(phase, timeout)| {
+                parse_timeout(timeout)
+                    .with_context(|| anyhow!("Invalid build.phase_timeouts.{}: '{}'", phase.as_str(), timeout))
+                    .map(|d| (phase.as_str().to_string(), d))
+            })
+            .collect()
+    }
+
+    /// How long a job may run
+    pub fn timeout_duration(&self) -> Result<Option<Duration>> {
+        self.timeout
+            .as_deref()
+            .map(|t| parse_timeout(t).with_context(|| anyhow!("Invalid build.timeout: '{}'", t)))
+            .transpose()
+    }
+
+    /// How long a job may run without writing to its log and what to do with it afterwards
+    pub fn inactivity(&self) -> Result<Option<(Duration, InactivityAction)>> {
+        self.inactivity_timeout
+            .as_deref()
+            .map(|t| parse_timeout(t).with_context(|| anyhow!("Invalid build.inactivity_timeout: '{}'", t)))
+            .transpose()
+            .map(|timeout| timeout.map(|t| (t, self.inactivity_action.unwrap_or(InactivityAction::Warn))))
+    }
+
+    /// Whether no resource limit is set
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_none() && self.memory.is_none()
+    }
+
+    /// The limits of `self`, with the limits which are not set taken from `fallback`
+    pub fn or(&self, fallback: Option<&BuildLimits>) -> BuildLimits {
+        BuildLimits {
+            cpus: self.cpus.or_else(|| fallback.and_then(|f| f.cpus)),
+            memory: self.memory.clone().or_else(|| fallback.and_then(|f| f.memory.clone())),
+            phase_timeouts: fallback
+                .map(|f| f.phase_timeouts.clone())
+                .unwrap_or_default()
+                .into_iter()
+                .chain(self.phase_timeouts.clone())
+                .collect(),
+            timeout: self.timeout.clone().or_else(|| fallback.and_then(|f| f.timeout.clone())),
+            inactivity_timeout: self
+                .inactivity_timeout
+                .clone()
+                .or_else(|| fallback.and_then(|f| f.inactivity_timeout.clone())),
+            inactivity_action: self.inactivity_action.or_else(|| fallback.and_then(|f| f.inactivity_action)),
+        }
+    }
+}
+
+/// Parse a timeout like "30m", which must be at least one second
+fn parse_timeout(s: &str) -> Result<Duration> {
+    humantime::parse_duration(s)
+        .map_err(Error::from)
+        .and_then(|d| if d.as_secs() == 0 {
+            Err(anyhow!("Timeout must be at least one second"))
+        } else {
+            Ok(d)
+        })
+}
+
+/// Parse a size in bytes or with a suffix "k", "m", "g" or "t" (powers of 1024)
+pub(in crate::config) fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (number, factor) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let factor = match c.to_ascii_lowercase() {
+                'b' => 1,
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                't' => 1 << 40,
+                _ => return Err(anyhow!("Unknown unit in size: '{}'", s)),
+            };
+            (&s[..i], factor)
+        }
+        _ => (s, 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(factor))
+        .filter(|n| *n > 0)
+        .ok_or_else(|| anyhow!("Invalid size: '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("512m").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size("4G").unwrap(), 4 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("100b").unwrap(), 100);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("4x").is_err());
+        assert!(parse_size("1.5g").is_err());
+    }
+
+    #[test]
+    fn test_package_limits_win() {
+        let global = BuildLimits { cpus: Some(4.0), memory: Some(String::from("8g")), ..BuildLimits::default() };
+        let package = BuildLimits { cpus: None, memory: Some(String::from("32g")), ..BuildLimits::default() };
+
+        let limits = package.or(Some(&global));
+        assert_eq!(limits.cpus(), Some(4.0));
+        assert_eq!(limits.memory_bytes().unwrap(), Some(32 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_phase_timeouts() {
+        let global: BuildLimits = toml::from_str(r#"phase_timeouts = { configure = "10m", build = "2h" }"#).unwrap();
+        let package: BuildLimits = toml::from_str(r#"phase_timeouts = { build = "6h" }"#).unwrap();
+
+        let timeouts = package.or(Some(&global)).phase_timeout_durations().unwrap();
+        assert_eq!(timeouts.len(), 2);
+        assert_eq!(timeouts["configure"], Duration::from_secs(10 * 60));
+        assert_eq!(timeouts["build"], Duration::from_secs(6 * 60 * 60));
+
+        let invalid: BuildLimits = toml::from_str(r#"phase_timeouts = { build = "forever" }"#).unwrap();
+        assert!(invalid.validate().is_err());
+        let zero: BuildLimits = toml::from_str(r#"phase_timeouts = { build = "0s" }"#).unwrap();
+        assert!(zero.validate().is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let global: BuildLimits = toml::from_str(r#"timeout = "6h""#).unwrap();
+        let package = BuildLimits::default();
+        assert_eq!(package.or(Some(&global)).timeout_duration().unwrap(), Some(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(package.timeout_duration().unwrap(), None);
+
+        let invalid: BuildLimits = toml::from_str(r#"timeout = "6 o'clock""#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_inactivity() {
+        let global: BuildLimits = toml::from_str(r#"inactivity_timeout = "30m""#).unwrap();
+        let package: BuildLimits = toml::from_str(r#"inactivity_action = "snapshot""#).unwrap();
+        assert_eq!(global.inactivity().unwrap(), Some((Duration::from_secs(30 * 60), InactivityAction::Warn)));
+        assert_eq!(
+            package.or(Some(&global)).inactivity().unwrap(),
+            Some((Duration::from_secs(30 * 60), InactivityAction::Snapshot))
+        );
+        assert_eq!(package.inactivity().unwrap(), None);
+
+        assert!(toml::from_str::<BuildLimits>(r#"inactivity_action = "ignore""#).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::Weekday;
+use serde::Deserialize;
+
+/// A recurring time window in which builds may be started
+///
+/// If `end` is not after `start`, the window spans midnight, e.g. "20:00" to "06:00".
+/// All times are local times.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "BuildWindowConfig")]
+pub struct BuildWindow {
+    start: NaiveTime,
+
+    end: NaiveTime,
+
+    /// The days of the week the window may start on, all days if empty
+    days: Vec<Weekday>,
+}
+
+/// A build window as written in the configuration file
+#[derive(Deserialize)]
+struct BuildWindowConfig {
+    start: String,
+    end: String,
+
+    #[serde(default)]
+    days: Vec<String>,
+}
+
+impl TryFrom<BuildWindowConfig> for BuildWindow {
+    type Error = Error;
+
+    fn try_from(cfg: BuildWindowConfig) -> Result<Self> {
+        let parse_time = |s: &str| {
+            NaiveTime::parse_from_str(s, "%H:%M")
+                .map_err(|e| anyhow!("Invalid time in build window, expected HH:MM: '{}': {}", s, e))
+        };
+
+        Ok(BuildWindow {
+            start: parse_time(&cfg.start)?,
+            end: parse_time(&cfg.end)?,
+            days: cfg
+                .days
+                .iter()
+                .map(|d| Weekday::from_str(d).map_err(|_| anyhow!("Invalid day in build window: '{}'", d)))
+                .collect::<Result<Vec<_>>>()?,
+        })
+    }
+}
+
+impl BuildWindow {
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    /// Whether the window is open at `t`
+    pub fn contains(&self, t: &NaiveDateTime) -> bool {
+        let time = t.time();
+        if self.start < self.end {
+            self.start <= time && time < self.end && self.starts_on(t.weekday())
+        } else if time >= self.start {
+            self.starts_on(t.weekday())
+        } else {
+            // Before midnight the window opened on the day before
+            time < self.end && self.starts_on(t.weekday().pred())
+        }
+    }
+
+    /// The first point in time after `t` at which the window opens
+    pub fn next_start_after(&self, t: &NaiveDateTime) -> NaiveDateTime {
+        (0..=7)
+            .map(|days| (t.date() + Duration::days(days)).and_time(self.start))
+            .find(|start| start > t && self.starts_on(start.weekday()))
+            .unwrap() // safe, because every weekday is covered within eight days
+    }
+}
+
+/// Whether `t` is within one of the `windows`
+///
+/// If no windows are configured, any time is within a build window.
+pub fn is_in_build_window(windows: &[BuildWindow], t: &NaiveDateTime) -> bool {
+    windows.is_empty() || windows.iter().any(|w| w.contains(t))
+}
+
+/// The first point in time at or after `t` which is within one of the `windows`
+pub fn next_build_window(windows: &[BuildWindow], t: &NaiveDateTime) -> NaiveDateTime {
+    if is_in_build_window(windows, t) {
+        *t
+    } else {
+        windows
+            .iter()
+            .map(|w| w.next_start_after(t))
+            .min()
+            .unwrap() // safe, because windows cannot be empty here
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn window(start: &str, end: &str, days: &[&str]) -> BuildWindow {
+        BuildWindow::try_from(BuildWindowConfig {
+            start: String::from(start),
+            end: String::from(end),
+            days: days.iter().map(|d| String::from(*d)).collect(),
+        })
+        .unwrap()
+    }
+
+    // 2021-03-05 is a friday
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 3, day).and_hms(hour, min, 0)
+    }
+
+    #[test]
+    fn test_window_within_day() {
+        let w = window("08:00", "12:00", &[]);
+        assert!(!w.contains(&at(5, 7, 59)));
+        assert!(w.contains(&at(5, 8, 0)));
+        assert!(w.contains(&at(5, 11, 59)));
+        assert!(!w.contains(&at(5, 12, 0)));
+        assert_eq!(w.next_start_after(&at(5, 9, 0)), at(6, 8, 0));
+        assert_eq!(w.next_start_after(&at(5, 7, 0)), at(5, 8, 0));
+    }
+
+    #[test]
+    fn test_window_spanning_midnight() {
+        let w = window("20:00", "06:00", &["fri"]);
+        assert!(w.contains(&at(5, 20, 0)));
+        assert!(w.contains(&at(6, 5, 59)));
+        assert!(!w.contains(&at(6, 6, 0)));
+        assert!(!w.contains(&at(6, 20, 0)));
+        assert!(!w.contains(&at(5, 5, 0)));
+        assert_eq!(w.next_start_after(&at(6, 1, 0)), at(12, 20, 0));
+    }
+
+    #[test]
+    fn test_next_build_window() {
+        let windows = vec![window("20:00", "06:00", &[]), window("00:00", "00:00", &["sat", "sun"])];
+        assert_eq!(next_build_window(&windows, &at(5, 12, 0)), at(5, 20, 0));
+        assert_eq!(next_build_window(&windows, &at(6, 12, 0)), at(6, 12, 0));
+        assert_eq!(next_build_window(&[], &at(5, 12, 0)), at(5, 12, 0));
+    }
+
+    #[test]
+    fn test_invalid_window() {
+        assert!(BuildWindow::try_from(BuildWindowConfig {
+            start: String::from("25:00"),
+            end: String::from("06:00"),
+            days: vec![],
+        })
+        .is_err());
+        assert!(BuildWindow::try_from(BuildWindowConfig {
+            start: String::from("20:00"),
+            end: String::from("06:00"),
+            days: vec![String::from("someday")],
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::App;
+use clap::Arg;
+use getset::Getters;
+use serde::Deserialize;
+
+/// Default command line arguments, by subcommand
+///
+/// The defaults of a subcommand are inserted right after its name on the command line, unless the
+/// flag is given on the command line already, so that the command line takes precedence. The
+/// defaults of nested subcommands are nested as well, e.g. the ones of "db jobs" are configured in
+/// `[defaults.db.jobs]`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct CliDefaults(BTreeMap<String, SubcommandDefaults>);
+
+/// The default arguments of one subcommand
+#[derive(Clone, Debug, Default, Deserialize, Getters)]
+pub struct SubcommandDefaults {
+    /// The arguments, each flag followed by its values, e.g. `["--write-log", "--timeout", "8h"]`
+    #[serde(default)]
+    #[getset(get = "pub")]
+    flags: Vec<String>,
+
+    /// The defaults of the subcommands of this subcommand
+    #[serde(flatten)]
+    subcommands: BTreeMap<String, SubcommandDefaults>,
+}
+
+impl CliDefaults {
+    /// The defaults of all subcommands, by their full path, e.g. "db.jobs"
+    pub fn iter(&self) -> impl Iterator<Item = (String, &SubcommandDefaults)> {
+        fn collect<'a>(prefix: Option<&str>, map: &'a BTreeMap<String, SubcommandDefaults>, out: &mut Vec<(String, &'a SubcommandDefaults)>) {
+            for (name, defaults) in map.iter() {
+                let path = prefix.map(|p| format!("{}.{}", p, name)).unwrap_or_else(|| name.clone());
+                out.push((path.clone(), defaults));
+                collect(Some(&path), &defaults.subcommands, out);
+            }
+        }
+
+        let mut all = vec![];
+        collect(None, &self.0, &mut all);
+        all.into_iter()
+    }
+
+    /// The defaults of the subcommand with the names in `path`
+    fn get(&self, path: &[&str]) -> Option<&SubcommandDefaults> {
+        let (first, rest) = path.split_first()?;
+        rest.iter()
+            .try_fold(self.0.get(*first)?, |defaults, name| defaults.subcommands.get(*name))
+    }
+
+    /// Check that the defaults of every subcommand start with a flag
+    pub fn validate(&self) -> Result<()> {
+        for (subcommand, defaults) in self.iter() {
+            if let Some(first) = defaults.flags.first() {
+                if !first.starts_with('-') {
+                    return Err(anyhow!("defaults.{}.flags must start with a flag, found '{}'", subcommand, first));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert the defaults of the subcommand in `args` (the full command line, including the name
+    /// of the binary), which is parsed with `app`
+    ///
+    /// The subcommand is the deepest one of `app` on the command line, the values of options are
+    /// skipped when looking for it. Flags which are given on the command line already, with their
+    /// long or short name, are not inserted.
+    pub fn apply(&self, args: Vec<OsString>, app: &App<'_>) -> Vec<OsString> {
+        let (position, apps) = match subcommand_path(&args, app) {
+            Some(found) => found,
+            None => return args,
+        };
+
+        let path = apps.iter().map(|a| a.get_name()).collect::<Vec<_>>();
+        let defaults = match self.get(&path) {
+            Some(defaults) => defaults,
+            None => return args,
+        };
+
+        let subcommand = apps[apps.len() - 1];
+        let given = &args[position + 1..];
+        let defaults = flag_groups(&defaults.flags)
+            .into_iter()
+            .filter(|group| {
+                let names = flag_names(subcommand, &group[0]);
+                !given.iter().any(|arg| {
+                    arg.to_str()
+                        .map(|a| names.iter().any(|name| is_same_flag(a, name)))
+                        .unwrap_or(false)
+                })
+            })
+            .flatten()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+
+        let mut result = args[..=position].to_vec();
+        result.extend(defaults);
+        result.extend(given.iter().cloned());
+        result
+    }
+}
+
+/// The position of the deepest subcommand in `args` and the subcommands leading to it, see
+/// `CliDefaults::apply()`
+fn subcommand_path<'a, 'help>(args: &[OsString], app: &'a App<'help>) -> Option<(usize, Vec<&'a App<'help>>)> {
+    let mut apps = vec![app];
+    let mut deepest = None;
+    let mut position = 1;
+
+    while let Some(arg) = args.get(position).and_then(|a| a.to_str()) {
+        if arg == "--" {
+            break;
+        }
+
+        if arg.starts_with('-') {
+            // The value of an option might be the name of a subcommand as well
+            position += if takes_separate_value(&apps, arg) { 2 } else { 1 };
+            continue;
+        }
+
+        let subcommand = apps[apps.len() - 1]
+            .get_subcommands()
+            .find(|sub| sub.get_name() == arg || sub.get_all_aliases().any(|alias| alias == arg));
+        match subcommand {
+            Some(sub) => {
+                apps.push(sub);
+                deepest = Some(position);
+                position += 1;
+            }
+
+            // A positional argument, there are no subcommands after it
+            None => break,
+        }
+    }
+
+    deepest.map(|p| (p, apps.into_iter().skip(1).collect()))
+}
+
+/// Whether `arg` is an option of one of `apps` which is followed by its value
+fn takes_separate_value(apps: &[&App<'_>], arg: &str) -> bool {
+    // "--jobs=8" and "-j8" carry their value
+    if arg.contains('=') || (!arg.starts_with("--") && arg.len() > 2) {
+        return false;
+    }
+
+    apps.iter()
+        .flat_map(|app| app.get_arguments())
+        .any(|a| a.is_set(clap::ArgSettings::TakesValue) && arg_names(a).iter().any(|name| name == arg))
+}
+
+/// The long and short names of the argument of `app` the flag `flag` (which might have a value
+/// attached with "=") refers to
+///
+/// Unknown flags only have their own name.
+fn flag_names(app: &App<'_>, flag: &str) -> Vec<String> {
+    let (name, _) = flag.split_once('=').unwrap_or((flag, ""));
+    app.get_arguments()
+        .map(arg_names)
+        .find(|names| names.iter().any(|n| n == name))
+        .unwrap_or_else(|| vec![name.to_string()])
+}
+
+/// The names an argument can be given with on the command line, e.g. "--jobs" and "-j"
+fn arg_names(arg: &Arg<'_>) -> Vec<String> {
+    let long = arg.get_long().map(|l| format!("--{}", l));
+    let shorts = arg
+        .get_short()
+        .into_iter()
+        .chain(arg.get_visible_short_aliases().unwrap_or_default())
+        .map(|s| format!("-{}", s));
+    long.into_iter().chain(shorts).collect()
+}
+
+/// Split arguments in groups of a flag and the values following it
+fn flag_groups(flags: &[String]) -> Vec<&[String]> {
+    let mut groups = vec![];
+    let mut start = 0;
+    for (i, flag) in flags.iter().enumerate().skip(1) {
+        if flag.starts_with('-') {
+            groups.push(&flags[start..i]);
+            start = i;
+        }
+    }
+    if start < flags.len() {
+        groups.push(&flags[start..]);
+    }
+    groups
+}
+
+/// Whether the argument `arg` from the command line sets the flag `flag`
+fn is_same_flag(arg: &str, flag: &str) -> bool {
+    let (flag, _) = flag.split_once('=').unwrap_or((flag, ""));
+    if arg == flag {
+        return true;
+    }
+
+    if flag.starts_with("--") {
+        arg.strip_prefix(flag).map(|rest| rest.starts_with('=')).unwrap_or(false)
+    } else {
+        // A short flag with its value attached, e.g. "-j8"
+        flag.len() == 2 && arg.starts_with(flag) && !arg.starts_with("--")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults(subcommand: &str, flags: &[&str]) -> CliDefaults {
+        let mut names = subcommand.rsplit('.');
+        let mut defaults = SubcommandDefaults {
+            flags: flags.iter().map(|s| s.to_string()).collect(),
+            subcommands: BTreeMap::new(),
+        };
+        let mut name = names.next().unwrap().to_string();
+        for parent in names {
+            let mut subcommands = BTreeMap::new();
+            subcommands.insert(name, defaults);
+            defaults = SubcommandDefaults { flags: vec![], subcommands };
+            name = parent.to_string();
+        }
+
+        let mut map = BTreeMap::new();
+        map.insert(name, defaults);
+        CliDefaults(map)
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn app() -> App<'static> {
+        App::new("butido")
+            .arg(Arg::new("database_url").long("db-url").takes_value(true))
+            .arg(Arg::new("hide_bars").long("hide-bars"))
+            .subcommand(App::new("build")
+                .arg(Arg::new("keep_going").long("keep-going").short('k'))
+                .arg(Arg::new("jobs").long("jobs").short('j').takes_value(true))
+                .arg(Arg::new("package_name").index(1)))
+            .subcommand(App::new("db")
+                .subcommand(App::new("jobs")
+                    .arg(Arg::new("output").long("output").short('o').takes_value(true))
+                    .arg(Arg::new("package").long("package").takes_value(true))))
+            .subcommand(App::new("tree-of")
+                .arg(Arg::new("package_name").index(1)))
+    }
+
+    #[test]
+    fn test_apply() {
+        let d = defaults("build", &["--keep-going", "--jobs", "8"]);
+        let app = app();
+        let apply = |a: &[&str]| d.apply(args(a), &app);
+
+        assert_eq!(apply(&["butido", "build", "foo"]), args(&["butido", "build", "--keep-going", "--jobs", "8", "foo"]));
+        assert_eq!(apply(&["butido", "--hide-bars", "build", "foo", "--jobs", "2"]), args(&["butido", "--hide-bars", "build", "--keep-going", "foo", "--jobs", "2"]));
+        assert_eq!(apply(&["butido", "build", "foo", "--jobs=2"]), args(&["butido", "build", "--keep-going", "foo", "--jobs=2"]));
+        assert_eq!(apply(&["butido", "--db-url", "db", "build", "foo"]), args(&["butido", "--db-url", "db", "build", "--keep-going", "--jobs", "8", "foo"]));
+        assert_eq!(apply(&["butido", "db", "jobs"]), args(&["butido", "db", "jobs"]));
+    }
+
+    #[test]
+    fn test_apply_short_and_long_flags() {
+        let d = defaults("build", &["--keep-going", "-j", "8"]);
+        let app = app();
+        let apply = |a: &[&str]| d.apply(args(a), &app);
+
+        assert_eq!(apply(&["butido", "build", "foo", "-k"]), args(&["butido", "build", "-j", "8", "foo", "-k"]));
+        assert_eq!(apply(&["butido", "build", "foo", "--jobs", "2"]), args(&["butido", "build", "--keep-going", "foo", "--jobs", "2"]));
+        assert_eq!(apply(&["butido", "build", "foo", "-j2"]), args(&["butido", "build", "--keep-going", "foo", "-j2"]));
+    }
+
+    #[test]
+    fn test_apply_to_nested_subcommand() {
+        let d = defaults("db.jobs", &["--output", "json"]);
+        let app = app();
+        let apply = |a: &[&str]| d.apply(args(a), &app);
+
+        assert_eq!(apply(&["butido", "db", "jobs"]), args(&["butido", "db", "jobs", "--output", "json"]));
+        assert_eq!(apply(&["butido", "db", "jobs", "-o", "csv"]), args(&["butido", "db", "jobs", "-o", "csv"]));
+
+        // The defaults of "db jobs" are not the defaults of "db"
+        assert_eq!(apply(&["butido", "db"]), args(&["butido", "db"]));
+    }
+
+    #[test]
+    fn test_apply_to_other_subcommand() {
+        let d = defaults("build", &["--keep-going"]);
+        let app = app();
+        let apply = |a: &[&str]| d.apply(args(a), &app);
+
+        // Positional arguments and values of options named like the subcommand
+        assert_eq!(apply(&["butido", "tree-of", "build"]), args(&["butido", "tree-of", "build"]));
+        assert_eq!(apply(&["butido", "db", "jobs", "--package", "build"]), args(&["butido", "db", "jobs", "--package", "build"]));
+        assert_eq!(apply(&["butido", "--db-url", "build", "db", "jobs"]), args(&["butido", "--db-url", "build", "db", "jobs"]));
+    }
+
+    #[test]
+    fn test_iter() {
+        let d: CliDefaults = toml::from_str(r#"
+            build.flags = ["--keep-going"]
+            db.jobs.flags = ["--output", "json"]
+        "#).unwrap();
+        let paths = d.iter().map(|(path, defaults)| (path, defaults.flags().len())).collect::<Vec<_>>();
+        assert_eq!(paths, vec![(String::from("build"), 1), (String::from("db"), 0), (String::from("db.jobs"), 2)]);
+    }
+
+    #[test]
+    fn test_flag_groups() {
+        let flags = ["--a", "--b", "1", "2", "-c", "3"].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let groups = flag_groups(&flags);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[1], &flags[1..4]);
+        assert_eq!(groups[2], &flags[4..]);
+    }
+
+    #[test]
+    fn test_is_same_flag() {
+        assert!(is_same_flag("--jobs", "--jobs"));
+        assert!(is_same_flag("--jobs=4", "--jobs"));
+        assert!(is_same_flag("--jobs", "--jobs=8"));
+        assert!(!is_same_flag("--jobs-max", "--jobs"));
+        assert!(is_same_flag("-j4", "-j"));
+        assert!(!is_same_flag("-k", "-j"));
+    }
+}
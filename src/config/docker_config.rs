@@ -15,6 +15,7 @@ use serde::Deserialize;
 
 use crate::config::Endpoint;
 use crate::config::EndpointName;
+use crate::config::ImageDefaults;
 use crate::util::docker::ImageName;
 
 /// Configuration of the docker daemon interfacing functionality
@@ -47,9 +48,57 @@ pub struct DockerConfig {
     #[getset(get_copy = "pub")]
     verify_images_present: bool,
 
+    /// Whether the digest of the image must match when searching for artifacts to reuse
+    ///
+    /// If this is false, only the name of the image is compared, which results in reusing
+    /// artifacts that were built with an older image after the image was rebuilt and retagged.
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    reuse_requires_image_digest_match: bool,
+
     #[getset(get = "pub")]
     images: Vec<ImageName>,
 
+    /// Digests the images are pinned to, per image
+    ///
+    /// Containers of a pinned image are created from the image with this digest instead of the
+    /// image the tag currently points to.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    pinned_images: HashMap<ImageName, String>,
+
+    /// Whether images which are missing on an endpoint are pulled before the jobs are scheduled
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    pull_images: bool,
+
+    /// Mapping of system dependency names, per image
+    ///
+    /// Different base images name the same system dependency differently (e.g. `libssl-dev` on
+    /// debian vs. `openssl-devel` on centos). The mapping is applied when rendering the `sysdep`
+    /// helper in package scripts.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    dependency_mapping: HashMap<ImageName, HashMap<String, String>>,
+
+    /// Defaults for jobs, per image
+    #[serde(default)]
+    #[getset(get = "pub")]
+    image_defaults: HashMap<ImageName, ImageDefaults>,
+
     #[getset(get = "pub")]
     endpoints: HashMap<EndpointName, Endpoint>,
 }
+
+impl DockerConfig {
+    /// The reference containers of `image` are created from
+    ///
+    /// This is the image pinned to its digest, if it is pinned in `pinned_images`, and the image
+    /// name otherwise.
+    pub fn image_reference(&self, image: &ImageName) -> ImageName {
+        self.pinned_images
+            .get(image)
+            .map(|digest| image.pinned(digest))
+            .unwrap_or_else(|| image.clone())
+    }
+}
@@ -11,6 +11,8 @@
 use getset::{CopyGetters, Getters};
 use serde::Deserialize;
 
+use crate::util::docker::ImageName;
+
 #[derive(Debug, Clone, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[serde(transparent)]
 pub struct EndpointName(String);
@@ -54,6 +56,62 @@ pub struct Endpoint {
     /// Duration length of timeout for connecting endpoint
     #[getset(get = "pub")]
     timeout: Option<u64>,
+
+    /// Connect to the endpoint via SSH ("ssh://user@host[:port]") and run the docker (or podman) CLI
+    /// there, instead of talking to the API of the daemon
+    #[getset(get = "pub")]
+    connection: Option<String>,
+
+    /// The kubeconfig to use for a Kubernetes endpoint, the default kubeconfig of kubectl if not set
+    #[getset(get = "pub")]
+    kubeconfig: Option<std::path::PathBuf>,
+
+    /// The namespace to run the pods of a Kubernetes endpoint in, the namespace of the context if
+    /// not set
+    #[getset(get = "pub")]
+    namespace: Option<String>,
+
+    /// The time windows in which jobs may be started on this endpoint, any time if empty
+    #[serde(default)]
+    #[getset(get = "pub")]
+    build_windows: Vec<crate::config::BuildWindow>,
+
+    /// The architectures jobs can be built for on this endpoint
+    ///
+    /// Jobs for an architecture are only scheduled to endpoints that list it. Jobs without an
+    /// architecture are scheduled to any endpoint.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    architectures: Vec<crate::util::Architecture>,
+
+    /// The images jobs can be run with on this endpoint
+    ///
+    /// Jobs are only scheduled to endpoints that list their image. If empty, the endpoint runs
+    /// jobs with any of the configured images.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    images: Vec<ImageName>,
+
+    /// The scratch space for the working directories of the jobs on this endpoint
+    ///
+    /// If not set, jobs work in the filesystem of their container.
+    #[getset(get = "pub")]
+    scratch: Option<crate::config::ScratchConfig>,
+
+    /// The security settings for the containers on this endpoint
+    #[getset(get = "pub")]
+    security: Option<crate::config::SecurityProfile>,
+
+    /// The simulated runs of a "mock" endpoint, the defaults of `MockConfig` if not set
+    #[getset(get = "pub")]
+    mock: Option<crate::config::MockConfig>,
+}
+
+impl Endpoint {
+    /// Whether jobs with `image` can be run on this endpoint
+    pub fn supports_image(&self, image: &ImageName) -> bool {
+        self.images.is_empty() || self.images.contains(image)
+    }
 }
 
 /// The type of an endpoint
@@ -63,5 +121,17 @@ pub enum EndpointType {
     Socket,
     #[serde(rename = "http")]
     Http,
+
+    /// Podman, via its Docker-compatible API, either on a socket or via http
+    #[serde(rename = "podman")]
+    Podman,
+
+    /// A Kubernetes cluster, jobs are run as pods. The URI is the context in the kubeconfig
+    #[serde(rename = "kubernetes")]
+    Kubernetes,
+
+    /// Simulated, no containers are run. For testing the scheduling of jobs
+    #[serde(rename = "mock")]
+    Mock,
 }
 
@@ -0,0 +1,46 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use getset::Getters;
+use serde::Deserialize;
+
+use crate::util::EnvironmentVariableName;
+
+/// Defaults for all jobs that run in a certain image
+#[derive(Debug, Getters, Deserialize)]
+pub struct ImageDefaults {
+    /// The shebang used for the package scripts in this image, instead of the global one
+    ///
+    /// The interpreter from the shebang is also used to run the script in the container.
+    #[getset(get = "pub")]
+    shebang: Option<String>,
+
+    /// Environment variables passed to each job in this image
+    ///
+    /// Variables passed on the commandline take precedence.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    env: HashMap<EnvironmentVariableName, String>,
+
+    /// Executables (absolute pathes) which must be present in the image before a job is run
+    #[serde(default)]
+    #[getset(get = "pub")]
+    required_executables: Vec<PathBuf>,
+
+    /// The security settings for the containers of jobs in this image
+    ///
+    /// Settings which are made here win over the ones of the endpoint.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    security: Option<crate::config::SecurityProfile>,
+}
@@ -0,0 +1,243 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use serde::Deserialize;
+
+/// The kind of target a log sink forwards to
+#[derive(parse_display::Display, Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+pub enum LogSinkKind {
+    /// The local syslog daemon, via its unix socket
+    #[serde(rename = "syslog")]
+    #[display("syslog")]
+    Syslog,
+
+    /// The local systemd journal, via its native protocol
+    #[serde(rename = "journald")]
+    #[display("journald")]
+    Journald,
+
+    /// A collector listening on a TCP port, records are separated by newlines
+    #[serde(rename = "tcp")]
+    #[display("tcp")]
+    Tcp,
+
+    /// A collector listening on a UDP port, one record per datagram
+    #[serde(rename = "udp")]
+    #[display("udp")]
+    Udp,
+}
+
+/// The format records are sent to TCP and UDP collectors in
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+pub enum LogSinkFormat {
+    /// RFC 5424 syslog messages, with the fields of the record as structured data
+    #[serde(rename = "syslog")]
+    Syslog,
+
+    /// JSON objects with the timestamp, level, line and fields of the record, as streamed with
+    /// "--log-format json"
+    #[serde(rename = "json")]
+    Json,
+}
+
+impl Default for LogSinkFormat {
+    fn default() -> Self {
+        LogSinkFormat::Syslog
+    }
+}
+
+/// The level of a forwarded record, ordered by importance
+///
+/// The levels are a subset of the syslog severities.
+#[derive(parse_display::Display, Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Deserialize)]
+pub enum LogLevel {
+    #[serde(rename = "debug")]
+    #[display("debug")]
+    Debug,
+
+    #[serde(rename = "info")]
+    #[display("info")]
+    Info,
+
+    #[serde(rename = "notice")]
+    #[display("notice")]
+    Notice,
+
+    #[serde(rename = "warning")]
+    #[display("warning")]
+    Warning,
+
+    #[serde(rename = "error")]
+    #[display("error")]
+    Error,
+}
+
+impl LogLevel {
+    /// The syslog severity of the level, which is also used as journald priority
+    pub fn severity(&self) -> u8 {
+        match self {
+            LogLevel::Debug => 7,
+            LogLevel::Info => 6,
+            LogLevel::Notice => 5,
+            LogLevel::Warning => 4,
+            LogLevel::Error => 3,
+        }
+    }
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        LogLevel::Info
+    }
+}
+
+/// A target the logs of jobs and the events of submits are forwarded to
+///
+/// Forwarding happens in addition to the log files and the database. Failing to forward never
+/// fails a job.
+#[derive(Clone, Debug, Deserialize, Getters, CopyGetters)]
+pub struct LogSinkConfig {
+    #[getset(get_copy = "pub")]
+    kind: LogSinkKind,
+
+    /// The "host:port" of the collector, required for "tcp" and "udp"
+    #[serde(default)]
+    #[getset(get = "pub")]
+    address: Option<String>,
+
+    /// The unix socket of the syslog daemon or the journal, if it is not at the default location
+    #[serde(default)]
+    #[getset(get = "pub")]
+    socket: Option<PathBuf>,
+
+    /// The format for "tcp" and "udp" collectors
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    format: LogSinkFormat,
+
+    /// The syslog facility, 1 (user) by default, 16 to 23 are local0 to local7
+    #[serde(default = "default_facility")]
+    #[getset(get_copy = "pub")]
+    facility: u8,
+
+    /// Records below this level are not forwarded
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    level: LogLevel,
+
+    /// Only records whose fields have one of the listed values are forwarded
+    ///
+    /// Records without one of the listed fields are not forwarded.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    fields: BTreeMap<String, Vec<String>>,
+}
+
+fn default_facility() -> u8 {
+    1
+}
+
+impl LogSinkConfig {
+    /// Check that the settings fit the kind of the sink
+    pub fn validate(&self) -> Result<()> {
+        match self.kind {
+            LogSinkKind::Tcp | LogSinkKind::Udp => {
+                if self.address.as_deref().map(str::is_empty).unwrap_or(true) {
+                    return Err(anyhow!("address is required for {} log sinks", self.kind));
+                }
+            }
+
+            LogSinkKind::Syslog | LogSinkKind::Journald => {
+                if self.address.is_some() {
+                    return Err(anyhow!("{} log sinks use a local socket, not an address", self.kind));
+                }
+            }
+        }
+
+        if self.facility > 23 {
+            return Err(anyhow!("Invalid syslog facility {}, must be between 0 and 23", self.facility));
+        }
+
+        Ok(())
+    }
+
+    /// Whether a record with `level` is forwarded to this sink
+    ///
+    /// `field` returns the value of a field of the record by name, if the record has that field.
+    pub fn accepts<'a, F>(&self, level: LogLevel, field: F) -> bool
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        level >= self.level && self.fields
+            .iter()
+            .all(|(name, values)| field(name).map(|v| values.iter().any(|value| value == v)).unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(kind: LogSinkKind, address: Option<&str>) -> LogSinkConfig {
+        LogSinkConfig {
+            kind,
+            address: address.map(String::from),
+            socket: None,
+            format: LogSinkFormat::default(),
+            facility: default_facility(),
+            level: LogLevel::default(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(sink(LogSinkKind::Syslog, None).validate().is_ok());
+        assert!(sink(LogSinkKind::Journald, None).validate().is_ok());
+        assert!(sink(LogSinkKind::Udp, Some("logs.example.com:514")).validate().is_ok());
+        assert!(sink(LogSinkKind::Tcp, None).validate().is_err());
+        assert!(sink(LogSinkKind::Udp, Some("")).validate().is_err());
+        assert!(sink(LogSinkKind::Syslog, Some("logs.example.com:514")).validate().is_err());
+    }
+
+    #[test]
+    fn test_accepts() {
+        let mut s = sink(LogSinkKind::Syslog, None);
+        s.level = LogLevel::Notice;
+        s.fields.insert(String::from("package"), vec![String::from("foo"), String::from("bar")]);
+
+        let field = |fields: &'static [(&'static str, &'static str)]| {
+            move |name: &str| fields.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+        };
+
+        let foo = field(&[("package", "foo"), ("version", "1.0")]);
+        assert!(s.accepts(LogLevel::Notice, &foo));
+        assert!(s.accepts(LogLevel::Error, &foo));
+        assert!(!s.accepts(LogLevel::Info, &foo));
+        assert!(!s.accepts(LogLevel::Error, field(&[("package", "baz")])));
+
+        // Records without the field are not forwarded
+        assert!(!s.accepts(LogLevel::Error, field(&[("submit", "00000000-0000-0000-0000-000000000000")])));
+    }
+
+    #[test]
+    fn test_level_order() {
+        assert!(LogLevel::Debug < LogLevel::Info);
+        assert!(LogLevel::Warning < LogLevel::Error);
+        assert_eq!(LogLevel::Error.severity(), 3);
+    }
+}
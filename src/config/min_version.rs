@@ -0,0 +1,67 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Minimum butido version required by a package repository
+//!
+//! The repository can declare `min_butido_version` in its `config.toml`. This is checked before
+//! the configuration is interpreted any further, so that an outdated butido refuses to operate on
+//! the repository with an upgrade hint rather than failing later with some obscure error.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+/// Check the `min_butido_version` setting of the repository configuration
+///
+/// `repo_config` must only contain the configuration from the repository, so that the setting
+/// cannot be overridden by the user configuration or the environment.
+pub fn check_min_butido_version(repo_config: &::config::Config) -> Result<()> {
+    let min_version = match repo_config.get_str("min_butido_version") {
+        Ok(v) => v,
+        Err(::config::ConfigError::NotFound(_)) => return Ok(()),
+        Err(e) => return Err(e).context("Reading 'min_butido_version' from repository configuration"),
+    };
+
+    let min_version = semver::Version::parse(&min_version)
+        .with_context(|| anyhow!("Parsing 'min_butido_version' = '{}' as semver version", min_version))?;
+    let crate_version = semver::Version::parse(env!("CARGO_PKG_VERSION"))
+        .context("Parsing version of crate (CARGO_PKG_VERSION) into semver::Version object")?;
+
+    check_version(&min_version, &crate_version)
+}
+
+fn check_version(min_version: &semver::Version, crate_version: &semver::Version) -> Result<()> {
+    if crate_version < min_version {
+        Err(anyhow!(
+            "This repository requires butido {} or newer, but this is butido {}. Please upgrade butido.",
+            min_version,
+            crate_version
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_version;
+
+    fn v(s: &str) -> semver::Version {
+        semver::Version::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert!(check_version(&v("0.3.0"), &v("0.3.0")).is_ok());
+        assert!(check_version(&v("0.2.5"), &v("0.3.0")).is_ok());
+        assert!(check_version(&v("0.3.1"), &v("0.3.0")).is_err());
+        assert!(check_version(&v("1.0.0"), &v("0.3.0")).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use serde::Deserialize;
+
+/// The simulated job runs of a "mock" endpoint
+///
+/// Mock endpoints do not run containers, they are meant for testing the scheduling of jobs (for
+/// example retries, failing jobs or cancelling a build) without a container engine.
+#[derive(Clone, Debug, Deserialize, Getters, CopyGetters)]
+pub struct MockConfig {
+    /// How long a simulated run takes at least (e.g. "2s")
+    #[serde(default = "default_min_duration")]
+    #[getset(get = "pub")]
+    min_duration: String,
+
+    /// How long a simulated run takes at most, the time is picked at random between the two,
+    /// `min_duration` if not set
+    #[serde(default)]
+    #[getset(get = "pub")]
+    max_duration: Option<String>,
+
+    /// The share of the runs whose script fails, between 0.0 and 1.0
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    failure_rate: f64,
+
+    /// The share of the containers which cannot be created (a failure of the endpoint instead of
+    /// the script), between 0.0 and 1.0
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    error_rate: f64,
+
+    /// The files a successful run produces, "{name}" and "{version}" are replaced with the name
+    /// and version of the package
+    #[serde(default = "default_artifacts")]
+    #[getset(get = "pub")]
+    artifacts: Vec<String>,
+}
+
+fn default_min_duration() -> String {
+    String::from("1s")
+}
+
+fn default_artifacts() -> Vec<String> {
+    vec![String::from("{name}-{version}.tar.gz")]
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            min_duration: default_min_duration(),
+            max_duration: None,
+            failure_rate: 0.0,
+            error_rate: 0.0,
+            artifacts: default_artifacts(),
+        }
+    }
+}
+
+impl MockConfig {
+    /// Check the durations, rates and artifact names
+    pub fn validate(&self) -> Result<()> {
+        for (key, rate) in [("failure_rate", self.failure_rate), ("error_rate", self.error_rate)].iter() {
+            if !(0.0..=1.0).contains(rate) {
+                return Err(anyhow!("mock.{} must be between 0.0 and 1.0, is {}", key, rate));
+            }
+        }
+
+        if let Some(name) = self.artifacts.iter().find(|a| !is_plain_file_name(a)) {
+            return Err(anyhow!("mock.artifacts must be plain file names, is '{}'", name));
+        }
+
+        self.durations().map(|_| ())
+    }
+
+    /// The shortest and the longest duration of a simulated run
+    pub fn durations(&self) -> Result<(Duration, Duration)> {
+        let min = parse_duration(&self.min_duration).context("Invalid mock.min_duration")?;
+        let max = self.max_duration
+            .as_deref()
+            .map(|d| parse_duration(d).context("Invalid mock.max_duration"))
+            .transpose()?
+            .unwrap_or(min);
+
+        if max < min {
+            return Err(anyhow!("mock.max_duration must not be shorter than mock.min_duration"));
+        }
+        Ok((min, max))
+    }
+
+    /// The names of the files a successful run of the package `name` in `version` produces
+    pub fn artifact_names(&self, name: &str, version: &str) -> Vec<String> {
+        self.artifacts
+            .iter()
+            .map(|a| a.replace("{name}", name).replace("{version}", version))
+            .collect()
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration> {
+    humantime::parse_duration(s).map_err(Error::from)
+}
+
+/// Whether `name` is the name of a file in the outputs directory, not a path
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock(min: &str, max: Option<&str>, failure_rate: f64, artifacts: &[&str]) -> MockConfig {
+        MockConfig {
+            min_duration: String::from(min),
+            max_duration: max.map(String::from),
+            failure_rate,
+            error_rate: 0.0,
+            artifacts: artifacts.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(MockConfig::default().validate().is_ok());
+        assert!(mock("0s", Some("10s"), 0.5, &["a.tar"]).validate().is_ok());
+        assert!(mock("1s", None, 1.5, &["a.tar"]).validate().is_err());
+        assert!(mock("1s", None, -0.1, &["a.tar"]).validate().is_err());
+        assert!(mock("10s", Some("1s"), 0.0, &["a.tar"]).validate().is_err());
+        assert!(mock("1x", None, 0.0, &["a.tar"]).validate().is_err());
+        assert!(mock("1s", None, 0.0, &["../a.tar"]).validate().is_err());
+        assert!(mock("1s", None, 0.0, &[".."]).validate().is_err());
+    }
+
+    #[test]
+    fn test_durations() {
+        let (min, max) = mock("1s", Some("1m"), 0.0, &[]).durations().unwrap();
+        assert_eq!(min, Duration::from_secs(1));
+        assert_eq!(max, Duration::from_secs(60));
+
+        let (min, max) = mock("2s", None, 0.0, &[]).durations().unwrap();
+        assert_eq!(min, max);
+    }
+
+    #[test]
+    fn test_artifact_names() {
+        let m = mock("1s", None, 0.0, &["{name}-{version}.tar.gz", "{name}.log"]);
+        assert_eq!(m.artifact_names("foo", "1.0"), vec!["foo-1.0.tar.gz", "foo.log"]);
+    }
+}
@@ -20,6 +20,18 @@
 //! that is not possible to do with TOML itself.
 //!
 
+mod artifact_owner;
+pub use artifact_owner::*;
+
+mod build_limits;
+pub use build_limits::*;
+
+mod build_window;
+pub use build_window::*;
+
+mod cli_defaults;
+pub use cli_defaults::*;
+
 mod configuration;
 pub use configuration::*;
 
@@ -32,7 +44,46 @@ pub use docker_config::*;
 mod endpoint_config;
 pub use endpoint_config::*;
 
+mod image_defaults;
+pub use image_defaults::*;
+
+mod log_sink_config;
+pub use log_sink_config::*;
+
+mod min_version;
+pub use min_version::*;
+
+mod mock_config;
+pub use mock_config::*;
+
 mod not_validated;
 pub use not_validated::*;
 
+mod notification_config;
+pub use notification_config::*;
+
+mod package_repository_config;
+pub use package_repository_config::*;
+
+mod remote_store_config;
+pub use remote_store_config::*;
+
+mod retry_policy;
+pub use retry_policy::*;
+
+mod reuse_policy;
+pub use reuse_policy::*;
+
+mod scratch_config;
+pub use scratch_config::*;
+
+mod secret_config;
+pub use secret_config::*;
+
+mod security_profile;
+pub use security_profile::*;
+
+mod signing_config;
+pub use signing_config::*;
+
 mod util;
@@ -13,12 +13,29 @@ use anyhow::Context;
 use anyhow::Result;
 use getset::Getters;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use crate::config::util::*;
+use crate::config::ArtifactOwner;
+use crate::config::BuildLimits;
+use crate::config::BuildWindow;
+use crate::config::CliDefaults;
 use crate::config::Configuration;
 use crate::config::ContainerConfig;
 use crate::config::DockerConfig;
+use crate::config::EndpointType;
+use crate::config::LogSinkConfig;
+use crate::config::NotificationConfig;
+use crate::config::PackageRepositoryConfig;
+use crate::config::RemoteStoreConfig;
+use crate::config::RetryPolicy;
+use crate::config::ReusePolicy;
+use crate::config::SecretConfig;
+use crate::config::SigningConfig;
+use crate::config::validate_package_repositories;
+use crate::config::validate_secrets;
+use crate::package::HashType;
 use crate::package::PhaseName;
 
 /// The configuration that is loaded from the filesystem
@@ -65,6 +82,13 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     build_error_lines: usize,
 
+    /// The maximum length (in bytes) of a log line from a container
+    ///
+    /// Longer lines are truncated before they are stored
+    #[serde(default = "default_log_max_line_length")]
+    #[getset(get = "pub")]
+    log_max_line_length: usize,
+
     /// The theme used to highlight scripts when printing them to the CLI
     #[getset(get = "pub")]
     script_highlight_theme: Option<String>,
@@ -95,6 +119,47 @@ pub struct NotValidatedConfiguration {
     #[getset(get = "pub")]
     staging_directory: PathBuf,
 
+    /// Artifacts (relative to the staging directory) which are allowed to keep
+    /// setuid/setgid/sticky bits when they are copied out of the container
+    #[serde(default)]
+    #[getset(get = "pub")]
+    artifact_setuid_whitelist: Vec<PathBuf>,
+
+    /// The owner (uid and gid) the files of artifacts are changed to when they are copied out of the
+    /// container
+    #[serde(default)]
+    #[getset(get = "pub")]
+    artifact_owner: ArtifactOwner,
+
+    /// The algorithm the checksums of artifacts are computed with
+    #[serde(default = "default_artifact_checksum_algorithm")]
+    #[getset(get = "pub")]
+    artifact_checksum_algorithm: HashType,
+
+    /// How released artifacts are signed, if they are signed at all
+    #[getset(get = "pub")]
+    release_signing: Option<SigningConfig>,
+
+    /// Additional package repositories, by precedence
+    ///
+    /// The packages of the repository butido runs in take precedence over the packages of these.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    package_repositories: Vec<PackageRepositoryConfig>,
+
+    /// The secrets packages can use during their build, by name
+    #[serde(default)]
+    #[getset(get = "pub")]
+    secrets: HashMap<String, SecretConfig>,
+
+    /// The object storage artifacts can be pushed to and pulled from, if there is one
+    #[getset(get = "pub")]
+    remote_store: Option<RemoteStoreConfig>,
+
+    /// How many days before they expire secrets and certificates are warned about
+    #[getset(get = "pub")]
+    expiry_warning_days: Option<u32>,
+
     /// Where the sources are cached
     #[serde(rename = "source_cache")]
     #[getset(get = "pub")]
@@ -130,13 +195,55 @@ pub struct NotValidatedConfiguration {
     #[serde(rename = "database_connection_timeout")]
     database_connection_timeout: Option<u16>,
 
+    /// The maximum number of database connections a build uses at the same time
+    #[getset(get = "pub")]
+    database_pool_size: Option<u32>,
+
     #[getset(get = "pub")]
     docker: DockerConfig,
 
+    /// Submits with at least this many jobs are only started within one of the `build_windows`
+    #[getset(get = "pub")]
+    heavy_submit_jobs: Option<usize>,
+
+    /// The time windows in which heavy submits may be started
+    #[serde(default)]
+    #[getset(get = "pub")]
+    build_windows: Vec<BuildWindow>,
+
+    /// How failed jobs are retried, if the package does not configure it. Not at all if not set
+    #[getset(get = "pub")]
+    retry: Option<RetryPolicy>,
+
+    /// Which artifacts may be reused instead of building the package again
+    #[serde(default)]
+    #[getset(get = "pub")]
+    reuse_policy: ReusePolicy,
+
+    /// Resource limits for the containers, if the package does not configure them
+    #[getset(get = "pub")]
+    build: Option<BuildLimits>,
+
+    /// Where events of submits are sent to
+    #[serde(default)]
+    #[getset(get = "pub")]
+    notifications: NotificationConfig,
+
+    /// Where the logs of jobs and the events of submits are forwarded to, in addition to the log
+    /// files and the database
+    #[serde(default)]
+    #[getset(get = "pub")]
+    log_sinks: Vec<LogSinkConfig>,
+
     /// The configuration for the containers
     #[getset(get = "pub")]
     containers: ContainerConfig,
 
+    /// Default command line arguments, by subcommand
+    #[serde(default)]
+    #[getset(get = "pub")]
+    defaults: CliDefaults,
+
     /// The names of the phases which should be compiled into the packaging script
     #[getset(get = "pub")]
     available_phases: Vec<PhaseName>,
@@ -192,6 +299,107 @@ impl NotValidatedConfiguration {
             return Err(anyhow!("No phases configured"));
         }
 
+        // Error if a phase is configured more than once
+        let _ = crate::package::phase_order(&self.available_phases, &[])?;
+
+        if self.log_max_line_length == 0 {
+            return Err(anyhow!("log_max_line_length must be greater than zero"));
+        }
+
+        // The commit id of a git archive is not a checksum of the artifact
+        if let HashType::Git = self.artifact_checksum_algorithm {
+            return Err(anyhow!("artifact_checksum_algorithm must be one of sha1, sha256, sha512 or blake3"));
+        }
+
+        if let Some(heavy_submit_jobs) = self.heavy_submit_jobs {
+            if heavy_submit_jobs == 0 {
+                return Err(anyhow!("heavy_submit_jobs must be greater than zero"));
+            }
+
+            if self.build_windows.is_empty() {
+                return Err(anyhow!("heavy_submit_jobs is set, but there are no build_windows configured"));
+            }
+        }
+
+        if let Some(retry) = self.retry.as_ref() {
+            retry.validate().context("Checking retry policy")?;
+        }
+
+        if let Some(build) = self.build.as_ref() {
+            build.validate().context("Checking build limits")?;
+        }
+
+        for (name, endpoint) in self.docker.endpoints().iter() {
+            if let Some(image) = endpoint.images().iter().find(|img| !self.docker.images().contains(img)) {
+                return Err(anyhow!("Endpoint {} lists image {}, which is not in the configured images", name, image));
+            }
+
+            // Podman reports its own version instead of a docker version, which never matches
+            if *endpoint.endpoint_type() == EndpointType::Podman && self.docker.docker_versions().is_some() {
+                return Err(anyhow!("Endpoint {} is a podman endpoint, which cannot be checked against the configured docker_versions", name));
+            }
+
+            if let Some(scratch) = endpoint.scratch().as_ref() {
+                scratch
+                    .validate()
+                    .with_context(|| anyhow!("Checking scratch space of endpoint {}", name))?;
+            }
+
+            if let Some(security) = endpoint.security().as_ref() {
+                security
+                    .validate()
+                    .with_context(|| anyhow!("Checking security settings of endpoint {}", name))?;
+            }
+
+            if let Some(mock) = endpoint.mock().as_ref() {
+                mock.validate()
+                    .with_context(|| anyhow!("Checking mock settings of endpoint {}", name))?;
+            }
+        }
+
+        for (image, digest) in self.docker.pinned_images().iter() {
+            if !self.docker.images().contains(image) {
+                return Err(anyhow!("Image {} is pinned, but not in the configured images", image));
+            }
+
+            let valid = digest
+                .split_once(':')
+                .map(|(algo, hex)| !algo.is_empty() && !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                .unwrap_or(false);
+            if !valid {
+                return Err(anyhow!("Image {} is pinned to '{}', which is not a digest like 'sha256:<hex>'", image, digest));
+            }
+        }
+
+        for (image, defaults) in self.docker.image_defaults().iter() {
+            if let Some(security) = defaults.security().as_ref() {
+                security
+                    .validate()
+                    .with_context(|| anyhow!("Checking security settings of image {}", image))?;
+            }
+        }
+
+        if let Some(signing) = self.release_signing.as_ref() {
+            signing.validate().context("Checking release signing configuration")?;
+        }
+
+        for (i, sink) in self.log_sinks.iter().enumerate() {
+            sink.validate().with_context(|| anyhow!("Checking log sink {}", i + 1))?;
+        }
+
+        self.defaults.validate().context("Checking default command line arguments")?;
+
+        validate_package_repositories(&self.package_repositories)
+            .context("Checking package repositories")?;
+
+        self.reuse_policy.validate().context("Checking reuse policy")?;
+
+        validate_secrets(&self.secrets).context("Checking secrets")?;
+
+        if let Some(remote_store) = self.remote_store.as_ref() {
+            remote_store.validate().context("Checking remote store configuration")?;
+        }
+
         // Error if script highlighting theme is not valid
         if let Some(configured_theme) = self.script_highlight_theme.as_ref() {
             let allowed_theme_present = [
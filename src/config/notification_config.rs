@@ -0,0 +1,88 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+
+use getset::Getters;
+use serde::Deserialize;
+use url::Url;
+
+/// Where events of submits (started, job failed, finished) are sent to
+#[derive(Clone, Debug, Default, Deserialize, Getters)]
+pub struct NotificationConfig {
+    /// The targets all events are sent to
+    #[serde(flatten)]
+    #[getset(get = "pub")]
+    targets: NotificationTargets,
+
+    /// The targets failed jobs are sent to in addition, by the maintainer of the package
+    #[serde(default)]
+    #[getset(get = "pub")]
+    maintainers: HashMap<String, NotificationTargets>,
+}
+
+/// A set of webhooks, chat rooms and mail addresses events are sent to
+#[derive(Clone, Debug, Default, Deserialize, Getters)]
+pub struct NotificationTargets {
+    /// URLs the events are POSTed to, as JSON
+    #[serde(default)]
+    #[getset(get = "pub")]
+    webhooks: Vec<Url>,
+
+    /// Slack incoming webhook URLs a summary of the events is sent to
+    #[serde(default)]
+    #[getset(get = "pub")]
+    slack: Vec<Url>,
+
+    /// Matrix rooms a summary of the events is sent to
+    #[serde(default)]
+    #[getset(get = "pub")]
+    matrix: Vec<MatrixRoom>,
+
+    /// Mail addresses a summary of the events is sent to, via `sendmail`
+    #[serde(default)]
+    #[getset(get = "pub")]
+    email: Vec<String>,
+}
+
+/// A Matrix room and the credentials to post to it
+#[derive(Clone, Debug, Deserialize, Getters)]
+pub struct MatrixRoom {
+    /// The URL of the homeserver, e.g. "https://matrix.example.com"
+    #[getset(get = "pub")]
+    homeserver: Url,
+
+    /// The ID of the room, e.g. "!abcdef:example.com"
+    #[getset(get = "pub")]
+    room_id: String,
+
+    /// The access token of the user the messages are sent as
+    #[getset(get = "pub")]
+    access_token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintainer_targets() {
+        let config: NotificationConfig = toml::from_str(r#"
+            email = [ "builds@example.com" ]
+
+            [maintainers.team-x]
+            email = [ "team-x@example.com" ]
+        "#).unwrap();
+
+        assert_eq!(config.targets().email(), &[String::from("builds@example.com")]);
+        assert_eq!(config.maintainers()["team-x"].email(), &[String::from("team-x@example.com")]);
+        assert!(config.maintainers()["team-x"].webhooks().is_empty());
+    }
+}
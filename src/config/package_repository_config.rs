@@ -0,0 +1,81 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::Getters;
+use serde::Deserialize;
+
+use crate::package::PackageName;
+
+/// The name the package repository butido runs in is known by, e.g. as origin of its packages
+pub const MAIN_PACKAGE_REPOSITORY: &str = "main";
+
+/// An additional package repository, whose packages are loaded below the packages of the
+/// repository butido runs in
+#[derive(Clone, Debug, Deserialize, Getters)]
+pub struct PackageRepositoryConfig {
+    /// The name of the repository, shown as origin of its packages
+    #[getset(get = "pub")]
+    name: String,
+
+    /// The path of the repository inside the repository butido runs in, e.g. a git submodule
+    ///
+    /// The paths of the patches of its packages are relative to the repository butido runs in as
+    /// well, so that they can be found when building.
+    #[getset(get = "pub")]
+    path: PathBuf,
+
+    /// The packages of this repository that are meant to be replaced by packages of the same
+    /// name and version in repositories with higher precedence
+    ///
+    /// Shadowing other packages of this repository is reported by `butido lint`.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    allow_shadowing: Vec<PackageName>,
+}
+
+impl PackageRepositoryConfig {
+    /// The path of the repository, with relative paths resolved against `repo_root`
+    pub fn path_in(&self, repo_root: &Path) -> PathBuf {
+        repo_root.join(&self.path)
+    }
+}
+
+/// Check that the additional package repositories are inside the repository butido runs in and
+/// that their names are unique
+pub fn validate_package_repositories(repositories: &[PackageRepositoryConfig]) -> Result<()> {
+    for (i, repository) in repositories.iter().enumerate() {
+        if repository.name.is_empty() {
+            return Err(anyhow!("The name of the package repository at {} is empty", repository.path.display()));
+        }
+
+        let inside_repository = repository.path.components().count() > 0
+            && repository.path.components().all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !inside_repository {
+            return Err(anyhow!("The package repository '{}' must be a directory inside the repository butido runs in, not {}",
+                repository.name,
+                repository.path.display()));
+        }
+
+        if repository.name == MAIN_PACKAGE_REPOSITORY {
+            return Err(anyhow!("The name '{}' is reserved for the repository butido runs in", MAIN_PACKAGE_REPOSITORY));
+        }
+
+        if repositories[..i].iter().any(|other| other.name == repository.name) {
+            return Err(anyhow!("The package repository '{}' is configured more than once", repository.name));
+        }
+    }
+
+    Ok(())
+}
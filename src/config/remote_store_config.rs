@@ -0,0 +1,79 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::Getters;
+use serde::Deserialize;
+use url::Url;
+
+/// An S3 compatible object storage (e.g. MinIO) artifacts are pushed to and pulled from
+#[derive(Clone, Debug, Deserialize, Getters)]
+pub struct RemoteStoreConfig {
+    /// The URL of the storage, e.g. "https://minio.example.com:9000"
+    ///
+    /// Buckets are addressed path-style, so the URL must not contain a path.
+    #[getset(get = "pub")]
+    endpoint: Url,
+
+    /// The bucket the artifacts are stored in
+    #[getset(get = "pub")]
+    bucket: String,
+
+    /// The region of the bucket
+    #[serde(default = "default_region")]
+    #[getset(get = "pub")]
+    region: String,
+
+    /// The prefix of all keys written by butido, if the bucket is shared
+    #[getset(get = "pub")]
+    prefix: Option<String>,
+
+    /// The environment variable the access key is read from
+    #[serde(default = "default_access_key_env")]
+    #[getset(get = "pub")]
+    access_key_env: String,
+
+    /// The environment variable the secret key is read from
+    #[serde(default = "default_secret_key_env")]
+    #[getset(get = "pub")]
+    secret_key_env: String,
+}
+
+fn default_region() -> String {
+    String::from("us-east-1")
+}
+
+fn default_access_key_env() -> String {
+    String::from("AWS_ACCESS_KEY_ID")
+}
+
+fn default_secret_key_env() -> String {
+    String::from("AWS_SECRET_ACCESS_KEY")
+}
+
+impl RemoteStoreConfig {
+    /// Check that the configuration is complete
+    pub fn validate(&self) -> Result<()> {
+        if self.bucket.is_empty() {
+            return Err(anyhow!("remote_store.bucket must not be empty"));
+        }
+
+        if self.endpoint.host_str().is_none() {
+            return Err(anyhow!("remote_store.endpoint has no host: {}", self.endpoint));
+        }
+
+        if self.endpoint.path() != "/" || self.endpoint.query().is_some() {
+            return Err(anyhow!("remote_store.endpoint must not contain a path: {}", self.endpoint));
+        }
+
+        Ok(())
+    }
+}
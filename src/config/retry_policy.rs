@@ -0,0 +1,106 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// How failed jobs are retried
+///
+/// Can be configured globally and per package, the setting of the package wins.
+#[derive(Clone, Debug, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct RetryPolicy {
+    /// How often a failed job is retried
+    #[getset(get_copy = "pub")]
+    retries: u32,
+
+    /// How long to wait before the first retry, doubled for every further retry
+    #[serde(default)]
+    backoff_seconds: u64,
+
+    /// Regular expressions matched against the log of a failed job (or the error, if the job
+    /// failed before its script was run)
+    ///
+    /// If not empty, a job is only retried if one of them matches.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    retry_on: Vec<String>,
+}
+
+impl RetryPolicy {
+    /// Check the `retry_on` patterns
+    pub fn validate(&self) -> Result<()> {
+        self.patterns().map(|_| ())
+    }
+
+    fn patterns(&self) -> Result<Vec<Regex>> {
+        self.retry_on
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| anyhow!("Invalid pattern in retry_on: '{}': {}", p, e)))
+            .collect()
+    }
+
+    /// Whether a job that failed with `output` (its log or error) should be retried
+    pub fn should_retry(&self, output: &str) -> Result<bool> {
+        if self.retry_on.is_empty() {
+            return Ok(true);
+        }
+
+        Ok(self.patterns()?.iter().any(|re| re.is_match(output)))
+    }
+
+    /// How long to wait before the retry number `retry` (starting at 1)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u64.saturating_pow(retry.saturating_sub(1));
+        Duration::from_secs(self.backoff_seconds.saturating_mul(factor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(retry_on: &[&str]) -> RetryPolicy {
+        RetryPolicy {
+            retries: 3,
+            backoff_seconds: 10,
+            retry_on: retry_on.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let p = policy(&[]);
+        assert_eq!(p.backoff(1), Duration::from_secs(10));
+        assert_eq!(p.backoff(2), Duration::from_secs(20));
+        assert_eq!(p.backoff(3), Duration::from_secs(40));
+    }
+
+    #[test]
+    fn test_should_retry() {
+        assert!(policy(&[]).should_retry("anything").unwrap());
+
+        let p = policy(&["Killed", "Connection (reset|refused)"]);
+        assert!(p.should_retry("make: *** [all] Killed").unwrap());
+        assert!(p.should_retry("curl: Connection reset by peer").unwrap());
+        assert!(!p.should_retry("error: undefined reference to `foo'").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(policy(&["("]).validate().is_err());
+    }
+}
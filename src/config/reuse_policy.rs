@@ -0,0 +1,148 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Which artifacts may be reused instead of building a package again
+///
+/// All conditions that are set must hold for an artifact to be reused. By default, every
+/// artifact that matches the job is reused.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct ReusePolicy {
+    /// Only reuse artifacts that were released to one of the release stores
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    release_only: bool,
+
+    /// If not empty, only reuse artifacts from submits by one of these users
+    #[serde(default)]
+    #[getset(get = "pub")]
+    submitted_by: Vec<String>,
+
+    /// Only reuse artifacts that were built without network access
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    hermetic_only: bool,
+
+    /// Only reuse artifacts that were released with a signature
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    signed_only: bool,
+
+    /// Only reuse artifacts that were built at most this long ago (e.g. "30d")
+    ///
+    /// Packages can override this with their `reuse_max_age`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_age: Option<String>,
+}
+
+impl ReusePolicy {
+    /// The policy that allows reusing every artifact
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    pub fn with_release_only(mut self, release_only: bool) -> Self {
+        self.release_only |= release_only;
+        self
+    }
+
+    pub fn with_submitted_by(mut self, users: Vec<String>) -> Self {
+        if !users.is_empty() {
+            self.submitted_by = users;
+        }
+        self
+    }
+
+    pub fn with_hermetic_only(mut self, hermetic_only: bool) -> Self {
+        self.hermetic_only |= hermetic_only;
+        self
+    }
+
+    pub fn with_signed_only(mut self, signed_only: bool) -> Self {
+        self.signed_only |= signed_only;
+        self
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.max_age_for(None).map(|_| ())
+    }
+
+    /// The maximum age of the artifacts of a package that are reused
+    ///
+    /// The `reuse_max_age` of the package wins over the `max_age` of the policy.
+    pub fn max_age_for(&self, package_max_age: Option<&str>) -> Result<Option<Duration>> {
+        package_max_age
+            .or(self.max_age.as_deref())
+            .map(|age| humantime::parse_duration(age).map_err(|e| anyhow!("Invalid maximum age '{}': {}", age, e)))
+            .transpose()
+    }
+
+    /// Whether an artifact may be reused, given whether it was released and whether the release
+    /// was signed
+    pub fn allows_release(&self, released: bool, signed: bool) -> bool {
+        (released || !(self.release_only || self.signed_only)) && (signed || !self.signed_only)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_allows_everything() {
+        let p = ReusePolicy::default();
+        assert!(p.allows_release(false, false));
+        assert!(p.allows_release(true, false));
+        assert!(p.allows_release(true, true));
+    }
+
+    #[test]
+    fn test_release_only() {
+        let p = ReusePolicy::any().with_release_only(true);
+        assert!(!p.allows_release(false, false));
+        assert!(p.allows_release(true, false));
+    }
+
+    #[test]
+    fn test_signed_only() {
+        let p = ReusePolicy::any().with_signed_only(true);
+        assert!(!p.allows_release(false, false));
+        assert!(!p.allows_release(true, false));
+        assert!(p.allows_release(true, true));
+    }
+
+    #[test]
+    fn test_overrides_tighten() {
+        let p = ReusePolicy::any()
+            .with_release_only(true)
+            .with_release_only(false)
+            .with_submitted_by(vec![String::from("alice")])
+            .with_submitted_by(vec![]);
+        assert!(p.release_only());
+        assert_eq!(p.submitted_by(), &[String::from("alice")]);
+    }
+
+    #[test]
+    fn test_max_age_package_overrides() {
+        let p = ReusePolicy { max_age: Some(String::from("30d")), ..ReusePolicy::default() };
+        assert_eq!(p.max_age_for(None).unwrap(), Some(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(p.max_age_for(Some("1h")).unwrap(), Some(Duration::from_secs(60 * 60)));
+        assert_eq!(ReusePolicy::any().max_age_for(None).unwrap(), None);
+        assert!(ReusePolicy::any().max_age_for(Some("30 days ago")).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+use serde::Deserialize;
+
+/// Scratch space for the working directories of the jobs on an endpoint
+///
+/// Every job gets a directory `<root>/<job uuid>` on the endpoint host, which is mounted into its
+/// container as working directory. The directory is removed after the artifacts of the job were
+/// copied out of the container.
+#[derive(Clone, Debug, Deserialize, Getters, CopyGetters)]
+pub struct ScratchConfig {
+    /// The directory on the endpoint host the working directories are created in
+    #[getset(get = "pub")]
+    root: PathBuf,
+
+    /// The space the working directory of a single job may use, in bytes or with a suffix "k",
+    /// "m", "g" or "t" (powers of 1024)
+    ///
+    /// Checked after the script of the job ran, a job which used more space fails.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    quota: Option<String>,
+
+    /// How many hours the working directories of failed jobs are kept for inspection
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    keep_failed_hours: u64,
+
+    /// The image of the helper containers which manage the scratch space on docker and podman
+    /// endpoints, which have to be able to run `sh`, `du`, `stat` and `rm`
+    #[serde(default = "default_image")]
+    #[getset(get = "pub")]
+    image: String,
+}
+
+fn default_image() -> String {
+    String::from("busybox:latest")
+}
+
+impl ScratchConfig {
+    /// Check that the root is absolute and the quota is valid
+    pub fn validate(&self) -> Result<()> {
+        if !self.root.is_absolute() {
+            return Err(anyhow!("scratch.root must be an absolute path: {}", self.root.display()));
+        }
+
+        self.quota_bytes().map(|_| ())
+    }
+
+    /// The quota in bytes
+    pub fn quota_bytes(&self) -> Result<Option<u64>> {
+        self.quota
+            .as_deref()
+            .map(|q| crate::config::parse_size(q).context("Invalid scratch.quota"))
+            .transpose()
+    }
+
+    /// The working directory of the job with the UUID `job` on the endpoint host
+    pub fn job_dir(&self, job: &uuid::Uuid) -> PathBuf {
+        self.root.join(job.to_string())
+    }
+
+    /// Whether the working directory of a failed job which was last modified `age` ago may be
+    /// removed
+    pub fn may_remove_failed(&self, age: chrono::Duration) -> bool {
+        age >= chrono::Duration::hours(self.keep_failed_hours as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(root: &str, quota: Option<&str>, keep_failed_hours: u64) -> ScratchConfig {
+        ScratchConfig {
+            root: PathBuf::from(root),
+            quota: quota.map(String::from),
+            keep_failed_hours,
+            image: default_image(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(scratch("/var/lib/butido", Some("20g"), 0).validate().is_ok());
+        assert!(scratch("/var/lib/butido", None, 0).validate().is_ok());
+        assert!(scratch("var/lib/butido", None, 0).validate().is_err());
+        assert!(scratch("/var/lib/butido", Some("20x"), 0).validate().is_err());
+    }
+
+    #[test]
+    fn test_quota_bytes() {
+        assert_eq!(scratch("/scratch", Some("20g"), 0).quota_bytes().unwrap(), Some(20 * 1024 * 1024 * 1024));
+        assert_eq!(scratch("/scratch", None, 0).quota_bytes().unwrap(), None);
+    }
+
+    #[test]
+    fn test_may_remove_failed() {
+        let s = scratch("/scratch", None, 24);
+        assert!(!s.may_remove_failed(chrono::Duration::hours(23)));
+        assert!(s.may_remove_failed(chrono::Duration::hours(24)));
+        assert!(scratch("/scratch", None, 0).may_remove_failed(chrono::Duration::zero()));
+    }
+}
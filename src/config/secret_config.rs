@@ -0,0 +1,129 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::job::SecretValue;
+
+/// Where the value of a secret is fetched from when a job that needs it is started
+///
+/// Exactly one of the settings has to be set. A trailing newline of the value is removed.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SecretConfig {
+    /// Read the value from this environment variable of butido
+    env: Option<String>,
+
+    /// Read the value from this file
+    file: Option<PathBuf>,
+
+    /// Use the output of this command, which is run with `sh -c`
+    command: Option<String>,
+}
+
+impl SecretConfig {
+    /// Fetch the value of the secret
+    pub async fn fetch(&self) -> Result<SecretValue> {
+        let mut value = match (self.env.as_ref(), self.file.as_ref(), self.command.as_ref()) {
+            (Some(env), None, None) => std::env::var(env)
+                .with_context(|| anyhow!("Reading environment variable {}", env))?,
+
+            (None, Some(file), None) => tokio::fs::read_to_string(file)
+                .await
+                .with_context(|| anyhow!("Reading {}", file.display()))?,
+
+            (None, None, Some(command)) => {
+                let output = tokio::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::inherit())
+                    .output()
+                    .await
+                    .with_context(|| anyhow!("Running '{}'", command))?;
+
+                if !output.status.success() {
+                    return Err(anyhow!("'{}' failed: {}", command, output.status));
+                }
+
+                String::from_utf8(output.stdout)
+                    .with_context(|| anyhow!("Output of '{}' is not UTF-8", command))?
+            }
+
+            _ => return Err(anyhow!("Exactly one of env, file and command has to be set")),
+        };
+
+        let len = value.trim_end_matches(&['\r', '\n'][..]).len();
+        value.truncate(len);
+        Ok(SecretValue::new(value))
+    }
+
+    /// Check that exactly one source is set
+    pub fn validate(&self) -> Result<()> {
+        let sources = [self.env.is_some(), self.file.is_some(), self.command.is_some()];
+        if sources.iter().filter(|set| **set).count() != 1 {
+            return Err(anyhow!("Exactly one of env, file and command has to be set"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Check the names of the secrets and that each of them has exactly one source
+pub fn validate_secrets(secrets: &HashMap<String, SecretConfig>) -> Result<()> {
+    for (name, secret) in secrets.iter() {
+        // The name is used as file name in the container
+        let valid_name = !name.is_empty()
+            && !name.starts_with('.')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+        if !valid_name {
+            return Err(anyhow!("Invalid secret name '{}', only letters, digits, '_', '-' and '.' are allowed", name));
+        }
+
+        secret.validate().with_context(|| anyhow!("Checking secret '{}'", name))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secrets(toml: &str) -> HashMap<String, SecretConfig> {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_validate_secrets() {
+        assert!(validate_secrets(&secrets(r#"
+            token = { env = "TOKEN" }
+            license-cert = { file = "/etc/license.pem" }
+            key = { command = "pass show key" }
+        "#)).is_ok());
+
+        assert!(validate_secrets(&secrets(r#"token = { }"#)).is_err());
+        assert!(validate_secrets(&secrets(r#"token = { env = "TOKEN", file = "/token" }"#)).is_err());
+        assert!(validate_secrets(&secrets(r#"".." = { env = "TOKEN" }"#)).is_err());
+        assert!(validate_secrets(&secrets(r#""a/b" = { env = "TOKEN" }"#)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_command() {
+        let secrets = secrets(r#"token = { command = "echo hunter2" }"#);
+        let value = secrets.get("token").unwrap().fetch().await.unwrap();
+        assert_eq!(value.expose(), "hunter2");
+    }
+}
@@ -0,0 +1,129 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::Getters;
+use serde::Deserialize;
+
+/// Security settings for the containers jobs are run in
+///
+/// Can be configured per endpoint and per image, the settings of the image win.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Getters)]
+pub struct SecurityProfile {
+    /// The seccomp profile (a JSON file on the host butido runs on) the containers are run with
+    #[serde(default)]
+    #[getset(get = "pub")]
+    seccomp_profile: Option<PathBuf>,
+
+    /// The AppArmor profile (loaded on the endpoint host) the containers are run with
+    #[serde(default)]
+    #[getset(get = "pub")]
+    apparmor_profile: Option<String>,
+
+    /// The user the containers are run as, as "uid" or "uid:gid"
+    #[serde(default)]
+    #[getset(get = "pub")]
+    user: Option<String>,
+
+    /// Whether the root filesystem of the containers is mounted read-only
+    ///
+    /// The directories butido copies files to and from are mounted as volumes, so they stay
+    /// writable.
+    #[serde(default)]
+    read_only_rootfs: Option<bool>,
+}
+
+impl SecurityProfile {
+    /// Check that the seccomp profile exists and the user is numeric
+    pub fn validate(&self) -> Result<()> {
+        if let Some(seccomp) = self.seccomp_profile.as_ref() {
+            if !seccomp.is_file() {
+                return Err(anyhow!("Seccomp profile does not exist: {}", seccomp.display()));
+            }
+        }
+
+        if let Some(user) = self.user.as_ref() {
+            let is_id = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+            let mut split = user.splitn(2, ':');
+            let valid = split.next().map(is_id).unwrap_or(false) && split.next().map(is_id).unwrap_or(true);
+            if !valid {
+                return Err(anyhow!("User must be given as \"uid\" or \"uid:gid\": '{}'", user));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the root filesystem of the containers is mounted read-only
+    pub fn read_only_rootfs(&self) -> bool {
+        self.read_only_rootfs.unwrap_or(false)
+    }
+
+    /// Whether no setting is made
+    pub fn is_empty(&self) -> bool {
+        self.seccomp_profile.is_none()
+            && self.apparmor_profile.is_none()
+            && self.user.is_none()
+            && self.read_only_rootfs.is_none()
+    }
+
+    /// The settings of `self`, with the settings which are not made taken from `fallback`
+    pub fn or(&self, fallback: Option<&SecurityProfile>) -> SecurityProfile {
+        SecurityProfile {
+            seccomp_profile: self.seccomp_profile.clone().or_else(|| fallback.and_then(|f| f.seccomp_profile.clone())),
+            apparmor_profile: self.apparmor_profile.clone().or_else(|| fallback.and_then(|f| f.apparmor_profile.clone())),
+            user: self.user.clone().or_else(|| fallback.and_then(|f| f.user.clone())),
+            read_only_rootfs: self.read_only_rootfs.or_else(|| fallback.and_then(|f| f.read_only_rootfs)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(user: &str) -> SecurityProfile {
+        SecurityProfile { user: Some(String::from(user)), ..SecurityProfile::default() }
+    }
+
+    #[test]
+    fn test_validate_user() {
+        assert!(user("1000").validate().is_ok());
+        assert!(user("1000:1000").validate().is_ok());
+        assert!(user("builder").validate().is_err());
+        assert!(user("1000:").validate().is_err());
+        assert!(user(":1000").validate().is_err());
+        assert!(user("1000:1000:1000").validate().is_err());
+    }
+
+    #[test]
+    fn test_image_settings_win() {
+        let endpoint = SecurityProfile {
+            apparmor_profile: Some(String::from("docker-default")),
+            user: Some(String::from("1000")),
+            read_only_rootfs: Some(true),
+            ..SecurityProfile::default()
+        };
+        let image = SecurityProfile {
+            user: Some(String::from("2000")),
+            read_only_rootfs: Some(false),
+            ..SecurityProfile::default()
+        };
+
+        let profile = image.or(Some(&endpoint));
+        assert_eq!(profile.apparmor_profile().as_deref(), Some("docker-default"));
+        assert_eq!(profile.user().as_deref(), Some("2000"));
+        assert!(!profile.read_only_rootfs());
+        assert!(SecurityProfile::default().or(None).is_empty());
+    }
+}
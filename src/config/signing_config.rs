@@ -0,0 +1,84 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use getset::Getters;
+use serde::Deserialize;
+
+use crate::config::SecretConfig;
+
+/// The tool released artifacts are signed with
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Debug, Eq, PartialEq, Deserialize)]
+pub enum SigningTool {
+    #[serde(rename = "gpg")]
+    #[display("gpg")]
+    Gpg,
+
+    #[serde(rename = "minisign")]
+    #[display("minisign")]
+    Minisign,
+}
+
+impl SigningTool {
+    /// The file extension of the detached signatures this tool creates
+    pub fn signature_extension(&self) -> &'static str {
+        match self {
+            SigningTool::Gpg => "asc",
+            SigningTool::Minisign => "minisig",
+        }
+    }
+}
+
+/// How released artifacts are signed
+#[derive(Clone, Debug, Deserialize, Getters)]
+pub struct SigningConfig {
+    /// The tool used for signing
+    #[getset(get = "pub")]
+    tool: SigningTool,
+
+    /// The key to sign with
+    ///
+    /// For gpg, this is the ID or fingerprint of the key, for minisign the path to the secret key.
+    #[getset(get = "pub")]
+    key: String,
+
+    /// The path to the public key, needed to verify minisign signatures
+    #[getset(get = "pub")]
+    public_key: Option<PathBuf>,
+
+    /// Where the password of the key is read from, if the key is encrypted
+    ///
+    /// The password is passed to the tool on stdin.
+    #[getset(get = "pub")]
+    password: Option<SecretConfig>,
+}
+
+impl SigningConfig {
+    /// Check that the configuration is complete
+    pub fn validate(&self) -> Result<()> {
+        if self.key.is_empty() {
+            return Err(anyhow!("release_signing.key must not be empty"));
+        }
+
+        if self.tool == SigningTool::Minisign && self.public_key.is_none() {
+            return Err(anyhow!("release_signing.public_key is required for minisign"));
+        }
+
+        if let Some(password) = self.password.as_ref() {
+            password.validate().context("Checking release_signing.password")?;
+        }
+
+        Ok(())
+    }
+}
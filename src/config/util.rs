@@ -11,6 +11,8 @@
 //! This module contains default functions that are called by serde when deserializing the
 //! configuration and having to use default values.
 
+use crate::package::HashType;
+
 /// The default progress bar format
 pub fn default_progress_format() -> String {
     String::from("[{elapsed_precise}] ({percent:>3}%): {bar:40.cyan/blue} | {msg}")
@@ -115,3 +117,13 @@ pub fn default_script_shebang() -> String {
 pub fn default_build_error_lines() -> usize {
     10
 }
+
+/// The default value for the maximum length of a log line from a container (64 KiB)
+pub fn default_log_max_line_length() -> usize {
+    64 * 1024
+}
+
+/// The default algorithm for the checksums of artifacts
+pub fn default_artifact_checksum_algorithm() -> HashType {
+    HashType::Sha256
+}
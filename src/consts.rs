@@ -20,6 +20,21 @@ pub const OUTPUTS_DIR_NAME: &str = "outputs";
 
 pub const PATCH_DIR_PATH: &str = "/patches";
 
+/// The path to the directory inside the container where the secrets of a job are copied to
+pub const SECRETS_DIR_PATH: &str = "/secrets";
+
+/// The path inside the container where the scratch directory of the job is mounted, if the
+/// endpoint has scratch space configured
+pub const WORK_DIR_PATH: &str = "/work";
+
+/// The label butido sets on the containers it creates, with the UUID of the job as value
+pub const CONTAINER_LABEL: &str = "butido.job";
+
 /// The path where the script that is executed inside the container is copied to.
 pub const SCRIPT_PATH: &str      = "/script";
 
+/// The directory the script is copied to in containers with a read-only root filesystem, which
+/// is mounted as volume
+pub const READ_ONLY_SCRIPT_DIR_PATH: &str = "/butido";
+pub const READ_ONLY_SCRIPT_PATH: &str     = "/butido/script";
+
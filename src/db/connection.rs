@@ -10,17 +10,43 @@
 
 use std::str::FromStr;
 
+use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use clap::ArgMatches;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::r2d2::Pool;
+use diesel::r2d2::PooledConnection;
 use getset::Getters;
 use log::debug;
 
 use crate::config::Configuration;
 
-#[derive(Getters)]
+/// A pool of database connections, which can be shared between concurrently running jobs
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+/// A database connection from a `DbPool`
+pub type DbConnection = PooledConnection<ConnectionManager<PgConnection>>;
+
+/// The maximum number of connections in a `DbPool` if it is not configured
+const DEFAULT_POOL_SIZE: u32 = 10;
+
+/// Get a connection from `pool` without blocking the async runtime
+///
+/// `Pool::get()` blocks until a connection is free (or the connection timeout is reached), which
+/// must not happen on a thread of the runtime, so it is called with `spawn_blocking()`.
+pub async fn get_connection(pool: &DbPool) -> Result<DbConnection> {
+    let pool = pool.clone();
+    tokio::task::spawn_blocking(move || pool.get())
+        .await
+        .context("Waiting for a database connection")?
+        .context("Getting a database connection from the pool")
+        .map_err(Error::from)
+}
+
+#[derive(Clone, Getters)]
 pub struct DbConnectionConfig<'a> {
     #[getset(get = "pub")]
     database_host: &'a str,
@@ -39,6 +65,9 @@ pub struct DbConnectionConfig<'a> {
 
     #[getset(get = "pub")]
     database_connection_timeout: u16,
+
+    #[getset(get = "pub")]
+    database_pool_size: u32,
 }
 
 impl<'a> std::fmt::Debug for DbConnectionConfig<'a> {
@@ -54,6 +83,25 @@ impl<'a> std::fmt::Debug for DbConnectionConfig<'a> {
 }
 
 impl<'a> DbConnectionConfig<'a> {
+    pub fn new(
+        database_host: &'a str,
+        database_port: u16,
+        database_user: &'a str,
+        database_password: &'a str,
+        database_name: &'a str,
+        database_connection_timeout: u16,
+    ) -> Self {
+        DbConnectionConfig {
+            database_host,
+            database_port,
+            database_user,
+            database_password,
+            database_name,
+            database_connection_timeout,
+            database_pool_size: DEFAULT_POOL_SIZE,
+        }
+    }
+
     pub fn parse(config: &'a Configuration, cli: &'a ArgMatches) -> Result<DbConnectionConfig<'a>> {
         Ok(DbConnectionConfig {
             database_host: cli.value_of("database_host").unwrap_or_else(|| config.database_host()),
@@ -75,12 +123,36 @@ impl<'a> DbConnectionConfig<'a> {
                         config.database_connection_timeout().unwrap_or(30)
                     })
             },
+            database_pool_size: {
+                cli.value_of("database_pool_size")
+                    .map(u32::from_str)
+                    .transpose()?
+                    .or(*config.database_pool_size())
+                    .unwrap_or(DEFAULT_POOL_SIZE)
+            },
         })
     }
 
     pub fn establish_connection(self) -> Result<PgConnection> {
         debug!("Trying to connect to database: {:?}", self);
-        let database_uri: String = format!(
+        PgConnection::establish(&self.database_uri()).map_err(Error::from)
+    }
+
+    /// Create a pool of connections to the database
+    ///
+    /// The connections are established right away, so that an unreachable database is reported
+    /// here and not when the pool is used.
+    pub fn establish_pool(self) -> Result<DbPool> {
+        debug!("Trying to connect to database: {:?} (pool size {})", self, self.database_pool_size);
+        Pool::builder()
+            .max_size(self.database_pool_size)
+            .connection_timeout(std::time::Duration::from_secs(u64::from(self.database_connection_timeout)))
+            .build(ConnectionManager::new(self.database_uri()))
+            .map_err(Error::from)
+    }
+
+    fn database_uri(&self) -> String {
+        format!(
             "postgres://{user}:{password}@{host}:{port}/{name}?connect_timeout={timeout}",
             host = self.database_host,
             port = self.database_port,
@@ -88,8 +160,7 @@ impl<'a> DbConnectionConfig<'a> {
             password = self.database_password,
             name = self.database_name,
             timeout = self.database_connection_timeout,
-        );
-        PgConnection::establish(&database_uri).map_err(Error::from)
+        )
     }
 
 }
@@ -20,20 +20,25 @@ use diesel::JoinOnDsl;
 use diesel::PgConnection;
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
+use itertools::Itertools;
 use log::trace;
 use resiter::AndThen;
-use resiter::FilterMap;
 
 use crate::config::Configuration;
+use crate::config::ReusePolicy;
 use crate::db::models as dbmodels;
 use crate::filestore::path::ArtifactPath;
 use crate::filestore::path::FullArtifactPath;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
+use crate::job::CacheKey;
+use crate::package::HashValue;
 use crate::package::Package;
+use crate::package::Script;
 use crate::package::ScriptBuilder;
 use crate::package::Shebang;
 use crate::schema;
+use crate::util::Architecture;
 use crate::util::EnvironmentVariableName;
 use crate::util::docker::ImageName;
 
@@ -52,7 +57,7 @@ use crate::util::docker::ImageName;
 #[derive(typed_builder::TypedBuilder)]
 pub struct FindArtifacts<'a> {
     config: &'a Configuration,
-    database_connection: Arc<PgConnection>,
+    database_connection: &'a PgConnection,
 
     /// The release stores to search in
     release_stores: &'a [Arc<ReleaseStore>],
@@ -74,25 +79,302 @@ pub struct FindArtifacts<'a> {
     #[builder(default)]
     image_name: Option<&'a ImageName>,
 
+    /// Filter for image digests
+    ///
+    /// If set, only artifacts from jobs that ran with an image with one of these digests are
+    /// returned.
+    #[builder(default)]
+    image_digests: Option<&'a [String]>,
+
+    /// Whether to only return artifacts that were built without network access
+    #[builder(default)]
+    hermetic_only: bool,
+
+    /// Filter for the native metadata of the artifacts
+    ///
+    /// Only artifacts matching all of the filters are returned.
+    #[builder(default)]
+    metadata_filter: &'a [MetadataFilter],
+
+    /// The policy the artifacts must satisfy to be reused, if any
+    #[builder(default)]
+    reuse_policy: Option<&'a ReusePolicy>,
+
+    /// Filter for the architecture the jobs were built for
+    ///
+    /// `Some(None)` only returns artifacts of jobs that were not built for a specific
+    /// architecture.
+    #[builder(default)]
+    architecture: Option<Option<&'a Architecture>>,
+
+    /// The submit whose artifacts are preferred
+    ///
+    /// If jobs of this submit produced matching artifacts, only these are returned.
+    #[builder(default)]
+    preferred_submit: Option<&'a dbmodels::Submit>,
+
+    /// The inputs of the cache key the jobs must have been built with, if any
+    ///
+    /// If set, only artifacts of jobs whose cache key equals a key computed from the package,
+    /// its script, the environment and these inputs are returned.
+    #[builder(default)]
+    cache_key: Option<CacheKeyInputs<'a>>,
+
     /// Search for this package
     package: &'a Package,
 }
 
+/// A filter for the native metadata of artifacts
+///
+/// Matches the artifacts of the kind `kind` (e.g. "rpm"), which have the metadata field `field`
+/// with the passed value, if set.
+#[derive(Debug)]
+pub struct MetadataFilter {
+    pub kind: &'static str,
+    pub field: Option<(&'static str, String)>,
+}
+
+/// The inputs of the cache key of a job that are not known from the package itself
+///
+/// A key is computed for each of the image digests, because the job could run on any endpoint
+/// that supports its image. `None` stands for an endpoint that does not know the digest of its
+/// images.
+#[derive(Debug)]
+pub struct CacheKeyInputs<'a> {
+    pub dependency_hashes: &'a [HashValue],
+    pub image_digests: &'a [Option<String>],
+}
+
+/// Why the artifacts of an earlier job were not reused
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReuseMismatch {
+    /// There is no earlier job of the package in this version that produced artifacts
+    NoCandidate,
+
+    /// A dependency of the job was built, so the job has to be built as well
+    DependencyBuilt,
+
+    /// The rebuild of the package was forced, e.g. with `--force-rebuild`
+    Forced,
+
+    /// The earlier job ran a different script
+    Script,
+
+    /// The earlier job was built in an image that does not match
+    Image { job: String, expected: String },
+
+    /// The earlier job ran with an image whose digest is not one of the current digests
+    ImageDigest { job: Option<String> },
+
+    /// The inputs of the earlier job differed, e.g. the artifacts of its dependencies
+    CacheKey { job: Option<String> },
+
+    /// The earlier job was built for another architecture
+    Architecture { job: Option<String>, expected: Option<String> },
+
+    /// An environment variable had other values in the earlier job
+    Env { name: String, job: Vec<String>, expected: Vec<String> },
+
+    /// The artifact was built with network access, but only hermetic artifacts are reused
+    NotHermetic { path: String },
+
+    /// The reuse policy does not allow the artifact
+    Policy { detail: String },
+
+    /// The artifact is in none of the stores
+    Missing { path: String },
+
+    /// The artifact does not match its recorded checksum
+    Corrupted { path: String, error: String },
+}
+
+impl ReuseMismatch {
+    /// The criterion that did not match, as recorded in the database
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ReuseMismatch::NoCandidate => "no-candidate",
+            ReuseMismatch::DependencyBuilt => "dependency-built",
+            ReuseMismatch::Forced => "forced",
+            ReuseMismatch::Script => "script",
+            ReuseMismatch::Image { .. } => "image",
+            ReuseMismatch::ImageDigest { .. } => "image-digest",
+            ReuseMismatch::CacheKey { .. } => "cache-key",
+            ReuseMismatch::Architecture { .. } => "architecture",
+            ReuseMismatch::Env { .. } => "env",
+            ReuseMismatch::NotHermetic { .. } => "not-hermetic",
+            ReuseMismatch::Policy { .. } => "policy",
+            ReuseMismatch::Missing { .. } => "missing",
+            ReuseMismatch::Corrupted { .. } => "corrupted",
+        }
+    }
+}
+
+impl std::fmt::Display for ReuseMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn values(v: &[String]) -> String {
+            if v.is_empty() {
+                String::from("<unset>")
+            } else {
+                v.join(", ")
+            }
+        }
+
+        match self {
+            ReuseMismatch::NoCandidate => write!(f, "No earlier job of the package produced artifacts"),
+            ReuseMismatch::DependencyBuilt => write!(f, "A dependency was built"),
+            ReuseMismatch::Forced => write!(f, "The rebuild of the package was forced"),
+            ReuseMismatch::Script => write!(f, "Script differed"),
+            ReuseMismatch::Image { job, expected } => write!(f, "Image {} differed, expected {}", job, expected),
+            ReuseMismatch::ImageDigest { job } => {
+                write!(f, "Image digest {} is not a current digest of the image", job.as_deref().unwrap_or("<unknown>"))
+            },
+            ReuseMismatch::CacheKey { job } => {
+                write!(f, "Cache key {} differed, the inputs of the build changed", job.as_deref().unwrap_or("<unknown>"))
+            },
+            ReuseMismatch::Architecture { job, expected } => write!(f,
+                "Architecture {} differed, expected {}",
+                job.as_deref().unwrap_or("<none>"),
+                expected.as_deref().unwrap_or("<none>")),
+            ReuseMismatch::Env { name, job, expected } => write!(f,
+                "Environment variable {} differed: {}, expected {}",
+                name,
+                values(job),
+                values(expected)),
+            ReuseMismatch::NotHermetic { path } => write!(f, "Artifact {} was not built hermetically", path),
+            ReuseMismatch::Policy { detail } => write!(f, "{}", detail),
+            ReuseMismatch::Missing { path } => write!(f, "Artifact {} is missing from the stores", path),
+            ReuseMismatch::Corrupted { path, error } => write!(f, "Artifact {} is corrupted: {}", path, error),
+        }
+    }
+}
+
+/// An earlier job of the package whose artifacts were not reused, and why
+#[derive(Debug)]
+pub struct ReuseCandidate {
+    pub job: dbmodels::Job,
+    pub mismatches: Vec<ReuseMismatch>,
+}
+
+/// The number of most recent jobs that are checked when explaining why nothing was reused
+const EXPLAIN_CANDIDATES: i64 = 5;
+
 impl<'a> FindArtifacts<'a> {
-    /// Run the FindArtifact as configured
-    pub fn run(self) -> Result<Vec<(FullArtifactPath<'a>, Option<NaiveDateTime>)>> {
-        let shebang = Shebang::from(self.config.shebang().clone());
-        let script = if self.script_filter {
-            let script = ScriptBuilder::new(&shebang).build(
+    /// Build the script the artifacts must have been built with, if the script is filtered for
+    fn script(&self) -> Result<Option<Script>> {
+        if !self.script_filter {
+            return Ok(None)
+        }
+
+        self.build_script().map(Some)
+    }
+
+    /// Build the script of the package as it is run in the image
+    fn build_script(&self) -> Result<Script> {
+        let shebang = Shebang::from({
+            self.image_name
+                .and_then(|image| self.config.docker().image_defaults().get(image))
+                .and_then(|defaults| defaults.shebang().clone())
+                .unwrap_or_else(|| self.config.shebang().clone())
+        });
+        let dependency_mapping = self.image_name
+            .and_then(|image| self.config.docker().dependency_mapping().get(image));
+
+        ScriptBuilder::new(&shebang)
+            .with_dependency_mapping(dependency_mapping)
+            .build(
                 self.package,
                 self.config.available_phases(),
                 *self.config.strict_script_interpolation(),
-            )?;
-            Some(script)
-        } else {
-            None
+            )
+    }
+
+    /// The cache keys the jobs must have been built with, if the cache key is filtered for
+    fn cache_keys(&self) -> Result<Option<Vec<String>>> {
+        let inputs = match self.cache_key.as_ref() {
+            Some(inputs) => inputs,
+            None => return Ok(None),
         };
 
+        let script = self.build_script()?;
+        let keys = inputs.image_digests
+            .iter()
+            .map(|digest| {
+                let env = self.package
+                    .environment()
+                    .iter()
+                    .flatten()
+                    .chain(self.env_filter.iter().map(|(k, v)| (k, v)));
+
+                CacheKey::new(self.package, &script, env, inputs.dependency_hashes, digest.as_deref()).to_string()
+            })
+            .unique()
+            .collect();
+
+        Ok(Some(keys))
+    }
+
+    /// The time artifacts must have been built after, if the reuse policy limits their age
+    fn oldest_build_time(&self) -> Result<Option<NaiveDateTime>> {
+        let max_age = match self.reuse_policy {
+            Some(policy) => policy.max_age_for(self.package.reuse_max_age().as_deref())?,
+            None => return Ok(None),
+        };
+
+        Ok(max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .and_then(|age| chrono::offset::Local::now().naive_local().checked_sub_signed(age)))
+    }
+
+    /// The staging store to search in
+    ///
+    /// Artifacts in the staging store are not released, so the staging store is not searched
+    /// if the policy requires released artifacts
+    fn searched_staging_store(&self) -> Option<&'a StagingStore> {
+        self.staging_store
+            .filter(|_| !self.reuse_policy.map(|p| p.release_only() || p.signed_only()).unwrap_or(false))
+    }
+
+    /// Find the artifact in the staging store (preferrably) or one of the release stores
+    fn find_in_stores(&self, staging_store: Option<&'a StagingStore>, artpath: &ArtifactPath) -> Result<Option<FullArtifactPath<'a>>> {
+        if let Some(staging) = staging_store {
+            trace!(
+                "Searching in staging: {:?} for {:?}",
+                staging.root_path(),
+                artpath
+            );
+            if let Some(found) = staging.get(artpath) {
+                trace!("Found in staging: {:?}", found);
+                return staging.root_path().join(found)
+            }
+        }
+
+        // If we cannot find the artifact in the release store either, we return None.
+        // This is the case if there indeed was a release, but it was removed from the
+        // filesystem.
+        for release_store in self.release_stores {
+            if let Some(found) = release_store.get(artpath) {
+                trace!("Found in release: {:?}", found);
+                return release_store.root_path().join(found)
+            }
+        }
+
+        trace!("Found no release for artifact {:?} in any release store", artpath.display());
+        Ok(None)
+    }
+
+    /// Run the FindArtifact as configured
+    ///
+    /// The checksums of the found artifacts are not verified, this is left to the callers which
+    /// actually use the artifacts.
+    pub fn run(self) -> Result<Vec<(FullArtifactPath<'a>, Option<NaiveDateTime>)>> {
+        self.run_with_artifacts()
+            .map(|found| found.into_iter().map(|(_, path, ndt)| (path, ndt)).collect())
+    }
+
+    /// Like `run()`, but also return the database rows of the found artifacts
+    pub fn run_with_artifacts(self) -> Result<Vec<(dbmodels::Artifact, FullArtifactPath<'a>, Option<NaiveDateTime>)>> {
+        let script = self.script()?;
+        let cache_keys = self.cache_keys()?;
         let package_environment = self.package.environment();
         let mut query = schema::packages::table
             .filter({
@@ -114,6 +396,9 @@ impl<'a> FindArtifacts<'a> {
             // So do not include release dates here, for now
             //.left_outer_join(schema::releases::table.on(schema::releases::artifact_id.eq(schema::artifacts::id)))
             .inner_join(schema::images::table.on(schema::submits::requested_image_id.eq(schema::images::id)))
+
+            // Artifacts of jobs which ran on a mock endpoint are fake and never reused
+            .filter(schema::jobs::simulated.eq(false))
             .into_boxed();
 
         if let Some(allowed_images) = self.package.allowed_images() {
@@ -142,9 +427,66 @@ impl<'a> FindArtifacts<'a> {
             query = query.filter(schema::images::name.eq(image_name.as_ref()));
         }
 
+        if let Some(image_digests) = self.image_digests {
+            trace!("Filtering with image_digests = {:?}", image_digests);
+            query = query.filter(schema::jobs::image_digest.eq_any(image_digests));
+        }
+
+        if let Some(cache_keys) = cache_keys.as_ref() {
+            trace!("Filtering with cache_keys = {:?}", cache_keys);
+            query = query.filter(schema::jobs::cache_key.eq_any(cache_keys));
+        }
+
+        match self.architecture {
+            Some(Some(architecture)) => {
+                trace!("Filtering with architecture = {}", architecture);
+                query = query.filter(schema::jobs::architecture.eq(architecture.as_ref()));
+            },
+            Some(None) => {
+                trace!("Filtering for jobs without architecture");
+                query = query.filter(schema::jobs::architecture.is_null());
+            },
+            None => {},
+        }
+
+        if self.hermetic_only || self.reuse_policy.map(ReusePolicy::hermetic_only).unwrap_or(false) {
+            trace!("Filtering for hermetic artifacts");
+            query = query.filter(schema::artifacts::hermetic.eq(true));
+        }
+
+        if let Some(users) = self.reuse_policy.map(ReusePolicy::submitted_by).filter(|u| !u.is_empty()) {
+            trace!("Filtering with submitted_by = {:?}", users);
+            let users = users.iter().map(String::as_str).collect::<Vec<_>>();
+            query = query.filter(schema::submits::submitted_by.eq_any(users));
+        }
+
+        if let Some(oldest) = self.oldest_build_time()? {
+            trace!("Filtering for artifacts built after {}", oldest);
+            query = query.filter(schema::submits::submit_time.gt(oldest));
+        }
+
+        for filter in self.metadata_filter {
+            trace!("Filtering with metadata filter = {:?}", filter);
+            let of_kind = schema::artifact_metadata::table
+                .select(schema::artifact_metadata::artifact_id)
+                .filter(schema::artifact_metadata::kind.eq(filter.kind));
+
+            query = match filter.field.as_ref() {
+                Some((key, value)) => {
+                    let with_field = of_kind
+                        .filter(schema::artifact_metadata::key.eq(*key))
+                        .filter(schema::artifact_metadata::value.eq(value));
+                    query.filter(schema::artifacts::id.eq_any(with_field))
+                }
+                None => query.filter(schema::artifacts::id.eq_any(of_kind)),
+            };
+        }
+
         trace!("Query = {}", diesel::debug_query(&query));
 
-        query
+        let staging_store = self.searched_staging_store();
+
+        let found = query
             .select({
                 let arts = schema::artifacts::all_columns;
                 let jobs = schema::jobs::all_columns;
@@ -152,7 +494,17 @@ impl<'a> FindArtifacts<'a> {
 
                 (arts, jobs)
             })
-            .load::<(dbmodels::Artifact, dbmodels::Job)>(&*self.database_connection)?
+            .load::<(dbmodels::Artifact, dbmodels::Job)>(self.database_connection)?;
+
+        let found = match self.preferred_submit {
+            Some(submit) if found.iter().any(|(_, job)| job.submit_id == submit.id) => {
+                trace!("Only using artifacts from preferred submit {}", submit.uuid);
+                found.into_iter().filter(|(_, job)| job.submit_id == submit.id).collect()
+            },
+            _ => found,
+        };
+
+        found
             .into_iter()
             .inspect(|(art, job)| log::debug!("Filtering further: {:?}, job {:?}", art, job.id))
             //
@@ -160,6 +512,8 @@ impl<'a> FindArtifacts<'a> {
             // All environment variables of the package must be present in the loaded
             // package, so that we can be sure that the loaded package was built with
             // the same ENV.
+            // If the package declares the variables its build is sensitive to, only these
+            // are compared.
             //
             // TODO:
             // Doing this in the database query would be way nicer, but I was not able
@@ -171,13 +525,16 @@ impl<'a> FindArtifacts<'a> {
 
                 let job = tpl.1;
                 let job_env: Vec<(String, String)> = job
-                    .env(&*self.database_connection)?
+                    .env(self.database_connection)?
                     .into_iter()
                     .map(|var: dbmodels::EnvVar| (var.name, var.value))
                     .collect();
 
                 trace!("The job we found had env: {:?}", job_env);
-                let envs_equal = environments_equal(&job_env, package_environment.as_ref(), self.env_filter);
+                let envs_equal = match self.package.env_sensitivity().as_ref() {
+                    Some(sensitivity) => sensitive_environments_equal(&job_env, package_environment.as_ref(), self.env_filter, sensitivity),
+                    None => environments_equal(&job_env, package_environment.as_ref(), self.env_filter),
+                };
                 trace!("environments where equal = {}", envs_equal);
                 Ok((tpl.0, envs_equal))
             })
@@ -186,41 +543,194 @@ impl<'a> FindArtifacts<'a> {
                 Ok((_, bl)) => *bl,
             })
             .and_then_ok(|(art, _)| {
-                if let Some(release) = art.get_release(&*self.database_connection)? {
-                    Ok((art, Some(release.release_date)))
-                } else {
-                    Ok((art, None))
+                let release = art.get_release(self.database_connection)?;
+                if let Some(policy) = self.reuse_policy {
+                    let signed = match release.as_ref() {
+                        Some(release) if policy.signed_only() => {
+                            dbmodels::ReleaseSignature::fetch_for_release(self.database_connection, release)?.is_some()
+                        },
+                        _ => false,
+                    };
+
+                    if !policy.allows_release(release.is_some(), signed) {
+                        trace!("Artifact {} is not allowed by the reuse policy", art.path);
+                        return Ok(None);
+                    }
                 }
+
+                Ok(Some((art, release.map(|r| r.release_date))))
             })
-            .and_then_ok(|(p, ndt)| ArtifactPath::new(PathBuf::from(p.path)).map(|a| (a, ndt)))
-            .and_then_ok(|(artpath, ndt)| {
-                if let Some(staging) = self.staging_store.as_ref() {
-                    trace!(
-                        "Searching in staging: {:?} for {:?}",
-                        staging.root_path(),
-                        artpath
-                    );
-                    if let Some(art) = staging.get(&artpath) {
-                        trace!("Found in staging: {:?}", art);
-                        return staging.root_path().join(art).map(|p| p.map(|p| (p, ndt)))
+            .filter_map_ok(|opt| opt)
+            .and_then_ok(|(art, ndt)| ArtifactPath::new(PathBuf::from(&art.path)).map(|a| (art, a, ndt)))
+            .and_then_ok(|(art, artpath, ndt)| {
+                self.find_in_stores(staging_store, &artpath)
+                    .map(|p| p.map(|p| (art, p, ndt)))
+            })
+            .filter_map_ok(|opt| opt)
+
+            // The checksums of the artifacts are not verified here, but only for the artifacts
+            // which are actually reused
+            .collect::<Result<Vec<(dbmodels::Artifact, FullArtifactPath<'a>, Option<NaiveDateTime>)>>>()
+    }
+
+    /// Explain why no artifacts were found
+    ///
+    /// The most recent jobs of the package that produced artifacts are checked against each of
+    /// the criteria `run()` filters for, and the criteria each of them did not match are
+    /// returned. Jobs that match all criteria are left out.
+    pub fn explain(self) -> Result<Vec<ReuseCandidate>> {
+        let script = self.script()?;
+        let cache_keys = self.cache_keys()?;
+        let package_environment = self.package.environment();
+        let staging_store = self.searched_staging_store();
+        let oldest_build_time = self.oldest_build_time()?;
+
+        let jobs_with_artifacts = schema::artifacts::table.select(schema::artifacts::job_id);
+        let candidates = schema::packages::table
+            .filter(schema::packages::name.eq(self.package.name().as_ref() as &str))
+            .filter(schema::packages::version.eq(self.package.version().as_ref() as &str))
+            .inner_join(schema::jobs::table.inner_join(schema::submits::table))
+            .inner_join(schema::images::table.on(schema::submits::requested_image_id.eq(schema::images::id)))
+            .filter(schema::jobs::id.eq_any(jobs_with_artifacts))
+            .filter(schema::jobs::simulated.eq(false))
+            .select((schema::jobs::all_columns, schema::submits::all_columns, schema::images::name))
+            .order_by(schema::jobs::id.desc())
+            .limit(EXPLAIN_CANDIDATES)
+            .load::<(dbmodels::Job, dbmodels::Submit, String)>(self.database_connection)?;
+
+        candidates
+            .into_iter()
+            .map(|(job, submit, image_name)| -> Result<ReuseCandidate> {
+                let mut mismatches = Vec::new();
+
+                if script.as_ref().map(|s| AsRef::<str>::as_ref(s) != job.script_text).unwrap_or(false) {
+                    mismatches.push(ReuseMismatch::Script);
+                }
+
+                if let Some(allowed_images) = self.package.allowed_images() {
+                    if !allowed_images.iter().any(|i| AsRef::<str>::as_ref(i) == image_name) {
+                        mismatches.push(ReuseMismatch::Image {
+                            job: image_name.clone(),
+                            expected: String::from("one of the allowed images of the package"),
+                        });
+                    }
+                }
+
+                if let Some(denied_images) = self.package.denied_images() {
+                    if denied_images.iter().any(|i| AsRef::<str>::as_ref(i) == image_name) {
+                        mismatches.push(ReuseMismatch::Image {
+                            job: image_name.clone(),
+                            expected: String::from("none of the denied images of the package"),
+                        });
                     }
                 }
 
-                // If we cannot find the artifact in the release store either, we return None.
-                // This is the case if there indeed was a release, but it was removed from the
-                // filesystem.
-                for release_store in self.release_stores {
-                    if let Some(art) = release_store.get(&artpath) {
-                        trace!("Found in release: {:?}", art);
-                        return release_store.root_path().join(art).map(|p| p.map(|p| (p, ndt)))
+                if let Some(expected) = self.image_name.filter(|i| AsRef::<str>::as_ref(*i) != image_name) {
+                    mismatches.push(ReuseMismatch::Image { job: image_name.clone(), expected: expected.to_string() });
+                }
+
+                if let Some(image_digests) = self.image_digests {
+                    if !job.image_digest.as_ref().map(|d| image_digests.contains(d)).unwrap_or(false) {
+                        mismatches.push(ReuseMismatch::ImageDigest { job: job.image_digest.clone() });
+                    }
+                }
+
+                if let Some(cache_keys) = cache_keys.as_ref() {
+                    if !job.cache_key.as_ref().map(|k| cache_keys.contains(k)).unwrap_or(false) {
+                        mismatches.push(ReuseMismatch::CacheKey { job: job.cache_key.clone() });
                     }
                 }
 
-                trace!("Found no release for artifact {:?} in any release store", artpath.display());
-                Ok(None)
+                if let Some(expected) = self.architecture {
+                    if job.architecture.as_deref() != expected.map(AsRef::<str>::as_ref) {
+                        mismatches.push(ReuseMismatch::Architecture {
+                            job: job.architecture.clone(),
+                            expected: expected.map(ToString::to_string),
+                        });
+                    }
+                }
+
+                let job_env: Vec<(String, String)> = job
+                    .env(self.database_connection)?
+                    .into_iter()
+                    .map(|var: dbmodels::EnvVar| (var.name, var.value))
+                    .collect();
+                mismatches.extend(env_mismatches(
+                    &job_env,
+                    package_environment.as_ref(),
+                    self.env_filter,
+                    self.package.env_sensitivity().as_deref(),
+                ));
+
+                if let Some(users) = self.reuse_policy.map(ReusePolicy::submitted_by).filter(|u| !u.is_empty()) {
+                    if !submit.submitted_by.as_ref().map(|u| users.contains(u)).unwrap_or(false) {
+                        mismatches.push(ReuseMismatch::Policy {
+                            detail: format!("Submitted by {}, but the reuse policy only allows {}",
+                                submit.submitted_by.as_deref().unwrap_or("<unknown>"),
+                                users.join(", ")),
+                        });
+                    }
+                }
+
+                if let Some(oldest) = oldest_build_time.filter(|oldest| submit.submit_time <= *oldest) {
+                    mismatches.push(ReuseMismatch::Policy {
+                        detail: format!("Built at {}, but the reuse policy only allows artifacts built after {}",
+                            submit.submit_time,
+                            oldest),
+                    });
+                }
+
+                let artifacts = schema::artifacts::table
+                    .filter(schema::artifacts::job_id.eq(job.id))
+                    .load::<dbmodels::Artifact>(self.database_connection)?;
+
+                for art in artifacts {
+                    mismatches.extend(self.artifact_mismatches(&art, staging_store)?);
+                }
+
+                Ok(ReuseCandidate { job, mismatches })
             })
-            .filter_map_ok(|opt| opt)
-            .collect::<Result<Vec<(FullArtifactPath<'a>, Option<NaiveDateTime>)>>>()
+            .filter_ok(|candidate| !candidate.mismatches.is_empty())
+            .collect()
+    }
+
+    /// The criteria the artifact `art` of a candidate job does not match
+    fn artifact_mismatches(&self, art: &dbmodels::Artifact, staging_store: Option<&'a StagingStore>) -> Result<Vec<ReuseMismatch>> {
+        let mut mismatches = Vec::new();
+
+        if !art.hermetic && (self.hermetic_only || self.reuse_policy.map(ReusePolicy::hermetic_only).unwrap_or(false)) {
+            mismatches.push(ReuseMismatch::NotHermetic { path: art.path.clone() });
+        }
+
+        if let Some(policy) = self.reuse_policy {
+            let release = art.get_release(self.database_connection)?;
+            let signed = match release.as_ref() {
+                Some(release) if policy.signed_only() => {
+                    dbmodels::ReleaseSignature::fetch_for_release(self.database_connection, release)?.is_some()
+                },
+                _ => false,
+            };
+
+            if !policy.allows_release(release.is_some(), signed) {
+                mismatches.push(ReuseMismatch::Policy {
+                    detail: format!("Artifact {} is not allowed by the reuse policy", art.path),
+                });
+            }
+        }
+
+        let artpath = ArtifactPath::new(PathBuf::from(&art.path))?;
+        match self.find_in_stores(staging_store, &artpath)? {
+            None => mismatches.push(ReuseMismatch::Missing { path: art.path.clone() }),
+            Some(path) => {
+                if let Some(checksum) = dbmodels::ArtifactChecksum::fetch_for_artifact(self.database_connection, art)? {
+                    if let Err(e) = checksum.verify(&path.joined()) {
+                        mismatches.push(ReuseMismatch::Corrupted { path: art.path.clone(), error: e.to_string() });
+                    }
+                }
+            },
+        }
+
+        Ok(mismatches)
     }
 }
 
@@ -267,3 +777,145 @@ fn environments_equal(job_env: &[(String, String)], pkg_env: Option<&HashMap<Env
     job_envs_all_found() && pkg_envs_all_found() && add_envs_all_found()
 }
 
+/// Compare only the variables in `sensitivity`
+///
+/// For each of these variables, the job must have had the same values as the package environment
+/// and the additional environment define (or none, if neither of them defines the variable).
+fn sensitive_environments_equal(
+    job_env: &[(String, String)],
+    pkg_env: Option<&HashMap<EnvironmentVariableName, String>>,
+    add_env: &[(EnvironmentVariableName, String)],
+    sensitivity: &[EnvironmentVariableName],
+) -> bool {
+    sensitivity.iter().all(|name| {
+        let job_values = job_env.iter()
+            .filter(|(k, _)| k == name.as_ref())
+            .map(|(_, v)| v)
+            .sorted()
+            .dedup()
+            .collect::<Vec<_>>();
+
+        let expected_values = pkg_env
+            .and_then(|hm| hm.get(name))
+            .into_iter()
+            .chain(add_env.iter().filter(|(k, _)| k == name).map(|(_, v)| v))
+            .sorted()
+            .dedup()
+            .collect::<Vec<_>>();
+
+        let r = job_values == expected_values;
+        trace!("Job Env {} equal: {} ({:?} == {:?})", name, r, job_values, expected_values);
+        r
+    })
+}
+
+/// The environment variables that have other values in the job than expected
+///
+/// The variables are compared like `environments_equal()` and `sensitive_environments_equal()`
+/// do, if `sensitivity` is set.
+fn env_mismatches(
+    job_env: &[(String, String)],
+    pkg_env: Option<&HashMap<EnvironmentVariableName, String>>,
+    add_env: &[(EnvironmentVariableName, String)],
+    sensitivity: Option<&[EnvironmentVariableName]>,
+) -> Vec<ReuseMismatch> {
+    let names = match sensitivity {
+        Some(sensitivity) => sensitivity.to_vec(),
+        None => job_env.iter()
+            .map(|(k, _)| EnvironmentVariableName::from(k.as_str()))
+            .chain(pkg_env.into_iter().flat_map(|hm| hm.keys().cloned()))
+            .chain(add_env.iter().map(|(k, _)| k.clone()))
+            .sorted()
+            .dedup()
+            .collect(),
+    };
+
+    names.into_iter()
+        .filter_map(|name| {
+            let job_values = job_env.iter()
+                .filter(|(k, _)| k == name.as_ref())
+                .map(|(_, v)| v.clone())
+                .sorted()
+                .dedup()
+                .collect::<Vec<_>>();
+
+            let expected_values = pkg_env
+                .and_then(|hm| hm.get(&name))
+                .into_iter()
+                .chain(add_env.iter().filter(|(k, _)| *k == name).map(|(_, v)| v))
+                .cloned()
+                .sorted()
+                .dedup()
+                .collect::<Vec<_>>();
+
+            if job_values == expected_values {
+                None
+            } else {
+                Some(ReuseMismatch::Env { name: name.to_string(), job: job_values, expected: expected_values })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> Vec<(EnvironmentVariableName, String)> {
+        pairs.iter().map(|(k, v)| (EnvironmentVariableName::from(*k), v.to_string())).collect()
+    }
+
+    fn job_env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_sensitive_environments_ignore_other_variables() {
+        let sensitivity = vec![EnvironmentVariableName::from("CFLAGS")];
+        let job = job_env(&[("CFLAGS", "-O2"), ("BUILD_ID", "1")]);
+        let add = env(&[("CFLAGS", "-O2"), ("BUILD_ID", "2")]);
+
+        assert!(sensitive_environments_equal(&job, None, &add, &sensitivity));
+        assert!(!environments_equal(&job, None, &add));
+    }
+
+    #[test]
+    fn test_sensitive_environments_differ() {
+        let sensitivity = vec![EnvironmentVariableName::from("CFLAGS"), EnvironmentVariableName::from("FEATURE_X")];
+        let job = job_env(&[("CFLAGS", "-O2")]);
+
+        assert!(!sensitive_environments_equal(&job, None, &env(&[("CFLAGS", "-O3")]), &sensitivity));
+        assert!(!sensitive_environments_equal(&job, None, &env(&[("CFLAGS", "-O2"), ("FEATURE_X", "1")]), &sensitivity));
+        assert!(sensitive_environments_equal(&job, None, &env(&[("CFLAGS", "-O2")]), &sensitivity));
+    }
+
+    #[test]
+    fn test_sensitive_environments_package_env() {
+        let sensitivity = vec![EnvironmentVariableName::from("CFLAGS")];
+        let mut pkg_env = HashMap::new();
+        pkg_env.insert(EnvironmentVariableName::from("CFLAGS"), String::from("-O2"));
+
+        assert!(sensitive_environments_equal(&job_env(&[("CFLAGS", "-O2")]), Some(&pkg_env), &[], &sensitivity));
+        assert!(!sensitive_environments_equal(&job_env(&[]), Some(&pkg_env), &[], &sensitivity));
+    }
+
+    #[test]
+    fn test_env_mismatches() {
+        let job = job_env(&[("CFLAGS", "-O2"), ("BUILD_ID", "1")]);
+        let add = env(&[("CFLAGS", "-O3"), ("FEATURE_X", "1")]);
+
+        let mismatches = env_mismatches(&job, None, &add, None);
+        assert_eq!(mismatches, vec![
+            ReuseMismatch::Env { name: String::from("BUILD_ID"), job: vec![String::from("1")], expected: vec![] },
+            ReuseMismatch::Env { name: String::from("CFLAGS"), job: vec![String::from("-O2")], expected: vec![String::from("-O3")] },
+            ReuseMismatch::Env { name: String::from("FEATURE_X"), job: vec![], expected: vec![String::from("1")] },
+        ]);
+
+        let sensitivity = vec![EnvironmentVariableName::from("FEATURE_X")];
+        let mismatches = env_mismatches(&job, None, &add, Some(&sensitivity));
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].kind(), "env");
+
+        assert!(env_mismatches(&job, None, &env(&[("CFLAGS", "-O2"), ("BUILD_ID", "1")]), None).is_empty());
+    }
+}
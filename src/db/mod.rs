@@ -12,6 +12,13 @@ mod connection;
 pub use connection::*;
 
 mod find_artifacts;
+pub use find_artifacts::CacheKeyInputs;
 pub use find_artifacts::FindArtifacts;
+pub use find_artifacts::MetadataFilter;
+pub use find_artifacts::ReuseCandidate;
+pub use find_artifacts::ReuseMismatch;
 
 pub mod models;
+
+mod resolve_dag;
+pub use resolve_dag::resolve_dag;
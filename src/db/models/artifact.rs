@@ -30,6 +30,10 @@ pub struct Artifact {
     pub id: i32,
     pub path: String,
     pub job_id: i32,
+    pub hermetic: bool,
+
+    /// The named output of the package the artifact belongs to, see the `outputs` of the package
+    pub output: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -37,6 +41,8 @@ pub struct Artifact {
 struct NewArtifact<'a> {
     pub path: &'a str,
     pub job_id: i32,
+    pub hermetic: bool,
+    pub output: Option<&'a str>,
 }
 
 impl Artifact {
@@ -70,6 +76,8 @@ impl Artifact {
         database_connection: &PgConnection,
         art_path: &ArtifactPath,
         job: &Job,
+        built_hermetic: bool,
+        artifact_output: Option<&str>,
     ) -> Result<Artifact> {
         let path_str = art_path
             .to_str()
@@ -78,6 +86,8 @@ impl Artifact {
         let new_art = NewArtifact {
             path: path_str,
             job_id: job.id,
+            hermetic: built_hermetic,
+            output: artifact_output,
         };
 
         database_connection.transaction::<_, Error, _>(|| {
@@ -0,0 +1,112 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Artifact;
+use crate::db::models::Job;
+use crate::package::HashType;
+use crate::package::HashValue;
+use crate::schema::artifact_checksums;
+use crate::schema::artifact_checksums::*;
+
+/// The checksum of an artifact, computed when the job that produced it finished
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Artifact)]
+pub struct ArtifactChecksum {
+    pub id: i32,
+    pub artifact_id: i32,
+    pub algorithm: String,
+    pub checksum: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "artifact_checksums"]
+struct NewArtifactChecksum<'a> {
+    pub artifact_id: i32,
+    pub algorithm: &'a str,
+    pub checksum: &'a str,
+}
+
+impl ArtifactChecksum {
+    pub fn create(
+        database_connection: &PgConnection,
+        art: &Artifact,
+        hashtype: &HashType,
+        value: &HashValue,
+    ) -> Result<ArtifactChecksum> {
+        let hashtype = hashtype.to_string();
+        let value = value.to_string();
+        let new_checksum = NewArtifactChecksum {
+            artifact_id: art.id,
+            algorithm: &hashtype,
+            checksum: &value,
+        };
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(artifact_checksums::table)
+                .values(&new_checksum)
+                .execute(database_connection)?;
+
+            dsl::artifact_checksums
+                .filter(artifact_id.eq(art.id))
+                .first::<ArtifactChecksum>(database_connection)
+                .map_err(Error::from)
+        })
+    }
+
+    pub fn fetch_for_artifact(database_connection: &PgConnection, art: &Artifact) -> Result<Option<ArtifactChecksum>> {
+        dsl::artifact_checksums
+            .filter(artifact_id.eq(art.id))
+            .first::<ArtifactChecksum>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// The checksum recorded for the artifact with the id `art_id`, with the job that produced it
+    pub fn fetch_for_artifact_id(database_connection: &PgConnection, art_id: i32) -> Result<Option<(ArtifactChecksum, Job)>> {
+        use crate::schema;
+
+        dsl::artifact_checksums
+            .inner_join(schema::artifacts::table.inner_join(schema::jobs::table))
+            .filter(artifact_id.eq(art_id))
+            .select((artifact_checksums::all_columns, schema::jobs::all_columns))
+            .first::<(ArtifactChecksum, Job)>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Check that the file at `path` still has this checksum
+    pub fn verify(&self, path: &Path) -> Result<()> {
+        let hashtype = HashType::from_str(&self.algorithm)
+            .with_context(|| anyhow!("Unknown checksum algorithm: {}", self.algorithm))?;
+        let actual = hashtype.hash_file(path)?.to_string();
+
+        if actual == self.checksum {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Checksum mismatch for {}: expected {} '{}', got '{}'",
+                path.display(),
+                self.algorithm,
+                self.checksum,
+                actual
+            ))
+        }
+    }
+}
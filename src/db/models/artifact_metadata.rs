@@ -0,0 +1,75 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Artifact;
+use crate::filestore::artifact_type::ArtifactMetadata as Metadata;
+use crate::schema::artifact_metadata;
+use crate::schema::artifact_metadata::*;
+
+/// One field of the native metadata of an artifact, e.g. the name of an rpm
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Artifact)]
+#[table_name = "artifact_metadata"]
+pub struct ArtifactMetadata {
+    pub id: i32,
+    pub artifact_id: i32,
+    pub kind: String,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "artifact_metadata"]
+struct NewArtifactMetadata<'a> {
+    pub artifact_id: i32,
+    pub kind: &'a str,
+    pub key: &'a str,
+    pub value: &'a str,
+}
+
+impl ArtifactMetadata {
+    /// Record the metadata of the artifact `art` of the kind `artifact_kind`
+    pub fn create_all(
+        database_connection: &PgConnection,
+        art: &Artifact,
+        artifact_kind: &str,
+        metadata: &Metadata,
+    ) -> Result<Vec<ArtifactMetadata>> {
+        let new_metadata = metadata
+            .iter()
+            .map(|(k, v)| NewArtifactMetadata {
+                artifact_id: art.id,
+                kind: artifact_kind,
+                key: k,
+                value: v,
+            })
+            .collect::<Vec<_>>();
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(artifact_metadata::table)
+                .values(&new_metadata)
+                .execute(database_connection)?;
+
+            Self::fetch_for_artifact(database_connection, art)
+        })
+    }
+
+    pub fn fetch_for_artifact(database_connection: &PgConnection, art: &Artifact) -> Result<Vec<ArtifactMetadata>> {
+        dsl::artifact_metadata
+            .filter(artifact_id.eq(art.id))
+            .load::<ArtifactMetadata>(database_connection)
+            .map_err(Error::from)
+    }
+}
@@ -0,0 +1,77 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Artifact;
+use crate::schema::artifact_pins;
+use crate::schema::artifact_pins::*;
+
+/// A pin on an artifact
+///
+/// Pinned artifacts are exempt from any garbage collection or retention policy.
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Artifact)]
+pub struct ArtifactPin {
+    pub id: i32,
+    pub artifact_id: i32,
+    pub reason: String,
+    pub pinned_by: String,
+    pub pinned_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "artifact_pins"]
+struct NewArtifactPin<'a> {
+    pub artifact_id: i32,
+    pub reason: &'a str,
+    pub pinned_by: &'a str,
+    pub pinned_at: &'a NaiveDateTime,
+}
+
+impl ArtifactPin {
+    pub fn create(
+        database_connection: &PgConnection,
+        art: &Artifact,
+        pin_reason: &str,
+        by: &str,
+        date: &NaiveDateTime,
+    ) -> Result<ArtifactPin> {
+        let new_pin = NewArtifactPin {
+            artifact_id: art.id,
+            reason: pin_reason,
+            pinned_by: by,
+            pinned_at: date,
+        };
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(artifact_pins::table)
+                .values(&new_pin)
+                .execute(database_connection)?;
+
+            dsl::artifact_pins
+                .filter(artifact_id.eq(art.id))
+                .first::<ArtifactPin>(database_connection)
+                .map_err(Error::from)
+        })
+    }
+
+    pub fn fetch_for_artifact(database_connection: &PgConnection, art: &Artifact) -> Result<Option<ArtifactPin>> {
+        dsl::artifact_pins
+            .filter(artifact_id.eq(art.id))
+            .first::<ArtifactPin>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+}
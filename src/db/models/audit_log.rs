@@ -0,0 +1,143 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::schema::audit_log;
+use crate::schema::audit_log::*;
+
+/// The `prev_hash` of the very first entry in the audit log
+pub const AUDIT_LOG_GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An entry in the append-only audit log
+///
+/// Each entry contains the hash of the previous entry, so that modifications of the log can be
+/// detected by re-computing the chain of hashes.
+#[derive(Debug, Identifiable, Queryable)]
+#[table_name = "audit_log"]
+pub struct AuditLogEntry {
+    pub id: i32,
+    pub logged_at: NaiveDateTime,
+    pub actor: String,
+    pub operation: String,
+    pub details: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+struct NewAuditLogEntry<'a> {
+    pub logged_at: &'a NaiveDateTime,
+    pub actor: &'a str,
+    pub operation: &'a str,
+    pub details: &'a str,
+    pub prev_hash: &'a str,
+    pub hash: &'a str,
+}
+
+impl AuditLogEntry {
+    /// Append an entry to the audit log
+    ///
+    /// The actor is the user running butido.
+    pub fn append(database_connection: &PgConnection, op: &str, det: &str) -> Result<AuditLogEntry> {
+        let who = std::env::var("USER").unwrap_or_else(|_| String::from("unknown"));
+
+        // The database stores microseconds, so the hash must not be computed from a more precise
+        // timestamp
+        let now = chrono::offset::Local::now().naive_local();
+        let now = NaiveDateTime::from_timestamp(now.timestamp(), now.timestamp_subsec_micros() * 1000);
+
+        database_connection.transaction::<_, Error, _>(|| {
+            // Serialize appends, otherwise two concurrent appends would fork the chain
+            diesel::sql_query("LOCK TABLE audit_log IN EXCLUSIVE MODE").execute(database_connection)?;
+
+            let prev = dsl::audit_log
+                .order_by(id.desc())
+                .first::<AuditLogEntry>(database_connection)
+                .optional()?
+                .map(|entry| entry.hash)
+                .unwrap_or_else(|| String::from(AUDIT_LOG_GENESIS_HASH));
+
+            let h = compute_hash(&prev, &now, &who, op, det);
+            let new_entry = NewAuditLogEntry {
+                logged_at: &now,
+                actor: &who,
+                operation: op,
+                details: det,
+                prev_hash: &prev,
+                hash: &h,
+            };
+
+            diesel::insert_into(audit_log::table)
+                .values(&new_entry)
+                .execute(database_connection)?;
+
+            dsl::audit_log
+                .filter(hash.eq(&h))
+                .first::<AuditLogEntry>(database_connection)
+                .map_err(Error::from)
+        })
+    }
+
+    /// Load all entries of the audit log, oldest first
+    pub fn all(database_connection: &PgConnection) -> Result<Vec<AuditLogEntry>> {
+        dsl::audit_log
+            .order_by(id.asc())
+            .load::<AuditLogEntry>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// Re-compute the hash of this entry from its contents
+    pub fn compute_hash(&self) -> String {
+        compute_hash(&self.prev_hash, &self.logged_at, &self.actor, &self.operation, &self.details)
+    }
+}
+
+fn compute_hash(prev: &str, at: &NaiveDateTime, who: &str, op: &str, det: &str) -> String {
+    use sha2::Digest;
+
+    let at = at.format("%Y-%m-%dT%H:%M:%S%.6f").to_string();
+    let mut m = sha2::Sha256::new();
+    for field in [prev, at.as_str(), who, op, det].iter() {
+        m.update(field.as_bytes());
+        m.update(b"\0");
+    }
+    format!("{:x}", m.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compute_hash;
+    use super::AUDIT_LOG_GENESIS_HASH;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn test_hash_depends_on_all_fields() {
+        let at = NaiveDateTime::from_timestamp(1_614_556_800, 123_000);
+        let h = compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "user", "submit", "details");
+        assert_eq!(h.len(), 64);
+        assert_eq!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "user", "submit", "details"));
+
+        let later = NaiveDateTime::from_timestamp(1_614_556_801, 123_000);
+        assert_ne!(h, compute_hash(&h, &at, "user", "submit", "details"));
+        assert_ne!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &later, "user", "submit", "details"));
+        assert_ne!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "other", "submit", "details"));
+        assert_ne!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "user", "release", "details"));
+        assert_ne!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "user", "submit", "other"));
+
+        // Moving content between fields must change the hash
+        assert_ne!(h, compute_hash(AUDIT_LOG_GENESIS_HASH, &at, "user", "submitdetails", ""));
+    }
+}
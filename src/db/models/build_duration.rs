@@ -0,0 +1,222 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::time::Duration;
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Image;
+use crate::db::models::Package;
+use crate::schema;
+use crate::schema::build_durations;
+use crate::schema::build_durations::*;
+
+/// The number of most recent jobs the rolling average of a build duration is (roughly) taken over
+///
+/// Older jobs lose their weight exponentially, so that a package which got faster or slower to
+/// build is estimated correctly after a few builds.
+const ROLLING_WINDOW: i32 = 10;
+
+/// How long building a package in an image takes, as rolling average over the successful jobs
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Package)]
+#[belongs_to(Image)]
+#[table_name = "build_durations"]
+pub struct BuildDuration {
+    pub id: i32,
+    pub package_id: i32,
+    pub image_id: i32,
+    pub average_seconds: f64,
+
+    /// The number of jobs the average was computed from
+    pub samples: i32,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "build_durations"]
+struct NewBuildDuration<'a> {
+    pub package_id: i32,
+    pub image_id: i32,
+    pub average_seconds: f64,
+    pub samples: i32,
+    pub updated_at: &'a NaiveDateTime,
+}
+
+impl BuildDuration {
+    /// Add the duration of a successful job of `package` in `image` to the rolling average
+    pub fn record(
+        database_connection: &PgConnection,
+        package: &Package,
+        image: &Image,
+        duration: chrono::Duration,
+        date: &NaiveDateTime,
+    ) -> Result<()> {
+        let seconds = duration.num_milliseconds() as f64 / 1000.0;
+
+        database_connection.transaction::<_, Error, _>(|| {
+            let existing = dsl::build_durations
+                .filter(package_id.eq(package.id))
+                .filter(image_id.eq(image.id))
+                .for_update()
+                .first::<BuildDuration>(database_connection)
+                .optional()?;
+
+            match existing {
+                Some(existing) => {
+                    diesel::update(&existing)
+                        .set((
+                            average_seconds.eq(rolling_average(existing.average_seconds, existing.samples, seconds)),
+                            samples.eq(existing.samples + 1),
+                            updated_at.eq(date),
+                        ))
+                        .execute(database_connection)?;
+                }
+
+                // If another job of the package inserted the first sample in the meantime, this
+                // sample is lost, which does not matter for an estimate
+                None => {
+                    let new_duration = NewBuildDuration {
+                        package_id: package.id,
+                        image_id: image.id,
+                        average_seconds: seconds,
+                        samples: 1,
+                        updated_at: date,
+                    };
+
+                    diesel::insert_into(build_durations::table)
+                        .values(&new_duration)
+                        .on_conflict_do_nothing()
+                        .execute(database_connection)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The build durations of all versions of the packages named `package_names`, in all images
+    pub fn expected_for(database_connection: &PgConnection, package_names: &[&str]) -> Result<ExpectedDurations> {
+        Self::list(database_connection, Some(package_names)).map(ExpectedDurations)
+    }
+
+    /// The build durations of the packages named `package_names`, or of all packages if `None`
+    ///
+    /// Ordered by package name, version and image name.
+    pub fn list(database_connection: &PgConnection, package_names: Option<&[&str]>) -> Result<Vec<(BuildDuration, Package, Image)>> {
+        let mut query = dsl::build_durations
+            .inner_join(schema::packages::table)
+            .inner_join(schema::images::table)
+            .into_boxed();
+
+        if let Some(names) = package_names {
+            query = query.filter(schema::packages::name.eq_any(names));
+        }
+
+        query
+            .order_by((schema::packages::name.asc(), schema::packages::version.asc(), schema::images::name.asc()))
+            .load::<(BuildDuration, Package, Image)>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// The average duration
+    pub fn average(&self) -> Duration {
+        Duration::from_secs_f64(self.average_seconds.max(0.0))
+    }
+}
+
+/// The build durations of a set of packages, to estimate how long their jobs take
+pub struct ExpectedDurations(Vec<(BuildDuration, Package, Image)>);
+
+impl ExpectedDurations {
+    /// How long building the package `name` in version `version` in `image` is expected to take
+    ///
+    /// If that version was never built in the image, the average over all versions of the package
+    /// built in the image is used, weighted by how many jobs each average is based on.
+    pub fn get(&self, name: &str, version: &str, image: &str) -> Option<Duration> {
+        let in_image = self.0
+            .iter()
+            .filter(|(_, p, i)| p.name == name && i.name == image)
+            .collect::<Vec<_>>();
+
+        if let Some((d, _, _)) = in_image.iter().find(|(_, p, _)| p.version == version) {
+            return Some(d.average())
+        }
+
+        if in_image.is_empty() {
+            return None
+        }
+
+        let weights = in_image.iter().map(|(d, _, _)| d.samples.min(ROLLING_WINDOW).max(1) as f64).sum::<f64>();
+        let weighted = in_image
+            .iter()
+            .map(|(d, _, _)| d.average_seconds * d.samples.min(ROLLING_WINDOW).max(1) as f64)
+            .sum::<f64>();
+        Some(Duration::from_secs_f64((weighted / weights).max(0.0)))
+    }
+}
+
+/// The average after adding `seconds` to the rolling `average` over `count` earlier jobs
+fn rolling_average(average: f64, count: i32, seconds: f64) -> f64 {
+    let n = (count + 1).min(ROLLING_WINDOW).max(1) as f64;
+    average + (seconds - average) / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(package: (&str, &str), image: &str, average: f64, count: i32) -> (BuildDuration, Package, Image) {
+        let d = BuildDuration {
+            id: 0,
+            package_id: 0,
+            image_id: 0,
+            average_seconds: average,
+            samples: count,
+            updated_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+        };
+        let p = Package { id: 0, name: package.0.to_string(), version: package.1.to_string() };
+        let i = Image { id: 0, name: image.to_string() };
+        (d, p, i)
+    }
+
+    #[test]
+    fn test_rolling_average() {
+        // The first samples are averaged plainly
+        assert_eq!(rolling_average(10.0, 1, 20.0), 15.0);
+        assert_eq!(rolling_average(15.0, 2, 30.0), 20.0);
+
+        // Once the window is full, each new sample has the same weight
+        assert_eq!(rolling_average(10.0, 50, 110.0), 20.0);
+    }
+
+    #[test]
+    fn test_expected_durations() {
+        let expected = ExpectedDurations(vec![
+            duration(("a", "1"), "debian", 100.0, 1),
+            duration(("a", "2"), "debian", 200.0, 3),
+            duration(("a", "1"), "fedora", 50.0, 1),
+        ]);
+
+        assert_eq!(expected.get("a", "1", "debian"), Some(Duration::from_secs(100)));
+        assert_eq!(expected.get("a", "1", "fedora"), Some(Duration::from_secs(50)));
+
+        // Unknown versions get the weighted average of the versions built in the image
+        assert_eq!(expected.get("a", "3", "debian"), Some(Duration::from_secs(175)));
+        assert_eq!(expected.get("a", "3", "fedora"), Some(Duration::from_secs(50)));
+
+        assert_eq!(expected.get("a", "1", "alpine"), None);
+        assert_eq!(expected.get("b", "1", "debian"), None);
+    }
+}
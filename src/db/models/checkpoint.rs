@@ -0,0 +1,129 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Endpoint;
+use crate::schema::checkpoints;
+use crate::schema::checkpoints::*;
+
+/// The state of the checkpoint of a container
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckpointState {
+    /// The container is being checkpointed
+    #[display("checkpointing")]
+    Checkpointing,
+
+    /// The container is checkpointed and stopped, its job was aborted
+    ///
+    /// butido does not restore the container, it is kept for inspection.
+    #[display("checkpointed")]
+    Checkpointed,
+
+    /// Checkpointing the container failed
+    #[display("failed")]
+    Failed,
+}
+
+/// A checkpoint of a container on an endpoint, taken for maintenance of the endpoint
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Endpoint)]
+pub struct Checkpoint {
+    pub id: i32,
+    pub endpoint_id: i32,
+    pub container_id: String,
+    pub job_uuid: Option<::uuid::Uuid>,
+    pub name: String,
+    pub state: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "checkpoints"]
+struct NewCheckpoint<'a> {
+    pub endpoint_id: i32,
+    pub container_id: &'a str,
+    pub job_uuid: Option<&'a ::uuid::Uuid>,
+    pub name: &'a str,
+    pub state: &'a str,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl Checkpoint {
+    /// Record that the container `container` on the endpoint `ep` is about to be checkpointed
+    ///
+    /// The checkpoint is recorded in the `Checkpointing` state before the container is actually
+    /// checkpointed, so that the job running in the container knows that its script was
+    /// interrupted on purpose and is aborted.
+    pub fn create(
+        database_connection: &PgConnection,
+        ep: &Endpoint,
+        container: &str,
+        job: Option<&::uuid::Uuid>,
+        checkpoint_name: &str,
+        date: &NaiveDateTime,
+    ) -> Result<Checkpoint> {
+        let state_name = CheckpointState::Checkpointing.to_string();
+        let new_checkpoint = NewCheckpoint {
+            endpoint_id: ep.id,
+            container_id: container,
+            job_uuid: job,
+            name: checkpoint_name,
+            state: &state_name,
+            created_at: date,
+        };
+
+        diesel::insert_into(checkpoints::table)
+            .values(&new_checkpoint)
+            .get_result::<Checkpoint>(database_connection)
+            .map_err(Error::from)
+    }
+
+    pub fn state(&self) -> Result<CheckpointState> {
+        self.state
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Unknown state of checkpoint {}: {}", self.id, self.state))
+    }
+
+    pub fn set_state(&self, database_connection: &PgConnection, new_state: CheckpointState) -> Result<Checkpoint> {
+        diesel::update(self)
+            .set(state.eq(new_state.to_string()))
+            .get_result::<Checkpoint>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// The newest checkpoint of the container `container` taken after `since`, if any
+    pub fn fetch_latest_for_container(
+        database_connection: &PgConnection,
+        container: &str,
+        since: &NaiveDateTime,
+    ) -> Result<Option<Checkpoint>> {
+        dsl::checkpoints
+            .filter(container_id.eq(container))
+            .filter(created_at.ge(since))
+            .order_by(created_at.desc())
+            .first::<Checkpoint>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// All checkpoints with their endpoints, newest first
+    pub fn list(database_connection: &PgConnection) -> Result<Vec<(Checkpoint, Endpoint)>> {
+        dsl::checkpoints
+            .inner_join(crate::schema::endpoints::table)
+            .order_by(created_at.desc())
+            .load::<(Checkpoint, Endpoint)>(database_connection)
+            .map_err(Error::from)
+    }
+}
@@ -0,0 +1,108 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::package::Package;
+use crate::schema::dag_cache;
+use crate::schema::dag_cache::*;
+
+/// A resolved dag for a root package, image and environment at a commit of the repository
+///
+/// Entries for other commits are removed when a new entry is stored, so the cache is invalidated
+/// when the repository changes.
+#[derive(Debug, Identifiable, Queryable)]
+#[table_name = "dag_cache"]
+pub struct DagCache {
+    pub id: i32,
+    pub root_name: String,
+    pub root_version: String,
+    pub image: String,
+    pub env_hash: String,
+    pub repo_hash: String,
+    pub resolution: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "dag_cache"]
+struct NewDagCache<'a> {
+    pub root_name: &'a str,
+    pub root_version: &'a str,
+    pub image: &'a str,
+    pub env_hash: &'a str,
+    pub repo_hash: &'a str,
+    pub resolution: &'a str,
+    pub created: &'a NaiveDateTime,
+}
+
+impl DagCache {
+    pub fn fetch(
+        database_connection: &PgConnection,
+        root: &Package,
+        image_name: &str,
+        env: &str,
+        hash: &str,
+    ) -> Result<Option<DagCache>> {
+        dsl::dag_cache
+            .filter(root_name.eq(root.name().as_ref() as &str))
+            .filter(root_version.eq(root.version().as_ref() as &str))
+            .filter(image.eq(image_name))
+            .filter(env_hash.eq(env))
+            .filter(repo_hash.eq(hash))
+            .first::<DagCache>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Store the resolution, replacing the entries for other commits of the repository
+    pub fn store(
+        database_connection: &PgConnection,
+        root: &Package,
+        image_name: &str,
+        env: &str,
+        hash: &str,
+        resolved: &str,
+    ) -> Result<()> {
+        let now = chrono::offset::Local::now().naive_local();
+        let new_entry = NewDagCache {
+            root_name: root.name().as_ref(),
+            root_version: root.version().as_ref(),
+            image: image_name,
+            env_hash: env,
+            repo_hash: hash,
+            resolution: resolved,
+            created: &now,
+        };
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::delete(dsl::dag_cache)
+                .filter(root_name.eq(new_entry.root_name))
+                .filter(root_version.eq(new_entry.root_version))
+                .filter(image.eq(image_name))
+                .filter(env_hash.eq(env))
+                .filter(repo_hash.ne(hash))
+                .execute(database_connection)
+                .context("Removing outdated entries from dag_cache table")?;
+
+            diesel::insert_into(dag_cache::table)
+                .values(&new_entry)
+                .on_conflict_do_nothing()
+                .execute(database_connection)
+                .context("Inserting into dag_cache table")
+                .map(|_| ())
+        })
+    }
+}
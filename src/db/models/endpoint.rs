@@ -21,6 +21,7 @@ use crate::schema::endpoints::*;
 pub struct Endpoint {
     pub id: i32,
     pub name: String,
+    pub drained: bool,
 }
 
 #[derive(Insertable)]
@@ -46,6 +47,28 @@ impl Endpoint {
         })
     }
 
+    /// Mark the endpoint as drained (or not drained)
+    ///
+    /// No new jobs are scheduled to a drained endpoint, but jobs that are already running on it
+    /// are not affected.
+    pub fn set_drained(database_connection: &PgConnection, ep_name: &EndpointName, is_drained: bool) -> Result<Endpoint> {
+        let ep = Self::create_or_fetch(database_connection, ep_name)?;
+
+        diesel::update(&ep)
+            .set(drained.eq(is_drained))
+            .get_result::<Endpoint>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// Get the names of all endpoints that are currently drained
+    pub fn drained_names(database_connection: &PgConnection) -> Result<Vec<String>> {
+        dsl::endpoints
+            .filter(drained.eq(true))
+            .select(name)
+            .load::<String>(database_connection)
+            .map_err(Error::from)
+    }
+
     pub fn fetch_for_job(database_connection: &PgConnection, j: &crate::db::models::Job) -> Result<Option<Endpoint>> {
         Self::fetch_by_id(database_connection, j.endpoint_id)
     }
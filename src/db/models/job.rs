@@ -11,14 +11,17 @@
 use anyhow::Error;
 use anyhow::Context;
 use anyhow::Result;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::PgConnection;
 use log::trace;
 
 use crate::db::models::{Endpoint, Image, Package, Submit};
+use crate::job::CacheKey;
 use crate::package::Script;
 use crate::schema::jobs;
 use crate::schema::jobs::*;
+use crate::util::Architecture;
 use crate::util::docker::ContainerHash;
 
 #[derive(Debug, Eq, PartialEq, Identifiable, Queryable, Associations)]
@@ -37,6 +40,19 @@ pub struct Job {
     pub script_text: String,
     pub log_text: String,
     pub uuid: ::uuid::Uuid,
+    pub image_digest: Option<String>,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub maintainers: Vec<String>,
+
+    /// The architecture the job was built for, if it was built for a specific one
+    pub architecture: Option<String>,
+
+    /// The key of the inputs the job was built from, see `crate::job::CacheKey`
+    pub cache_key: Option<String>,
+
+    /// Whether the job was simulated by a mock endpoint, its artifacts are fake then
+    pub simulated: bool,
 }
 
 #[derive(Debug, Insertable)]
@@ -50,6 +66,13 @@ struct NewJob<'a> {
     pub script_text: String,
     pub log_text: String,
     pub uuid: &'a ::uuid::Uuid,
+    pub image_digest: Option<&'a str>,
+    pub started_at: &'a NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub maintainers: &'a [String],
+    pub architecture: Option<&'a str>,
+    pub cache_key: Option<&'a str>,
+    pub simulated: bool,
 }
 
 impl Job {
@@ -61,9 +84,15 @@ impl Job {
         endpoint: &Endpoint,
         package: &Package,
         image: &Image,
+        digest: Option<&str>,
         container: &ContainerHash,
         script: &Script,
         log: &str,
+        started: &NaiveDateTime,
+        job_maintainers: &[String],
+        job_architecture: Option<&Architecture>,
+        job_cache_key: Option<&CacheKey>,
+        job_simulated: bool,
     ) -> Result<Job> {
         let new_job = NewJob {
             uuid: job_uuid,
@@ -71,9 +100,16 @@ impl Job {
             endpoint_id: endpoint.id,
             package_id: package.id,
             image_id: image.id,
+            image_digest: digest,
             container_hash: container.as_ref(),
             script_text: script.as_ref().replace('\0', ""),
             log_text: log.replace('\0', ""),
+            started_at: started,
+            finished_at: chrono::offset::Local::now().naive_local(),
+            maintainers: job_maintainers,
+            architecture: job_architecture.map(AsRef::as_ref),
+            cache_key: job_cache_key.map(AsRef::as_ref),
+            simulated: job_simulated,
         };
 
         trace!("Creating Job in database: {:?}", new_job);
@@ -96,6 +132,15 @@ impl Job {
         })
     }
 
+    /// Find the job with the passed UUID
+    pub fn find_by_uuid(database_connection: &PgConnection, job_uuid: &::uuid::Uuid) -> Result<Option<Job>> {
+        dsl::jobs
+            .filter(uuid.eq(job_uuid))
+            .first::<Job>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
     pub fn env(&self, database_connection: &PgConnection) -> Result<Vec<crate::db::models::EnvVar>> {
         use crate::schema;
 
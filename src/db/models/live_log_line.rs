@@ -0,0 +1,95 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::schema::live_log_lines;
+use crate::schema::live_log_lines::*;
+
+/// A log line of a job that is still running
+///
+/// The lines are recorded while the job runs, so that the job can be followed from another
+/// butido process. Once the job is finished, its log is recorded with the job and the lines are
+/// removed.
+#[derive(Debug, Identifiable, Queryable)]
+pub struct LiveLogLine {
+    pub id: i32,
+    pub job_uuid: ::uuid::Uuid,
+    pub line_number: i32,
+    pub stream: String,
+    pub line: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "live_log_lines"]
+struct NewLiveLogLine<'a> {
+    pub job_uuid: &'a ::uuid::Uuid,
+    pub line_number: i32,
+    pub stream: &'a str,
+    pub line: &'a str,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl LiveLogLine {
+    /// Record the `lines` of the job `job`, as (line number, stream, line)
+    pub fn append(
+        database_connection: &PgConnection,
+        job: &::uuid::Uuid,
+        lines: &[(i32, String, String)],
+        date: &NaiveDateTime,
+    ) -> Result<()> {
+        let new_lines = lines
+            .iter()
+            .map(|(number, strm, l)| NewLiveLogLine {
+                job_uuid: job,
+                line_number: *number,
+                stream: strm,
+                line: l,
+                created_at: date,
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(live_log_lines::table)
+            .values(&new_lines)
+            .execute(database_connection)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// The lines of the job `job` after the line `after`, in order
+    pub fn fetch_after(database_connection: &PgConnection, job: &::uuid::Uuid, after: Option<i32>) -> Result<Vec<LiveLogLine>> {
+        dsl::live_log_lines
+            .filter(job_uuid.eq(job))
+            .filter(line_number.gt(after.unwrap_or(-1)))
+            .order_by(line_number.asc())
+            .load::<LiveLogLine>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// Whether there are any lines of the job `job`
+    pub fn exists_for_job(database_connection: &PgConnection, job: &::uuid::Uuid) -> Result<bool> {
+        diesel::select(diesel::dsl::exists(dsl::live_log_lines.filter(job_uuid.eq(job))))
+            .get_result::<bool>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// Remove the lines of the job `job`
+    pub fn delete_for_job(database_connection: &PgConnection, job: &::uuid::Uuid) -> Result<()> {
+        diesel::delete(dsl::live_log_lines.filter(job_uuid.eq(job)))
+            .execute(database_connection)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
@@ -11,6 +11,27 @@
 mod artifact;
 pub use artifact::*;
 
+mod artifact_checksum;
+pub use artifact_checksum::*;
+
+mod artifact_metadata;
+pub use artifact_metadata::*;
+
+mod artifact_pin;
+pub use artifact_pin::*;
+
+mod audit_log;
+pub use audit_log::*;
+
+mod build_duration;
+pub use build_duration::*;
+
+mod checkpoint;
+pub use checkpoint::*;
+
+mod dag_cache;
+pub use dag_cache::*;
+
 mod endpoint;
 pub use endpoint::*;
 
@@ -29,14 +50,29 @@ pub use job_env::*;
 mod githash;
 pub use githash::*;
 
+mod live_log_line;
+pub use live_log_line::*;
+
 mod package;
 pub use package::*;
 
+mod rebuild_reason;
+pub use rebuild_reason::*;
+
 mod releases;
 pub use releases::*;
 
+mod release_signature;
+pub use release_signature::*;
+
 mod release_store;
 pub use release_store::*;
 
+mod scheduled_submit;
+pub use scheduled_submit::*;
+
 mod submit;
 pub use submit::*;
+
+mod submit_env;
+pub use submit_env::*;
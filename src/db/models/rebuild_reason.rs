@@ -0,0 +1,94 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::db::models::Job;
+use crate::db::ReuseCandidate;
+use crate::db::ReuseMismatch;
+use crate::schema;
+use crate::schema::rebuild_reasons;
+use crate::schema::rebuild_reasons::*;
+
+/// A reason why a job was built instead of reusing the artifacts of an earlier job
+///
+/// One reason is recorded for each criterion an earlier job of the package (the candidate) did
+/// not match. Reasons without a candidate apply to the job as a whole, e.g. because a dependency
+/// was built.
+#[derive(Debug, Identifiable, Queryable)]
+pub struct RebuildReason {
+    pub id: i32,
+    pub job_uuid: ::uuid::Uuid,
+    pub candidate_job_id: Option<i32>,
+    pub kind: String,
+    pub detail: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "rebuild_reasons"]
+struct NewRebuildReason<'a> {
+    pub job_uuid: &'a ::uuid::Uuid,
+    pub candidate_job_id: Option<i32>,
+    pub kind: &'a str,
+    pub detail: String,
+    pub created_at: &'a NaiveDateTime,
+}
+
+impl RebuildReason {
+    /// Record why the job `job` was built
+    ///
+    /// `mismatches` are the reasons that apply to the job as a whole, `candidates` the earlier
+    /// jobs that were not reused.
+    pub fn create(
+        database_connection: &PgConnection,
+        job: &::uuid::Uuid,
+        mismatches: &[ReuseMismatch],
+        candidates: &[ReuseCandidate],
+        date: &NaiveDateTime,
+    ) -> Result<()> {
+        let new_reasons = mismatches
+            .iter()
+            .map(|m| (None, m))
+            .chain({
+                candidates
+                    .iter()
+                    .flat_map(|c| c.mismatches.iter().map(move |m| (Some(c.job.id), m)))
+            })
+            .map(|(candidate, m)| NewRebuildReason {
+                job_uuid: job,
+                candidate_job_id: candidate,
+                kind: m.kind(),
+                detail: m.to_string(),
+                created_at: date,
+            })
+            .collect::<Vec<_>>();
+
+        diesel::insert_into(rebuild_reasons::table)
+            .values(&new_reasons)
+            .execute(database_connection)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// The reasons why the job `job` was built, with the candidate they belong to
+    pub fn fetch_for_job(database_connection: &PgConnection, job: &::uuid::Uuid) -> Result<Vec<(RebuildReason, Option<Job>)>> {
+        dsl::rebuild_reasons
+            .left_join(schema::jobs::table)
+            .filter(job_uuid.eq(job))
+            .order_by(id.asc())
+            .load::<(RebuildReason, Option<Job>)>(database_connection)
+            .map_err(Error::from)
+    }
+}
@@ -0,0 +1,80 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::config::SigningConfig;
+use crate::db::models::Release;
+use crate::schema::release_signatures;
+use crate::schema::release_signatures::*;
+
+/// The detached signature of a released artifact
+#[derive(Debug, Identifiable, Queryable, Associations)]
+#[belongs_to(Release)]
+pub struct ReleaseSignature {
+    pub id: i32,
+    pub release_id: i32,
+    pub tool: String,
+    pub signing_key: String,
+    pub signature: String,
+    pub signed_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "release_signatures"]
+struct NewReleaseSignature<'a> {
+    pub release_id: i32,
+    pub tool: &'a str,
+    pub signing_key: &'a str,
+    pub signature: &'a str,
+    pub signed_at: &'a NaiveDateTime,
+}
+
+impl ReleaseSignature {
+    pub fn create(
+        database_connection: &PgConnection,
+        release: &Release,
+        config: &SigningConfig,
+        sig: &str,
+        date: &NaiveDateTime,
+    ) -> Result<ReleaseSignature> {
+        let tool_name = config.tool().to_string();
+        let new_signature = NewReleaseSignature {
+            release_id: release.id,
+            tool: &tool_name,
+            signing_key: config.key(),
+            signature: sig,
+            signed_at: date,
+        };
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(release_signatures::table)
+                .values(&new_signature)
+                .execute(database_connection)?;
+
+            dsl::release_signatures
+                .filter(release_id.eq(release.id))
+                .first::<ReleaseSignature>(database_connection)
+                .map_err(Error::from)
+        })
+    }
+
+    pub fn fetch_for_release(database_connection: &PgConnection, release: &Release) -> Result<Option<ReleaseSignature>> {
+        dsl::release_signatures
+            .filter(release_id.eq(release.id))
+            .first::<ReleaseSignature>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+}
@@ -0,0 +1,120 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::schema::scheduled_submits;
+use crate::schema::scheduled_submits::*;
+
+/// How many minutes after its start time a scheduled submit which was not started is removed
+const STALE_AFTER_MINUTES: i64 = 10;
+
+/// A submit which waits for the next build window to be started
+///
+/// The entry is removed when the submit is started.
+#[derive(Clone, Debug, Identifiable, Queryable)]
+#[table_name = "scheduled_submits"]
+pub struct ScheduledSubmit {
+    pub id: i32,
+    pub uuid: ::uuid::Uuid,
+    pub package_name: String,
+    pub package_version: String,
+    pub image: String,
+    pub repo_hash: String,
+    pub requested_by: String,
+    pub requested_at: NaiveDateTime,
+    pub scheduled_for: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "scheduled_submits"]
+struct NewScheduledSubmit<'a> {
+    pub uuid: &'a ::uuid::Uuid,
+    pub package_name: &'a str,
+    pub package_version: &'a str,
+    pub image: &'a str,
+    pub repo_hash: &'a str,
+    pub requested_by: &'a str,
+    pub requested_at: &'a NaiveDateTime,
+    pub scheduled_for: &'a NaiveDateTime,
+}
+
+impl ScheduledSubmit {
+    #[allow(clippy::too_many_arguments)]
+    pub fn create(
+        database_connection: &PgConnection,
+        submit_id: &::uuid::Uuid,
+        pkg_name: &str,
+        pkg_version: &str,
+        image_name: &str,
+        hash: &str,
+        requested_datetime: &NaiveDateTime,
+        scheduled_datetime: &NaiveDateTime,
+    ) -> Result<ScheduledSubmit> {
+        let who = std::env::var("USER").unwrap_or_else(|_| String::from("unknown"));
+        let new_entry = NewScheduledSubmit {
+            uuid: submit_id,
+            package_name: pkg_name,
+            package_version: pkg_version,
+            image: image_name,
+            repo_hash: hash,
+            requested_by: &who,
+            requested_at: requested_datetime,
+            scheduled_for: scheduled_datetime,
+        };
+
+        database_connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(scheduled_submits::table)
+                .values(&new_entry)
+                .execute(database_connection)
+                .context("Inserting new scheduled submit into scheduled_submits table")?;
+
+            dsl::scheduled_submits
+                .filter(uuid.eq(submit_id))
+                .first::<ScheduledSubmit>(database_connection)
+                .map_err(Error::from)
+        })
+    }
+
+    /// Remove the entries which were not started in time
+    ///
+    /// The butido process which waits for a scheduled submit removes its entry when it starts the
+    /// submit. An entry that is overdue by more than `STALE_AFTER_MINUTES` belongs to a process which
+    /// was killed (or to a machine which was rebooted), so the submit will never be started.
+    pub fn delete_stale(database_connection: &PgConnection, now: &NaiveDateTime) -> Result<usize> {
+        let deadline = *now - chrono::Duration::minutes(STALE_AFTER_MINUTES);
+        diesel::delete(dsl::scheduled_submits.filter(scheduled_for.lt(deadline)))
+            .execute(database_connection)
+            .context("Removing stale scheduled submits")
+            .map_err(Error::from)
+    }
+
+    /// All scheduled submits, the next one to be started first
+    pub fn all(database_connection: &PgConnection) -> Result<Vec<ScheduledSubmit>> {
+        dsl::scheduled_submits
+            .order_by(scheduled_for.asc())
+            .load::<ScheduledSubmit>(database_connection)
+            .map_err(Error::from)
+    }
+
+    /// Remove the entry, because the submit is started now
+    pub fn delete(self, database_connection: &PgConnection) -> Result<()> {
+        diesel::delete(&self)
+            .execute(database_connection)
+            .map(|_| ())
+            .context("Removing scheduled submit")
+            .map_err(Error::from)
+    }
+}
@@ -32,6 +32,13 @@ pub struct Submit {
     pub requested_image_id: i32,
     pub requested_package_id: i32,
     pub repo_hash_id: i32,
+    pub cancelled: bool,
+
+    /// The version of butido that created the submit, not known for submits of old versions
+    pub butido_version: Option<String>,
+
+    /// The user who created the submit, not known for submits of old versions
+    pub submitted_by: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -42,6 +49,8 @@ struct NewSubmit<'a> {
     pub requested_image_id: i32,
     pub requested_package_id: i32,
     pub repo_hash_id: i32,
+    pub butido_version: &'a str,
+    pub submitted_by: &'a str,
 }
 
 impl Submit {
@@ -53,12 +62,15 @@ impl Submit {
         requested_package: &Package,
         repo_hash: &GitHash,
     ) -> Result<Submit> {
+        let who = std::env::var("USER").unwrap_or_else(|_| String::from("unknown"));
         let new_submit = NewSubmit {
             uuid: submit_id,
             submit_time: submit_datetime,
             requested_image_id: requested_image.id,
             requested_package_id: requested_package.id,
             repo_hash_id: repo_hash.id,
+            butido_version: env!("CARGO_PKG_VERSION"),
+            submitted_by: &who,
         };
 
         database_connection.transaction::<_, Error, _>(|| {
@@ -82,4 +94,22 @@ impl Submit {
             .context("Loading submit")
             .map_err(Error::from)
     }
+
+    /// Find the submit with the passed UUID
+    pub fn find_by_uuid(database_connection: &PgConnection, submit_id: &::uuid::Uuid) -> Result<Option<Submit>> {
+        dsl::submits
+            .filter(submits::uuid.eq(submit_id))
+            .first::<Submit>(database_connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Mark the submit as cancelled by the user
+    pub fn mark_cancelled(&self, database_connection: &PgConnection) -> Result<()> {
+        diesel::update(self)
+            .set(submits::cancelled.eq(true))
+            .execute(database_connection)
+            .with_context(|| format!("Marking submit {} as cancelled", self.uuid))
+            .map(|_| ())
+    }
 }
@@ -0,0 +1,163 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use diesel::PgConnection;
+use indicatif::ProgressBar;
+use itertools::Itertools;
+use log::debug;
+use log::trace;
+use log::warn;
+
+use crate::db::models as dbmodels;
+use crate::package::Dag;
+use crate::package::DagResolution;
+use crate::package::Package;
+use crate::package::condition::ConditionData;
+use crate::repository::Repository;
+
+/// Resolve the dag for `package`, reusing the resolution stored in the database for the same root
+/// package, image, environment, images on the endpoints and repository commit
+///
+/// If the working tree of the repository is not clean, the commit does not identify the package
+/// definitions, so the database is not used at all.
+/// If `read_only` is set, a new resolution is not stored.
+pub fn resolve_dag(
+    database_connection: &PgConnection,
+    read_only: bool,
+    git_repo: &git2::Repository,
+    repo: &Repository,
+    package: Package,
+    progress: Option<&ProgressBar>,
+    condition_data: &ConditionData<'_>,
+) -> Result<Dag> {
+    if !worktree_is_clean(git_repo)? {
+        debug!("Repository is not clean, not using cached dag");
+        return Dag::for_root_package(package, repo, progress, condition_data);
+    }
+
+    let repo_hash = crate::util::git::get_repo_head_commit_hash(git_repo)?;
+    let image = condition_data.image_name.map(|i| i.to_string()).unwrap_or_default();
+    let env_hash = env_hash(condition_data);
+
+    match dbmodels::DagCache::fetch(database_connection, &package, &image, &env_hash, &repo_hash) {
+        Ok(Some(entry)) => {
+            let dag = serde_json::from_str::<DagResolution>(&entry.resolution)
+                .map_err(Error::from)
+                .and_then(|resolution| Dag::from_resolution(&resolution, repo));
+
+            match dag {
+                Ok(dag) => {
+                    debug!("Using dag for {} {} cached at {}", package.name(), package.version(), entry.created);
+                    return Ok(dag);
+                }
+                Err(e) => warn!("Ignoring invalid cached dag for {} {}: {:?}", package.name(), package.version(), e),
+            }
+        }
+        Ok(None) => trace!("No cached dag for {} {}", package.name(), package.version()),
+        Err(e) => warn!("Failed to load cached dag for {} {}: {:?}", package.name(), package.version(), e),
+    }
+
+    let dag = Dag::for_root_package(package.clone(), repo, progress, condition_data)?;
+    if !read_only {
+        let stored = serde_json::to_string(&dag.resolution())
+            .map_err(Error::from)
+            .and_then(|resolution| {
+                dbmodels::DagCache::store(database_connection, &package, &image, &env_hash, &repo_hash, &resolution)
+            });
+
+        if let Err(e) = stored {
+            warn!("Failed to cache dag for {} {}: {:?}", package.name(), package.version(), e);
+        }
+    }
+    Ok(dag)
+}
+
+/// Whether the working tree has neither changed nor untracked files
+fn worktree_is_clean(git_repo: &git2::Repository) -> Result<bool> {
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true).include_ignored(false);
+    git_repo
+        .statuses(Some(&mut opts))
+        .map(|statuses| statuses.is_empty())
+        .map_err(Error::from)
+}
+
+/// Hash of the environment the dag is resolved with, independent of the order of the variables
+///
+/// If the images on the endpoints were queried for conditions on images, they are part of the
+/// hash as well.
+fn env_hash(condition_data: &ConditionData<'_>) -> String {
+    use sha2::Digest;
+
+    let mut m = sha2::Sha256::new();
+    for (k, v) in condition_data.env.iter().sorted() {
+        m.update(k.as_ref().as_bytes());
+        m.update(b"=");
+        m.update(v.as_bytes());
+        m.update(b"\0");
+    }
+    if let Some(info) = condition_data.image_info {
+        for image in info.images.iter().map(AsRef::<str>::as_ref).sorted().dedup() {
+            m.update(b"image:");
+            m.update(image.as_bytes());
+            m.update(b"\0");
+        }
+        for (k, v) in info.labels.iter() {
+            m.update(b"label:");
+            m.update(k.as_bytes());
+            m.update(b"=");
+            m.update(v.as_bytes());
+            m.update(b"\0");
+        }
+    }
+    m.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::EnvironmentVariableName;
+
+    #[test]
+    fn test_env_hash_is_independent_of_order() {
+        let a = (EnvironmentVariableName::from("A"), String::from("1"));
+        let b = (EnvironmentVariableName::from("B"), String::from("2"));
+
+        let env1 = vec![a.clone(), b.clone()];
+        let env2 = vec![b, a.clone()];
+        let env3 = vec![a];
+        let hash = |env: &[(EnvironmentVariableName, String)]| env_hash(&ConditionData { image_name: None, env, image_info: None });
+
+        assert_eq!(hash(&env1), hash(&env2));
+        assert_ne!(hash(&env1), hash(&env3));
+    }
+
+    #[test]
+    fn test_env_hash_includes_image_labels() {
+        use std::collections::BTreeMap;
+
+        use crate::package::condition::ImageInfo;
+
+        let labels = |distro: &str| {
+            let mut labels = BTreeMap::new();
+            labels.insert(String::from("distro"), String::from(distro));
+            labels
+        };
+        let centos7 = ImageInfo::new(Vec::new(), labels("centos7"));
+        let centos8 = ImageInfo::new(Vec::new(), labels("centos8"));
+        let hash = |info: &ImageInfo| env_hash(&ConditionData { image_name: None, env: &[], image_info: Some(info) });
+
+        assert_eq!(hash(&centos7), hash(&ImageInfo::new(Vec::new(), labels("centos7"))));
+        assert_ne!(hash(&centos7), hash(&centos8));
+    }
+}
@@ -0,0 +1,79 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Result;
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use log::debug;
+
+use crate::db::DbPool;
+use crate::db::get_connection;
+use crate::db::models::Checkpoint;
+use crate::db::models::CheckpointState;
+
+/// How often the database is asked whether checkpointing a container finished
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long checkpointing a container may take before the checkpoint is considered failed
+///
+/// If `butido endpoint checkpoint` dies while checkpointing, the checkpoint would be in the
+/// `Checkpointing` state forever.
+const CHECKPOINTING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+/// Helper for finding out whether the script of a job was interrupted because its container was
+/// checkpointed (see `butido endpoint checkpoint`)
+pub struct CheckpointWatch {
+    db: DbPool,
+}
+
+impl CheckpointWatch {
+    pub fn new(db: DbPool) -> Self {
+        CheckpointWatch { db }
+    }
+
+    /// The checkpoint of the container `container_id`, if it was checkpointed after `since`
+    ///
+    /// If the container is still being checkpointed, this waits until checkpointing finished.
+    /// Returns None if the container was not checkpointed or checkpointing it failed, in which
+    /// case the job failed on its own.
+    pub async fn checkpoint_of(&self, container_id: &str, since: &NaiveDateTime) -> Result<Option<Checkpoint>> {
+        loop {
+            let conn = get_connection(&self.db).await?;
+            let checkpoint = match Checkpoint::fetch_latest_for_container(&conn, container_id, since)? {
+                Some(checkpoint) => checkpoint,
+                None => return Ok(None),
+            };
+
+            match checkpoint.state()? {
+                CheckpointState::Checkpointing if is_stale(&checkpoint) => {
+                    checkpoint.set_state(&conn, CheckpointState::Failed)?;
+                    return Err(anyhow!(
+                        "Checkpointing container {} did not finish within {}",
+                        container_id,
+                        humantime::format_duration(CHECKPOINTING_TIMEOUT)
+                    ))
+                }
+                CheckpointState::Checkpointing => {
+                    debug!("Container {} is being checkpointed as '{}', waiting", container_id, checkpoint.name);
+                    drop(conn);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                CheckpointState::Checkpointed => return Ok(Some(checkpoint)),
+                CheckpointState::Failed => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Whether the checkpoint was created longer than `CHECKPOINTING_TIMEOUT` ago
+fn is_stale(checkpoint: &Checkpoint) -> bool {
+    let age = chrono::offset::Local::now().naive_local() - checkpoint.created_at;
+    age.to_std().map(|age| age > CHECKPOINTING_TIMEOUT).unwrap_or(false)
+}
@@ -21,6 +21,10 @@ pub struct EndpointConfiguration {
     #[getset(get = "pub")]
     endpoint: crate::config::Endpoint,
 
+    /// The maximum number of jobs the scheduler runs on the endpoint at the same time
+    #[getset(get = "pub")]
+    max_jobs: usize,
+
     #[getset(get = "pub")]
     #[builder(default)]
     required_images: Vec<ImageName>,
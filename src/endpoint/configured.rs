@@ -8,37 +8,62 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::path::Path;
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::Stdio;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use anyhow::anyhow;
 use futures::FutureExt;
+use futures::Stream;
 use getset::{CopyGetters, Getters};
+use indicatif::ProgressBar;
+use log::info;
 use log::trace;
+use log::warn;
 use result_inspect::ResultInspect;
 use shiplift::Container;
 use shiplift::Docker;
 use shiplift::ExecContainerOptions;
+use shiplift::tty::TtyChunk;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_stream::StreamExt;
 use typed_builder::TypedBuilder;
 
 use crate::config::EndpointName;
+use crate::config::InactivityAction;
+use crate::config::SecurityProfile;
+use crate::endpoint::CheckpointWatch;
 use crate::endpoint::EndpointConfiguration;
+use crate::endpoint::InactivityWatch;
+use crate::endpoint::PhaseWatch;
+use crate::endpoint::ScratchDir;
+use crate::endpoint::scratch;
+use crate::endpoint::util::wait_for_process;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
 use crate::filestore::path::ArtifactPath;
+use crate::filestore::path::FullArtifactPath;
 use crate::job::JobResource;
+use crate::job::JobSecret;
 use crate::job::RunnableJob;
+use crate::job::redact_secrets;
 use crate::log::LogItem;
+use crate::log::LogStream;
 use crate::log::buffer_stream_to_line_stream;
 use crate::package::Script;
+use crate::util::Architecture;
 use crate::util::docker::ContainerHash;
 use crate::util::docker::ImageName;
 
@@ -47,8 +72,7 @@ pub struct Endpoint {
     #[getset(get = "pub")]
     name: EndpointName,
 
-    #[getset(get = "pub")]
-    docker: Docker,
+    backend: EndpointBackend,
 
     #[getset(get_copy = "pub")]
     num_max_jobs: usize,
@@ -59,10 +83,44 @@ pub struct Endpoint {
     #[getset(get = "pub")]
     uri: String,
 
+    /// The time windows in which jobs may be started on this endpoint
+    #[getset(get = "pub")]
+    build_windows: Vec<crate::config::BuildWindow>,
+
+    /// The architectures jobs can be built for on this endpoint
+    #[getset(get = "pub")]
+    architectures: Vec<crate::util::Architecture>,
+
+    /// The images jobs can be run with on this endpoint, any image if empty
+    images: Vec<ImageName>,
+
+    /// The scratch space for the working directories of the jobs on this endpoint
+    #[getset(get = "pub")]
+    scratch: Option<crate::config::ScratchConfig>,
+
+    /// The security settings for the containers on this endpoint
+    #[getset(get = "pub")]
+    security: Option<SecurityProfile>,
+
     #[builder(default)]
     running_jobs: std::sync::atomic::AtomicUsize,
 }
 
+/// How the jobs on an endpoint are run
+pub enum EndpointBackend {
+    /// As containers on a docker (or Podman) daemon
+    Docker(Docker),
+
+    /// As pods on Kubernetes
+    Kubernetes(crate::endpoint::Kubernetes),
+
+    /// As containers, by running the docker (or podman) CLI on a host via SSH
+    Ssh(crate::endpoint::Ssh),
+
+    /// Not at all, the runs are simulated
+    Mock(crate::endpoint::Mock),
+}
+
 impl Debug for Endpoint {
     fn fmt(&self, f: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
         write!(f, "Endpoint({}, max: {})", self.name, self.num_max_jobs)
@@ -71,7 +129,11 @@ impl Debug for Endpoint {
 
 impl Endpoint {
     pub(super) async fn setup(epc: EndpointConfiguration) -> Result<Self> {
-        let ep = Endpoint::setup_endpoint(epc.endpoint_name(), epc.endpoint()).with_context(|| {
+        if *epc.max_jobs() == 0 {
+            return Err(anyhow!("Endpoint {} must allow at least one job", epc.endpoint_name()));
+        }
+
+        let ep = Endpoint::setup_endpoint(epc.endpoint_name(), epc.endpoint(), *epc.max_jobs()).with_context(|| {
             anyhow!(
                 "Setting up endpoint: {} -> {}",
                 epc.endpoint_name(),
@@ -79,8 +141,35 @@ impl Endpoint {
             )
         })?;
 
-        let versions_compat =
-            Endpoint::check_version_compat(epc.required_docker_versions().as_ref(), &ep);
+        // Images and versions are not checked on Kubernetes, the cluster pulls the images itself
+        if let EndpointBackend::Kubernetes(kubernetes) = &ep.backend {
+            let timeout = std::time::Duration::from_secs(epc.endpoint().timeout().unwrap_or(10));
+            let _ = tokio::time::timeout(timeout, kubernetes.check_access())
+                .await
+                .with_context(|| anyhow!("Checking access to {} -> {}", epc.endpoint_name(), epc.endpoint().uri()))??;
+            return Ok(ep);
+        }
+
+        // Mock endpoints have nothing to check
+        if let EndpointBackend::Mock(_) = &ep.backend {
+            return Ok(ep);
+        }
+
+        // The docker versions are only reported by the API, so on endpoints which are connected via
+        // SSH, only the images are checked
+        if let EndpointBackend::Ssh(ssh) = &ep.backend {
+            let timeout = std::time::Duration::from_secs(epc.endpoint().timeout().unwrap_or(10));
+            let checks = async {
+                ssh.check_access().await?;
+                Endpoint::check_images_available(epc.required_images().as_ref(), &ep).await
+            };
+            let _ = tokio::time::timeout(timeout, checks)
+                .await
+                .with_context(|| anyhow!("Checking {} -> {}", epc.endpoint_name(), epc.endpoint().uri()))??;
+            return Ok(ep);
+        }
+
+        let versions_compat = Endpoint::check_version_compat(epc.required_docker_versions().as_ref(), &ep);
         let api_versions_compat =
             Endpoint::check_api_version_compat(epc.required_docker_api_versions().as_ref(), &ep);
         let imgs_avail = Endpoint::check_images_available(epc.required_images().as_ref(), &ep);
@@ -118,32 +207,91 @@ impl Endpoint {
         Ok(ep)
     }
 
-    fn setup_endpoint(ep_name: &EndpointName, ep: &crate::config::Endpoint) -> Result<Endpoint> {
-        match ep.endpoint_type() {
-            crate::config::EndpointType::Http => shiplift::Uri::from_str(ep.uri())
+    /// Create the endpoint without checking whether it is reachable and set up correctly
+    pub(crate) fn setup_endpoint(ep_name: &EndpointName, ep: &crate::config::Endpoint, max_jobs: usize) -> Result<Endpoint> {
+        let connect_http = || {
+            shiplift::Uri::from_str(ep.uri())
                 .map(shiplift::Docker::host)
                 .with_context(|| anyhow!("Connecting to {}", ep.uri()))
                 .map_err(Error::from)
-                .map(|docker| {
-                    Endpoint::builder()
-                        .name(ep_name.clone())
-                        .uri(ep.uri().clone())
-                        .docker(docker)
-                        .num_max_jobs(ep.maxjobs())
-                        .network_mode(ep.network_mode().clone())
-                        .build()
-                }),
-
-            crate::config::EndpointType::Socket => Ok({
+        };
+
+        if let Some(connection) = ep.connection().as_ref() {
+            let cli = match ep.endpoint_type() {
+                crate::config::EndpointType::Http | crate::config::EndpointType::Socket => "docker",
+                crate::config::EndpointType::Podman => "podman",
+                crate::config::EndpointType::Kubernetes => {
+                    return Err(anyhow!("Kubernetes endpoints cannot be connected via SSH"))
+                }
+                crate::config::EndpointType::Mock => {
+                    return Err(anyhow!("Mock endpoints cannot be connected via SSH"))
+                }
+            };
+
+            return Ok({
                 Endpoint::builder()
                     .name(ep_name.clone())
                     .uri(ep.uri().clone())
-                    .num_max_jobs(ep.maxjobs())
+                    .backend(EndpointBackend::Ssh(crate::endpoint::Ssh::new(connection, cli, ep.uri().clone())?))
+                    .num_max_jobs(max_jobs)
                     .network_mode(ep.network_mode().clone())
-                    .docker(shiplift::Docker::unix(ep.uri()))
+                    .build_windows(ep.build_windows().clone())
+                    .architectures(ep.architectures().clone())
+                    .images(ep.images().clone())
+                    .scratch(ep.scratch().clone())
+                    .security(ep.security().clone())
                     .build()
-            }),
+            })
         }
+
+        let backend = match ep.endpoint_type() {
+            crate::config::EndpointType::Http => EndpointBackend::Docker(connect_http()?),
+            crate::config::EndpointType::Socket => EndpointBackend::Docker(shiplift::Docker::unix(ep.uri())),
+
+            // Podman serves the Docker-compatible API either on a (usually rootless) socket or via http
+            crate::config::EndpointType::Podman => if ep.uri().starts_with("http://") || ep.uri().starts_with("https://") {
+                EndpointBackend::Docker(connect_http()?)
+            } else {
+                EndpointBackend::Docker(shiplift::Docker::unix(ep.uri()))
+            },
+
+            crate::config::EndpointType::Kubernetes => {
+                if ep.scratch().is_some() {
+                    return Err(anyhow!("Kubernetes endpoints do not support scratch space"))
+                }
+                if ep.security().is_some() {
+                    return Err(anyhow!("Kubernetes endpoints do not support security settings"))
+                }
+
+                EndpointBackend::Kubernetes({
+                    crate::endpoint::Kubernetes::new(ep.uri().clone(), ep.kubeconfig().clone(), ep.namespace().clone())?
+                })
+            }
+
+            // The security settings are ignored, there are no containers they could apply to
+            crate::config::EndpointType::Mock => {
+                if ep.scratch().is_some() {
+                    return Err(anyhow!("Mock endpoints do not support scratch space"))
+                }
+
+                EndpointBackend::Mock(crate::endpoint::Mock::new(ep.mock().clone().unwrap_or_default())?)
+            }
+        };
+
+        Ok({
+            Endpoint::builder()
+                .name(ep_name.clone())
+                .uri(ep.uri().clone())
+                .backend(backend)
+                .num_max_jobs(max_jobs)
+                .network_mode(ep.network_mode().clone())
+                .build_windows(ep.build_windows().clone())
+                .architectures(ep.architectures().clone())
+                .images(ep.images().clone())
+                .scratch(ep.scratch().clone())
+                .security(ep.security().clone())
+                .build()
+        })
     }
 
     async fn check_version_compat(req: Option<&Vec<String>>, ep: &Endpoint) -> Result<()> {
@@ -151,7 +299,7 @@ impl Endpoint {
             None => Ok(()),
             Some(v) => {
                 let avail = ep
-                    .docker()
+                    .docker()?
                     .version()
                     .await
                     .with_context(|| anyhow!("Getting version of endpoint: {}", ep.name))?;
@@ -175,7 +323,7 @@ impl Endpoint {
             None => Ok(()),
             Some(v) => {
                 let avail = ep
-                    .docker()
+                    .docker()?
                     .version()
                     .await
                     .with_context(|| anyhow!("Getting API version of endpoint: {}", ep.name))?;
@@ -191,31 +339,16 @@ impl Endpoint {
     }
 
     async fn check_images_available(imgs: &[ImageName], ep: &Endpoint) -> Result<()> {
-        use shiplift::ImageListOptions;
-
         trace!("Checking availability of images: {:?}", imgs);
-        let available_names = ep
-            .docker()
-            .images()
-            .list(&ImageListOptions::builder().all().build())
-            .await
-            .with_context(|| anyhow!("Listing images on endpoint: {}", ep.name))?
-            .into_iter()
-            .map(|image_rep| {
-                image_rep
-                    .repo_tags
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(ImageName::from)
-            })
-            .flatten()
-            .collect::<Vec<ImageName>>();
+        let available_names = ep.image_names()
+            .await?
+            .ok_or_else(|| anyhow!("Endpoint {} cannot list its images", ep.name))?;
 
         trace!("Available images = {:?}", available_names);
 
         imgs.iter()
             .map(|img| {
-                if !available_names.contains(img) {
+                if !available_names.iter().any(|name| name.is_same_image(img)) {
                     Err(anyhow!(
                         "Image '{}' missing from endpoint '{}'",
                         img.as_ref(),
@@ -229,6 +362,28 @@ impl Endpoint {
             .map(|_| ())
     }
 
+    /// The docker daemon of the endpoint
+    ///
+    /// Fails for Kubernetes and mock endpoints, which are not controlled via a docker daemon.
+    fn docker(&self) -> Result<&Docker> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => Ok(docker),
+            EndpointBackend::Kubernetes(_) => Err(anyhow!("Endpoint {} is a Kubernetes endpoint, which does not support this operation", self.name)),
+            EndpointBackend::Ssh(_) => Err(anyhow!("Endpoint {} is connected via SSH, which does not support this operation", self.name)),
+            EndpointBackend::Mock(_) => Err(anyhow!("Endpoint {} is a mock endpoint, which does not support this operation", self.name)),
+        }
+    }
+
+    /// The command to use to debug the job in the container (or pod) with the passed ID
+    pub fn debug_command(&self, container_id: &str) -> String {
+        match &self.backend {
+            EndpointBackend::Docker(_) => format!("docker --host {} exec -it {} /bin/bash", self.uri, container_id),
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.debug_command(container_id),
+            EndpointBackend::Ssh(ssh) => ssh.debug_command(container_id),
+            EndpointBackend::Mock(mock) => mock.debug_command(container_id),
+        }
+    }
+
     pub async fn prepare_container(
         &self,
         job: RunnableJob,
@@ -238,10 +393,93 @@ impl Endpoint {
         PreparedContainer::new(self, job, staging_store, release_stores).await
     }
 
+    /// Stop and remove the container (or pod) with the passed ID, for example because its job
+    /// was cancelled
+    pub async fn remove_container(&self, container_id: &str) -> Result<()> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => {
+                let container = docker.containers().get(container_id);
+
+                // Fails if the container was not started yet, which is fine
+                if let Err(e) = container.stop(Some(std::time::Duration::new(1, 0))).await {
+                    trace!("Stopping container {} failed: {}", container_id, e);
+                }
+
+                container
+                    .delete()
+                    .await
+                    .with_context(|| anyhow!("Removing container {}", container_id))
+                    .map_err(Error::from)
+            }
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.delete_pod(container_id).await,
+            EndpointBackend::Ssh(ssh) => ssh.remove(container_id).await,
+            EndpointBackend::Mock(mock) => mock.remove(container_id),
+        }
+    }
+
+    /// Write `secrets` to the started container `container_id`
+    ///
+    /// The secrets directory is a tmpfs, which the container engines cannot copy files to, so each
+    /// file is written by a shell in the container. It is only readable by the user of the container.
+    async fn write_secrets(&self, container_id: &str, secrets: &[JobSecret]) -> Result<()> {
+        for secret in secrets.iter() {
+            let path = secret.path().display().to_string();
+            let cmd = ["sh", "-c", "umask 077 && cat > \"$1\"", "sh", path.as_str()];
+            match &self.backend {
+                EndpointBackend::Mock(mock) => mock.write_secret(container_id, &path),
+                _ => self.run_in_container(container_id, &cmd, secret.value().expose().as_bytes()).await,
+            }
+            .with_context(|| anyhow!("Writing secret '{}' to container {} on '{}'", secret.name(), container_id, self.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the files of `secrets` from the container `container_id`
+    async fn remove_secrets(&self, container_id: &str, secrets: &[JobSecret]) -> Result<()> {
+        if secrets.is_empty() {
+            return Ok(())
+        }
+
+        let paths = secrets.iter().map(|secret| secret.path().display().to_string()).collect::<Vec<_>>();
+        let mut cmd = vec!["rm", "-f", "--"];
+        cmd.extend(paths.iter().map(String::as_str));
+        match &self.backend {
+            EndpointBackend::Mock(mock) => mock.remove_secrets(container_id, &cmd[3..]),
+            _ => self.run_in_container(container_id, &cmd, &[]).await,
+        }
+        .with_context(|| anyhow!("Removing the secrets from container {} on '{}'", container_id, self.name))
+    }
+
+    /// Run `cmd` in the container `container_id` with `input` on its stdin and wait for it to
+    /// finish
+    async fn run_in_container(&self, container_id: &str, cmd: &[&str], input: &[u8]) -> Result<()> {
+        match &self.backend {
+            EndpointBackend::Docker(_) => {
+                let mut args = vec!["exec", "--interactive", container_id];
+                args.extend(cmd);
+                self.run_docker_cli(&args, Some(input)).await.map(|_| ())
+            }
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.run_in_pod(container_id, cmd, input).await,
+            EndpointBackend::Ssh(ssh) => ssh.run_in_container(container_id, cmd, input).await,
+            EndpointBackend::Mock(_) => Err(anyhow!("Commands cannot be run in mock container {}", container_id)),
+        }
+    }
+
     pub fn running_jobs(&self) -> usize {
         self.running_jobs.load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Whether jobs with `image` can be run on this endpoint
+    pub fn supports_image(&self, image: &ImageName) -> bool {
+        self.images.is_empty() || self.images.contains(image)
+    }
+
+    /// Whether the jobs on this endpoint are only simulated
+    pub fn is_mock(&self) -> bool {
+        matches!(self.backend, EndpointBackend::Mock(_))
+    }
+
     /// Super non-scientific utilization calculation for the endpoint
     pub fn utilization(&self) -> f64 {
         let max_jobs = self.num_max_jobs() as f64;
@@ -252,11 +490,331 @@ impl Endpoint {
 
     /// Ping the endpoint (once)
     pub async fn ping(&self) -> Result<String> {
-        self.docker.ping().await.map_err(Error::from)
+        match &self.backend {
+            EndpointBackend::Docker(docker) => docker.ping().await.map_err(Error::from),
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.check_access().await.map(|_| String::from("OK")),
+            EndpointBackend::Ssh(ssh) => ssh.check_access().await.map(|_| String::from("OK")),
+            EndpointBackend::Mock(_) => Ok(String::from("OK")),
+        }
+    }
+
+    /// The version of the container engine (or the Kubernetes cluster) of the endpoint
+    pub async fn engine_version(&self) -> Result<String> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => docker
+                .version()
+                .await
+                .map(|v| format!("{} (API {})", v.version, v.api_version))
+                .with_context(|| anyhow!("Getting version of endpoint: {}", self.name))
+                .map_err(Error::from),
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.server_version().await,
+            EndpointBackend::Ssh(ssh) => ssh.version().await,
+            EndpointBackend::Mock(_) => Ok(String::from("mock")),
+        }
+    }
+
+    /// The number of containers (or pods) created by butido which are running on the endpoint
+    ///
+    /// Unlike `running_jobs()`, this also counts the containers of other butido processes.
+    pub async fn running_butido_containers(&self) -> Result<usize> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => docker
+                .containers()
+                .list(&shiplift::builder::ContainerListOptions::builder()
+                    .filter(vec![shiplift::builder::ContainerFilter::LabelName(crate::consts::CONTAINER_LABEL.to_string())])
+                    .build())
+                .await
+                .map(|containers| containers.len())
+                .with_context(|| anyhow!("Listing containers on endpoint: {}", self.name))
+                .map_err(Error::from),
+            EndpointBackend::Kubernetes(kubernetes) => kubernetes.running_pods().await,
+            EndpointBackend::Ssh(ssh) => ssh.running_containers().await,
+            EndpointBackend::Mock(mock) => mock.running_containers(),
+        }
+    }
+
+    /// The IDs of the running containers created by butido, with the UUIDs of their jobs
+    pub async fn butido_containers(&self) -> Result<Vec<(String, String)>> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => docker
+                .containers()
+                .list(&shiplift::builder::ContainerListOptions::builder()
+                    .filter(vec![shiplift::builder::ContainerFilter::LabelName(crate::consts::CONTAINER_LABEL.to_string())])
+                    .build())
+                .await
+                .map(|containers| {
+                    containers
+                        .into_iter()
+                        .map(|c| {
+                            let job = c.labels.get(crate::consts::CONTAINER_LABEL).cloned().unwrap_or_default();
+                            (c.id, job)
+                        })
+                        .collect()
+                })
+                .with_context(|| anyhow!("Listing containers on endpoint: {}", self.name))
+                .map_err(Error::from),
+            EndpointBackend::Kubernetes(_) => Err(anyhow!("Endpoint {} is a Kubernetes endpoint, which does not support checkpoints", self.name)),
+            EndpointBackend::Ssh(ssh) => ssh.butido_containers().await,
+            EndpointBackend::Mock(mock) => mock.butido_containers(),
+        }
+    }
+
+    /// Checkpoint the container with the passed ID to disk as `name`, which stops the container
+    ///
+    /// This requires CRIU on the endpoint and, for docker, a daemon with experimental features
+    /// enabled. Shiplift does not support checkpoints, so the docker CLI is used.
+    pub async fn checkpoint_container(&self, container_id: &str, name: &str) -> Result<()> {
+        match &self.backend {
+            EndpointBackend::Docker(_) => self.run_docker_cli(&["checkpoint", "create", container_id, name], None).await.map(|_| ()),
+            EndpointBackend::Kubernetes(_) => Err(anyhow!("Endpoint {} is a Kubernetes endpoint, which does not support checkpoints", self.name)),
+            EndpointBackend::Ssh(ssh) => ssh.checkpoint(container_id, name).await,
+            EndpointBackend::Mock(_) => Err(anyhow!("Endpoint {} is a mock endpoint, which does not support checkpoints", self.name)),
+        }
+        .with_context(|| anyhow!("Checkpointing container {} on {}", container_id, self.name))
+    }
+
+    /// Run the local docker CLI against the daemon of this endpoint, with `stdin` as input, and
+    /// return its output
+    async fn run_docker_cli<S: AsRef<str>>(&self, args: &[S], stdin: Option<&[u8]>) -> Result<String> {
+        let host = if self.uri.starts_with('/') {
+            format!("unix://{}", self.uri)
+        } else if let Some(rest) = self.uri.strip_prefix("http://") {
+            format!("tcp://{}", rest)
+        } else {
+            self.uri.clone()
+        };
+
+        let mut child = tokio::process::Command::new("docker")
+            .arg("--host")
+            .arg(&host)
+            .args(args.iter().map(AsRef::as_ref))
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Running docker")?;
+
+        if let Some(input) = stdin {
+            let mut pipe = child.stdin.take().ok_or_else(|| anyhow!("No stdin for docker"))?;
+            pipe.write_all(input).await.context("Writing to docker")?;
+        } // stdin is closed here, so the CLI can finish
+
+        let output = child.wait_with_output().await.context("Running docker")?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            let args = args.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+            Err(anyhow!("docker {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    /// The working directory of the job `job` on the endpoint host, if the endpoint has scratch
+    /// space
+    pub fn scratch_dir(&self, job: &uuid::Uuid) -> Option<PathBuf> {
+        self.scratch.as_ref().map(|s| s.job_dir(job))
+    }
+
+    /// Create the working directory of the job `job` in the scratch space, if the endpoint has
+    /// scratch space
+    pub async fn allocate_scratch_dir(&self, job: &uuid::Uuid) -> Result<()> {
+        if let Some(dir) = self.scratch_dir(job) {
+            trace!("Creating scratch directory {} on {}", dir.display(), self.name);
+            self.run_scratch_script(&scratch::allocate_script(&dir))
+                .await
+                .with_context(|| anyhow!("Creating scratch directory {} on {}", dir.display(), self.name))?;
+        }
+        Ok(())
+    }
+
+    /// Fail if the working directory of the job `job` uses more space than the quota allows
+    pub async fn check_scratch_quota(&self, job: &uuid::Uuid) -> Result<()> {
+        let quota = match self.scratch.as_ref().map(|s| s.quota_bytes()).transpose()?.flatten() {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        let dir = self.scratch_dir(job).ok_or_else(|| anyhow!("BUG: quota without scratch space"))?;
+        let used = self.run_scratch_script(&scratch::usage_script(&dir))
+            .await
+            .and_then(|out| scratch::parse_usage(&out))
+            .with_context(|| anyhow!("Checking space used by {} on {}", dir.display(), self.name))?;
+
+        if used > quota {
+            Err(anyhow!(
+                "Job {} used {} bytes in its working directory on {}, the quota is {} bytes",
+                job,
+                used,
+                self.name,
+                quota
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove the working directory of the job `job` after its artifacts were harvested
+    ///
+    /// The directories of failed jobs are kept if the endpoint is configured to keep them for a
+    /// while, `butido endpoint scratch --clean` removes them later.
+    pub async fn release_scratch_dir(&self, job: &uuid::Uuid, failed: bool) -> Result<()> {
+        match self.scratch.as_ref() {
+            None => Ok(()),
+            Some(s) if failed && s.keep_failed_hours() > 0 => {
+                info!("Keeping scratch directory of failed job {} on {} for {} hours", job, self.name, s.keep_failed_hours());
+                Ok(())
+            }
+            Some(_) => self.remove_scratch_dir(job).await,
+        }
+    }
+
+    /// Remove the working directory of the job `job`
+    pub async fn remove_scratch_dir(&self, job: &uuid::Uuid) -> Result<()> {
+        let dir = self.scratch_dir(job)
+            .ok_or_else(|| anyhow!("Endpoint {} has no scratch space", self.name))?;
+        trace!("Removing scratch directory {} on {}", dir.display(), self.name);
+        self.run_scratch_script(&scratch::remove_script(&dir))
+            .await
+            .map(|_| ())
+            .with_context(|| anyhow!("Removing scratch directory {} on {}", dir.display(), self.name))
+    }
+
+    /// The working directories in the scratch space of the endpoint
+    pub async fn scratch_dirs(&self) -> Result<Vec<ScratchDir>> {
+        let root = self.scratch
+            .as_ref()
+            .map(|s| s.root().clone())
+            .ok_or_else(|| anyhow!("Endpoint {} has no scratch space", self.name))?;
+
+        self.run_scratch_script(&scratch::list_script(&root))
+            .await
+            .and_then(|out| scratch::parse_listing(&out))
+            .with_context(|| anyhow!("Listing scratch directories on {}", self.name))
+    }
+
+    /// Run the shell script `script` with access to the scratch space and return its output
+    ///
+    /// Via SSH, the script is run on the host. On docker (and podman) endpoints, it is run in a
+    /// helper container which has the scratch root mounted at the same path as on the host.
+    async fn run_scratch_script(&self, script: &str) -> Result<String> {
+        let scratch = self.scratch
+            .as_ref()
+            .ok_or_else(|| anyhow!("Endpoint {} has no scratch space", self.name))?;
+
+        match &self.backend {
+            EndpointBackend::Docker(docker) => {
+                let root = scratch.root().display().to_string();
+                let bind = format!("{}:{}", root, root);
+                let opts = shiplift::ContainerOptions::builder(scratch.image())
+                    .cmd(vec!["sh", "-c", script])
+                    .volumes(vec![bind.as_str()])
+                    .network_mode("none")
+                    .build();
+
+                let create_info = docker
+                    .containers()
+                    .create(&opts)
+                    .await
+                    .with_context(|| anyhow!("Creating helper container on '{}'", self.name))?;
+                let container = docker.containers().get(&create_info.id);
+
+                let output = async {
+                    container.start().await?;
+                    let exit = container.wait().await?;
+                    let chunks = container
+                        .logs(&shiplift::LogsOptions::builder().stdout(true).stderr(true).build())
+                        .collect::<std::result::Result<Vec<_>, _>>()
+                        .await?;
+                    Ok::<_, shiplift::Error>((exit, chunks))
+                }
+                .await
+                .with_context(|| anyhow!("Running helper container {} on '{}'", create_info.id, self.name));
+
+                // The helper container is removed in any case, the error of the script wins
+                let removed = container
+                    .delete()
+                    .await
+                    .with_context(|| anyhow!("Removing helper container {} on '{}'", create_info.id, self.name));
+
+                let (exit, chunks) = output?;
+                removed?;
+
+                let (mut stdout, mut stderr) = (vec![], vec![]);
+                for chunk in chunks {
+                    match chunk {
+                        TtyChunk::StdOut(buf) => stdout.extend(buf),
+                        TtyChunk::StdErr(buf) => stderr.extend(buf),
+                        TtyChunk::StdIn(_) => {}
+                    }
+                }
+
+                if exit.status_code == 0 {
+                    Ok(String::from_utf8_lossy(&stdout).into_owned())
+                } else {
+                    Err(anyhow!("Script failed ({}): {}", exit.status_code, String::from_utf8_lossy(&stderr).trim()))
+                }
+            }
+            EndpointBackend::Kubernetes(_) => Err(anyhow!("Endpoint {} is a Kubernetes endpoint, which does not support scratch space", self.name)),
+            EndpointBackend::Ssh(ssh) => ssh.run_shell(script).await,
+            EndpointBackend::Mock(_) => Err(anyhow!("Endpoint {} is a mock endpoint, which does not support scratch space", self.name)),
+        }
+    }
+
+    /// The names of the images available on the endpoint
+    ///
+    /// Besides the "name:tag" names, this contains the "name@digest" references of the images
+    /// that were pulled from a registry.
+    /// Returns None for Kubernetes endpoints, where images are pulled by the nodes when needed, and
+    /// for mock endpoints, which do not use images.
+    pub async fn image_names(&self) -> Result<Option<Vec<ImageName>>> {
+        let tags = match &self.backend {
+            EndpointBackend::Docker(docker) => docker
+                .images()
+                .list(&shiplift::builder::ImageListOptions::builder().all().build())
+                .await
+                .with_context(|| anyhow!("Listing images on endpoint: {}", self.name))?
+                .into_iter()
+                .map(|image_rep| {
+                    let mut names = image_rep.repo_tags.unwrap_or_default();
+                    names.extend(image_rep.repo_digests.unwrap_or_default());
+                    names
+                })
+                .flatten()
+                .collect::<Vec<String>>(),
+            EndpointBackend::Kubernetes(_) => return Ok(None),
+            EndpointBackend::Ssh(ssh) => ssh.image_names().await?,
+            EndpointBackend::Mock(_) => return Ok(None),
+        };
+
+        Ok(Some(tags.into_iter().map(ImageName::from_endpoint_tag).collect()))
+    }
+
+    /// Pull `image` on the endpoint, the progress is shown as message of `bar`
+    ///
+    /// Kubernetes endpoints do not pull images, the nodes pull them when a pod needs them. Mock
+    /// endpoints do not use images at all.
+    pub async fn pull_image(&self, image: &ImageName, bar: &ProgressBar) -> Result<()> {
+        match &self.backend {
+            EndpointBackend::Docker(docker) => {
+                let opts = shiplift::builder::PullOptions::builder().image(image.as_ref()).build();
+                let images = docker.images();
+                let mut stream = images.pull(&opts);
+                while let Some(status) = stream.next().await {
+                    let status = status.with_context(|| anyhow!("Pulling image {} on {}", image, self.name))?;
+                    if let Some(msg) = status.get("status").and_then(|s| s.as_str()) {
+                        let progress = status.get("progress").and_then(|p| p.as_str()).unwrap_or_default();
+                        bar.set_message(format!("Pulling {} on {}: {} {}", image, self.name, msg, progress));
+                    }
+                }
+                Ok(())
+            }
+            EndpointBackend::Kubernetes(_) | EndpointBackend::Mock(_) => Ok(()),
+            EndpointBackend::Ssh(ssh) => ssh.pull_image(image.as_ref()).await,
+        }
     }
 
     pub async fn stats(&self) -> Result<EndpointStats> {
-        self.docker
+        self.docker()?
             .info()
             .await
             .map(EndpointStats::from)
@@ -264,7 +822,7 @@ impl Endpoint {
     }
 
     pub async fn container_stats(&self) -> Result<Vec<ContainerStat>> {
-        self.docker
+        self.docker()?
             .containers()
             .list({
                 &shiplift::builder::ContainerListOptions::builder()
@@ -281,24 +839,6 @@ impl Endpoint {
             })
     }
 
-    pub async fn number_of_running_containers(&self) -> Result<usize> {
-        self.docker
-            .containers()
-            .list({
-                &shiplift::builder::ContainerListOptions::builder()
-                .all()
-                .build()
-            })
-            .await
-            .map_err(Error::from)
-            .map(|list| {
-                list.into_iter()
-                    .inspect(|stat| trace!("stat = {:?}", stat))
-                    .filter(|stat| stat.state == "running")
-                    .count()
-            })
-    }
-
     pub async fn has_container_with_id(&self, id: &str) -> Result<bool> {
         self.container_stats()
             .await?
@@ -311,12 +851,54 @@ impl Endpoint {
 
     pub async fn get_container_by_id(&self, id: &str) -> Result<Option<Container<'_>>> {
         if self.has_container_with_id(id).await? {
-            Ok(Some(self.docker.containers().get(id)))
+            Ok(Some(self.docker()?.containers().get(id)))
         } else {
             Ok(None)
         }
     }
 
+    /// Get the digest (ID) of the image with the passed name on this endpoint
+    ///
+    /// Returns None for Kubernetes endpoints, where the image is only resolved on the node a pod
+    /// runs on, and for mock endpoints.
+    pub async fn image_digest(&self, image: &ImageName) -> Result<Option<String>> {
+        let docker = match &self.backend {
+            EndpointBackend::Docker(docker) => docker,
+            EndpointBackend::Kubernetes(_) | EndpointBackend::Mock(_) => return Ok(None),
+            EndpointBackend::Ssh(ssh) => return ssh.image_digest(image.as_ref()).await.map(Some),
+        };
+
+        docker
+            .images()
+            .get(image.as_ref())
+            .inspect()
+            .await
+            .map(|details| Some(details.id))
+            .with_context(|| anyhow!("Inspecting image {} on {}", image, self.name))
+            .map_err(Error::from)
+    }
+
+    /// Get the labels of the image with the passed name on this endpoint
+    ///
+    /// Returns None for Kubernetes endpoints, where the image is only resolved on the node a pod
+    /// runs on, and for mock endpoints.
+    pub async fn image_labels(&self, image: &ImageName) -> Result<Option<BTreeMap<String, String>>> {
+        let docker = match &self.backend {
+            EndpointBackend::Docker(docker) => docker,
+            EndpointBackend::Kubernetes(_) | EndpointBackend::Mock(_) => return Ok(None),
+            EndpointBackend::Ssh(ssh) => return ssh.image_labels(image.as_ref()).await.map(Some),
+        };
+
+        docker
+            .images()
+            .get(image.as_ref())
+            .inspect()
+            .await
+            .map(|details| Some(details.config.labels.unwrap_or_default().into_iter().collect()))
+            .with_context(|| anyhow!("Inspecting image {} on {}", image, self.name))
+            .map_err(Error::from)
+    }
+
     pub async fn images(&self, name_filter: Option<&str>) -> Result<impl Iterator<Item = Image>> {
         let mut listopts = shiplift::builder::ImageListOptions::builder();
 
@@ -326,7 +908,7 @@ impl Endpoint {
             listopts.all();
         }
 
-        self.docker
+        self.docker()?
             .images()
             .list(&listopts.build())
             .await
@@ -411,25 +993,50 @@ impl From<shiplift::rep::Image> for Image {
         Image {
             created: img.created,
             id: img.id,
-            tags: img.repo_tags,
+            tags: img.repo_tags.map(|tags| {
+                tags.into_iter()
+                    .map(|tag| ImageName::from_endpoint_tag(tag).to_string())
+                    .collect()
+            }),
         }
     }
 }
 
-pub struct EndpointHandle(Arc<Endpoint>);
+/// A slot for running one job on an endpoint
+///
+/// When the handle is dropped, the slot is freed and everyone waiting on the passed notification is
+/// notified.
+pub struct EndpointHandle(Arc<Endpoint>, Arc<tokio::sync::Notify>);
 
 impl EndpointHandle {
-    pub fn new(ep: Arc<Endpoint>) -> Self {
-        let res = ep.running_jobs.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        trace!("Endpoint {} has one job more: {}", ep.name(), res + 1);
-        EndpointHandle(ep)
+    /// Reserve a slot for a job on the endpoint
+    ///
+    /// Returns None if the maximum number of jobs is already running on the endpoint.
+    pub fn try_reserve(ep: Arc<Endpoint>, job_finished: Arc<tokio::sync::Notify>) -> Option<Self> {
+        use std::sync::atomic::Ordering;
+
+        let max_jobs = ep.num_max_jobs;
+        ep.running_jobs
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |running| {
+                if running < max_jobs {
+                    Some(running + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|res| {
+                trace!("Endpoint {} has one job more: {}", ep.name(), res + 1);
+                EndpointHandle(ep, job_finished)
+            })
     }
 }
 
 impl Drop for EndpointHandle {
     fn drop(&mut self) {
-        let res = self.0.running_jobs.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        let res = self.0.running_jobs.fetch_sub(1, std::sync::atomic::Ordering::AcqRel);
         trace!("Endpoint {} has one job less: {}", self.0.name(), res - 1);
+        self.1.notify_waiters();
     }
 }
 
@@ -447,8 +1054,21 @@ pub struct PreparedContainer<'a> {
     endpoint: &'a Endpoint,
     script: Script,
 
+    /// Where the script was copied to in the container
+    script_path: &'static str,
+
+    /// How long the phases of the script may run, by the name of the phase
+    phase_timeouts: HashMap<String, Duration>,
+
+    /// How long the script may run without writing to its log and what to do afterwards
+    inactivity: Option<(Duration, InactivityAction)>,
+
+    /// The ID of the container, or the name of the pod on Kubernetes endpoints
     #[getset(get = "pub")]
-    create_info: shiplift::rep::ContainerCreateInfo,
+    container_id: String,
+
+    /// The secrets of the job, which are redacted from its log
+    secrets: Vec<JobSecret>,
 }
 
 impl<'a> PreparedContainer<'a> {
@@ -459,20 +1079,93 @@ impl<'a> PreparedContainer<'a> {
         release_stores: Vec<Arc<ReleaseStore>>,
     ) -> Result<PreparedContainer<'a>> {
         let script = job.script().clone();
-        let create_info = Self::build_container(endpoint, &job).await?;
-        let container = endpoint.docker.containers().get(&create_info.id);
+        let security = job.security().or(endpoint.security().as_ref());
+        let phase_timeouts = job.limits().phase_timeout_durations()?;
+        let inactivity = job.limits().inactivity()?;
+        endpoint.allocate_scratch_dir(job.uuid()).await?;
+        let container_id = match &endpoint.backend {
+            EndpointBackend::Docker(docker) => {
+                Self::prepare_docker_container(endpoint, docker, &job, &script, &security, staging_store, &release_stores).await?
+            }
+            EndpointBackend::Kubernetes(kubernetes) => {
+                if !security.is_empty() {
+                    return Err(anyhow!("Kubernetes endpoints do not support security settings, but they are set for image {}", job.image()))
+                }
+                Self::prepare_pod(endpoint, kubernetes, &job, &script, staging_store, &release_stores).await?
+            }
+            EndpointBackend::Ssh(ssh) => {
+                Self::prepare_ssh_container(endpoint, ssh, &job, &script, &security, staging_store, &release_stores).await?
+            }
+            EndpointBackend::Mock(mock) => {
+                mock.create(&job).with_context(|| anyhow!("Creating mock container on '{}'", endpoint.name))?
+            }
+        };
+
+        Ok({
+            PreparedContainer {
+                endpoint,
+                script,
+                script_path: script_path(&security),
+                phase_timeouts,
+                inactivity,
+                container_id,
+                secrets: job.secrets().clone(),
+            }
+        })
+    }
+
+    async fn prepare_docker_container(
+        endpoint: &Endpoint,
+        docker: &Docker,
+        job: &RunnableJob,
+        script: &Script,
+        security: &SecurityProfile,
+        staging_store: Arc<RwLock<StagingStore>>,
+        release_stores: &[Arc<ReleaseStore>],
+    ) -> Result<String> {
+        // Shiplift can neither set the security options nor mount the tmpfs for the secrets, so the
+        // docker CLI is used if there are any
+        let container_id = if security.is_empty() && job.secrets().is_empty() {
+            Self::build_container(endpoint, docker, job).await?.id
+        } else {
+            Self::build_container_with_cli(endpoint, job, security).await?
+        };
+        let container = docker.containers().get(&container_id);
+
+        Self::verify_executables(&container, job)
+            .await
+            .with_context(|| {
+                anyhow!(
+                    "Verifying executables in container {} on '{}'",
+                    container_id,
+                    endpoint.name
+                )
+            })?;
+
+        // Only the volumes are writable, so the inputs are copied to them one by one
+        if security.read_only_rootfs() {
+            for (dir, archive) in Self::inputs_archives(job, script, security, staging_store, release_stores).await? {
+                let destination = format!("{}:{}", container_id, dir.display());
+                endpoint
+                    .run_docker_cli(&["cp", "-", destination.as_str()], Some(&archive))
+                    .await
+                    .with_context(|| anyhow!("Copying the inputs to container {} on '{}'", container_id, endpoint.name))?;
+            }
+
+            return Ok(container_id)
+        }
 
         let (cpysrc, cpypch, cpyart, cpyscr) = tokio::join!(
-            Self::copy_source_to_container(&container, &job),
-            Self::copy_patches_to_container(&container, &job),
-            Self::copy_artifacts_to_container(&container, &job, staging_store, &release_stores),
-            Self::copy_script_to_container(&container, &script)
+            Self::copy_source_to_container(&container, job),
+            Self::copy_patches_to_container(&container, job),
+            Self::copy_artifacts_to_container(&container, job, staging_store, release_stores),
+            Self::copy_script_to_container(&container, script)
         );
 
         let _ = cpysrc.with_context(|| {
             anyhow!(
                 "Copying the sources to container {} on '{}'",
-                create_info.id,
+                container_id,
                 endpoint.name
             )
         })?;
@@ -480,7 +1173,7 @@ impl<'a> PreparedContainer<'a> {
         let _ = cpypch.with_context(|| {
             anyhow!(
                 "Copying the patches to container {} on '{}'",
-                create_info.id,
+                container_id,
                 endpoint.name
             )
         })?;
@@ -488,7 +1181,7 @@ impl<'a> PreparedContainer<'a> {
         let _ = cpyart.with_context(|| {
             anyhow!(
                 "Copying the artifacts to container {} on '{}'",
-                create_info.id,
+                container_id,
                 endpoint.name
             )
         })?;
@@ -496,55 +1189,276 @@ impl<'a> PreparedContainer<'a> {
         let _ = cpyscr.with_context(|| {
             anyhow!(
                 "Copying the script to container {} on '{}'",
-                create_info.id,
+                container_id,
                 endpoint.name
             )
         })?;
 
-        Ok({
-            PreparedContainer {
-                endpoint,
-                script,
-                create_info,
+        Ok(container_id)
+    }
+
+    /// Create the pod for the job and copy everything the job needs into it
+    ///
+    /// Returns the name of the pod.
+    async fn prepare_pod(
+        endpoint: &Endpoint,
+        kubernetes: &crate::endpoint::Kubernetes,
+        job: &RunnableJob,
+        script: &Script,
+        staging_store: Arc<RwLock<StagingStore>>,
+        release_stores: &[Arc<ReleaseStore>],
+    ) -> Result<String> {
+        let pod = format!("butido-{}", job.uuid());
+        kubernetes
+            .create_pod(&pod, job, endpoint.network_mode().as_deref())
+            .await
+            .with_context(|| anyhow!("Creating pod on '{}'", endpoint.name))?;
+
+        // The interpreter is not checked, it is already running as the command of the pod
+        for exe in job.required_executables().iter() {
+            kubernetes
+                .verify_exists(&pod, &exe.display().to_string())
+                .await
+                .with_context(|| anyhow!("Executable {} not found in image {}", exe.display(), job.image()))?;
+        }
+
+        for (_, inputs) in Self::inputs_archives(job, script, &SecurityProfile::default(), staging_store, release_stores).await? {
+            kubernetes
+                .copy_tar_into(&pod, &inputs)
+                .await
+                .with_context(|| anyhow!("Copying the inputs to pod {} on '{}'", pod, endpoint.name))?;
+        }
+
+        Ok(pod)
+    }
+
+    /// Create the container for the job on a host connected via SSH and copy everything the job
+    /// needs into it
+    ///
+    /// Returns the ID of the container.
+    async fn prepare_ssh_container(
+        endpoint: &Endpoint,
+        ssh: &crate::endpoint::Ssh,
+        job: &RunnableJob,
+        script: &Script,
+        security: &SecurityProfile,
+        staging_store: Arc<RwLock<StagingStore>>,
+        release_stores: &[Arc<ReleaseStore>],
+    ) -> Result<String> {
+        let container_id = ssh
+            .create_container(job, endpoint.network_mode().as_deref(), endpoint.scratch_dir(job.uuid()).as_deref(), security)
+            .await
+            .with_context(|| anyhow!("Creating container on '{}'", endpoint.name))?;
+
+        let setup = async {
+            let interpreter = job.script().interpreter().first().map(|i| PathBuf::from(*i));
+            for exe in interpreter.iter().chain(job.required_executables().iter()) {
+                ssh.verify_exists(&container_id, &exe.display().to_string())
+                    .await
+                    .with_context(|| anyhow!("Executable {} not found in image {}", exe.display(), job.image()))?;
             }
-        })
+
+            for (dir, inputs) in Self::inputs_archives(job, script, security, staging_store, release_stores).await? {
+                ssh.copy_tar_into(&container_id, &dir, &inputs)
+                    .await
+                    .with_context(|| anyhow!("Copying the inputs to container {} on '{}'", container_id, endpoint.name))?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        // The ID of a container that was not set up completely is not returned, so the container
+        // is removed here
+        match setup {
+            Ok(()) => Ok(container_id),
+            Err(e) => match ssh.remove(&container_id).await {
+                Ok(()) => Err(e),
+                Err(re) => Err(re.context(format!("{:?}", e))),
+            },
+        }
+    }
+
+    /// The sources, patches, artifacts and the script of the job as tar archives, with the
+    /// directories in the container they have to be unpacked in
+    ///
+    /// If the root filesystem of the container is read-only, there is one archive per volume.
+    /// Otherwise, there is one archive, which has to be unpacked in the root directory.
+    async fn inputs_archives(
+        job: &RunnableJob,
+        script: &Script,
+        security: &SecurityProfile,
+        staging_store: Arc<RwLock<StagingStore>>,
+        release_stores: &[Arc<ReleaseStore>],
+    ) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut files: Vec<(PathBuf, Vec<u8>)> = vec![];
+
+        for entry in job.package_sources() {
+            let source_path = entry.path();
+            let destination = PathBuf::from(crate::consts::INPUTS_DIR_PATH).join({
+                source_path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Not a file: {}", source_path.display()))?
+            });
+            let buf = tokio::fs::read(&source_path)
+                .await
+                .with_context(|| anyhow!("Reading file {}", source_path.display()))?;
+            files.push((destination, buf));
+        }
+
+        for patch in job.package().patches().iter() {
+            let destination = PathBuf::from(crate::consts::PATCH_DIR_PATH).join(patch);
+            let buf = tokio::fs::read(&patch)
+                .await
+                .with_context(|| anyhow!("Reading file {}", patch.display()))?;
+            files.push((destination, buf));
+        }
+
+        for art in job.resources().iter().filter_map(JobResource::artifact) {
+            let artifact_file_name = art
+                .file_name()
+                .ok_or_else(|| anyhow!("BUG: artifact {} is not a file", art.display()))?;
+            let destination = PathBuf::from(crate::consts::INPUTS_DIR_PATH).join(artifact_file_name);
+            files.push((destination, read_artifact(art, &staging_store, release_stores).await?));
+        }
+
+        files.push((PathBuf::from(script_path(security)), script.as_ref().as_bytes().to_vec()));
+
+        let mut archives: BTreeMap<PathBuf, tar::Builder<Vec<u8>>> = BTreeMap::new();
+        for (path, buf) in files {
+            let dir = if security.read_only_rootfs() {
+                path.components().take(2).collect::<PathBuf>()
+            } else {
+                PathBuf::from("/")
+            };
+            let path = path.strip_prefix(&dir).unwrap_or(path.as_path());
+
+            trace!("Adding {} to inputs archive for {}", path.display(), dir.display());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(buf.len() as u64);
+            header.set_mode(0o644);
+            archives
+                .entry(dir)
+                .or_insert_with(|| tar::Builder::new(Vec::new()))
+                .append_data(&mut header, path, buf.as_slice())
+                .with_context(|| anyhow!("Adding {} to inputs archive", path.display()))?;
+        }
+
+        archives
+            .into_iter()
+            .map(|(dir, builder)| {
+                builder
+                    .into_inner()
+                    .map(|archive| (dir, archive))
+                    .context("Finishing inputs archive")
+                    .map_err(Error::from)
+            })
+            .collect()
     }
 
     async fn build_container(
         endpoint: &Endpoint,
+        docker: &Docker,
         job: &RunnableJob,
     ) -> Result<shiplift::rep::ContainerCreateInfo> {
         let envs = job
             .environment()
             .map(|(k, v)| format!("{}={}", k.as_ref(), v))
             .collect::<Vec<_>>();
-        trace!("Job resources: Environment variables = {:?}", envs);
+        // Only the names are logged, the values can be secrets
+        trace!("Job resources: Environment variables = {:?}", job.environment().map(|(k, _)| k.as_ref()).collect::<Vec<_>>());
 
         let builder_opts = {
-            let mut builder_opts = shiplift::ContainerOptions::builder(job.image().as_ref());
+            let mut builder_opts = shiplift::ContainerOptions::builder(job.image_reference().as_ref());
             builder_opts.env(envs.iter().map(AsRef::as_ref).collect::<Vec<&str>>());
-            builder_opts.cmd(vec!["/bin/bash"]); // we start the container with /bin/bash, but exec() the script in it later
-            builder_opts.attach_stdin(true); // we have to attach, otherwise bash exits
+            builder_opts.cmd(job.script().interpreter()); // we start the container with the interpreter, but exec() the script in it later
+            builder_opts.attach_stdin(true); // we have to attach, otherwise the interpreter exits
+
+            let workdir = endpoint
+                .scratch_dir(job.uuid())
+                .map(|dir| format!("{}:{}", dir.display(), crate::consts::WORK_DIR_PATH));
+            if let Some(workdir) = workdir.as_ref() {
+                builder_opts.volumes(vec![workdir.as_str()]);
+                builder_opts.working_dir(crate::consts::WORK_DIR_PATH);
+            }
+
+            if let Some(cpus) = job.limits().cpus() {
+                builder_opts.cpus(cpus);
+            }
+            if let Some(memory) = job.limits().memory_bytes()? {
+                builder_opts.memory(memory);
+            }
 
-            if let Some(network_mode) = endpoint.network_mode().as_ref() {
+            let job_uuid = job.uuid().to_string();
+            let mut labels = std::collections::HashMap::new();
+            labels.insert(crate::consts::CONTAINER_LABEL, job_uuid.as_str());
+            builder_opts.labels(&labels);
+
+            if job.hermetic() {
+                builder_opts.network_mode("none");
+            } else if let Some(network_mode) = endpoint.network_mode().as_ref() {
                 builder_opts.network_mode(network_mode);
             }
 
             builder_opts.build()
         };
-        trace!("Builder options = {:?}", builder_opts);
 
-        let create_info = endpoint
-            .docker
+        let create_info = docker
             .containers()
             .create(&builder_opts)
             .await
-            .with_context(|| anyhow!("Creating container with builder options = {:?}", builder_opts))
+            .with_context(|| anyhow!("Creating container for job {}", job.uuid()))
             .with_context(|| anyhow!("Creating container on '{}'", endpoint.name))?;
         trace!("Create info = {:?}", create_info);
         Ok(create_info)
     }
 
+    /// Create the container for the job with the local docker CLI, which (unlike shiplift)
+    /// supports the security settings
+    ///
+    /// The environment of the job is passed to the CLI as env file on stdin, so that it is not
+    /// visible on the command line. Returns the ID of the container.
+    async fn build_container_with_cli(endpoint: &Endpoint, job: &RunnableJob, security: &SecurityProfile) -> Result<String> {
+        let seccomp_profile = security.seccomp_profile().as_ref().map(|p| p.display().to_string());
+        let args = crate::endpoint::util::create_args(
+            job,
+            endpoint.network_mode().as_deref(),
+            endpoint.scratch_dir(job.uuid()).as_deref(),
+            security,
+            seccomp_profile.as_deref(),
+            "/dev/stdin",
+        )?;
+        trace!("Creating container with docker {:?}", args);
+
+        let env_file = crate::endpoint::util::env_file(job)?;
+        endpoint
+            .run_docker_cli(&args, Some(env_file.as_bytes()))
+            .await
+            .map(|out| out.trim().to_string())
+            .with_context(|| anyhow!("Creating container on '{}'", endpoint.name))
+    }
+
+    /// Verify that the interpreter of the script and the executables required for the image exist
+    /// in the container
+    async fn verify_executables<'ca>(container: &Container<'ca>, job: &RunnableJob) -> Result<()> {
+        let interpreter = job.script().interpreter().first().map(|i| PathBuf::from(*i));
+
+        for exe in interpreter.iter().chain(job.required_executables().iter()) {
+            trace!("Verifying that {} exists in container {}", exe.display(), container.id());
+            let mut stream = Box::pin(container.copy_from(exe));
+            match stream.next().await {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    return Err(Error::from(e))
+                        .with_context(|| anyhow!("Executable {} not found in image {}", exe.display(), job.image()))
+                }
+                None => return Err(anyhow!("Executable {} not found in image {}", exe.display(), job.image())),
+            }
+        }
+
+        Ok(())
+    }
+
     async fn copy_source_to_container<'ca>(
         container: &Container<'ca>,
         job: &RunnableJob,
@@ -669,39 +1583,7 @@ impl<'a> PreparedContainer<'a> {
                     container.id(),
                     destination.display()
                 );
-                let staging_read = staging_store.read().await;
-                let buf = match staging_read.root_path().join(&art)?  {
-                    Some(fp) => fp,
-                    None     => {
-                        // TODO: Optimize.
-                        // I know this is not nice, but it works for now.
-                        let mut found = None;
-                        for release_store in release_stores.iter() {
-                            let p = release_store.root_path().join(&art);
-                            match p {
-                                Ok(Some(path)) => {
-                                    found = Some(path);
-                                    break;
-                                },
-                                Err(e) => {
-                                    trace!("Failed to join '{:?}' + '{:?}'", release_store.root_path(), art.display());
-                                    return Err(e)
-                                },
-                                Ok(None) =>  continue,
-                            }
-                        }
-                        found.ok_or_else(|| anyhow!("Not found in staging or release store: {:?}", art))?
-                    },
-                }
-                .read()
-                .await
-                .with_context(|| {
-                    anyhow!(
-                        "Reading artifact {}, so it can be copied to container",
-                        art.display()
-                    )
-                })?;
-                trace!("Successfully read {} into buffer", art.display());
+                let buf = read_artifact(&art, &staging_store, release_stores).await?;
 
                 let r = container
                     .copy_file_into(&destination, &buf)
@@ -742,155 +1624,427 @@ impl<'a> PreparedContainer<'a> {
     }
 
     pub async fn start(self) -> Result<StartedContainer<'a>> {
-        self.endpoint
-            .docker
-            .containers()
-            .get(&self.create_info.id)
-            .start()
-            .inspect(|r| trace!("Starting container {} -> {:?}", self.create_info.id, r))
-            .map(|r| {
-                r.with_context(|| {
-                    anyhow!(
-                        "Starting the container {} on '{}'",
-                        self.create_info.id,
-                        self.endpoint.name
-                    )
-                })
-            })
-            .await?;
+        match &self.endpoint.backend {
+            EndpointBackend::Docker(docker) => {
+                docker
+                    .containers()
+                    .get(&self.container_id)
+                    .start()
+                    .inspect(|r| trace!("Starting container {} -> {:?}", self.container_id, r))
+                    .map(|r| {
+                        r.with_context(|| {
+                            anyhow!(
+                                "Starting the container {} on '{}'",
+                                self.container_id,
+                                self.endpoint.name
+                            )
+                        })
+                    })
+                    .await?;
+            }
+
+            // The pod is already running, it was started when it was created
+            EndpointBackend::Kubernetes(_) => {}
+
+            EndpointBackend::Ssh(ssh) => ssh.start(&self.container_id).await?,
+
+            // The run is simulated when the script is executed
+            EndpointBackend::Mock(_) => {}
+        }
+
+        // A container with only some of the secrets is of no use, it is removed with them
+        if let Err(e) = self.endpoint.write_secrets(&self.container_id, &self.secrets).await {
+            return match self.endpoint.remove_container(&self.container_id).await {
+                Ok(()) => Err(e),
+                Err(re) => Err(re.context(format!("{:?}", e))),
+            }
+        }
 
         Ok({
             StartedContainer {
                 endpoint: self.endpoint,
                 script: self.script,
-                create_info: self.create_info,
+                script_path: self.script_path,
+                phase_timeouts: self.phase_timeouts,
+                inactivity: self.inactivity,
+                container_id: self.container_id,
+                secrets: self.secrets,
             }
         })
     }
 }
 
+/// Where the script is copied to in a container with the security settings `security`
+///
+/// If the root filesystem is read-only, the script is copied to a volume.
+fn script_path(security: &SecurityProfile) -> &'static str {
+    if security.read_only_rootfs() {
+        crate::consts::READ_ONLY_SCRIPT_PATH
+    } else {
+        crate::consts::SCRIPT_PATH
+    }
+}
+
+/// Find an artifact in the staging store or, if it is not found there, in the release stores
+pub fn locate_artifact<'a>(
+    art: &'a ArtifactPath,
+    staging_store: &'a StagingStore,
+    release_stores: &'a [Arc<ReleaseStore>],
+) -> Result<FullArtifactPath<'a>> {
+    if let Some(fp) = staging_store.root_path().join(art)? {
+        return Ok(fp);
+    }
+
+    // TODO: Optimize.
+    // I know this is not nice, but it works for now.
+    for release_store in release_stores.iter() {
+        match release_store.root_path().join(art) {
+            Ok(Some(path)) => return Ok(path),
+            Err(e) => {
+                trace!("Failed to join '{:?}' + '{:?}'", release_store.root_path(), art.display());
+                return Err(e)
+            },
+            Ok(None) => continue,
+        }
+    }
+
+    Err(anyhow!("Not found in staging or release store: {:?}", art))
+}
+
+/// Read an artifact from the staging store or, if it is not found there, from the release stores
+async fn read_artifact(
+    art: &ArtifactPath,
+    staging_store: &Arc<RwLock<StagingStore>>,
+    release_stores: &[Arc<ReleaseStore>],
+) -> Result<Vec<u8>> {
+    let staging_read = staging_store.read().await;
+    let buf = locate_artifact(art, &staging_read, release_stores)?
+        .read()
+        .await
+    .with_context(|| {
+        anyhow!(
+            "Reading artifact {}, so it can be copied to container",
+            art.display()
+        )
+    })?;
+    trace!("Successfully read {} into buffer", art.display());
+    Ok(buf)
+}
+
+/// The output of a command run in a container
+type ExecOutput<'a> = Pin<Box<dyn Stream<Item = shiplift::Result<TtyChunk>> + 'a>>;
+
 pub struct StartedContainer<'a> {
     endpoint: &'a Endpoint,
     script: Script,
-    create_info: shiplift::rep::ContainerCreateInfo,
+    script_path: &'static str,
+    phase_timeouts: HashMap<String, Duration>,
+    inactivity: Option<(Duration, InactivityAction)>,
+    container_id: String,
+    secrets: Vec<JobSecret>,
 }
 
 impl<'a> StartedContainer<'a> {
+    /// Run the script in the container and send its log to `logsink`
+    ///
+    /// If the script is interrupted because the container was checkpointed for endpoint
+    /// maintenance (see `butido endpoint checkpoint`), the job is aborted: its log ends with a
+    /// failed state saying so and the container is kept with its checkpoint. The output of a
+    /// restored script could not be followed again, so the job is not continued.
+    ///
+    /// The secrets are removed from the container once the script finished, before the container
+    /// is stopped or kept for debugging. If that fails, the container is removed.
     pub async fn execute_script(
         self,
-        logsink: UnboundedSender<LogItem>,
+        logsink: UnboundedSender<(LogStream, LogItem)>,
+        log_max_line_length: usize,
+        checkpoints: &CheckpointWatch,
     ) -> Result<ExecutedContainer<'a>> {
-        let exec_opts = ExecContainerOptions::builder()
-            .cmd(vec!["/bin/bash", "/script"])
-            .attach_stderr(true)
-            .attach_stdout(true)
-            .build();
-        trace!("Exec options = {:?}", exec_opts);
-
-        trace!("Moving logs to log sink for container {}", self.create_info.id);
-        let stream = self.endpoint
-            .docker
-            .containers()
-            .get(&self.create_info.id)
-            .exec(&exec_opts);
-
-        let exited_successfully: Option<(bool, Option<String>)> =
-            buffer_stream_to_line_stream(stream)
-                .map(|line| {
-                    trace!(
-                        "['{}':{}] Found log line: {:?}",
+        let started_at = chrono::offset::Local::now().naive_local();
+        let result = match self.run_script(&logsink, log_max_line_length).await {
+            Ok(Some(exit_info)) => Ok(Some(exit_info)),
+            result => {
+                if let Some(checkpoint) = checkpoints.checkpoint_of(&self.container_id, &started_at).await? {
+                    let msg = format!(
+                        "Job aborted: container {} on {} was checkpointed as '{}' for endpoint maintenance",
+                        self.container_id,
                         self.endpoint.name,
-                        self.create_info.id,
-                        line
+                        checkpoint.name
                     );
-                    line.with_context(|| {
-                        anyhow!(
-                            "Getting log from {}:{}",
-                            self.endpoint.name,
-                            self.create_info.id
-                        )
-                    })
-                    .and_then(|l| {
-                        crate::log::parser()
-                            .parse(l.as_bytes())
-                            .with_context(|| {
-                                anyhow!(
-                                    "Parsing log from {}:{}: {:?}",
-                                    self.endpoint.name,
-                                    self.create_info.id,
-                                    l
-                                )
-                            })
-                    })
-                    .and_then(|item| {
-                        let exited_successfully = match item {
-                            LogItem::State(Ok(_)) => Some((true, None)),
-                            LogItem::State(Err(ref msg)) => Some((false, Some(msg.clone()))),
-                            _ => None, // Nothing
-                        };
-
-                        trace!("Log item: {}", item.display()?);
-                        logsink
-                            .send(item)
-                            .with_context(|| anyhow!("Sending log to log sink"))
-                            .map(|_| exited_successfully)
-                    })
-                    .map_err(Error::from)
-                })
-                .collect::<Result<Vec<_>>>()
-                .map(|r| {
-                    r.with_context(|| {
-                        anyhow!(
-                            "Fetching log from container {} on {}",
-                            self.create_info.id,
-                            self.endpoint.name
-                        )
-                    })
-                })
-                .await
-                .with_context(|| {
-                    anyhow!(
-                        "Copying script to container, running container and getting logs: {}",
-                        self.create_info.id
-                    )
-                })?
-                .into_iter()
-                .fold(None, |accu, elem| match (accu, elem) {
-                    (None, b) => b,
-                    (Some((false, msg)), _) => Some((false, msg)),
-                    (_, Some((false, msg))) => Some((false, msg)),
-                    (a, None) => a,
-                    (Some((true, _)), Some((true, _))) => Some((true, None)),
-                });
+                    warn!("{}, keeping the container", msg);
+                    let _ = logsink.send((LogStream::Stdout, LogItem::State(Err(msg.clone()))));
+                    return Err(anyhow!(msg))
+                }
+
+                result
+            }
+        };
+
+        if let Err(err) = self.endpoint.remove_secrets(&self.container_id, &self.secrets).await {
+            warn!("{:?}, removing the container", err);
+            return match self.endpoint.remove_container(&self.container_id).await {
+                Ok(()) => Err(err),
+                Err(e) => Err(e.context(format!("{:?}", err))),
+            }
+        }
 
+        let exited_successfully = result?;
         Ok({
             ExecutedContainer {
                 endpoint: self.endpoint,
-                create_info: self.create_info,
+                container_id: self.container_id,
                 script: self.script,
                 exit_info: exited_successfully,
             }
         })
     }
+
+    /// Run the script in the container once
+    ///
+    /// Returns None if the script did not report whether it succeeded.
+    async fn run_script(
+        &self,
+        logsink: &UnboundedSender<(LogStream, LogItem)>,
+        log_max_line_length: usize,
+    ) -> Result<Option<(bool, Option<String>)>> {
+        // The secrets with an environment variable are exported by a shell, which then runs the script
+        let export_secrets = crate::job::export_secrets_script(&self.secrets);
+        let cmd = {
+            let mut cmd = match export_secrets.as_ref() {
+                Some(export) => vec!["sh", "-c", export.as_str(), "sh"],
+                None => vec![],
+            };
+            cmd.extend(self.script.interpreter());
+            cmd.push(self.script_path);
+            cmd
+        };
+
+        trace!("Moving logs to log sink for container {}", self.container_id);
+        let (kubectl, stream) = self.exec(cmd)?;
+
+        let phase_watch = PhaseWatch::new(self.phase_timeouts.clone());
+        let inactivity_watch = InactivityWatch::new(self.inactivity);
+        let log_lines = buffer_stream_to_line_stream(stream, log_max_line_length)
+            .map(|line| line.map(|(stream, l)| (stream, redact_secrets(l, &self.secrets))))
+            .map(|line| {
+                inactivity_watch.output();
+                trace!(
+                    "['{}':{}] Found log line: {:?}",
+                    self.endpoint.name,
+                    self.container_id,
+                    line
+                );
+                line.with_context(|| {
+                    anyhow!(
+                        "Getting log from {}:{}",
+                        self.endpoint.name,
+                        self.container_id
+                    )
+                })
+                .and_then(|(stream, l)| {
+                    crate::log::parser()
+                        .parse(l.as_bytes())
+                        .map(|item| (stream, item))
+                        .with_context(|| {
+                            anyhow!(
+                                "Parsing log from {}:{}: {:?}",
+                                self.endpoint.name,
+                                self.container_id,
+                                l
+                            )
+                        })
+                })
+                .and_then(|(stream, item)| {
+                    let exited_successfully = match item {
+                        LogItem::State(Ok(_)) => Some((true, None)),
+                        LogItem::State(Err(ref msg)) => Some((false, Some(msg.clone()))),
+                        LogItem::CurrentPhase(ref phase) => {
+                            phase_watch.enter(phase);
+                            None
+                        }
+                        _ => None, // Nothing
+                    };
+
+                    trace!("Log item: {}", item.display()?);
+                    logsink
+                        .send((stream, item))
+                        .with_context(|| anyhow!("Sending log to log sink"))
+                        .map(|_| exited_successfully)
+                })
+                .map_err(Error::from)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(|r| {
+                r.with_context(|| {
+                    anyhow!(
+                        "Fetching log from container {} on {}",
+                        self.container_id,
+                        self.endpoint.name
+                    )
+                })
+            });
+
+        // A phase that does not finish in time kills the container, which frees the endpoint
+        tokio::pin!(log_lines);
+        let log_lines = loop {
+            tokio::select! {
+                log_lines = &mut log_lines => break log_lines,
+                (phase, timeout) = phase_watch.timed_out() => {
+                    let err = anyhow!(
+                        "Phase '{}' did not finish within {} in container {} on {}",
+                        phase,
+                        humantime::format_duration(timeout),
+                        self.container_id,
+                        self.endpoint.name
+                    );
+                    warn!("{}, killing the container", err);
+                    return match self.endpoint.remove_container(&self.container_id).await {
+                        Ok(()) => Err(err),
+                        Err(e) => Err(e.context(err.to_string())),
+                    }
+                }
+                (silence, action) = inactivity_watch.inactive() => {
+                    let msg = format!(
+                        "No output for {} from container {} on {}",
+                        humantime::format_duration(silence),
+                        self.container_id,
+                        self.endpoint.name
+                    );
+
+                    match action {
+                        InactivityAction::Warn => warn!("{}", msg),
+                        InactivityAction::Snapshot => {
+                            warn!("{}, writing its processes to the log", msg);
+                            if let Err(e) = self.snapshot_processes(logsink, log_max_line_length).await {
+                                warn!("Failed to list the processes in container {}: {:?}", self.container_id, e);
+                            }
+                        }
+                        InactivityAction::Kill => {
+                            warn!("{}, killing the container", msg);
+                            return match self.endpoint.remove_container(&self.container_id).await {
+                                Ok(()) => Err(anyhow!(msg)),
+                                Err(e) => Err(e.context(msg)),
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        let exited_successfully: Option<(bool, Option<String>)> = log_lines
+            .with_context(|| {
+                anyhow!(
+                    "Copying script to container, running container and getting logs: {}",
+                    self.container_id
+                )
+            })?
+            .into_iter()
+            .fold(None, |accu, elem| match (accu, elem) {
+                (None, b) => b,
+                (Some((false, msg)), _) => Some((false, msg)),
+                (_, Some((false, msg))) => Some((false, msg)),
+                (a, None) => a,
+                (Some((true, _)), Some((true, _))) => Some((true, None)),
+            });
+
+        if let Some(child) = kubectl {
+            wait_for_process(child)
+                .await
+                .with_context(|| anyhow!("Running script in pod {} on {}", self.container_id, self.endpoint.name))?;
+        }
+
+        Ok(exited_successfully)
+    }
+
+    /// Run `cmd` in the container, returning its output and the process to wait for, if the
+    /// endpoint runs one
+    fn exec(&self, cmd: Vec<&str>) -> Result<(Option<tokio::process::Child>, ExecOutput<'_>)> {
+        match &self.endpoint.backend {
+            EndpointBackend::Docker(docker) => {
+                let exec_opts = ExecContainerOptions::builder()
+                    .cmd(cmd)
+                    .attach_stderr(true)
+                    .attach_stdout(true)
+                    .build();
+                trace!("Exec options = {:?}", exec_opts);
+
+                let stream = docker
+                    .containers()
+                    .get(&self.container_id)
+                    .exec(&exec_opts);
+                Ok((None, Box::pin(stream)))
+            }
+
+            EndpointBackend::Kubernetes(kubernetes) => {
+                let (child, stream) = kubernetes.exec(&self.container_id, &cmd)?;
+                Ok((Some(child), Box::pin(stream)))
+            }
+
+            EndpointBackend::Ssh(ssh) => {
+                let (child, stream) = ssh.exec(&self.container_id, &cmd)?;
+                Ok((Some(child), Box::pin(stream)))
+            }
+
+            EndpointBackend::Mock(mock) => Ok((None, mock.exec(&self.container_id, &cmd)?)),
+        }
+    }
+
+    /// Write the processes running in the container to the log, to find out why a script hangs
+    async fn snapshot_processes(
+        &self,
+        logsink: &UnboundedSender<(LogStream, LogItem)>,
+        log_max_line_length: usize,
+    ) -> Result<()> {
+        let (child, stream) = self.exec(vec!["sh", "-c", PROCESS_SNAPSHOT_COMMAND])?;
+        let lines = tokio::time::timeout(PROCESS_SNAPSHOT_TIMEOUT, buffer_stream_to_line_stream(stream, log_max_line_length).collect::<std::io::Result<Vec<_>>>())
+            .await
+            .map_err(|_| anyhow!("Listing the processes did not finish within {}", humantime::format_duration(PROCESS_SNAPSHOT_TIMEOUT)))?
+            .context("Getting the process list")?;
+
+        if let Some(child) = child {
+            wait_for_process(child).await?;
+        }
+
+        let header = format!("butido: no output for a while, processes in container {}:", self.container_id);
+        std::iter::once((LogStream::Stdout, header))
+            .chain(lines)
+            .try_for_each(|(stream, line)| {
+                logsink
+                    .send((stream, LogItem::Line(redact_secrets(line, &self.secrets).into_bytes())))
+                    .map_err(|_| anyhow!("Sending log to log sink"))
+            })
+    }
 }
 
+/// The command which lists the processes in a container, `top` is tried if `ps` is not available
+const PROCESS_SNAPSHOT_COMMAND: &str = "ps aux 2>/dev/null || top -b -n 1";
+
+/// How long listing the processes in a container may take
+const PROCESS_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct ExecutedContainer<'a> {
     endpoint: &'a Endpoint,
-    create_info: shiplift::rep::ContainerCreateInfo,
+    container_id: String,
     script: Script,
     exit_info: Option<(bool, Option<String>)>,
 }
 
 impl<'a> ExecutedContainer<'a> {
     pub fn container_hash(&self) -> ContainerHash {
-        ContainerHash::from(self.create_info.id.clone())
+        ContainerHash::from(self.container_id.clone())
     }
 
     pub fn script(&self) -> &Script {
         &self.script
     }
 
-    pub async fn finalize(self, staging_store: Arc<RwLock<StagingStore>>) -> Result<FinalizedContainer> {
+    /// Copy the outputs of the container to the staging store
+    ///
+    /// The outputs of a job for an architecture are written to a directory named after the
+    /// architecture in the staging store.
+    pub async fn finalize(self, staging_store: Arc<RwLock<StagingStore>>, architecture: Option<&Architecture>) -> Result<FinalizedContainer> {
+        let subdir = architecture.map(|a| Path::new(a.as_ref()));
+
         let (exit_info, artifacts) = match self.exit_info {
             Some((false, msg)) => {
                 let err = anyhow!("Error during container run: '{msg}'", msg = msg.as_deref().unwrap_or(""));
@@ -900,30 +2054,75 @@ impl<'a> ExecutedContainer<'a> {
             }
 
             Some((true, _)) | None => {
-                let container = self.endpoint.docker.containers().get(&self.create_info.id);
-
-                trace!("Fetching {} from container {}", crate::consts::OUTPUTS_DIR_PATH, self.create_info.id);
-                let tar_stream = container
-                    .copy_from(&PathBuf::from(crate::consts::OUTPUTS_DIR_PATH))
-                    .map(|item| {
-                        item.with_context(|| {
-                            anyhow!(
-                                "Copying item from container {} to host",
-                                self.create_info.id
-                            )
-                        })
-                        .map_err(Error::from)
-                    });
-
-                let mut writelock = staging_store.write().await;
-                let artifacts = writelock
-                    .write_files_from_tar_stream(tar_stream)
-                    .await
-                    .with_context(|| anyhow!("Copying the TAR stream to the staging store"))?;
-                container
-                    .stop(Some(std::time::Duration::new(1, 0)))
-                    .await
-                    .with_context(|| anyhow!("Stopping container {}", self.create_info.id))?;
+                trace!("Fetching {} from container {}", crate::consts::OUTPUTS_DIR_PATH, self.container_id);
+                let artifacts = match &self.endpoint.backend {
+                    EndpointBackend::Docker(docker) => {
+                        let container = docker.containers().get(&self.container_id);
+                        let tar_stream = container
+                            .copy_from(&PathBuf::from(crate::consts::OUTPUTS_DIR_PATH))
+                            .map(|item| {
+                                item.with_context(|| {
+                                    anyhow!(
+                                        "Copying item from container {} to host",
+                                        self.container_id
+                                    )
+                                })
+                                .map_err(Error::from)
+                            });
+
+                        let mut writelock = staging_store.write().await;
+                        let artifacts = writelock
+                            .write_files_from_tar_stream(tar_stream, subdir)
+                            .await
+                            .with_context(|| anyhow!("Copying the TAR stream to the staging store"))?;
+                        container
+                            .stop(Some(std::time::Duration::new(1, 0)))
+                            .await
+                            .with_context(|| anyhow!("Stopping container {}", self.container_id))?;
+                        artifacts
+                    }
+
+                    EndpointBackend::Kubernetes(kubernetes) => {
+                        let (child, tar_stream) = kubernetes.copy_from(&self.container_id, crate::consts::OUTPUTS_DIR_PATH)?;
+
+                        let mut writelock = staging_store.write().await;
+                        let artifacts = writelock
+                            .write_files_from_tar_stream(tar_stream, subdir)
+                            .await
+                            .with_context(|| anyhow!("Copying the TAR stream to the staging store"))?;
+                        wait_for_process(child)
+                            .await
+                            .with_context(|| anyhow!("Copying outputs from pod {}", self.container_id))?;
+                        kubernetes.delete_pod(&self.container_id).await?;
+                        artifacts
+                    }
+
+                    EndpointBackend::Ssh(ssh) => {
+                        let archive = ssh.copy_from(&self.container_id, crate::consts::OUTPUTS_DIR_PATH).await?;
+                        let tar_stream = futures::stream::once(async move { Ok(archive) });
+
+                        let mut writelock = staging_store.write().await;
+                        let artifacts = writelock
+                            .write_files_from_tar_stream(tar_stream, subdir)
+                            .await
+                            .with_context(|| anyhow!("Copying the TAR stream to the staging store"))?;
+                        ssh.stop(&self.container_id).await?;
+                        artifacts
+                    }
+
+                    EndpointBackend::Mock(mock) => {
+                        let archive = mock.copy_outputs(&self.container_id)?;
+                        let tar_stream = futures::stream::once(async move { Ok(archive) });
+
+                        let mut writelock = staging_store.write().await;
+                        let artifacts = writelock
+                            .write_files_from_tar_stream(tar_stream, subdir)
+                            .await
+                            .with_context(|| anyhow!("Copying the TAR stream to the staging store"))?;
+                        mock.stop(&self.container_id)?;
+                        artifacts
+                    }
+                };
                 (Ok(()), artifacts)
             }
         };
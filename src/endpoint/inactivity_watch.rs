@@ -0,0 +1,97 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::config::InactivityAction;
+
+/// Watches whether a script writes to its log
+pub struct InactivityWatch {
+    /// How long the script may be silent and what to do afterwards
+    limit: Option<(Duration, InactivityAction)>,
+
+    /// When the script last wrote to its log, or when the last action was taken
+    last_output: Mutex<Instant>,
+}
+
+impl InactivityWatch {
+    pub fn new(limit: Option<(Duration, InactivityAction)>) -> Self {
+        InactivityWatch {
+            limit,
+            last_output: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// The script wrote to its log
+    pub fn output(&self) {
+        self.output_at(Instant::now())
+    }
+
+    fn output_at(&self, now: Instant) {
+        if let Ok(mut last_output) = self.last_output.lock() {
+            *last_output = now;
+        }
+    }
+
+    /// How long the script is silent, if that is longer than allowed
+    fn silent_at(&self, now: Instant) -> Option<Duration> {
+        let (timeout, _) = self.limit?;
+        let silence = now.saturating_duration_since(*self.last_output.lock().ok()?);
+
+        if silence >= timeout {
+            Some(silence)
+        } else {
+            None
+        }
+    }
+
+    /// Wait until the script is silent for longer than allowed and return how long it was silent
+    /// with the action to take
+    ///
+    /// The watch starts over afterwards, so if the script stays silent, this finishes again after
+    /// the timeout. Never finishes if no timeout is configured.
+    pub async fn inactive(&self) -> (Duration, InactivityAction) {
+        let action = match self.limit {
+            Some((_, action)) => action,
+            None => return futures::future::pending().await,
+        };
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let now = Instant::now();
+            if let Some(silence) = self.silent_at(now) {
+                self.output_at(now);
+                return (silence, action)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silent() {
+        let watch = InactivityWatch::new(Some((Duration::from_secs(60), InactivityAction::Warn)));
+        let start = Instant::now();
+        watch.output_at(start);
+        assert_eq!(watch.silent_at(start + Duration::from_secs(59)), None);
+        assert_eq!(watch.silent_at(start + Duration::from_secs(61)), Some(Duration::from_secs(61)));
+
+        watch.output_at(start + Duration::from_secs(30));
+        assert_eq!(watch.silent_at(start + Duration::from_secs(61)), None);
+
+        let unlimited = InactivityWatch::new(None);
+        assert_eq!(unlimited.silent_at(start + Duration::from_secs(3600)), None);
+    }
+}
@@ -0,0 +1,317 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Running jobs as pods on Kubernetes
+//!
+//! The cluster is controlled with `kubectl`, which has to be installed on the host butido runs on.
+//! Files are copied into and out of the pods with `tar`, so the images used on Kubernetes endpoints
+//! have to contain a `tar` executable.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use futures::Stream;
+use futures::TryStreamExt;
+use log::trace;
+use shiplift::tty::TtyChunk;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tokio::process::Command;
+
+use crate::endpoint::util::check_status;
+use crate::endpoint::util::read_chunks;
+use crate::job::RunnableJob;
+
+/// How long to wait for a pod to become ready, in the format kubectl understands
+const POD_READY_TIMEOUT: &str = "10m";
+
+/// A Kubernetes namespace jobs are run in
+#[derive(Debug)]
+pub struct Kubernetes {
+    kubectl: PathBuf,
+
+    /// The context from the kubeconfig to use
+    context: String,
+
+    kubeconfig: Option<PathBuf>,
+    namespace: Option<String>,
+}
+
+impl Kubernetes {
+    pub fn new(context: String, kubeconfig: Option<PathBuf>, namespace: Option<String>) -> Result<Self> {
+        let kubectl = which::which("kubectl").context("Finding kubectl executable")?;
+        Ok(Kubernetes { kubectl, context, kubeconfig, namespace })
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.kubectl);
+        cmd.arg("--context").arg(&self.context);
+        if let Some(kubeconfig) = self.kubeconfig.as_ref() {
+            cmd.arg("--kubeconfig").arg(kubeconfig);
+        }
+        if let Some(namespace) = self.namespace.as_ref() {
+            cmd.arg("--namespace").arg(namespace);
+        }
+        cmd.stdin(Stdio::null()).kill_on_drop(true);
+        cmd
+    }
+
+    /// Run kubectl with `args` and return its output
+    async fn run<S: AsRef<std::ffi::OsStr>>(&self, args: &[S]) -> Result<String> {
+        let mut cmd = self.command();
+        cmd.args(args);
+        trace!("Running {:?}", cmd);
+
+        let output = cmd.output().await.context("Running kubectl")?;
+        check_status(&output.status, &output.stderr)?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run kubectl with `args` and `input` on its stdin and return its output
+    async fn run_with_input<S: AsRef<std::ffi::OsStr>>(&self, args: &[S], input: &[u8]) -> Result<String> {
+        let mut cmd = self.command();
+        cmd.args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        trace!("Running {:?}", cmd);
+
+        let mut child = cmd.spawn().context("Running kubectl")?;
+        {
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for kubectl"))?;
+            stdin.write_all(input).await.context("Writing to kubectl")?;
+        } // stdin is closed here, so kubectl can finish
+
+        let output = child.wait_with_output().await.context("Running kubectl")?;
+        check_status(&output.status, &output.stderr)?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Check that jobs can be run, i.e. pods can be created in the namespace
+    pub async fn check_access(&self) -> Result<()> {
+        self.run(&["auth", "can-i", "create", "pods"])
+            .await
+            .map(|_| ())
+            .with_context(|| anyhow!("Checking permission to create pods in context {}", self.context))
+    }
+
+    /// The version of the Kubernetes cluster
+    pub async fn server_version(&self) -> Result<String> {
+        let out = self.run(&["version", "--output=json"])
+            .await
+            .with_context(|| anyhow!("Getting server version in context {}", self.context))?;
+
+        serde_json::from_str::<serde_json::Value>(&out)
+            .context("Parsing output of 'kubectl version'")?
+            .get("serverVersion")
+            .and_then(|v| v.get("gitVersion"))
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .ok_or_else(|| anyhow!("No server version in output of 'kubectl version'"))
+    }
+
+    /// The number of running pods created by butido
+    pub async fn running_pods(&self) -> Result<usize> {
+        self.run(&[
+            "get", "pods",
+            "--selector=app.kubernetes.io/managed-by=butido",
+            "--field-selector=status.phase=Running",
+            "--output=name",
+        ])
+        .await
+        .with_context(|| anyhow!("Listing pods in context {}", self.context))
+        .map(|out| out.lines().filter(|l| !l.trim().is_empty()).count())
+    }
+
+    /// The client certificate (PEM) the context authenticates with, if it uses one
+    pub async fn client_certificate(&self) -> Result<Option<Vec<u8>>> {
+        let out = self.run(&[
+            "config", "view", "--raw", "--minify",
+            "--output=jsonpath={.users[0].user.client-certificate-data}{\"\\n\"}{.users[0].user.client-certificate}",
+        ])
+        .await
+        .with_context(|| anyhow!("Reading kubeconfig of context {}", self.context))?;
+
+        let mut lines = out.lines().map(str::trim);
+        match (lines.next(), lines.next()) {
+            (Some(data), _) if !data.is_empty() => base64::decode(data)
+                .map(Some)
+                .with_context(|| anyhow!("Decoding client certificate of context {}", self.context)),
+            (_, Some(path)) if !path.is_empty() => tokio::fs::read(path)
+                .await
+                .map(Some)
+                .with_context(|| anyhow!("Reading client certificate {}", path)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The command to use to debug a job in `pod`
+    pub fn debug_command(&self, pod: &str) -> String {
+        let mut s = format!("kubectl --context {}", self.context);
+        if let Some(kubeconfig) = self.kubeconfig.as_ref() {
+            s.push_str(&format!(" --kubeconfig {}", kubeconfig.display()));
+        }
+        if let Some(namespace) = self.namespace.as_ref() {
+            s.push_str(&format!(" --namespace {}", namespace));
+        }
+        s.push_str(&format!(" exec -it {} -- /bin/bash", pod));
+        s
+    }
+
+    /// Create the pod for `job` and wait until it is running
+    ///
+    /// Like the containers on docker endpoints, the pod runs the interpreter of the script with an
+    /// open stdin, so that it keeps running until the script was executed in it.
+    ///
+    /// The pod manifest is passed to kubectl on stdin, so that the environment of the job is not
+    /// visible on the command line.
+    pub async fn create_pod(&self, pod: &str, job: &RunnableJob, network_mode: Option<&str>) -> Result<()> {
+        if job.hermetic() {
+            return Err(anyhow!("Hermetic jobs cannot be run on Kubernetes endpoints"));
+        }
+        if let Some(network_mode) = network_mode {
+            return Err(anyhow!("Network mode '{}' is not supported on Kubernetes endpoints", network_mode));
+        }
+
+        let mut limits = serde_json::Map::new();
+        if let Some(cpus) = job.limits().cpus() {
+            limits.insert(String::from("cpu"), serde_json::Value::from(cpus.to_string()));
+        }
+        if let Some(memory) = job.limits().memory_bytes()? {
+            limits.insert(String::from("memory"), serde_json::Value::from(memory.to_string()));
+        }
+
+        let env = job.environment()
+            .map(|(k, v)| serde_json::json!({ "name": k.as_ref(), "value": v }))
+            .collect::<Vec<_>>();
+
+        // The secrets are only kept in memory, they are written to the volume once the pod runs
+        let (volumes, volume_mounts) = if job.secrets().is_empty() {
+            (serde_json::json!([]), serde_json::json!([]))
+        } else {
+            (
+                serde_json::json!([{ "name": "secrets", "emptyDir": { "medium": "Memory" } }]),
+                serde_json::json!([{ "name": "secrets", "mountPath": crate::consts::SECRETS_DIR_PATH }]),
+            )
+        };
+
+        let manifest = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": pod,
+                "labels": { "app.kubernetes.io/managed-by": "butido" },
+            },
+            "spec": {
+                "restartPolicy": "Never",
+                "containers": [{
+                    "name": pod,
+                    "image": job.image_reference().as_ref(),
+                    "stdin": true,
+                    "command": job.script().interpreter(),
+                    "env": env,
+                    "resources": { "limits": limits },
+                    "volumeMounts": volume_mounts,
+                }],
+                "volumes": volumes,
+            }
+        });
+
+        self.run_with_input(&["create", "--filename=-"], manifest.to_string().as_bytes())
+            .await
+            .with_context(|| anyhow!("Creating pod {}", pod))?;
+
+        let target = format!("pod/{}", pod);
+        let timeout = format!("--timeout={}", POD_READY_TIMEOUT);
+        self.run(&["wait", "--for=condition=Ready", target.as_str(), timeout.as_str()])
+            .await
+            .with_context(|| anyhow!("Waiting for pod {} to become ready", pod))
+            .map(|_| ())
+    }
+
+    /// Delete `pod`, without waiting for it to be gone
+    pub async fn delete_pod(&self, pod: &str) -> Result<()> {
+        self.run(&["delete", "pod", pod, "--wait=false"])
+            .await
+            .with_context(|| anyhow!("Deleting pod {}", pod))
+            .map(|_| ())
+    }
+
+    /// Unpack `tar` (a tar archive) to the root directory of `pod`
+    pub async fn copy_tar_into(&self, pod: &str, tar: &[u8]) -> Result<()> {
+        self.run_with_input(&["exec", "-i", pod, "--", "tar", "xf", "-", "-C", "/"], tar)
+            .await
+            .with_context(|| anyhow!("Copying files into pod {}", pod))
+            .map(|_| ())
+    }
+
+    /// Run `cmd` in `pod` with `input` on its stdin and wait for it to finish
+    pub async fn run_in_pod(&self, pod: &str, cmd: &[&str], input: &[u8]) -> Result<()> {
+        let mut args = vec!["exec", "-i", pod, "--"];
+        args.extend(cmd);
+        self.run_with_input(&args, input)
+            .await
+            .with_context(|| anyhow!("Running {:?} in pod {}", cmd, pod))
+            .map(|_| ())
+    }
+
+    /// Check that `path` exists in `pod`
+    pub async fn verify_exists(&self, pod: &str, path: &str) -> Result<()> {
+        self.run(&["exec", pod, "--", "tar", "cf", "/dev/null", path])
+            .await
+            .with_context(|| anyhow!("Checking for {} in pod {}", path, pod))
+            .map(|_| ())
+    }
+
+    /// Run `cmd` in `pod`
+    ///
+    /// Returns the kubectl process, which has to be waited for after the output stream ended, and
+    /// the output of the command (both stdout and stderr).
+    pub fn exec(&self, pod: &str, cmd: &[&str]) -> Result<(Child, impl Stream<Item = shiplift::Result<TtyChunk>> + Unpin)> {
+        let mut child = self.command()
+            .args(&["exec", pod, "--"])
+            .args(cmd)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running kubectl")?;
+
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout for kubectl"))?;
+        let stderr = child.stderr.take().ok_or_else(|| anyhow!("No stderr for kubectl"))?;
+        let stream = futures::stream::select(
+            Box::pin(read_chunks(stdout, TtyChunk::StdOut)),
+            Box::pin(read_chunks(stderr, TtyChunk::StdErr)),
+        );
+
+        Ok((child, stream))
+    }
+
+    /// Fetch `dir` from `pod` as tar archive
+    ///
+    /// Returns the kubectl process, which has to be waited for after the stream ended, and the
+    /// archive.
+    pub fn copy_from(&self, pod: &str, dir: &str) -> Result<(Child, impl Stream<Item = Result<Vec<u8>>> + Unpin)> {
+        let dir = dir.trim_start_matches('/');
+        let mut child = self.command()
+            .args(&["exec", pod, "--", "tar", "cf", "-", "-C", "/", dir])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running kubectl")?;
+
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout for kubectl"))?;
+        let stream = read_chunks::<_, _, std::io::Error>(stdout, |buf| buf).map_err(Error::from);
+        Ok((child, Box::pin(stream)))
+    }
+}
@@ -0,0 +1,292 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Simulated endpoints, for testing the scheduling of jobs without a container engine
+//!
+//! A mock endpoint does not run the scripts of the jobs. A simulated run waits for a random time
+//! within the configured bounds, then it fails with the configured probability or produces the
+//! configured fake artifacts. Removing a container ends its run right away, like killing a real
+//! container does. The secrets of a job have to be removed from its container before the container
+//! is stopped, like on the other endpoints.
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::Stream;
+use futures::StreamExt;
+use log::trace;
+use rand::Rng;
+use shiplift::tty::TtyChunk;
+use tokio::sync::Notify;
+
+use crate::config::MockConfig;
+use crate::job::RunnableJob;
+
+/// The phase a simulated run announces, so phase timeouts can be tested with it
+const MOCK_PHASE: &str = "mock";
+
+/// An endpoint which simulates the containers of the jobs
+#[derive(Debug)]
+pub struct Mock {
+    config: MockConfig,
+
+    /// The shortest and the longest duration of a run
+    durations: (Duration, Duration),
+
+    /// The containers of the jobs, by ID
+    containers: Mutex<HashMap<String, Arc<MockContainer>>>,
+}
+
+#[derive(Debug)]
+struct MockContainer {
+    job: uuid::Uuid,
+    package_name: String,
+    package_version: String,
+
+    /// Whether the script was run, a container which is not running any more is not listed
+    stopped: AtomicBool,
+
+    /// Notified when the container is removed, which ends the run of the script
+    removed: Notify,
+
+    /// The paths of the secrets which were written to the container and not removed yet
+    secrets: Mutex<BTreeSet<String>>,
+}
+
+impl Mock {
+    pub fn new(config: MockConfig) -> Result<Self> {
+        let durations = config.durations()?;
+        Ok(Mock { config, durations, containers: Mutex::new(HashMap::new()) })
+    }
+
+    /// Create the container for `job`, which fails with the probability `error_rate`
+    ///
+    /// Returns the ID of the container.
+    pub fn create(&self, job: &RunnableJob) -> Result<String> {
+        if roll(self.config.error_rate()) {
+            return Err(anyhow!("Simulated error while creating the container for job {}", job.uuid()));
+        }
+
+        let id = format!("mock-{}", job.uuid());
+        let container = MockContainer {
+            job: *job.uuid(),
+            package_name: job.package().name().to_string(),
+            package_version: job.package().version().to_string(),
+            stopped: AtomicBool::new(false),
+            removed: Notify::new(),
+            secrets: Mutex::new(BTreeSet::new()),
+        };
+
+        trace!("Creating mock container {}", id);
+        self.lock()?.insert(id.clone(), Arc::new(container));
+        Ok(id)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Arc<MockContainer>>>> {
+        self.containers.lock().map_err(|_| anyhow!("Lock on mock containers poisoned"))
+    }
+
+    fn container(&self, id: &str) -> Result<Arc<MockContainer>> {
+        self.lock()?
+            .get(id)
+            .cloned()
+            .ok_or_else(|| anyhow!("No such mock container: {}", id))
+    }
+
+    /// Remove the container `id`, which ends its run if the script is still running
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let container = self.lock()?
+            .remove(id)
+            .ok_or_else(|| anyhow!("No such mock container: {}", id))?;
+
+        trace!("Removing mock container {}", id);
+        container.removed.notify_one();
+        Ok(())
+    }
+
+    /// Stop and remove the container `id` after the run of its script
+    ///
+    /// Fails if there are secrets left in the container.
+    pub fn stop(&self, id: &str) -> Result<()> {
+        let secrets = self.container(id)?.secrets.lock().map_err(|_| anyhow!("Lock on mock secrets poisoned"))?.clone();
+        if !secrets.is_empty() {
+            return Err(anyhow!("Mock container {} was stopped with the secrets {:?} still in it", id, secrets));
+        }
+        self.remove(id)
+    }
+
+    /// Simulate writing the secret file `path` to the container `id`
+    pub fn write_secret(&self, id: &str, path: &str) -> Result<()> {
+        trace!("Writing {} to mock container {}", path, id);
+        self.container(id)?
+            .secrets
+            .lock()
+            .map_err(|_| anyhow!("Lock on mock secrets poisoned"))?
+            .insert(path.to_string());
+        Ok(())
+    }
+
+    /// Simulate removing the secret files `paths` from the container `id`
+    pub fn remove_secrets(&self, id: &str, paths: &[&str]) -> Result<()> {
+        trace!("Removing {:?} from mock container {}", paths, id);
+        let container = self.container(id)?;
+        let mut secrets = container.secrets.lock().map_err(|_| anyhow!("Lock on mock secrets poisoned"))?;
+        for path in paths {
+            secrets.remove(*path);
+        }
+        Ok(())
+    }
+
+    /// The number of containers which did not finish their run yet
+    pub fn running_containers(&self) -> Result<usize> {
+        self.lock().map(|containers| containers.values().filter(|c| !c.stopped.load(Ordering::Relaxed)).count())
+    }
+
+    /// The IDs of the containers which did not finish their run yet, with the UUIDs of their jobs
+    pub fn butido_containers(&self) -> Result<Vec<(String, String)>> {
+        self.lock().map(|containers| {
+            containers
+                .iter()
+                .filter(|(_, c)| !c.stopped.load(Ordering::Relaxed))
+                .map(|(id, c)| (id.clone(), c.job.to_string()))
+                .collect()
+        })
+    }
+
+    pub fn debug_command(&self, container_id: &str) -> String {
+        format!("# {} is a mock container, there is nothing to debug", container_id)
+    }
+
+    /// Run `cmd` in the container `id`
+    ///
+    /// Only running the script is simulated, every other command succeeds without output.
+    pub fn exec(&self, id: &str, cmd: &[&str]) -> Result<Pin<Box<dyn Stream<Item = shiplift::Result<TtyChunk>> + Send>>> {
+        let container = self.container(id)?;
+        let runs_script = cmd
+            .last()
+            .map(|c| *c == crate::consts::SCRIPT_PATH || *c == crate::consts::READ_ONLY_SCRIPT_PATH)
+            .unwrap_or(false);
+
+        if !runs_script {
+            trace!("Not running {:?} in mock container {}", cmd, id);
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+
+        let duration = pick_duration(self.durations);
+        let fails = roll(self.config.failure_rate());
+        trace!("Simulating a run of {:?} in mock container {}, fails: {}", duration, id, fails);
+
+        let start = vec![
+            format!("#BUTIDO:PHASE:{}\n", MOCK_PHASE),
+            format!("Simulating the build of {} {} for {:?}\n", container.package_name, container.package_version, duration),
+        ];
+
+        let end = async move {
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {
+                    container.stopped.store(true, Ordering::Relaxed);
+                    if fails {
+                        vec![String::from("#BUTIDO:STATE:ERR:simulated failure\n")]
+                    } else {
+                        vec![String::from("#BUTIDO:STATE:OK\n")]
+                    }
+                },
+
+                // Like a killed container, a removed one does not report a state
+                _ = container.removed.notified() => vec![],
+            }
+        };
+
+        let stream = futures::stream::iter(start)
+            .chain(futures::stream::once(end).flat_map(futures::stream::iter))
+            .map(|line| Ok(TtyChunk::StdOut(line.into_bytes())));
+        Ok(Box::pin(stream))
+    }
+
+    /// The outputs directory of the container `id` as tar archive, with the fake artifacts
+    pub fn copy_outputs(&self, id: &str) -> Result<Vec<u8>> {
+        let container = self.container(id)?;
+        let names = self.config.artifact_names(&container.package_name, &container.package_version);
+        let content = format!("Fake artifact of {} {}\n", container.package_name, container.package_version);
+        outputs_archive(&names, content.as_bytes())
+    }
+}
+
+/// Whether an event with the probability `rate` happens
+fn roll(rate: f64) -> bool {
+    rate > 0.0 && rand::thread_rng().next_f64() < rate
+}
+
+/// A random duration between the two of `durations`
+fn pick_duration((min, max): (Duration, Duration)) -> Duration {
+    let (min_ms, max_ms) = (min.as_millis() as u64, max.as_millis() as u64);
+    if max_ms > min_ms {
+        Duration::from_millis(rand::thread_rng().gen_range(min_ms, max_ms + 1))
+    } else {
+        min
+    }
+}
+
+/// A tar archive of the outputs directory with the files `names`, which all contain `content`
+fn outputs_archive(names: &[String], content: &[u8]) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for name in names {
+        let path = Path::new(crate::consts::OUTPUTS_DIR_NAME).join(name);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, &path, content)
+            .with_context(|| anyhow!("Adding {} to outputs archive", path.display()))?;
+    }
+
+    builder.into_inner().context("Finishing outputs archive")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll() {
+        assert!((0..100).all(|_| !roll(0.0)));
+        assert!((0..100).all(|_| roll(1.0)));
+    }
+
+    #[test]
+    fn test_pick_duration() {
+        let (min, max) = (Duration::from_secs(1), Duration::from_secs(2));
+        assert!((0..100).map(|_| pick_duration((min, max))).all(|d| (min..=max).contains(&d)));
+        assert_eq!(pick_duration((min, min)), min);
+    }
+
+    #[test]
+    fn test_outputs_archive() {
+        let names = vec![String::from("foo-1.0.tar.gz"), String::from("foo.log")];
+        let archive = outputs_archive(&names, b"fake").unwrap();
+
+        let paths = tar::Archive::new(&archive[..])
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![Path::new("outputs/foo-1.0.tar.gz"), Path::new("outputs/foo.log")]);
+    }
+}
@@ -8,14 +8,35 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+mod checkpoint;
+pub use checkpoint::*;
+
 mod configuration;
 pub use configuration::*;
 
 mod scheduler;
 pub use scheduler::*;
 
+mod scratch;
+pub use scratch::*;
+
 mod configured;
 pub use configured::*;
 
+mod kubernetes;
+pub use kubernetes::*;
+
+mod mock;
+pub use mock::*;
+
+mod inactivity_watch;
+pub use inactivity_watch::*;
+
+mod phase_watch;
+pub use phase_watch::*;
+
+mod ssh;
+pub use ssh::*;
+
 pub mod util;
 
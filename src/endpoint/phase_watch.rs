@@ -0,0 +1,96 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Watches the phases a script announces and whether the current one runs longer than allowed
+pub struct PhaseWatch {
+    /// How long the phases may run, by the name of the phase
+    timeouts: HashMap<String, Duration>,
+
+    /// The phase the script is in, with the time it was announced
+    current: Mutex<Option<(String, Instant)>>,
+}
+
+impl PhaseWatch {
+    pub fn new(timeouts: HashMap<String, Duration>) -> Self {
+        PhaseWatch {
+            timeouts,
+            current: Mutex::new(None),
+        }
+    }
+
+    /// The script announced the phase `phase`, which ends the previous one
+    pub fn enter(&self, phase: &str) {
+        self.enter_at(phase, Instant::now())
+    }
+
+    fn enter_at(&self, phase: &str, now: Instant) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = Some((phase.to_string(), now));
+        }
+    }
+
+    /// The current phase and its timeout, if it runs longer than allowed
+    fn exceeded_at(&self, now: Instant) -> Option<(String, Duration)> {
+        let current = self.current.lock().ok()?;
+        let (phase, started) = current.as_ref()?;
+        let timeout = self.timeouts.get(phase)?;
+
+        if now.saturating_duration_since(*started) > *timeout {
+            Some((phase.clone(), *timeout))
+        } else {
+            None
+        }
+    }
+
+    /// Wait until the current phase runs longer than allowed and return it with its timeout
+    ///
+    /// Never finishes if no timeouts are configured.
+    pub async fn timed_out(&self) -> (String, Duration) {
+        if self.timeouts.is_empty() {
+            return futures::future::pending().await
+        }
+
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if let Some(exceeded) = self.exceeded_at(Instant::now()) {
+                return exceeded
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeded() {
+        let timeouts = vec![(String::from("configure"), Duration::from_secs(60))].into_iter().collect();
+        let watch = PhaseWatch::new(timeouts);
+        let start = Instant::now();
+        assert_eq!(watch.exceeded_at(start), None);
+
+        watch.enter_at("configure", start);
+        assert_eq!(watch.exceeded_at(start + Duration::from_secs(60)), None);
+        assert_eq!(
+            watch.exceeded_at(start + Duration::from_secs(61)),
+            Some((String::from("configure"), Duration::from_secs(60)))
+        );
+
+        // Phases without timeout run as long as they need
+        watch.enter_at("build", start + Duration::from_secs(30));
+        assert_eq!(watch.exceeded_at(start + Duration::from_secs(3600)), None);
+    }
+}
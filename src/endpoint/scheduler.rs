@@ -8,8 +8,12 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -19,52 +23,181 @@ use colored::Colorize;
 use diesel::PgConnection;
 use indicatif::ProgressBar;
 use itertools::Itertools;
+use log::debug;
 use log::trace;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+use crate::config::EndpointName;
+use crate::db::DbPool;
+use crate::db::get_connection;
 use crate::db::models as dbmodels;
+use crate::endpoint::CheckpointWatch;
 use crate::endpoint::Endpoint;
 use crate::endpoint::EndpointHandle;
 use crate::endpoint::EndpointConfiguration;
 use crate::filestore::ArtifactPath;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
+use crate::job::CacheKey;
 use crate::job::JobResource;
 use crate::job::RunnableJob;
+use crate::log::JsonLogRecord;
+use crate::log::LogFormat;
 use crate::log::LogItem;
+use crate::log::LogPrefix;
+use crate::log::LogRecord;
+use crate::log::LogSinks;
+use crate::log::LogStream;
+use crate::package::HashType;
+use crate::package::condition::ImageInfo;
+use crate::util::Architecture;
+use crate::util::docker::ImageName;
+use crate::util::metrics::Metrics;
+use crate::util::progress::ProgressBars;
+
+/// The number of log lines of a job that are recorded as live log at once
+const LIVE_LOG_BATCH_SIZE: usize = 100;
+
+/// How long log lines of a job are collected at most before they are recorded as live log
+const LIVE_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long to wait for a job to finish before checking for free endpoints again, at first
+///
+/// The interval doubles with every check that did not find a free endpoint, up to
+/// `FREE_ENDPOINT_RECHECK_MAX_INTERVAL`.
+const FREE_ENDPOINT_RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// The longest time to wait for a job to finish before checking for free endpoints again
+const FREE_ENDPOINT_RECHECK_MAX_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Endpoints which are connected once and shared by several schedulers
+///
+/// The running jobs are counted by the endpoints, so schedulers that share the endpoints (e.g.
+/// the ones of submits which run at the same time in one process) share their slots, and all
+/// schedulers are notified when a slot gets free. A free slot goes to the submit with the fewest
+/// running jobs among the submits waiting for it, see `SlotShares`.
+#[derive(Clone)]
+pub struct SharedEndpoints {
+    endpoints: Vec<Arc<Endpoint>>,
+
+    /// Notified whenever a job finished and its endpoint has a free slot again
+    job_finished: Arc<Notify>,
+
+    /// The running and waiting jobs of the submits which share the endpoints
+    slot_shares: Arc<SlotShares>,
+}
+
+impl SharedEndpoints {
+    pub async fn setup(endpoints: Vec<EndpointConfiguration>) -> Result<Self> {
+        Ok(SharedEndpoints {
+            endpoints: crate::endpoint::util::setup_endpoints(endpoints).await?,
+            job_finished: Arc::new(Notify::new()),
+            slot_shares: Arc::new(SlotShares::default()),
+        })
+    }
+
+    /// Query what is known about the images on the endpoints which support `image`, for the
+    /// conditions of packages on images
+    ///
+    /// `reference` is the reference the containers of the image are created from.
+    /// The images are the ones that exist on all of these endpoints, the labels of `image` are
+    /// taken from the first endpoint that knows them. Kubernetes and mock endpoints do not know
+    /// their images, so they are skipped.
+    pub async fn image_info(&self, image: &ImageName, reference: &ImageName) -> Result<ImageInfo> {
+        let mut images: Option<Vec<ImageName>> = None;
+        let mut labels = None;
+
+        for ep in self.endpoints.iter().filter(|ep| ep.supports_image(image)) {
+            if let Some(names) = ep.image_names().await? {
+                images = Some(match images {
+                    Some(known) => known.into_iter().filter(|i| names.contains(i)).collect(),
+                    None => names,
+                });
+            }
+
+            if labels.is_none() {
+                labels = ep.image_labels(reference).await?;
+            }
+        }
+
+        if labels.is_none() {
+            log::warn!("No endpoint knows the labels of image {}, conditions on them do not match", image);
+        }
+
+        Ok(ImageInfo::new(images.unwrap_or_default(), labels.unwrap_or_default()))
+    }
+}
 
 pub struct EndpointScheduler {
     log_dir: Option<PathBuf>,
+    log_split_dir: Option<PathBuf>,
+
+    /// Where the logs of the jobs are forwarded to, if any log sinks are configured
+    log_sinks: Option<Arc<LogSinks>>,
+    stream_logs: bool,
+    log_format: LogFormat,
+    log_max_line_length: usize,
+    checksum_algorithm: HashType,
     endpoints: Vec<Arc<Endpoint>>,
 
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
-    db: Arc<PgConnection>,
-    submit: crate::db::models::Submit,
+    db: DbPool,
+
+    /// Notified whenever a job finished and its endpoint has a free slot again
+    job_finished: Arc<Notify>,
+
+    /// The running and waiting jobs of the submits which share the endpoints
+    slot_shares: Arc<SlotShares>,
+
+    /// The number of jobs waiting for a free endpoint
+    queued_jobs: AtomicUsize,
+
+    /// The submit the scheduled jobs belong to, if this scheduler is used for scheduling jobs at all
+    submit: Option<crate::db::models::Submit>,
+    metrics: Arc<Metrics>,
 }
 
 impl EndpointScheduler {
-    pub async fn setup(
-        endpoints: Vec<EndpointConfiguration>,
+    #[allow(clippy::too_many_arguments)]
+    pub fn setup(
+        endpoints: SharedEndpoints,
         staging_store: Arc<RwLock<StagingStore>>,
         release_stores: Vec<Arc<ReleaseStore>>,
-        db: Arc<PgConnection>,
-        submit: crate::db::models::Submit,
+        db: DbPool,
+        submit: Option<crate::db::models::Submit>,
         log_dir: Option<PathBuf>,
-    ) -> Result<Self> {
-        let endpoints = crate::endpoint::util::setup_endpoints(endpoints).await?;
-
-        Ok(EndpointScheduler {
+        log_split_dir: Option<PathBuf>,
+        log_sinks: Option<Arc<LogSinks>>,
+        stream_logs: bool,
+        log_format: LogFormat,
+        log_max_line_length: usize,
+        checksum_algorithm: HashType,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        EndpointScheduler {
             log_dir,
-            endpoints,
+            log_split_dir,
+            log_sinks,
+            stream_logs,
+            log_format,
+            log_max_line_length,
+            checksum_algorithm,
+            endpoints: endpoints.endpoints,
             staging_store,
             release_stores,
             db,
+            job_finished: endpoints.job_finished,
+            slot_shares: endpoints.slot_shares,
+            queued_jobs: AtomicUsize::new(0),
             submit,
-        })
+            metrics,
+        }
     }
 
     /// Schedule a Job
@@ -72,94 +205,436 @@ impl EndpointScheduler {
     /// # Warning
     ///
     /// This function blocks as long as there is no free endpoint available!
-    pub async fn schedule_job(&self, job: RunnableJob, bar: indicatif::ProgressBar) -> Result<JobHandle> {
-        let endpoint = self.select_free_endpoint().await?;
+    ///
+    /// The endpoints in `avoid` are only used if no other endpoint is free.
+    /// If `cancellation` is cancelled while the job runs, its container is removed.
+    pub async fn schedule_job(
+        &self,
+        job: RunnableJob,
+        bar: indicatif::ProgressBar,
+        avoid: &[EndpointName],
+        cancellation: CancellationToken,
+    ) -> Result<JobHandle> {
+        let submit = self.submit
+            .clone()
+            .ok_or_else(|| anyhow!("Cannot schedule job {} without a submit", job.uuid()))?;
+        if let Some(architecture) = job.architecture() {
+            if !self.endpoints.iter().any(|ep| ep.architectures().contains(architecture)) {
+                return Err(anyhow!("No endpoint can build job {} for architecture {}", job.uuid(), architecture));
+            }
+        }
+        if !self.endpoints.iter().any(|ep| ep.supports_image(job.image())) {
+            return Err(anyhow!("No endpoint can run job {} with image {}", job.uuid(), job.image()));
+        }
+        let (endpoint, slot) = self.select_free_endpoint(&submit.uuid, avoid, job.architecture().as_ref(), job.image()).await?;
 
         Ok(JobHandle {
             log_dir: self.log_dir.clone(),
+            log_split_dir: self.log_split_dir.clone(),
+            log_sinks: self.log_sinks.clone(),
+            stream_logs: self.stream_logs,
+            log_format: self.log_format,
+            log_max_line_length: self.log_max_line_length,
+            checksum_algorithm: self.checksum_algorithm.clone(),
             bar,
             endpoint,
+            _slot: slot,
             job,
             staging_store: self.staging_store.clone(),
             release_stores: self.release_stores.clone(),
             db: self.db.clone(),
-            submit: self.submit.clone(),
+            submit,
+            metrics: self.metrics.clone(),
+            cancellation,
         })
     }
 
-    async fn select_free_endpoint(&self) -> Result<EndpointHandle> {
+    /// Get the names of all endpoints jobs could be scheduled to right now
+    ///
+    /// These are all configured endpoints which are not drained.
+    pub async fn schedulable_endpoints(&self) -> Result<Vec<EndpointName>> {
+        let drained = dbmodels::Endpoint::drained_names(&*get_connection(&self.db).await?)?;
+        Ok(self.endpoints
+            .iter()
+            .filter(|ep| !drained.iter().any(|d| d == ep.name().as_ref()))
+            .map(|ep| ep.name().clone())
+            .collect())
+    }
+
+    /// Get the number of endpoints which are running jobs and the number of idle endpoints
+    pub fn endpoint_utilization(&self) -> (usize, usize) {
+        let busy = self.endpoints.iter().filter(|ep| ep.running_jobs() > 0).count();
+        (busy, self.endpoints.len() - busy)
+    }
+
+    /// Get the number of jobs that wait for a free endpoint
+    pub fn queued_jobs(&self) -> usize {
+        self.queued_jobs.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of jobs that can run at the same time on all endpoints
+    pub fn job_slots(&self) -> usize {
+        self.endpoints.iter().map(|ep| ep.num_max_jobs()).sum()
+    }
+
+    /// Make sure that `images` are available on all endpoints jobs could be scheduled to
+    ///
+    /// The images are passed by name, with the reference the containers are created from. An
+    /// endpoint only needs the images it supports.
+    /// If `pull` is set, the missing images are pulled, showing a spinner per pull. Otherwise the
+    /// missing images are reported as error.
+    /// Kubernetes endpoints are skipped, their nodes pull the images when they need them.
+    pub async fn prepare_images(&self, images: &[(ImageName, ImageName)], pull: bool, progressbars: &ProgressBars) -> Result<()> {
         use futures::stream::StreamExt;
 
+        let drained = dbmodels::Endpoint::drained_names(&*get_connection(&self.db).await?)?;
+        self.endpoints
+            .iter()
+            .filter(|ep| !drained.iter().any(|d| d == ep.name().as_ref()))
+            .map(|ep| prepare_images_on(ep, images, pull, progressbars))
+            .collect::<futures::stream::FuturesUnordered<_>>()
+            .collect::<Vec<Result<()>>>()
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Get the digests of the image with the passed name on all endpoints which support it
+    ///
+    /// `reference` is the reference the containers of the image are created from.
+    /// The returned list is unique, so if all endpoints have the same image, the list contains
+    /// exactly one element.
+    /// Endpoints which do not know the digest of their images (Kubernetes and mock endpoints) report
+    /// `None`.
+    pub async fn image_digests(&self, image: &ImageName, reference: &ImageName) -> Result<Vec<Option<String>>> {
+        use futures::stream::StreamExt;
+
+        self.endpoints
+            .iter()
+            .filter(|ep| ep.supports_image(image))
+            .map(|ep| ep.image_digest(reference))
+            .collect::<futures::stream::FuturesUnordered<_>>()
+            .collect::<Vec<Result<Option<String>>>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<Option<String>>>>()
+            .map(|digests| digests.into_iter().unique().collect())
+    }
+
+    /// Wait for a free endpoint which can run a job of `submit` for `architecture` with `image`
+    ///
+    /// The endpoints are checked again whenever a job finished. Endpoints might also become usable
+    /// because they are undrained or enter a build window, which nobody is notified about, so
+    /// they are also checked after some time without finished jobs, with capped exponential
+    /// backoff.
+    /// A free slot is left to the jobs of other submits if they have fewer running jobs and wait
+    /// for the same endpoint.
+    async fn select_free_endpoint(
+        &self,
+        submit: &Uuid,
+        avoid: &[EndpointName],
+        architecture: Option<&Architecture>,
+        image: &ImageName,
+    ) -> Result<(EndpointHandle, SubmitSlot)> {
+        let mut recheck_interval = FREE_ENDPOINT_RECHECK_INTERVAL;
+        let mut queued = None;
+
+        let usable = self.endpoints
+            .iter()
+            .filter(|ep| architecture.map(|a| ep.architectures().contains(a)).unwrap_or(true))
+            .filter(|ep| ep.supports_image(image))
+            .map(|ep| ep.name().clone())
+            .collect::<Vec<_>>();
+        let _waiting = WaitingJob::new(&self.slot_shares, *submit, usable, self.job_finished.clone());
+
         loop {
+            // Register for the notification before looking for a free endpoint, so that a job which
+            // finishes while we are looking is not missed
+            let job_finished = self.job_finished.notified();
+
+            let drained = dbmodels::Endpoint::drained_names(&*get_connection(&self.db).await?)?;
+            let now = chrono::offset::Local::now().naive_local();
             let ep = self
                 .endpoints
                 .iter()
-                .filter(|ep| { // filter out all running containers where the number of max jobs is reached
-                    let r = ep.running_jobs() < ep.num_max_jobs();
-                    trace!("Endpoint {} considered for scheduling job: {}", ep.name(), r);
-                    r
+                .filter(|ep| { // filter out all endpoints which are drained for maintenance
+                    let is_drained = drained.iter().any(|d| d == ep.name().as_ref());
+                    if is_drained {
+                        trace!("Endpoint {} is drained, not considered for scheduling job", ep.name());
+                    }
+                    !is_drained
                 })
-                .map(|ep| {
-                    let ep = ep.clone();
-                    async {
-                        let num = ep.number_of_running_containers().await?;
-                        trace!("Number of running containers on {} = {}", ep.name(), num);
-                        Ok((ep, num))
+                .filter(|ep| { // filter out all endpoints which cannot build for the architecture of the job
+                    let supported = architecture.map(|a| ep.architectures().contains(a)).unwrap_or(true);
+                    if !supported {
+                        trace!("Endpoint {} does not support the architecture of the job, not considered for scheduling job", ep.name());
                     }
+                    supported
                 })
-                .collect::<futures::stream::FuturesUnordered<_>>()
-                .collect::<Vec<_>>()
-                .await // Vec<Result<_>>
-                .into_iter()
-                .collect::<Result<Vec<_>>>()? // -> Vec<_>
-                .into_iter()
-                .sorted_by(|(ep1, ep1_running), (ep2, ep2_running)| {
-                    match ep1_running.partial_cmp(ep2_running).unwrap_or(std::cmp::Ordering::Equal) {
-                        std::cmp::Ordering::Equal =>  {
-                            trace!("Number of running containers on {} and {} equal ({}), using utilization", ep1.name(), ep2.name(), ep2_running);
-                            let ep1_util = ep1.utilization();
-                            let ep2_util = ep1.utilization();
-
-                            trace!("{} utilization: {}", ep1.name(), ep1_util);
-                            trace!("{} utilization: {}", ep2.name(), ep2_util);
-
-                            ep1_util.partial_cmp(&ep2_util).unwrap_or(std::cmp::Ordering::Equal)
-                        },
-
-                        std::cmp::Ordering::Less => {
-                            trace!("On {} run less ({}) containers than on {} ({})", ep1.name(), ep1_running, ep2.name(), ep2_running);
-                            std::cmp::Ordering::Less
-                        },
-
-                        std::cmp::Ordering::Greater => {
-                            trace!("On {} run more ({}) containers than on {} ({})", ep1.name(), ep1_running, ep2.name(), ep2_running);
-                            std::cmp::Ordering::Greater
-                        }
+                .filter(|ep| { // filter out all endpoints which do not run jobs with the image of the job
+                    let supported = ep.supports_image(image);
+                    if !supported {
+                        trace!("Endpoint {} does not support the image of the job, not considered for scheduling job", ep.name());
                     }
+                    supported
                 })
-                .next()
-                .map(|(ep, _)| ep);
+                .filter(|ep| { // filter out all endpoints which are outside of their build windows
+                    let in_window = crate::config::is_in_build_window(ep.build_windows(), &now);
+                    if !in_window {
+                        trace!("Endpoint {} is outside of its build windows, not considered for scheduling job", ep.name());
+                    }
+                    in_window
+                })
+                .filter(|ep| { // filter out all endpoints where the number of max jobs is reached
+                    let r = ep.running_jobs() < ep.num_max_jobs();
+                    trace!("Endpoint {} considered for scheduling job: {}", ep.name(), r);
+                    r
+                })
+
+                // Prefer endpoints which are not to be avoided, then the least utilized endpoint. The
+                // slots are counted by the scheduler itself, so the endpoints do not have to be asked
+                // for their running containers
+                .sorted_by(|ep1, ep2| {
+                    let ep1_util = ep1.utilization();
+                    let ep2_util = ep2.utilization();
+
+                    trace!("{} utilization: {}", ep1.name(), ep1_util);
+                    trace!("{} utilization: {}", ep2.name(), ep2_util);
 
-            if let Some(endpoint) = ep {
+                    let ep1_avoided = avoid.contains(ep1.name());
+                    let ep2_avoided = avoid.contains(ep2.name());
+                    ep1_avoided.cmp(&ep2_avoided)
+                        .then_with(|| ep1_util.partial_cmp(&ep2_util).unwrap_or(std::cmp::Ordering::Equal))
+                })
+                .cloned()
+
+                // Another job might have taken the last slot on the endpoint since we checked, so
+                // the slot is only ours if we can reserve it
+                .find_map(|ep| self.slot_shares.try_reserve(submit, ep, &self.job_finished));
+
+            if let Some((endpoint, slot)) = ep {
                 trace!("Selected = {}", endpoint.name());
-                return Ok(EndpointHandle::new(endpoint));
-            } else {
-                trace!("No free endpoint found, retry...");
-                tokio::task::yield_now().await
+                return Ok((endpoint, slot));
+            }
+
+            if queued.is_none() {
+                debug!("All endpoints for the job are busy, queueing the job");
+                queued = Some(QueuedJob::new(&self.queued_jobs));
+            }
+
+            trace!("No free endpoint found, waiting up to {:?} for a job to finish...", recheck_interval);
+            if tokio::time::timeout(recheck_interval, job_finished).await.is_err() {
+                recheck_interval = next_recheck_interval(recheck_interval);
+            }
+        }
+    }
+}
+
+/// The interval after `interval` passed without a job finishing and without a free endpoint
+fn next_recheck_interval(interval: std::time::Duration) -> std::time::Duration {
+    std::cmp::min(interval * 2, FREE_ENDPOINT_RECHECK_MAX_INTERVAL)
+}
+
+/// Counts a job as queued for as long as it waits for a free endpoint
+///
+/// The job stops waiting when the guard is dropped, also if the wait is cancelled.
+struct QueuedJob<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedJob<'a> {
+    fn new(queued_jobs: &'a AtomicUsize) -> Self {
+        queued_jobs.fetch_add(1, Ordering::Relaxed);
+        QueuedJob(queued_jobs)
+    }
+}
+
+impl Drop for QueuedJob<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How the slots of shared endpoints are shared between the submits which run at the same time
+///
+/// A free slot on an endpoint goes to the submit with the fewest running jobs among the submits
+/// with jobs waiting for that endpoint, so that a submit with many jobs cannot starve the others.
+#[derive(Default)]
+struct SlotShares(Mutex<HashMap<Uuid, SubmitShare>>);
+
+#[derive(Debug, Default)]
+struct SubmitShare {
+    /// The number of running jobs of the submit
+    running: usize,
+
+    /// The number of jobs of the submit waiting for a free slot, by the endpoints they can run on
+    waiting: HashMap<EndpointName, usize>,
+}
+
+impl SlotShares {
+    fn submits(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, SubmitShare>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a job of `submit` may take a free slot on `endpoint`
+    ///
+    /// It may not if another submit with fewer running jobs waits for the endpoint.
+    fn may_take(submits: &HashMap<Uuid, SubmitShare>, submit: &Uuid, endpoint: &EndpointName) -> bool {
+        let running = submits.get(submit).map(|s| s.running).unwrap_or(0);
+        !submits
+            .iter()
+            .filter(|(uuid, _)| *uuid != submit)
+            .filter(|(_, share)| share.waiting.get(endpoint).map(|n| *n > 0).unwrap_or(false))
+            .any(|(_, share)| share.running < running)
+    }
+
+    /// Reserve a slot on `ep` for a job of `submit`, if the endpoint has a free slot and the submit
+    /// is entitled to it
+    fn try_reserve(self: &Arc<Self>, submit: &Uuid, ep: Arc<Endpoint>, job_finished: &Arc<Notify>) -> Option<(EndpointHandle, SubmitSlot)> {
+        let mut submits = self.submits();
+        if !Self::may_take(&submits, submit, ep.name()) {
+            trace!("Leaving the free slot on {} to a submit with fewer running jobs", ep.name());
+            return None;
+        }
+
+        let endpoint = EndpointHandle::try_reserve(ep, job_finished.clone())?;
+        submits.entry(*submit).or_default().running += 1;
+        let slot = SubmitSlot {
+            shares: self.clone(),
+            submit: *submit,
+            job_finished: job_finished.clone(),
+        };
+        Some((endpoint, slot))
+    }
+
+    /// Forget submits without running or waiting jobs
+    fn remove_idle(submits: &mut HashMap<Uuid, SubmitShare>, submit: &Uuid) {
+        let idle = submits
+            .get(submit)
+            .map(|s| s.running == 0 && s.waiting.values().all(|n| *n == 0))
+            .unwrap_or(false);
+        if idle {
+            submits.remove(submit);
+        }
+    }
+}
+
+/// Counts a running job in the share of its submit
+///
+/// When the guard is dropped, the job is not counted anymore and everyone waiting for a free slot
+/// is notified, as the slot might go to another submit now.
+struct SubmitSlot {
+    shares: Arc<SlotShares>,
+    submit: Uuid,
+    job_finished: Arc<Notify>,
+}
+
+impl Drop for SubmitSlot {
+    fn drop(&mut self) {
+        {
+            let mut submits = self.shares.submits();
+            if let Some(share) = submits.get_mut(&self.submit) {
+                share.running = share.running.saturating_sub(1);
+            }
+            SlotShares::remove_idle(&mut submits, &self.submit);
+        }
+        self.job_finished.notify_waiters();
+    }
+}
+
+/// Counts a job as waiting for a slot on one of the endpoints it can run on, in the share of its
+/// submit
+///
+/// When the guard is dropped, everyone waiting for a free slot is notified, as a slot that was left
+/// to this job might be free for them now.
+struct WaitingJob<'a> {
+    shares: &'a SlotShares,
+    submit: Uuid,
+    endpoints: Vec<EndpointName>,
+    job_finished: Arc<Notify>,
+}
+
+impl<'a> WaitingJob<'a> {
+    fn new(shares: &'a SlotShares, submit: Uuid, endpoints: Vec<EndpointName>, job_finished: Arc<Notify>) -> Self {
+        {
+            let mut submits = shares.submits();
+            let share = submits.entry(submit).or_default();
+            for ep in endpoints.iter() {
+                *share.waiting.entry(ep.clone()).or_insert(0) += 1;
+            }
+        }
+        WaitingJob { shares, submit, endpoints, job_finished }
+    }
+}
+
+impl Drop for WaitingJob<'_> {
+    fn drop(&mut self) {
+        {
+            let mut submits = self.shares.submits();
+            if let Some(share) = submits.get_mut(&self.submit) {
+                for ep in self.endpoints.iter() {
+                    if let Some(n) = share.waiting.get_mut(ep) {
+                        *n = n.saturating_sub(1);
+                    }
+                }
             }
+            SlotShares::remove_idle(&mut submits, &self.submit);
         }
+        self.job_finished.notify_waiters();
     }
 }
 
+/// Make sure that `images` are available on `ep`, see `EndpointScheduler::prepare_images()`
+async fn prepare_images_on(ep: &Endpoint, images: &[(ImageName, ImageName)], pull: bool, progressbars: &ProgressBars) -> Result<()> {
+    let available = match ep.image_names().await? {
+        Some(names) => names,
+        None => return Ok(()),
+    };
+
+    let missing = images
+        .iter()
+        .filter(|(name, _)| ep.supports_image(name))
+        .map(|(_, reference)| reference)
+        .filter(|reference| !available.iter().any(|name| name.is_same_image(reference)))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    if !pull {
+        return Err(anyhow!("Images missing from endpoint {}: {}", ep.name(), missing.iter().join(", ")))
+            .context("Enable 'docker.pull_images' to pull missing images before building");
+    }
+
+    for image in missing {
+        let bar = progressbars.spinner();
+        bar.set_message(format!("Pulling {} on {}", image, ep.name()));
+        let res = ep.pull_image(image, &bar).await;
+        let state = if res.is_ok() { "Pulled" } else { "Failed pulling" };
+        bar.finish_with_message(format!("{} {} on {}", state, image, ep.name()));
+        res?;
+    }
+    Ok(())
+}
+
 pub struct JobHandle {
     log_dir: Option<PathBuf>,
+    log_split_dir: Option<PathBuf>,
+    log_sinks: Option<Arc<LogSinks>>,
+    stream_logs: bool,
+    log_format: LogFormat,
+    log_max_line_length: usize,
+    checksum_algorithm: HashType,
     endpoint: EndpointHandle,
+
+    /// Counts the job in the share of its submit of the endpoint slots while it runs
+    _slot: SubmitSlot,
     job: RunnableJob,
     bar: ProgressBar,
-    db: Arc<PgConnection>,
+    db: DbPool,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
     submit: crate::db::models::Submit,
+    metrics: Arc<Metrics>,
+    cancellation: CancellationToken,
 }
 
 impl std::fmt::Debug for JobHandle {
@@ -169,20 +644,96 @@ impl std::fmt::Debug for JobHandle {
 }
 
 impl JobHandle {
-    pub async fn run(self) -> Result<Result<Vec<ArtifactPath>>> {
-        let (log_sender, log_receiver) = tokio::sync::mpsc::unbounded_channel::<LogItem>();
-        let endpoint_uri = self.endpoint.uri().clone();
+    /// The name of the endpoint the job is run on
+    pub fn endpoint_name(&self) -> &EndpointName {
+        self.endpoint.name()
+    }
+
+    /// Run the job
+    ///
+    /// Returns the artifacts the job produced, with the ids of their database rows.
+    pub async fn run(self) -> Result<Result<Vec<(ArtifactPath, i32)>>> {
+        let job_id = *self.job.uuid();
+        let db = self.db.clone();
+        let metrics = self.metrics.clone();
         let endpoint_name = self.endpoint.name().clone();
-        let endpoint = dbmodels::Endpoint::create_or_fetch(&self.db, self.endpoint.name())?;
-        let package = dbmodels::Package::create_or_fetch(&self.db, self.job.package())?;
-        let image = dbmodels::Image::create_or_fetch(&self.db, self.job.image())?;
-        let envs = self.create_env_in_db()?;
+
+        metrics.container_started(endpoint_name.as_ref());
+        let res = self.run_job().await;
+        metrics.container_finished(endpoint_name.as_ref());
+
+        // Either the log is recorded with the job now or the job failed, the live log is not
+        // needed anymore in both cases
+        let removed = get_connection(&db)
+            .await
+            .and_then(|conn| dbmodels::LiveLogLine::delete_for_job(&conn, &job_id));
+        if let Err(e) = removed {
+            log::warn!("Removing live log of job {} failed: {:?}", job_id, e);
+        }
+
+        res
+    }
+
+    /// Compute the cache key of the job, which is recorded with the job so that its artifacts can
+    /// be reused by a job with the very same inputs
+    async fn cache_key(&self, image_digest: Option<&str>) -> Result<CacheKey> {
+        let dependencies = self.job
+            .resources()
+            .iter()
+            .filter_map(JobResource::artifact)
+            .cloned()
+            .collect::<Vec<ArtifactPath>>();
+        let dependency_hashes = async {
+            let paths = {
+                let staging_read = self.staging_store.read().await;
+                crate::job::locate_artifacts(&dependencies, &staging_read, &self.release_stores)?
+            };
+            crate::job::hash_artifacts(paths).await
+        }
+        .await
+        .with_context(|| anyhow!("Hashing input artifacts of job {}", self.job.uuid()))?;
+
+        Ok(CacheKey::new(
+            self.job.package(),
+            self.job.script(),
+            self.job.environment(),
+            &dependency_hashes,
+            image_digest,
+        ))
+    }
+
+    async fn run_job(self) -> Result<Result<Vec<(ArtifactPath, i32)>>> {
+        let started = chrono::offset::Local::now().naive_local();
+        let (log_sender, log_receiver) = tokio::sync::mpsc::unbounded_channel::<(LogStream, LogItem)>();
+        let endpoint_name = self.endpoint.name().clone();
+        let endpoint_is_mock = self.endpoint.is_mock();
+        let (endpoint, package, image, envs) = {
+            let conn = get_connection(&self.db).await?;
+            let endpoint = dbmodels::Endpoint::create_or_fetch(&conn, self.endpoint.name())?;
+            let package = dbmodels::Package::create_or_fetch(&conn, self.job.package())?;
+            let image = dbmodels::Image::create_or_fetch(&conn, self.job.image())?;
+            let envs = self.create_env_in_db(&conn)?;
+            (endpoint, package, image, envs)
+        };
+        let image_digest = self.endpoint.image_digest(self.job.image_reference()).await?;
+        let cache_key = self.cache_key(image_digest.as_deref()).await?;
         let job_id = *self.job.uuid();
+        let hermetic = self.job.hermetic();
+        let maintainer = self.job.package().maintainer().clone();
+        let job_package = self.job.package().clone();
+        let architecture = self.job.architecture().clone();
         trace!("Running on Job {} on Endpoint {}", job_id, self.endpoint.name());
         let prepared_container = self.endpoint
             .prepare_container(self.job, self.staging_store.clone(), self.release_stores.clone())
             .await?;
-        let container_id = prepared_container.create_info().id.clone();
+        let container_id = prepared_container.container_id().clone();
+        let debug_command = self.endpoint.debug_command(&container_id);
+        if self.cancellation.is_cancelled() {
+            drop(prepared_container);
+            return Err(Self::cancel(&self.endpoint, &job_id, &container_id).await);
+        }
+        self.bar.inc(1); // inputs are uploaded to the container
+        let checkpoints = CheckpointWatch::new(self.db.clone());
         let running_container = prepared_container
             .start()
             .await
@@ -191,11 +742,10 @@ impl JobHandle {
                     &job_id,
                     &package.name,
                     &package.version,
-                    &endpoint_uri,
-                    &container_id,
+                    &debug_command,
                 )
             })?
-            .execute_script(log_sender);
+            .execute_script(log_sender, self.log_max_line_length, &checkpoints);
 
         let logres = LogReceiver {
             endpoint_name: endpoint_name.as_ref(),
@@ -203,14 +753,25 @@ impl JobHandle {
             package_name: &package.name,
             package_version: &package.version,
             log_dir: self.log_dir.as_ref(),
+            log_split_dir: self.log_split_dir.as_ref(),
+            log_sinks: self.log_sinks.as_deref(),
+            log_prefix: LogPrefix::new(&package.name, &package.version, endpoint_name.as_ref(), &job_id),
+            stream_logs: self.stream_logs,
+            log_format: self.log_format,
             job_id,
+            db: self.db.clone(),
             log_receiver,
             bar: self.bar.clone(),
         }
         .join();
         drop(self.bar);
 
-        let (run_container, logres) = tokio::join!(running_container, logres);
+        let (run_container, logres) = tokio::select! {
+            results = async { tokio::join!(running_container, logres) } => results,
+            _ = self.cancellation.cancelled() => {
+                return Err(Self::cancel(&self.endpoint, &job_id, &container_id).await)
+            },
+        };
         let log = logres.with_context(|| anyhow!("Collecting logs for job on '{}'", endpoint_name))?;
         let run_container = run_container
             .with_context(|| anyhow!("Running container {} failed"))
@@ -219,32 +780,45 @@ impl JobHandle {
                     &job_id,
                     &package.name,
                     &package.version,
-                    &endpoint_uri,
-                    &container_id,
+                    &debug_command,
                 )
             })?;
 
-        let job = dbmodels::Job::create(
-            &self.db,
-            &job_id,
-            &self.submit,
-            &endpoint,
-            &package,
-            &image,
-            &run_container.container_hash(),
-            run_container.script(),
-            &log,
-        )
+        let conn = get_connection(&self.db).await?;
+        // The closures below must not capture `self`, the bar was moved out of it
+        let submit = &self.submit;
+        let job = self.metrics.db_write(|| {
+            dbmodels::Job::create(
+                &conn,
+                &job_id,
+                submit,
+                &endpoint,
+                &package,
+                &image,
+                image_digest.as_deref(),
+                &run_container.container_hash(),
+                run_container.script(),
+                &log,
+                &started,
+                &maintainer,
+                architecture.as_ref(),
+                Some(&cache_key),
+                endpoint_is_mock,
+            )
+        })
         .context("Recording job that is ready in database")?;
 
         trace!("DB: Job entry for job {} created: {}", job.uuid, job.id);
         for env in envs {
-            let _ = dbmodels::JobEnv::create(&self.db, &job, &env)
+            let _ = self.metrics.db_write(|| dbmodels::JobEnv::create(&conn, &job, &env))
                 .with_context(|| format!("Creating Environment Variable mapping for Job: {}", job.uuid))?;
         }
 
+        // The connection is not held while the artifacts are copied out of the container
+        drop(conn);
+
         let res: crate::endpoint::FinalizedContainer = run_container
-            .finalize(self.staging_store.clone())
+            .finalize(self.staging_store.clone(), architecture.as_ref())
             .await
             .context("Finalizing container")
             .with_context(|| {
@@ -252,13 +826,22 @@ impl JobHandle {
                     &job.uuid,
                     &package.name,
                     &package.version,
-                    &endpoint_uri,
-                    &container_id,
+                    &debug_command,
                 )
             })?;
 
         trace!("Found result for job {}: {:?}", job_id, res);
         let (paths, res) = res.unpack();
+
+        // The working directory is not needed anymore once the outputs were harvested
+        let res = match res {
+            Ok(()) => self.endpoint.check_scratch_quota(&job_id).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = self.endpoint.release_scratch_dir(&job_id, res.is_err()).await {
+            log::warn!("Could not clean up scratch directory of job {}: {:?}", job_id, e);
+        }
+
         let res = res
             .with_context(|| anyhow!("Error during running job on '{}'", endpoint_name))
             .with_context(|| {
@@ -266,8 +849,7 @@ impl JobHandle {
                     &job.uuid,
                     &package.name,
                     &package.version,
-                    &endpoint_uri,
-                    &container_id,
+                    &debug_command,
                 )
             })
             .map_err(Error::from);
@@ -279,24 +861,100 @@ impl JobHandle {
              })
         }
 
-        // Have to do it the ugly way here because of borrowing semantics
-        let mut r = vec![];
-        let staging_read = self.staging_store.read().await;
-        for p in paths.iter() {
-            trace!("DB: Creating artifact entry for path: {}", p.display());
-            let _ = dbmodels::Artifact::create(&self.db, p, &job)?;
-            r.push({
-                staging_read
-                    .get(p)
-                    .ok_or_else(|| anyhow!("Artifact not in store: {:?}", p))?
-                    .clone()
+        let artifacts = {
+            let staging_read = self.staging_store.read().await;
+            paths.iter()
+                .map(|p| -> Result<(ArtifactPath, PathBuf)> {
+                    let art_path = staging_read
+                        .get(p)
+                        .ok_or_else(|| anyhow!("Artifact not in store: {:?}", p))?
+                        .clone();
+
+                    let full_path = staging_read
+                        .root_path()
+                        .join(&art_path)?
+                        .ok_or_else(|| anyhow!("Artifact not in store: {:?}", p))?
+                        .joined();
+                    Ok((art_path, full_path))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        // Hashing the artifacts and extracting their metadata reads the files, which is done
+        // without holding the lock on the staging store and not on the threads of the runtime
+        let checksum_algorithm = self.checksum_algorithm.clone();
+        let inspected = tokio::task::spawn_blocking(move || {
+            artifacts
+                .into_iter()
+                .map(|(art_path, full_path)| -> Result<_> {
+                    let checksum = checksum_algorithm
+                        .hash_file(&full_path)
+                        .with_context(|| anyhow!("Computing checksum of {}", full_path.display()))?;
+
+                    // Metadata is only a search aid, failing to extract it does not fail the job
+                    let metadata = crate::filestore::artifact_type::handler_for(&full_path).and_then(|handler| {
+                        match handler.metadata(&full_path) {
+                            Ok(metadata) => Some((handler.kind(), metadata)),
+                            Err(e) => {
+                                log::warn!("Could not extract {} metadata of {}: {:?}", handler.kind(), art_path.display(), e);
+                                None
+                            }
+                        }
+                    });
+
+                    Ok((art_path, checksum, metadata))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .await
+        .context("Inspecting artifacts")??;
+
+        let conn = get_connection(&self.db).await?;
+
+        // Only successful jobs tell how long building the package takes. The estimate is not
+        // worth failing the job for.
+        if let (Some(started_at), Some(finished_at)) = (job.started_at, job.finished_at) {
+            let recorded = self.metrics.db_write(|| {
+                dbmodels::BuildDuration::record(&conn, &package, &image, finished_at - started_at, &finished_at)
             });
+            if let Err(e) = recorded {
+                log::warn!("Could not record the build duration of job {}: {:?}", job_id, e);
+            }
+        }
+
+        let checksum_algorithm = &self.checksum_algorithm;
+        let mut r = vec![];
+        for (art_path, checksum, metadata) in inspected {
+            trace!("DB: Creating artifact entry for path: {}", art_path.display());
+            let output = job_package.output_of(art_path.as_ref())?;
+            let artifact = self.metrics.db_write(|| dbmodels::Artifact::create(&conn, &art_path, &job, hermetic, output))?;
+
+            trace!("DB: Recording {} checksum {} for {}", checksum_algorithm, checksum, art_path.display());
+            let _ = self.metrics.db_write(|| {
+                dbmodels::ArtifactChecksum::create(&conn, &artifact, checksum_algorithm, &checksum)
+            })?;
+
+            if let Some((kind, metadata)) = metadata {
+                trace!("DB: Recording {} metadata for {}: {:?}", kind, art_path.display(), metadata);
+                let _ = dbmodels::ArtifactMetadata::create_all(&conn, &artifact, kind, &metadata)?;
+            }
+
+            r.push((art_path, artifact.id));
         }
         Ok(Ok(r))
     }
 
+    /// Remove the container of the cancelled job `job_id` and create the error the job fails with
+    async fn cancel(endpoint: &EndpointHandle, job_id: &Uuid, container_id: &str) -> Error {
+        log::warn!("Job {} cancelled, removing container {} on {}", job_id, container_id, endpoint.name());
+        match endpoint.remove_container(container_id).await {
+            Ok(()) => anyhow!("Job {} cancelled", job_id),
+            Err(e) => e.context(format!("Job {} cancelled", job_id)),
+        }
+    }
+
     /// Helper to create an error object with a nice message.
-    fn create_job_run_error(job_id: &Uuid, package_name: &str, package_version: &str, endpoint_uri: &str, container_id: &str) -> Error {
+    fn create_job_run_error(job_id: &Uuid, package_name: &str, package_version: &str, debug_command: &str) -> Error {
         anyhow!(indoc::formatdoc!(
             r#"Error while running job
 
@@ -306,9 +964,9 @@ impl JobHandle {
 
             {package_name} {package_version}
 
-        Connect to docker using
+        Connect to the endpoint using
 
-            {docker_connect_string}
+            {debug_command}
 
         to debug.
         "#,
@@ -316,14 +974,11 @@ impl JobHandle {
             package_name = package_name.to_string().red(),
             package_version = package_version.to_string().red(),
 
-            docker_connect_string = format!("docker --host {endpoint_uri} exec -it {container_id} /bin/bash",
-                endpoint_uri = endpoint_uri,
-                container_id = container_id
-            ).yellow().bold(),
+            debug_command = debug_command.yellow().bold(),
         ))
     }
 
-    fn create_env_in_db(&self) -> Result<Vec<dbmodels::EnvVar>> {
+    fn create_env_in_db(&self, conn: &PgConnection) -> Result<Vec<dbmodels::EnvVar>> {
         trace!("Creating environment in database");
         trace!("Hardcoded = {:?}", self.job.package().environment());
         trace!("Dynamic   = {:?}", self.job.resources());
@@ -336,7 +991,7 @@ impl JobHandle {
                     .inspect(|(k, v)| {
                         trace!("Creating environment variable in database: {} = {}", k, v)
                     })
-                    .map(|(k, v)| dbmodels::EnvVar::create_or_fetch(&self.db, k, v))
+                    .map(|(k, v)| dbmodels::EnvVar::create_or_fetch(conn, k, v))
                     .collect::<Result<Vec<_>>>()
             })
             .transpose()?
@@ -351,7 +1006,7 @@ impl JobHandle {
                     .inspect(|(k, v)| {
                         trace!("Creating environment variable in database: {} = {}", k, v)
                     })
-                    .map(|(k, v)| dbmodels::EnvVar::create_or_fetch(&self.db, k, v))
+                    .map(|(k, v)| dbmodels::EnvVar::create_or_fetch(conn, k, v))
             })
             .collect()
     }
@@ -363,8 +1018,14 @@ struct LogReceiver<'a> {
     package_name: &'a str,
     package_version: &'a str,
     log_dir: Option<&'a PathBuf>,
+    log_split_dir: Option<&'a PathBuf>,
+    log_sinks: Option<&'a LogSinks>,
+    log_prefix: LogPrefix,
+    stream_logs: bool,
+    log_format: LogFormat,
     job_id: Uuid,
-    log_receiver: UnboundedReceiver<LogItem>,
+    db: DbPool,
+    log_receiver: UnboundedReceiver<(LogStream, LogItem)>,
     bar: ProgressBar,
 }
 
@@ -381,6 +1042,11 @@ impl<'a> LogReceiver<'a> {
             .transpose()
             .context("Getting Logfile")?;
 
+        let mut split_logfile = self.get_split_logfile()
+            .await
+            .transpose()
+            .context("Getting Logfile in split directory")?;
+
         // The timeout for the log-receive-timeout
         //
         // We're using a rather small timeout of just 250ms here, because we have some worktime
@@ -390,36 +1056,88 @@ impl<'a> LogReceiver<'a> {
         // progress bar secondly.
         let timeout_duration = std::time::Duration::from_millis(250);
 
+        // The number of phases the script announced so far
+        let mut announced_phases: u64 = 0;
+
+        // The lines that were not recorded as live log yet
+        let mut live_lines = Vec::new();
+        let mut live_lines_persisted = std::time::Instant::now();
+
         loop {
             // Timeout for receiving from the log receiver channel
             // This way we can update (`tick()`) the progress bar and show the user that things are
             // happening, even if there was no log output for several seconds.
-            let logitem = match tokio::time::timeout(timeout_duration, self.log_receiver.recv()).await {
+            let (stream, logitem) = match tokio::time::timeout(timeout_duration, self.log_receiver.recv()).await {
                 Err(_ /* elapsed */) => {
                     self.bar.tick(); // just ping the progressbar here
+                    self.persist_live_lines(&mut live_lines).await;
+                    live_lines_persisted = std::time::Instant::now();
                     continue
                 },
 
                 Ok(None) => break, // if the log is empty, we're done
-                Ok(Some(logitem)) => logitem,
+                Ok(Some(item)) => item,
             };
 
+            live_lines.push((accu.len() as i32, stream.to_string(), logitem.raw()?));
+            if live_lines.len() >= LIVE_LOG_BATCH_SIZE || live_lines_persisted.elapsed() >= LIVE_LOG_INTERVAL {
+                self.persist_live_lines(&mut live_lines).await;
+                live_lines_persisted = std::time::Instant::now();
+            }
+
             if let Some(lf) = logfile.as_mut() {
                 lf.write_all(logitem.display()?.to_string().as_bytes())
                     .await?;
                 lf.write_all(b"\n").await?;
             }
 
+            if let Some(lf) = split_logfile.as_mut() {
+                lf.write_all(logitem.raw()?.as_bytes()).await?;
+                lf.write_all(b"\n").await?;
+            }
+
+            let record = LogRecord::for_job_log(
+                &self.job_id,
+                self.package_name,
+                self.package_version,
+                self.endpoint_name,
+                stream,
+                &logitem,
+            )?;
+
+            if let Some(sinks) = self.log_sinks {
+                sinks.forward(&record);
+            }
+
+            if self.stream_logs {
+                let line = match self.log_format {
+                    LogFormat::Text => format!("{} {}", self.log_prefix.colored(), logitem.display()?),
+                    LogFormat::Json => JsonLogRecord(&record).to_json()?,
+                };
+                if self.bar.is_hidden() {
+                    use std::io::Write;
+                    writeln!(std::io::stdout(), "{}", line)?;
+                } else {
+                    // print above the progress bars, so that these do not get garbled
+                    self.bar.println(line);
+                }
+            }
+
             match logitem {
                 LogItem::Line(_) => {
                     // ignore
                 }
                 LogItem::Progress(u) => {
-                    trace!("Setting bar to {}", u as u64);
-                    self.bar.set_position(u as u64);
+                    trace!("Ignoring progress report: {}", u);
                 }
                 LogItem::CurrentPhase(ref phasename) => {
                     trace!("Setting bar phase to {}", phasename);
+
+                    // Announcing a phase finishes the previous one. The first step of the bar is
+                    // the upload of the inputs, so the first phase starts at position 1.
+                    let position = std::cmp::min(1 + announced_phases, self.bar.length().saturating_sub(1));
+                    self.bar.set_position(position);
+                    announced_phases += 1;
                     self.bar.set_message(format!(
                         "[{}/{} {} {} {}]: Phase: {}",
                         self.endpoint_name, self.container_id_chrs, self.job_id, self.package_name, self.package_version, phasename
@@ -427,6 +1145,7 @@ impl<'a> LogReceiver<'a> {
                 }
                 LogItem::State(Ok(())) => {
                     trace!("Setting bar state to Ok");
+                    self.bar.set_position(self.bar.length().saturating_sub(1));
                     self.bar.set_message(format!(
                         "[{}/{} {} {} {}]: State Ok",
                         self.endpoint_name, self.container_id_chrs, self.job_id, self.package_name, self.package_version
@@ -435,6 +1154,7 @@ impl<'a> LogReceiver<'a> {
                 }
                 LogItem::State(Err(ref e)) => {
                     trace!("Setting bar state to Err: {}", e);
+                    self.bar.set_position(self.bar.length().saturating_sub(1));
                     self.bar.set_message(format!(
                         "[{}/{} {} {} {}]: State Err: {}",
                         self.endpoint_name, self.container_id_chrs, self.job_id, self.package_name, self.package_version, e
@@ -445,6 +1165,8 @@ impl<'a> LogReceiver<'a> {
             accu.push(logitem);
         }
 
+        self.persist_live_lines(&mut live_lines).await;
+
         trace!("Finishing bar = {:?}", success);
         let finish_msg = match success {
             Some(true) => format!(
@@ -466,6 +1188,10 @@ impl<'a> LogReceiver<'a> {
             let _ = lf.flush().await?;
         }
 
+        if let Some(mut lf) = split_logfile {
+            let _ = lf.flush().await?;
+        }
+
         Ok({
             accu.iter()
                 .map(crate::log::LogItem::raw)
@@ -474,6 +1200,48 @@ impl<'a> LogReceiver<'a> {
         })
     }
 
+    /// Record `lines` as live log of the job and clear it
+    ///
+    /// Failing to do so does not fail the job, the live log is only a convenience for following
+    /// the job from another butido process.
+    async fn persist_live_lines(&self, lines: &mut Vec<(i32, String, String)>) {
+        if lines.is_empty() {
+            return;
+        }
+
+        let now = chrono::offset::Local::now().naive_local();
+        let res = get_connection(&self.db)
+            .await
+            .and_then(|conn| dbmodels::LiveLogLine::append(&conn, &self.job_id, lines, &now));
+        if let Err(e) = res {
+            log::warn!("Recording live log of job {} failed: {:?}", self.job_id, e);
+        }
+        lines.clear();
+    }
+
+    /// Get the logfile in the split directory, if any
+    ///
+    /// The file is named after the log prefix and the UUID of the job, so that the logs of the jobs
+    /// of a submit can be found easily and jobs with the same prefix do not share a file.
+    async fn get_split_logfile(&self) -> Option<Result<tokio::io::BufWriter<tokio::fs::File>>> {
+        if let Some(split_dir) = self.log_split_dir.as_ref() {
+            Some({
+                let path = split_dir.join(format!("{}.log", self.log_prefix.file_stem()));
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .create_new(true)
+                    .write(true)
+                    .open(&path)
+                    .await
+                    .map(tokio::io::BufWriter::new)
+                    .with_context(|| anyhow!("Opening {}", path.display()))
+                    .map_err(Error::from)
+            })
+        } else {
+            None
+        }
+    }
+
     async fn get_logfile(&self) -> Option<Result<tokio::io::BufWriter<tokio::fs::File>>> {
         if let Some(log_dir) = self.log_dir.as_ref() {
             Some({
@@ -493,3 +1261,77 @@ impl<'a> LogReceiver<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recheck_interval_backoff() {
+        let mut interval = FREE_ENDPOINT_RECHECK_INTERVAL;
+        let mut intervals = vec![];
+        for _ in 0..8 {
+            intervals.push(interval.as_secs());
+            interval = next_recheck_interval(interval);
+        }
+        assert_eq!(intervals, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+    }
+
+    fn shares(entries: &[(Uuid, usize, &[&str])]) -> HashMap<Uuid, SubmitShare> {
+        entries
+            .iter()
+            .map(|(uuid, running, waiting)| {
+                let waiting = waiting.iter().map(|ep| (EndpointName::from(ep.to_string()), 1)).collect();
+                (*uuid, SubmitShare { running: *running, waiting })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_slot_goes_to_submit_with_fewest_running_jobs() {
+        let (big, small) = (Uuid::new_v4(), Uuid::new_v4());
+        let ep1 = EndpointName::from(String::from("ep1"));
+        let ep2 = EndpointName::from(String::from("ep2"));
+
+        // The small submit waits for ep1, so the big one has to leave it the slot
+        let submits = shares(&[(big, 5, &["ep1", "ep2"]), (small, 1, &["ep1"])]);
+        assert!(!SlotShares::may_take(&submits, &big, &ep1));
+        assert!(SlotShares::may_take(&submits, &small, &ep1));
+
+        // Nobody else waits for ep2
+        assert!(SlotShares::may_take(&submits, &big, &ep2));
+
+        // Submits with as many running jobs share the slots
+        let submits = shares(&[(big, 2, &["ep1"]), (small, 2, &["ep1"])]);
+        assert!(SlotShares::may_take(&submits, &big, &ep1));
+        assert!(SlotShares::may_take(&submits, &small, &ep1));
+
+        // A submit which is not known yet has no running jobs
+        assert!(SlotShares::may_take(&submits, &Uuid::new_v4(), &ep1));
+    }
+
+    #[test]
+    fn test_waiting_job_guard() {
+        let slot_shares = SlotShares::default();
+        let submit = Uuid::new_v4();
+        let notify = Arc::new(Notify::new());
+        let ep = EndpointName::from(String::from("ep1"));
+        {
+            let _a = WaitingJob::new(&slot_shares, submit, vec![ep.clone()], notify.clone());
+            let _b = WaitingJob::new(&slot_shares, submit, vec![ep.clone()], notify.clone());
+            assert_eq!(slot_shares.submits()[&submit].waiting[&ep], 2);
+        }
+        assert!(slot_shares.submits().is_empty());
+    }
+
+    #[test]
+    fn test_queued_job_guard() {
+        let queued_jobs = AtomicUsize::new(0);
+        {
+            let _a = QueuedJob::new(&queued_jobs);
+            let _b = QueuedJob::new(&queued_jobs);
+            assert_eq!(queued_jobs.load(Ordering::Relaxed), 2);
+        }
+        assert_eq!(queued_jobs.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,136 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Scratch space for the working directories of jobs on an endpoint host
+//!
+//! The scratch space is managed with shell scripts, which are run on the host via SSH or in a
+//! helper container which has the scratch root mounted at the same path as on the host.
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
+
+use crate::endpoint::util::shell_quote;
+
+/// The working directory of a job in the scratch space of an endpoint
+#[derive(Clone, Debug, Getters, CopyGetters)]
+pub struct ScratchDir {
+    /// The UUID of the job
+    #[getset(get_copy = "pub")]
+    job: uuid::Uuid,
+
+    /// The space used by the directory
+    #[getset(get_copy = "pub")]
+    size_bytes: u64,
+
+    /// When the directory was modified last (on the clock of the endpoint host)
+    #[getset(get = "pub")]
+    modified: chrono::NaiveDateTime,
+}
+
+/// Script creating `dir`
+pub(super) fn allocate_script(dir: &Path) -> String {
+    format!("mkdir -p {}", quote_path(dir))
+}
+
+/// Script removing `dir`
+pub(super) fn remove_script(dir: &Path) -> String {
+    format!("rm -rf {}", quote_path(dir))
+}
+
+/// Script printing the space used by `dir` in KiB
+pub(super) fn usage_script(dir: &Path) -> String {
+    format!("du -sk {} | cut -f1", quote_path(dir))
+}
+
+/// Script printing name, used space (KiB) and modification time (seconds since the epoch) of
+/// every directory in `root`, one per line
+pub(super) fn list_script(root: &Path) -> String {
+    format!(
+        r#"for d in {}/*/; do [ -d "$d" ] || continue; echo "$(basename "$d") $(du -sk "$d" | cut -f1) $(stat -c %Y "$d")"; done"#,
+        quote_path(root)
+    )
+}
+
+/// Parse the output of `usage_script()`, the used space in bytes
+pub(super) fn parse_usage(output: &str) -> Result<u64> {
+    output
+        .trim()
+        .parse::<u64>()
+        .map(|kib| kib * 1024)
+        .with_context(|| anyhow!("Parsing disk usage: '{}'", output.trim()))
+}
+
+/// Parse the output of `list_script()`
+///
+/// Directories which are not named after a job UUID were not created by butido and are skipped.
+pub(super) fn parse_listing(output: &str) -> Result<Vec<ScratchDir>> {
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|line| {
+            let mut split = line.split_whitespace();
+            let job = uuid::Uuid::parse_str(split.next()?).ok()?;
+            Some(parse_listing_line(job, split.next(), split.next()).with_context(|| anyhow!("Parsing scratch directory: '{}'", line)))
+        })
+        .collect()
+}
+
+fn parse_listing_line(job: uuid::Uuid, size: Option<&str>, modified: Option<&str>) -> Result<ScratchDir> {
+    let size_bytes = parse_usage(size.ok_or_else(|| anyhow!("Missing size"))?)?;
+    let modified = modified
+        .ok_or_else(|| anyhow!("Missing modification time"))?
+        .parse::<i64>()
+        .context("Parsing modification time")?;
+
+    Ok(ScratchDir {
+        job,
+        size_bytes,
+        modified: chrono::NaiveDateTime::from_timestamp(modified, 0),
+    })
+}
+
+fn quote_path(p: &Path) -> String {
+    shell_quote(&p.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listing() {
+        let output = "\
+            6c1f2d10-4ad8-4a6e-b6c5-0d2b8ef2c1a4 2048 1616000000\n\
+            lost+found 16 1615000000\n\
+            \n\
+            0b8e5d5e-93a4-4c1c-8f0e-3a1f58c9d2e7 0 1616003600\n";
+
+        let dirs = parse_listing(output).unwrap();
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].job().to_string(), "6c1f2d10-4ad8-4a6e-b6c5-0d2b8ef2c1a4");
+        assert_eq!(dirs[0].size_bytes(), 2048 * 1024);
+        assert_eq!(dirs[0].modified().timestamp(), 1616000000);
+        assert_eq!(dirs[1].size_bytes(), 0);
+
+        assert!(parse_listing("6c1f2d10-4ad8-4a6e-b6c5-0d2b8ef2c1a4 many 1616000000").is_err());
+        assert!(parse_listing("6c1f2d10-4ad8-4a6e-b6c5-0d2b8ef2c1a4 2048").is_err());
+    }
+
+    #[test]
+    fn test_scripts_quote_paths() {
+        assert_eq!(remove_script(Path::new("/scratch/job")), "rm -rf /scratch/job");
+        assert_eq!(allocate_script(Path::new("/my scratch/job")), "mkdir -p '/my scratch/job'");
+    }
+}
@@ -0,0 +1,471 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Running jobs on hosts which are only reachable via SSH
+//!
+//! The docker (or podman) CLI on the remote host is run via `ssh`, so the API of the daemon does not
+//! have to be exposed. The outputs of a job are copied back with `sftp`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::Stream;
+use log::trace;
+use shiplift::tty::TtyChunk;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tokio::process::Command;
+
+use crate::config::SecurityProfile;
+use crate::endpoint::util::check_status;
+use crate::endpoint::util::create_args;
+use crate::endpoint::util::env_file;
+use crate::endpoint::util::read_chunks;
+use crate::endpoint::util::shell_quote;
+use crate::job::RunnableJob;
+
+/// A host on which the docker (or podman) CLI is run via SSH
+#[derive(Debug)]
+pub struct Ssh {
+    ssh: PathBuf,
+    sftp: PathBuf,
+
+    /// The host to connect to, as "user@host" or "host"
+    destination: String,
+    port: Option<u16>,
+
+    /// The CLI to run on the host, "docker" or "podman"
+    cli: &'static str,
+
+    /// The address of the daemon on the host, passed to the CLI if not empty
+    daemon_uri: String,
+}
+
+impl Ssh {
+    /// Connect to the host from `connection` ("ssh://user@host[:port]")
+    pub fn new(connection: &str, cli: &'static str, daemon_uri: String) -> Result<Self> {
+        let url = url::Url::parse(connection).with_context(|| anyhow!("Parsing connection {}", connection))?;
+        if url.scheme() != "ssh" {
+            return Err(anyhow!("Not a SSH connection: {}", connection));
+        }
+
+        let host = url.host_str().ok_or_else(|| anyhow!("No host in connection {}", connection))?;
+        let destination = if url.username().is_empty() {
+            host.to_string()
+        } else {
+            format!("{}@{}", url.username(), host)
+        };
+
+        Ok(Ssh {
+            ssh: which::which("ssh").context("Finding ssh executable")?,
+            sftp: which::which("sftp").context("Finding sftp executable")?,
+            destination,
+            port: url.port(),
+            cli,
+            daemon_uri,
+        })
+    }
+
+    /// The command line to run the CLI with `args` on the host
+    fn cli_command_line<S: AsRef<str>>(&self, args: &[S]) -> String {
+        let mut line = String::from(self.cli);
+        if !self.daemon_uri.is_empty() {
+            let flag = if self.cli == "podman" { "--url" } else { "--host" };
+            line.push_str(&format!(" {} {}", flag, shell_quote(&self.daemon_uri)));
+        }
+        for arg in args {
+            line.push(' ');
+            line.push_str(&shell_quote(arg.as_ref()));
+        }
+        line
+    }
+
+    /// A command running the shell command line `remote` on the host
+    fn command(&self, remote: &str) -> Command {
+        let mut cmd = Command::new(&self.ssh);
+        cmd.args(&["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        cmd.arg(&self.destination).arg("--").arg(remote);
+        cmd.stdin(Stdio::null()).kill_on_drop(true);
+        cmd
+    }
+
+    /// Run the shell command line `remote` on the host and return its output
+    async fn run_remote(&self, remote: &str) -> Result<String> {
+        let mut cmd = self.command(remote);
+        trace!("Running {:?}", cmd);
+
+        let output = cmd.output().await.context("Running ssh")?;
+        check_status(&output.status, &output.stderr)?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Run the shell script `script` on the host and return its output
+    pub async fn run_shell(&self, script: &str) -> Result<String> {
+        self.run_remote(script)
+            .await
+            .with_context(|| anyhow!("Running script on {}", self.destination))
+    }
+
+    /// Run the CLI with `args` on the host and return its output
+    async fn run<S: AsRef<str>>(&self, args: &[S]) -> Result<String> {
+        self.run_remote(&self.cli_command_line(args)).await
+    }
+
+    /// Check that the CLI can be run on the host
+    pub async fn check_access(&self) -> Result<()> {
+        self.run(&["version"])
+            .await
+            .map(|_| ())
+            .with_context(|| anyhow!("Running {} on {}", self.cli, self.destination))
+    }
+
+    /// The command to use to debug a job in `container`
+    pub fn debug_command(&self, container: &str) -> String {
+        let port = self.port.map(|p| format!(" -p {}", p)).unwrap_or_default();
+        format!("ssh -t{} {} {}", port, self.destination, self.cli_command_line(&["exec", "-it", container, "/bin/bash"]))
+    }
+
+    /// The names ("name:tag") and the digests ("name@digest") of the images on the host
+    pub async fn image_names(&self) -> Result<Vec<String>> {
+        self.run(&["images", "--digests", "--format", "{{.Repository}}:{{.Tag}} {{.Repository}}@{{.Digest}}"])
+            .await
+            .with_context(|| anyhow!("Listing images on {}", self.destination))
+            .map(|out| out.split_whitespace().map(String::from).collect())
+    }
+
+    /// Pull `image` on the host
+    pub async fn pull_image(&self, image: &str) -> Result<()> {
+        self.run(&["pull", image])
+            .await
+            .map(|_| ())
+            .with_context(|| anyhow!("Pulling image {} on {}", image, self.destination))
+    }
+
+    /// The digest (ID) of `image` on the host
+    pub async fn image_digest(&self, image: &str) -> Result<String> {
+        self.run(&["image", "inspect", "--format", "{{.Id}}", image])
+            .await
+            .with_context(|| anyhow!("Inspecting image {} on {}", image, self.destination))
+            .map(|out| out.trim().to_string())
+    }
+
+    /// The labels of `image` on the host
+    pub async fn image_labels(&self, image: &str) -> Result<BTreeMap<String, String>> {
+        let out = self.run(&["image", "inspect", "--format", "{{json .Config.Labels}}", image])
+            .await
+            .with_context(|| anyhow!("Inspecting image {} on {}", image, self.destination))?;
+
+        // An image without labels has `null` as labels
+        serde_json::from_str::<Option<BTreeMap<String, String>>>(out.trim())
+            .map(Option::unwrap_or_default)
+            .with_context(|| anyhow!("Parsing labels of image {} on {}", image, self.destination))
+    }
+
+    /// Create the container for `job` and return its ID
+    ///
+    /// Like on docker endpoints, the container runs the interpreter of the script with an open
+    /// stdin, so that it keeps running until the script was executed in it. If `scratch_dir` is
+    /// set, it is mounted as working directory of the container.
+    ///
+    /// The seccomp profile from `security` and the environment of the job are copied to files on
+    /// the host for the creation of the container.
+    pub async fn create_container(
+        &self,
+        job: &RunnableJob,
+        network_mode: Option<&str>,
+        scratch_dir: Option<&Path>,
+        security: &SecurityProfile,
+    ) -> Result<String> {
+        let remote_seccomp = match security.seccomp_profile().as_ref() {
+            Some(profile) => {
+                let remote = format!("/tmp/butido-seccomp-{}.json", job.uuid());
+                let content = tokio::fs::read(profile)
+                    .await
+                    .with_context(|| anyhow!("Reading seccomp profile {}", profile.display()))?;
+                self.write_file(&remote, &content).await?;
+                Some(remote)
+            }
+            None => None,
+        };
+
+        let remote_env = format!("/tmp/butido-env-{}", job.uuid());
+        let created = async {
+            self.write_file(&remote_env, env_file(job)?.as_bytes()).await?;
+            let args = create_args(job, network_mode, scratch_dir, security, remote_seccomp.as_deref(), &remote_env)?;
+            self.run(&args).await
+        }
+        .await;
+
+        // The files are removed in any case, the environment file can contain secrets
+        let mut removed = Ok(());
+        for remote in remote_seccomp.iter().chain(std::iter::once(&remote_env)) {
+            let r = self.run_remote(&format!("rm -f {}", shell_quote(remote)))
+                .await
+                .with_context(|| anyhow!("Removing {} on {}", remote, self.destination))
+                .map(|_| ());
+            removed = removed.and(r);
+        }
+
+        match (created, removed) {
+            (Ok(out), Ok(())) => Ok(out.trim().to_string()),
+
+            // The container is not returned if the files were not removed, so it is removed here
+            (Ok(out), Err(e)) => match self.remove(out.trim()).await {
+                Ok(()) => Err(e),
+                Err(re) => Err(re.context(format!("{:?}", e))),
+            },
+
+            (Err(e), removed) => {
+                let e = e.context(anyhow!("Creating container on {}", self.destination));
+                match removed {
+                    Ok(()) => Err(e),
+                    Err(re) => Err(re.context(format!("{:?}", e))),
+                }
+            }
+        }
+    }
+
+    /// Write `content` to the file `path` on the host, which is only readable by the user
+    async fn write_file(&self, path: &str, content: &[u8]) -> Result<()> {
+        let mut child = self.command(&format!("umask 077 && cat > {}", shell_quote(path)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running ssh")?;
+
+        {
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for ssh"))?;
+            stdin.write_all(content).await.with_context(|| anyhow!("Writing {} on {}", path, self.destination))?;
+        } // stdin is closed here, so cat can finish
+
+        let output = child.wait_with_output().await.context("Running ssh")?;
+        check_status(&output.status, &output.stderr).with_context(|| anyhow!("Writing {} on {}", path, self.destination))
+    }
+
+    /// Check that `path` exists in `container`
+    pub async fn verify_exists(&self, container: &str, path: &str) -> Result<()> {
+        let src = format!("{}:{}", container, path);
+        let line = format!("{} > /dev/null", self.cli_command_line(&["cp", src.as_str(), "-"]));
+        self.run_remote(&line)
+            .await
+            .with_context(|| anyhow!("Checking for {} in container {}", path, container))
+            .map(|_| ())
+    }
+
+    /// Unpack `tar` (a tar archive) to the directory `dir` of `container`
+    pub async fn copy_tar_into(&self, container: &str, dir: &Path, tar: &[u8]) -> Result<()> {
+        let dest = format!("{}:{}", container, dir.display());
+        let mut child = self.command(&self.cli_command_line(&["cp", "-", dest.as_str()]))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running ssh")?;
+
+        {
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for ssh"))?;
+            stdin.write_all(tar).await.with_context(|| anyhow!("Copying files into container {}", container))?;
+        } // stdin is closed here, so the CLI can finish
+
+        let output = child.wait_with_output().await.context("Running ssh")?;
+        check_status(&output.status, &output.stderr).with_context(|| anyhow!("Copying files into container {}", container))
+    }
+
+    pub async fn start(&self, container: &str) -> Result<()> {
+        self.run(&["start", container])
+            .await
+            .with_context(|| anyhow!("Starting container {} on {}", container, self.destination))
+            .map(|_| ())
+    }
+
+    pub async fn stop(&self, container: &str) -> Result<()> {
+        self.run(&["stop", "--time", "1", container])
+            .await
+            .with_context(|| anyhow!("Stopping container {} on {}", container, self.destination))
+            .map(|_| ())
+    }
+
+    /// The version of the container engine on the remote host
+    pub async fn version(&self) -> Result<String> {
+        let format = if self.cli == "podman" { "{{.Version.Version}}" } else { "{{.ServerVersion}}" };
+        self.run(&["info", "--format", format])
+            .await
+            .with_context(|| anyhow!("Getting {} version on {}", self.cli, self.destination))
+            .map(|out| out.trim().to_string())
+    }
+
+    /// The number of running containers created by butido
+    pub async fn running_containers(&self) -> Result<usize> {
+        let filter = format!("--filter=label={}", crate::consts::CONTAINER_LABEL);
+        self.run(&["ps", "--quiet", filter.as_str()])
+            .await
+            .with_context(|| anyhow!("Listing containers on {}", self.destination))
+            .map(|out| out.lines().filter(|l| !l.trim().is_empty()).count())
+    }
+
+    /// The IDs of the running containers created by butido, with the UUIDs of their jobs
+    pub async fn butido_containers(&self) -> Result<Vec<(String, String)>> {
+        let filter = format!("--filter=label={}", crate::consts::CONTAINER_LABEL);
+        let format = if self.cli == "podman" {
+            format!("--format={{{{.ID}}}} {{{{index .Labels \"{}\"}}}}", crate::consts::CONTAINER_LABEL)
+        } else {
+            format!("--format={{{{.ID}}}} {{{{.Label \"{}\"}}}}", crate::consts::CONTAINER_LABEL)
+        };
+        self.run(&["ps", filter.as_str(), format.as_str()])
+            .await
+            .with_context(|| anyhow!("Listing containers on {}", self.destination))
+            .map(|out| {
+                out.lines()
+                    .filter_map(|l| {
+                        let mut split = l.trim().splitn(2, ' ');
+                        Some((split.next()?.to_string(), split.next()?.to_string()))
+                    })
+                    .collect()
+            })
+    }
+
+    /// Checkpoint `container` to disk with CRIU, which stops it
+    ///
+    /// Podman does not name checkpoints, so `name` is only used with docker.
+    pub async fn checkpoint(&self, container: &str, name: &str) -> Result<()> {
+        let args = if self.cli == "podman" {
+            vec!["container", "checkpoint", container]
+        } else {
+            vec!["checkpoint", "create", container, name]
+        };
+
+        self.run(&args)
+            .await
+            .with_context(|| anyhow!("Checkpointing container {} on {}", container, self.destination))
+            .map(|_| ())
+    }
+
+    /// Remove `container`, stopping it if it is still running
+    pub async fn remove(&self, container: &str) -> Result<()> {
+        self.run(&["rm", "--force", container])
+            .await
+            .with_context(|| anyhow!("Removing container {} on {}", container, self.destination))
+            .map(|_| ())
+    }
+
+    /// Run `cmd` in `container`
+    ///
+    /// Returns the ssh process, which has to be waited for after the output stream ended, and the
+    /// output of the command (both stdout and stderr).
+    pub fn exec(&self, container: &str, cmd: &[&str]) -> Result<(Child, impl Stream<Item = shiplift::Result<TtyChunk>> + Unpin)> {
+        let mut args = vec!["exec", container];
+        args.extend(cmd);
+        let mut child = self.command(&self.cli_command_line(&args))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running ssh")?;
+
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout for ssh"))?;
+        let stderr = child.stderr.take().ok_or_else(|| anyhow!("No stderr for ssh"))?;
+        let stream = futures::stream::select(
+            Box::pin(read_chunks(stdout, TtyChunk::StdOut)),
+            Box::pin(read_chunks(stderr, TtyChunk::StdErr)),
+        );
+
+        Ok((child, stream))
+    }
+
+    /// Run `cmd` in `container` with `input` on its stdin and wait for it to finish
+    pub async fn run_in_container(&self, container: &str, cmd: &[&str], input: &[u8]) -> Result<()> {
+        let mut args = vec!["exec", "--interactive", container];
+        args.extend(cmd);
+        let mut child = self.command(&self.cli_command_line(&args))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Running ssh")?;
+
+        {
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for ssh"))?;
+            stdin.write_all(input).await.with_context(|| anyhow!("Writing to {:?} in container {}", cmd, container))?;
+        } // stdin is closed here, so the command can finish
+
+        let output = child.wait_with_output().await.context("Running ssh")?;
+        check_status(&output.status, &output.stderr).with_context(|| anyhow!("Running {:?} in container {}", cmd, container))
+    }
+
+    /// Fetch `dir` from `container` as tar archive
+    ///
+    /// The archive is written to a temporary file on the host, which is only readable by the user
+    /// and fetched via SFTP into a temporary file here.
+    pub async fn copy_from(&self, container: &str, dir: &str) -> Result<Vec<u8>> {
+        let remote_tmp = self.run_remote("umask 077 && mktemp")
+            .await
+            .with_context(|| anyhow!("Creating temporary file on {}", self.destination))?
+            .trim()
+            .to_string();
+        let local_tmp = tempfile::Builder::new()
+            .prefix("butido-")
+            .suffix(".tar")
+            .tempfile()
+            .context("Creating temporary file")?;
+
+        let src = format!("{}:{}", container, dir);
+        let line = format!("{} > {}", self.cli_command_line(&["cp", src.as_str(), "-"]), shell_quote(&remote_tmp));
+        let fetched = match self.run_remote(&line).await {
+            Ok(_) => self.sftp_get(&remote_tmp, local_tmp.path()).await,
+            Err(e) => Err(e),
+        };
+
+        // Clean up in any case, the file on the host might be partially written
+        let removed = self.run_remote(&format!("rm -f {}", shell_quote(&remote_tmp))).await;
+        fetched.with_context(|| anyhow!("Copying {} from container {}", dir, container))?;
+        removed.with_context(|| anyhow!("Removing {} on {}", remote_tmp, self.destination))?;
+
+        // The local file is removed when it is dropped
+        tokio::fs::read(local_tmp.path())
+            .await
+            .with_context(|| anyhow!("Reading {}", local_tmp.path().display()))
+    }
+
+    /// Fetch `remote` from the host to `local`
+    async fn sftp_get(&self, remote: &str, local: &std::path::Path) -> Result<()> {
+        let mut cmd = Command::new(&self.sftp);
+        cmd.args(&["-o", "BatchMode=yes", "-b", "-"]);
+        if let Some(port) = self.port {
+            cmd.arg("-P").arg(port.to_string());
+        }
+        cmd.arg(&self.destination);
+        trace!("Running {:?}", cmd);
+
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Running sftp")?;
+
+        {
+            let batch = format!("get \"{}\" \"{}\"\n", remote, local.display());
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for sftp"))?;
+            stdin.write_all(batch.as_bytes()).await.context("Sending commands to sftp")?;
+        } // stdin is closed here, so sftp exits after the batch
+
+        let output = child.wait_with_output().await.context("Running sftp")?;
+        check_status(&output.status, &output.stderr).with_context(|| anyhow!("Fetching {} from {}", remote, self.destination))
+    }
+}
@@ -8,14 +8,23 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::path::Path;
 use std::sync::Arc;
 
+use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use futures::FutureExt;
+use futures::Stream;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::process::Child;
 use tokio_stream::StreamExt;
 
+use crate::config::SecurityProfile;
 use crate::endpoint::Endpoint;
 use crate::endpoint::EndpointConfiguration;
+use crate::job::RunnableJob;
 
 pub async fn setup_endpoints(endpoints: Vec<EndpointConfiguration>) -> Result<Vec<Arc<Endpoint>>> {
     let unordered = futures::stream::FuturesUnordered::new();
@@ -28,3 +37,173 @@ pub async fn setup_endpoints(endpoints: Vec<EndpointConfiguration>) -> Result<Ve
     unordered.collect().await
 }
 
+/// Wait for a process which streams from or to an endpoint (e.g. `kubectl exec`) to finish
+pub async fn wait_for_process(child: Child) -> Result<()> {
+    let output = child.wait_with_output().await.context("Waiting for process")?;
+    check_status(&output.status, &output.stderr)
+}
+
+/// Check the exit status of a process which was run for an endpoint, the error contains `stderr`
+pub(super) fn check_status(status: &std::process::ExitStatus, stderr: &[u8]) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Command failed ({}): {}", status, String::from_utf8_lossy(stderr).trim()))
+    }
+}
+
+/// Read `reader` in chunks, which are converted with `mk`
+pub(super) fn read_chunks<R, T, E>(reader: R, mk: fn(Vec<u8>) -> T) -> impl Stream<Item = std::result::Result<T, E>>
+where
+    R: AsyncRead + Unpin,
+    E: From<std::io::Error>,
+{
+    futures::stream::try_unfold(reader, move |mut reader| async move {
+        let mut buf = vec![0; 8192];
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            Ok(None)
+        } else {
+            buf.truncate(n);
+            Ok(Some((mk(buf), reader)))
+        }
+    })
+}
+
+/// The content of an env file (as the docker and podman CLIs read it with `--env-file`) with the
+/// environment of `job`
+///
+/// The environment is not passed on the command line, where it would be visible in the process list
+/// of the host and in the logs.
+pub(super) fn env_file(job: &RunnableJob) -> Result<String> {
+    job.environment()
+        .map(|(k, v)| {
+            if v.contains('\n') {
+                Err(anyhow!("Value of environment variable {} contains a newline, which is not supported by env files", k.as_ref()))
+            } else {
+                Ok(format!("{}={}\n", k.as_ref(), v))
+            }
+        })
+        .collect()
+}
+
+/// The arguments for the docker (or podman) CLI to create the container for `job`
+///
+/// `seccomp_profile` is the path of the seccomp profile from `security` and `env_file` the path of
+/// the file with the environment of the job (see `env_file()`) on the host the CLI runs on.
+pub(super) fn create_args(
+    job: &RunnableJob,
+    network_mode: Option<&str>,
+    scratch_dir: Option<&Path>,
+    security: &SecurityProfile,
+    seccomp_profile: Option<&str>,
+    env_file: &str,
+) -> Result<Vec<String>> {
+    let mut args = vec![
+        String::from("create"),
+        String::from("--interactive"),
+        format!("--label={}={}", crate::consts::CONTAINER_LABEL, job.uuid()),
+        format!("--env-file={}", env_file),
+    ];
+    if let Some(cpus) = job.limits().cpus() {
+        args.push(format!("--cpus={}", cpus));
+    }
+    if let Some(memory) = job.limits().memory_bytes()? {
+        args.push(format!("--memory={}", memory));
+    }
+    if let Some(scratch_dir) = scratch_dir {
+        args.push(format!("--volume={}:{}", scratch_dir.display(), crate::consts::WORK_DIR_PATH));
+        args.push(format!("--workdir={}", crate::consts::WORK_DIR_PATH));
+    }
+    if job.hermetic() {
+        args.push(String::from("--network=none"));
+    } else if let Some(network_mode) = network_mode {
+        args.push(format!("--network={}", network_mode));
+    }
+    if !job.secrets().is_empty() {
+        // The secrets are only kept in memory, they are written to the tmpfs once the container runs
+        args.push(format!("--tmpfs={}", crate::consts::SECRETS_DIR_PATH));
+    }
+    args.extend(security_args(security, seccomp_profile));
+    args.push(job.image_reference().as_ref().to_string());
+    args.extend(job.script().interpreter().into_iter().map(String::from));
+    Ok(args)
+}
+
+/// The arguments for the docker (or podman) CLI to create a container with `security`
+fn security_args(security: &SecurityProfile, seccomp_profile: Option<&str>) -> Vec<String> {
+    let mut args = vec![];
+    if let Some(seccomp_profile) = seccomp_profile {
+        args.push(format!("--security-opt=seccomp={}", seccomp_profile));
+    }
+    if let Some(apparmor_profile) = security.apparmor_profile() {
+        args.push(format!("--security-opt=apparmor={}", apparmor_profile));
+    }
+    if let Some(user) = security.user() {
+        args.push(format!("--user={}", user));
+    }
+    if security.read_only_rootfs() {
+        // The directories butido copies files to and from have to stay writable
+        args.push(String::from("--read-only"));
+        args.extend({
+            [
+                crate::consts::INPUTS_DIR_PATH,
+                crate::consts::OUTPUTS_DIR_PATH,
+                crate::consts::PATCH_DIR_PATH,
+                crate::consts::READ_ONLY_SCRIPT_DIR_PATH,
+            ]
+            .iter()
+            .map(|dir| format!("--volume={}", dir))
+        });
+        args.push(String::from("--tmpfs=/tmp"));
+    }
+    args
+}
+
+/// Quote `s` for a shell on an endpoint host (or in a container)
+pub(super) fn shell_quote(s: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r#"'\''"#))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::security_args;
+    use super::shell_quote;
+    use crate::config::SecurityProfile;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("docker"), "docker");
+        assert_eq!(shell_quote("--env=FOO=bar"), "--env=FOO=bar");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("{{.Id}}"), "'{{.Id}}'");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r#"'it'\''s'"#);
+    }
+
+    #[test]
+    fn test_security_args() {
+        assert!(security_args(&SecurityProfile::default(), None).is_empty());
+
+        let security: SecurityProfile = toml::from_str(r#"
+            apparmor_profile = "butido-build"
+            user = "1000:1000"
+            read_only_rootfs = true
+        "#).unwrap();
+        let args = security_args(&security, Some("/tmp/seccomp.json"));
+        assert_eq!(&args[..4], &[
+            "--security-opt=seccomp=/tmp/seccomp.json",
+            "--security-opt=apparmor=butido-build",
+            "--user=1000:1000",
+            "--read-only",
+        ]);
+        assert!(args.contains(&String::from("--volume=/outputs")));
+        assert!(args.contains(&String::from("--tmpfs=/tmp")));
+        assert!(!args.iter().any(|arg| arg.contains(crate::consts::SECRETS_DIR_PATH)));
+    }
+}
@@ -0,0 +1,394 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Artifact types
+//!
+//! Butido does not only store opaque files: the kind of an artifact (rpm, deb, tar) is detected
+//! and its native metadata (e.g. the NEVRA of an rpm or the control fields of a deb) is extracted,
+//! so that it can be recorded in the database and be searched for.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+/// The metadata of an artifact, as key-value pairs
+pub type ArtifactMetadata = BTreeMap<String, String>;
+
+/// A handler for one kind of artifacts
+pub trait ArtifactTypeHandler: Sync {
+    /// The name of the kind of artifacts this handler is responsible for
+    fn kind(&self) -> &'static str;
+
+    /// Whether the artifact at `path` is of this kind
+    fn detect(&self, path: &Path) -> bool;
+
+    /// Extract the metadata of the artifact at `path`
+    fn metadata(&self, path: &Path) -> Result<ArtifactMetadata>;
+}
+
+/// All known artifact type handlers, the first one which detects an artifact is used
+static HANDLERS: &[&dyn ArtifactTypeHandler] = &[&RpmHandler, &DebHandler, &TarHandler];
+
+/// The name of the kind of artifacts `name`, if there is a handler for this kind
+pub fn artifact_kind(name: &str) -> Option<&'static str> {
+    HANDLERS.iter().map(|h| h.kind()).find(|kind| *kind == name)
+}
+
+/// Find the handler for the artifact at `path`, if the kind of the artifact is known
+pub fn handler_for(path: &Path) -> Option<&'static dyn ArtifactTypeHandler> {
+    HANDLERS.iter().find(|h| h.detect(path)).copied()
+}
+
+fn file_name_ends_with(path: &Path, suffixes: &[&str]) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| suffixes.iter().any(|suffix| name.ends_with(suffix)))
+        .unwrap_or(false)
+}
+
+/// Handler for rpm packages
+///
+/// The header of the package is parsed directly, no rpm tooling is required.
+struct RpmHandler;
+
+impl ArtifactTypeHandler for RpmHandler {
+    fn kind(&self) -> &'static str {
+        "rpm"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        file_name_ends_with(path, &[".rpm"])
+    }
+
+    fn metadata(&self, path: &Path) -> Result<ArtifactMetadata> {
+        let file = File::open(path).with_context(|| anyhow!("Opening {}", path.display()))?;
+        rpm_metadata(BufReader::new(file)).with_context(|| anyhow!("Reading rpm header of {}", path.display()))
+    }
+}
+
+const RPM_LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const RPM_HEADER_MAGIC: [u8; 4] = [0x8e, 0xad, 0xe8, 0x01];
+const RPM_LEAD_SIZE: usize = 96;
+
+/// Upper bound for the size of a header, so that a broken file cannot make us allocate arbitrary
+/// amounts of memory
+const RPM_MAX_HEADER_SIZE: usize = 64 * 1024 * 1024;
+
+const RPM_TYPE_INT32: u32 = 4;
+const RPM_TYPE_STRING: u32 = 6;
+
+const RPMTAG_NAME: u32 = 1000;
+const RPMTAG_VERSION: u32 = 1001;
+const RPMTAG_RELEASE: u32 = 1002;
+const RPMTAG_EPOCH: u32 = 1003;
+const RPMTAG_ARCH: u32 = 1022;
+
+/// The index entries (tag, type, offset) and the data store of an rpm header
+struct RpmHeader {
+    index: Vec<(u32, u32, usize)>,
+    store: Vec<u8>,
+}
+
+impl RpmHeader {
+    fn read<R: Read>(r: &mut R) -> Result<(Self, usize)> {
+        let mut intro = [0u8; 16];
+        r.read_exact(&mut intro)?;
+        if intro[0..4] != RPM_HEADER_MAGIC {
+            return Err(anyhow!("Invalid rpm header magic"));
+        }
+
+        let nindex = u32::from_be_bytes([intro[8], intro[9], intro[10], intro[11]]) as usize;
+        let hsize = u32::from_be_bytes([intro[12], intro[13], intro[14], intro[15]]) as usize;
+        if nindex * 16 + hsize > RPM_MAX_HEADER_SIZE {
+            return Err(anyhow!("rpm header too big: {} entries, {} bytes", nindex, hsize));
+        }
+
+        let mut entries = vec![0u8; nindex * 16];
+        r.read_exact(&mut entries)?;
+        let index = entries
+            .chunks(16)
+            .map(|e| {
+                let be = |i: usize| u32::from_be_bytes([e[i], e[i + 1], e[i + 2], e[i + 3]]);
+                (be(0), be(4), be(8) as usize)
+            })
+            .collect();
+
+        let mut store = vec![0u8; hsize];
+        r.read_exact(&mut store)?;
+
+        Ok((RpmHeader { index, store }, 16 + nindex * 16 + hsize))
+    }
+
+    fn entry(&self, tag: u32, typ: u32) -> Option<usize> {
+        self.index
+            .iter()
+            .find(|(t, ty, _)| *t == tag && *ty == typ)
+            .map(|(_, _, offset)| *offset)
+    }
+
+    fn string(&self, tag: u32) -> Option<String> {
+        let offset = self.entry(tag, RPM_TYPE_STRING)?;
+        let data = self.store.get(offset..)?;
+        let end = data.iter().position(|b| *b == 0)?;
+        Some(String::from_utf8_lossy(&data[..end]).into_owned())
+    }
+
+    fn int32(&self, tag: u32) -> Option<u32> {
+        let offset = self.entry(tag, RPM_TYPE_INT32)?;
+        let data = self.store.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+    }
+}
+
+fn rpm_metadata<R: Read>(mut r: R) -> Result<ArtifactMetadata> {
+    let mut lead = [0u8; RPM_LEAD_SIZE];
+    r.read_exact(&mut lead)?;
+    if lead[0..4] != RPM_LEAD_MAGIC {
+        return Err(anyhow!("Not an rpm file"));
+    }
+
+    // The signature header is padded to a multiple of 8 bytes
+    let (_, signature_size) = RpmHeader::read(&mut r).context("Reading signature header")?;
+    let mut padding = vec![0u8; (8 - signature_size % 8) % 8];
+    r.read_exact(&mut padding)?;
+
+    let (header, _) = RpmHeader::read(&mut r).context("Reading main header")?;
+    let get = |tag, name| header.string(tag).ok_or_else(|| anyhow!("rpm header has no {}", name));
+    let name = get(RPMTAG_NAME, "name")?;
+    let version = get(RPMTAG_VERSION, "version")?;
+    let release = get(RPMTAG_RELEASE, "release")?;
+    let arch = get(RPMTAG_ARCH, "arch")?;
+    let epoch = header.int32(RPMTAG_EPOCH);
+
+    let nevra = match epoch {
+        Some(epoch) => format!("{}-{}:{}-{}.{}", name, epoch, version, release, arch),
+        None => format!("{}-{}-{}.{}", name, version, release, arch),
+    };
+
+    let mut metadata = ArtifactMetadata::new();
+    metadata.insert(String::from("name"), name);
+    metadata.insert(String::from("version"), version);
+    metadata.insert(String::from("release"), release);
+    metadata.insert(String::from("arch"), arch);
+    if let Some(epoch) = epoch {
+        metadata.insert(String::from("epoch"), epoch.to_string());
+    }
+    metadata.insert(String::from("nevra"), nevra);
+    Ok(metadata)
+}
+
+/// Handler for deb packages
+///
+/// The control fields are read with `dpkg-deb`.
+struct DebHandler;
+
+impl ArtifactTypeHandler for DebHandler {
+    fn kind(&self) -> &'static str {
+        "deb"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        file_name_ends_with(path, &[".deb", ".udeb"])
+    }
+
+    fn metadata(&self, path: &Path) -> Result<ArtifactMetadata> {
+        let output = std::process::Command::new("dpkg-deb")
+            .arg("--field")
+            .arg(path)
+            .output()
+            .context("Running dpkg-deb")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "dpkg-deb failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(parse_control(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Parse the fields of a debian control file, the keys are lowercased
+///
+/// Continuation lines of multiline fields are joined with newlines.
+fn parse_control(control: &str) -> ArtifactMetadata {
+    let mut metadata = ArtifactMetadata::new();
+    let mut current: Option<String> = None;
+
+    for line in control.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(value) = current.as_ref().and_then(|key| metadata.get_mut(key)) {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+        } else {
+            let mut split = line.splitn(2, ':');
+            if let (Some(key), Some(value)) = (split.next(), split.next()) {
+                let key = key.trim().to_lowercase();
+                metadata.insert(key.clone(), value.trim().to_string());
+                current = Some(key);
+            }
+        }
+    }
+
+    metadata
+}
+
+/// Handler for tar archives
+struct TarHandler;
+
+const TAR_COMPRESSIONS: &[(&str, &str)] = &[
+    (".tar", "none"),
+    (".tar.gz", "gzip"),
+    (".tgz", "gzip"),
+    (".tar.bz2", "bzip2"),
+    (".tar.xz", "xz"),
+    (".tar.zst", "zstd"),
+];
+
+impl ArtifactTypeHandler for TarHandler {
+    fn kind(&self) -> &'static str {
+        "tar"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        let suffixes = TAR_COMPRESSIONS.iter().map(|(suffix, _)| *suffix).collect::<Vec<_>>();
+        file_name_ends_with(path, &suffixes)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<ArtifactMetadata> {
+        let compression = TAR_COMPRESSIONS
+            .iter()
+            .find(|(suffix, _)| file_name_ends_with(path, &[*suffix]))
+            .map(|(_, compression)| *compression)
+            .unwrap_or("none");
+
+        let mut metadata = ArtifactMetadata::new();
+        metadata.insert(String::from("compression"), compression.to_string());
+
+        // Compressed archives cannot be read without decompressing them, which we cannot do here
+        if compression == "none" {
+            let file = File::open(path).with_context(|| anyhow!("Opening {}", path.display()))?;
+            let entries = tar::Archive::new(BufReader::new(file))
+                .entries()?
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .with_context(|| anyhow!("Reading entries of {}", path.display()))?
+                .len();
+            metadata.insert(String::from("entries"), entries.to_string());
+        }
+
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an rpm header with the passed string and int32 tags
+    fn rpm_header(strings: &[(u32, &str)], ints: &[(u32, u32)]) -> Vec<u8> {
+        let mut index = vec![];
+        let mut store = vec![];
+
+        for (tag, value) in strings {
+            index.push((*tag, RPM_TYPE_STRING, store.len()));
+            store.extend_from_slice(value.as_bytes());
+            store.push(0);
+        }
+        for (tag, value) in ints {
+            while store.len() % 4 != 0 {
+                store.push(0);
+            }
+            index.push((*tag, RPM_TYPE_INT32, store.len()));
+            store.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let mut header = RPM_HEADER_MAGIC.to_vec();
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&(index.len() as u32).to_be_bytes());
+        header.extend_from_slice(&(store.len() as u32).to_be_bytes());
+        for (tag, typ, offset) in index {
+            header.extend_from_slice(&tag.to_be_bytes());
+            header.extend_from_slice(&typ.to_be_bytes());
+            header.extend_from_slice(&(offset as u32).to_be_bytes());
+            header.extend_from_slice(&1u32.to_be_bytes());
+        }
+        header.extend_from_slice(&store);
+        header
+    }
+
+    fn rpm(epoch: Option<u32>) -> Vec<u8> {
+        let mut rpm = RPM_LEAD_MAGIC.to_vec();
+        rpm.resize(RPM_LEAD_SIZE, 0);
+
+        let signature = rpm_header(&[(1000, "abc")], &[]);
+        let padding = (8 - signature.len() % 8) % 8;
+        rpm.extend_from_slice(&signature);
+        rpm.extend(std::iter::repeat(0).take(padding));
+
+        let ints = epoch.map(|e| vec![(RPMTAG_EPOCH, e)]).unwrap_or_default();
+        rpm.extend_from_slice(&rpm_header(&[
+            (RPMTAG_NAME, "foo"),
+            (RPMTAG_VERSION, "1.2"),
+            (RPMTAG_RELEASE, "3.el8"),
+            (RPMTAG_ARCH, "x86_64"),
+        ], &ints));
+        rpm
+    }
+
+    #[test]
+    fn test_rpm_metadata() {
+        let metadata = rpm_metadata(&rpm(None)[..]).unwrap();
+        assert_eq!(metadata.get("name").unwrap(), "foo");
+        assert_eq!(metadata.get("arch").unwrap(), "x86_64");
+        assert_eq!(metadata.get("nevra").unwrap(), "foo-1.2-3.el8.x86_64");
+        assert!(metadata.get("epoch").is_none());
+
+        let metadata = rpm_metadata(&rpm(Some(2))[..]).unwrap();
+        assert_eq!(metadata.get("epoch").unwrap(), "2");
+        assert_eq!(metadata.get("nevra").unwrap(), "foo-2:1.2-3.el8.x86_64");
+    }
+
+    #[test]
+    fn test_rpm_metadata_not_an_rpm() {
+        assert!(rpm_metadata(&[0u8; 200][..]).is_err());
+        assert!(rpm_metadata(&RPM_LEAD_MAGIC[..]).is_err());
+    }
+
+    #[test]
+    fn test_parse_control() {
+        let control = "Package: foo\nVersion: 1.2-3\nArchitecture: amd64\nDescription: A foo\n Which does foo.\n";
+        let metadata = parse_control(control);
+        assert_eq!(metadata.get("package").unwrap(), "foo");
+        assert_eq!(metadata.get("version").unwrap(), "1.2-3");
+        assert_eq!(metadata.get("architecture").unwrap(), "amd64");
+        assert_eq!(metadata.get("description").unwrap(), "A foo\nWhich does foo.");
+    }
+
+    #[test]
+    fn test_handler_for() {
+        let kind = |p: &str| handler_for(Path::new(p)).map(|h| h.kind());
+        assert_eq!(kind("outputs/foo-1.2-3.el8.x86_64.rpm"), Some("rpm"));
+        assert_eq!(kind("outputs/foo_1.2-3_amd64.deb"), Some("deb"));
+        assert_eq!(kind("outputs/foo-1.2.tar.gz"), Some("tar"));
+        assert_eq!(kind("outputs/foo-1.2.zip"), None);
+
+        assert_eq!(artifact_kind("rpm"), Some("rpm"));
+        assert_eq!(artifact_kind("zip"), None);
+    }
+}
@@ -0,0 +1,262 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Indexes of release stores for package managers
+//!
+//! A release store can be served to package managers directly, if an index of its artifacts is
+//! written to it: a "Packages" file for apt, "repodata" for yum/dnf or a flat JSON list of all
+//! artifacts. The indexes are built from the metadata extracted from the artifacts (see
+//! `artifact_type`).
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::Error;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::filestore::artifact_type::ArtifactMetadata;
+
+/// The flat JSON index, in the root of the store
+pub const FLAT_JSON_INDEX: &str = "index.json";
+
+/// The apt index, in the root of the store
+pub const APT_INDEX: &str = "Packages";
+
+/// The directory of the yum index, in the root of the store
+pub const YUM_INDEX_DIR: &str = "repodata";
+
+/// An artifact in the index
+#[derive(Debug, Serialize)]
+pub struct IndexEntry {
+    /// The path of the artifact, relative to the root of the store
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+
+    /// The kind of the artifact (e.g. "rpm") and its metadata, if the kind is known
+    pub kind: Option<String>,
+    pub metadata: ArtifactMetadata,
+}
+
+impl IndexEntry {
+    fn is_kind(&self, kind: &str) -> bool {
+        self.kind.as_deref() == Some(kind)
+    }
+
+    fn get(&self, key: &str) -> &str {
+        self.metadata.get(key).map(String::as_str).unwrap_or("")
+    }
+}
+
+/// Whether the path (relative to the root of a store) is part of an index and not an artifact
+pub fn is_index_file(path: &Path) -> bool {
+    path == Path::new(FLAT_JSON_INDEX) || path == Path::new(APT_INDEX) || path.starts_with(YUM_INDEX_DIR)
+}
+
+/// The flat JSON index: a list of all artifacts with their size, checksum, kind and metadata
+pub fn flat_json(entries: &[IndexEntry]) -> Result<Vec<u8>> {
+    serde_json::to_vec_pretty(entries).map_err(Error::from)
+}
+
+/// The "Packages" file of a flat apt repository, listing the deb artifacts
+pub fn apt_packages(entries: &[IndexEntry]) -> String {
+    let mut packages = String::new();
+
+    for entry in entries.iter().filter(|e| e.is_kind("deb")) {
+        if !packages.is_empty() {
+            packages.push('\n');
+        }
+
+        // "Package" has to come first, the other fields are sorted by name
+        let fields = entry
+            .metadata
+            .get_key_value("package")
+            .into_iter()
+            .chain(entry.metadata.iter().filter(|(key, _)| !APT_GENERATED_FIELDS.contains(&key.as_str())));
+
+        for (key, value) in fields {
+            let value = value
+                .lines()
+                .enumerate()
+                .map(|(i, line)| match (i, line) {
+                    (0, line) => line.to_string(),
+                    (_, "") => String::from(" ."),
+                    (_, line) => format!(" {}", line),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let _ = writeln!(packages, "{}: {}", control_field_name(key), value);
+        }
+
+        let _ = writeln!(packages, "Filename: {}", entry.path);
+        let _ = writeln!(packages, "Size: {}", entry.size);
+        let _ = writeln!(packages, "SHA256: {}", entry.sha256);
+    }
+
+    packages
+}
+
+/// The fields of the apt index which are not taken from the control file of a package
+const APT_GENERATED_FIELDS: &[&str] = &["package", "filename", "size", "md5sum", "sha1", "sha256"];
+
+/// The name of a control field as written in a control file, e.g. "Installed-Size"
+fn control_field_name(key: &str) -> String {
+    key.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The "primary.xml" of a yum repository, listing the rpm artifacts
+///
+/// Dependencies are not part of the extracted metadata, so the packages only provide themselves
+/// and do not require anything.
+pub fn yum_primary(entries: &[IndexEntry]) -> String {
+    let rpms = entries.iter().filter(|e| e.is_kind("rpm")).collect::<Vec<_>>();
+    let mut primary = String::new();
+
+    let _ = writeln!(primary, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        primary,
+        r#"<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="{}">"#,
+        rpms.len()
+    );
+
+    for entry in rpms {
+        let name = xml_escape(entry.get("name"));
+        let epoch = match entry.get("epoch") {
+            "" => String::from("0"),
+            epoch => xml_escape(epoch),
+        };
+        let version = format!(
+            r#"epoch="{}" ver="{}" rel="{}""#,
+            epoch,
+            xml_escape(entry.get("version")),
+            xml_escape(entry.get("release"))
+        );
+
+        let _ = writeln!(primary, r#"<package type="rpm">"#);
+        let _ = writeln!(primary, "  <name>{}</name>", name);
+        let _ = writeln!(primary, "  <arch>{}</arch>", xml_escape(entry.get("arch")));
+        let _ = writeln!(primary, "  <version {}/>", version);
+        let _ = writeln!(primary, r#"  <checksum type="sha256" pkgid="YES">{}</checksum>"#, entry.sha256);
+        let _ = writeln!(primary, r#"  <size package="{}" installed="0" archive="0"/>"#, entry.size);
+        let _ = writeln!(primary, r#"  <location href="{}"/>"#, xml_escape(&entry.path));
+        let _ = writeln!(primary, "  <format>");
+        let _ = writeln!(primary, "    <rpm:provides>");
+        let _ = writeln!(primary, r#"      <rpm:entry name="{}" flags="EQ" {}/>"#, name, version);
+        let _ = writeln!(primary, "    </rpm:provides>");
+        let _ = writeln!(primary, "  </format>");
+        let _ = writeln!(primary, "</package>");
+    }
+
+    let _ = writeln!(primary, "</metadata>");
+    primary
+}
+
+/// The "repomd.xml" of a yum repository, pointing to the primary.xml at `primary_href`
+pub fn yum_repomd(primary_href: &str, primary_sha256: &str, primary_size: usize, timestamp: i64) -> String {
+    indoc::formatdoc!(r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+              <revision>{timestamp}</revision>
+              <data type="primary">
+                <checksum type="sha256">{sha256}</checksum>
+                <location href="{href}"/>
+                <timestamp>{timestamp}</timestamp>
+                <size>{size}</size>
+              </data>
+            </repomd>
+        "#,
+        timestamp = timestamp,
+        sha256 = primary_sha256,
+        href = xml_escape(primary_href),
+        size = primary_size,
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, kind: &str, metadata: &[(&str, &str)]) -> IndexEntry {
+        IndexEntry {
+            path: path.to_string(),
+            size: 1234,
+            sha256: String::from("abcd"),
+            kind: Some(kind.to_string()),
+            metadata: metadata.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_apt_packages() {
+        let entries = vec![
+            entry("outputs/foo_1.2-3_amd64.deb", "deb", &[
+                ("package", "foo"),
+                ("version", "1.2-3"),
+                ("installed-size", "42"),
+                ("description", "A foo\nWhich does foo.\n\nAnd more."),
+            ]),
+            entry("outputs/foo-1.2.tar.gz", "tar", &[("compression", "gzip")]),
+        ];
+
+        assert_eq!(apt_packages(&entries), indoc::indoc!("
+            Package: foo
+            Description: A foo
+             Which does foo.
+             .
+             And more.
+            Installed-Size: 42
+            Version: 1.2-3
+            Filename: outputs/foo_1.2-3_amd64.deb
+            Size: 1234
+            SHA256: abcd
+        "));
+    }
+
+    #[test]
+    fn test_yum_primary() {
+        let entries = vec![entry("outputs/foo-1.2-3.el8.x86_64.rpm", "rpm", &[
+            ("name", "foo"),
+            ("version", "1.2"),
+            ("release", "3.el8"),
+            ("arch", "x86_64"),
+        ])];
+
+        let primary = yum_primary(&entries);
+        assert!(primary.contains(r#"packages="1""#));
+        assert!(primary.contains(r#"<version epoch="0" ver="1.2" rel="3.el8"/>"#));
+        assert!(primary.contains(r#"<location href="outputs/foo-1.2-3.el8.x86_64.rpm"/>"#));
+        assert_eq!(xml_escape(r#"a<b & "c""#), "a&lt;b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn test_is_index_file() {
+        assert!(is_index_file(Path::new("Packages")));
+        assert!(is_index_file(Path::new("repodata/repomd.xml")));
+        assert!(!is_index_file(Path::new("outputs/Packages")));
+    }
+}
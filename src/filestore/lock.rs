@@ -0,0 +1,139 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Advisory locking of stores
+//!
+//! A store is locked with an advisory lock (`flock`) on a lock file in its root directory. The lock
+//! is released by the operating system when the butido process holding it ends, so there are no
+//! stale locks to clean up. The lock file contains the PID of the holder, which is only used in
+//! the "store busy" error of other processes.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use fs2::FileExt;
+use log::debug;
+
+/// The name of the lock file in the root of a locked store
+pub const LOCK_FILE_NAME: &str = ".butido.lock";
+
+/// A lock on a store, which is released when it is dropped
+#[derive(Debug)]
+pub struct StoreLock {
+    path: PathBuf,
+
+    /// The open lock file, closing it releases the lock
+    file: File,
+}
+
+impl StoreLock {
+    /// Lock the store in `root`
+    ///
+    /// Fails with a "store busy" error if another butido process holds the lock.
+    pub fn acquire(root: &Path) -> Result<Self> {
+        let path = root.join(LOCK_FILE_NAME);
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false) // the PID of the holder is only overwritten once the lock is acquired
+            .open(&path)
+            .with_context(|| anyhow!("Opening lock file {}", path.display()))?;
+
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+                return Err(e).with_context(|| anyhow!("Locking {}", path.display()))
+            }
+
+            let holder = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|s| s.trim().parse::<u32>().ok())
+                .map(|pid| format!("butido process {}", pid))
+                .unwrap_or_else(|| String::from("another butido process"));
+            return Err(anyhow!("Store busy: {} is in use by {} (lock file: {})", root.display(), holder, path.display()))
+        }
+
+        // The lock file is removed with its store, possibly after it was opened here
+        if !is_same_file(&file, &path) {
+            return Err(anyhow!("Store busy: {} is being removed by another butido process", root.display()))
+        }
+
+        file.set_len(0)
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .and_then(|_| file.sync_all())
+            .with_context(|| anyhow!("Writing lock file {}", path.display()))?;
+        debug!("Locked store {}", root.display());
+        Ok(StoreLock { path, file })
+    }
+
+    /// Remove the lock file and release the lock, for removing the locked store
+    ///
+    /// Processes which opened the lock file before it was removed do not get the lock, see
+    /// `acquire()`.
+    pub fn remove(self) -> Result<()> {
+        let StoreLock { path, file } = self;
+        std::fs::remove_file(&path).with_context(|| anyhow!("Removing lock file {}", path.display()))?;
+        drop(file);
+        Ok(())
+    }
+}
+
+/// Check whether `file` is (still) the file at `path`
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = std::env::temp_dir().join(format!("butido-test-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let lock = StoreLock::acquire(&dir).unwrap();
+        let err = StoreLock::acquire(&dir).unwrap_err();
+        assert!(err.to_string().starts_with("Store busy"), "{}", err);
+        assert!(err.to_string().contains(&format!("butido process {}", std::process::id())), "{}", err);
+
+        drop(lock);
+        let lock = StoreLock::acquire(&dir).unwrap();
+
+        lock.remove().unwrap();
+        assert!(!dir.join(LOCK_FILE_NAME).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_removed_lock_file_is_not_locked() {
+        let dir = std::env::temp_dir().join(format!("butido-test-lock-removed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Like a process which opened the lock file right before the store was removed
+        let lock = StoreLock::acquire(&dir).unwrap();
+        let file = std::fs::File::open(dir.join(LOCK_FILE_NAME)).unwrap();
+        lock.remove().unwrap();
+        assert!(file.try_lock_exclusive().is_ok());
+        assert!(!is_same_file(&file, &dir.join(LOCK_FILE_NAME)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -8,12 +8,25 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+pub mod artifact_type;
+pub mod index;
+
 mod release;
 pub use release::*;
 
 mod staging;
 pub use staging::*;
 
+pub mod remote;
+
+mod storage;
+pub use storage::ArtifactContent;
+pub use storage::ArtifactStorage;
+
+mod lock;
+pub use lock::StoreLock;
+pub use lock::LOCK_FILE_NAME;
+
 pub mod path;
 pub use path::ArtifactPath;
 
@@ -20,6 +20,7 @@ use resiter::AndThen;
 use resiter::Filter;
 use resiter::Map;
 
+use crate::config::ArtifactOwner;
 use crate::filestore::staging::StagingStore;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -60,16 +61,55 @@ impl StoreRoot {
         self.0.join(subpath).is_dir()
     }
 
+    pub(in crate::filestore) fn path(&self) -> &Path {
+        &self.0
+    }
+
     pub fn display(&self) -> std::path::Display {
         self.0.display()
     }
 
-    pub(in crate::filestore) fn find_artifacts_recursive(
-        &self,
-    ) -> impl Iterator<Item = Result<ArtifactPath>> {
+    /// Find all artifacts in the store, calling `on_found` for each of them
+    ///
+    /// The entries in the root of the store are walked in parallel on the rayon thread pool, which
+    /// is bounded by the number of CPUs.
+    pub(in crate::filestore) fn find_artifacts_recursive<F>(&self, on_found: F) -> Result<Vec<ArtifactPath>>
+    where
+        F: Fn(&ArtifactPath) + Sync,
+    {
+        use rayon::iter::IntoParallelIterator;
+        use rayon::iter::ParallelIterator;
+
         log::trace!("Loading artifacts from directory: {:?}", self.0);
+
+        // Symlinks in the root are skipped like all other symlinks, walking them would follow them
+        let entries = std::fs::read_dir(&self.0)
+            .with_context(|| anyhow!("Reading directory {}", self.0.display()))?
+            .filter_map(|entry| match entry.and_then(|e| e.file_type().map(|ft| (e, ft))) {
+                Ok((_, ft)) if ft.is_symlink() => None,
+                Ok((e, _)) => Some(Ok(e.path())),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| anyhow!("Reading directory {}", self.0.display()))?;
+
+        entries
+            .into_par_iter()
+            .map(|entry| {
+                self.find_artifacts_below(&entry)
+                    .inspect(|r| if let Ok(ap) = r { on_found(ap) })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(|artifacts| artifacts.into_iter().flatten().collect())
+    }
+
+    /// Find the artifacts at or below `start`, which is an entry in the root of the store
+    fn find_artifacts_below(&self, start: &Path) -> impl Iterator<Item = Result<ArtifactPath>> {
         let root = self.0.clone();
-        walkdir::WalkDir::new(&self.0)
+        let index_root = self.0.clone();
+        let internal_root = self.0.clone();
+        walkdir::WalkDir::new(start)
             .follow_links(false)
             .into_iter()
             .filter_ok(|e| {
@@ -77,6 +117,14 @@ impl StoreRoot {
                 log::trace!("{:?} is file = {}", e, is_file);
                 is_file
             })
+            .filter_ok(move |e| !(e.path().parent() == Some(internal_root.as_path()) && is_internal_file(e.file_name())))
+            .filter_ok(move |e| {
+                !e.path()
+                    .strip_prefix(&index_root)
+                    .map(crate::filestore::index::is_index_file)
+                    .unwrap_or(false)
+            })
+            .filter_ok(|e| !crate::util::signing::is_signature_file(e.path()))
             .inspect(|p| log::trace!("Loading Artifact from path: {:?}", p))
             .map_err(Error::from)
             .and_then_ok(move |de| {
@@ -94,50 +142,194 @@ impl StoreRoot {
     /// `self` and returns the written pathes.
     ///
     /// The function filteres out the "/output" directory (that's what is meant by "butido-style").
-    pub(in crate::filestore) fn unpack_archive_here<R>(&self, mut ar: tar::Archive<R>) -> Result<Vec<PathBuf>>
+    /// If `subdir` is set, the files are unpacked into this directory instead.
+    ///
+    /// Permissions are normalized while unpacking: ownership is never restored from the archive,
+    /// all files are changed to belong to `owner`, and setuid/setgid/sticky bits are stripped
+    /// unless the (filtered) path is contained in `setuid_whitelist`.
+    /// Archives containing device nodes or links pointing outside of the archive root are rejected.
+    pub(in crate::filestore) fn unpack_archive_here<R>(
+        &self,
+        mut ar: tar::Archive<R>,
+        setuid_whitelist: &[PathBuf],
+        owner: ArtifactOwner,
+        subdir: Option<&Path>,
+    ) -> Result<Vec<PathBuf>>
     where
         R: std::io::Read,
     {
+        ar.set_preserve_permissions(false);
         ar.entries()?
             .into_iter()
             .map_err(Error::from)
+            .and_then_ok(|entry| check_archive_entry(&entry).map(|_| entry))
             .filter_ok(|entry| entry.header().entry_type() == tar::EntryType::Regular)
             .and_then_ok(|mut entry| -> Result<_> {
-                let path = entry
-                    .path()
-                    .context("Getting path from entry in Archive")?
-                    .components()
-                    .filter(|comp| {
-                        log::trace!("Filtering path component: '{:?}'", comp);
-                        let osstr = std::ffi::OsStr::new(crate::consts::OUTPUTS_DIR_NAME);
-                        match comp {
-                            std::path::Component::Normal(s) => *s != osstr,
-                            _ => true,
-                        }
-                    })
-                    .collect::<PathBuf>();
-
+                let path = unpack_path(&entry)?;
                 log::trace!("Path = '{:?}'", path);
-                let unpack_dest = self.0.join(&path);
+                let dest_path = in_subdir(subdir, &path);
+                let unpack_dest = self.0.join(&dest_path);
                 log::trace!("Unpack to = '{:?}'", unpack_dest);
 
-                entry.unpack(unpack_dest)
-                    .map(|_| path)
-                    .map_err(Error::from)
+                if let Some(parent) = unpack_dest.parent() {
+                    std::fs::create_dir_all(parent)
+                        .with_context(|| anyhow!("Creating directory {}", parent.display()))?;
+                }
+
+                let mode = entry.header().mode().context("Getting mode from entry in Archive")?;
+                entry.unpack(&unpack_dest)?;
+
+                // Changing the owner clears the setuid/setgid bits, so it has to happen first
+                std::os::unix::fs::chown(&unpack_dest, Some(owner.uid()), Some(owner.gid()))
+                    .with_context(|| anyhow!("Changing owner of {} to {}:{}", unpack_dest.display(), owner.uid(), owner.gid()))?;
+
+                if mode & SPECIAL_MODE_BITS != 0 {
+                    if setuid_whitelist.contains(&path) {
+                        use std::os::unix::fs::PermissionsExt;
+
+                        log::debug!("Keeping setuid/setgid/sticky bits of whitelisted artifact {}", path.display());
+                        std::fs::set_permissions(&unpack_dest, std::fs::Permissions::from_mode(mode & 0o7777))
+                            .with_context(|| anyhow!("Setting permissions of {}", unpack_dest.display()))?;
+                    } else {
+                        log::warn!("Stripped setuid/setgid/sticky bits from artifact {}", path.display());
+                    }
+                }
+
+                Ok(dest_path)
             })
             .collect::<Result<Vec<_>>>()
     }
 }
 
+/// The pathes (relative to the store root) `unpack_archive_here()` writes the files of the
+/// provided tar archive to
+///
+/// Fails for archives `unpack_archive_here()` would refuse to unpack.
+pub(in crate::filestore) fn archive_destinations<R>(mut ar: tar::Archive<R>, subdir: Option<&Path>) -> Result<Vec<PathBuf>>
+where
+    R: std::io::Read,
+{
+    ar.entries()?
+        .into_iter()
+        .map_err(Error::from)
+        .and_then_ok(|entry| check_archive_entry(&entry).map(|_| entry))
+        .filter_ok(|entry| entry.header().entry_type() == tar::EntryType::Regular)
+        .and_then_ok(|entry| unpack_path(&entry))
+        .map_ok(|path| in_subdir(subdir, &path))
+        .collect::<Result<Vec<_>>>()
+}
+
+/// The path `path` in the directory `subdir`, if set
+fn in_subdir(subdir: Option<&Path>, path: &Path) -> PathBuf {
+    subdir.map(|d| d.join(path)).unwrap_or_else(|| path.to_path_buf())
+}
+
+/// The path of an entry of an archive from a container, with the "/output" directory and "."
+/// components filtered out
+fn unpack_path<R: std::io::Read>(entry: &tar::Entry<'_, R>) -> Result<PathBuf> {
+    let path = entry
+        .path()
+        .context("Getting path from entry in Archive")?
+        .components()
+        .filter(|comp| {
+            log::trace!("Filtering path component: '{:?}'", comp);
+            let osstr = std::ffi::OsStr::new(crate::consts::OUTPUTS_DIR_NAME);
+            match comp {
+                std::path::Component::Normal(s) => *s != osstr,
+                std::path::Component::CurDir => false,
+                _ => true,
+            }
+        })
+        .collect::<PathBuf>();
+    Ok(path)
+}
+
+/// Whether a file in the root of a store is used by butido itself and is not an artifact
+pub fn is_internal_file(name: &OsStr) -> bool {
+    name == crate::filestore::lock::LOCK_FILE_NAME || name == crate::filestore::staging::JOURNAL_FILE_NAME
+}
+
+/// The setuid, setgid and sticky bits of a file mode
+const SPECIAL_MODE_BITS: u32 = 0o7000;
+
+/// Check whether an entry of an archive from a container may be unpacked
+///
+/// Entries outside of the archive root and device nodes are rejected, as are symlinks and hardlinks
+/// with an absolute target or a target outside of the archive root.
+fn check_archive_entry<R: std::io::Read>(entry: &tar::Entry<'_, R>) -> Result<()> {
+    let path = entry.path().context("Getting path from entry in Archive")?;
+    if escapes_root(&path) {
+        return Err(anyhow!("Archive contains path outside of the archive: {}", path.display()));
+    }
+
+    match entry.header().entry_type() {
+        tar::EntryType::Char | tar::EntryType::Block => {
+            Err(anyhow!("Archive contains device node: {}", path.display()))
+        }
+
+        typ @ tar::EntryType::Symlink | typ @ tar::EntryType::Link => {
+            let target = entry
+                .link_name()
+                .context("Getting link target from entry in Archive")?
+                .ok_or_else(|| anyhow!("Link without target in archive: {}", path.display()))?;
+
+            // Symlinks are relative to the directory they live in, hardlinks to the archive root
+            let resolved = if typ == tar::EntryType::Symlink {
+                path.parent().unwrap_or_else(|| Path::new("")).join(&target)
+            } else {
+                target.to_path_buf()
+            };
+
+            if target.is_absolute() || escapes_root(&resolved) {
+                Err(anyhow!(
+                    "Archive contains link pointing outside of the archive: {} -> {}",
+                    path.display(),
+                    target.display()
+                ))
+            } else {
+                Ok(())
+            }
+        }
+
+        _ => Ok(()),
+    }
+}
+
+/// Check whether a relative path leaves the directory it is relative to
+fn escapes_root(path: &Path) -> bool {
+    let mut depth: usize = 0;
+    for comp in path.components() {
+        match comp {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ArtifactPath(PathBuf);
 
 impl ArtifactPath {
+    /// Create an ArtifactPath from a relative path
+    ///
+    /// Artifact pathes are also read from the database and from remote stores, so pathes which
+    /// contain anything but normal components (like "..") are rejected, as they could point outside
+    /// of the store.
     pub fn new(p: PathBuf) -> Result<Self> {
-        if p.is_relative() {
-            Ok(ArtifactPath(p))
-        } else {
+        if !p.is_relative() {
             Err(anyhow!("Path is not relative: {}", p.display()))
+        } else if p.components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+            Err(anyhow!("Path is not a plain relative path: {}", p.display()))
+        } else {
+            Ok(ArtifactPath(p))
         }
     }
 
@@ -208,3 +400,58 @@ impl<'a> std::fmt::Display for FullArtifactPathDisplay<'a> {
         write!(fmt, "{}/{}", self.0.display(), self.1.display())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::escapes_root;
+    use super::ArtifactPath;
+    use super::StoreRoot;
+    use crate::config::ArtifactOwner;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_escapes_root() {
+        assert!(!escapes_root(Path::new("outputs/foo")));
+        assert!(!escapes_root(Path::new("outputs/../foo")));
+        assert!(!escapes_root(Path::new("./outputs/./foo")));
+        assert!(escapes_root(Path::new("outputs/../../foo")));
+        assert!(escapes_root(Path::new("../foo")));
+        assert!(escapes_root(Path::new("/etc/passwd")));
+    }
+    #[test]
+    fn test_artifact_path_new() {
+        assert!(ArtifactPath::new(PathBuf::from("foo-1.0.tar.gz")).is_ok());
+        assert!(ArtifactPath::new(PathBuf::from("outputs/foo-1.0.tar.gz")).is_ok());
+        assert!(ArtifactPath::new(PathBuf::from("../../home/user/.bashrc")).is_err());
+        assert!(ArtifactPath::new(PathBuf::from("outputs/../foo-1.0.tar.gz")).is_err());
+        assert!(ArtifactPath::new(PathBuf::from("./foo-1.0.tar.gz")).is_err());
+        assert!(ArtifactPath::new(PathBuf::from("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn test_unpack_archive_changes_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let meta = std::fs::metadata(dir.path()).unwrap();
+        let owner = ArtifactOwner::new(meta.uid(), meta.gid());
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let content = b"artifact";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(12345);
+        header.set_gid(23456);
+        header.set_cksum();
+        builder.append_data(&mut header, "outputs/foo-1.0.tar.gz", &content[..]).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        let root = StoreRoot::new(dir.path().to_path_buf()).unwrap();
+        let written = root.unpack_archive_here(tar::Archive::new(&archive[..]), &[], owner, None).unwrap();
+        assert_eq!(written, vec![PathBuf::from("foo-1.0.tar.gz")]);
+
+        let meta = std::fs::metadata(dir.path().join("foo-1.0.tar.gz")).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (owner.uid(), owner.gid()));
+    }
+}
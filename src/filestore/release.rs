@@ -38,4 +38,8 @@ impl ReleaseStore {
     pub fn get(&self, p: &ArtifactPath) -> Option<&ArtifactPath> {
         self.0.get(p)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ArtifactPath> {
+        self.0.iter()
+    }
 }
@@ -0,0 +1,406 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! An S3 compatible object storage as a store for artifacts
+//!
+//! Requests are signed with AWS Signature Version 4, which is understood by Amazon S3 as well as by
+//! MinIO and other compatible implementations. Buckets are always addressed path-style.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use anyhow::anyhow;
+use futures::future::FutureExt;
+use futures::future::LocalBoxFuture;
+use futures::stream::TryStreamExt;
+use hmac::Hmac;
+use hmac::Mac;
+use hmac::NewMac;
+use itertools::Itertools;
+use log::trace;
+use log::warn;
+use reqwest::Method;
+use sha2::Digest;
+use tokio_util::io::ReaderStream;
+use tokio_util::io::StreamReader;
+
+use crate::config::RemoteStoreConfig;
+use crate::filestore::ArtifactContent;
+use crate::filestore::ArtifactStorage;
+use crate::filestore::path::ArtifactPath;
+
+/// The headers which are included in the signature of a request
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// The payload hash of requests whose content is not part of the signature
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// The artifacts below a prefix in an S3 bucket
+pub struct S3Store {
+    client: reqwest::Client,
+    config: RemoteStoreConfig,
+    access_key: String,
+    secret_key: String,
+
+    /// The prefix of the keys of the artifacts, without trailing slash
+    prefix: String,
+}
+
+impl S3Store {
+    /// Access the artifacts below `prefix` (which is appended to the prefix from the configuration)
+    ///
+    /// The credentials are read from the environment variables named in the configuration.
+    pub fn new(config: &RemoteStoreConfig, prefix: &str) -> Result<Self> {
+        let env = |name: &str| {
+            std::env::var(name)
+                .with_context(|| anyhow!("Reading credentials for the remote store from environment variable {}", name))
+        };
+        let access_key = env(config.access_key_env())?;
+        let secret_key = env(config.secret_key_env())?;
+
+        let prefix = match config.prefix().as_ref() {
+            Some(p) => format!("{}/{}", p.trim_matches('/'), prefix.trim_matches('/')),
+            None => prefix.trim_matches('/').to_string(),
+        };
+
+        let client = reqwest::Client::builder()
+            .build()
+            .context("Building HTTP client for the remote store")?;
+
+        Ok(S3Store { client, config: config.clone(), access_key, secret_key, prefix })
+    }
+
+    /// The key of an artifact in the bucket
+    fn key(&self, path: &ArtifactPath) -> Result<String> {
+        let components = path
+            .as_ref()
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow!("Not a valid UTF-8 path: {}", path.display()))?;
+
+        Ok(format!("{}/{}", self.prefix, components.join("/")))
+    }
+
+    /// Send a signed request for `key` (or the bucket itself) and fail if it was not successful
+    ///
+    /// The content of an upload is streamed, so it is not part of the signature.
+    async fn send(&self, method: Method, key: Option<&str>, query: &[(&str, &str)], upload: Option<ArtifactContent>) -> Result<reqwest::Response> {
+        let endpoint = self.config.endpoint();
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(anyhow!("Remote store endpoint has no host: {}", endpoint)),
+        };
+
+        let uri = canonical_uri(self.config.bucket(), key);
+        let query = canonical_query(query);
+        let payload_hash = match upload {
+            Some(_) => String::from(UNSIGNED_PAYLOAD),
+            None => hex_sha256(&[]),
+        };
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = authorization(
+            &self.access_key,
+            &self.secret_key,
+            self.config.region(),
+            &Request {
+                method: method.as_str(),
+                host: &host,
+                uri: &uri,
+                query: &query,
+                payload_hash: &payload_hash,
+                amz_date: &amz_date,
+            },
+        );
+
+        let url = if query.is_empty() {
+            format!("{}{}", endpoint.as_str().trim_end_matches('/'), uri)
+        } else {
+            format!("{}{}?{}", endpoint.as_str().trim_end_matches('/'), uri, query)
+        };
+        trace!("Sending {} {}", method, url);
+
+        let request = self.client
+            .request(method.clone(), url.as_str())
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(reqwest::header::AUTHORIZATION, authorization);
+
+        // S3 does not accept chunked uploads, so the length has to be sent
+        let request = match upload {
+            Some(content) => request
+                .header(reqwest::header::CONTENT_LENGTH, content.length)
+                .body(reqwest::Body::wrap_stream(ReaderStream::new(content.reader))),
+            None => request,
+        };
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| anyhow!("Sending {} {}", method, url))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let text = response.text().await.unwrap_or_default();
+            Err(anyhow!("Remote store answered {} {}: {}", method, url, status))
+                .with_context(|| anyhow!("Response: {}", text))
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<ArtifactPath>> {
+        let prefix = format!("{}/", self.prefix);
+        let mut token: Option<String> = None;
+        let mut artifacts = vec![];
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(t) = token.as_deref() {
+                query.push(("continuation-token", t));
+            }
+
+            let body = self.send(Method::GET, None, &query, None)
+                .await?
+                .text()
+                .await
+                .context("Reading list of objects")?;
+            let (keys, next) = parse_list_response(&body)
+                .with_context(|| anyhow!("Parsing list of objects: {}", body))?;
+
+            for key in keys {
+                match key.strip_prefix(&prefix) {
+                    Some(path) if !path.is_empty() && !path.ends_with('/') => {
+                        match ArtifactPath::new(PathBuf::from(path)) {
+                            Ok(ap) => artifacts.push(ap),
+                            Err(e) => warn!("Ignoring object {}: {}", key, e),
+                        }
+                    }
+                    _ => trace!("Ignoring object {}", key),
+                }
+            }
+
+            match next {
+                Some(next) => token = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(artifacts)
+    }
+}
+
+impl ArtifactStorage for S3Store {
+    fn describe(&self) -> String {
+        format!("remote store {}/{}/{}", self.config.endpoint().as_str().trim_end_matches('/'), self.config.bucket(), self.prefix)
+    }
+
+    fn artifacts(&self) -> LocalBoxFuture<'_, Result<Vec<ArtifactPath>>> {
+        self.list().boxed_local()
+    }
+
+    fn read<'a>(&'a self, path: &'a ArtifactPath) -> LocalBoxFuture<'a, Result<ArtifactContent>> {
+        async move {
+            let key = self.key(path)?;
+            let response = self.send(Method::GET, Some(&key), &[], None)
+                .await
+                .with_context(|| anyhow!("Downloading {}", key))?;
+            let length = response
+                .content_length()
+                .ok_or_else(|| anyhow!("Remote store sent no length for {}", key))?;
+            let stream = response
+                .bytes_stream()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+
+            Ok(ArtifactContent { length, reader: Box::new(StreamReader::new(stream)) })
+        }
+        .boxed_local()
+    }
+
+    fn write<'a>(&'a mut self, path: &'a ArtifactPath, content: ArtifactContent) -> LocalBoxFuture<'a, Result<()>> {
+        async move {
+            let key = self.key(path)?;
+            self.send(Method::PUT, Some(&key), &[], Some(content))
+                .await
+                .map(|_| ())
+                .with_context(|| anyhow!("Uploading {}", key))
+                .map_err(Error::from)
+        }
+        .boxed_local()
+    }
+}
+
+/// The parts of a request which are signed
+struct Request<'a> {
+    method: &'a str,
+    host: &'a str,
+    uri: &'a str,
+    query: &'a str,
+    payload_hash: &'a str,
+    amz_date: &'a str,
+}
+
+/// The value of the Authorization header for a request
+fn authorization(access_key: &str, secret_key: &str, region: &str, request: &Request) -> String {
+    let date = &request.amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        request.method,
+        request.uri,
+        request.query,
+        request.host,
+        request.payload_hash,
+        request.amz_date,
+        SIGNED_HEADERS,
+        request.payload_hash,
+    );
+
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        request.amz_date,
+        scope,
+        hex_sha256(canonical_request.as_bytes())
+    );
+
+    let key = signing_key(secret_key, date, region, "s3");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, SIGNED_HEADERS, signature
+    )
+}
+
+/// The key requests of one day are signed with
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// HMAC (RFC 2104) with SHA-256
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    format!("{:x}", sha2::Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+fn canonical_uri(bucket: &str, key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("/{}/{}", uri_encode(bucket), key.split('/').map(uri_encode).join("/")),
+        None => format!("/{}", uri_encode(bucket)),
+    }
+}
+
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    query
+        .iter()
+        .map(|(k, v)| (uri_encode(k), uri_encode(v)))
+        .sorted()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .join("&")
+}
+
+/// The keys in a ListObjectsV2 response and the token for the next page, if there is one
+fn parse_list_response(body: &str) -> Result<(Vec<String>, Option<String>)> {
+    let doc = roxmltree::Document::parse(body)?;
+    let root = doc.root_element();
+    let child_text = |node: roxmltree::Node<'_, '_>, name: &str| {
+        node.children()
+            .find(|c| c.has_tag_name(name))
+            .map(|c| c.text().unwrap_or("").to_string())
+    };
+
+    let keys = root
+        .children()
+        .filter(|c| c.has_tag_name("Contents"))
+        .filter_map(|c| child_text(c, "Key"))
+        .collect();
+
+    let next = if child_text(root, "IsTruncated").as_deref() == Some("true") {
+        child_text(root, "NextContinuationToken")
+    } else {
+        None
+    };
+
+    Ok((keys, next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test case 2
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(hex(&mac), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // From the AWS documentation of Signature Version 4
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn test_canonical_uri_and_query() {
+        assert_eq!(canonical_uri("bucket", None), "/bucket");
+        assert_eq!(canonical_uri("bucket", Some("releases/stable/foo bar+1.tar.gz")), "/bucket/releases/stable/foo%20bar%2B1.tar.gz");
+        assert_eq!(canonical_query(&[("prefix", "a/b"), ("list-type", "2")]), "list-type=2&prefix=a%2Fb");
+    }
+
+    #[test]
+    fn test_parse_list_response() {
+        let body = r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+            <IsTruncated>true</IsTruncated>
+            <Contents><Key>staging/a.tar</Key></Contents>
+            <Contents><Key>staging/b&amp;c.tar</Key></Contents>
+            <NextContinuationToken>abc=</NextContinuationToken>
+        </ListBucketResult>"#;
+        let (keys, next) = parse_list_response(body).unwrap();
+        assert_eq!(keys, vec!["staging/a.tar", "staging/b&c.tar"]);
+        assert_eq!(next.as_deref(), Some("abc="));
+
+        let (keys, next) = parse_list_response("<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>").unwrap();
+        assert!(keys.is_empty());
+        assert!(next.is_none());
+
+        assert!(parse_list_response("<Error><Code>AccessDenied</Error>").is_err());
+    }
+}
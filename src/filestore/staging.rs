@@ -9,6 +9,8 @@
 //
 
 use std::fmt::Debug;
+use std::path::Path;
+use std::path::PathBuf;
 
 use anyhow::Context;
 use anyhow::Error;
@@ -17,13 +19,26 @@ use anyhow::anyhow;
 use futures::stream::Stream;
 use indicatif::ProgressBar;
 use log::trace;
+use log::warn;
 use result_inspect::ResultInspect;
 
+use crate::config::ArtifactOwner;
+use crate::filestore::lock::StoreLock;
 use crate::filestore::path::ArtifactPath;
 use crate::filestore::path::StoreRoot;
 use crate::filestore::util::FileStoreImpl;
 
-pub struct StagingStore(pub(in crate::filestore) FileStoreImpl);
+/// The name of the journal file in the root of a staging store
+///
+/// The journal lists the files which are currently written to the store. If it exists when the
+/// store is loaded, writing these files was interrupted and they are removed.
+pub(in crate::filestore) const JOURNAL_FILE_NAME: &str = ".butido-journal";
+
+/// The staging store
+///
+/// Unless it was loaded read-only, the store is locked while this object exists, so that only one
+/// butido process at a time writes to it.
+pub struct StagingStore(pub(in crate::filestore) FileStoreImpl, Vec<PathBuf>, Option<StoreLock>, ArtifactOwner);
 
 impl Debug for StagingStore {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
@@ -32,30 +47,78 @@ impl Debug for StagingStore {
 }
 
 impl StagingStore {
+    /// Lock and load the staging store
+    ///
+    /// Fails if the store is locked by another butido process.
     pub fn load(root: StoreRoot, progress: &ProgressBar) -> Result<Self> {
-        FileStoreImpl::load(root, progress).map(StagingStore)
+        let lock = StoreLock::acquire(root.path())?;
+        recover_from_journal(root.path())?;
+        FileStoreImpl::load(root, progress).map(|store| StagingStore(store, Vec::new(), Some(lock), ArtifactOwner::default()))
+    }
+
+    /// Load the staging store for reading, without locking it
+    ///
+    /// This works while another butido process writes to the store. The files which are currently
+    /// written (or whose writing was interrupted) are not loaded.
+    pub fn load_read_only(root: StoreRoot, progress: &ProgressBar) -> Result<Self> {
+        let incomplete = read_journal(root.path())?;
+        let mut store = FileStoreImpl::load(root, progress)?;
+        store.retain(|ap| !incomplete.iter().any(|p| p == ap.as_ref()));
+        Ok(StagingStore(store, Vec::new(), None, ArtifactOwner::default()))
+    }
+
+    /// Fail if the store was loaded read-only
+    pub(in crate::filestore) fn check_writable(&self) -> Result<()> {
+        if self.2.is_some() {
+            Ok(())
+        } else {
+            Err(anyhow!("Staging store {} was loaded read-only", self.0.root_path().display()))
+        }
+    }
+
+    /// Set the artifacts which are allowed to keep their setuid/setgid/sticky bits when they are
+    /// written to the store
+    pub fn with_setuid_whitelist(mut self, whitelist: Vec<PathBuf>) -> Self {
+        self.1 = whitelist;
+        self
+    }
+
+    /// Set the owner the files of artifacts are changed to when they are written to the store
+    pub fn with_artifact_owner(mut self, owner: ArtifactOwner) -> Self {
+        self.3 = owner;
+        self
     }
 
     /// Write the passed tar stream to the file store
     ///
+    /// If `subdir` is set, the files are written to this directory (relative to the root of the
+    /// store).
+    ///
     /// # Returns
     ///
     /// Returns a list of Artifacts that were written from the stream
-    pub async fn write_files_from_tar_stream<S>(&mut self, stream: S) -> Result<Vec<ArtifactPath>>
+    pub async fn write_files_from_tar_stream<S>(&mut self, stream: S, subdir: Option<&Path>) -> Result<Vec<ArtifactPath>>
     where
         S: Stream<Item = Result<Vec<u8>>>,
     {
         use futures::stream::TryStreamExt;
 
+        self.check_writable()?;
         let dest = self.0.root_path();
         stream
             .try_concat()
             .await
             .and_then(|bytes| {
+                let destinations = crate::filestore::path::archive_destinations(tar::Archive::new(&bytes[..]), subdir)
+                    .context("Listing files in TAR")?;
+                write_journal(dest.path(), &destinations)?;
+
                 trace!("Unpacking archive to {}", dest.display());
-                dest.unpack_archive_here(tar::Archive::new(&bytes[..]))
-                    .context("Unpacking TAR")
-                    .map_err(Error::from)
+                let written = dest.unpack_archive_here(tar::Archive::new(&bytes[..]), &self.1, self.3, subdir)
+                    .context("Unpacking TAR")?;
+
+                remove_journal(dest.path())?;
+                Ok(written)
             })
             .context("Concatenating the output bytestream")?
             .into_iter()
@@ -84,4 +147,97 @@ impl StagingStore {
     pub fn get(&self, p: &ArtifactPath) -> Option<&ArtifactPath> {
         self.0.get(p)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ArtifactPath> {
+        self.0.iter()
+    }
+}
+
+/// Record that the files at `pathes` (relative to `root`) are about to be written
+fn write_journal(root: &Path, pathes: &[PathBuf]) -> Result<()> {
+    use std::io::Write;
+
+    let journal_path = root.join(JOURNAL_FILE_NAME);
+    let content = serde_json::to_vec(pathes).context("Serializing journal")?;
+    let mut file = std::fs::File::create(&journal_path)
+        .with_context(|| anyhow!("Creating journal {}", journal_path.display()))?;
+    file.write_all(&content)
+        .and_then(|_| file.sync_all())
+        .with_context(|| anyhow!("Writing journal {}", journal_path.display()))
+        .map_err(Error::from)
+}
+
+/// Record that all files from the journal were written completely
+fn remove_journal(root: &Path) -> Result<()> {
+    let journal_path = root.join(JOURNAL_FILE_NAME);
+    std::fs::remove_file(&journal_path)
+        .with_context(|| anyhow!("Removing journal {}", journal_path.display()))
+        .map_err(Error::from)
+}
+
+/// The files (relative to `root`) which are currently written to the store in `root`, or whose
+/// writing was interrupted
+///
+/// Fails if the journal contains a path outside of the store.
+fn read_journal(root: &Path) -> Result<Vec<PathBuf>> {
+    let journal_path = root.join(JOURNAL_FILE_NAME);
+    if !journal_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read(&journal_path)
+        .with_context(|| anyhow!("Reading journal {}", journal_path.display()))?;
+
+    // If the journal itself was not written completely, no file was written yet
+    let pathes = serde_json::from_slice::<Vec<PathBuf>>(&content).unwrap_or_default();
+    let outside = |path: &PathBuf| {
+        path.is_absolute() || path.components().any(|comp| !matches!(comp, std::path::Component::Normal(_) | std::path::Component::CurDir))
+    };
+    if let Some(path) = pathes.iter().find(|p| outside(p)) {
+        return Err(anyhow!("Journal {} contains path outside of the store: {}", journal_path.display(), path.display()));
+    }
+
+    Ok(pathes)
+}
+
+/// Remove the files of an interrupted write to the store in `root`, if there was one
+fn recover_from_journal(root: &Path) -> Result<()> {
+    if !root.join(JOURNAL_FILE_NAME).exists() {
+        return Ok(());
+    }
+
+    for path in read_journal(root)? {
+        let path = root.join(path);
+        if path.is_file() {
+            warn!("Removing {}, writing it to the staging store was interrupted", path.display());
+            std::fs::remove_file(&path).with_context(|| anyhow!("Removing {}", path.display()))?;
+        }
+    }
+
+    remove_journal(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_with_path_outside_of_store_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("butido-test-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        write_journal(&dir, &[PathBuf::from("ok/file"), PathBuf::from("../file")]).unwrap();
+        let err = recover_from_journal(&dir).unwrap_err();
+        assert!(err.to_string().contains("outside of the store"), "{}", err);
+
+        write_journal(&dir, &[PathBuf::from("/etc/passwd")]).unwrap();
+        assert!(recover_from_journal(&dir).is_err());
+
+        write_journal(&dir, &[PathBuf::from("ok/file")]).unwrap();
+        assert_eq!(read_journal(&dir).unwrap(), vec![PathBuf::from("ok/file")]);
+        recover_from_journal(&dir).unwrap();
+        assert!(!dir.join(JOURNAL_FILE_NAME).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,108 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The interface all places artifacts can be stored in have in common
+
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use futures::future::FutureExt;
+use futures::future::LocalBoxFuture;
+use tokio::io::AsyncRead;
+
+use crate::filestore::path::ArtifactPath;
+use crate::filestore::ReleaseStore;
+use crate::filestore::StagingStore;
+use crate::filestore::util::FileStoreImpl;
+
+/// The content of an artifact
+///
+/// Artifacts can be several gigabytes big, so they are streamed instead of being read into memory.
+pub struct ArtifactContent {
+    /// The size of the artifact in bytes
+    pub length: u64,
+    pub reader: Box<dyn AsyncRead + Send + Sync + Unpin>,
+}
+
+/// A place artifacts are stored in
+///
+/// This is implemented by the local stores as well as by the remote object storage, so that
+/// artifacts can be copied between any of them.
+pub trait ArtifactStorage {
+    /// A human readable description of the storage, for messages
+    fn describe(&self) -> String;
+
+    /// List the artifacts in the storage
+    fn artifacts(&self) -> LocalBoxFuture<'_, Result<Vec<ArtifactPath>>>;
+
+    /// Open an artifact for reading
+    fn read<'a>(&'a self, path: &'a ArtifactPath) -> LocalBoxFuture<'a, Result<ArtifactContent>>;
+
+    /// Write an artifact, replacing it if it exists already
+    fn write<'a>(&'a mut self, path: &'a ArtifactPath, content: ArtifactContent) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+impl ArtifactStorage for StagingStore {
+    fn describe(&self) -> String {
+        format!("staging store {}", self.root_path().display())
+    }
+
+    fn artifacts(&self) -> LocalBoxFuture<'_, Result<Vec<ArtifactPath>>> {
+        futures::future::ready(Ok(self.iter().cloned().collect())).boxed_local()
+    }
+
+    fn read<'a>(&'a self, path: &'a ArtifactPath) -> LocalBoxFuture<'a, Result<ArtifactContent>> {
+        read_local(&self.0, path).boxed_local()
+    }
+
+    fn write<'a>(&'a mut self, path: &'a ArtifactPath, mut content: ArtifactContent) -> LocalBoxFuture<'a, Result<()>> {
+        async move {
+            self.check_writable()?;
+            self.0.write(path, &mut content.reader).await
+        }
+        .boxed_local()
+    }
+}
+
+impl ArtifactStorage for ReleaseStore {
+    fn describe(&self) -> String {
+        format!("release store {}", self.root_path().display())
+    }
+
+    fn artifacts(&self) -> LocalBoxFuture<'_, Result<Vec<ArtifactPath>>> {
+        futures::future::ready(Ok(self.iter().cloned().collect())).boxed_local()
+    }
+
+    fn read<'a>(&'a self, path: &'a ArtifactPath) -> LocalBoxFuture<'a, Result<ArtifactContent>> {
+        read_local(&self.0, path).boxed_local()
+    }
+
+    fn write<'a>(&'a mut self, path: &'a ArtifactPath, mut content: ArtifactContent) -> LocalBoxFuture<'a, Result<()>> {
+        async move { self.0.write(path, &mut content.reader).await }.boxed_local()
+    }
+}
+
+async fn read_local(store: &FileStoreImpl, path: &ArtifactPath) -> Result<ArtifactContent> {
+    let full = store
+        .root_path()
+        .join(path)?
+        .ok_or_else(|| anyhow!("Artifact {} does not exist in {}", path.display(), store.root_path().display()))?;
+
+    let file = tokio::fs::File::open(full.joined())
+        .await
+        .with_context(|| anyhow!("Opening {}", full.display()))?;
+    let length = file
+        .metadata()
+        .await
+        .with_context(|| anyhow!("Getting metadata of {}", full.display()))?
+        .len();
+
+    Ok(ArtifactContent { length, reader: Box::new(file) })
+}
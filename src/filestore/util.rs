@@ -13,8 +13,11 @@
 
 use std::collections::HashSet;
 
+use anyhow::Context;
 use anyhow::Result;
+use anyhow::anyhow;
 use indicatif::ProgressBar;
+use tokio::io::AsyncRead;
 
 use crate::filestore::path::ArtifactPath;
 use crate::filestore::path::StoreRoot;
@@ -36,12 +39,12 @@ impl FileStoreImpl {
     /// Loads the passed path recursively
     pub fn load(root_path: StoreRoot, progress: &ProgressBar) -> Result<Self> {
         let store = root_path
-            .find_artifacts_recursive()
-            .inspect(|path| {
+            .find_artifacts_recursive(|path| {
                 log::trace!("Found artifact path: {:?}", path);
                 progress.tick();
-            })
-            .collect::<Result<HashSet<ArtifactPath>>>()?;
+            })?
+            .into_iter()
+            .collect::<HashSet<ArtifactPath>>();
 
         Ok(FileStoreImpl { root_path, store })
     }
@@ -50,6 +53,51 @@ impl FileStoreImpl {
         self.store.get(artifact_path)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &ArtifactPath> {
+        self.store.iter()
+    }
+
+    /// Remove the artifacts for which `f` returns false from the loaded artifacts, without
+    /// touching the files
+    pub(in crate::filestore) fn retain<F: FnMut(&ArtifactPath) -> bool>(&mut self, f: F) {
+        self.store.retain(f)
+    }
+
+    /// Write an artifact to the store
+    ///
+    /// The content is streamed to a temporary file first, so that the artifact is either complete
+    /// or missing.
+    pub(in crate::filestore) async fn write<R>(&mut self, artifact_path: &ArtifactPath, content: &mut R) -> Result<()>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let path = self.root_path.path().join(artifact_path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| anyhow!("Creating directory {}", parent.display()))?;
+        }
+
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".part");
+        let written = async {
+            let mut file = tokio::fs::File::create(&tmp).await?;
+            tokio::io::copy(content, &mut file).await?;
+            file.sync_all().await
+        };
+        if let Err(e) = written.await {
+            // A partially written file would be loaded as an artifact with the store
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e).with_context(|| anyhow!("Writing {}", path.display()));
+        }
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .with_context(|| anyhow!("Moving {} into place", path.display()))?;
+
+        self.load_from_path(artifact_path);
+        Ok(())
+    }
+
     pub(in crate::filestore) fn load_from_path<'a>(
         &mut self,
         artifact_path: &'a ArtifactPath,
@@ -0,0 +1,181 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::filestore::ArtifactPath;
+use crate::filestore::ReleaseStore;
+use crate::filestore::StagingStore;
+use crate::package::HashType;
+use crate::package::HashValue;
+use crate::package::Package;
+use crate::package::Script;
+use crate::util::EnvironmentVariableName;
+
+/// The content-addressed key of the inputs of a build
+///
+/// Two builds with the same cache key are expected to produce the same artifacts, so the
+/// artifacts of a job are only reused if its cache key matches the key of the job that would be
+/// built.
+#[derive(parse_display::Display, Clone, Debug, Eq, PartialEq, Hash)]
+#[display("{0}")]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    /// Compute the cache key of a build of `package`
+    ///
+    /// The key covers the name and version of the package, the rendered script, the environment
+    /// of the build, the hashes of the sources, the hashes of the artifacts of the dependencies
+    /// and the digest of the image the build runs in.
+    /// The order of the environment variables and of the dependency hashes does not matter.
+    /// If the package declares the environment variables its build is sensitive to, only these
+    /// are part of the key.
+    pub fn new<'a, E>(
+        package: &Package,
+        script: &Script,
+        env: E,
+        dependency_hashes: &[HashValue],
+        image_digest: Option<&str>,
+    ) -> CacheKey
+    where
+        E: IntoIterator<Item = (&'a EnvironmentVariableName, &'a String)>,
+    {
+        let sensitivity = package.env_sensitivity().as_ref();
+        let env = env
+            .into_iter()
+            .filter(|(name, _)| sensitivity.map(|s| s.contains(*name)).unwrap_or(true))
+            .map(|(name, value)| format!("{}={}", name, value))
+            .sorted()
+            .dedup()
+            .collect::<Vec<_>>();
+
+        let sources = package
+            .sources()
+            .iter()
+            .flat_map(|(name, source)| {
+                source.hashes().map(move |hash| format!("{}={}:{}", name, hash.hashtype(), hash.value()))
+            })
+            .sorted()
+            .collect::<Vec<_>>();
+
+        let dependencies = dependency_hashes
+            .iter()
+            .map(ToString::to_string)
+            .sorted()
+            .dedup()
+            .collect::<Vec<_>>();
+
+        let mut hasher = blake3::Hasher::new();
+        let mut section = |name: &str, values: &[String]| {
+            // Every value is prefixed with its length, so that the boundaries of the values are
+            // part of the key
+            for value in std::iter::once(name).chain(values.iter().map(String::as_str)) {
+                hasher.update(&(value.len() as u64).to_le_bytes());
+                hasher.update(value.as_bytes());
+            }
+        };
+
+        section("package", &[package.name().to_string(), package.version().to_string()]);
+        section("script", &[script.as_ref().to_string()]);
+        section("env", &env);
+        section("sources", &sources);
+        section("dependencies", &dependencies);
+        section("image", &image_digest.map(String::from).into_iter().collect::<Vec<_>>());
+
+        CacheKey(hasher.finalize().to_hex().to_string())
+    }
+}
+
+impl AsRef<str> for CacheKey {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+/// The files of the artifacts of the dependencies of a build, which are hashed for its cache key
+/// with `hash_artifacts()`
+///
+/// The artifacts are searched in the staging store first, then in the release stores.
+pub fn locate_artifacts(
+    artifacts: &[ArtifactPath],
+    staging_store: &StagingStore,
+    release_stores: &[Arc<ReleaseStore>],
+) -> Result<Vec<PathBuf>> {
+    artifacts
+        .iter()
+        .map(|art| crate::endpoint::locate_artifact(art, staging_store, release_stores).map(|p| p.joined()))
+        .collect()
+}
+
+/// Hash the contents of the files from `locate_artifacts()`
+///
+/// The files are read on a thread where blocking is allowed, so the staging store does not have to
+/// be locked while they are hashed.
+pub async fn hash_artifacts(paths: Vec<PathBuf>) -> Result<Vec<HashValue>> {
+    tokio::task::spawn_blocking(move || paths.iter().map(|path| HashType::Blake3.hash_file(path)).collect())
+        .await
+        .context("Hashing artifacts")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::package::tests::package;
+
+    fn script(s: &str) -> Script {
+        Script::from(String::from(s))
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(EnvironmentVariableName, String)> {
+        vars.iter()
+            .map(|(k, v)| (EnvironmentVariableName::from(*k), String::from(*v)))
+            .collect()
+    }
+
+    fn key(pkg: &Package, script: &Script, env: &[(EnvironmentVariableName, String)], deps: &[HashValue], digest: Option<&str>) -> CacheKey {
+        CacheKey::new(pkg, script, env.iter().map(|(k, v)| (k, v)), deps, digest)
+    }
+
+    #[test]
+    fn test_order_does_not_matter() {
+        let pkg = package("a", "1", "https://rust-lang.org", "123");
+        let s = script("#!/bin/bash\nmake");
+        let deps = vec![HashValue::from(String::from("aa")), HashValue::from(String::from("bb"))];
+        let deps_reversed = deps.iter().cloned().rev().collect::<Vec<_>>();
+
+        assert_eq!(
+            key(&pkg, &s, &env(&[("A", "1"), ("B", "2")]), &deps, Some("sha256:00")),
+            key(&pkg, &s, &env(&[("B", "2"), ("A", "1")]), &deps_reversed, Some("sha256:00")),
+        );
+    }
+
+    #[test]
+    fn test_every_input_changes_key() {
+        let pkg = package("a", "1", "https://rust-lang.org", "123");
+        let s = script("#!/bin/bash\nmake");
+        let e = env(&[("A", "1")]);
+        let deps = vec![HashValue::from(String::from("aa"))];
+        let base = key(&pkg, &s, &e, &deps, Some("sha256:00"));
+
+        let other_source = package("a", "1", "https://rust-lang.org", "456");
+        assert_ne!(base, key(&other_source, &s, &e, &deps, Some("sha256:00")));
+        assert_ne!(base, key(&pkg, &script("#!/bin/bash\nmake install"), &e, &deps, Some("sha256:00")));
+        assert_ne!(base, key(&pkg, &s, &env(&[("A", "2")]), &deps, Some("sha256:00")));
+        assert_ne!(base, key(&pkg, &s, &e, &[HashValue::from(String::from("ab"))], Some("sha256:00")));
+        assert_ne!(base, key(&pkg, &s, &e, &deps, Some("sha256:01")));
+        assert_ne!(base, key(&pkg, &s, &e, &deps, None));
+    }
+}
@@ -8,16 +8,22 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use anyhow::Result;
+use anyhow::anyhow;
 use daggy::Dag as DaggyDag;
+use daggy::NodeIndex;
 use daggy::Walker;
 use getset::Getters;
+use itertools::Itertools;
 use uuid::Uuid;
 
 use crate::job::Job;
 use crate::job::JobResource;
 use crate::package::Package;
+use crate::package::PackageName;
 use crate::package::PhaseName;
 use crate::package::Shebang;
+use crate::util::Architecture;
 use crate::util::docker::ImageName;
 
 #[derive(Debug, Getters)]
@@ -71,6 +77,122 @@ impl Dag {
             })
     }
 
+    /// The part of the DAG with the job for the package `name` as root, i.e. that job and all jobs
+    /// it (transitively) depends on
+    pub fn up_to(&self, name: &PackageName) -> Result<Dag> {
+        let root = self.find_package(name)?;
+        let mut nodes = self.dependencies_of(root);
+        nodes.push(root);
+        Ok(self.sub_dag(&nodes))
+    }
+
+    /// The DAG with only the job for the package `name`
+    ///
+    /// The jobs this job (transitively) depends on are returned as well, because they are not part
+    /// of the returned DAG and their artifacts have to be provided otherwise.
+    pub fn only(&self, name: &PackageName) -> Result<(Dag, Vec<Job>)> {
+        let root = self.find_package(name)?;
+        let dependencies = self.dependencies_of(root)
+            .into_iter()
+            .filter_map(|idx| self.dag.graph().node_weight(idx))
+            .cloned()
+            .collect();
+
+        Ok((self.sub_dag(&[root]), dependencies))
+    }
+
+    /// Find the node of the job for the package `name`
+    ///
+    /// Fails if there is no such job or if there are jobs for several versions of the package.
+    fn find_package(&self, name: &PackageName) -> Result<NodeIndex> {
+        let mut nodes = self.dag
+            .graph()
+            .node_indices()
+            .filter(|idx| self.dag.graph().node_weight(*idx).map(|job| job.package().name() == name).unwrap_or(false));
+
+        match (nodes.next(), nodes.next()) {
+            (Some(idx), None) => Ok(idx),
+            (None, _) => Err(anyhow!("Package {} is not part of the tree", name)),
+            (Some(_), Some(_)) => Err(anyhow!("Package {} appears in several versions in the tree", name)),
+        }
+    }
+
+    /// The DAG with a copy of every job for each of the `architectures`
+    ///
+    /// The copies for one architecture only depend on each other. If no architectures are passed,
+    /// the DAG is returned unchanged.
+    pub fn for_architectures(self, architectures: &[Architecture]) -> Dag {
+        if architectures.is_empty() {
+            return self;
+        }
+
+        let mut dag = DaggyDag::new();
+        for architecture in architectures {
+            let new_indices = self.dag
+                .graph()
+                .node_indices()
+                .filter_map(|idx| {
+                    let job = self.dag.graph().node_weight(idx)?;
+                    Some((idx, dag.add_node(job.for_architecture(architecture.clone()))))
+                })
+                .collect::<Vec<(NodeIndex, NodeIndex)>>();
+
+            for edge in self.dag.graph().edge_indices() {
+                let endpoints = self.dag.graph().edge_endpoints(edge);
+                let weight = self.dag.edge_weight(edge);
+                if let (Some((old_parent, old_child)), Some(weight)) = (endpoints, weight) {
+                    let new_parent = new_indices.iter().find(|(old, _)| *old == old_parent).map(|(_, new)| *new);
+                    let new_child = new_indices.iter().find(|(old, _)| *old == old_child).map(|(_, new)| *new);
+                    if let (Some(new_parent), Some(new_child)) = (new_parent, new_child) {
+                        // The edges are taken from a DAG, so they cannot form a cycle
+                        let _ = dag.add_edge(new_parent, new_child, *weight);
+                    }
+                }
+            }
+        }
+
+        Dag { dag }
+    }
+
+    /// The nodes the node `idx` (transitively) depends on, without `idx` itself
+    fn dependencies_of(&self, idx: NodeIndex) -> Vec<NodeIndex> {
+        let mut found = Vec::new();
+        let mut pending = vec![idx];
+        while let Some(next) = pending.pop() {
+            for (_, child) in self.dag.children(next).iter(&self.dag) {
+                if !found.contains(&child) {
+                    found.push(child);
+                    pending.push(child);
+                }
+            }
+        }
+        found
+    }
+
+    /// Build a DAG of the nodes `nodes` and the edges between them
+    fn sub_dag(&self, nodes: &[NodeIndex]) -> Dag {
+        let mut dag = DaggyDag::new();
+        let new_indices = nodes
+            .iter()
+            .unique()
+            .filter_map(|idx| {
+                let job = self.dag.graph().node_weight(*idx)?;
+                Some((*idx, dag.add_node(job.clone())))
+            })
+            .collect::<Vec<(NodeIndex, NodeIndex)>>();
+
+        for (old_parent, new_parent) in new_indices.iter() {
+            for (edge, old_child) in self.dag.children(*old_parent).iter(&self.dag) {
+                let new_child = new_indices.iter().find(|(old, _)| *old == old_child).map(|(_, new)| *new);
+                if let (Some(new_child), Some(weight)) = (new_child, self.dag.edge_weight(edge)) {
+                    // The edges are taken from a DAG, so they cannot form a cycle
+                    let _ = dag.add_edge(*new_parent, new_child, *weight);
+                }
+            }
+        }
+
+        Dag { dag }
+    }
 }
 
 #[derive(Debug)]
@@ -79,3 +201,101 @@ pub struct JobDefinition<'a> {
     pub dependencies: Vec<Uuid>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+    use crate::package::condition::ConditionData;
+    use crate::package::tests::package;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::repository::Repository;
+
+    // a 1 -> b 2 -> c 3
+    fn dag() -> Dag {
+        let mut btree = BTreeMap::new();
+
+        let mut p1 = package("a", "1", "https://rust-lang.org", "123");
+        p1.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(String::from("b =2"))));
+        btree.insert((pname("a"), pversion("1")), p1.clone());
+
+        let mut p2 = package("b", "2", "https://rust-lang.org", "124");
+        p2.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(String::from("c =3"))));
+        btree.insert((pname("b"), pversion("2")), p2);
+
+        btree.insert((pname("c"), pversion("3")), package("c", "3", "https://rust-lang.org", "125"));
+
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+            image_info: None,
+        };
+
+        let package_dag = crate::package::Dag::for_root_package(p1, &repo, None, &condition_data).unwrap();
+        Dag::from_package_dag(
+            package_dag,
+            Shebang::from(String::from("#!/bin/bash")),
+            ImageName::from(String::from("debian:bullseye")),
+            vec![],
+            vec![],
+        )
+    }
+
+    fn dependencies_of<'a>(dag: &'a Dag, name: &str) -> Vec<&'a str> {
+        let jobdef = dag.iter().find(|jobdef| jobdef.job.package().name() == &pname(name)).unwrap();
+        dag.iter()
+            .filter(|other| jobdef.dependencies.contains(other.job.uuid()))
+            .map(|other| other.job.package().name().as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_up_to() {
+        let dag = dag().up_to(&pname("b")).unwrap();
+
+        assert_eq!(dag.iter().count(), 2);
+        assert_eq!(dependencies_of(&dag, "b"), vec!["c"]);
+        assert!(dependencies_of(&dag, "c").is_empty());
+    }
+
+    #[test]
+    fn test_only() {
+        let (dag, dependencies) = dag().only(&pname("b")).unwrap();
+
+        assert_eq!(dag.iter().count(), 1);
+        assert!(dependencies_of(&dag, "b").is_empty());
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].package().name(), &pname("c"));
+    }
+
+    #[test]
+    fn test_for_architectures() {
+        let archs = vec![Architecture::from(String::from("x86_64")), Architecture::from(String::from("aarch64"))];
+        let dag = dag().for_architectures(&archs);
+
+        assert_eq!(dag.iter().count(), 6);
+        for jobdef in dag.iter() {
+            let deps = dag.iter()
+                .filter(|other| jobdef.dependencies.contains(other.job.uuid()))
+                .collect::<Vec<_>>();
+
+            let expected = match jobdef.job.package().name().as_str() {
+                "c" => 0,
+                _ => 1,
+            };
+            assert_eq!(deps.len(), expected);
+            assert!(deps.iter().all(|dep| dep.job.architecture() == jobdef.job.architecture()));
+        }
+    }
+
+    #[test]
+    fn test_unknown_package() {
+        assert!(dag().up_to(&pname("d")).is_err());
+        assert!(dag().only(&pname("d")).is_err());
+    }
+}
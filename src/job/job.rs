@@ -15,10 +15,11 @@ use crate::job::JobResource;
 use crate::package::Package;
 use crate::package::PhaseName;
 use crate::package::Shebang;
+use crate::util::Architecture;
 use crate::util::docker::ImageName;
 
 /// A prepared, but not necessarily runnable, job configuration
-#[derive(Debug, Getters)]
+#[derive(Clone, Debug, Getters)]
 pub struct Job {
     /// A unique name for the job, not necessarily human-readable
     #[getset(get = "pub")]
@@ -38,6 +39,10 @@ pub struct Job {
 
     #[getset(get = "pub")]
     resources: Vec<JobResource>,
+
+    /// The architecture the job is built for, if it is built for a specific one
+    #[getset(get = "pub")]
+    architecture: Option<Architecture>,
 }
 
 impl Job {
@@ -57,6 +62,16 @@ impl Job {
             script_shebang,
             script_phases: phases,
             resources,
+            architecture: None,
+        }
+    }
+
+    /// A copy of this job that is built for `architecture`, with a new UUID
+    pub fn for_architecture(&self, architecture: Architecture) -> Self {
+        Job {
+            uuid: Uuid::new_v4(),
+            architecture: Some(architecture),
+            ..self.clone()
         }
     }
 }
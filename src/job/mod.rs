@@ -12,6 +12,9 @@
 mod job;
 pub use job::*;
 
+mod cache_key;
+pub use cache_key::*;
+
 mod dag;
 pub use dag::*;
 
@@ -20,3 +23,6 @@ pub use resource::*;
 
 mod runnable;
 pub use runnable::*;
+
+mod secret;
+pub use secret::*;
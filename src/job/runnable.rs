@@ -8,28 +8,35 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::path::PathBuf;
+
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
+use getset::CopyGetters;
 use getset::Getters;
 use log::debug;
 use log::trace;
 use uuid::Uuid;
 
+use crate::config::BuildLimits;
 use crate::config::Configuration;
+use crate::config::SecurityProfile;
 use crate::filestore::ArtifactPath;
 use crate::job::Job;
 use crate::job::JobResource;
+use crate::job::JobSecret;
 use crate::package::Package;
 use crate::package::Script;
 use crate::package::ScriptBuilder;
 use crate::source::SourceCache;
 use crate::source::SourceEntry;
+use crate::util::Architecture;
 use crate::util::EnvironmentVariableName;
 use crate::util::docker::ImageName;
 
 /// A job configuration that can be run. All inputs are clear here.
-#[derive(Debug, Getters)]
+#[derive(Debug, Getters, CopyGetters)]
 pub struct RunnableJob {
     #[getset(get = "pub")]
     uuid: Uuid,
@@ -40,6 +47,11 @@ pub struct RunnableJob {
     #[getset(get = "pub")]
     image: ImageName,
 
+    /// The reference the container of the job is created from, pinned to a digest if the image is
+    /// pinned in the configuration
+    #[getset(get = "pub")]
+    image_reference: ImageName,
+
     #[getset(get = "pub")]
     source_cache: SourceCache,
 
@@ -48,9 +60,34 @@ pub struct RunnableJob {
 
     #[getset(get = "pub")]
     resources: Vec<JobResource>,
+
+    /// Executables which must exist in the image before the job is run
+    #[getset(get = "pub")]
+    required_executables: Vec<PathBuf>,
+
+    /// Whether the job is run without network access
+    #[getset(get_copy = "pub")]
+    hermetic: bool,
+
+    /// The resource limits for the container of the job
+    #[getset(get = "pub")]
+    limits: BuildLimits,
+
+    /// The architecture the job is built for, if it is built for a specific one
+    #[getset(get = "pub")]
+    architecture: Option<Architecture>,
+
+    /// The security settings for the container of the job from the defaults of its image
+    #[getset(get = "pub")]
+    security: SecurityProfile,
+
+    /// The secrets of the package, fetched once before the first run of the job
+    #[getset(get = "pub")]
+    secrets: Vec<JobSecret>,
 }
 
 impl RunnableJob {
+    #[allow(clippy::too_many_arguments)]
     pub fn build_from_job(
         job: &Job,
         source_cache: &SourceCache,
@@ -58,6 +95,8 @@ impl RunnableJob {
         git_author_env: Option<&(EnvironmentVariableName, String)>,
         git_commit_env: Option<&(EnvironmentVariableName, String)>,
         dependencies: Vec<ArtifactPath>,
+        secrets: Vec<JobSecret>,
+        hermetic: bool,
     ) -> Result<Self> {
         if config.containers().check_env_names() {
             debug!("Checking environment if all variables are allowed!");
@@ -109,23 +148,55 @@ impl RunnableJob {
             .collect();
 
         debug!("Building script now");
-        let script = ScriptBuilder::new(job.script_shebang()).build(
-            job.package(),
-            job.script_phases(),
-            *config.strict_script_interpolation(),
-        )?;
+        let script = ScriptBuilder::new(job.script_shebang())
+            .with_dependency_mapping(config.docker().dependency_mapping().get(job.image()))
+            .build(
+                job.package(),
+                job.script_phases(),
+                *config.strict_script_interpolation(),
+            )?;
+
+        let image_defaults = config.docker().image_defaults().get(job.image());
+        let required_executables = image_defaults
+            .map(|defaults| defaults.required_executables().clone())
+            .unwrap_or_default();
+
+        // Merged with the settings of the endpoint when the container is created
+        let security = image_defaults
+            .and_then(|defaults| defaults.security().clone())
+            .unwrap_or_default();
+
+        // The limits of the package win over the global ones
+        let limits = job.package()
+            .build()
+            .clone()
+            .unwrap_or_default()
+            .or(config.build().as_ref());
 
         Ok(RunnableJob {
             uuid: *job.uuid(),
             package: job.package().clone(),
             image: job.image().clone(),
+            image_reference: config.docker().image_reference(job.image()),
             resources,
             source_cache: source_cache.clone(),
+            required_executables,
+            hermetic,
+            limits,
+            architecture: job.architecture().clone(),
+            security,
+            secrets,
 
             script,
         })
     }
 
+    /// Give the job a new UUID, so that a retry of the job is recorded separately from the failed
+    /// run
+    pub fn renew_uuid(&mut self) {
+        self.uuid = Uuid::new_v4();
+    }
+
     pub fn package_sources(&self) -> Vec<SourceEntry> {
         self.source_cache.sources_for(self.package())
     }
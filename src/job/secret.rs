@@ -0,0 +1,194 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use getset::Getters;
+use zeroize::Zeroize;
+
+use crate::config::SecretConfig;
+use crate::package::Package;
+use crate::util::EnvironmentVariableName;
+
+/// What the values of secrets are replaced with in the log of a job
+const REDACTED: &str = "[REDACTED]";
+
+/// The value of a secret
+///
+/// The value is never printed, not even in debug output, and is overwritten when dropped.
+#[derive(Clone)]
+pub struct SecretValue(String);
+
+impl SecretValue {
+    pub fn new(value: String) -> Self {
+        SecretValue(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl Drop for SecretValue {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// A secret, fetched for a job that needs it
+///
+/// The secret is written to the container as file and, if the package says so, exported as
+/// environment variable to the script. It is not part of the environment of the job, so it is
+/// neither recorded in the database nor compared when looking for artifacts to reuse.
+#[derive(Clone, Debug, Getters)]
+pub struct JobSecret {
+    #[getset(get = "pub")]
+    name: String,
+
+    #[getset(get = "pub")]
+    env: Option<EnvironmentVariableName>,
+
+    #[getset(get = "pub")]
+    value: SecretValue,
+}
+
+impl JobSecret {
+    pub fn new(name: String, env: Option<EnvironmentVariableName>, value: SecretValue) -> Result<Self> {
+        if let Some(env) = env.as_ref() {
+            let mut chars = env.as_ref().chars();
+            let is_identifier = chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if !is_identifier {
+                return Err(anyhow!("Secret '{}' cannot be exported as '{}', not a valid variable name", name, env));
+            }
+        }
+
+        Ok(JobSecret { name, env, value })
+    }
+
+    /// The path of the file with the secret in the container
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(crate::consts::SECRETS_DIR_PATH).join(&self.name)
+    }
+}
+
+/// Fetch the secrets `package` needs from their sources in the `secrets` of the configuration
+pub async fn fetch_secrets(package: &Package, secrets: &HashMap<String, SecretConfig>) -> Result<Vec<JobSecret>> {
+    let mut fetched = Vec::with_capacity(package.secrets().len());
+    for secret in package.secrets().iter() {
+        let value = secrets
+            .get(secret.name())
+            .ok_or_else(|| anyhow!("Secret '{}' is not configured", secret.name()))?
+            .fetch()
+            .await
+            .with_context(|| anyhow!("Fetching secret '{}'", secret.name()))?;
+
+        fetched.push(JobSecret::new(secret.name().clone(), secret.env().clone(), value)?);
+    }
+
+    Ok(fetched)
+}
+
+/// The shell code which exports the secrets that have an environment variable and then runs its
+/// arguments, or None if there are no such secrets
+///
+/// The values are read from the files in the container, so that they do not show up in the
+/// command line of a process.
+pub fn export_secrets_script(secrets: &[JobSecret]) -> Option<String> {
+    let exports = secrets
+        .iter()
+        .filter_map(|secret| {
+            secret.env().as_ref().map(|env| {
+                format!("export {}=\"$(cat '{}')\"", env.as_ref(), secret.path().display())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if exports.is_empty() {
+        None
+    } else {
+        Some(format!("{} && exec \"$@\"", exports.join(" && ")))
+    }
+}
+
+/// Replace the values of `secrets` in a line of the log of a job
+///
+/// Secrets with more than one line are replaced line by line, as the log is processed line by
+/// line.
+pub fn redact_secrets(line: String, secrets: &[JobSecret]) -> String {
+    secrets
+        .iter()
+        .flat_map(|secret| secret.value().expose().lines())
+        .filter(|value| !value.is_empty())
+        .fold(line, |line, value| {
+            if line.contains(value) {
+                line.replace(value, REDACTED)
+            } else {
+                line
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(name: &str, env: Option<&str>, value: &str) -> JobSecret {
+        JobSecret::new(
+            String::from(name),
+            env.map(EnvironmentVariableName::from),
+            SecretValue::new(String::from(value)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_secret_value_is_not_printed() {
+        let s = secret("token", None, "hunter2");
+        assert!(!format!("{:?}", s).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_invalid_env_name() {
+        let r = JobSecret::new(
+            String::from("token"),
+            Some(EnvironmentVariableName::from("A=B; rm")),
+            SecretValue::new(String::from("hunter2")),
+        );
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_export_secrets_script() {
+        assert!(export_secrets_script(&[secret("cert", None, "c")]).is_none());
+
+        let script = export_secrets_script(&[secret("cert", None, "c"), secret("token", Some("TOKEN"), "t")]).unwrap();
+        assert_eq!(script, r#"export TOKEN="$(cat '/secrets/token')" && exec "$@""#);
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let secrets = [secret("token", None, "hunter2"), secret("key", None, "line1\nline2\n")];
+        assert_eq!(redact_secrets(String::from("token=hunter2"), &secrets), "token=[REDACTED]");
+        assert_eq!(redact_secrets(String::from("a line2 b"), &secrets), "a [REDACTED] b");
+        assert_eq!(redact_secrets(String::from("nothing"), &secrets), "nothing");
+    }
+}
@@ -0,0 +1,102 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Error;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::log::LogRecord;
+
+/// The format the logs of jobs are streamed in
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Colored log lines, prefixed with the job they belong to
+    #[display("text")]
+    Text,
+
+    /// Newline-delimited JSON records, one per log line
+    #[display("json")]
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+/// A log record as flat JSON object, with the fields of the record next to the timestamp, level
+/// and line
+///
+/// This is the format of the logs streamed with `LogFormat::Json` as well as of the records sent to
+/// log sinks with the "json" format, e.g. a log line of a job has the fields "job", "package",
+/// "version", "endpoint" and "stream".
+#[derive(Debug)]
+pub struct JsonLogRecord<'a>(pub &'a LogRecord);
+
+impl Serialize for JsonLogRecord<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let record = self.0;
+        let mut map = serializer.serialize_map(Some(3 + record.fields.len()))?;
+        map.serialize_entry("timestamp", &record.timestamp.to_rfc3339())?;
+        map.serialize_entry("level", &record.level.to_string())?;
+        map.serialize_entry("line", &record.message)?;
+        for (name, value) in record.fields.iter() {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl JsonLogRecord<'_> {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use uuid::Uuid;
+
+    use crate::log::LogItem;
+    use crate::log::LogStream;
+
+    #[test]
+    fn test_json_record() {
+        let job = Uuid::nil();
+        let item = LogItem::CurrentPhase(String::from("build"));
+        let record = LogRecord::for_job_log(&job, "foo", "1.0", "ep", LogStream::Stderr, &item).unwrap();
+        let json = JsonLogRecord(&record).to_json().unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["job"], "00000000-0000-0000-0000-000000000000");
+        assert_eq!(value["package"], "foo");
+        assert_eq!(value["version"], "1.0");
+        assert_eq!(value["endpoint"], "ep");
+        assert_eq!(value["stream"], "stderr");
+        assert_eq!(value["line"], "#BUTIDO:PHASE:build");
+        assert_eq!(value["level"], "notice");
+        assert!(!json.contains('\n'));
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Forwarding of the logs of jobs and the events of submits to the `log_sinks` from the
+//! configuration
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use log::warn;
+
+use crate::config::LogSinkConfig;
+use crate::config::LogSinkFormat;
+use crate::config::LogSinkKind;
+use crate::log::JsonLogRecord;
+use crate::log::LogRecord;
+use crate::log::LogSink;
+
+/// The default socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The default socket of the native protocol of the systemd journal
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// How long connecting to and sending to a TCP collector may take
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many records may wait for being forwarded before further records are dropped
+const QUEUE_SIZE: usize = 1024;
+
+/// The log sinks of a submit
+///
+/// The records are sent by a dedicated thread, so that a slow or unreachable collector never
+/// blocks the jobs. If that thread cannot keep up, further records are dropped and counted.
+///
+/// A sink that fails once is not used anymore for the rest of the submit, so that an unreachable
+/// collector does not flood the output with warnings.
+pub struct LogSinks {
+    configs: Vec<LogSinkConfig>,
+    sender: Option<SyncSender<LogRecord>>,
+    forwarder: Option<JoinHandle<()>>,
+    dropped: AtomicUsize,
+}
+
+struct ConfiguredSink {
+    config: LogSinkConfig,
+    sink: Box<dyn LogSink + Send>,
+    failed: bool,
+}
+
+impl LogSinks {
+    /// Create the sinks and start the thread forwarding to them
+    ///
+    /// The connections are only opened when the first record is sent.
+    pub fn new(configs: &[LogSinkConfig]) -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|h| h.trim().to_string())
+            .unwrap_or_default();
+
+        let sinks = configs
+            .iter()
+            .map(|config| {
+                let sink: Box<dyn LogSink + Send> = match config.kind() {
+                    LogSinkKind::Syslog => Box::new(SyslogSink {
+                        socket: config.socket().clone().unwrap_or_else(|| PathBuf::from(SYSLOG_SOCKET)),
+                        facility: config.facility(),
+                    }),
+                    LogSinkKind::Journald => Box::new(JournaldSink {
+                        socket: config.socket().clone().unwrap_or_else(|| PathBuf::from(JOURNALD_SOCKET)),
+                    }),
+                    LogSinkKind::Tcp => Box::new(TcpSink {
+                        encoder: NetworkEncoder::new(config, hostname.clone()),
+                        address: config.address().clone().unwrap_or_default(),
+                        stream: None,
+                    }),
+                    LogSinkKind::Udp => Box::new(UdpSink {
+                        encoder: NetworkEncoder::new(config, hostname.clone()),
+                        address: config.address().clone().unwrap_or_default(),
+                        socket: None,
+                    }),
+                };
+
+                ConfiguredSink { config: config.clone(), sink, failed: false }
+            })
+            .collect::<Vec<_>>();
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        let forwarder = std::thread::spawn(move || forward_records(sinks, receiver));
+
+        LogSinks {
+            configs: configs.to_vec(),
+            sender: Some(sender),
+            forwarder: Some(forwarder),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue `record` for all sinks which accept it, without waiting for it to be sent
+    pub fn forward(&self, record: &LogRecord) {
+        if !self.configs.iter().any(|c| c.accepts(record.level, |name| record.field(name))) {
+            return
+        }
+
+        let queued = self.sender
+            .as_ref()
+            .map(|sender| sender.try_send(record.clone()).is_ok())
+            .unwrap_or(false);
+
+        if !queued {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for LogSinks {
+    /// Wait until the queued records are sent
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(forwarder) = self.forwarder.take() {
+            if forwarder.join().is_err() {
+                warn!("Forwarding logs to the log sinks panicked");
+            }
+        }
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("Dropped {} records, because the log sinks could not keep up", dropped);
+        }
+    }
+}
+
+/// Send the records to all sinks which accept them until all senders are gone, failures are only
+/// logged
+fn forward_records(mut sinks: Vec<ConfiguredSink>, receiver: Receiver<LogRecord>) {
+    for record in receiver.iter() {
+        for s in sinks.iter_mut().filter(|s| !s.failed) {
+            if !s.config.accepts(record.level, |name| record.field(name)) {
+                continue
+            }
+
+            if let Err(e) = s.sink.send(&record) {
+                warn!("Forwarding logs to {} sink failed, not forwarding to it anymore: {:#}", s.config.kind(), e);
+                s.failed = true;
+            }
+        }
+    }
+}
+
+/// The local syslog daemon, RFC 3164 messages on a unix datagram socket
+struct SyslogSink {
+    socket: PathBuf,
+    facility: u8,
+}
+
+impl LogSink for SyslogSink {
+    fn send(&mut self, record: &LogRecord) -> Result<()> {
+        UnixDatagram::unbound()?
+            .send_to(record.to_rfc3164(self.facility).as_bytes(), &self.socket)
+            .with_context(|| anyhow!("Sending to {}", self.socket.display()))
+            .map(|_| ())
+    }
+}
+
+/// The systemd journal, via its native protocol on a unix datagram socket
+struct JournaldSink {
+    socket: PathBuf,
+}
+
+impl LogSink for JournaldSink {
+    fn send(&mut self, record: &LogRecord) -> Result<()> {
+        UnixDatagram::unbound()?
+            .send_to(&record.to_journald(), &self.socket)
+            .with_context(|| anyhow!("Sending to {}", self.socket.display()))
+            .map(|_| ())
+    }
+}
+
+/// Encodes the records for TCP and UDP collectors
+struct NetworkEncoder {
+    format: LogSinkFormat,
+    facility: u8,
+    hostname: String,
+}
+
+impl NetworkEncoder {
+    fn new(config: &LogSinkConfig, hostname: String) -> Self {
+        NetworkEncoder {
+            format: config.format(),
+            facility: config.facility(),
+            hostname,
+        }
+    }
+
+    fn encode(&self, record: &LogRecord) -> Result<String> {
+        match self.format {
+            LogSinkFormat::Syslog => Ok(record.to_rfc5424(self.facility, &self.hostname)),
+            LogSinkFormat::Json => JsonLogRecord(record).to_json(),
+        }
+    }
+}
+
+/// A collector listening on a TCP port, one record per line
+struct TcpSink {
+    encoder: NetworkEncoder,
+    address: String,
+    stream: Option<TcpStream>,
+}
+
+impl LogSink for TcpSink {
+    fn send(&mut self, record: &LogRecord) -> Result<()> {
+        let mut line = self.encoder.encode(record)?;
+        line.push('\n');
+
+        if self.stream.is_none() {
+            let stream = resolve(&self.address)?
+                .into_iter()
+                .find_map(|addr| TcpStream::connect_timeout(&addr, TCP_TIMEOUT).ok())
+                .ok_or_else(|| anyhow!("Cannot connect to {}", self.address))?;
+            stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+            self.stream = Some(stream);
+        }
+
+        match self.stream.as_mut() {
+            Some(stream) => stream
+                .write_all(line.as_bytes())
+                .with_context(|| anyhow!("Sending to {}", self.address)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A collector listening on a UDP port, one record per datagram
+struct UdpSink {
+    encoder: NetworkEncoder,
+    address: String,
+    socket: Option<(UdpSocket, SocketAddr)>,
+}
+
+impl LogSink for UdpSink {
+    fn send(&mut self, record: &LogRecord) -> Result<()> {
+        let message = self.encoder.encode(record)?;
+
+        if self.socket.is_none() {
+            let addr = resolve(&self.address)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("{} does not resolve to an address", self.address))?;
+            let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            self.socket = Some((UdpSocket::bind(local)?, addr));
+        }
+
+        match self.socket.as_ref() {
+            Some((socket, addr)) => socket
+                .send_to(message.as_bytes(), addr)
+                .with_context(|| anyhow!("Sending to {}", self.address))
+                .map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn resolve(address: &str) -> Result<Vec<SocketAddr>> {
+    address
+        .to_socket_addrs()
+        .with_context(|| anyhow!("Resolving {}", address))
+        .map(Iterator::collect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use uuid::Uuid;
+
+    use crate::log::LogItem;
+    use crate::log::LogStream;
+
+    fn udp_sinks(listener: &UdpSocket, extra: serde_json::Value) -> LogSinks {
+        let mut config = serde_json::json!({
+            "kind": "udp",
+            "address": listener.local_addr().unwrap().to_string(),
+            "format": "json",
+        });
+        config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        let config: LogSinkConfig = serde_json::from_value(config).unwrap();
+        LogSinks::new(&[config])
+    }
+
+    fn record(package: &str, item: LogItem) -> LogRecord {
+        LogRecord::for_job_log(&Uuid::nil(), package, "1.0", "ep", LogStream::Stdout, &item).unwrap()
+    }
+
+    fn receive(listener: &UdpSocket) -> Option<serde_json::Value> {
+        let mut buf = [0; 4096];
+        let n = listener.recv(&mut buf).ok()?;
+        serde_json::from_slice(&buf[..n]).ok()
+    }
+
+    #[test]
+    fn test_udp_sink() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let sinks = udp_sinks(&listener, serde_json::json!({}));
+
+        sinks.forward(&record("foo", LogItem::Line(b"hello".to_vec())));
+        let value = receive(&listener).unwrap();
+        assert_eq!(value["line"], "hello");
+        assert_eq!(value["package"], "foo");
+        assert_eq!(value["level"], "info");
+    }
+
+    #[test]
+    fn test_filtered_records_are_not_sent() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let sinks = udp_sinks(&listener, serde_json::json!({
+            "level": "notice",
+            "fields": { "package": ["foo"] },
+        }));
+
+        sinks.forward(&record("foo", LogItem::Line(b"hello".to_vec())));
+        sinks.forward(&record("bar", LogItem::CurrentPhase(String::from("build"))));
+        sinks.forward(&record("foo", LogItem::CurrentPhase(String::from("build"))));
+        drop(sinks);
+
+        let value = receive(&listener).unwrap();
+        assert_eq!(value["package"], "foo");
+        assert_eq!(value["line"], "#BUTIDO:PHASE:build");
+        assert!(receive(&listener).is_none());
+    }
+}
@@ -11,6 +11,19 @@
 use anyhow::Error;
 use anyhow::Result;
 use colored::Colorize;
+use serde::Serialize;
+
+/// The output stream of a container a log line was printed to
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum LogStream {
+    #[serde(rename = "stdout")]
+    #[display("stdout")]
+    Stdout,
+
+    #[serde(rename = "stderr")]
+    #[display("stderr")]
+    Stderr,
+}
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum LogItem {
@@ -11,10 +11,17 @@
 mod parser;
 pub use parser::*;
 
+mod format;
+pub use format::*;
+
+mod forward;
+pub use forward::*;
+
 mod item;
 pub use item::*;
 
 mod sink;
 pub use sink::*;
 
-mod util;
+mod prefix;
+pub use prefix::*;
@@ -8,32 +8,186 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::VecDeque;
 use std::result::Result as RResult;
 use std::str::FromStr;
 
 use anyhow::Error;
 use anyhow::Result;
-use futures::AsyncBufReadExt;
 use futures::Stream;
 use futures::StreamExt;
-use futures::TryStreamExt;
 use pom::parser::Parser as PomParser;
 use shiplift::tty::TtyChunk;
 
-use crate::log::util::*;
 use crate::log::LogItem;
+use crate::log::LogStream;
 
 type IoResult<T> = RResult<T, futures::io::Error>;
 
-pub fn buffer_stream_to_line_stream<S>(stream: S) -> impl Stream<Item = IoResult<String>>
+/// Marker that is put into log lines which were altered before they were stored
+const SANITIZED_MARKER: &str = "[butido]";
+
+/// Number of bytes that are sampled from a line that looks like binary data
+const BINARY_SAMPLE_LENGTH: usize = 32;
+
+/// Split the output stream of a container into lines
+///
+/// The lines printed to stdout and stderr are assembled separately, so that every line can be
+/// attributed to the stream it was printed to.
+///
+/// Lines are never longer than `max_line_length` bytes and always valid UTF-8, see
+/// [sanitize_log_line].
+pub fn buffer_stream_to_line_stream<S>(stream: S, max_line_length: usize) -> impl Stream<Item = IoResult<(LogStream, String)>>
 where
     S: Stream<Item = shiplift::Result<TtyChunk>> + std::marker::Unpin,
 {
-    stream
-        .map(|r| r.map(TtyChunkBuf::from))
-        .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
-        .into_async_read()
-        .lines()
+    let state = (
+        Some(stream),
+        LineBuffer::new(max_line_length),
+        LineBuffer::new(max_line_length),
+        VecDeque::new(),
+    );
+
+    futures::stream::try_unfold(state, |(mut stream, mut stdout, mut stderr, mut lines)| async move {
+        loop {
+            if let Some(line) = lines.pop_front() {
+                return Ok(Some((line, (stream, stdout, stderr, lines))));
+            }
+
+            let chunk = match stream.as_mut() {
+                Some(s) => s.next().await,
+                None => return Ok(None),
+            };
+
+            match chunk {
+                Some(Ok(TtyChunk::StdOut(buf))) => lines.extend(stdout.push(&buf).into_iter().map(|l| (LogStream::Stdout, l))),
+                Some(Ok(TtyChunk::StdErr(buf))) => lines.extend(stderr.push(&buf).into_iter().map(|l| (LogStream::Stderr, l))),
+                Some(Ok(TtyChunk::StdIn(_))) => {
+                    // not attached, nothing to log
+                }
+                Some(Err(e)) => return Err(futures::io::Error::new(futures::io::ErrorKind::Other, e)),
+
+                None => {
+                    lines.extend(stdout.finish().map(|l| (LogStream::Stdout, l)));
+                    lines.extend(stderr.finish().map(|l| (LogStream::Stderr, l)));
+                    stream = None;
+                }
+            }
+        }
+    })
+}
+
+/// Assembles the lines of one output stream of a container from the chunks it is received in
+///
+/// At most `max_line_length` bytes of a line are kept, the rest of an overlong line is dropped,
+/// so memory usage stays bounded no matter how long a line is.
+struct LineBuffer {
+    max_line_length: usize,
+    line: Vec<u8>,
+    dropped_bytes: usize,
+}
+
+impl LineBuffer {
+    fn new(max_line_length: usize) -> Self {
+        LineBuffer {
+            max_line_length,
+            line: Vec::new(),
+            dropped_bytes: 0,
+        }
+    }
+
+    /// Append a chunk of output, returning the lines it completed
+    fn push(&mut self, mut chunk: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        loop {
+            let (content, found_newline) = match chunk.iter().position(|b| *b == b'\n') {
+                Some(pos) => (&chunk[..pos], true),
+                None => (chunk, false),
+            };
+
+            let keep = std::cmp::min(content.len(), self.max_line_length.saturating_sub(self.line.len()));
+            self.line.extend_from_slice(&content[..keep]);
+            self.dropped_bytes += content.len() - keep;
+
+            if !found_newline {
+                break;
+            }
+
+            lines.push(self.take_line());
+            chunk = &chunk[content.len() + 1..];
+        }
+
+        lines
+    }
+
+    /// The last line of the stream, if it did not end with a newline
+    fn finish(&mut self) -> Option<String> {
+        if self.line.is_empty() && self.dropped_bytes == 0 {
+            None
+        } else {
+            Some(self.take_line())
+        }
+    }
+
+    fn take_line(&mut self) -> String {
+        if self.dropped_bytes == 0 && self.line.last() == Some(&b'\r') {
+            let _ = self.line.pop();
+        }
+
+        let line = sanitize_log_line(&self.line, self.dropped_bytes);
+        self.line.clear();
+        self.dropped_bytes = 0;
+        line
+    }
+}
+
+/// Make a valid log line from the raw bytes of a line a container printed
+///
+/// Invalid UTF-8 is replaced lossily, lines that look like binary data are replaced by a hex sample
+/// of their first bytes. If `dropped_bytes` were cut off the end of the line because it was too
+/// long, this is noted at the end of the line.
+pub fn sanitize_log_line(raw: &[u8], dropped_bytes: usize) -> String {
+    // If the line was truncated, the cut might have happened in the middle of a character
+    let line = match std::str::from_utf8(raw) {
+        Err(e) if dropped_bytes > 0 && e.error_len().is_none() => &raw[..e.valid_up_to()],
+        _ => raw,
+    };
+    let dropped_bytes = dropped_bytes + (raw.len() - line.len());
+
+    match std::str::from_utf8(line) {
+        Ok(s) if dropped_bytes == 0 => s.to_string(),
+        Ok(s) => format!("{} {} line truncated, {} bytes dropped", s, SANITIZED_MARKER, dropped_bytes),
+
+        Err(_) if looks_binary(line) => {
+            let sample = line
+                .iter()
+                .take(BINARY_SAMPLE_LENGTH)
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>();
+
+            format!("{} binary output ({} bytes): {}", SANITIZED_MARKER, line.len() + dropped_bytes, sample)
+        }
+
+        Err(_) => {
+            let mut s = format!("{} {} invalid UTF-8 replaced", String::from_utf8_lossy(line), SANITIZED_MARKER);
+            if dropped_bytes > 0 {
+                s.push_str(&format!(", line truncated, {} bytes dropped", dropped_bytes));
+            }
+            s
+        }
+    }
+}
+
+/// Check whether a line that is not valid UTF-8 looks like binary data rather than text in some
+/// other encoding
+fn looks_binary(line: &[u8]) -> bool {
+    let non_text = line
+        .iter()
+        .filter(|b| **b == 0 || (b.is_ascii_control() && !b.is_ascii_whitespace() && **b != 0x1b))
+        .count();
+
+    non_text * 10 > line.len()
 }
 
 pub struct ParsedLog(Vec<LogItem>);
@@ -145,6 +299,7 @@ pub fn parser<'a>() -> PomParser<'a, u8, LogItem> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::TryStreamExt;
     use anyhow::Error;
     use anyhow::Result;
 
@@ -464,4 +619,62 @@ mod tests {
         let log = ParsedLog::from_str(buffer).unwrap();
         assert_eq!(log.is_successfull(), JobResult::Errored);
     }
+
+    #[test]
+    fn test_sanitize_valid_line() {
+        assert_eq!(sanitize_log_line(b"foo bar", 0), "foo bar");
+    }
+
+    #[test]
+    fn test_sanitize_truncated_line() {
+        assert_eq!(sanitize_log_line(b"foo", 5), "foo [butido] line truncated, 5 bytes dropped");
+    }
+
+    #[test]
+    fn test_sanitize_truncated_in_character() {
+        // "fooä" cut in the middle of the "ä"
+        let line = [b'f', b'o', b'o', 0xc3];
+        assert_eq!(sanitize_log_line(&line, 1), "foo [butido] line truncated, 2 bytes dropped");
+    }
+
+    #[test]
+    fn test_sanitize_invalid_utf8() {
+        let line = b"foo \xe4 bar";
+        assert_eq!(sanitize_log_line(line, 0), "foo \u{FFFD} bar [butido] invalid UTF-8 replaced");
+    }
+
+    #[test]
+    fn test_sanitize_binary() {
+        let line = [0x7f, b'E', b'L', b'F', 0x02, 0x01, 0x01, 0x00, 0x00, 0xff];
+        assert_eq!(sanitize_log_line(&line, 0), "[butido] binary output (10 bytes): 7f454c460201010000ff");
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let mut buffer = LineBuffer::new(8);
+
+        assert_eq!(buffer.push(b"sho"), Vec::<String>::new());
+        assert_eq!(buffer.push(b"rt\r\nway too long"), vec![String::from("short")]);
+        assert_eq!(buffer.push(b" line\nlast"), vec![String::from("way too  [butido] line truncated, 9 bytes dropped")]);
+        assert_eq!(buffer.finish(), Some(String::from("last")));
+        assert_eq!(buffer.finish(), None);
+    }
+
+    #[test]
+    fn test_line_stream_separates_stdout_and_stderr() {
+        let chunks: Vec<shiplift::Result<TtyChunk>> = vec![
+            Ok(TtyChunk::StdOut(b"out ".to_vec())),
+            Ok(TtyChunk::StdErr(b"err\n".to_vec())),
+            Ok(TtyChunk::StdOut(b"line\n".to_vec())),
+        ];
+        let lines = futures::executor::block_on({
+            buffer_stream_to_line_stream(futures::stream::iter(chunks), 100).try_collect::<Vec<_>>()
+        })
+        .unwrap();
+
+        assert_eq!(lines, vec![
+            (LogStream::Stderr, String::from("err")),
+            (LogStream::Stdout, String::from("out line")),
+        ]);
+    }
 }
@@ -0,0 +1,99 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use colored::Color;
+use colored::ColoredString;
+use colored::Colorize;
+use uuid::Uuid;
+
+/// Colors used for prefixing log lines
+///
+/// Dark/light variants of the same color are left out on purpose, because they are hard to
+/// distinguish on most terminals.
+const PREFIX_COLORS: &[Color] = &[
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::BrightRed,
+    Color::BrightGreen,
+    Color::BrightYellow,
+    Color::BrightBlue,
+    Color::BrightMagenta,
+    Color::BrightCyan,
+];
+
+/// A prefix for log lines of a job, used when logs of several jobs are streamed to one output
+///
+/// The prefix has the form `[<package>-<version>@<endpoint>]` and is colored with a color that is
+/// derived from the job UUID, so that it is stable over the whole run of the job.
+#[derive(Clone, Debug)]
+pub struct LogPrefix {
+    prefix: String,
+    color: Color,
+    job_id: Uuid,
+}
+
+impl LogPrefix {
+    pub fn new(package_name: &str, package_version: &str, endpoint_name: &str, job_id: &Uuid) -> Self {
+        let idx = job_id.as_bytes().iter().map(|b| *b as usize).sum::<usize>() % PREFIX_COLORS.len();
+
+        LogPrefix {
+            prefix: format!("[{}-{}@{}]", package_name, package_version, endpoint_name),
+            color: PREFIX_COLORS[idx],
+            job_id: *job_id,
+        }
+    }
+
+    /// The prefix without the surrounding brackets, followed by the job UUID, usable as a file name
+    ///
+    /// The UUID is part of the name because several jobs share a prefix, e.g. the retries of a job
+    /// and the jobs of a package for different architectures.
+    pub fn file_stem(&self) -> String {
+        format!("{}-{}", self.prefix.trim_start_matches('[').trim_end_matches(']'), self.job_id)
+    }
+
+    pub fn colored(&self) -> ColoredString {
+        self.prefix.as_str().color(self.color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_format() {
+        let uuid = Uuid::parse_str("c6e2ae7a-2a6d-4b4d-a1a4-9c3d7c0f6d58").unwrap();
+        let prefix = LogPrefix::new("foo", "1.0", "ep1", &uuid);
+
+        assert_eq!(prefix.prefix, "[foo-1.0@ep1]");
+        assert_eq!(prefix.file_stem(), "foo-1.0@ep1-c6e2ae7a-2a6d-4b4d-a1a4-9c3d7c0f6d58");
+    }
+
+    #[test]
+    fn test_file_stem_of_jobs_with_same_prefix() {
+        let p1 = LogPrefix::new("foo", "1.0", "ep1", &Uuid::new_v4());
+        let p2 = LogPrefix::new("foo", "1.0", "ep1", &Uuid::new_v4());
+
+        assert_eq!(p1.prefix, p2.prefix);
+        assert_ne!(p1.file_stem(), p2.file_stem());
+    }
+
+    #[test]
+    fn test_prefix_color_is_stable() {
+        let uuid = Uuid::parse_str("c6e2ae7a-2a6d-4b4d-a1a4-9c3d7c0f6d58").unwrap();
+        let p1 = LogPrefix::new("foo", "1.0", "ep1", &uuid);
+        let p2 = LogPrefix::new("bar", "2.0", "ep2", &uuid);
+
+        assert_eq!(p1.color, p2.color);
+    }
+}
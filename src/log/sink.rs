@@ -9,13 +9,261 @@
 //
 
 use anyhow::Result;
+use chrono::DateTime;
+use chrono::Local;
+use uuid::Uuid;
 
+use crate::config::LogLevel;
 use crate::log::LogItem;
+use crate::log::LogStream;
+use crate::notification::Event;
 
-pub trait LogSink: Sized {
-    fn log_item(&mut self, item: &LogItem) -> Result<()>;
+/// The name of the application in forwarded records
+const APP_NAME: &str = "butido";
 
-    fn close(self) -> Result<()> {
-        Ok(())
+/// The ID of the structured data element of RFC 5424 messages, with the reserved example
+/// enterprise number of RFC 5612
+const SD_ID: &str = "butido@32473";
+
+/// A target log records are forwarded to, see `crate::log::LogSinks`
+pub trait LogSink {
+    fn send(&mut self, record: &LogRecord) -> Result<()>;
+}
+
+/// A line of the log of a job or an event of a submit, as forwarded to the log sinks
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Local>,
+    pub level: LogLevel,
+    pub message: String,
+
+    /// What the record belongs to, e.g. the job, package and endpoint of a log line
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl LogRecord {
+    /// The record of a line of the log of a job, with the raw line as message
+    ///
+    /// Lines are forwarded as "info", phases and a successful end as "notice", a failed end as
+    /// "error" and progress reports as "debug".
+    pub fn for_job_log(
+        job: &Uuid,
+        package: &str,
+        version: &str,
+        endpoint: &str,
+        stream: LogStream,
+        item: &LogItem,
+    ) -> Result<Self> {
+        let level = match item {
+            LogItem::Line(_) => LogLevel::Info,
+            LogItem::Progress(_) => LogLevel::Debug,
+            LogItem::CurrentPhase(_) | LogItem::State(Ok(())) => LogLevel::Notice,
+            LogItem::State(Err(_)) => LogLevel::Error,
+        };
+
+        Ok(LogRecord {
+            timestamp: Local::now(),
+            level,
+            message: item.raw()?,
+            fields: vec![
+                ("job", job.to_string()),
+                ("package", package.to_string()),
+                ("version", version.to_string()),
+                ("endpoint", endpoint.to_string()),
+                ("stream", stream.to_string()),
+            ],
+        })
+    }
+
+    /// The record of an event of a submit, with the summary of the event as message
+    ///
+    /// Failed jobs and submits are forwarded as "error", all other events as "notice".
+    pub fn for_event(event: &Event) -> Result<Self> {
+        let level = match event {
+            Event::JobFailed { .. } | Event::SubmitFinished { success: false, .. } => LogLevel::Error,
+            Event::SubmitStarted { .. } | Event::SubmitFinished { success: true, .. } => LogLevel::Notice,
+        };
+
+        let fields = match serde_json::to_value(event)? {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .filter_map(|(k, v)| {
+                    let name = EVENT_FIELDS.iter().find(|name| **name == k)?;
+                    let value = match v {
+                        serde_json::Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    Some((*name, value))
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(LogRecord {
+            timestamp: Local::now(),
+            level,
+            message: event.summary(),
+            fields,
+        })
+    }
+
+    /// The value of the field `name`, if the record has it
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The record as RFC 3164 message, as understood by the local syslog daemon
+    ///
+    /// The fields are appended to the message, as the format has no place for them.
+    pub fn to_rfc3164(&self, facility: u8) -> String {
+        let fields = self.fields.iter().map(|(n, v)| format!("{}={}", n, v)).collect::<Vec<_>>();
+        format!("<{}>{} {}[{}]: {} [{}]",
+            self.priority(facility),
+            self.timestamp.format("%b %e %H:%M:%S"),
+            APP_NAME,
+            std::process::id(),
+            self.message.replace('\n', " "),
+            fields.join(" "))
+    }
+
+    /// The record as RFC 5424 message, with the fields as structured data
+    pub fn to_rfc5424(&self, facility: u8, hostname: &str) -> String {
+        let params = self.fields
+            .iter()
+            .map(|(n, v)| format!(" {}=\"{}\"", n, escape_param_value(v)))
+            .collect::<String>();
+
+        format!("<{}>1 {} {} {} {} - [{}{}] {}",
+            self.priority(facility),
+            self.timestamp.to_rfc3339(),
+            if hostname.is_empty() { "-" } else { hostname },
+            APP_NAME,
+            std::process::id(),
+            SD_ID,
+            params,
+            self.message.replace('\n', " "))
+    }
+
+    /// The record in the native protocol of the systemd journal
+    ///
+    /// The fields are prefixed with "BUTIDO_" and uppercased, e.g. "BUTIDO_PACKAGE".
+    pub fn to_journald(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let priority = self.level.severity().to_string();
+        journald_field(&mut buf, "MESSAGE", &self.message);
+        journald_field(&mut buf, "PRIORITY", &priority);
+        journald_field(&mut buf, "SYSLOG_IDENTIFIER", APP_NAME);
+        for (name, value) in self.fields.iter() {
+            journald_field(&mut buf, &format!("BUTIDO_{}", name.to_uppercase()), value);
+        }
+        buf
+    }
+
+    fn priority(&self, facility: u8) -> u16 {
+        facility as u16 * 8 + self.level.severity() as u16
+    }
+}
+
+/// The keys of events that are forwarded as fields of the record
+const EVENT_FIELDS: &[&str] = &["event", "submit", "job", "package", "version", "image", "jobs", "failed_jobs", "success"];
+
+/// Escape `"`, `\` and `]` in a parameter value of the structured data of a RFC 5424 message
+fn escape_param_value(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut s, c| {
+            if c == '"' || c == '\\' || c == ']' {
+                s.push('\\');
+            }
+            s.push(c);
+            s
+        })
+}
+
+/// Append a field to a message in the native journal protocol
+///
+/// Values with newlines are sent with their length, all other values as "NAME=value" line.
+fn journald_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    } else {
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> LogRecord {
+        let job = Uuid::nil();
+        let item = LogItem::Line(b"compiling \"foo\"".to_vec());
+        LogRecord::for_job_log(&job, "foo", "1.0", "ep", LogStream::Stderr, &item).unwrap()
+    }
+
+    #[test]
+    fn test_job_log_record() {
+        let r = record();
+        assert_eq!(r.level, LogLevel::Info);
+        assert_eq!(r.message, "compiling \"foo\"");
+        assert_eq!(r.field("package"), Some("foo"));
+        assert_eq!(r.field("stream"), Some("stderr"));
+        assert_eq!(r.field("submit"), None);
+
+        let item = LogItem::State(Err(String::from("failed")));
+        let r = LogRecord::for_job_log(&Uuid::nil(), "foo", "1.0", "ep", LogStream::Stdout, &item).unwrap();
+        assert_eq!(r.level, LogLevel::Error);
+    }
+
+    #[test]
+    fn test_event_record() {
+        let event = Event::SubmitFinished {
+            submit: Uuid::nil(),
+            success: false,
+            jobs: 5,
+            failed_jobs: 2,
+        };
+
+        let r = LogRecord::for_event(&event).unwrap();
+        assert_eq!(r.level, LogLevel::Error);
+        assert_eq!(r.field("event"), Some("submit_finished"));
+        assert_eq!(r.field("failed_jobs"), Some("2"));
+        assert_eq!(r.field("submit"), Some("00000000-0000-0000-0000-000000000000"));
+    }
+
+    #[test]
+    fn test_rfc5424() {
+        let msg = record().to_rfc5424(16, "buildhost");
+        assert!(msg.starts_with("<134>1 "), "{}", msg);
+        assert!(msg.contains(" buildhost butido "), "{}", msg);
+        assert!(msg.contains("[butido@32473 job=\"00000000-0000-0000-0000-000000000000\" package=\"foo\""), "{}", msg);
+        assert!(msg.ends_with("] compiling \"foo\""), "{}", msg);
+        assert_eq!(escape_param_value(r#"a"b]c\"#), r#"a\"b\]c\\"#);
+    }
+
+    #[test]
+    fn test_rfc3164() {
+        let msg = record().to_rfc3164(1);
+        assert!(msg.starts_with("<14>"), "{}", msg);
+        assert!(msg.contains(" butido["), "{}", msg);
+        assert!(msg.ends_with("compiling \"foo\" [job=00000000-0000-0000-0000-000000000000 package=foo version=1.0 endpoint=ep stream=stderr]"), "{}", msg);
+    }
+
+    #[test]
+    fn test_journald() {
+        let mut r = record();
+        r.message = String::from("two\nlines");
+        let buf = r.to_journald();
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\nPRIORITY=6\nSYSLOG_IDENTIFIER=butido\nBUTIDO_JOB=");
+        assert!(buf.starts_with(&expected));
+        assert!(buf.ends_with(b"BUTIDO_STREAM=stderr\n"));
     }
 }
@@ -51,6 +51,7 @@ extern crate diesel;
 extern crate diesel_migrations;
 
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use anyhow::anyhow;
 use anyhow::Context;
@@ -73,6 +74,7 @@ mod endpoint;
 mod filestore;
 mod job;
 mod log;
+mod notification;
 mod orchestrator;
 mod package;
 mod repository;
@@ -82,6 +84,7 @@ mod ui;
 mod util;
 
 use crate::config::*;
+use crate::log::LogFormat;
 use crate::repository::Repository;
 use crate::util::progress::ProgressBars;
 
@@ -97,8 +100,21 @@ async fn main() -> Result<()> {
     let _ = env_logger::try_init()?;
     debug!("Debugging enabled");
 
-    let app = cli::cli();
-    let cli = app.get_matches();
+    let args = std::env::args_os().collect::<Vec<_>>();
+
+    // The command line is parsed again with the defaults from the configuration below. Required
+    // arguments might be set in these defaults, so only the other errors (and --help) are reported
+    // here.
+    let cli = match cli::cli().try_get_matches_from(&args) {
+        Ok(matches) => Some(matches),
+        Err(e) if e.kind == clap::ErrorKind::MissingRequiredArgument => None,
+        Err(e) => e.exit(),
+    };
+
+    // Initializing does not need (and cannot have) a configuration or repository yet
+    if let Some(("init", matches)) = cli.as_ref().and_then(ArgMatches::subcommand) {
+        return crate::commands::init(matches).await.context("init command failed");
+    }
 
     let repo = git2::Repository::discover(PathBuf::from("."))
         .map_err(|e| match e.code() {
@@ -114,6 +130,7 @@ async fn main() -> Result<()> {
     let mut config = ::config::Config::default();
     config.merge(::config::File::from(repo_path.join("config.toml")).required(true))
         .context("Failed to load config.toml from repository")?;
+    crate::config::check_min_butido_version(&config)?;
 
     {
         let xdg = xdg::BaseDirectories::with_prefix("butido")?;
@@ -129,11 +146,17 @@ async fn main() -> Result<()> {
 
     config.merge(::config::Environment::with_prefix("BUTIDO"))?;
 
+    // Plugins get the configuration as it was loaded
+    let raw_config = config.clone();
     let config = config.try_into::<NotValidatedConfiguration>()
         .context("Failed to load Configuration object")?
         .validate()
         .context("Failed to validate configuration")?;
 
+    let args = config.defaults().apply(args, &cli::cli());
+    debug!("Command line with defaults from the configuration: {:?}", args);
+    let cli = cli::cli().get_matches_from(args);
+
     let hide_bars = cli.is_present("hide_bars") || crate::util::stdout_is_pipe();
     let progressbars = ProgressBars::setup(
         config.progress_format().clone(),
@@ -143,18 +166,38 @@ async fn main() -> Result<()> {
 
     let load_repo = || -> Result<Repository> {
         let bar = progressbars.bar();
-        let repo = Repository::load(repo_path, &bar)
+        let repo = Repository::load(repo_path, repo_path, config.package_repositories(), &bar)
             .context("Loading the repository")?;
         bar.finish_with_message("Repository loading finished");
         Ok(repo)
     };
 
+    // Read-only queries can be answered for the repository at an earlier revision
+    let load_repo_at = |matches: &ArgMatches| -> Result<Repository> {
+        match matches.value_of("at") {
+            Some(rev) => crate::commands::load_repository_at(repo_path, rev, &config, &progressbars)
+                .with_context(|| anyhow!("Loading the repository at {}", rev)),
+            None => load_repo(),
+        }
+    };
+
+    // Warn about expiring secrets before they make a build or release fail
+    if std::matches!(cli.subcommand_name(), Some("build") | Some("submit") | Some("canary") | Some("bisect") | Some("release")) {
+        crate::util::expiry::warn_expiring(&config).await;
+    }
+
     let db_connection_config = crate::db::DbConnectionConfig::parse(&config, &cli)?;
+    let log_format = cli
+        .value_of("log_format")
+        .map(LogFormat::from_str)
+        .transpose()?
+        .unwrap_or_default();
     match cli.subcommand() {
         Some(("generate-completions", matches)) => generate_completions(matches),
-        Some(("db", matches)) => crate::commands::db(db_connection_config, &config, matches)?,
+        Some(("config", matches)) => crate::commands::config(&config, matches)?,
+        Some(("db", matches)) => crate::commands::db(db_connection_config, &config, matches, repo_path, &progressbars)?,
         Some(("build", matches)) => {
-            let conn = db_connection_config.establish_connection()?;
+            let pool = db_connection_config.establish_pool()?;
 
             let repo = load_repo()?;
 
@@ -162,16 +205,38 @@ async fn main() -> Result<()> {
                 repo_path,
                 matches,
                 progressbars,
-                conn,
+                pool,
                 &config,
                 repo,
                 repo_path,
+                log_format,
+                None,
             )
             .await
             .context("build command failed")?
         }
-        Some(("what-depends", matches)) => {
+        Some(("canary", matches)) => {
             let repo = load_repo()?;
+            crate::commands::canary(repo_path, matches, progressbars, db_connection_config, &config, repo, log_format)
+                .await
+                .context("canary command failed")?
+        }
+        Some(("bisect", matches)) => {
+            crate::commands::bisect(repo_path, matches, progressbars, db_connection_config, &config, log_format)
+                .await
+                .context("bisect command failed")?
+        }
+        Some(("submit", matches)) => {
+            let pool = db_connection_config.establish_pool()?;
+
+            let repo = load_repo()?;
+
+            crate::commands::submit(repo_path, matches, progressbars, pool, &config, repo, log_format)
+                .await
+                .context("submit command failed")?
+        }
+        Some(("what-depends", matches)) => {
+            let repo = load_repo_at(matches)?;
             crate::commands::what_depends(matches, &config, repo)
                 .await
                 .context("what-depends command failed")?
@@ -185,19 +250,27 @@ async fn main() -> Result<()> {
         }
 
         Some(("versions-of", matches)) => {
-            let repo = load_repo()?;
+            let repo = load_repo_at(matches)?;
             crate::commands::versions_of(matches, repo)
                 .await
                 .context("versions-of command failed")?
         }
 
         Some(("env-of", matches)) => {
-            let repo = load_repo()?;
+            let repo = load_repo_at(matches)?;
             crate::commands::env_of(matches, repo)
                 .await
                 .context("env-of command failed")?
         }
 
+        Some(("diff-artifacts", matches)) => {
+            let repo = load_repo()?;
+            let conn = db_connection_config.establish_connection()?;
+            crate::commands::diff_artifacts(matches, &config, progressbars, repo, conn)
+                .await
+                .context("diff-artifacts command failed")?
+        }
+
         Some(("find-artifact", matches)) => {
             let repo = load_repo()?;
             let conn = db_connection_config.establish_connection()?;
@@ -234,8 +307,21 @@ async fn main() -> Result<()> {
         }
 
         Some(("tree-of", matches)) => {
-            let repo = load_repo()?;
-            crate::commands::tree_of(matches, repo)
+            let repo = load_repo_at(matches)?;
+            // The database is only used for caching here, so tree-of works without it. The cache
+            // is keyed by the checked out commit, so it is not used for other revisions.
+            let conn = if matches.is_present("at") {
+                None
+            } else {
+                match db_connection_config.establish_connection() {
+                    Ok(conn) => Some(conn),
+                    Err(e) => {
+                        debug!("Not caching trees, connecting to the database failed: {:?}", e);
+                        None
+                    }
+                }
+            };
+            crate::commands::tree_of(matches, repo, repo_path, conn)
                 .await
                 .context("tree-of command failed")?
         }
@@ -248,15 +334,73 @@ async fn main() -> Result<()> {
                 .context("metrics command failed")?
         }
 
+        Some(("store", matches)) => {
+            crate::commands::store(db_connection_config, &config, matches, progressbars)
+                .await
+                .context("store command failed")?
+        }
+
+        Some(("gc", matches)) => {
+            crate::commands::gc(db_connection_config, &config, matches, progressbars)
+                .context("gc command failed")?
+        }
+
+        Some(("verify", matches)) => {
+            crate::commands::verify(db_connection_config, &config, matches)
+                .await
+                .context("verify command failed")?
+        }
+
+        Some(("plugins", matches)) => {
+            crate::commands::plugins(matches)
+                .context("plugins command failed")?
+        }
+
+        Some(("explain-rebuild", matches)) => {
+            crate::commands::explain_rebuild(db_connection_config, matches)
+                .context("explain-rebuild command failed")?
+        }
+
+        Some(("log", matches)) => {
+            crate::commands::log(matches, &config, db_connection_config)
+                .await
+                .context("log command failed")?
+        }
+
+        Some(("doctor", matches)) => {
+            crate::commands::doctor(matches, &config)
+                .await
+                .context("doctor command failed")?
+        }
+
+        Some(("audit", matches)) => {
+            crate::commands::audit(db_connection_config, matches)
+                .context("audit command failed")?
+        }
+
         Some(("endpoint", matches)) => {
-            crate::commands::endpoint(matches, &config, progressbars)
+            crate::commands::endpoint(matches, &config, progressbars, db_connection_config)
                 .await
                 .context("endpoint command failed")?
         },
-        Some((other, _)) => {
-            error!("Unknown subcommand: {}", other);
-            error!("Use --help to find available subcommands");
-            return Err(anyhow!("Unknown subcommand: {}", other))
+
+        #[cfg(feature = "api")]
+        Some(("serve-api", matches)) => {
+            crate::commands::serve_api(db_connection_config, matches)
+                .await
+                .context("serve-api command failed")?
+        },
+
+        #[cfg(feature = "serve")]
+        Some(("serve", matches)) => {
+            crate::commands::serve(db_connection_config, &config, matches)
+                .await
+                .context("serve command failed")?
+        },
+        Some((other, matches)) => {
+            crate::commands::plugin(other, matches, raw_config, repo_path, db_connection_config)
+                .await
+                .with_context(|| anyhow!("{} plugin failed", other))?
         },
         None => {
             error!("No subcommand.");
@@ -0,0 +1,105 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use serde::Serialize;
+use uuid::Uuid;
+
+/// An event in the lifecycle of a submit
+///
+/// Webhooks receive this serialized as JSON, with the kind of the event in the "event" key.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    SubmitStarted {
+        submit: Uuid,
+        package: String,
+        version: String,
+        image: String,
+        jobs: usize,
+    },
+
+    JobFailed {
+        submit: Uuid,
+        job: Uuid,
+        package: String,
+        version: String,
+        maintainers: Vec<String>,
+        error: String,
+    },
+
+    SubmitFinished {
+        submit: Uuid,
+        success: bool,
+        jobs: usize,
+        failed_jobs: usize,
+    },
+}
+
+impl Event {
+    /// A one-line description of the event, for chat messages and mail subjects
+    pub fn summary(&self) -> String {
+        match self {
+            Event::SubmitStarted { submit, package, version, image, jobs } => {
+                format!("Submit {} started: {} {} on {} ({} jobs)", submit, package, version, image, jobs)
+            },
+
+            Event::JobFailed { submit, job, package, version, error, .. } => {
+                let error = error.lines().next().unwrap_or("");
+                format!("Job {} ({} {}) of submit {} failed: {}", job, package, version, submit, error)
+            },
+
+            Event::SubmitFinished { submit, success: true, jobs, .. } => {
+                format!("Submit {} succeeded ({} jobs)", submit, jobs)
+            },
+
+            Event::SubmitFinished { submit, success: false, jobs, failed_jobs } => {
+                format!("Submit {} failed ({} of {} jobs failed)", submit, failed_jobs, jobs)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let submit = Uuid::nil();
+        let event = Event::JobFailed {
+            submit,
+            job: submit,
+            package: String::from("foo"),
+            version: String::from("1.0"),
+            maintainers: vec![String::from("team-x")],
+            error: String::from("Script failed\nat line 3"),
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "job_failed");
+        assert_eq!(json["package"], "foo");
+        assert_eq!(json["maintainers"][0], "team-x");
+        assert_eq!(json["submit"], submit.to_string());
+        assert_eq!(event.summary(), format!("Job {} (foo 1.0) of submit {} failed: Script failed", submit, submit));
+    }
+
+    #[test]
+    fn test_submit_finished_summary() {
+        let event = Event::SubmitFinished {
+            submit: Uuid::nil(),
+            success: false,
+            jobs: 5,
+            failed_jobs: 2,
+        };
+
+        assert_eq!(serde_json::to_value(&event).unwrap()["event"], "submit_finished");
+        assert!(event.summary().ends_with("failed (2 of 5 jobs failed)"));
+    }
+}
@@ -0,0 +1,21 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Notifications about the lifecycle of submits
+//!
+//! Events are sent to the webhooks, chat rooms and mail addresses from the `[notifications]`
+//! section of the configuration, and forwarded to the `log_sinks`. Failing to send a
+//! notification never fails the submit.
+
+mod event;
+pub use event::*;
+
+mod notifier;
+pub use notifier::*;
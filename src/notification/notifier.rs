@@ -0,0 +1,170 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::process::Stdio;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use anyhow::Result;
+use log::trace;
+use log::warn;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+use uuid::Uuid;
+
+use crate::config::MatrixRoom;
+use crate::config::NotificationConfig;
+use crate::config::NotificationTargets;
+use crate::log::LogRecord;
+use crate::log::LogSinks;
+use crate::notification::Event;
+
+/// Sends the events of one submit to the configured notification targets and log sinks
+pub struct Notifier {
+    config: NotificationConfig,
+    submit: Uuid,
+    client: reqwest::Client,
+    log_sinks: Option<Arc<LogSinks>>,
+}
+
+impl Notifier {
+    pub fn new(config: NotificationConfig, submit: Uuid, log_sinks: Option<Arc<LogSinks>>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()
+            .context("Building HTTP client for notifications")?;
+
+        Ok(Notifier { config, submit, client, log_sinks })
+    }
+
+    pub async fn submit_started(&self, package: String, version: String, image: String, jobs: usize) {
+        self.notify(Event::SubmitStarted { submit: self.submit, package, version, image, jobs }).await
+    }
+
+    pub async fn job_failed(&self, job: Uuid, package: String, version: String, maintainers: Vec<String>, error: &Error) {
+        let error = format!("{:#}", error);
+        self.notify(Event::JobFailed { submit: self.submit, job, package, version, maintainers, error }).await
+    }
+
+    pub async fn submit_finished(&self, jobs: usize, failed_jobs: usize, success: bool) {
+        self.notify(Event::SubmitFinished { submit: self.submit, success, jobs, failed_jobs }).await
+    }
+
+    /// Send the event to all targets, failures are only logged
+    async fn notify(&self, event: Event) {
+        trace!("Sending notification: {:?}", event);
+
+        if let Some(sinks) = self.log_sinks.as_ref() {
+            match LogRecord::for_event(&event) {
+                Ok(record) => sinks.forward(&record),
+                Err(e) => warn!("Forwarding event to log sinks failed: {:#}", e),
+            }
+        }
+
+        self.send_to(self.config.targets(), &event).await;
+
+        // Failed jobs are also sent to the maintainers of the package
+        if let Event::JobFailed { maintainers, .. } = &event {
+            for maintainer in maintainers.iter() {
+                if let Some(targets) = self.config.maintainers().get(maintainer) {
+                    trace!("Sending notification to the targets of maintainer {}", maintainer);
+                    self.send_to(targets, &event).await;
+                }
+            }
+        }
+    }
+
+    async fn send_to(&self, targets: &NotificationTargets, event: &Event) {
+        for url in targets.webhooks() {
+            if let Err(e) = self.send_webhook(url, event).await {
+                warn!("Sending notification to webhook {} failed: {:#}", url, e);
+            }
+        }
+
+        for url in targets.slack() {
+            if let Err(e) = self.send_slack(url, event).await {
+                warn!("Sending notification to Slack webhook {} failed: {:#}", url, e);
+            }
+        }
+
+        for room in targets.matrix() {
+            if let Err(e) = self.send_matrix(room, event).await {
+                warn!("Sending notification to Matrix room {} failed: {:#}", room.room_id(), e);
+            }
+        }
+
+        for address in targets.email() {
+            if let Err(e) = send_email(address, event).await {
+                warn!("Sending notification mail to {} failed: {:#}", address, e);
+            }
+        }
+    }
+
+    async fn send_webhook(&self, url: &Url, event: &Event) -> Result<()> {
+        self.post_json(self.client.post(url.clone()), serde_json::to_string(event)?).await
+    }
+
+    async fn send_slack(&self, url: &Url, event: &Event) -> Result<()> {
+        let body = serde_json::json!({ "text": event.summary() });
+        self.post_json(self.client.post(url.clone()), body.to_string()).await
+    }
+
+    async fn send_matrix(&self, room: &MatrixRoom, event: &Event) -> Result<()> {
+        let txn_id = Uuid::new_v4().to_string();
+        let mut url = room.homeserver().clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Not a valid homeserver URL: {}", room.homeserver()))?
+            .pop_if_empty()
+            .extend(&["_matrix", "client", "r0", "rooms", room.room_id().as_str(), "send", "m.room.message", txn_id.as_str()]);
+
+        let body = serde_json::json!({ "msgtype": "m.text", "body": event.summary() });
+        let request = self.client.put(url).bearer_auth(room.access_token());
+        self.post_json(request, body.to_string()).await
+    }
+
+    async fn post_json(&self, request: reqwest::RequestBuilder, body: String) -> Result<()> {
+        request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+/// Send a mail with the summary of the event as subject and the event as body, via `sendmail`
+async fn send_email(address: &str, event: &Event) -> Result<()> {
+    let mail = format!("To: {}\nSubject: [butido] {}\n\n{}\n",
+        address,
+        event.summary(),
+        serde_json::to_string_pretty(event)?);
+
+    let mut child = tokio::process::Command::new("sendmail")
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Starting sendmail")?;
+
+    {
+        let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin of sendmail"))?;
+        stdin.write_all(mail.as_bytes()).await?;
+    }
+
+    let status = child.wait().await.context("Waiting for sendmail")?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("sendmail exited with {}", status))
+    }
+}
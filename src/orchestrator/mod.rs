@@ -12,5 +12,10 @@
 mod orchestrator;
 pub use orchestrator::*;
 
+mod status;
+
+mod timeout;
+pub use timeout::Timeout;
+
 mod util;
 
@@ -14,38 +14,67 @@ use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
 use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
 use diesel::PgConnection;
+use getset::Getters;
 use git2::Repository;
 use indicatif::ProgressBar;
 use itertools::Itertools;
 use log::debug;
+use log::info;
 use log::trace;
+use log::warn;
 use resiter::FilterMap;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use typed_builder::TypedBuilder;
 use uuid::Uuid;
 
 use crate::config::Configuration;
+use crate::config::EndpointName;
+use crate::config::ReusePolicy;
+use crate::db::DbPool;
+use crate::db::get_connection;
+use crate::db::ReuseCandidate;
+use crate::db::ReuseMismatch;
 use crate::db::models as dbmodels;
 use crate::endpoint::EndpointConfiguration;
 use crate::endpoint::EndpointScheduler;
+use crate::endpoint::SharedEndpoints;
 use crate::filestore::ArtifactPath;
 use crate::filestore::ReleaseStore;
 use crate::filestore::StagingStore;
 use crate::job::Dag;
+use crate::job::Job;
 use crate::job::JobDefinition;
 use crate::job::RunnableJob;
+use crate::job::fetch_secrets;
+use crate::log::LogFormat;
+use crate::log::LogSinks;
+use crate::notification::Notifier;
+use crate::orchestrator::status::SubmitStatus;
+use crate::orchestrator::timeout::Deadline;
+use crate::orchestrator::timeout::Timeout;
+use crate::orchestrator::timeout::deadline_passed;
+use crate::orchestrator::timeout::run_limit;
 use crate::orchestrator::util::*;
+use crate::package::HashValue;
+use crate::package::Package;
+use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::source::SourceCache;
+use crate::util::Architecture;
 use crate::util::EnvironmentVariableName;
+use crate::util::docker::ImageName;
+use crate::util::metrics::Metrics;
 use crate::util::progress::ProgressBars;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -57,7 +86,7 @@ use crate::util::progress::ProgressBars;
 ///
 /// Because of the implementation of [JobTask], the work happens in
 /// form of a tree, propagating results to the root (which is held by the Orchestrator itself).
-/// The Orchestrator also holds the connection to the database, the access to the filesystem via
+/// The Orchestrator also holds the pool of connections to the database, the access to the filesystem via
 /// the [ReleaseStore](crate::filestore::ReleaseStore) and the
 /// [StagingStore](crate::filestore::StagingStore), which are merged into a
 /// [MergedStores](crate::filestore::MergedStores) object.
@@ -164,35 +193,220 @@ pub struct Orchestrator<'a> {
     jobdag: Dag,
     config: &'a Configuration,
     repository: Repository,
-    database: Arc<PgConnection>,
+    database: DbPool,
+    submit: Option<dbmodels::Submit>,
+    hermetic: bool,
+    reuse_policy: ReusePolicy,
+    force_rebuild: ForceRebuild,
+    resumed_artifacts: ResumedArtifacts,
+
+    /// Sends the events of the submit, if there is one
+    notifier: Option<Notifier>,
+    metrics: Arc<Metrics>,
+
+    /// The jobs that were pruned from the DAG by `PackageFilter::Only`, their artifacts must exist
+    pruned_jobs: Vec<Job>,
+
+    /// How long the submit may run
+    timeout: Option<Duration>,
+
+    /// The submit whose artifacts are preferred over other artifacts when reusing artifacts
+    use_artifacts_from: Option<dbmodels::Submit>,
+}
+
+/// Restrict a submit to a part of the DAG
+#[derive(Clone, Debug)]
+pub enum PackageFilter {
+    /// Only build the package, use existing artifacts for its dependencies
+    Only(PackageName),
+
+    /// Build the tree with the package as root
+    UpTo(PackageName),
+}
+
+/// Packages that are built even if there are artifacts that could be reused
+///
+/// The packages depending on them are built as well, because one of their dependencies was built.
+#[derive(Clone, Debug)]
+pub enum ForceRebuild {
+    /// Reuse artifacts wherever possible
+    None,
+
+    /// Build the listed packages
+    Packages(Vec<PackageName>),
+
+    /// Build all packages
+    All,
+}
+
+impl Default for ForceRebuild {
+    fn default() -> Self {
+        ForceRebuild::None
+    }
 }
 
+impl ForceRebuild {
+    /// Whether the jobs of the package `name` have to be built
+    pub fn applies_to(&self, name: &PackageName) -> bool {
+        match self {
+            ForceRebuild::None => false,
+            ForceRebuild::Packages(names) => names.contains(name),
+            ForceRebuild::All => true,
+        }
+    }
+}
+
+/// How often the status line below the progress bars of the jobs is updated
+const STATUS_UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Artifacts of a resumed submit that are still in the staging store, with the ids of their
+/// database rows, by package and architecture
+type ResumedArtifacts = HashMap<(PackageName, PackageVersion, Option<Architecture>), Vec<(ArtifactPath, i32)>>;
+
 #[derive(TypedBuilder)]
 pub struct OrchestratorSetup<'a> {
     progress_generator: ProgressBars,
+
+    /// The endpoints to connect to, if no `shared_endpoints` are passed
+    #[builder(default)]
     endpoint_config: Vec<EndpointConfiguration>,
+
+    /// Endpoints that are already connected and shared with the orchestrators of other submits
+    #[builder(default)]
+    shared_endpoints: Option<SharedEndpoints>,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
     source_cache: SourceCache,
     jobdag: Dag,
-    database: Arc<PgConnection>,
-    submit: dbmodels::Submit,
+    database: DbPool,
+
+    /// The submit the jobs belong to
+    ///
+    /// Only required for running the jobs, not for planning them
+    #[builder(default)]
+    submit: Option<dbmodels::Submit>,
     log_dir: Option<PathBuf>,
+    #[builder(default)]
+    log_split_dir: Option<PathBuf>,
+    #[builder(default)]
+    stream_logs: bool,
+    #[builder(default)]
+    log_format: LogFormat,
+    #[builder(default)]
+    hermetic: bool,
+
+    /// Which artifacts may be reused
+    #[builder(default)]
+    reuse_policy: ReusePolicy,
+
+    /// The packages that are built even if there are artifacts that could be reused
+    ///
+    /// The packages must be part of the DAG and must not be pruned by `PackageFilter::Only`.
+    #[builder(default)]
+    force_rebuild: ForceRebuild,
+
+    /// Where the metrics of the submit are collected
+    #[builder(default)]
+    metrics: Arc<Metrics>,
+
+    /// The submit that is resumed
+    ///
+    /// Jobs of this submit which already produced artifacts in the staging store are not run
+    /// again.
+    #[builder(default)]
+    resume: Option<dbmodels::Submit>,
+
+    /// The part of the DAG that is built
+    #[builder(default)]
+    package_filter: Option<PackageFilter>,
+
+    /// The architectures the DAG is built for, none means that the jobs are not built for a
+    /// specific architecture
+    #[builder(default)]
+    architectures: Vec<Architecture>,
+
+    /// How long the submit may run, the jobs which did not finish by then are killed
+    #[builder(default)]
+    timeout: Option<Duration>,
+
+    /// The submit whose artifacts are preferred over other artifacts when reusing artifacts
+    ///
+    /// Its artifacts must be in the staging store or the release stores.
+    #[builder(default)]
+    use_artifacts_from: Option<dbmodels::Submit>,
     config: &'a Configuration,
     repository: Repository,
 }
 
 impl<'a> OrchestratorSetup<'a> {
     pub async fn setup(self) -> Result<Orchestrator<'a>> {
+        let endpoints = match self.shared_endpoints {
+            Some(endpoints) => endpoints,
+            None => SharedEndpoints::setup(self.endpoint_config).await?,
+        };
+        let log_sinks = if self.config.log_sinks().is_empty() {
+            None
+        } else {
+            Some(Arc::new(LogSinks::new(self.config.log_sinks())))
+        };
         let scheduler = EndpointScheduler::setup(
-            self.endpoint_config,
+            endpoints,
             self.staging_store.clone(),
             self.release_stores.clone(),
             self.database.clone(),
             self.submit.clone(),
             self.log_dir,
-        )
-        .await?;
+            self.log_split_dir,
+            log_sinks.clone(),
+            self.stream_logs,
+            self.log_format,
+            *self.config.log_max_line_length(),
+            self.config.artifact_checksum_algorithm().clone(),
+            self.metrics.clone(),
+        );
+
+        let resumed_artifacts = match self.resume.as_ref() {
+            Some(submit) => {
+                let staging_store = self.staging_store.read().await;
+                load_resumed_artifacts(&*get_connection(&self.database).await?, submit, &staging_store)?
+            },
+            None => HashMap::new(),
+        };
+
+        // Bound before the dag is moved out of `self`, so the closures below do not capture `self`
+        let architectures = &self.architectures;
+        let (jobdag, pruned_jobs) = match self.package_filter.as_ref() {
+            None => (self.jobdag, Vec::new()),
+            Some(PackageFilter::UpTo(name)) => (self.jobdag.up_to(name)?, Vec::new()),
+            Some(PackageFilter::Only(name)) => self.jobdag.only(name)?,
+        };
+        let jobdag = jobdag.for_architectures(architectures);
+        let pruned_jobs = if architectures.is_empty() {
+            pruned_jobs
+        } else {
+            pruned_jobs
+                .iter()
+                .flat_map(|job| architectures.iter().map(move |a| job.for_architecture(a.clone())))
+                .collect()
+        };
+
+        if let ForceRebuild::Packages(names) = &self.force_rebuild {
+            for name in names {
+                if pruned_jobs.iter().any(|job| job.package().name() == name) {
+                    return Err(anyhow!("Cannot force the rebuild of {}, only its artifacts are used in this submit", name))
+                }
+
+                if !jobdag.iter().any(|jobdef| jobdef.job.package().name() == name) {
+                    return Err(anyhow!("Cannot force the rebuild of {}, it is not part of the package tree", name))
+                }
+            }
+        }
+
+        let notifications = self.config.notifications();
+        let notifier = self.submit
+            .as_ref()
+            .map(|submit| Notifier::new(notifications.clone(), submit.uuid, log_sinks.clone()))
+            .transpose()?;
 
         Ok(Orchestrator {
             scheduler,
@@ -200,10 +414,20 @@ impl<'a> OrchestratorSetup<'a> {
             release_stores: self.release_stores.clone(),
             progress_generator: self.progress_generator,
             source_cache: self.source_cache,
-            jobdag: self.jobdag,
+            jobdag,
             config: self.config,
             database: self.database,
+            submit: self.submit,
             repository: self.repository,
+            hermetic: self.hermetic,
+            reuse_policy: self.reuse_policy,
+            force_rebuild: self.force_rebuild,
+            resumed_artifacts,
+            notifier,
+            metrics: self.metrics,
+            pruned_jobs,
+            timeout: self.timeout,
+            use_artifacts_from: self.use_artifacts_from,
         })
     }
 }
@@ -220,6 +444,9 @@ impl<'a> OrchestratorSetup<'a> {
 /// why.
 type JobResult = std::result::Result<HashMap<Uuid, Vec<ProducedArtifact>>, HashMap<Uuid, Error>>;
 
+/// An environment variable with information about the git repository, passed to all jobs
+type GitEnv = (EnvironmentVariableName, String);
+
 /// A type that represents whether an artifact was built or reused from an old job
 ///
 /// This is necessary to decide in dependent jobs whether a package needs to be rebuild even though
@@ -227,23 +454,34 @@ type JobResult = std::result::Result<HashMap<Uuid, Vec<ProducedArtifact>>, HashM
 ///
 /// E.G.: If a libA depends on libB, if libB changed and needs to be rebuilt, we need to rebuilt
 /// all packages that depend (directly or indirectly) on that library.
+///
+/// Both carry the id of the database row of the artifact, so that the jobs which get the artifact
+/// verify it against the checksum recorded for exactly this artifact.
 #[derive(Clone, Debug)]
 enum ProducedArtifact {
-    Built(ArtifactPath),
-    Reused(ArtifactPath),
+    Built(ArtifactPath, i32),
+    Reused(ArtifactPath, i32),
 }
 
 impl ProducedArtifact {
     /// Get whether the ProducedArtifact was built or reused from another job
     fn was_build(&self) -> bool {
-        std::matches!(self, ProducedArtifact::Built(_))
+        std::matches!(self, ProducedArtifact::Built(..))
     }
 
     /// Unpack the ProducedArtifact object into the ArtifactPath object it contains
     fn unpack(self) -> ArtifactPath {
         match self {
-            ProducedArtifact::Built(a) => a,
-            ProducedArtifact::Reused(a) => a,
+            ProducedArtifact::Built(a, _) => a,
+            ProducedArtifact::Reused(a, _) => a,
+        }
+    }
+
+    /// The id of the database row of the artifact
+    fn artifact_id(&self) -> i32 {
+        match self {
+            ProducedArtifact::Built(_, id) => *id,
+            ProducedArtifact::Reused(_, id) => *id,
         }
     }
 }
@@ -251,28 +489,361 @@ impl ProducedArtifact {
 impl Borrow<ArtifactPath> for ProducedArtifact {
     fn borrow(&self) -> &ArtifactPath  {
         match self {
-            ProducedArtifact::Built(a) => a,
-            ProducedArtifact::Reused(a) => a,
+            ProducedArtifact::Built(a, _) => a,
+            ProducedArtifact::Reused(a, _) => a,
         }
     }
 }
 
+/// A job of a submit, as planned by [Orchestrator::plan]
+#[derive(Debug, Getters)]
+pub struct PlannedJob {
+    #[getset(get = "pub")]
+    uuid: Uuid,
+
+    #[getset(get = "pub")]
+    package_name: PackageName,
+
+    #[getset(get = "pub")]
+    package_version: PackageVersion,
+
+    #[getset(get = "pub")]
+    image: ImageName,
+
+    #[getset(get = "pub")]
+    action: PlannedAction,
+
+    /// The environment the job would be built with
+    #[getset(get = "pub")]
+    env: Vec<(EnvironmentVariableName, String)>,
+
+    /// Why the job would be built, if the plan was explained
+    #[getset(get = "pub")]
+    reasons: Vec<ReuseMismatch>,
+
+    /// The earlier jobs of the package whose artifacts were not reused, if the plan was explained
+    #[getset(get = "pub")]
+    candidates: Vec<ReuseCandidate>,
+
+    /// How long building the job is expected to take, if it would be built and the package was
+    /// built in the image before
+    #[getset(get = "pub")]
+    expected_duration: Option<Duration>,
+}
+
+/// What would happen to a job if the submit was run
+#[derive(Debug)]
+pub enum PlannedAction {
+    /// The job would be built in a container
+    Build,
+
+    /// The job would not be built, the listed artifacts would be reused instead
+    Reuse(Vec<ArtifactPath>),
+}
+
+impl PlannedAction {
+    pub fn is_build(&self) -> bool {
+        std::matches!(self, PlannedAction::Build)
+    }
+}
+
 impl<'a> Orchestrator<'a> {
     pub async fn run(self, output: &mut Vec<ArtifactPath>) -> Result<HashMap<Uuid, Error>> {
-        let (results, errors) = self.run_tree().await?;
+        let n_jobs = self.jobdag.iter().count();
+        if n_jobs == 0 {
+            info!("No jobs to run");
+            return Ok(HashMap::new())
+        }
+
+        let images = self.jobdag
+            .iter()
+            .map(|jobdef| jobdef.job.image())
+            .unique()
+            .map(|image| (image.clone(), self.config.docker().image_reference(image)))
+            .collect::<Vec<_>>();
+        self.scheduler
+            .prepare_images(&images, self.config.docker().pull_images(), &self.progress_generator)
+            .await
+            .context("Preparing images on the endpoints")?;
+
+        if let (Some(notifier), Some(root)) = (self.notifier.as_ref(), self.root_job()) {
+            let image: &str = root.image().as_ref();
+            notifier.submit_started(root.package().name().to_string(),
+                root.package().version().to_string(),
+                image.to_string(),
+                n_jobs).await;
+        }
+
+        let result = self.run_tree().await;
+        if let Some(notifier) = self.notifier.as_ref() {
+            let (success, failed_jobs) = match result.as_ref() {
+                Ok((_, errors)) => (errors.is_empty(), errors.len()),
+                Err(_) => (false, 0),
+            };
+            notifier.submit_finished(n_jobs, failed_jobs, success).await;
+        }
+
+        let (results, errors) = result?;
         output.extend(results.into_iter());
         Ok(errors)
     }
 
-    async fn run_tree(self) -> Result<(Vec<ArtifactPath>, HashMap<Uuid, Error>)> {
-        let multibar = Arc::new({
-            let mp = indicatif::MultiProgress::new();
-            if self.progress_generator.hide() {
-                mp.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    /// The job no other job depends on, i.e. the job of the package the submit was requested for
+    ///
+    /// If the submit is built for several architectures, this is the job for the first one.
+    fn root_job(&self) -> Option<&Job> {
+        self.jobdag
+            .iter()
+            .find(|jobdef| !self.jobdag.iter().any(|other| other.dependencies.contains(jobdef.job.uuid())))
+            .map(|jobdef| jobdef.job)
+    }
+
+    /// Get the names of the endpoints the jobs would be scheduled to
+    pub async fn endpoints(&self) -> Result<Vec<EndpointName>> {
+        self.scheduler.schedulable_endpoints().await
+    }
+
+    /// Plan the submit without running it
+    ///
+    /// This walks the job DAG in dependency order and decides for each job whether it would be
+    /// built or whether artifacts from the staging store or the release stores would be reused.
+    /// A job is always built if any of its dependencies is built or if the rebuild of its package
+    /// is forced. The jobs that were pruned from the DAG by `PackageFilter::Only` are always
+    /// reused.
+    ///
+    /// No containers are scheduled and nothing is written to the database.
+    /// The returned list is ordered so that each job comes after its dependencies.
+    pub async fn plan(&self) -> Result<Vec<PlannedJob>> {
+        self.plan_jobs(false, false).await
+    }
+
+    /// Plan the submit without running it, and explain the decision for each job
+    ///
+    /// Like `plan()`, but each planned job also lists why it would be built and the earlier jobs
+    /// whose artifacts were considered but not reused, with the criteria they did not match.
+    pub async fn explain_reuse(&self) -> Result<Vec<PlannedJob>> {
+        self.plan_jobs(false, true).await
+    }
+
+    /// Estimate how long running the planned jobs would take
+    ///
+    /// Only the jobs that would be built are taken into account, spread over the job slots of the
+    /// endpoints. `None` if none of the packages to build was built in its image before.
+    pub fn estimate_duration(&self, plan: &[PlannedJob]) -> Option<Duration> {
+        let jobs = plan
+            .iter()
+            .filter(|job| job.action.is_build())
+            .map(|job| (job.uuid, job.expected_duration));
+
+        SubmitStatus::new(jobs).estimate(self.scheduler.job_slots())
+    }
+
+    /// The full paths of the artifacts of all jobs, if none of the jobs has to be built
+    ///
+    /// Like `plan()`, but stops at the first job that would be built, so that the check is cheap
+    /// if there is something to do. If the rebuild of a package is forced, no job is planned at
+    /// all.
+    pub async fn up_to_date_artifacts(&self) -> Result<Option<Vec<PathBuf>>> {
+        if self.jobdag.iter().any(|jobdef| self.force_rebuild.applies_to(jobdef.job.package().name())) {
+            return Ok(None)
+        }
+
+        let planned = self.plan_jobs(true, false).await?;
+        if planned.iter().any(|job| job.action.is_build()) {
+            return Ok(None)
+        }
+
+        let staging_store = self.staging_store.read().await;
+        planned
+            .iter()
+            .flat_map(|job| match &job.action {
+                PlannedAction::Reuse(artifacts) => artifacts.as_slice(),
+                PlannedAction::Build => &[],
+            })
+            .map(|artifact| {
+                crate::endpoint::locate_artifact(artifact, &staging_store, &self.release_stores)
+                    .map(|path| path.joined())
+            })
+            .collect::<Result<Vec<PathBuf>>>()
+            .map(Some)
+    }
+
+    /// Plan the jobs, optionally stopping at the first job that would be built and optionally
+    /// explaining the decisions
+    async fn plan_jobs(&self, stop_at_build: bool, explain: bool) -> Result<Vec<PlannedJob>> {
+        let (git_author_env, git_commit_env) = self.git_envs()?;
+        let pruned_artifacts = self.pruned_artifacts(git_author_env.as_ref(), git_commit_env.as_ref())
+            .await?
+            .into_iter()
+            .map(|(uuid, artifacts)| (uuid, artifacts.into_iter().map(ProducedArtifact::unpack).collect()))
+            .collect::<HashMap<Uuid, Vec<ArtifactPath>>>();
+        let staging_store = self.staging_store.read().await;
+
+        // The durations are only needed for the estimate of a complete plan
+        let durations = if stop_at_build {
+            None
+        } else {
+            Some(self.expected_durations().await?)
+        };
+
+        let mut planned = self.pruned_jobs
+            .iter()
+            .map(|job| PlannedJob {
+                uuid: *job.uuid(),
+                package_name: job.package().name().clone(),
+                package_version: job.package().version().clone(),
+                image: job.image().clone(),
+                action: PlannedAction::Reuse(pruned_artifacts.get(job.uuid()).cloned().unwrap_or_default()),
+                env: job_env(job, git_author_env.as_ref(), git_commit_env.as_ref()),
+                reasons: Vec::new(),
+                candidates: Vec::new(),
+                expected_duration: None,
+            })
+            .collect::<Vec<PlannedJob>>();
+        let mut is_built: HashMap<Uuid, bool> = HashMap::new();
+
+        // The artifacts each job would receive, like in `run_tree()`: The artifacts of the pruned
+        // jobs for the same architecture and the artifacts of the jobs it depends on, transitively
+        let mut received: HashMap<Uuid, HashMap<Uuid, Vec<ArtifactPath>>> = HashMap::new();
+        let mut pending = self.jobdag.iter().collect::<Vec<JobDefinition>>();
+
+        while !pending.is_empty() {
+            let (ready, rest): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|jobdef| jobdef.dependencies.iter().all(|d| is_built.contains_key(d)));
+
+            if ready.is_empty() {
+                return Err(anyhow!("Cannot plan jobs with circular dependencies: {}",
+                    rest.iter().map(|jobdef| jobdef.job.uuid()).join(", ")));
             }
-            mp
-        });
 
+            for jobdef in ready {
+                let any_dependency_is_built = jobdef.dependencies
+                    .iter()
+                    .any(|d| is_built.get(d).copied().unwrap_or(false));
+
+                let mut job_received = self.pruned_jobs
+                    .iter()
+                    .filter(|job| job.architecture() == jobdef.job.architecture())
+                    .filter_map(|job| Some((*job.uuid(), pruned_artifacts.get(job.uuid())?.clone())))
+                    .collect::<HashMap<Uuid, Vec<ArtifactPath>>>();
+                for dependency in jobdef.dependencies.iter() {
+                    if let Some(dependency_received) = received.get(dependency) {
+                        job_received.extend(dependency_received.iter().map(|(uuid, artifacts)| (*uuid, artifacts.clone())));
+                    }
+                }
+                let dependency_artifacts = job_received
+                    .values()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<ArtifactPath>>();
+
+                let resumed = self.resumed_artifacts
+                    .get(&(jobdef.job.package().name().clone(), jobdef.job.package().version().clone(), jobdef.job.architecture().clone()));
+
+                let mut reasons = Vec::new();
+                let mut candidates = Vec::new();
+                let action = if any_dependency_is_built {
+                    if explain {
+                        reasons.push(ReuseMismatch::DependencyBuilt);
+                    }
+                    PlannedAction::Build
+                } else if self.force_rebuild.applies_to(jobdef.job.package().name()) {
+                    if explain {
+                        reasons.push(ReuseMismatch::Forced);
+                    }
+                    PlannedAction::Build
+                } else if let Some(artifacts) = resumed {
+                    PlannedAction::Reuse(artifacts.iter().map(|(a, _)| a.clone()).collect())
+                } else {
+                    let artifacts = find_replacement_artifacts(
+                        jobdef.job,
+                        self.config,
+                        git_author_env.as_ref(),
+                        git_commit_env.as_ref(),
+                        &self.scheduler,
+                        &staging_store,
+                        &self.release_stores,
+                        self.database.clone(),
+                        self.hermetic,
+                        &self.reuse_policy,
+                        self.use_artifacts_from.as_ref(),
+                        Some(&dependency_artifacts))
+                        .await?;
+
+                    if explain {
+                        candidates = explain_rebuild(
+                            jobdef.job,
+                            self.config,
+                            git_author_env.as_ref(),
+                            git_commit_env.as_ref(),
+                            &self.scheduler,
+                            &staging_store,
+                            &self.release_stores,
+                            self.database.clone(),
+                            self.hermetic,
+                            &self.reuse_policy,
+                            self.use_artifacts_from.as_ref(),
+                            Some(&dependency_artifacts))
+                            .await?;
+                    }
+
+                    if artifacts.is_empty() {
+                        if explain && candidates.is_empty() {
+                            reasons.push(ReuseMismatch::NoCandidate);
+                        }
+                        PlannedAction::Build
+                    } else {
+                        PlannedAction::Reuse(artifacts.into_iter().map(|(a, _)| a).collect())
+                    }
+                };
+
+                let stop = stop_at_build && action.is_build();
+                is_built.insert(*jobdef.job.uuid(), action.is_build());
+                if let PlannedAction::Reuse(artifacts) = &action {
+                    job_received.insert(*jobdef.job.uuid(), artifacts.clone());
+                }
+                received.insert(*jobdef.job.uuid(), job_received);
+                let expected_duration = durations
+                    .as_ref()
+                    .filter(|_| action.is_build())
+                    .and_then(|d| d.get(jobdef.job.package().name(), jobdef.job.package().version(), jobdef.job.image().as_ref()));
+                planned.push(PlannedJob {
+                    uuid: *jobdef.job.uuid(),
+                    package_name: jobdef.job.package().name().clone(),
+                    package_version: jobdef.job.package().version().clone(),
+                    image: jobdef.job.image().clone(),
+                    action,
+                    env: job_env(jobdef.job, git_author_env.as_ref(), git_commit_env.as_ref()),
+                    reasons,
+                    candidates,
+                    expected_duration,
+                });
+
+                if stop {
+                    return Ok(planned)
+                }
+            }
+
+            pending = rest;
+        }
+
+        Ok(planned)
+    }
+
+    /// The build durations of the packages of the jobs, as far as they are known from earlier runs
+    async fn expected_durations(&self) -> Result<dbmodels::ExpectedDurations> {
+        let package_names = self.jobdag
+            .iter()
+            .map(|jobdef| jobdef.job.package().name().as_str())
+            .unique()
+            .collect::<Vec<&str>>();
+
+        dbmodels::BuildDuration::expected_for(&*get_connection(&self.database).await?, &package_names)
+    }
+
+    /// Get the environment variables for the git author and the git commit hash, if configured
+    fn git_envs(&self) -> Result<(Option<GitEnv>, Option<GitEnv>)> {
         let git_author_env = {
             self.config
                 .containers()
@@ -300,6 +871,114 @@ impl<'a> Orchestrator<'a> {
                 .transpose()?
         };
 
+        Ok((git_author_env, git_commit_env))
+    }
+
+    /// Find the existing artifacts of the jobs that were pruned from the DAG
+    ///
+    /// Fails if there are no artifacts for one of the jobs, because it has to be built first then.
+    async fn pruned_artifacts(
+        &self,
+        git_author_env: Option<&(EnvironmentVariableName, String)>,
+        git_commit_env: Option<&(EnvironmentVariableName, String)>,
+    ) -> Result<HashMap<Uuid, Vec<ProducedArtifact>>> {
+        let staging_store = self.staging_store.read().await;
+        let mut pruned_artifacts = HashMap::with_capacity(self.pruned_jobs.len());
+
+        for job in self.pruned_jobs.iter() {
+            let resumed = self.resumed_artifacts
+                .get(&(job.package().name().clone(), job.package().version().clone(), job.architecture().clone()));
+
+            let artifacts = if let Some(resumed) = resumed {
+                resumed.clone()
+            } else {
+                find_replacement_artifacts(
+                    job,
+                    self.config,
+                    git_author_env,
+                    git_commit_env,
+                    &self.scheduler,
+                    &staging_store,
+                    &self.release_stores,
+                    self.database.clone(),
+                    self.hermetic,
+                    &self.reuse_policy,
+                    self.use_artifacts_from.as_ref(),
+                    // The dependencies of a pruned job are not known, so its cache key cannot be
+                    // computed
+                    None)
+                    .await?
+            };
+
+            if artifacts.is_empty() {
+                return Err(anyhow!("No artifacts found for dependency {} {}, it has to be built first",
+                    job.package().name(),
+                    job.package().version()))
+            }
+
+            debug!("Using {} existing artifacts for dependency {} {}", artifacts.len(), job.package().name(), job.package().version());
+            pruned_artifacts.insert(*job.uuid(), artifacts.into_iter().map(|(a, id)| ProducedArtifact::Reused(a, id)).collect());
+        }
+
+        Ok(pruned_artifacts)
+    }
+
+    async fn run_tree(&self) -> Result<(Vec<ArtifactPath>, HashMap<Uuid, Error>)> {
+        let multibar = Arc::new({
+            let mp = indicatif::MultiProgress::new();
+            if self.progress_generator.hide() {
+                mp.set_draw_target(indicatif::ProgressDrawTarget::hidden());
+            }
+            mp
+        });
+
+        let (git_author_env, git_commit_env) = self.git_envs()?;
+        let pruned_artifacts = self.pruned_artifacts(git_author_env.as_ref(), git_commit_env.as_ref()).await?;
+
+        // The jobs only get the artifacts of the pruned jobs for the same architecture
+        let pruned_artifacts = self.pruned_jobs
+            .iter()
+            .filter_map(|job| Some((job, pruned_artifacts.get(job.uuid())?)))
+            .fold(HashMap::<Option<&Architecture>, HashMap<Uuid, Vec<ProducedArtifact>>>::new(), |mut map, (job, artifacts)| {
+                map.entry(job.architecture().as_ref())
+                    .or_default()
+                    .insert(*job.uuid(), artifacts.clone());
+                map
+            });
+        let no_pruned_artifacts = HashMap::new();
+
+        // The package of every job, so that a job only gets the named outputs of its dependencies
+        // it depends on
+        let job_packages = self.jobdag
+            .iter()
+            .map(|jobdef| jobdef.job)
+            .chain(self.pruned_jobs.iter())
+            .map(|job| (*job.uuid(), job.package()))
+            .collect::<HashMap<Uuid, &Package>>();
+
+        let status = {
+            let durations = self.expected_durations().await?;
+            let jobs = self.jobdag
+                .iter()
+                .map(|jobdef| {
+                    let package = jobdef.job.package();
+                    let expected = durations.get(package.name(), package.version(), jobdef.job.image().as_ref());
+                    (*jobdef.job.uuid(), expected)
+                });
+
+            SubmitStatus::new(jobs)
+        };
+        for jobdef in self.jobdag.iter() {
+            self.metrics.job_queued(*jobdef.job.uuid(), jobdef.job.package().name().as_str());
+        }
+
+        // Cancelled when the user interrupts the submit (or a job fails), so that all jobs stop
+        // and remove their containers
+        let cancellation = CancellationToken::new();
+
+        // When the deadline passes, the jobs which did not finish yet fail with a timeout
+        let deadline = self.timeout.map(Deadline::after);
+
         // For each job in the jobdag, built a tuple with
         //
         // 1. The receiver that is used by the task to receive results from dependency tasks from
@@ -320,7 +999,17 @@ impl<'a> Orchestrator<'a> {
                 trace!("Creating TaskPreparation object for job {}", jobdef.job.uuid());
                 let bar = self.progress_generator.bar();
                 let bar = multibar.add(bar);
-                bar.set_length(100);
+                // One step per phase of the script, plus one for uploading the inputs to the
+                // container and one for collecting the artifacts
+                let n_phases = jobdef.job
+                    .package()
+                    .phase_order(jobdef.job.script_phases())
+                    .map(|order| order.len())
+                    .unwrap_or_else(|_| jobdef.job.script_phases().len());
+                bar.set_length(n_phases as u64 + 2);
+                let pruned_artifacts = pruned_artifacts
+                    .get(&jobdef.job.architecture().as_ref())
+                    .unwrap_or(&no_pruned_artifacts);
                 let tp = TaskPreparation {
                     jobdef,
 
@@ -333,6 +1022,18 @@ impl<'a> Orchestrator<'a> {
                     staging_store: self.staging_store.clone(),
                     release_stores: self.release_stores.clone(),
                     database: self.database.clone(),
+                    hermetic: self.hermetic,
+                    reuse_policy: &self.reuse_policy,
+                    force_rebuild: &self.force_rebuild,
+                    use_artifacts_from: self.use_artifacts_from.as_ref(),
+                    resumed_artifacts: &self.resumed_artifacts,
+                    pruned_artifacts,
+                    job_packages: &job_packages,
+                    status: &status,
+                    notifier: self.notifier.as_ref(),
+                    metrics: &self.metrics,
+                    cancellation: cancellation.clone(),
+                    deadline,
                 };
 
                 (receiver, tp, sender, std::cell::RefCell::new(None as Option<Vec<Sender<JobResult>>>))
@@ -385,18 +1086,20 @@ impl<'a> Orchestrator<'a> {
             };
         }
 
-        // Find the id of the root task
+        // Find the root tasks
         //
         // By now, all tasks should be associated with their respective sender.
-        // Only one has None sender: The task that is the "root" of the tree.
-        // By that property, we can find the root task.
+        // Only the tasks that are the "root" of a tree have a None sender, there is one tree for
+        // each architecture the submit is built for.
         //
-        // Here, we copy its uuid, because we need it later.
-        let root_job_id = jobs.iter()
-            .find(|j| j.3.borrow().is_none())
-            .map(|j| j.1.jobdef.job.uuid())
-            .ok_or_else(|| anyhow!("Failed to find root task"))?;
-        trace!("Root job id = {}", root_job_id);
+        // Here, we count them, because we have to wait for the results of all of them later.
+        let n_root_jobs = jobs.iter()
+            .filter(|j| j.3.borrow().is_none())
+            .inspect(|j| trace!("Root job id = {}", j.1.jobdef.job.uuid()))
+            .count();
+        if n_root_jobs == 0 {
+            return Err(anyhow!("Failed to find root task"));
+        }
 
         // Create a sender and a receiver for the root of the tree
         let (root_sender, mut root_receiver) = tokio::sync::mpsc::channel(100);
@@ -421,22 +1124,86 @@ impl<'a> Orchestrator<'a> {
             .collect::<futures::stream::FuturesUnordered<_>>();
         debug!("Built {} jobs", running_jobs.len());
 
+        // The status line is added last, so it is shown below the bars of all jobs
+        let status_bar = multibar.add(self.progress_generator.status_line());
+        let jobs_finished = std::sync::atomic::AtomicBool::new(false);
+        let running_jobs = async {
+            let mut running_jobs = running_jobs;
+            let mut first_error = None;
+            let mut interrupted = false;
+            let mut cancelled_by_user = false;
+            let ctrl_c = tokio::signal::ctrl_c();
+            tokio::pin!(ctrl_c);
+
+            // Wait for all jobs, even if one of them fails, so that the others can remove their
+            // containers after the cancellation
+            loop {
+                tokio::select! {
+                    job_result = running_jobs.next() => match job_result {
+                        None => break,
+                        Some(Ok(())) => {},
+                        Some(Err(e)) => {
+                            cancellation.cancel();
+                            first_error.get_or_insert(e);
+                        },
+                    },
+
+                    signal = &mut ctrl_c, if !interrupted => {
+                        interrupted = true;
+                        match signal {
+                            Ok(()) => {
+                                warn!("Interrupted, cancelling all jobs...");
+                                cancelled_by_user = true;
+                                cancellation.cancel();
+                            },
+                            Err(e) => warn!("Cannot listen for interrupts: {}", e),
+                        }
+                    },
+                }
+            }
+
+            jobs_finished.store(true, std::sync::atomic::Ordering::Release);
+            (first_error.map(Err).unwrap_or(Ok(())), cancelled_by_user)
+        };
+        let status_updates = async {
+            while !jobs_finished.load(std::sync::atomic::Ordering::Acquire) {
+                status_bar.set_message(status.message(&self.scheduler));
+                status_bar.tick();
+                tokio::time::sleep(STATUS_UPDATE_INTERVAL).await;
+            }
+            status_bar.finish_with_message(status.message(&self.scheduler));
+        };
+
         let multibar_block = tokio::task::spawn_blocking(move || multibar.join());
-        let (_, jobs_result) = tokio::join!(multibar_block, running_jobs.collect::<Result<()>>());
+        let (_, (jobs_result, cancelled_by_user), _) = tokio::join!(multibar_block, running_jobs, status_updates);
+        if cancelled_by_user {
+            if let Some(submit) = self.submit.as_ref() {
+                submit.mark_cancelled(&*get_connection(&self.database).await?)?;
+            }
+            return Err(anyhow!("Submit cancelled"));
+        }
         let _ = jobs_result?;
         trace!("All jobs finished");
-        match root_receiver.recv().await {
-            None                     => Err(anyhow!("No result received...")),
-            Some(Ok(results)) => {
-                let results = results.into_iter()
-                    .map(|tpl| tpl.1.into_iter())
-                    .flatten()
-                    .map(ProducedArtifact::unpack)
-                    .collect();
-                Ok((results, HashMap::with_capacity(0)))
-            },
-            Some(Err(errors))        => Ok((vec![], errors)),
+        drop(root_sender);
+
+        let mut artifacts = vec![];
+        let mut errors = HashMap::with_capacity(0);
+        for _ in 0..n_root_jobs {
+            match root_receiver.recv().await {
+                None                     => return Err(anyhow!("No result received...")),
+                Some(Ok(results)) => {
+                    artifacts.extend({
+                        results.into_iter()
+                            .map(|tpl| tpl.1.into_iter())
+                            .flatten()
+                            .map(ProducedArtifact::unpack)
+                    });
+                },
+                Some(Err(e))        => errors.extend(e),
+            }
         }
+
+        Ok((artifacts, errors))
     }
 }
 
@@ -458,7 +1225,19 @@ struct TaskPreparation<'a> {
     scheduler: &'a EndpointScheduler,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
-    database: Arc<PgConnection>,
+    database: DbPool,
+    hermetic: bool,
+    reuse_policy: &'a ReusePolicy,
+    force_rebuild: &'a ForceRebuild,
+    use_artifacts_from: Option<&'a dbmodels::Submit>,
+    resumed_artifacts: &'a ResumedArtifacts,
+    pruned_artifacts: &'a HashMap<Uuid, Vec<ProducedArtifact>>,
+    job_packages: &'a HashMap<Uuid, &'a Package>,
+    status: &'a SubmitStatus,
+    notifier: Option<&'a Notifier>,
+    metrics: &'a Metrics,
+    cancellation: CancellationToken,
+    deadline: Option<Deadline>,
 }
 
 /// Helper type for executing one job task
@@ -476,7 +1255,28 @@ struct JobTask<'a> {
     scheduler: &'a EndpointScheduler,
     staging_store: Arc<RwLock<StagingStore>>,
     release_stores: Vec<Arc<ReleaseStore>>,
-    database: Arc<PgConnection>,
+    database: DbPool,
+    hermetic: bool,
+    reuse_policy: &'a ReusePolicy,
+    force_rebuild: &'a ForceRebuild,
+    use_artifacts_from: Option<&'a dbmodels::Submit>,
+    resumed_artifacts: &'a ResumedArtifacts,
+
+    /// The artifacts of the jobs that were pruned from the DAG, which are passed to the job as
+    /// if they were received from dependencies
+    pruned_artifacts: &'a HashMap<Uuid, Vec<ProducedArtifact>>,
+
+    /// The package of every job of the submit, including the pruned ones
+    job_packages: &'a HashMap<Uuid, &'a Package>,
+    status: &'a SubmitStatus,
+    notifier: Option<&'a Notifier>,
+    metrics: &'a Metrics,
+
+    /// Cancelled if the submit is cancelled, the job stops (and removes its container) then
+    cancellation: CancellationToken,
+
+    /// The deadline of the submit, the job fails with a timeout if it is not done by then
+    deadline: Option<Deadline>,
 
     /// Channel where the dependencies arrive
     receiver: Receiver<JobResult>,
@@ -508,7 +1308,9 @@ impl<'a> Drop for JobTask<'a> {
         if !self.bar.is_finished() {
             // If there are dependencies, the error is probably from another task
             // If there are no dependencies, the error was caused by something else
-            let errmsg = if self.jobdef.dependencies.is_empty() {
+            let errmsg = if self.cancellation.is_cancelled() {
+                "cancelled"
+            } else if self.jobdef.dependencies.is_empty() {
                 "error occured"
             } else {
                 "error on other task"
@@ -544,12 +1346,110 @@ impl<'a> JobTask<'a> {
             staging_store: prep.staging_store,
             release_stores: prep.release_stores,
             database: prep.database.clone(),
+            hermetic: prep.hermetic,
+            reuse_policy: prep.reuse_policy,
+            force_rebuild: prep.force_rebuild,
+            use_artifacts_from: prep.use_artifacts_from,
+            resumed_artifacts: prep.resumed_artifacts,
+            pruned_artifacts: prep.pruned_artifacts,
+            job_packages: prep.job_packages,
+            status: prep.status,
+            notifier: prep.notifier,
+            metrics: prep.metrics,
+            cancellation: prep.cancellation,
+            deadline: prep.deadline,
 
             receiver,
             sender,
         }
     }
 
+    /// The artifacts of the dependency job `uuid` that are passed to the job
+    ///
+    /// If the package depends only on named outputs of the package of the dependency job (e.g.
+    /// "foo:devel =1.0"), these are the artifacts of these outputs, otherwise all artifacts.
+    /// The artifacts are returned with the ids of their database rows.
+    fn selected_artifacts(&self, uuid: &Uuid, artifacts: &[ProducedArtifact]) -> Result<Vec<(ArtifactPath, i32)>> {
+        let with_id = |artifact: &ProducedArtifact| (Borrow::<ArtifactPath>::borrow(artifact).clone(), artifact.artifact_id());
+        let all = || artifacts.iter().map(with_id).collect::<Vec<(ArtifactPath, i32)>>();
+
+        let producer = match self.job_packages.get(uuid) {
+            Some(producer) => producer,
+            None => return Ok(all()),
+        };
+
+        let outputs = match self.jobdef.job.package().outputs_of_dependency(producer.name())? {
+            Some(outputs) => outputs,
+            None => return Ok(all()),
+        };
+
+        if let Some(undeclared) = outputs.iter().find(|o| !producer.outputs().contains_key(*o)) {
+            return Err(anyhow!("{} {} depends on output '{}' of {} {}, which is not declared in its outputs",
+                self.jobdef.job.package().name(),
+                self.jobdef.job.package().version(),
+                undeclared,
+                producer.name(),
+                producer.version()));
+        }
+
+        let mut selected = Vec::new();
+        for artifact in artifacts {
+            let path: &ArtifactPath = artifact.borrow();
+            if let Some(output) = producer.output_of(path.as_ref())? {
+                if outputs.iter().any(|o| o == output) {
+                    selected.push(with_id(artifact));
+                }
+            }
+        }
+
+        trace!("[{}]: Using outputs {:?} of {}: {:?}", self.jobdef.job.uuid(), outputs, uuid, selected);
+        Ok(selected)
+    }
+
+    /// Log and record why the job is built instead of reusing the artifacts of an earlier job
+    async fn record_rebuild_reasons(&self, any_dependency_was_built: bool, forced: bool, dependency_artifacts: &[ArtifactPath]) -> Result<()> {
+        let job_uuid = self.jobdef.job.uuid();
+        let (mismatches, candidates) = if any_dependency_was_built {
+            (vec![ReuseMismatch::DependencyBuilt], Vec::new())
+        } else if forced {
+            (vec![ReuseMismatch::Forced], Vec::new())
+        } else {
+            let staging_store = self.staging_store.read().await;
+            let candidates = explain_rebuild(
+                self.jobdef.job,
+                self.config,
+                self.git_author_env,
+                self.git_commit_env,
+                self.scheduler,
+                &staging_store,
+                &self.release_stores,
+                self.database.clone(),
+                self.hermetic,
+                self.reuse_policy,
+                self.use_artifacts_from,
+                Some(dependency_artifacts))
+                .await?;
+
+            if candidates.is_empty() {
+                (vec![ReuseMismatch::NoCandidate], candidates)
+            } else {
+                (Vec::new(), candidates)
+            }
+        };
+
+        for mismatch in mismatches.iter() {
+            info!("[{}]: Building: {}", job_uuid, mismatch);
+        }
+        for candidate in candidates.iter() {
+            for mismatch in candidate.mismatches.iter() {
+                info!("[{}]: Not reusing job {}: {}", job_uuid, candidate.job.uuid, mismatch);
+            }
+        }
+
+        let now = chrono::offset::Local::now().naive_local();
+        dbmodels::RebuildReason::create(&*get_connection(&self.database).await?, job_uuid, &mismatches, &candidates, &now)
+    }
+
     /// Run the job
     ///
     /// This function runs the job from this object on the scheduler as soon as all dependend jobs
@@ -564,6 +1464,7 @@ impl<'a> JobTask<'a> {
         // A list of job run results from dependencies that were received from the tasks for the
         // dependencies
         let mut received_dependencies: HashMap<Uuid, Vec<ProducedArtifact>> = HashMap::with_capacity(dep_len);
+        received_dependencies.extend(self.pruned_artifacts.iter().map(|(uuid, artifacts)| (*uuid, artifacts.clone())));
 
         // A list of errors that were received from the tasks for the dependencies
         let mut received_errors: HashMap<Uuid, Error> = HashMap::with_capacity(dep_len);
@@ -590,7 +1491,11 @@ impl<'a> JobTask<'a> {
 
             trace!("[{}]: receiving...", self.jobdef.job.uuid());
             // receive from the receiver
-            let continue_receiving = self.perform_receive(&mut received_dependencies, &mut received_errors).await?;
+            let cancellation = self.cancellation.clone();
+            let continue_receiving = tokio::select! {
+                r = self.perform_receive(&mut received_dependencies, &mut received_errors) => r?,
+                _ = cancellation.cancelled() => return Err(anyhow!("Job {} cancelled", self.jobdef.job.uuid())),
+            };
 
             trace!("[{}]: Received errors = {}", self.jobdef.job.uuid(), received_errors.display_error_map());
             // if there are any errors from child tasks
@@ -600,6 +1505,8 @@ impl<'a> JobTask<'a> {
                 // We only send to one parent, because it doesn't matter
                 // And we know that we have at least one sender
                 log::error!("[{}]: Received errors = {}", self.jobdef.job.uuid(), received_errors.display_error_map());
+                self.status.job_failed(self.jobdef.job.uuid());
+                self.metrics.job_failed(self.jobdef.job.uuid());
                 self.sender[0].send(Err(received_errors)).await;
 
                 // ... and stop operation, because the whole tree will fail anyways.
@@ -622,84 +1529,52 @@ impl<'a> JobTask<'a> {
             .flatten()
             .any(ProducedArtifact::was_build);
 
-        // If no dependency was built, we can check for replacements for this job as well, so
-        // check if a job that looks very similar to this job has already produced artifacts.
-        // If it has, simply return those (plus the received ones)
-        if !any_dependency_was_built {
-            let staging_store = self.staging_store.read().await;
+        // Map the list of received dependencies from
+        //      Vec<(Uuid, Vec<ArtifactPath>)>
+        // to
+        //      Vec<ArtifactPath>
+        // with only the named outputs of the dependencies the package depends on
+        let (dependency_artifacts, dependency_artifact_ids): (Vec<ArtifactPath>, Vec<i32>) = received_dependencies
+            .iter()
+            .map(|(uuid, artifacts)| self.selected_artifacts(uuid, artifacts))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .unzip();
 
-            // Use the environment of the job definition, as it appears in the job DAG.
-            //
-            // This is because we do not have access to the commandline-passed (additional)
-            // environment variables at this point. But using the JobResource::env() variables
-            // works as well.
-            let additional_env = self.jobdef.job.resources()
-                .iter()
-                .filter_map(crate::job::JobResource::env)
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .chain(self.git_author_env.cloned().into_iter())
-                .chain(self.git_commit_env.cloned().into_iter())
-                .collect::<Vec<_>>();
-
-            let replacement_artifacts = crate::db::FindArtifacts::builder()
-                .database_connection(self.database.clone())
-                .config(self.config)
-                .package(self.jobdef.job.package())
-                .release_stores(&self.release_stores)
-                .image_name(Some(self.jobdef.job.image()))
-
-                // We can simply pass the staging store here, because it doesn't hurt. There are
-                // two scenarios:
-                //
-                // 1. We are in a fresh build for a package. In this case, the artifacts for this
-                //    very build are not in there yet, and there won't be any artifacts from the
-                //    staging store (possibly from the release store, which would be fine).
-                // 2. We are in a re-build, where the user passed the staging store to the build
-                //    subcommand. In this case, there might be an artifact for this job in the
-                //    staging store. In this case, we want to use it as a replacement, of course.
-                //
-                // The fact that released artifacts are returned prefferably from this function
-                // call does not change anything, because if there is an artifact that's a released
-                // one that matches this job, we should use it anyways.
-                .staging_store(Some(&staging_store))
-                .env_filter(&additional_env)
-                .script_filter(true)
-                .build()
-                .run()?;
-
-            debug!("[{}]: Found {} replacement artifacts", self.jobdef.job.uuid(), replacement_artifacts.len());
-            trace!("[{}]: Found replacement artifacts: {:?}", self.jobdef.job.uuid(), replacement_artifacts);
-            let mut artifacts = replacement_artifacts
+        // If no dependency was built (and the rebuild of the package is not forced), we can check
+        // for replacements for this job as well, so check if the job already produced artifacts in
+        // the submit that is resumed, or if a job that looks very similar to this job has already
+        // produced artifacts.
+        // If it has, simply return those (plus the received ones)
+        let forced = self.force_rebuild.applies_to(self.jobdef.job.package().name());
+        if !any_dependency_was_built && !forced {
+            let resumed = self.resumed_artifacts
+                .get(&(self.jobdef.job.package().name().clone(), self.jobdef.job.package().version().clone(), self.jobdef.job.architecture().clone()));
+
+            let artifacts = if let Some(resumed) = resumed {
+                debug!("[{}]: Reusing {} artifacts from resumed submit", self.jobdef.job.uuid(), resumed.len());
+                resumed.clone()
+            } else {
+                let staging_store = self.staging_store.read().await;
+                find_replacement_artifacts(
+                    self.jobdef.job,
+                    self.config,
+                    self.git_author_env,
+                    self.git_commit_env,
+                    self.scheduler,
+                    &staging_store,
+                    &self.release_stores,
+                    self.database.clone(),
+                    self.hermetic,
+                    self.reuse_policy,
+                    self.use_artifacts_from,
+                    Some(&dependency_artifacts))
+                    .await?
+            };
+            let artifacts = artifacts
                 .into_iter()
-
-                // First of all, we sort by whether the artifact path is in the staging store,
-                // because we prefer staging store artifacts at this point.
-                .sorted_by(|(p1, _), (p2, _)| {
-                    let r1 = p1.is_in_staging_store(&staging_store);
-                    let r2 = p2.is_in_staging_store(&staging_store);
-                    r1.cmp(&r2)
-                })
-
-                // We don't need duplicates here, so remove them by making the iterator unique
-                // If we have two artifacts that are the same, the one in the staging store will be
-                // preffered in the next step
-                .unique_by(|tpl| tpl.0.artifact_path().clone())
-
-                // Fetch the artifact from the staging store, if there is one.
-                // If there is none, try the release store.
-                // If there is none, there won't be a replacement artifact
-                .filter_map(|(full_artifact_path, _)| {
-                    trace!("Searching for {:?} in stores", full_artifact_path.display());
-                    if let Some(ap) = staging_store.get(full_artifact_path.artifact_path()) {
-                        Some(ap.clone())
-                    } else {
-                        self.release_stores
-                            .iter()
-                            .find_map(|rs| rs.get(full_artifact_path.artifact_path()))
-                            .cloned()
-                    }
-                })
-                .map(ProducedArtifact::Reused)
+                .map(|(a, id)| ProducedArtifact::Reused(a, id))
                 .collect::<Vec<ProducedArtifact>>();
 
             if !artifacts.is_empty() {
@@ -716,6 +1591,8 @@ impl<'a> JobTask<'a> {
                                 self.jobdef.job.package().version())
                         })?;
                 }
+                self.status.job_done(self.jobdef.job.uuid(), 0);
+                self.metrics.job_reused(self.jobdef.job.uuid());
                 self.bar.finish_with_message(format!("[{} {} {}] Reusing artifact",
                     self.jobdef.job.uuid(),
                     self.jobdef.job.package().name(),
@@ -724,44 +1601,179 @@ impl<'a> JobTask<'a> {
             }
         }
 
-        // Map the list of received dependencies from
-        //      Vec<(Uuid, Vec<ArtifactPath>)>
-        // to
-        //      Vec<ArtifactPath>
-        let dependency_artifacts = received_dependencies
-            .values()
-            .map(|v| v.iter())
-            .flatten()
-            .map(ProducedArtifact::borrow)
-            .cloned()
-            .collect::<Vec<ArtifactPath>>();
+        // Failing to explain the rebuild must not fail the job
+        if let Err(e) = self.record_rebuild_reasons(any_dependency_was_built, forced, &dependency_artifacts).await {
+            warn!("[{}]: Failed to record why the job is built: {:?}", self.jobdef.job.uuid(), e);
+        }
+
         trace!("[{}]: Dependency artifacts = {:?}", self.jobdef.job.uuid(), dependency_artifacts);
-        self.bar.set_message(format!("[{} {} {}]: Preparing...",
+        self.bar.set_message(format!("[{} {} {}]: Verifying inputs...",
             self.jobdef.job.uuid(),
             self.jobdef.job.package().name(),
             self.jobdef.job.package().version()
         ));
 
-        // Create a RunnableJob object
-        let runnable = RunnableJob::build_from_job(
-            self.jobdef.job,
-            self.source_cache,
-            self.config,
-            self.git_author_env,
-            self.git_commit_env,
-            dependency_artifacts)?;
+        // The inputs are verified before an endpoint slot is reserved for the job, so that a slot
+        // is not held while the files are read
+        {
+            let to_verify = {
+                let staging_store = self.staging_store.read().await;
+                dependency_artifacts
+                    .iter()
+                    .zip(dependency_artifact_ids.iter())
+                    .map(|(art, id)| {
+                        crate::endpoint::locate_artifact(art, &staging_store, &self.release_stores)
+                            .map(|path| (art.clone(), path.joined(), *id))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            };
+            verify_input_artifacts(&self.database, to_verify)
+                .await
+                .with_context(|| anyhow!("Verifying input artifacts of job {}", self.jobdef.job.uuid()))?;
+        }
 
-        self.bar.set_message(format!("[{} {} {}]: Scheduling...",
+        self.bar.set_message(format!("[{} {} {}]: Preparing...",
             self.jobdef.job.uuid(),
             self.jobdef.job.package().name(),
             self.jobdef.job.package().version()
         ));
+
         let job_uuid = *self.jobdef.job.uuid();
 
-        // Schedule the job on the scheduler
-        match self.scheduler.schedule_job(runnable, self.bar.clone()).await?.run().await? {
+        // The retry policy of the package wins over the global one
+        let retry_policy = self.jobdef.job.package()
+            .retry()
+            .as_ref()
+            .or_else(|| self.config.retry().as_ref());
+        let mut failed_endpoints: Vec<EndpointName> = Vec::new();
+        let mut attempt = 0;
+
+        // Fetched only once, so that the commands of the secrets do not run again for each retry
+        let secrets = fetch_secrets(self.jobdef.job.package(), self.config.secrets())
+            .await
+            .with_context(|| {
+                anyhow!(
+                    "Fetching the secrets of package {} {}",
+                    self.jobdef.job.package().name(),
+                    self.jobdef.job.package().version()
+                )
+            })?;
+
+        let result = loop {
+            // Create a RunnableJob object
+            let mut runnable = RunnableJob::build_from_job(
+                self.jobdef.job,
+                self.source_cache,
+                self.config,
+                self.git_author_env,
+                self.git_commit_env,
+                dependency_artifacts.clone(),
+                secrets.clone(),
+                self.hermetic)?;
+
+            // Every run of the job is recorded as a job of its own in the database
+            if attempt > 0 {
+                runnable.renew_uuid();
+            }
+            let run_uuid = *runnable.uuid();
+            let job_timeout = runnable.limits().timeout_duration()?;
+
+            self.bar.set_message(format!("[{} {} {}]: Scheduling...",
+                self.jobdef.job.uuid(),
+                self.jobdef.job.package().name(),
+                self.jobdef.job.package().version()
+            ));
+
+            // Cancelled if the run times out, which stops the run and removes its container
+            let run_cancellation = self.cancellation.child_token();
+
+            // Schedule the job on the scheduler, preferring endpoints the job did not fail on yet
+            let job_handle = tokio::select! {
+                h = self.scheduler.schedule_job(runnable, self.bar.clone(), &failed_endpoints, run_cancellation.clone()) => h?,
+                _ = self.cancellation.cancelled() => return Err(anyhow!("Job {} cancelled", self.jobdef.job.uuid())),
+                timeout = deadline_passed(self.deadline.as_ref()) => break Ok(Err(self.timeout_error(timeout, None))),
+            };
+            let endpoint_name = job_handle.endpoint_name().clone();
+            self.status.job_running(&job_uuid);
+            self.metrics.job_running(&job_uuid);
+
+            let run = job_handle.run();
+            tokio::pin!(run);
+            let mut timed_out = false;
+            let run_result = match run_limit(job_timeout, self.deadline.as_ref(), tokio::time::Instant::now()) {
+                None => run.await,
+                Some((limit, timeout)) => tokio::select! {
+                    r = &mut run => r,
+                    _ = tokio::time::sleep(limit) => {
+                        warn!("[{}]: {}, killing run {} on {}", self.jobdef.job.uuid(), timeout, run_uuid, endpoint_name);
+                        timed_out = true;
+                        run_cancellation.cancel();
+                        match run.await {
+                            Ok(Ok(artifacts)) => Ok(Ok(artifacts)),
+                            Ok(Err(e)) | Err(e) => Ok(Err(self.timeout_error(timeout, Some(e)))),
+                        }
+                    },
+                },
+            };
+
+            // A run that timed out is not retried, it would most likely time out again
+            let policy = match retry_policy {
+                Some(policy) if attempt < policy.retries() && !self.cancellation.is_cancelled() && !timed_out => policy,
+                _ => break run_result,
+            };
+
+            let failure_output = match run_result.as_ref() {
+                Ok(Ok(_)) => break run_result,
+                Ok(Err(e)) => dbmodels::Job::find_by_uuid(&*get_connection(&self.database).await?, &run_uuid)?
+                    .map(|job| job.log_text)
+                    .unwrap_or_else(|| format!("{:?}", e)),
+                Err(e) => format!("{:?}", e),
+            };
+
+            if !policy.should_retry(&failure_output)? {
+                break run_result;
+            }
+
+            attempt += 1;
+            let backoff = policy.backoff(attempt);
+            warn!("[{}]: Run {} on {} failed, retrying in {}s ({}/{})",
+                self.jobdef.job.uuid(),
+                run_uuid,
+                endpoint_name,
+                backoff.as_secs(),
+                attempt,
+                policy.retries());
+
+            self.bar.set_position(0);
+            self.bar.set_message(format!("[{} {} {}]: Retrying ({}/{})...",
+                self.jobdef.job.uuid(),
+                self.jobdef.job.package().name(),
+                self.jobdef.job.package().version(),
+                attempt,
+                policy.retries()
+            ));
+
+            failed_endpoints.push(endpoint_name);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {},
+                _ = self.cancellation.cancelled() => return Err(anyhow!("Job {} cancelled", self.jobdef.job.uuid())),
+                _ = deadline_passed(self.deadline.as_ref()) => break run_result,
+            }
+        };
+
+        match result? {
             Err(e) => {
                 trace!("[{}]: Scheduler returned error = {:?}", self.jobdef.job.uuid(), e);
+                self.status.job_failed(&job_uuid);
+                self.metrics.job_failed(&job_uuid);
+                if let Some(notifier) = self.notifier {
+                    notifier.job_failed(job_uuid,
+                        self.jobdef.job.package().name().to_string(),
+                        self.jobdef.job.package().version().to_string(),
+                        self.jobdef.job.package().maintainer().clone(),
+                        &e).await;
+                }
+
                 // ... and we send that to our parent
                 //
                 // We only send to one parent, because it doesn't matter anymore
@@ -782,9 +1794,11 @@ impl<'a> JobTask<'a> {
             // it returns the database artifact objects it created!
             Ok(artifacts) => {
                 trace!("[{}]: Scheduler returned artifacts = {:?}", self.jobdef.job.uuid(), artifacts);
+                self.status.job_done(&job_uuid, artifacts.len());
+                self.metrics.job_succeeded(&job_uuid);
 
                 // mark the produced artifacts as "built" (rather than reused)
-                let artifacts = artifacts.into_iter().map(ProducedArtifact::Built).collect();
+                let artifacts = artifacts.into_iter().map(|(a, id)| ProducedArtifact::Built(a, id)).collect();
 
                 received_dependencies.insert(*self.jobdef.job.uuid(), artifacts);
                 for s in self.sender.iter() {
@@ -797,6 +1811,21 @@ impl<'a> JobTask<'a> {
         Ok(())
     }
 
+    /// The error of the job if it was killed (or not started) because of `timeout`
+    ///
+    /// `cause` is the error the run of the job failed with after it was killed.
+    fn timeout_error(&self, timeout: Timeout, cause: Option<Error>) -> Error {
+        let error = match cause {
+            Some(cause) => cause.context(timeout),
+            None => Error::from(timeout),
+        };
+
+        error.context(anyhow!("Job {} for {} {} was killed",
+            self.jobdef.job.uuid(),
+            self.jobdef.job.package().name(),
+            self.jobdef.job.package().version()))
+    }
+
     /// Performe a recv() call on the receiving side of the channel
     ///
     /// Put the dependencies you received into the `received_dependencies`, the errors in the
@@ -856,3 +1885,705 @@ impl<'a> JobTask<'a> {
 
 }
 
+
+/// The environment variables of `job`, in addition to the environment of its package
+fn additional_env(
+    job: &crate::job::Job,
+    git_author_env: Option<&(EnvironmentVariableName, String)>,
+    git_commit_env: Option<&(EnvironmentVariableName, String)>,
+) -> Vec<(EnvironmentVariableName, String)> {
+    // Use the environment of the job definition, as it appears in the job DAG.
+    //
+    // This is because we do not have access to the commandline-passed (additional)
+    // environment variables at this point. But using the JobResource::env() variables
+    // works as well.
+    job.resources()
+        .iter()
+        .filter_map(crate::job::JobResource::env)
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .chain(git_author_env.cloned().into_iter())
+        .chain(git_commit_env.cloned().into_iter())
+        .collect()
+}
+
+/// The whole environment `job` is built with, sorted by the names of the variables
+fn job_env(
+    job: &crate::job::Job,
+    git_author_env: Option<&(EnvironmentVariableName, String)>,
+    git_commit_env: Option<&(EnvironmentVariableName, String)>,
+) -> Vec<(EnvironmentVariableName, String)> {
+    job.package()
+        .environment()
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .chain(additional_env(job, git_author_env, git_commit_env))
+        .sorted()
+        .collect()
+}
+
+/// The criteria the artifacts of a job must match to be reused, besides the package itself
+struct ReuseCriteria {
+    /// The environment variables of the job, in addition to the environment of the package
+    additional_env: Vec<(EnvironmentVariableName, String)>,
+
+    /// The digests the image must have had, if the configuration requires them to match
+    image_digests: Option<Vec<String>>,
+
+    /// The hashes of the artifacts of the dependencies and the digests of the image, from which
+    /// the cache key is computed
+    cache_key: Option<(Vec<HashValue>, Vec<Option<String>>)>,
+}
+
+/// The criteria the artifacts of `job` must have been built with to be reused
+///
+/// The cache key is only checked if the artifacts of the dependencies of the job are known.
+#[allow(clippy::too_many_arguments)]
+async fn reuse_criteria(
+    job: &crate::job::Job,
+    config: &Configuration,
+    git_author_env: Option<&(EnvironmentVariableName, String)>,
+    git_commit_env: Option<&(EnvironmentVariableName, String)>,
+    scheduler: &EndpointScheduler,
+    staging_store: &StagingStore,
+    release_stores: &[Arc<ReleaseStore>],
+    dependency_artifacts: Option<&[ArtifactPath]>,
+) -> Result<ReuseCriteria> {
+    let additional_env = additional_env(job, git_author_env, git_commit_env);
+
+    let digest_match = config.docker().reuse_requires_image_digest_match();
+    let digests = if digest_match || dependency_artifacts.is_some() {
+        Some(scheduler.image_digests(job.image(), &config.docker().image_reference(job.image())).await?)
+    } else {
+        None
+    };
+
+    let image_digests = digests
+        .as_ref()
+        .filter(|_| digest_match)
+        .map(|digests| digests.iter().flatten().cloned().collect());
+
+    let cache_key = match dependency_artifacts {
+        Some(artifacts) => {
+            let hashes = async {
+                let paths = crate::job::locate_artifacts(artifacts, staging_store, release_stores)?;
+                crate::job::hash_artifacts(paths).await
+            }
+            .await
+            .with_context(|| anyhow!("Hashing dependency artifacts of job {}", job.uuid()))?;
+            Some((hashes, digests.clone().unwrap_or_default()))
+        },
+        None => None,
+    };
+
+    Ok(ReuseCriteria { additional_env, image_digests, cache_key })
+}
+
+/// The query for artifacts of jobs that look very similar to `job`
+#[allow(clippy::too_many_arguments)]
+fn find_artifacts_like<'a>(
+    job: &'a crate::job::Job,
+    config: &'a Configuration,
+    database_connection: &'a PgConnection,
+    staging_store: &'a StagingStore,
+    release_stores: &'a [Arc<ReleaseStore>],
+    hermetic: bool,
+    reuse_policy: &'a ReusePolicy,
+    use_artifacts_from: Option<&'a dbmodels::Submit>,
+    criteria: &'a ReuseCriteria,
+) -> crate::db::FindArtifacts<'a> {
+    crate::db::FindArtifacts::builder()
+        .database_connection(database_connection)
+        .config(config)
+        .package(job.package())
+        .release_stores(release_stores)
+        .image_name(Some(job.image()))
+        .image_digests(criteria.image_digests.as_deref())
+        .cache_key({
+            criteria.cache_key
+                .as_ref()
+                .map(|(dependency_hashes, image_digests)| crate::db::CacheKeyInputs {
+                    dependency_hashes: dependency_hashes.as_slice(),
+                    image_digests: image_digests.as_slice(),
+                })
+        })
+        .hermetic_only(hermetic)
+        .reuse_policy(Some(reuse_policy))
+        .architecture(Some(job.architecture().as_ref()))
+        .preferred_submit(use_artifacts_from)
+
+        // We can simply pass the staging store here, because it doesn't hurt. There are
+        // two scenarios:
+        //
+        // 1. We are in a fresh build for a package. In this case, the artifacts for this
+        //    very build are not in there yet, and there won't be any artifacts from the
+        //    staging store (possibly from the release store, which would be fine).
+        // 2. We are in a re-build, where the user passed the staging store to the build
+        //    subcommand. In this case, there might be an artifact for this job in the
+        //    staging store. In this case, we want to use it as a replacement, of course.
+        //
+        // The fact that released artifacts are returned prefferably from this function
+        // call does not change anything, because if there is an artifact that's a released
+        // one that matches this job, we should use it anyways.
+        .staging_store(Some(staging_store))
+        .env_filter(&criteria.additional_env)
+        .script_filter(true)
+        .build()
+}
+
+/// Find artifacts that can be reused instead of building `job`
+///
+/// This checks whether a job that looks very similar to `job` has already produced artifacts that
+/// are still available in the staging store or one of the release stores.
+/// `dependency_artifacts` are the artifacts the job would be built with. If they are passed, only
+/// artifacts of jobs with the same cache key are reused.
+#[allow(clippy::too_many_arguments)]
+async fn find_replacement_artifacts(
+    job: &crate::job::Job,
+    config: &Configuration,
+    git_author_env: Option<&(EnvironmentVariableName, String)>,
+    git_commit_env: Option<&(EnvironmentVariableName, String)>,
+    scheduler: &EndpointScheduler,
+    staging_store: &StagingStore,
+    release_stores: &[Arc<ReleaseStore>],
+    database: DbPool,
+    hermetic: bool,
+    reuse_policy: &ReusePolicy,
+    use_artifacts_from: Option<&dbmodels::Submit>,
+    dependency_artifacts: Option<&[ArtifactPath]>,
+) -> Result<Vec<(ArtifactPath, i32)>> {
+    let criteria = reuse_criteria(
+        job,
+        config,
+        git_author_env,
+        git_commit_env,
+        scheduler,
+        staging_store,
+        release_stores,
+        dependency_artifacts)
+        .await?;
+    let database_connection = get_connection(&database).await?;
+
+    let replacement_artifacts = find_artifacts_like(
+        job,
+        config,
+        &*database_connection,
+        staging_store,
+        release_stores,
+        hermetic,
+        reuse_policy,
+        use_artifacts_from,
+        &criteria)
+        .run_with_artifacts()?;
+
+    debug!("[{}]: Found {} replacement artifacts", job.uuid(), replacement_artifacts.len());
+    trace!("[{}]: Found replacement artifacts: {:?}", job.uuid(), replacement_artifacts);
+    let artifacts = replacement_artifacts
+        .into_iter()
+
+        // First of all, we sort by whether the artifact path is in the staging store,
+        // because we prefer staging store artifacts at this point.
+        .sorted_by(|(_, p1, _), (_, p2, _)| {
+            let r1 = p1.is_in_staging_store(staging_store);
+            let r2 = p2.is_in_staging_store(staging_store);
+            r1.cmp(&r2)
+        })
+
+        // We don't need duplicates here, so remove them by making the iterator unique
+        // If we have two artifacts that are the same, the one in the staging store will be
+        // preffered in the next step
+        .unique_by(|tpl| tpl.1.artifact_path().clone())
+
+        // Fetch the artifact from the staging store, if there is one and the reuse policy does
+        // not require released artifacts.
+        // If there is none, try the release store.
+        // If there is none, there won't be a replacement artifact
+        .filter_map(|(artifact, full_artifact_path, _)| {
+            trace!("Searching for {:?} in stores", full_artifact_path.display());
+            let staged = if reuse_policy.release_only() || reuse_policy.signed_only() {
+                None
+            } else {
+                staging_store
+                    .get(full_artifact_path.artifact_path())
+                    .map(|ap| (ap, staging_store.root_path()))
+            };
+
+            staged
+                .or_else(|| {
+                    release_stores
+                        .iter()
+                        .find_map(|rs| rs.get(full_artifact_path.artifact_path()).map(|ap| (ap, rs.root_path())))
+                })
+                .map(|(ap, root)| (ap, root, artifact.id))
+        })
+        .map(|(ap, root, id)| root.join(ap).map(|full| full.map(|full| (ap.clone(), full.joined(), id))))
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<(ArtifactPath, PathBuf, i32)>>>()?;
+
+    // The connection is needed again to verify the artifacts
+    drop(database_connection);
+    verify_reused_artifacts(&database, artifacts).await
+}
+
+/// Verify the artifacts which are about to be reused against the checksums recorded when they
+/// were built
+///
+/// Every artifact is checked against the checksum of the database row with the id it comes with.
+/// A corrupted artifact is not reused, so that it is built again. Artifacts from before checksums
+/// were recorded cannot be verified.
+async fn verify_reused_artifacts(database: &DbPool, artifacts: Vec<(ArtifactPath, PathBuf, i32)>) -> Result<Vec<(ArtifactPath, i32)>> {
+    let database = database.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = database.get()?;
+        artifacts
+            .into_iter()
+            .filter_map(|(ap, path, id)| match dbmodels::ArtifactChecksum::fetch_for_artifact_id(&conn, id) {
+                Err(e) => Some(Err(e)),
+                Ok(None) => {
+                    trace!("No checksum recorded for artifact {}", ap.display());
+                    Some(Ok((ap, id)))
+                },
+                Ok(Some((checksum, _))) => {
+                    if checksum.verify(&path).is_ok() {
+                        Some(Ok((ap, id)))
+                    } else {
+                        warn!("Not reusing corrupted artifact {}", ap.display());
+                        None
+                    }
+                },
+            })
+            .collect()
+    })
+    .await
+    .context("Verifying reused artifacts")?
+}
+
+/// Verify the artifacts of the dependencies of a job before they are copied into its container
+///
+/// Every artifact has to match the checksum of the database row with the id it comes with, which
+/// was recorded when it was built, so that a corrupted artifact does not result in a subtly broken
+/// build. Artifacts from before checksums were recorded cannot be verified.
+async fn verify_input_artifacts(database: &DbPool, artifacts: Vec<(ArtifactPath, PathBuf, i32)>) -> Result<()> {
+    let database = database.clone();
+
+    // The files are hashed without holding the lock on the staging store and not on the threads
+    // of the runtime
+    tokio::task::spawn_blocking(move || {
+        let conn = database.get()?;
+        for (art, path, id) in artifacts {
+            match dbmodels::ArtifactChecksum::fetch_for_artifact_id(&conn, id)? {
+                None => trace!("No checksum recorded for input artifact {}", art.display()),
+                Some((checksum, job)) => checksum
+                    .verify(&path)
+                    .with_context(|| anyhow!("Corrupted input artifact {} from job {}", art.display(), job.uuid))?,
+            }
+        }
+        Ok(())
+    })
+    .await
+    .context("Verifying input artifacts")?
+}
+
+/// Explain why no artifacts were found that can be reused instead of building `job`
+///
+/// Returns the earlier jobs of the package whose artifacts were not reused, with the criteria
+/// they did not match.
+#[allow(clippy::too_many_arguments)]
+async fn explain_rebuild(
+    job: &crate::job::Job,
+    config: &Configuration,
+    git_author_env: Option<&(EnvironmentVariableName, String)>,
+    git_commit_env: Option<&(EnvironmentVariableName, String)>,
+    scheduler: &EndpointScheduler,
+    staging_store: &StagingStore,
+    release_stores: &[Arc<ReleaseStore>],
+    database: DbPool,
+    hermetic: bool,
+    reuse_policy: &ReusePolicy,
+    use_artifacts_from: Option<&dbmodels::Submit>,
+    dependency_artifacts: Option<&[ArtifactPath]>,
+) -> Result<Vec<ReuseCandidate>> {
+    let criteria = reuse_criteria(
+        job,
+        config,
+        git_author_env,
+        git_commit_env,
+        scheduler,
+        staging_store,
+        release_stores,
+        dependency_artifacts)
+        .await?;
+    let database_connection = get_connection(&database).await?;
+
+    find_artifacts_like(
+        job,
+        config,
+        &*database_connection,
+        staging_store,
+        release_stores,
+        hermetic,
+        reuse_policy,
+        use_artifacts_from,
+        &criteria)
+        .explain()
+}
+
+/// Find the artifacts the jobs of `submit` produced that are still in the staging store
+fn load_resumed_artifacts(database: &PgConnection, submit: &dbmodels::Submit, staging_store: &StagingStore) -> Result<ResumedArtifacts> {
+    use diesel::ExpressionMethods;
+    use diesel::QueryDsl;
+    use diesel::RunQueryDsl;
+    use crate::schema;
+
+    let artifacts = schema::artifacts::table
+        .inner_join(schema::jobs::table.inner_join(schema::packages::table))
+        .filter(schema::jobs::submit_id.eq(submit.id))
+        .filter(schema::jobs::simulated.eq(false))
+        .select((schema::packages::name, schema::packages::version, schema::jobs::architecture, schema::artifacts::path, schema::artifacts::id))
+        .load::<(String, String, Option<String>, String, i32)>(database)
+        .with_context(|| anyhow!("Loading artifacts of submit {}", submit.uuid))?;
+
+    let resumed = artifacts
+        .into_iter()
+        .map(|(name, version, architecture, path, id)| {
+            let key = (PackageName::from(name), PackageVersion::from(version), architecture.map(Architecture::from));
+            ArtifactPath::new(PathBuf::from(path))
+                .map(|path| staging_store.get(&path).cloned())
+                .map(|path| path.map(|p| (key, (p, id))))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .into_group_map();
+
+    debug!("Artifacts of {} packages can be reused from submit {}", resumed.len(), submit.uuid);
+    Ok(resumed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::sync::Once;
+
+    use diesel::ExpressionMethods;
+    use diesel::QueryDsl;
+    use diesel::RunQueryDsl;
+    use diesel::r2d2::ConnectionManager;
+    use diesel::r2d2::Pool;
+
+    use crate::config::NotValidatedConfiguration;
+    use crate::filestore::path::StoreRoot;
+    use crate::package::Dependencies;
+    use crate::package::Dependency;
+    use crate::package::Shebang;
+    use crate::package::condition::ConditionData;
+    use crate::package::tests::pname;
+    use crate::package::tests::pversion;
+    use crate::schema;
+
+    diesel_migrations::embed_migrations!("migrations");
+
+    /// The variable with the URL of the database the tests run against, they are skipped if it
+    /// is not set
+    const DATABASE_URL_VAR: &str = "BUTIDO_TEST_DATABASE_URL";
+
+    static MIGRATIONS: Once = Once::new();
+
+    fn test_database() -> Option<DbPool> {
+        let url = match std::env::var(DATABASE_URL_VAR) {
+            Ok(url) => url,
+            Err(_) => {
+                eprintln!("{} is not set, skipping test", DATABASE_URL_VAR);
+                return None;
+            },
+        };
+
+        let pool = Pool::builder().max_size(4).build(ConnectionManager::new(url)).unwrap();
+        MIGRATIONS.call_once(|| embedded_migrations::run(&pool.get().unwrap()).unwrap());
+        Some(pool)
+    }
+
+    /// A configuration with a single mock endpoint and its directories in `dir`, `settings` are
+    /// appended to it
+    fn test_config(dir: &Path, settings: &str) -> Configuration {
+        for subdir in ["logs", "releases", "releases/default", "sources", "staging"].iter() {
+            std::fs::create_dir(dir.join(subdir)).unwrap();
+        }
+
+        let config = format!(r#"
+            compatibility = "*"
+            log_dir = "{dir}/logs"
+            releases_root = "{dir}/releases"
+            release_stores = [ "default" ]
+            staging = "{dir}/staging"
+            source_cache = "{dir}/sources"
+            database_host = "localhost"
+            database_port = 5432
+            database_user = "pgdev"
+            database_password = "password"
+            database_name = "butido"
+            available_phases = [ "build" ]
+
+            [containers]
+            check_env_names = false
+            allowed_env = []
+
+            [docker]
+            verify_images_present = false
+            images = [ "mock-image" ]
+
+            [docker.endpoints.mock]
+            uri = "mock://"
+            endpoint_type = "mock"
+            maxjobs = 2
+
+            {settings}
+            "#,
+            dir = dir.display(),
+            settings = settings);
+
+        toml::from_str::<NotValidatedConfiguration>(&config)
+            .unwrap()
+            .validate()
+            .unwrap()
+    }
+
+    /// A package without sources, optionally depending on `dependency`
+    fn package(name: &str, dependency: Option<&str>) -> Package {
+        let mut package = Package::new(pname(name), pversion("1"), false, HashMap::new(), Dependencies::empty());
+        if let Some(dependency) = dependency {
+            let dependency = Dependency::from(format!("{} =1", dependency));
+            package.set_dependencies(Dependencies::with_runtime_dependency(dependency));
+        }
+        package
+    }
+
+    /// Build `packages` (the first one is the root of the DAG) in a new submit on the mock
+    /// endpoint of `config`, with the staging directory `staging` (like `--staging-dir`)
+    async fn build(
+        config: &Configuration,
+        database: &DbPool,
+        packages: Vec<Package>,
+        staging: &str,
+        timeout: Option<Duration>,
+    ) -> (dbmodels::Submit, Vec<ArtifactPath>, HashMap<Uuid, Error>) {
+        let root = packages[0].clone();
+        let repo = packages
+            .into_iter()
+            .map(|p| ((p.name().clone(), p.version().clone()), p))
+            .collect::<BTreeMap<_, _>>();
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+            image_info: None,
+        };
+        let dag = crate::package::Dag::for_root_package(root.clone(), &crate::repository::Repository::from(repo), None, &condition_data).unwrap();
+
+        let image = ImageName::from(String::from("mock-image"));
+        let submit = {
+            let conn = database.get().unwrap();
+            dbmodels::Submit::create(
+                &conn,
+                &chrono::offset::Local::now().naive_local(),
+                &Uuid::new_v4(),
+                &dbmodels::Image::create_or_fetch(&conn, &image).unwrap(),
+                &dbmodels::Package::create_or_fetch(&conn, &root).unwrap(),
+                &dbmodels::GitHash::create_or_fetch(&conn, "0000000000000000000000000000000000000000").unwrap(),
+            )
+            .unwrap()
+        };
+
+        let staging_dir = config.staging_directory().join(staging);
+        std::fs::create_dir_all(&staging_dir).unwrap();
+        let staging_store = StagingStore::load(StoreRoot::new(staging_dir).unwrap(), &ProgressBar::hidden()).unwrap();
+
+        let endpoint_config = config
+            .docker()
+            .endpoints()
+            .iter()
+            .map(|(ep_name, ep_cfg)| {
+                EndpointConfiguration::builder()
+                    .endpoint_name(ep_name.clone())
+                    .endpoint(ep_cfg.clone())
+                    .max_jobs(ep_cfg.maxjobs())
+                    .build()
+            })
+            .collect();
+
+        let jobdag = Dag::from_package_dag(dag,
+            Shebang::from(config.shebang().clone()),
+            image,
+            config.available_phases().clone(),
+            vec![]);
+
+        let orchestrator = OrchestratorSetup::builder()
+            .progress_generator(ProgressBars::setup(config.progress_format().clone(), config.spinner_format().clone(), true))
+            .endpoint_config(endpoint_config)
+            .staging_store(Arc::new(RwLock::new(staging_store)))
+            .release_stores(vec![])
+            .source_cache(SourceCache::new(config.source_cache_root().clone()))
+            .jobdag(jobdag)
+            .database(database.clone())
+            .submit(Some(submit.clone()))
+            .log_dir(None)
+            .timeout(timeout)
+            .config(config)
+            .repository(Repository::init(config.log_dir().join("repo")).unwrap())
+            .build()
+            .setup()
+            .await
+            .unwrap();
+
+        let mut output = vec![];
+        let errors = orchestrator.run(&mut output).await.unwrap();
+        (submit, output, errors)
+    }
+
+    /// The number of jobs of `submit` and how many of them were simulated
+    fn count_jobs(database: &DbPool, submit: &dbmodels::Submit) -> (usize, usize) {
+        let simulated = schema::jobs::table
+            .filter(schema::jobs::submit_id.eq(submit.id))
+            .select(schema::jobs::simulated)
+            .load::<bool>(&database.get().unwrap())
+            .unwrap();
+        (simulated.len(), simulated.into_iter().filter(|s| *s).count())
+    }
+
+    #[tokio::test]
+    async fn test_mock_build_is_not_reused() {
+        let database = match test_database() {
+            Some(database) => database,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), r#"
+            [docker.endpoints.mock.mock]
+            min_duration = "10ms"
+            "#);
+        let packages = || vec![package("mock-reuse-a", Some("mock-reuse-b")), package("mock-reuse-b", None)];
+
+        let (submit, output, errors) = build(&config, &database, packages(), "shared", None).await;
+        assert!(errors.is_empty(), "{}", errors.display_error_map());
+        let mut names = output.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["mock-reuse-a-1.tar.gz", "mock-reuse-b-1.tar.gz"]);
+        assert_eq!(count_jobs(&database, &submit), (2, 2));
+
+        // The fake artifacts of the first submit are in the staging directory, but not reused
+        let (submit, output, errors) = build(&config, &database, packages(), "shared", None).await;
+        assert!(errors.is_empty(), "{}", errors.display_error_map());
+        assert_eq!(output.len(), 2);
+        assert_eq!(count_jobs(&database, &submit), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_mock_failure_is_retried() {
+        let database = match test_database() {
+            Some(database) => database,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), r#"
+            [docker.endpoints.mock.mock]
+            min_duration = "10ms"
+            failure_rate = 1.0
+
+            [retry]
+            retries = 2
+            "#);
+        let packages = vec![package("mock-retry-a", Some("mock-retry-b")), package("mock-retry-b", None)];
+
+        let (submit, output, errors) = build(&config, &database, packages, "retry", None).await;
+        assert!(output.is_empty());
+        assert_eq!(errors.len(), 1);
+
+        // Every run of the failing job is recorded, its dependant is not built
+        assert_eq!(count_jobs(&database, &submit), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn test_mock_build_is_cancelled_by_timeout() {
+        let database = match test_database() {
+            Some(database) => database,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), r#"
+            [docker.endpoints.mock.mock]
+            min_duration = "1h"
+            "#);
+
+        let started = std::time::Instant::now();
+        let (submit, output, errors) = build(&config, &database, vec![package("mock-timeout-a", None)], "timeout", Some(Duration::from_secs(1))).await;
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert!(output.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(count_jobs(&database, &submit), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_mock_secrets_are_removed_before_stop() {
+        let database = match test_database() {
+            Some(database) => database,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), r#"
+            [docker.endpoints.mock.mock]
+            min_duration = "10ms"
+
+            [secrets]
+            token = { command = "echo hunter2" }
+            "#);
+        let mut package = package("mock-secret-a", None);
+        package.set_secrets(vec![toml::from_str(r#"name = "token""#).unwrap()]);
+
+        // The mock endpoint fails the job if the secret is still in the container when it is stopped
+        let (submit, output, errors) = build(&config, &database, vec![package], "secret", None).await;
+        assert!(errors.is_empty(), "{}", errors.display_error_map());
+        assert_eq!(output.len(), 1);
+        assert_eq!(count_jobs(&database, &submit), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_input_artifact_is_verified_against_its_own_checksum() {
+        let database = match test_database() {
+            Some(database) => database,
+            None => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(dir.path(), r#"
+            [docker.endpoints.mock.mock]
+            min_duration = "10ms"
+            "#);
+
+        let (submit, output, errors) = build(&config, &database, vec![package("mock-verify-a", None)], "verify", None).await;
+        assert!(errors.is_empty(), "{}", errors.display_error_map());
+        let art_path = output[0].clone();
+        let path = config.staging_directory().join("verify").join(art_path.as_ref() as &Path);
+
+        // The job of another submit produced an artifact at the same path, with different contents
+        let (other_submit, _, errors) = build(&config, &database, vec![package("mock-verify-a", None)], "verify-other", None).await;
+        assert!(errors.is_empty(), "{}", errors.display_error_map());
+        let artifact_of = |submit: &dbmodels::Submit| {
+            schema::artifacts::table
+                .inner_join(schema::jobs::table)
+                .filter(schema::jobs::submit_id.eq(submit.id))
+                .select(schema::artifacts::all_columns)
+                .first::<dbmodels::Artifact>(&database.get().unwrap())
+                .unwrap()
+        };
+        let built = artifact_of(&submit);
+        let other = artifact_of(&other_submit);
+        diesel::update(schema::artifact_checksums::table.filter(schema::artifact_checksums::artifact_id.eq(other.id)))
+            .set(schema::artifact_checksums::checksum.eq("00"))
+            .execute(&database.get().unwrap())
+            .unwrap();
+
+        verify_input_artifacts(&database, vec![(art_path.clone(), path.clone(), built.id)]).await.unwrap();
+        assert!(verify_input_artifacts(&database, vec![(art_path, path, other.id)]).await.is_err());
+    }
+}
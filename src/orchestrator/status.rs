@@ -0,0 +1,184 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! The summary line at the bottom of the progress output of a submit
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use uuid::Uuid;
+
+use crate::endpoint::EndpointScheduler;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JobState {
+    Waiting,
+    Running(Instant),
+    Done,
+
+    /// The job failed or was not run because one of its dependencies failed
+    Failed,
+}
+
+/// The state of all jobs of a submit, shown in a status line
+pub struct SubmitStatus {
+    started: Instant,
+    jobs: Mutex<HashMap<Uuid, JobState>>,
+    artifacts: Mutex<usize>,
+
+    /// How long the jobs are expected to run, as far as it is known from earlier runs
+    expected_durations: HashMap<Uuid, Duration>,
+}
+
+impl SubmitStatus {
+    /// Create the status for the passed jobs, with the durations they are expected to take
+    pub fn new<I>(jobs: I) -> Self
+    where
+        I: IntoIterator<Item = (Uuid, Option<Duration>)>,
+    {
+        let mut states = HashMap::new();
+        let mut expected_durations = HashMap::new();
+        for (uuid, expected) in jobs {
+            states.insert(uuid, JobState::Waiting);
+            if let Some(expected) = expected {
+                expected_durations.insert(uuid, expected);
+            }
+        }
+
+        SubmitStatus {
+            started: Instant::now(),
+            jobs: Mutex::new(states),
+            artifacts: Mutex::new(0),
+            expected_durations,
+        }
+    }
+
+    pub fn job_running(&self, uuid: &Uuid) {
+        self.set_state(uuid, JobState::Running(Instant::now()))
+    }
+
+    pub fn job_done(&self, uuid: &Uuid, produced_artifacts: usize) {
+        self.set_state(uuid, JobState::Done);
+        *self.artifacts.lock().unwrap() += produced_artifacts;
+    }
+
+    pub fn job_failed(&self, uuid: &Uuid) {
+        self.set_state(uuid, JobState::Failed)
+    }
+
+    fn set_state(&self, uuid: &Uuid, state: JobState) {
+        self.jobs.lock().unwrap().insert(*uuid, state);
+    }
+
+    /// The text of the status line
+    pub fn message(&self, scheduler: &EndpointScheduler) -> String {
+        let jobs = self.jobs.lock().unwrap();
+        let count = |f: fn(&JobState) -> bool| jobs.values().filter(|s| f(s)).count();
+        let (busy, free) = scheduler.endpoint_utilization();
+
+        format!("Jobs: {} waiting, {} running, {} done, {} failed | Endpoints: {} busy, {} free, {} jobs queued | Artifacts: {} | Elapsed: {} | ETA: {}",
+            count(|s| *s == JobState::Waiting),
+            count(|s| matches!(s, JobState::Running(_))),
+            count(|s| *s == JobState::Done),
+            count(|s| *s == JobState::Failed),
+            busy,
+            free,
+            scheduler.queued_jobs(),
+            *self.artifacts.lock().unwrap(),
+            format_duration(self.started.elapsed()),
+            self.eta(&jobs, scheduler.job_slots())
+                .map(format_duration)
+                .unwrap_or_else(|| String::from("unknown")))
+    }
+
+    /// Estimate how long the jobs that are not done yet will take, see `eta()`
+    pub fn estimate(&self, slots: usize) -> Option<Duration> {
+        let jobs = self.jobs.lock().unwrap();
+        self.eta(&jobs, slots)
+    }
+
+    /// Estimate how long the remaining jobs will take
+    ///
+    /// Jobs of packages which were never built before are expected to take as long as the average
+    /// job. If no package was built before, there is no estimate.
+    fn eta(&self, jobs: &HashMap<Uuid, JobState>, slots: usize) -> Option<Duration> {
+        let default_duration = if self.expected_durations.is_empty() {
+            None
+        } else {
+            Some(self.expected_durations.values().sum::<Duration>() / self.expected_durations.len() as u32)
+        };
+
+        let mut remaining_work = Duration::from_secs(0);
+        let mut longest_running = Duration::from_secs(0);
+        for (uuid, state) in jobs.iter() {
+            let expected = self.expected_durations.get(uuid).copied().or(default_duration)?;
+
+            match state {
+                JobState::Waiting => remaining_work += expected,
+                JobState::Running(since) => {
+                    let remaining = expected.checked_sub(since.elapsed()).unwrap_or_default();
+                    remaining_work += remaining;
+                    longest_running = longest_running.max(remaining);
+                }
+                JobState::Done | JobState::Failed => {}
+            }
+        }
+
+        // The remaining jobs are (optimistically) spread over all job slots, but the submit cannot
+        // be done before the longest running job finished
+        Some((remaining_work / slots.max(1) as u32).max(longest_running))
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "00:00:00");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 61)), "03:01:01");
+    }
+
+    #[test]
+    fn test_eta() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        let c = Uuid::new_v4();
+        let status = SubmitStatus::new(vec![
+            (a, Some(Duration::from_secs(100))),
+            (b, Some(Duration::from_secs(300))),
+            (c, None),
+        ]);
+
+        // c is expected to take as long as the average job
+        let jobs = status.jobs.lock().unwrap().clone();
+        assert_eq!(status.eta(&jobs, 1), Some(Duration::from_secs(600)));
+        assert_eq!(status.eta(&jobs, 2), Some(Duration::from_secs(300)));
+
+        status.job_done(&a, 1);
+        let jobs = status.jobs.lock().unwrap().clone();
+        assert_eq!(status.eta(&jobs, 1), Some(Duration::from_secs(500)));
+    }
+
+    #[test]
+    fn test_eta_unknown() {
+        let status = SubmitStatus::new(vec![(Uuid::new_v4(), None)]);
+        let jobs = status.jobs.lock().unwrap().clone();
+        assert_eq!(status.eta(&jobs, 1), None);
+    }
+}
@@ -0,0 +1,111 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Timeouts for the jobs of a submit and for the submit as a whole
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// The error of a job that was killed (or not started) because of a timeout
+///
+/// Jobs that fail because of a timeout are reported apart from the jobs that failed on their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeout {
+    /// The job ran longer than its timeout
+    Job(Duration),
+
+    /// The submit did not finish within its timeout
+    Submit(Duration),
+}
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timeout::Job(d) => write!(f, "Job did not finish within its timeout of {}", humantime::format_duration(*d)),
+            Timeout::Submit(d) => write!(f, "Submit did not finish within its timeout of {}", humantime::format_duration(*d)),
+        }
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// The point in time at which all jobs of a submit that did not finish yet are killed
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    /// The deadline `timeout` after now
+    pub(super) fn after(timeout: Duration) -> Self {
+        Deadline {
+            at: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    /// Wait until the deadline passed
+    pub(super) async fn passed(&self) -> Timeout {
+        tokio::time::sleep_until(self.at).await;
+        Timeout::Submit(self.timeout)
+    }
+}
+
+/// Wait until the deadline passed, never finishes if there is no deadline
+pub(super) async fn deadline_passed(deadline: Option<&Deadline>) -> Timeout {
+    match deadline {
+        Some(deadline) => deadline.passed().await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// How long a job started at `now` may run, and the timeout it fails with afterwards
+///
+/// This is the job timeout, unless the deadline of the submit passes earlier.
+pub(super) fn run_limit(job_timeout: Option<Duration>, deadline: Option<&Deadline>, now: Instant) -> Option<(Duration, Timeout)> {
+    let submit_limit = deadline.map(|d| (d.at.saturating_duration_since(now), Timeout::Submit(d.timeout)));
+    let job_limit = job_timeout.map(|t| (t, Timeout::Job(t)));
+
+    match (job_limit, submit_limit) {
+        (Some(job), Some(submit)) => Some(if submit.0 < job.0 { submit } else { job }),
+        (job, submit) => job.or(submit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_limit() {
+        let now = Instant::now();
+        let hour = Duration::from_secs(3600);
+        let deadline = Deadline { at: now + 2 * hour, timeout: 3 * hour };
+
+        assert_eq!(run_limit(None, None, now), None);
+        assert_eq!(run_limit(Some(hour), None, now), Some((hour, Timeout::Job(hour))));
+        assert_eq!(run_limit(None, Some(&deadline), now), Some((2 * hour, Timeout::Submit(3 * hour))));
+        assert_eq!(run_limit(Some(hour), Some(&deadline), now), Some((hour, Timeout::Job(hour))));
+
+        // The deadline passes before the job timeout
+        assert_eq!(
+            run_limit(Some(hour), Some(&deadline), now + hour + hour / 2),
+            Some((hour / 2, Timeout::Submit(3 * hour)))
+        );
+
+        // The deadline already passed
+        assert_eq!(
+            run_limit(Some(hour), Some(&deadline), now + 4 * hour),
+            Some((Duration::from_secs(0), Timeout::Submit(3 * hour)))
+        );
+    }
+}
@@ -24,9 +24,12 @@ use log::trace;
 use ptree::Style;
 use ptree::TreeItem;
 use resiter::AndThen;
+use serde::Deserialize;
+use serde::Serialize;
 
 use crate::package::Package;
 use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::package::PackageVersionConstraint;
 use crate::package::condition::ConditionCheckable;
 use crate::package::condition::ConditionData;
@@ -113,25 +116,19 @@ impl Dag {
             get_package_dependencies(p, conditional_data)
                 .and_then_ok(|(name, constr)| {
                     trace!("Dependency for {} {} found: {:?}", p.name(), p.version(), name);
-                    let packs = repo.find_with_version(&name, &constr);
-                    if packs.is_empty() {
-                        return Err(anyhow!("Dependency of {} {} not found: {} {}", p.name(), p.version(), name, constr))
-                    }
-                    trace!("Found in repo: {:?}", packs);
+                    let pack = repo.find_highest_with_version(&name, &constr)
+                        .ok_or_else(|| anyhow!("Dependency of {} {} not found: {} {}", p.name(), p.version(), name, constr))?;
+                    trace!("Found in repo: {:?}", pack);
 
                     // If we didn't check that dependency already
-                    if !mappings.keys().any(|p| packs.iter().any(|pk| pk.name() == p.name() && pk.version() == p.version())) {
-                        // recurse
-                        packs.into_iter()
-                            .try_for_each(|p| {
-                                let _ = progress.as_ref().map(|p| p.tick());
+                    if !mappings.keys().any(|p| pack.name() == p.name() && pack.version() == p.version()) {
+                        let _ = progress.as_ref().map(|p| p.tick());
 
-                                let idx = dag.add_node(p);
-                                mappings.insert(p, idx);
+                        let idx = dag.add_node(pack);
+                        mappings.insert(pack, idx);
 
-                                trace!("Recursing for: {:?}", p);
-                                add_sub_packages(repo, mappings, dag, p, progress, conditional_data)
-                            })
+                        trace!("Recursing for: {:?}", pack);
+                        add_sub_packages(repo, mappings, dag, pack, progress, conditional_data)
                     } else {
                         Ok(())
                     }
@@ -139,7 +136,8 @@ impl Dag {
                 .collect::<Result<()>>()
         }
 
-        fn add_edges(mappings: &HashMap<&Package, daggy::NodeIndex>,
+        fn add_edges(repo: &Repository,
+            mappings: &HashMap<&Package, daggy::NodeIndex>,
             dag: &mut daggy::Dag<&Package, i8>,
             conditional_data: &ConditionData<'_>,
         ) -> Result<()>
@@ -147,9 +145,13 @@ impl Dag {
             for (package, idx) in mappings {
                 get_package_dependencies(package, conditional_data)
                     .and_then_ok(|(name, constr)| {
+                        // The dependency is resolved to the same package as in `add_sub_packages()`
+                        let dep = repo.find_highest_with_version(&name, &constr)
+                            .ok_or_else(|| anyhow!("Dependency of {} {} not found: {} {}", package.name(), package.version(), name, constr))?;
+
                         mappings
                             .iter()
-                            .filter(|(package, _)| *package.name() == name && constr.matches(package.version()))
+                            .filter(|(package, _)| package.name() == dep.name() && package.version() == dep.version())
                             .try_for_each(|(_, dep_idx)| {
                                 dag.add_edge(*idx, *dep_idx, 0)
                                     .map(|_| ())
@@ -169,7 +171,7 @@ impl Dag {
         let root_idx = dag.add_node(&p);
         mappings.insert(&p, root_idx);
         add_sub_packages(repo, &mut mappings, &mut dag, &p, progress, conditional_data)?;
-        add_edges(&mappings, &mut dag, conditional_data)?;
+        add_edges(repo, &mappings, &mut dag, conditional_data)?;
         trace!("Finished makeing package Tree");
 
         Ok(Dag {
@@ -178,6 +180,57 @@ impl Dag {
         })
     }
 
+    /// The result of resolving the dag, without the package definitions
+    pub fn resolution(&self) -> DagResolution {
+        let graph = self.dag.graph();
+        DagResolution {
+            packages: graph
+                .node_indices()
+                .filter_map(|idx| graph.node_weight(idx))
+                .map(|p| (p.name().clone(), p.version().clone()))
+                .collect(),
+            edges: graph
+                .edge_indices()
+                .filter_map(|idx| graph.edge_endpoints(idx))
+                .map(|(from, to)| (from.index(), to.index()))
+                .collect(),
+            root: self.root_idx.index(),
+        }
+    }
+
+    /// Build the dag from a previous resolution, with the package definitions from `repo`
+    ///
+    /// The repository must be the one the resolution was computed with.
+    pub fn from_resolution(resolution: &DagResolution, repo: &Repository) -> Result<Self> {
+        let mut dag: daggy::Dag<Package, i8> = daggy::Dag::new();
+        for (name, version) in resolution.packages.iter() {
+            let package = repo.find(name, version)
+                .into_iter()
+                .next()
+                .cloned()
+                .ok_or_else(|| anyhow!("Package {} {} of resolved dag not found in repository", name, version))?;
+            let _ = dag.add_node(package);
+        }
+
+        for (from, to) in resolution.edges.iter() {
+            // Adding an edge to a node which does not exist panics
+            if *from >= resolution.packages.len() || *to >= resolution.packages.len() {
+                return Err(anyhow!("Edge {} -> {} of resolved dag points to a package that does not exist", from, to));
+            }
+            dag.add_edge(daggy::NodeIndex::new(*from), daggy::NodeIndex::new(*to), 0)
+                .map_err(Error::from)?;
+        }
+
+        if resolution.root >= resolution.packages.len() {
+            return Err(anyhow!("Root of resolved dag does not exist"));
+        }
+
+        Ok(Dag {
+            dag,
+            root_idx: daggy::NodeIndex::new(resolution.root),
+        })
+    }
+
     /// Get all packages in the tree by reference
     ///
     /// # Warning
@@ -196,6 +249,16 @@ impl Dag {
     }
 }
 
+/// The packages and dependencies of a resolved `Dag`, identified by name and version
+///
+/// Node indices in `edges` and `root` are positions in `packages`.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DagResolution {
+    packages: Vec<(PackageName, PackageVersion)>,
+    edges: Vec<(usize, usize)>,
+    root: usize,
+}
+
 #[derive(Clone)]
 pub struct DagDisplay<'a>(&'a Dag, daggy::NodeIndex);
 
@@ -253,6 +316,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
@@ -291,6 +355,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let dag = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
@@ -304,6 +369,69 @@ mod tests {
         assert!(ps.iter().any(|p| *p.version() == pversion("2")));
     }
 
+    #[test]
+    fn test_version_range_resolves_to_highest_version() {
+        let mut btree = BTreeMap::new();
+
+        let mut p1 = package("a", "1", "https://rust-lang.org", "123");
+        btree.insert((pname("a"), pversion("1")), p1.clone());
+        for (vers, hash) in [("1.2", "124"), ("1.10", "125"), ("2.0", "126")].iter() {
+            btree.insert((pname("b"), pversion(vers)), package("b", vers, "https://rust-lang.org", hash));
+        }
+
+        {
+            let d = Dependency::from(String::from("b >=1.2 <2"));
+            let ds = Dependencies::with_runtime_dependency(d);
+            p1.set_dependencies(ds);
+        }
+
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+            image_info: None,
+        };
+
+        let dag = Dag::for_root_package(p1, &repo, None, &condition_data).unwrap();
+        let ps = dag.all_packages();
+        assert_eq!(ps.len(), 2);
+        assert!(ps.iter().any(|p| *p.name() == pname("b") && *p.version() == pversion("1.10")));
+        assert_eq!(dag.dag().edge_count(), 1);
+    }
+
+    #[test]
+    fn test_dag_from_resolution() {
+        let mut btree = BTreeMap::new();
+
+        let mut p1 = package("a", "1", "https://rust-lang.org", "123");
+        p1.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(String::from("b =2"))));
+        btree.insert((pname("a"), pversion("1")), p1.clone());
+        btree.insert((pname("b"), pversion("2")), package("b", "2", "https://rust-lang.org", "124"));
+
+        let repo = Repository::from(btree);
+        let condition_data = ConditionData {
+            image_name: None,
+            env: &[],
+            image_info: None,
+        };
+
+        let dag = Dag::for_root_package(p1, &repo, None, &condition_data).unwrap();
+        let resolution = dag.resolution();
+        let json = serde_json::to_string(&resolution).unwrap();
+        let resolution: DagResolution = serde_json::from_str(&json).unwrap();
+
+        let restored = Dag::from_resolution(&resolution, &repo).unwrap();
+        assert_eq!(restored.resolution(), dag.resolution());
+        assert_eq!(*restored.dag().graph()[*restored.root_idx()].name(), pname("a"));
+
+        let empty = Repository::from(BTreeMap::new());
+        assert!(Dag::from_resolution(&resolution, &empty).is_err());
+
+        let mut corrupt: DagResolution = serde_json::from_str(&json).unwrap();
+        corrupt.edges.push((0, 5));
+        assert!(Dag::from_resolution(&corrupt, &repo).is_err());
+    }
+
     #[test]
     fn test_add_deep_package_tree() {
         let mut btree = BTreeMap::new();
@@ -385,6 +513,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
@@ -533,6 +662,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
@@ -643,6 +773,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let r = Dag::for_root_package(p1, &repo, Some(&progress), &condition_data);
@@ -699,6 +830,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let progress = ProgressBar::hidden();
@@ -733,6 +865,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: Some(&img_name),
             env: &[],
+            image_info: None,
         };
 
         let progress = ProgressBar::hidden();
@@ -763,6 +896,7 @@ mod tests {
         let condition_data = ConditionData {
             image_name: Some(&img_name),
             env: &[],
+            image_info: None,
         };
 
         let progress = ProgressBar::hidden();
@@ -51,6 +51,10 @@ impl ParseDependency for BuildDependency {
     fn parse_as_name_and_version(&self) -> Result<(PackageName, PackageVersionConstraint)> {
         crate::package::dependency::parse_package_dependency_string_into_name_and_version(self.as_ref())
     }
+
+    fn parse_output(&self) -> Result<Option<String>> {
+        crate::package::dependency::parse_package_dependency_string_into_output(self.as_ref())
+    }
 }
 
 #[cfg(test)]
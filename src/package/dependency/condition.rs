@@ -23,8 +23,8 @@ use crate::util::docker::ImageName;
 /// This type represents a condition whether a dependency should be included in the package tree or
 /// not.
 ///
-/// Right now, we are supporting condition by environment (set or equal) or whether a specific
-/// build image is used.
+/// Right now, we are supporting condition by environment (set or equal), whether a specific
+/// build image is used, whether images exist on the endpoints and the labels of the build image.
 /// All these settings are optional, of course.
 ///
 #[derive(Serialize, Deserialize, Getters, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -40,6 +40,16 @@ pub struct Condition {
     #[serde(rename = "in_image", skip_serializing_if = "Option::is_none")]
     #[getset(get = "pub")]
     pub(super) in_image: Option<OneOrMore<String>>,
+
+    /// Images which must all exist on the endpoints the build could run on
+    #[serde(rename = "image_exists", skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    pub(super) image_exists: Option<OneOrMore<String>>,
+
+    /// Labels the build image must have, with these values
+    #[serde(rename = "image_labels", skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    pub(super) image_labels: Option<BTreeMap<String, String>>,
 }
 
 impl Condition {
@@ -49,7 +59,26 @@ impl Condition {
                in_image: Option<OneOrMore<String>>)
         -> Self
     {
-        Condition { has_env, env_eq, in_image }
+        Condition { has_env, env_eq, in_image, image_exists: None, image_labels: None }
+    }
+
+    /// Whether the condition depends on the images on the endpoints or the labels of the build
+    /// image, which have to be queried from the endpoints
+    pub fn needs_image_info(&self) -> bool {
+        self.image_exists.is_some() || self.image_labels.is_some()
+    }
+
+    /// Whether the `in_image` condition can match when building in one of `images`
+    ///
+    /// If it can't, the dependency is never used when building in these images.
+    pub fn can_match_in_images(&self, images: &[&ImageName]) -> bool {
+        match self.in_image.as_ref() {
+            None => true,
+            Some(OneOrMore::One(req_image)) => images.iter().any(|i| i.as_ref() == req_image),
+            Some(OneOrMore::More(req_images)) => req_images
+                .iter()
+                .any(|ri| images.iter().any(|i| i.as_ref() == ri)),
+        }
     }
 
     /// Check whether the condition matches a certain set of data
@@ -70,6 +99,14 @@ impl Condition {
             return Ok(false)
         }
 
+        if !self.matches_image_exists_cond(data)? {
+            return Ok(false)
+        }
+
+        if !self.matches_image_labels_cond(data)? {
+            return Ok(false)
+        }
+
         Ok(true)
     }
 
@@ -144,6 +181,44 @@ impl Condition {
             Ok(true)
         }
     }
+
+    fn matches_image_exists_cond(&self, data: &ConditionData<'_>) -> Result<bool> {
+        if let Some(image_exists_cond) = self.image_exists.as_ref() {
+            // Like with `in_image`, if nothing is known about the images (e.g. in the "tree-of"
+            // subcommand), the images do not exist
+            let exists = |req_image: &String| {
+                let req_image = ImageName::from(req_image.clone());
+                data.image_info
+                    .map(|info| info.images.iter().any(|i| i.is_same_image(&req_image)))
+                    .unwrap_or(false)
+            };
+
+            let b = match image_exists_cond {
+                OneOrMore::One(req_image) => exists(req_image),
+                OneOrMore::More(req_images) => req_images.iter().all(exists),
+            };
+
+            Ok(b)
+        } else {
+            Ok(true)
+        }
+    }
+
+    fn matches_image_labels_cond(&self, data: &ConditionData<'_>) -> Result<bool> {
+        if let Some(image_labels_cond) = self.image_labels.as_ref() {
+            let b = image_labels_cond.iter()
+                .all(|(req_label, req_value)| {
+                    data.image_info
+                        .and_then(|info| info.labels.get(req_label))
+                        .map(|value| value == req_value)
+                        .unwrap_or(false)
+                });
+
+            Ok(b)
+        } else {
+            Ok(true)
+        }
+    }
 }
 
 
@@ -185,6 +260,22 @@ impl From<String> for OneOrMore<String> {
 pub struct ConditionData<'a> {
     pub(crate) image_name: Option<&'a ImageName>,
     pub(crate) env: &'a [(EnvironmentVariableName, String)],
+
+    /// What is known about the images on the endpoints, if they were queried
+    pub(crate) image_info: Option<&'a ImageInfo>,
+}
+
+/// The images on the endpoints and the labels of the build image, for conditions on images
+#[derive(Debug, Default)]
+pub struct ImageInfo {
+    pub(crate) images: Vec<ImageName>,
+    pub(crate) labels: BTreeMap<String, String>,
+}
+
+impl ImageInfo {
+    pub fn new(images: Vec<ImageName>, labels: BTreeMap<String, String>) -> Self {
+        ImageInfo { images, labels }
+    }
 }
 
 /// Trait for all things that have a condition that can be checked against ConditionData.
@@ -199,6 +290,12 @@ pub struct ConditionData<'a> {
 ///
 pub trait ConditionCheckable {
     fn check_condition(&self, data: &ConditionData<'_>) -> Result<bool>;
+
+    /// Whether checking the condition needs `ConditionData::image_info`
+    fn needs_image_info(&self) -> bool;
+
+    /// The condition, if the dependency is a conditional one
+    fn condition(&self) -> Option<&Condition>;
 }
 
 impl ConditionCheckable for crate::package::BuildDependency {
@@ -210,6 +307,20 @@ impl ConditionCheckable for crate::package::BuildDependency {
             crate::package::BuildDependency::Conditional { condition, .. } => condition.matches(data),
         }
     }
+
+    fn needs_image_info(&self) -> bool {
+        match self {
+            crate::package::BuildDependency::Simple(_) => false,
+            crate::package::BuildDependency::Conditional { condition, .. } => condition.needs_image_info(),
+        }
+    }
+
+    fn condition(&self) -> Option<&Condition> {
+        match self {
+            crate::package::BuildDependency::Simple(_) => None,
+            crate::package::BuildDependency::Conditional { condition, .. } => Some(condition),
+        }
+    }
 }
 
 impl ConditionCheckable for crate::package::Dependency {
@@ -221,6 +332,20 @@ impl ConditionCheckable for crate::package::Dependency {
             crate::package::Dependency::Conditional { condition, .. } => condition.matches(data),
         }
     }
+
+    fn needs_image_info(&self) -> bool {
+        match self {
+            crate::package::Dependency::Simple(_) => false,
+            crate::package::Dependency::Conditional { condition, .. } => condition.needs_image_info(),
+        }
+    }
+
+    fn condition(&self) -> Option<&Condition> {
+        match self {
+            crate::package::Dependency::Simple(_) => None,
+            crate::package::Dependency::Conditional { condition, .. } => Some(condition),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -290,6 +415,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new(None, None, None);
@@ -302,6 +428,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new(None, None, {
@@ -317,6 +444,7 @@ mod tests {
         let data = ConditionData {
             image_name: Some(&img),
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new(None, None, {
@@ -332,6 +460,7 @@ mod tests {
         let data = ConditionData {
             image_name: Some(&img),
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new(None, None, {
@@ -346,6 +475,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new({
@@ -360,6 +490,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[(EnvironmentVariableName::from("A"), String::from("1"))],
+            image_info: None,
         };
 
         let condition = Condition::new({
@@ -374,6 +505,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[],
+            image_info: None,
         };
 
         let condition = Condition::new(None, {
@@ -390,6 +522,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[(EnvironmentVariableName::from("A"), String::from("1"))],
+            image_info: None,
         };
 
         let condition = Condition::new(None, {
@@ -406,6 +539,7 @@ mod tests {
         let data = ConditionData {
             image_name: None,
             env: &[(EnvironmentVariableName::from("A"), String::from("1"))],
+            image_info: None,
         };
 
         let condition = Condition::new(None, {
@@ -417,4 +551,61 @@ mod tests {
         assert!(condition.matches(&data).unwrap());
     }
 
+    #[test]
+    fn test_image_labels_deserialization() {
+        let s = r#"image_labels = { distro = "centos7" }"#;
+        let c: Condition = toml::from_str(s).expect("Deserializing image_labels");
+
+        assert!(c.needs_image_info());
+        assert_eq!(c.image_labels.unwrap(), {
+            let mut hm = BTreeMap::new();
+            hm.insert(String::from("distro"), String::from("centos7"));
+            hm
+        });
+    }
+
+    #[test]
+    fn test_condition_image_labels() {
+        let condition: Condition = toml::from_str(r#"image_labels = { distro = "centos7" }"#).unwrap();
+        let matches = |info: Option<&ImageInfo>| {
+            condition.matches(&ConditionData { image_name: None, env: &[], image_info: info }).unwrap()
+        };
+        let info = |distro: &str| {
+            let mut labels = BTreeMap::new();
+            labels.insert(String::from("distro"), String::from(distro));
+            ImageInfo::new(Vec::new(), labels)
+        };
+
+        assert!(matches(Some(&info("centos7"))));
+        assert!(!matches(Some(&info("centos8"))));
+        assert!(!matches(Some(&ImageInfo::default())));
+        assert!(!matches(None));
+    }
+
+    #[test]
+    fn test_condition_image_exists() {
+        let condition: Condition = toml::from_str(r#"image_exists = ["tools:1", "tools:2"]"#).unwrap();
+        let info = |images: &[&str]| ImageInfo::new(images.iter().map(|i| ImageName::from(*i)).collect(), BTreeMap::new());
+        let matches = |info: &ImageInfo| {
+            condition.matches(&ConditionData { image_name: None, env: &[], image_info: Some(info) }).unwrap()
+        };
+
+        assert!(matches(&info(&["tools:1", "tools:2", "other:1"])));
+        assert!(!matches(&info(&["tools:1"])));
+        assert!(!condition.matches(&ConditionData { image_name: None, env: &[], image_info: None }).unwrap());
+    }
+
+    #[test]
+    fn test_condition_can_match_in_images() {
+        let centos7 = ImageName::from("centos:7");
+        let debian10 = ImageName::from("debian:10");
+
+        let condition: Condition = toml::from_str(r#"in_image = ["centos:7", "centos:8"]"#).unwrap();
+        assert!(condition.can_match_in_images(&[&debian10, &centos7]));
+        assert!(!condition.can_match_in_images(&[&debian10]));
+        assert!(!condition.can_match_in_images(&[]));
+
+        let condition: Condition = toml::from_str(r#"has_env = "foo""#).unwrap();
+        assert!(condition.can_match_in_images(&[]));
+    }
 }
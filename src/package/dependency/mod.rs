@@ -32,11 +32,16 @@ pub trait StringEqual {
 
 pub trait ParseDependency {
     fn parse_as_name_and_version(&self) -> Result<(PackageName, PackageVersionConstraint)>;
+
+    /// The named output of the package that is depended on, e.g. "devel" for "foo:devel =1.0"
+    ///
+    /// `None` if the dependency is on all artifacts of the package.
+    fn parse_output(&self) -> Result<Option<String>>;
 }
 
 lazy_static! {
     pub(in crate::package::dependency)  static ref DEPENDENCY_PARSING_RE: Regex =
-        Regex::new("^(?P<name>[[:alpha:]]([[[:alnum:]]\\.\\-_])*) (?P<version>([\\*=><])?[[:alnum:]]([[[:alnum:]][[:punct:]]])*)$").unwrap();
+        Regex::new("^(?P<name>[[:alpha:]]([[[:alnum:]]\\.\\-_])*)(:(?P<output>[[:alpha:]]([[[:alnum:]]\\-_])*))? (?P<version>(>=|<=|[\\*=><])?[[:alnum:]]([[[:alnum:]][[:punct:]]])*( (>=|<=|[=><])[[:alnum:]]([[[:alnum:]][[:punct:]]])*)?)$").unwrap();
 }
 
 /// Helper function for the actual implementation of the ParseDependency trait.
@@ -68,6 +73,16 @@ pub(in crate::package::dependency) fn parse_package_dependency_string_into_name_
     Ok((PackageName::from(name), v))
 }
 
+/// Helper function for the actual implementation of the ParseDependency trait.
+pub(in crate::package::dependency) fn parse_package_dependency_string_into_output(
+    s: &str,
+) -> Result<Option<String>> {
+    crate::package::dependency::DEPENDENCY_PARSING_RE
+        .captures(s)
+        .map(|caps| caps.name("output").map(|m| String::from(m.as_str())))
+        .ok_or_else(|| anyhow!("Could not parse into package name and package version constraint: '{}'", s))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +147,30 @@ mod tests {
             PackageVersionConstraint::from_version(String::from("="), exact("0.123"))
         );
     }
+
+    #[test]
+    fn test_dependency_with_version_range() {
+        let s = "vim >=8.2 <9";
+        let d = Dependency::from(String::from(s));
+
+        let (n, c) = d.parse_as_name_and_version().unwrap();
+
+        assert_eq!(n, name("vim"));
+        assert_eq!(c.to_string(), ">=8.2 <9");
+        assert!(c.matches(&exact("8.2.3")));
+        assert!(!c.matches(&exact("9.0")));
+    }
+
+    #[test]
+    fn test_dependency_on_output() {
+        let d = Dependency::from(String::from("foo-bar:devel =1.0"));
+
+        let (n, c) = d.parse_as_name_and_version().unwrap();
+        assert_eq!(n, name("foo-bar"));
+        assert_eq!(c, PackageVersionConstraint::from_version(String::from("="), exact("1.0")));
+        assert_eq!(d.parse_output().unwrap(), Some(String::from("devel")));
+
+        let d = Dependency::from(String::from("foo-bar =1.0"));
+        assert_eq!(d.parse_output().unwrap(), None);
+    }
 }
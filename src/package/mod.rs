@@ -26,6 +26,8 @@ pub use phase::*;
 mod script;
 pub use script::*;
 
+mod secret;
+
 mod source;
 pub use source::*;
 
@@ -8,18 +8,25 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
 use getset::Getters;
+use regex::Regex;
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::package::dependency::*;
 use crate::package::name::*;
+use crate::package::secret::*;
 use crate::package::source::*;
 use crate::package::version::*;
-use crate::package::{Phase, PhaseName};
+use crate::package::{ExtraPhase, Phase, PhaseName};
 use crate::util::docker::ImageName;
 use crate::util::EnvironmentVariableName;
 
@@ -34,8 +41,10 @@ pub struct Package {
     #[getset(get = "pub")]
     version_is_semver: bool,
 
+    /// Empty if the package has no `sources` at all, which `butido lint` reports
     #[getset(get = "pub")]
-    sources: HashMap<String, Source>,
+    #[serde(default)]
+    sources: Sources,
 
     #[getset(get = "pub")]
     dependencies: Dependencies,
@@ -47,6 +56,19 @@ pub struct Package {
     #[serde(skip_serializing_if = "Option::is_none")]
     environment: Option<HashMap<EnvironmentVariableName, String>>,
 
+    /// The environment variables which influence the build of the package
+    ///
+    /// If set, only these variables are compared when looking for artifacts of an earlier build
+    /// that can be reused, instead of the whole environment of the job.
+    #[getset(get = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env_sensitivity: Option<Vec<EnvironmentVariableName>>,
+
+    /// The secrets the package needs during its build
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    secrets: Vec<PackageSecret>,
+
     #[getset(get = "pub")]
     #[serde(skip_serializing_if = "Option::is_none")]
     allowed_images: Option<Vec<ImageName>>,
@@ -58,6 +80,51 @@ pub struct Package {
     #[getset(get = "pub")]
     phases: HashMap<PhaseName, Phase>,
 
+    /// Phases the package inserts into the configured phases
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra_phases: Vec<ExtraPhase>,
+
+    /// Whether the package script needs network access in the container
+    ///
+    /// Packages that need network cannot be built with `build --hermetic`.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    needs_network: bool,
+
+    /// How failed jobs of the package are retried, overrides the `retry` setting of the
+    /// configuration
+    #[getset(get = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry: Option<crate::config::RetryPolicy>,
+
+    /// Resource limits for the containers of the package, override the `build` setting of the
+    /// configuration
+    #[getset(get = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<crate::config::BuildLimits>,
+
+    /// How old artifacts of the package may be to be reused (e.g. "14d"), overrides the `max_age`
+    /// of the `reuse_policy` of the configuration
+    #[getset(get = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reuse_max_age: Option<String>,
+
+    /// The teams maintaining the package
+    ///
+    /// Recorded with every job of the package, failures are reported per team.
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    maintainer: Vec<String>,
+
+    /// The named outputs of the package, e.g. "devel" or "debuginfo", with a pattern (regex) for
+    /// the file names of the artifacts belonging to them
+    ///
+    /// Other packages can depend on a single output with "name:output", e.g. "foo:devel =1.0".
+    #[getset(get = "pub")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    outputs: BTreeMap<String, String>,
+
     /// Meta field
     ///
     /// Contains only key-value string-string data, that the packager can set for a package and
@@ -88,22 +155,121 @@ impl Package {
             name,
             version,
             version_is_semver,
-            sources,
+            sources: Sources::from(sources),
             dependencies,
             patches: vec![],
             environment: None,
+            env_sensitivity: None,
+            secrets: vec![],
             allowed_images: None,
             denied_images: None,
             phases: HashMap::new(),
+            extra_phases: vec![],
+            needs_network: false,
+            retry: None,
+            build: None,
+            reuse_max_age: None,
+            maintainer: vec![],
+            outputs: BTreeMap::new(),
             meta: None,
         }
     }
 
+    /// The phases of the package in the order they are run
+    ///
+    /// These are the `configured` phases with the extra phases of the package inserted.
+    /// Fails if the package has a script for a phase which is not in this list.
+    pub fn phase_order(&self, configured: &[PhaseName]) -> Result<Vec<PhaseName>> {
+        let order = crate::package::phase_order(configured, &self.extra_phases)
+            .with_context(|| anyhow!("Ordering phases of {} {}", self.name, self.version))?;
+
+        if let Some(unknown) = self.phases.keys().find(|name| !order.contains(name)) {
+            return Err(anyhow!(
+                "Phase '{}' of {} {} is neither configured nor an extra phase of the package",
+                unknown.as_str(),
+                self.name,
+                self.version
+            ));
+        }
+
+        Ok(order)
+    }
+
+    /// Whether a dependency of the package is conditional on the images on the endpoints or the
+    /// labels of the build image
+    pub fn has_image_info_conditions(&self) -> bool {
+        use crate::package::condition::ConditionCheckable;
+
+        self.dependencies.build().iter().any(ConditionCheckable::needs_image_info)
+            || self.dependencies.runtime().iter().any(ConditionCheckable::needs_image_info)
+    }
+
+    /// The named output the artifact `artifact` of the package belongs to
+    ///
+    /// The outputs are checked in alphabetical order, the first one whose pattern matches the file
+    /// name of the artifact is used. Artifacts that match no pattern belong to no named output.
+    pub fn output_of(&self, artifact: &Path) -> Result<Option<&str>> {
+        let file_name = artifact
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Artifact has no valid file name: {}", artifact.display()))?;
+
+        for (name, pattern) in self.outputs.iter() {
+            let re = Regex::new(pattern).with_context(|| {
+                anyhow!("Invalid pattern of output '{}' of {} {}: '{}'", name, self.name, self.version, pattern)
+            })?;
+
+            if re.is_match(file_name) {
+                return Ok(Some(name))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The named outputs of the package `name` this package depends on
+    ///
+    /// Returns `None` if the package needs all artifacts of `name`, because one of its dependencies
+    /// on `name` does not name an output or because it does not depend on `name` directly.
+    pub fn outputs_of_dependency(&self, name: &PackageName) -> Result<Option<Vec<String>>> {
+        let build = self.dependencies.build().iter().map(|d| -> Result<_> {
+            Ok((d.parse_as_name_and_version()?.0, d.parse_output()?))
+        });
+        let runtime = self.dependencies.runtime().iter().map(|d| -> Result<_> {
+            Ok((d.parse_as_name_and_version()?.0, d.parse_output()?))
+        });
+
+        let mut outputs = Vec::new();
+        for dependency in build.chain(runtime) {
+            match dependency? {
+                (dep_name, _) if dep_name != *name => continue,
+                (_, Some(output)) => outputs.push(output),
+                (_, None) => return Ok(None),
+            }
+        }
+
+        if outputs.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(outputs))
+        }
+    }
+
     #[cfg(test)]
     pub fn set_dependencies(&mut self, dependencies: Dependencies) {
         self.dependencies = dependencies;
     }
 
+    #[cfg(test)]
+    pub fn set_secrets(&mut self, secrets: Vec<PackageSecret>) {
+        self.secrets = secrets;
+    }
+
+    #[cfg(test)]
+    pub fn set_outputs(&mut self, outputs: BTreeMap<String, String>) {
+        self.outputs = outputs;
+    }
+
     /// Get a wrapper object around self which implements a debug interface with all details about
     /// the Package object
     #[cfg(debug_assertions)]
@@ -141,11 +307,12 @@ impl<'a> std::fmt::Debug for DebugPackage<'a> {
             semver = if self.0.version_is_semver { "is semver" } else { "not semver" })?;
 
         writeln!(f, "\tSources = ")?;
-        self.0.sources.iter().try_for_each(|(k, v)| writeln!(f, "\t\t{name} = (Url = {url}, Hash = {hash} ({hasht}), {dl})",
+        self.0.sources.iter().try_for_each(|(k, v)| writeln!(f, "\t\t{name} = (Url = {url}, Hash = {hash} ({hasht}), Additional hashes = [{additional}], {dl})",
             name = k,
             url = v.url(),
             hash = v.hash().value(),
             hasht = v.hash().hashtype(),
+            additional = v.additional_hashes().iter().map(|h| format!("{} ({})", h.value(), h.hashtype())).collect::<Vec<_>>().join(", "),
             dl = if *v.download_manually() { "manual download" } else { "automatic download" },
         ))?;
 
@@ -274,4 +441,38 @@ pub mod tests {
         let dependencies = Dependencies::empty();
         Package::new(name, version, version_is_semver, sources, dependencies)
     }
+
+    #[test]
+    fn test_output_of() {
+        let mut pack = package("foo", "1", "https://rust-lang.org", "123");
+        pack.outputs.insert(String::from("debuginfo"), String::from("-debuginfo-"));
+        pack.outputs.insert(String::from("devel"), String::from("-devel-"));
+
+        assert_eq!(pack.output_of(Path::new("foo-devel-1.rpm")).unwrap(), Some("devel"));
+        assert_eq!(pack.output_of(Path::new("foo-devel-debuginfo-1.rpm")).unwrap(), Some("debuginfo"));
+        assert_eq!(pack.output_of(Path::new("foo-1.rpm")).unwrap(), None);
+
+        pack.outputs.insert(String::from("broken"), String::from("("));
+        assert!(pack.output_of(Path::new("foo-1.rpm")).is_err());
+    }
+
+    #[test]
+    fn test_outputs_of_dependency() {
+        use crate::package::Dependency;
+
+        let mut pack = package("a", "1", "https://rust-lang.org", "123");
+        pack.set_dependencies(Dependencies::with_runtime_dependencies(vec![
+            Dependency::from(String::from("foo:devel =1")),
+            Dependency::from(String::from("foo:debuginfo =1")),
+            Dependency::from(String::from("bar:devel =1")),
+            Dependency::from(String::from("bar =1")),
+        ]));
+
+        assert_eq!(
+            pack.outputs_of_dependency(&pname("foo")).unwrap(),
+            Some(vec![String::from("devel"), String::from("debuginfo")])
+        );
+        assert_eq!(pack.outputs_of_dependency(&pname("bar")).unwrap(), None);
+        assert_eq!(pack.outputs_of_dependency(&pname("baz")).unwrap(), None);
+    }
 }
@@ -10,6 +10,9 @@
 
 use std::path::PathBuf;
 
+use anyhow::anyhow;
+use anyhow::Result;
+use getset::Getters;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -38,3 +41,103 @@ pub enum Phase {
     #[serde(rename = "script")]
     Text(String),
 }
+
+/// A phase a package adds to the configured phases
+///
+/// The phase is inserted right after or right before another phase, which is either one of the
+/// configured phases or an extra phase the package declares before this one.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Getters)]
+pub struct ExtraPhase {
+    #[getset(get = "pub")]
+    name: PhaseName,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    after: Option<PhaseName>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    before: Option<PhaseName>,
+}
+
+/// Insert the `extra` phases into the `configured` phases
+///
+/// Fails if a phase name is used twice or if an extra phase is not positioned relative to exactly
+/// one known phase.
+pub fn phase_order(configured: &[PhaseName], extra: &[ExtraPhase]) -> Result<Vec<PhaseName>> {
+    let mut order: Vec<PhaseName> = Vec::with_capacity(configured.len() + extra.len());
+
+    for name in configured {
+        if order.contains(name) {
+            return Err(anyhow!("Phase '{}' is configured more than once", name.as_str()));
+        }
+        order.push(name.clone());
+    }
+
+    for phase in extra {
+        if order.contains(&phase.name) {
+            return Err(anyhow!("Extra phase '{}' is already a phase", phase.name.as_str()));
+        }
+
+        let (reference, offset) = match (phase.after.as_ref(), phase.before.as_ref()) {
+            (Some(after), None) => (after, 1),
+            (None, Some(before)) => (before, 0),
+            _ => return Err(anyhow!("Extra phase '{}' must have either 'after' or 'before' set", phase.name.as_str())),
+        };
+
+        let position = order
+            .iter()
+            .position(|name| name == reference)
+            .ok_or_else(|| anyhow!("Extra phase '{}' is positioned relative to unknown phase '{}'", phase.name.as_str(), reference.as_str()))?;
+
+        order.insert(position + offset, phase.name.clone());
+    }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<PhaseName> {
+        names.iter().map(|n| PhaseName::from(n.to_string())).collect()
+    }
+
+    fn extra(name: &str, after: Option<&str>, before: Option<&str>) -> ExtraPhase {
+        ExtraPhase {
+            name: PhaseName::from(name.to_string()),
+            after: after.map(|n| PhaseName::from(n.to_string())),
+            before: before.map(|n| PhaseName::from(n.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_phase_order_without_extra_phases() {
+        let configured = names(&["unpack", "build", "pack"]);
+        assert_eq!(phase_order(&configured, &[]).unwrap(), configured);
+    }
+
+    #[test]
+    fn test_phase_order_inserts_extra_phases() {
+        let configured = names(&["unpack", "build", "pack"]);
+        let extra = vec![
+            extra("patch", Some("unpack"), None),
+            extra("configure", None, Some("build")),
+            extra("check", Some("build"), None),
+            extra("strip", None, Some("check")),
+        ];
+
+        let order = phase_order(&configured, &extra).unwrap();
+        assert_eq!(order, names(&["unpack", "patch", "configure", "build", "strip", "check", "pack"]));
+    }
+
+    #[test]
+    fn test_phase_order_errors() {
+        let configured = names(&["unpack", "build", "pack"]);
+
+        assert!(phase_order(&names(&["build", "build"]), &[]).is_err());
+        assert!(phase_order(&configured, &[extra("build", Some("unpack"), None)]).is_err());
+        assert!(phase_order(&configured, &[extra("patch", Some("fetch"), None)]).is_err());
+        assert!(phase_order(&configured, &[extra("patch", None, None)]).is_err());
+        assert!(phase_order(&configured, &[extra("patch", Some("unpack"), Some("build"))]).is_err());
+    }
+}
@@ -8,6 +8,7 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
 use std::process::ExitStatus;
 
 use anyhow::anyhow;
@@ -54,6 +55,19 @@ impl Script {
         self.0.lines().enumerate().map(|(n, l)| (n + 1, l))
     }
 
+    /// The interpreter (with its arguments) from the shebang line of the script
+    ///
+    /// Falls back to `/bin/bash` if the script does not start with a shebang.
+    pub fn interpreter(&self) -> Vec<&str> {
+        self.0
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("#!"))
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|interp| !interp.is_empty())
+            .unwrap_or_else(|| vec!["/bin/bash"])
+    }
+
     pub async fn lint(&self, mut cmd: Command) -> Result<(ExitStatus, String, String)> {
         use tokio::io::AsyncWriteExt;
         use tokio::io::BufWriter;
@@ -157,11 +171,23 @@ impl AsRef<str> for Script {
 
 pub struct ScriptBuilder<'a> {
     shebang: &'a Shebang,
+    dependency_mapping: Option<&'a HashMap<String, String>>,
 }
 
 impl<'a> ScriptBuilder<'a> {
     pub fn new(shebang: &'a Shebang) -> Self {
-        ScriptBuilder { shebang }
+        ScriptBuilder {
+            shebang,
+            dependency_mapping: None,
+        }
+    }
+
+    /// Use the passed mapping for rendering system dependency names with the `sysdep` helper
+    ///
+    /// If no mapping is set, the `sysdep` helper renders the names unchanged.
+    pub fn with_dependency_mapping(mut self, mapping: Option<&'a HashMap<String, String>>) -> Self {
+        self.dependency_mapping = mapping;
+        self
     }
 
     pub fn build(
@@ -172,7 +198,7 @@ impl<'a> ScriptBuilder<'a> {
     ) -> Result<Script> {
         let mut script = format!("{shebang}\n", shebang = self.shebang.0);
 
-        for name in phaseorder {
+        for name in package.phase_order(phaseorder)?.iter() {
             match package.phases().get(name) {
                 Some(Phase::Text(text)) => {
                     use unindent::Unindent;
@@ -219,10 +245,15 @@ impl<'a> ScriptBuilder<'a> {
             }
         }
 
-        Self::interpolate_package(script, package, strict_mode).map(Script)
+        Self::interpolate_package(script, package, self.dependency_mapping, strict_mode).map(Script)
     }
 
-    fn interpolate_package(script: String, package: &Package, strict_mode: bool) -> Result<String> {
+    fn interpolate_package(
+        script: String,
+        package: &Package,
+        dependency_mapping: Option<&HashMap<String, String>>,
+        strict_mode: bool,
+    ) -> Result<String> {
         let mut hb = Handlebars::new();
         hb.register_escape_fn(handlebars::no_escape);
         hb.register_template_string("script", script)?;
@@ -231,6 +262,7 @@ impl<'a> ScriptBuilder<'a> {
         hb.register_helper("progress", Box::new(ProgressHelper));
         hb.register_helper("join", Box::new(JoinHelper));
         hb.register_helper("joinwith", Box::new(JoinWithHelper));
+        hb.register_helper("sysdep", Box::new(SysDepHelper(dependency_mapping.cloned())));
         hb.set_strict_mode(strict_mode);
 
         #[cfg(debug_assertions)]
@@ -374,6 +406,39 @@ impl HelperDef for JoinWithHelper {
     }
 }
 
+#[derive(Clone)]
+struct SysDepHelper(Option<HashMap<String, String>>);
+
+impl HelperDef for SysDepHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _rc: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let name = h
+            .param(0)
+            .ok_or_else(|| RenderError::new("Required parameter missing: dependency name"))?
+            .value()
+            .as_str()
+            .ok_or_else(|| RenderError::new("Required parameter must be a string: dependency name"))?;
+
+        match self.0.as_ref() {
+            None => out.write(name)?,
+            Some(mapping) => {
+                let mapped = mapping.get(name).ok_or_else(|| {
+                    RenderError::new(format!("No mapping for system dependency '{}'", name))
+                })?;
+                out.write(mapped)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
 fn joinstrs<'reg: 'rc, 'rc, I>(with: &str, params: I, out: &mut dyn Output) -> HelperResult
 where
     I: Iterator<Item = &'rc PathAndJson<'reg, 'rc>>,
@@ -394,3 +459,18 @@ where
     out.write(&s)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Script;
+
+    #[test]
+    fn test_interpreter_from_shebang() {
+        let interp = |s: &str| Script::from(String::from(s)).interpreter().join(" ");
+        assert_eq!(interp("#!/bin/bash\necho"), "/bin/bash");
+        assert_eq!(interp("#!/bin/busybox sh\necho"), "/bin/busybox sh");
+        assert_eq!(interp("#! /bin/dash\necho"), "/bin/dash");
+        assert_eq!(interp("echo"), "/bin/bash");
+        assert_eq!(interp("#!\necho"), "/bin/bash");
+    }
+}
@@ -0,0 +1,30 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use getset::Getters;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::util::EnvironmentVariableName;
+
+/// A secret the package needs during its build
+///
+/// The secret is available in the container as file `/secrets/<name>`.
+#[derive(Clone, Debug, Serialize, Deserialize, Getters)]
+pub struct PackageSecret {
+    /// The name of the secret in the `secrets` of the configuration
+    #[getset(get = "pub")]
+    name: String,
+
+    /// The environment variable the secret is exported as to the script, in addition to the file
+    #[getset(get = "pub")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<EnvironmentVariableName>,
+}
@@ -8,6 +8,10 @@
 // SPDX-License-Identifier: EPL-2.0
 //
 
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
@@ -17,25 +21,214 @@ use serde::Deserialize;
 use serde::Serialize;
 use url::Url;
 
+/// A source of a package
+///
+/// A source is either a file that is downloaded from `url`, or a revision of a git repository:
+///
+/// ```toml
+/// [sources.src]
+/// git = "https://example.com/repo.git"
+/// rev = "v1.0"
+/// hash.type = "git"
+/// hash.hash = "<id of the commit rev points to>"
+/// download_manually = false
+/// ```
+///
+/// A git source is downloaded as tar archive of the revision, whose hash is the id of the commit
+/// the archive was created from. The `url` of a git source is the repository URL with the
+/// revision as fragment, as understood by the git source fetcher.
+///
+/// A source can have `additional_hashes` (e.g. a sha512 hash in addition to a sha256 hash), which
+/// have to match as well.
 #[derive(Clone, Debug, Serialize, Deserialize, Getters)]
+#[serde(try_from = "SourceDefinition", into = "SourceDefinition")]
 pub struct Source {
     #[getset(get = "pub")]
     url: Url,
     #[getset(get = "pub")]
     hash: SourceHash,
     #[getset(get = "pub")]
+    additional_hashes: Vec<SourceHash>,
+    #[getset(get = "pub")]
+    download_manually: bool,
+}
+
+/// How a source is written in the package definition
+#[derive(Clone, Serialize, Deserialize)]
+struct SourceDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    hash: SourceHash,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    additional_hashes: Vec<SourceHash>,
     download_manually: bool,
 }
 
+impl TryFrom<SourceDefinition> for Source {
+    type Error = String;
+
+    fn try_from(def: SourceDefinition) -> std::result::Result<Self, Self::Error> {
+        let is_git_hash = matches!(def.hash.hashtype, HashType::Git);
+        if def.additional_hashes.iter().any(|h| matches!(h.hashtype, HashType::Git)) {
+            return Err(String::from("Hash type 'git' is only valid as the hash of a git source, not as additional hash"))
+        }
+
+        let url = match (def.url, def.git, def.rev) {
+            (Some(url), None, None) if is_git_hash => {
+                return Err(format!("Source {}: hash type 'git' is only valid for git sources", url))
+            },
+            (Some(url), None, None) => url,
+            (None, Some(git), Some(rev)) if is_git_hash => {
+                let url = if git.starts_with("git://") {
+                    format!("{}#{}", git, rev)
+                } else {
+                    format!("git+{}#{}", git, rev)
+                };
+
+                Url::parse(&url).map_err(|e| format!("Git source {}: {}", git, e))?
+            },
+            (None, Some(git), Some(_)) => {
+                return Err(format!("Git source {}: the hash of a git source must have type 'git'", git))
+            },
+            (None, Some(git), None) => {
+                return Err(format!("Git source {}: 'rev' is required", git))
+            },
+            (Some(_), Some(_), _) => {
+                return Err(String::from("A source must have either 'url' or 'git', not both"))
+            },
+            (Some(url), None, Some(_)) => {
+                return Err(format!("Source {}: 'rev' is only valid for git sources", url))
+            },
+            (None, None, _) => {
+                return Err(String::from("A source must have either 'url' or 'git'"))
+            },
+        };
+
+        Ok(Source {
+            url,
+            hash: def.hash,
+            additional_hashes: def.additional_hashes,
+            download_manually: def.download_manually,
+        })
+    }
+}
+
+impl From<Source> for SourceDefinition {
+    fn from(source: Source) -> Self {
+        let (url, git, rev) = if matches!(source.hash.hashtype, HashType::Git) {
+            let rev = source.url.fragment().map(String::from);
+            let mut repo_url = source.url;
+            repo_url.set_fragment(None);
+            let git = repo_url.as_str().trim_start_matches("git+").to_string();
+            (None, Some(git), rev)
+        } else {
+            (Some(source.url), None, None)
+        };
+
+        SourceDefinition {
+            url,
+            git,
+            rev,
+            hash: source.hash,
+            additional_hashes: source.additional_hashes,
+            download_manually: source.download_manually,
+        }
+    }
+}
+
 impl Source {
     #[cfg(test)]
     pub fn new(url: Url, hash: SourceHash) -> Self {
         Source {
             url,
             hash,
+            additional_hashes: Vec::new(),
             download_manually: false,
         }
     }
+
+    /// All hashes the source has to match
+    pub fn hashes(&self) -> impl Iterator<Item = &SourceHash> {
+        std::iter::once(&self.hash).chain(self.additional_hashes.iter())
+    }
+}
+
+/// The sources of a package, by name
+///
+/// A package that does not need any sources (e.g. a meta package that only pulls in other
+/// packages) declares `sources = []`, so that it can be told apart from a package whose sources
+/// were forgotten.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "SourcesDefinition", into = "SourcesDefinition")]
+pub struct Sources {
+    sources: HashMap<String, Source>,
+    declared_none: bool,
+}
+
+/// How the sources are written in the package definition
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SourcesDefinition {
+    Sources(HashMap<String, Source>),
+    None(Vec<Source>),
+}
+
+impl TryFrom<SourcesDefinition> for Sources {
+    type Error = String;
+
+    fn try_from(def: SourcesDefinition) -> std::result::Result<Self, Self::Error> {
+        match def {
+            SourcesDefinition::Sources(sources) => Ok(Sources { sources, declared_none: false }),
+            SourcesDefinition::None(list) if list.is_empty() => Ok(Sources { sources: HashMap::new(), declared_none: true }),
+            SourcesDefinition::None(_) => {
+                Err(String::from("sources must be a table of named sources, or [] for a package without sources"))
+            }
+        }
+    }
+}
+
+impl From<Sources> for SourcesDefinition {
+    fn from(sources: Sources) -> Self {
+        if sources.declared_none {
+            SourcesDefinition::None(Vec::new())
+        } else {
+            SourcesDefinition::Sources(sources.sources)
+        }
+    }
+}
+
+#[cfg(test)]
+impl From<HashMap<String, Source>> for Sources {
+    fn from(sources: HashMap<String, Source>) -> Self {
+        Sources { sources, declared_none: false }
+    }
+}
+
+impl Sources {
+    /// Whether the package declared `sources = []`, i.e. does not need any sources
+    pub fn declared_none(&self) -> bool {
+        self.declared_none
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Source> {
+        self.sources.get(name)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.sources.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Source)> {
+        self.sources.iter()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Getters)]
@@ -77,7 +270,7 @@ impl SourceHash {
     }
 }
 
-#[derive(parse_display::Display, Clone, Debug, Serialize, Deserialize)]
+#[derive(parse_display::Display, parse_display::FromStr, Clone, Debug, Serialize, Deserialize)]
 pub enum HashType {
     #[serde(rename = "sha1")]
     #[display("sha1")]
@@ -90,9 +283,71 @@ pub enum HashType {
     #[serde(rename = "sha512")]
     #[display("sha512")]
     Sha512,
+
+    #[serde(rename = "blake3")]
+    #[display("blake3")]
+    Blake3,
+
+    /// The id of the commit a tar archive was created from with `git archive`
+    #[serde(rename = "git")]
+    #[display("git")]
+    Git,
 }
 
 impl HashType {
+    /// Hash the contents of the file at `path`
+    pub fn hash_file(&self, path: &Path) -> Result<HashValue> {
+        use sha2::Digest;
+
+        fn read_chunks<F: FnMut(&[u8])>(path: &Path, mut update: F) -> Result<()> {
+            use std::io::Read;
+
+            let mut file = std::fs::File::open(path)
+                .with_context(|| anyhow!("Opening {}", path.display()))?;
+            let mut buffer = [0; 8192];
+            loop {
+                let count = file.read(&mut buffer)
+                    .with_context(|| anyhow!("Reading {}", path.display()))?;
+
+                if count == 0 {
+                    return Ok(());
+                }
+
+                update(&buffer[..count]);
+            }
+        }
+
+        fn hash<D: sha2::Digest>(mut m: D, path: &Path) -> Result<HashValue> {
+            read_chunks(path, |chunk| m.update(chunk))?;
+            Ok(HashValue(m.finalize().iter().map(|b| format!("{:02x}", b)).collect()))
+        }
+
+        match self {
+            HashType::Sha1 => hash(sha1::Sha1::new(), path),
+            HashType::Sha256 => hash(sha2::Sha256::new(), path),
+            HashType::Sha512 => hash(sha2::Sha512::new(), path),
+            HashType::Blake3 => {
+                let mut m = blake3::Hasher::new();
+                read_chunks(path, |chunk| {
+                    m.update(chunk);
+                })?;
+                Ok(HashValue(m.finalize().to_hex().to_string()))
+            }
+            HashType::Git => {
+                use std::io::Read;
+
+                let file = std::fs::File::open(path)
+                    .with_context(|| anyhow!("Opening {}", path.display()))?;
+                let mut head = Vec::with_capacity(GIT_ARCHIVE_HEADER_LEN as usize);
+                file.take(GIT_ARCHIVE_HEADER_LEN)
+                    .read_to_end(&mut head)
+                    .with_context(|| anyhow!("Reading {}", path.display()))?;
+
+                git_commit_of_archive(&head).with_context(|| anyhow!("Reading commit id from {}", path.display()))
+            }
+        }
+    }
+
     async fn hash_from_reader<R: tokio::io::AsyncRead + Unpin>(&self, mut reader: R) -> Result<HashValue> {
         use tokio::io::AsyncReadExt;
 
@@ -158,10 +413,74 @@ impl HashType {
                 }
                 Ok(HashValue(String::from_utf8(m.finalize()[..].to_vec())?))
             }
+            HashType::Blake3 => {
+                trace!("BLAKE3 hashing buffer");
+                let mut m = blake3::Hasher::new();
+                loop {
+                    let count = reader.read(&mut buffer)
+                        .await
+                        .context("Reading buffer failed")?;
+
+                    if count == 0 {
+                        trace!("ready");
+                        break;
+                    }
+
+                    m.update(&buffer[..count]);
+                }
+                Ok(HashValue(m.finalize().to_hex().to_string()))
+            }
+            HashType::Git => {
+                trace!("Reading commit id from buffer");
+                let mut head = Vec::with_capacity(GIT_ARCHIVE_HEADER_LEN as usize);
+                reader.take(GIT_ARCHIVE_HEADER_LEN)
+                    .read_to_end(&mut head)
+                    .await
+                    .context("Reading buffer failed")?;
+
+                git_commit_of_archive(&head)
+            }
         }
     }
 }
 
+/// The length of the pax global header that `git archive` writes at the start of a tar archive,
+/// i.e. the tar header block and one block of content
+const GIT_ARCHIVE_HEADER_LEN: u64 = 1024;
+
+/// Get the id of the commit a tar archive was created from, from the start of the archive
+///
+/// `git archive` records the commit id as `comment` in a pax global header, which is the first
+/// entry of the archive.
+fn git_commit_of_archive(head: &[u8]) -> Result<HashValue> {
+    use std::io::Read;
+
+    let mut archive = tar::Archive::new(head);
+    let mut entry = archive
+        .entries()
+        .context("Reading tar archive")?
+        .next()
+        .ok_or_else(|| anyhow!("Empty tar archive"))?
+        .context("Reading tar archive")?;
+
+    if !entry.header().entry_type().is_pax_global_extensions() {
+        return Err(anyhow!("Not an archive created by 'git archive', no pax global header found"));
+    }
+
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .context("Reading pax global header")?;
+
+    // Records have the form "<length> <key>=<value>\n"
+    content
+        .lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("comment="))
+        .map(|commit| HashValue(commit.to_string()))
+        .ok_or_else(|| anyhow!("No commit id found in pax global header of the archive"))
+}
+
 #[derive(parse_display::Display, Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
 #[serde(transparent)]
 #[display("{0}")]
@@ -173,3 +492,131 @@ impl From<String> for HashValue {
         HashValue(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Deserialize)]
+    struct TestSetting {
+        sources: Sources,
+    }
+
+    #[test]
+    fn test_sources() {
+        let s: TestSetting = toml::from_str(indoc::indoc!(r#"
+            [sources.src]
+            url = "https://example.com/src.tar.gz"
+            hash.type = "sha1"
+            hash.hash = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+            download_manually = false
+        "#)).unwrap();
+        assert!(s.sources.get("src").is_some());
+        assert!(!s.sources.declared_none());
+
+        let s: TestSetting = toml::from_str("sources = []").unwrap();
+        assert!(s.sources.is_empty());
+        assert!(s.sources.declared_none());
+
+        let s: TestSetting = toml::from_str("sources = {}").unwrap();
+        assert!(s.sources.is_empty());
+        assert!(!s.sources.declared_none());
+
+        let s = toml::from_str::<TestSetting>(indoc::indoc!(r#"
+            sources = [
+                { url = "https://example.com/src.tar.gz", hash = { type = "sha1", hash = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d" }, download_manually = false },
+            ]
+        "#));
+        assert!(s.is_err());
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let path = std::env::temp_dir().join(format!("butido-test-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "hello").unwrap();
+
+        let sha1 = HashType::Sha1.hash_file(&path).unwrap();
+        let sha256 = HashType::Sha256.hash_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(sha1.to_string(), "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
+        assert_eq!(sha256.to_string(), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+    }
+
+    #[test]
+    fn test_additional_hashes() {
+        let s: TestSetting = toml::from_str(indoc::indoc!(r#"
+            [sources.src]
+            url = "https://example.com/src.tar.gz"
+            hash.type = "sha256"
+            hash.hash = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+            additional_hashes = [
+                { type = "blake3", hash = "ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f" },
+            ]
+            download_manually = false
+        "#)).unwrap();
+        let src = s.sources.get("src").unwrap();
+        let types = src.hashes().map(|h| h.hashtype().to_string()).collect::<Vec<_>>();
+        assert_eq!(types, vec!["sha256", "blake3"]);
+
+        let path = std::env::temp_dir().join(format!("butido-test-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "hello").unwrap();
+        let blake3 = HashType::Blake3.hash_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(blake3, *src.additional_hashes()[0].value());
+    }
+
+    #[test]
+    fn test_git_sources() {
+        let s: TestSetting = toml::from_str(indoc::indoc!(r#"
+            [sources.src]
+            git = "https://example.com/repo.git"
+            rev = "v1.0"
+            hash.type = "git"
+            hash.hash = "0123456789abcdef0123456789abcdef01234567"
+            download_manually = false
+        "#)).unwrap();
+        let src = s.sources.get("src").unwrap();
+        assert_eq!(src.url().as_str(), "git+https://example.com/repo.git#v1.0");
+
+        let def = SourceDefinition::from(src.clone());
+        assert!(def.url.is_none());
+        assert_eq!(def.git.as_deref(), Some("https://example.com/repo.git"));
+        assert_eq!(def.rev.as_deref(), Some("v1.0"));
+
+        let invalid = [
+            // git without rev
+            r#"sources.src = { git = "https://example.com/repo.git", hash = { type = "git", hash = "0123" }, download_manually = false }"#,
+            // git with a hash of the archive
+            r#"sources.src = { git = "https://example.com/repo.git", rev = "v1.0", hash = { type = "sha1", hash = "0123" }, download_manually = false }"#,
+            // url with a commit id
+            r#"sources.src = { url = "https://example.com/src.tar.gz", hash = { type = "git", hash = "0123" }, download_manually = false }"#,
+            // url and git
+            r#"sources.src = { url = "https://example.com/src.tar.gz", git = "https://example.com/repo.git", rev = "v1.0", hash = { type = "git", hash = "0123" }, download_manually = false }"#,
+        ];
+        for toml in invalid.iter() {
+            assert!(toml::from_str::<TestSetting>(toml).is_err(), "Should not parse: {}", toml);
+        }
+    }
+
+    #[test]
+    fn test_git_commit_of_archive() {
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let record = format!("comment={}\n", commit);
+        let record = format!("{} {}", record.len() + 3, record);
+        assert_eq!(record.len(), 52);
+
+        let mut header = tar::Header::new_ustar();
+        header.set_path("pax_global_header").unwrap();
+        header.set_entry_type(tar::EntryType::XGlobalHeader);
+        header.set_size(record.len() as u64);
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append(&header, record.as_bytes()).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        assert_eq!(git_commit_of_archive(&archive[..GIT_ARCHIVE_HEADER_LEN as usize]).unwrap().to_string(), commit);
+        assert!(git_commit_of_archive(&[0; 1024]).is_err());
+    }
+}
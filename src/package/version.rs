@@ -19,26 +19,56 @@ use serde::Serialize;
 
 use crate::util::parser::*;
 
+/// A constraint on a package version
+///
+/// A constraint is a comparator (`=`, `<`, `<=`, `>`, `>=`) and a version, optionally followed by
+/// a second comparator and version separated by whitespace, to express ranges like
+/// `>=1.2.0 <2.0.0`. A version matches a range if it matches both bounds.
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PackageVersionConstraint {
     constraint: String,
     version: PackageVersion,
+
+    /// The second bound of a range
+    range_end: Option<(String, PackageVersion)>,
 }
 
 impl PackageVersionConstraint {
     fn parser<'a>() -> PomParser<'a, u8, Self> {
-        (pom::parser::sym(b'=') + PackageVersion::parser())
-            .convert(|(constraint, version)| {
-                String::from_utf8(vec![constraint]).map(|c| (c, version))
-            })
-            .map(|(constraint, version)| PackageVersionConstraint {
+        let bound = || {
+            (comparator() + PackageVersion::parser())
+                .convert(|(constraint, version)| String::from_utf8(constraint).map(|c| (c, version)))
+        };
+
+        (bound() + (pom::parser::sym(b' ').repeat(1..) * bound()).opt())
+            .map(|((constraint, version), range_end)| PackageVersionConstraint {
                 constraint,
                 version,
+                range_end,
             })
     }
 
     pub fn matches(&self, v: &PackageVersion) -> bool {
-        self.version == *v
+        Self::matches_bound(&self.constraint, &self.version, v)
+            && self
+                .range_end
+                .as_ref()
+                .map(|(constraint, version)| Self::matches_bound(constraint, version, v))
+                .unwrap_or(true)
+    }
+
+    fn matches_bound(constraint: &str, bound: &PackageVersion, v: &PackageVersion) -> bool {
+        use std::cmp::Ordering;
+
+        match constraint {
+            // Equality is a string match, so that "1.0" does not match "1.00"
+            "=" => bound == v,
+            "<" => v.compare(bound) == Ordering::Less,
+            "<=" => v.compare(bound) != Ordering::Greater,
+            ">" => v.compare(bound) == Ordering::Greater,
+            ">=" => v.compare(bound) != Ordering::Less,
+            _ => false,
+        }
     }
 
     #[cfg(test)]
@@ -46,6 +76,7 @@ impl PackageVersionConstraint {
         PackageVersionConstraint {
             constraint,
             version,
+            range_end: None,
         }
     }
 }
@@ -65,7 +96,7 @@ impl std::convert::TryFrom<&str> for PackageVersionConstraint {
         PackageVersionConstraint::parser()
             .parse(s.as_bytes())
             .context("Failed to parse package version constraint")
-            .context("A package version constraint must have a comparator and a version string, like so: =0.1.0")
+            .context("A package version constraint must have a comparator and a version string, like so: =0.1.0 or >=0.1.0 <0.2.0")
             .map_err(Error::from)
 
     }
@@ -73,10 +104,19 @@ impl std::convert::TryFrom<&str> for PackageVersionConstraint {
 
 impl std::fmt::Display for PackageVersionConstraint {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.constraint, self.version)
+        write!(f, "{}{}", self.constraint, self.version)?;
+        if let Some((constraint, version)) = self.range_end.as_ref() {
+            write!(f, " {}{}", constraint, version)?;
+        }
+        Ok(())
     }
 }
 
+fn comparator<'a>() -> PomParser<'a, u8, Vec<u8>> {
+    (pom::parser::seq(b">=") | pom::parser::seq(b"<=") | pom::parser::seq(b">") | pom::parser::seq(b"<") | pom::parser::seq(b"="))
+        .map(|c| c.to_vec())
+}
+
 #[derive(
     parse_display::Display,
     Serialize,
@@ -118,6 +158,62 @@ impl PackageVersion {
             .collect()
             .convert(|b| String::from_utf8(b.to_vec()).map(Self::from))
     }
+
+    /// Compare two versions
+    ///
+    /// If both versions are valid semver versions, they are compared according to semver.
+    /// Otherwise, they are split into runs of digits and non-digits, where runs of digits are
+    /// compared numerically and everything else lexicographically.
+    pub fn compare(&self, other: &PackageVersion) -> std::cmp::Ordering {
+        match (semver::Version::parse(&self.0), semver::Version::parse(&other.0)) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => {
+                let segments_a = version_segments(&self.0);
+                let segments_b = version_segments(&other.0);
+
+                segments_a
+                    .iter()
+                    .zip(segments_b.iter())
+                    .map(|(a, b)| compare_segments(a, b))
+                    .find(|ord| *ord != std::cmp::Ordering::Equal)
+                    .unwrap_or_else(|| segments_a.len().cmp(&segments_b.len()))
+            }
+        }
+    }
+}
+
+/// Split a version string into runs of ascii digits and runs of other characters
+fn version_segments(s: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        let is_digit = c.is_ascii_digit();
+        match chars.peek() {
+            Some((idx, next)) if next.is_ascii_digit() != is_digit => {
+                segments.push(&s[start..*idx]);
+                start = *idx;
+            }
+            None => segments.push(&s[start..]),
+            _ => {}
+        }
+    }
+
+    segments
+}
+
+fn compare_segments(a: &str, b: &str) -> std::cmp::Ordering {
+    let is_number = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+
+    if is_number(a) && is_number(b) {
+        // Compare numerically without parsing, so that arbitrarily long numbers work
+        let a = a.trim_start_matches('0');
+        let b = b.trim_start_matches('0');
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    } else {
+        a.cmp(b)
+    }
 }
 
 #[cfg(test)]
@@ -146,12 +242,6 @@ mod tests {
         assert!(PackageVersionConstraint::parser()
             .parse(b"*1")
             .is_err());
-        assert!(PackageVersionConstraint::parser()
-            .parse(b">1")
-            .is_err());
-        assert!(PackageVersionConstraint::parser()
-            .parse(b"<1")
-            .is_err());
         assert!(PackageVersionConstraint::parser()
             .parse(b"=a")
             .is_err());
@@ -210,4 +300,64 @@ mod tests {
             PackageVersion::from(String::from("1-0B17-beta1247_commit_12653hasd"))
         );
     }
+
+    fn constraint(s: &str) -> PackageVersionConstraint {
+        PackageVersionConstraint::parser().parse(s.as_bytes()).unwrap()
+    }
+
+    fn version(s: &str) -> PackageVersion {
+        PackageVersion::from(String::from(s))
+    }
+
+    #[test]
+    fn test_parse_comparators() {
+        for s in ["=1", "<1", "<=1", ">1", ">=1"].iter() {
+            assert_eq!(constraint(s).to_string(), *s);
+        }
+
+        let c = constraint(">=1.2.0 <2.0.0");
+        assert_eq!(c.version, version("1.2.0"));
+        assert_eq!(c.range_end, Some((String::from("<"), version("2.0.0"))));
+        assert_eq!(c.to_string(), ">=1.2.0 <2.0.0");
+    }
+
+    #[test]
+    fn test_compare_semver() {
+        use std::cmp::Ordering;
+
+        assert_eq!(version("1.2.0").compare(&version("1.10.0")), Ordering::Less);
+        assert_eq!(version("2.0.0").compare(&version("1.10.0")), Ordering::Greater);
+        assert_eq!(version("1.0.0-beta").compare(&version("1.0.0")), Ordering::Less);
+        assert_eq!(version("1.0.0").compare(&version("1.0.0")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_non_semver() {
+        use std::cmp::Ordering;
+
+        assert_eq!(version("1.2").compare(&version("1.10")), Ordering::Less);
+        assert_eq!(version("8.2").compare(&version("8.2.1")), Ordering::Less);
+        assert_eq!(version("1b").compare(&version("1a")), Ordering::Greater);
+        assert_eq!(version("2021_02").compare(&version("2021_10")), Ordering::Less);
+        assert_eq!(version("10").compare(&version("9")), Ordering::Greater);
+        assert_eq!(version("1.01").compare(&version("1.1")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(constraint("=1.0").matches(&version("1.0")));
+        assert!(!constraint("=1.0").matches(&version("1.00")));
+
+        assert!(constraint(">1.0").matches(&version("1.1")));
+        assert!(!constraint(">1.0").matches(&version("1.0")));
+        assert!(constraint(">=1.0").matches(&version("1.0")));
+        assert!(constraint("<1.10").matches(&version("1.9")));
+        assert!(!constraint("<=1.9").matches(&version("1.10")));
+
+        let range = constraint(">=1.2.0 <2.0.0");
+        assert!(range.matches(&version("1.2.0")));
+        assert!(range.matches(&version("1.99.3")));
+        assert!(!range.matches(&version("2.0.0")));
+        assert!(!range.matches(&version("1.1.9")));
+    }
 }
@@ -44,7 +44,9 @@ pub struct FileSystemRepresentation {
 
 impl FileSystemRepresentation {
     /// Load the FileSystemRepresentation object starting a `root`.
-    pub fn load(root: PathBuf) -> Result<Self> {
+    ///
+    /// The directories `excluded` (relative to `root`) are not loaded.
+    pub fn load(root: PathBuf, excluded: &[PathBuf]) -> Result<Self> {
         let mut fsr = FileSystemRepresentation {
             root: root.clone(),
             elements: HashMap::new(),
@@ -64,12 +66,13 @@ impl FileSystemRepresentation {
 
         log::trace!("Loading files from filesystem starting at: {}", root.display());
         log::trace!("Loading with a maximum of {} files open", max_files_open);
+        let excluded = excluded.iter().map(|dir| root.join(dir)).collect::<Vec<_>>();
         WalkDir::new(root)
             .follow_links(false)
             .max_open(max_files_open)
             .same_file_system(true)
             .into_iter()
-            .filter_entry(|e| !is_hidden(e) && (is_pkgtoml(e) || is_dir(e)))
+            .filter_entry(|e| !is_hidden(e) && (is_pkgtoml(e) || is_dir(e)) && !excluded.iter().any(|dir| e.path() == dir))
             .filter_ok(|e| is_pkgtoml(e))
             .inspect(|el| log::trace!("Loading: {:?}", el))
             .map_err(Error::from)
@@ -83,7 +86,7 @@ impl FileSystemRepresentation {
                     match PathComponent::try_from(&cmp)? {
                         PathComponent::PkgToml => {
                             curr_hm.entry(PathComponent::PkgToml)
-                                .or_insert(Element::File(load_file(de_path)?));
+                                .or_insert(Element::File(load_file(de.path())?));
                         },
                         dir @ PathComponent::DirName(_) => {
                             curr_hm.entry(dir.clone())
@@ -16,41 +16,182 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
+use getset::CopyGetters;
+use getset::Getters;
 use log::trace;
 use resiter::AndThen;
 use resiter::FilterMap;
 use resiter::Map;
 
+use crate::config::PackageRepositoryConfig;
+use crate::config::MAIN_PACKAGE_REPOSITORY;
 use crate::package::Package;
 use crate::package::PackageName;
 use crate::package::PackageVersion;
 use crate::package::PackageVersionConstraint;
 
+/// The packages loaded from a package repository, by name and version
+type LoadedPackages = BTreeMap<(PackageName, PackageVersion), Package>;
+
 /// A repository represents a collection of packages
+///
+/// The packages might be loaded from several package repositories, see `Repository::load()`.
+#[derive(Clone)]
 pub struct Repository {
     inner: BTreeMap<(PackageName, PackageVersion), Package>,
+
+    /// The name of the package repository each package was loaded from
+    origins: BTreeMap<(PackageName, PackageVersion), String>,
+
+    /// The packages that were not loaded, because a package repository with higher precedence
+    /// has a package with the same name and version
+    shadowed: Vec<ShadowedPackage>,
+
+    /// The packages that were not loaded, because the same package repository defines a package
+    /// with the same name and version more than once
+    duplicates: Vec<DuplicatePackage>,
+}
+
+/// A package that is shadowed by a package with the same name and version in a package repository
+/// with higher precedence
+#[derive(Clone, Debug, CopyGetters, Getters)]
+pub struct ShadowedPackage {
+    #[getset(get = "pub")]
+    name: PackageName,
+
+    #[getset(get = "pub")]
+    version: PackageVersion,
+
+    /// The package repository of the shadowed package
+    #[getset(get = "pub")]
+    repository: String,
+
+    /// The package repository of the package that is used instead
+    #[getset(get = "pub")]
+    shadowed_by: String,
+
+    /// Whether the repository of the shadowed package allows shadowing it
+    #[getset(get_copy = "pub")]
+    allowed: bool,
+}
+
+/// A package that is defined more than once in the same package repository
+///
+/// Only the first definition (ordered by path) is loaded.
+#[derive(Clone, Debug, Getters)]
+pub struct DuplicatePackage {
+    #[getset(get = "pub")]
+    name: PackageName,
+
+    #[getset(get = "pub")]
+    version: PackageVersion,
+
+    #[getset(get = "pub")]
+    repository: String,
+
+    /// The pkg.toml of the definition that is used
+    #[getset(get = "pub")]
+    path: PathBuf,
+
+    /// The pkg.toml of the definition that is ignored
+    #[getset(get = "pub")]
+    duplicate_path: PathBuf,
 }
 
 #[cfg(test)]
 impl From<BTreeMap<(PackageName, PackageVersion), Package>> for Repository {
     fn from(inner: BTreeMap<(PackageName, PackageVersion), Package>) -> Self {
-        Repository { inner }
+        let origins = inner.keys().map(|k| (k.clone(), String::from(MAIN_PACKAGE_REPOSITORY))).collect();
+        Repository { inner, origins, shadowed: Vec::new(), duplicates: Vec::new() }
     }
 }
 
 impl Repository {
-    fn new(inner: BTreeMap<(PackageName, PackageVersion), Package>) -> Self {
-        Repository { inner }
+    /// Load the packages of the repository at `path` and of the additional package repositories
+    ///
+    /// The packages at `path` take precedence over the packages of the additional repositories,
+    /// which take precedence over the repositories configured after them.
+    /// The additional repositories are loaded from `repo_root`, the checkout of the repository
+    /// `path` belongs to.
+    pub fn load(
+        path: &Path,
+        repo_root: &Path,
+        repositories: &[PackageRepositoryConfig],
+        progress: &indicatif::ProgressBar,
+    ) -> Result<Self> {
+        let mut repo = Repository {
+            inner: BTreeMap::new(),
+            origins: BTreeMap::new(),
+            shadowed: Vec::new(),
+            duplicates: Vec::new(),
+        };
+
+        let excluded = repositories.iter().map(|r| r.path().clone()).collect::<Vec<_>>();
+        let (packages, duplicates) = Self::load_packages(MAIN_PACKAGE_REPOSITORY, path, Path::new(""), &excluded, progress)?;
+        repo.add_below(MAIN_PACKAGE_REPOSITORY, packages, &[]);
+        repo.duplicates.extend(duplicates);
+
+        for repository in repositories {
+            // Repositories nested in this repository are loaded on their own
+            let excluded = repositories
+                .iter()
+                .filter_map(|other| other.path().strip_prefix(repository.path()).ok())
+                .filter(|nested| nested.components().count() > 0)
+                .map(Path::to_path_buf)
+                .collect::<Vec<_>>();
+
+            let root = repository.path_in(repo_root);
+            trace!("Loading package repository '{}' at {}", repository.name(), root.display());
+            let (packages, duplicates) = Self::load_packages(repository.name(), &root, repository.path(), &excluded, progress)
+                .with_context(|| anyhow!("Loading package repository '{}' at {}", repository.name(), root.display()))?;
+            repo.add_below(repository.name(), packages, repository.allow_shadowing());
+            repo.duplicates.extend(duplicates);
+        }
+
+        Ok(repo)
+    }
+
+    /// Add the packages of a package repository with a lower precedence than the packages that
+    /// are already loaded
+    fn add_below(&mut self, repository: &str, packages: BTreeMap<(PackageName, PackageVersion), Package>, allow_shadowing: &[PackageName]) {
+        for (key, package) in packages {
+            if let Some(shadowed_by) = self.origins.get(&key) {
+                trace!("{} {} of '{}' is shadowed by '{}'", key.0, key.1, repository, shadowed_by);
+                self.shadowed.push(ShadowedPackage {
+                    allowed: allow_shadowing.contains(&key.0),
+                    name: key.0,
+                    version: key.1,
+                    repository: repository.to_string(),
+                    shadowed_by: shadowed_by.clone(),
+                });
+            } else {
+                self.origins.insert(key.clone(), repository.to_string());
+                self.inner.insert(key, package);
+            }
+        }
     }
 
-    pub fn load(path: &Path, progress: &indicatif::ProgressBar) -> Result<Self> {
+    /// Load the packages of the package repository at `path`
+    ///
+    /// The paths of the patches are prefixed with `prefix`, the path of the package repository
+    /// relative to the repository butido runs in.
+    ///
+    /// If a package is defined more than once, the definition with the first path is used and the
+    /// others are returned as duplicates.
+    fn load_packages(
+        repository: &str,
+        path: &Path,
+        prefix: &Path,
+        excluded: &[PathBuf],
+        progress: &indicatif::ProgressBar,
+    ) -> Result<(LoadedPackages, Vec<DuplicatePackage>)> {
         use crate::repository::fs::FileSystemRepresentation;
         use config::Config;
         use rayon::iter::IntoParallelRefIterator;
         use rayon::iter::ParallelIterator;
 
         trace!("Loading files from filesystem");
-        let fsr = FileSystemRepresentation::load(path.to_path_buf())?;
+        let fsr = FileSystemRepresentation::load(path.to_path_buf(), excluded)?;
 
         fn get_patches(config: &Config) -> Result<Vec<PathBuf>> {
             match config.get_array("patches") {
@@ -66,7 +207,7 @@ impl Repository {
             }
         }
 
-        fsr.files()
+        let mut loaded = fsr.files()
             .par_iter()
             .inspect(|path| trace!("Checking for leaf file: {}", path.display()))
             .filter_map(|path| {
@@ -94,7 +235,7 @@ impl Repository {
                         let patches = get_patches(&config)?
                             .into_iter()
                             .map(|p| if let Some(current_dir) = path.parent() {
-                                Ok(current_dir.join(p))
+                                Ok(prefix.join(current_dir).join(p))
                             } else {
                                 Err(anyhow!("Path should point to path with parent, but doesn't: {}", path.display()))
                             })
@@ -137,10 +278,58 @@ impl Repository {
                         Ok(config)
                     })
                     .and_then(|c| c.try_into::<Package>().map_err(Error::from))
-                    .map(|pkg| ((pkg.name().clone(), pkg.version().clone()), pkg))
+                    .and_then(|pkg| {
+                        if let Some(retry) = pkg.retry().as_ref() {
+                            retry.validate()
+                                .with_context(|| anyhow!("Checking retry policy of {} {}", pkg.name(), pkg.version()))?;
+                        }
+                        Ok(pkg)
+                    })
+                    .map(|pkg| (prefix.join(path), pkg))
             })
-            .collect::<Result<BTreeMap<_, _>>>()
-            .map(Repository::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        loaded.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut packages: BTreeMap<(PackageName, PackageVersion), (PathBuf, Package)> = BTreeMap::new();
+        let mut duplicates = Vec::new();
+        for (pkg_path, pkg) in loaded {
+            let key = (pkg.name().clone(), pkg.version().clone());
+            if let Some((first_path, _)) = packages.get(&key) {
+                trace!("{} {} is defined in {} and {}", key.0, key.1, first_path.display(), pkg_path.display());
+                duplicates.push(DuplicatePackage {
+                    name: key.0,
+                    version: key.1,
+                    repository: repository.to_string(),
+                    path: first_path.clone(),
+                    duplicate_path: pkg_path,
+                });
+            } else {
+                packages.insert(key, (pkg_path, pkg));
+            }
+        }
+
+        let packages = packages.into_iter().map(|(key, (_, pkg))| (key, pkg)).collect();
+        Ok((packages, duplicates))
+    }
+
+    /// The name of the package repository `package` was loaded from
+    pub fn origin_of(&self, package: &Package) -> Option<&str> {
+        self.origins
+            .get(&(package.name().clone(), package.version().clone()))
+            .map(String::as_str)
+    }
+
+    /// The packages that were not loaded, because a package repository with higher precedence has
+    /// a package with the same name and version
+    pub fn shadowed(&self) -> &[ShadowedPackage] {
+        &self.shadowed
+    }
+
+    /// The packages that were not loaded, because their package repository defines a package with
+    /// the same name and version more than once
+    pub fn duplicates(&self) -> &[DuplicatePackage] {
+        &self.duplicates
     }
 
     pub fn find_by_name<'a>(&'a self, name: &PackageName) -> Vec<&'a Package> {
@@ -175,6 +364,19 @@ impl Repository {
             .collect()
     }
 
+    /// The package with the highest version that matches the constraint
+    ///
+    /// A dependency is always resolved to this package, even if more versions match.
+    pub fn find_highest_with_version<'a>(
+        &'a self,
+        name: &PackageName,
+        vc: &PackageVersionConstraint,
+    ) -> Option<&'a Package> {
+        self.find_with_version(name, vc)
+            .into_iter()
+            .max_by(|a, b| a.version().compare(b.version()))
+    }
+
     pub fn packages(&self) -> impl Iterator<Item = &Package> {
         self.inner.values()
     }
@@ -272,4 +474,30 @@ pub mod tests {
         assert_eq!(*p.version(), pversion("2"));
         assert!(!p.version_is_semver());
     }
+
+    #[test]
+    fn test_shadowing() {
+        let mut main = BTreeMap::new();
+        main.insert((pname("a"), pversion("1")), package("a", "1", "https://rust-lang.org", "123"));
+        let mut repo = Repository::from(main);
+
+        let mut base = BTreeMap::new();
+        base.insert((pname("a"), pversion("1")), package("a", "1", "https://rust-lang.org", "456"));
+        base.insert((pname("b"), pversion("1")), package("b", "1", "https://rust-lang.org", "789"));
+        repo.add_below("base", base, &[pname("a")]);
+
+        let a = repo.find(&pname("a"), &pversion("1"));
+        assert_eq!(a.len(), 1);
+        assert_eq!(repo.origin_of(a[0]), Some(MAIN_PACKAGE_REPOSITORY));
+
+        let b = repo.find(&pname("b"), &pversion("1"));
+        assert_eq!(repo.origin_of(b[0]), Some("base"));
+
+        assert_eq!(repo.shadowed().len(), 1);
+        let shadowed = &repo.shadowed()[0];
+        assert_eq!(*shadowed.name(), pname("a"));
+        assert_eq!(shadowed.repository(), "base");
+        assert_eq!(shadowed.shadowed_by(), MAIN_PACKAGE_REPOSITORY);
+        assert!(shadowed.allowed());
+    }
 }
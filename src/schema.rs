@@ -1,8 +1,87 @@
+table! {
+    artifact_checksums (id) {
+        id -> Int4,
+        artifact_id -> Int4,
+        algorithm -> Varchar,
+        checksum -> Varchar,
+    }
+}
+
+table! {
+    artifact_metadata (id) {
+        id -> Int4,
+        artifact_id -> Int4,
+        kind -> Varchar,
+        key -> Varchar,
+        value -> Text,
+    }
+}
+
+table! {
+    artifact_pins (id) {
+        id -> Int4,
+        artifact_id -> Int4,
+        reason -> Text,
+        pinned_by -> Varchar,
+        pinned_at -> Timestamptz,
+    }
+}
+
 table! {
     artifacts (id) {
         id -> Int4,
         path -> Varchar,
         job_id -> Int4,
+        hermetic -> Bool,
+        output -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int4,
+        logged_at -> Timestamptz,
+        actor -> Varchar,
+        operation -> Varchar,
+        details -> Text,
+        prev_hash -> Varchar,
+        hash -> Varchar,
+    }
+}
+
+table! {
+    build_durations (id) {
+        id -> Int4,
+        package_id -> Int4,
+        image_id -> Int4,
+        average_seconds -> Float8,
+        samples -> Int4,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    checkpoints (id) {
+        id -> Int4,
+        endpoint_id -> Int4,
+        container_id -> Varchar,
+        job_uuid -> Nullable<Uuid>,
+        name -> Varchar,
+        state -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    dag_cache (id) {
+        id -> Int4,
+        root_name -> Varchar,
+        root_version -> Varchar,
+        image -> Varchar,
+        env_hash -> Varchar,
+        repo_hash -> Varchar,
+        resolution -> Text,
+        created -> Timestamptz,
     }
 }
 
@@ -10,6 +89,7 @@ table! {
     endpoints (id) {
         id -> Int4,
         name -> Varchar,
+        drained -> Bool,
     }
 }
 
@@ -54,6 +134,24 @@ table! {
         script_text -> Text,
         log_text -> Text,
         uuid -> Uuid,
+        image_digest -> Nullable<Varchar>,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+        maintainers -> Array<Varchar>,
+        architecture -> Nullable<Varchar>,
+        cache_key -> Nullable<Varchar>,
+        simulated -> Bool,
+    }
+}
+
+table! {
+    live_log_lines (id) {
+        id -> Int4,
+        job_uuid -> Uuid,
+        line_number -> Int4,
+        stream -> Varchar,
+        line -> Text,
+        created_at -> Timestamptz,
     }
 }
 
@@ -65,6 +163,28 @@ table! {
     }
 }
 
+table! {
+    rebuild_reasons (id) {
+        id -> Int4,
+        job_uuid -> Uuid,
+        candidate_job_id -> Nullable<Int4>,
+        kind -> Varchar,
+        detail -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    release_signatures (id) {
+        id -> Int4,
+        release_id -> Int4,
+        tool -> Varchar,
+        signing_key -> Varchar,
+        signature -> Text,
+        signed_at -> Timestamptz,
+    }
+}
+
 table! {
     release_stores (id) {
         id -> Int4,
@@ -81,6 +201,20 @@ table! {
     }
 }
 
+table! {
+    scheduled_submits (id) {
+        id -> Int4,
+        uuid -> Uuid,
+        package_name -> Varchar,
+        package_version -> Varchar,
+        image -> Varchar,
+        repo_hash -> Varchar,
+        requested_by -> Varchar,
+        requested_at -> Timestamptz,
+        scheduled_for -> Timestamptz,
+    }
+}
+
 table! {
     submit_envs (id) {
         id -> Int4,
@@ -97,16 +231,27 @@ table! {
         requested_image_id -> Int4,
         requested_package_id -> Int4,
         repo_hash_id -> Int4,
+        cancelled -> Bool,
+        butido_version -> Nullable<Varchar>,
+        submitted_by -> Nullable<Varchar>,
     }
 }
 
+joinable!(artifact_checksums -> artifacts (artifact_id));
+joinable!(artifact_metadata -> artifacts (artifact_id));
+joinable!(artifact_pins -> artifacts (artifact_id));
 joinable!(artifacts -> jobs (job_id));
+joinable!(build_durations -> images (image_id));
+joinable!(build_durations -> packages (package_id));
+joinable!(checkpoints -> endpoints (endpoint_id));
 joinable!(job_envs -> envvars (env_id));
 joinable!(job_envs -> jobs (job_id));
 joinable!(jobs -> endpoints (endpoint_id));
 joinable!(jobs -> images (image_id));
 joinable!(jobs -> packages (package_id));
 joinable!(jobs -> submits (submit_id));
+joinable!(rebuild_reasons -> jobs (candidate_job_id));
+joinable!(release_signatures -> releases (release_id));
 joinable!(releases -> artifacts (artifact_id));
 joinable!(releases -> release_stores (release_store_id));
 joinable!(submit_envs -> envvars (env_id));
@@ -116,16 +261,27 @@ joinable!(submits -> images (requested_image_id));
 joinable!(submits -> packages (requested_package_id));
 
 allow_tables_to_appear_in_same_query!(
+    artifact_checksums,
+    artifact_metadata,
+    artifact_pins,
     artifacts,
+    audit_log,
+    build_durations,
+    checkpoints,
+    dag_cache,
     endpoints,
     envvars,
     githashes,
     images,
     job_envs,
     jobs,
+    live_log_lines,
     packages,
+    rebuild_reasons,
+    release_signatures,
     release_stores,
     releases,
+    scheduled_submits,
     submit_envs,
     submits,
 );
@@ -0,0 +1,53 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use url::Url;
+
+use crate::source::fetch::FetchSink;
+use crate::source::fetch::SourceFetcher;
+
+/// Copies sources from the local filesystem (or a mounted network share)
+pub struct FileFetcher;
+
+impl FileFetcher {
+    async fn copy(&self, url: &Url, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()> {
+        let path = url
+            .to_file_path()
+            .map_err(|_| anyhow!("Not a local path: {}", url))?;
+
+        let file = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| anyhow!("Opening {}", path.display()))?;
+        let len = file.metadata().await?.len();
+        bar.set_length(len);
+
+        super::copy_with_progress(url, file, Some(len), sink, bar).await
+    }
+}
+
+impl SourceFetcher for FileFetcher {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn schemes(&self) -> &'static [&'static str] {
+        &["file"]
+    }
+
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>> {
+        self.copy(url, sink, bar).boxed()
+    }
+}
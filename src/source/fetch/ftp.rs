@@ -0,0 +1,41 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use url::Url;
+
+use crate::source::fetch::FetchSink;
+use crate::source::fetch::SourceFetcher;
+
+/// Downloads sources via FTP(S), using `curl`
+pub struct FtpFetcher;
+
+impl SourceFetcher for FtpFetcher {
+    fn name(&self) -> &'static str {
+        "ftp"
+    }
+
+    fn schemes(&self) -> &'static [&'static str] {
+        &["ftp", "ftps"]
+    }
+
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>> {
+        let mut cmd = tokio::process::Command::new("curl");
+        cmd.arg("--fail")
+            .arg("--silent")
+            .arg("--show-error")
+            .arg(url.as_str());
+
+        super::copy_command_output(url, cmd, sink, bar).boxed()
+    }
+}
@@ -0,0 +1,113 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use log::warn;
+use url::Url;
+
+use crate::source::fetch::FetchSink;
+use crate::source::fetch::SourceFetcher;
+
+/// Downloads a revision of a git repository as tar archive, using `git`
+///
+/// The URL is the URL of the repository, prefixed with "git+" unless it is a "git://" URL, with the
+/// revision as fragment, e.g. `git+https://example.com/repo.git#v1.0`.
+/// Without a revision, HEAD is downloaded.
+pub struct GitFetcher;
+
+impl GitFetcher {
+    async fn archive(&self, url: &Url, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()> {
+        let revision = url.fragment().unwrap_or("HEAD");
+
+        // git would take the revision as an option
+        if revision.starts_with('-') {
+            return Err(anyhow!("Invalid revision '{}' in {}", revision, url));
+        }
+
+        let repo_url = {
+            let mut repo_url = url.clone();
+            repo_url.set_fragment(None);
+            repo_url.as_str().trim_start_matches("git+").to_string()
+        };
+
+        let clone_dir = std::env::temp_dir().join(format!("butido-git-{}", uuid::Uuid::new_v4()));
+        let result = self.archive_from_clone(url, &repo_url, revision, &clone_dir, sink, bar).await;
+
+        if clone_dir.exists() {
+            if let Err(e) = tokio::fs::remove_dir_all(&clone_dir).await {
+                warn!("Failed to remove clone of {}: {}: {}", repo_url, clone_dir.display(), e);
+            }
+        }
+
+        result
+    }
+
+    async fn archive_from_clone(
+        &self,
+        url: &Url,
+        repo_url: &str,
+        revision: &str,
+        clone_dir: &Path,
+        sink: &mut FetchSink,
+        bar: &ProgressBar,
+    ) -> Result<()> {
+        bar.set_message(format!("Cloning {}", repo_url));
+        let output = tokio::process::Command::new("git")
+            .arg("clone")
+            .arg("--quiet")
+            .arg("--bare")
+            .arg("--")
+            .arg(repo_url)
+            .arg(clone_dir)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .await
+            .context("Spawning git")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Cloning {} failed with {}: {}",
+                repo_url,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let mut cmd = tokio::process::Command::new("git");
+        cmd.arg("--git-dir")
+            .arg(clone_dir)
+            .arg("archive")
+            .arg("--format=tar")
+            .arg(revision);
+
+        super::copy_command_output(url, cmd, sink, bar).await
+    }
+}
+
+impl SourceFetcher for GitFetcher {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn schemes(&self) -> &'static [&'static str] {
+        &["git", "git+https", "git+http", "git+ssh", "git+file"]
+    }
+
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>> {
+        self.archive(url, sink, bar).boxed()
+    }
+}
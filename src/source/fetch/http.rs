@@ -0,0 +1,77 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+use url::Url;
+
+use crate::source::fetch::FetchSink;
+use crate::source::fetch::SourceFetcher;
+
+/// Downloads sources via HTTP(S)
+pub struct HttpFetcher {
+    client: reqwest::Client,
+}
+
+impl HttpFetcher {
+    pub fn new() -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::limited(10))
+            .build()
+            .context("Building HTTP client failed")?;
+
+        Ok(HttpFetcher { client })
+    }
+
+    async fn download(&self, url: &Url, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()> {
+        let request = self.client
+            .get(url.as_ref())
+            .build()
+            .with_context(|| anyhow!("Building request for {} failed", url))?;
+
+        let response = self.client.execute(request).await?;
+
+        let total = response.content_length();
+        if let Some(len) = total {
+            bar.set_length(len);
+        }
+
+        let mut stream = response.bytes_stream();
+        let mut bytes_written = 0;
+        while let Some(bytes) = stream.next().await {
+            let bytes = bytes?;
+            sink.write_all(bytes.as_ref()).await?;
+            bytes_written += bytes.len();
+            super::report_progress(url, bytes.len(), bytes_written, total, bar);
+        }
+
+        Ok(())
+    }
+}
+
+impl SourceFetcher for HttpFetcher {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn schemes(&self) -> &'static [&'static str] {
+        &["http", "https"]
+    }
+
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>> {
+        self.download(url, sink, bar).boxed()
+    }
+}
@@ -0,0 +1,191 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Backends for downloading sources
+//!
+//! Every backend implements `SourceFetcher` and handles a set of URL schemes. The backends are
+//! registered in `SourceFetchers`, which selects the backend for the URL of a source.
+//! Backends besides "http" and "file" can be disabled with the `fetch-*` feature flags.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use indicatif::ProgressBar;
+use log::trace;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+mod file;
+mod http;
+
+#[cfg(feature = "fetch-ftp")]
+mod ftp;
+
+#[cfg(feature = "fetch-git")]
+mod git;
+
+#[cfg(feature = "fetch-s3")]
+mod s3;
+
+/// The sink a source is downloaded into
+pub type FetchSink = dyn AsyncWrite + Send + Unpin;
+
+/// A backend for downloading sources
+pub trait SourceFetcher: Send + Sync {
+    /// The name of the backend, used in messages
+    fn name(&self) -> &'static str;
+
+    /// The URL schemes the backend can download
+    fn schemes(&self) -> &'static [&'static str];
+
+    /// Download `url` into `sink`
+    ///
+    /// The progress is reported on `bar`, its length is set if the size of the download is known.
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>>;
+}
+
+/// The registered backends for downloading sources
+pub struct SourceFetchers(Vec<Box<dyn SourceFetcher>>);
+
+impl SourceFetchers {
+    /// All backends compiled into butido
+    pub fn new() -> Result<Self> {
+        let mut fetchers = SourceFetchers(Vec::new());
+        fetchers.register(Box::new(http::HttpFetcher::new()?));
+        fetchers.register(Box::new(file::FileFetcher));
+
+        #[cfg(feature = "fetch-ftp")]
+        fetchers.register(Box::new(ftp::FtpFetcher));
+
+        #[cfg(feature = "fetch-git")]
+        fetchers.register(Box::new(git::GitFetcher));
+
+        #[cfg(feature = "fetch-s3")]
+        fetchers.register(Box::new(s3::S3Fetcher));
+
+        Ok(fetchers)
+    }
+
+    /// Register a backend
+    ///
+    /// Backends registered later take precedence for the schemes they share with earlier ones.
+    pub fn register(&mut self, fetcher: Box<dyn SourceFetcher>) {
+        trace!("Registering source fetcher {} for {:?}", fetcher.name(), fetcher.schemes());
+        self.0.insert(0, fetcher);
+    }
+
+    /// The backend that downloads `url`
+    pub fn fetcher_for(&self, url: &Url) -> Result<&dyn SourceFetcher> {
+        self.0
+            .iter()
+            .find(|fetcher| fetcher.schemes().contains(&url.scheme()))
+            .map(AsRef::as_ref)
+            .ok_or_else(|| anyhow!("No backend for downloading sources with scheme '{}': {}", url.scheme(), url))
+    }
+
+    /// Download `url` into `sink` with the backend for its scheme
+    pub async fn fetch(&self, url: &Url, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()> {
+        let fetcher = self.fetcher_for(url)?;
+        trace!("Downloading {} with {}", url, fetcher.name());
+        fetcher
+            .fetch(url, sink, bar)
+            .await
+            .with_context(|| anyhow!("Downloading {} with {}", url, fetcher.name()))
+    }
+}
+
+/// Copy `reader` into `sink`, reporting the progress of downloading `url` on `bar`
+///
+/// `total` is the size of the download, if known.
+async fn copy_with_progress<R>(url: &Url, mut reader: R, total: Option<u64>, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = vec![0; 64 * 1024];
+    let mut bytes_written = 0;
+
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        sink.write_all(&buffer[..n]).await?;
+        bytes_written += n;
+        report_progress(url, n, bytes_written, total, bar);
+    }
+}
+
+/// Report that `n` more bytes of `url` were downloaded, `bytes_written` of `total` bytes
+fn report_progress(url: &Url, n: usize, bytes_written: usize, total: Option<u64>, bar: &ProgressBar) {
+    bar.inc(n as u64);
+    if let Some(len) = total {
+        bar.set_message(format!("Downloading {} ({}/{} bytes)", url, bytes_written, len));
+    } else {
+        bar.set_message(format!("Downloading {} ({} bytes)", url, bytes_written));
+    }
+}
+
+/// Run `cmd` and copy its stdout into `sink`, for backends using an external tool
+#[cfg(any(feature = "fetch-ftp", feature = "fetch-git", feature = "fetch-s3"))]
+async fn copy_command_output(url: &Url, mut cmd: tokio::process::Command, sink: &mut FetchSink, bar: &ProgressBar) -> Result<()> {
+    let mut child = cmd
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| anyhow!("Spawning {:?}", cmd))?;
+
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout of {:?}", cmd))?;
+    let copied = copy_with_progress(url, stdout, None, sink, bar).await;
+    let output = child.wait_with_output().await.with_context(|| anyhow!("Waiting for {:?}", cmd))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{:?} failed with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetcher_name(url: &str) -> Result<&'static str> {
+        let fetchers = SourceFetchers::new().unwrap();
+        let url = Url::parse(url).unwrap();
+        fetchers.fetcher_for(&url).map(|f| f.name())
+    }
+
+    #[test]
+    fn test_fetcher_for() {
+        assert_eq!(fetcher_name("https://example.com/foo.tar.gz").unwrap(), "http");
+        assert_eq!(fetcher_name("http://example.com/foo.tar.gz").unwrap(), "http");
+        assert_eq!(fetcher_name("file:///tmp/foo.tar.gz").unwrap(), "file");
+        assert!(fetcher_name("gopher://example.com/foo").is_err());
+    }
+
+    #[cfg(all(feature = "fetch-ftp", feature = "fetch-git", feature = "fetch-s3"))]
+    #[test]
+    fn test_fetcher_for_optional_backends() {
+        assert_eq!(fetcher_name("ftp://example.com/foo.tar.gz").unwrap(), "ftp");
+        assert_eq!(fetcher_name("git+https://example.com/foo.git#v1.0").unwrap(), "git");
+        assert_eq!(fetcher_name("s3://bucket/foo.tar.gz").unwrap(), "s3");
+    }
+}
@@ -0,0 +1,44 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use url::Url;
+
+use crate::source::fetch::FetchSink;
+use crate::source::fetch::SourceFetcher;
+
+/// Downloads sources from S3 buckets (`s3://bucket/key`), using the `aws` CLI
+///
+/// Credentials and region are taken from the environment of butido, as usual for the `aws` CLI.
+pub struct S3Fetcher;
+
+impl SourceFetcher for S3Fetcher {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn schemes(&self) -> &'static [&'static str] {
+        &["s3"]
+    }
+
+    fn fetch<'a>(&'a self, url: &'a Url, sink: &'a mut FetchSink, bar: &'a ProgressBar) -> BoxFuture<'a, Result<()>> {
+        let mut cmd = tokio::process::Command::new("aws");
+        cmd.arg("s3")
+            .arg("cp")
+            .arg("--only-show-errors")
+            .arg(url.as_str())
+            .arg("-");
+
+        super::copy_command_output(url, cmd, sink, bar).boxed()
+    }
+}
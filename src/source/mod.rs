@@ -22,6 +22,9 @@ use crate::package::PackageName;
 use crate::package::PackageVersion;
 use crate::package::Source;
 
+mod fetch;
+pub use fetch::*;
+
 #[derive(Clone, Debug)]
 pub struct SourceCache {
     root: PathBuf,
@@ -55,14 +58,13 @@ impl SourceEntry {
     fn for_package(cache_root: PathBuf, package: &Package) -> Vec<Self> {
         package
             .sources()
-            .clone()
-            .into_iter()
+            .iter()
             .map(|(source_name, source)| SourceEntry {
                 cache_root: cache_root.clone(),
                 package_name: package.name().clone(),
                 package_version: package.version().clone(),
-                package_source_name: source_name,
-                package_source: source,
+                package_source_name: source_name.clone(),
+                package_source: source.clone(),
             })
             .collect()
     }
@@ -87,24 +89,28 @@ impl SourceEntry {
         Ok(())
     }
 
+    /// Verify the file against all hashes of the source
     pub async fn verify_hash(&self) -> Result<()> {
         let p = self.path();
         trace!("Verifying : {}", p.display());
 
-        let reader = tokio::fs::OpenOptions::new()
-            .create(false)
-            .create_new(false)
-            .read(true)
-            .open(&p)
-            .await
-            .map(tokio::io::BufReader::new)
-            .context("Opening file failed")?;
+        for hash in self.package_source.hashes() {
+            let reader = tokio::fs::OpenOptions::new()
+                .create(false)
+                .create_new(false)
+                .read(true)
+                .open(&p)
+                .await
+                .map(tokio::io::BufReader::new)
+                .context("Opening file failed")?;
+
+            trace!("Reader constructed for path: {}", p.display());
+            hash.matches_hash_of(reader)
+                .await
+                .with_context(|| anyhow!("Verifying {} hash", hash.hashtype()))?;
+        }
 
-        trace!("Reader constructed for path: {}", p.display());
-        self.package_source
-            .hash()
-            .matches_hash_of(reader)
-            .await
+        Ok(())
     }
 
     pub async fn create(&self) -> Result<tokio::fs::File> {
@@ -45,6 +45,47 @@ impl AsRef<str> for ImageName {
     }
 }
 
+/// Registry prefixes which Podman reports in image names, but Docker does not
+const DEFAULT_REGISTRY_PREFIXES: &[&str] = &["docker.io/library/", "docker.io/", "localhost/"];
+
+/// The image name `name` without a default registry, so that names from the configuration and
+/// names reported by endpoints can be compared
+fn normalize(name: &str) -> &str {
+    DEFAULT_REGISTRY_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl ImageName {
+    /// Create an ImageName from an image tag as reported by an endpoint
+    ///
+    /// Podman reports images with the registry they come from (e.g.
+    /// "docker.io/library/debian:bullseye") where Docker reports "debian:bullseye", so the default
+    /// registries are removed to get the name as it is used in the configuration.
+    pub fn from_endpoint_tag(tag: String) -> Self {
+        ImageName(normalize(&tag).to_string())
+    }
+
+    /// Whether this name and `other` name the same image, with or without a default registry
+    pub fn is_same_image(&self, other: &ImageName) -> bool {
+        normalize(&self.0) == normalize(&other.0)
+    }
+
+    /// The reference of this image pinned to `digest`, e.g. "debian@sha256:..."
+    ///
+    /// The tag is removed, because it is ignored when a digest is given anyways.
+    pub fn pinned(&self, digest: &str) -> Self {
+        let name = self.0.split('@').next().unwrap_or(&self.0);
+        let repository = match (name.rfind(':'), name.rfind('/')) {
+            (Some(colon), Some(slash)) if colon < slash => name, // the colon separates a registry port
+            (Some(colon), _) => &name[..colon],
+            (None, _) => name,
+        };
+        ImageName(format!("{}@{}", repository, digest))
+    }
+}
+
 #[derive(
     parse_display::Display,
     Serialize,
@@ -72,3 +113,39 @@ impl AsRef<str> for ContainerHash {
         self.0.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ImageName;
+
+    #[test]
+    fn test_image_name_from_endpoint_tag() {
+        let name = |s: &str| ImageName::from_endpoint_tag(String::from(s));
+        assert_eq!(name("debian:bullseye"), ImageName::from("debian:bullseye"));
+        assert_eq!(name("docker.io/library/debian:bullseye"), ImageName::from("debian:bullseye"));
+        assert_eq!(name("docker.io/someone/image:1"), ImageName::from("someone/image:1"));
+        assert_eq!(name("localhost/local:latest"), ImageName::from("local:latest"));
+        assert_eq!(name("registry.example.com/image:1"), ImageName::from("registry.example.com/image:1"));
+    }
+
+    #[test]
+    fn test_image_name_is_same_image() {
+        let same = |a: &str, b: &str| ImageName::from(a).is_same_image(&ImageName::from(b));
+        assert!(same("debian:bullseye", "debian:bullseye"));
+        assert!(same("docker.io/library/debian:bullseye", "debian:bullseye"));
+        assert!(same("debian:bullseye", "docker.io/library/debian:bullseye"));
+        assert!(same("docker.io/someone/image:1", "someone/image:1"));
+        assert!(!same("debian:bullseye", "debian:buster"));
+        assert!(!same("registry.example.com/debian:bullseye", "debian:bullseye"));
+    }
+
+    #[test]
+    fn test_image_name_pinned() {
+        let pinned = |s: &str| ImageName::from(s).pinned("sha256:abc");
+        assert_eq!(pinned("debian:bullseye"), ImageName::from("debian@sha256:abc"));
+        assert_eq!(pinned("debian"), ImageName::from("debian@sha256:abc"));
+        assert_eq!(pinned("registry.example.com:5000/image:1"), ImageName::from("registry.example.com:5000/image@sha256:abc"));
+        assert_eq!(pinned("registry.example.com:5000/image"), ImageName::from("registry.example.com:5000/image@sha256:abc"));
+        assert_eq!(pinned("debian@sha256:def"), ImageName::from("debian@sha256:abc"));
+    }
+}
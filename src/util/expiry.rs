@@ -0,0 +1,244 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Expiry dates of the secrets and certificates butido uses
+//!
+//! Only the gpg key released artifacts are signed with and the client certificates in the
+//! kubeconfig of Kubernetes endpoints are checked. Minisign keys do not expire, and the other
+//! endpoints and the database are not configured with credentials that expire.
+
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use log::debug;
+use log::warn;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::Configuration;
+use crate::config::EndpointType;
+use crate::config::SigningTool;
+
+/// The default for `expiry_warning_days` in the configuration
+const DEFAULT_WARNING_DAYS: u32 = 30;
+
+/// The expiry date of one secret or certificate
+pub struct ExpiryCheck {
+    /// What was checked, for humans
+    pub name: String,
+
+    /// When it expires, None if it does not expire
+    pub expires: Result<Option<NaiveDateTime>>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ExpiryStatus {
+    Valid,
+    ExpiresSoon,
+    Expired,
+    NoExpiry,
+    Unknown,
+}
+
+impl ExpiryCheck {
+    /// The status of the checked item at `now`, warning `warning_days` days before it expires
+    pub fn status(&self, now: &NaiveDateTime, warning_days: u32) -> ExpiryStatus {
+        match self.expires.as_ref() {
+            Err(_) => ExpiryStatus::Unknown,
+            Ok(None) => ExpiryStatus::NoExpiry,
+            Ok(Some(date)) if date <= now => ExpiryStatus::Expired,
+            Ok(Some(date)) if *date <= *now + chrono::Duration::days(i64::from(warning_days)) => ExpiryStatus::ExpiresSoon,
+            Ok(Some(_)) => ExpiryStatus::Valid,
+        }
+    }
+}
+
+/// How many days before they expire secrets and certificates are warned about
+pub fn warning_days(config: &Configuration) -> u32 {
+    config.expiry_warning_days().unwrap_or(DEFAULT_WARNING_DAYS)
+}
+
+/// Check the expiry dates of all secrets and certificates in the configuration
+pub async fn check_all(config: &Configuration) -> Vec<ExpiryCheck> {
+    let mut checks = Vec::new();
+
+    if let Some(signing) = config.release_signing().as_ref() {
+        if *signing.tool() == SigningTool::Gpg {
+            checks.push(ExpiryCheck {
+                name: format!("gpg signing key {}", signing.key()),
+                expires: gpg_key_expiry(signing.key()).await,
+            });
+        }
+    }
+
+    for (ep_name, ep) in config.docker().endpoints().iter() {
+        if *ep.endpoint_type() != EndpointType::Kubernetes {
+            continue;
+        }
+
+        let expires = async {
+            let kubernetes = crate::endpoint::Kubernetes::new(ep.uri().clone(), ep.kubeconfig().clone(), ep.namespace().clone())?;
+            match kubernetes.client_certificate().await? {
+                Some(pem) => certificate_expiry(&pem).await.map(Some),
+                None => Ok(None),
+            }
+        }
+        .await;
+
+        checks.push(ExpiryCheck {
+            name: format!("client certificate of endpoint {}", ep_name),
+            expires,
+        });
+    }
+
+    checks
+}
+
+/// Warn about secrets and certificates that expired or expire soon
+///
+/// Failing checks are only logged, so that they do not get in the way of a build.
+pub async fn warn_expiring(config: &Configuration) {
+    let warning_days = warning_days(config);
+    if warning_days == 0 {
+        return;
+    }
+
+    let now = chrono::offset::Utc::now().naive_utc();
+    for check in check_all(config).await {
+        match (check.status(&now, warning_days), check.expires) {
+            (ExpiryStatus::Expired, Ok(Some(date))) => warn!("The {} expired at {} UTC", check.name, date),
+            (ExpiryStatus::ExpiresSoon, Ok(Some(date))) => warn!("The {} expires at {} UTC", check.name, date),
+            (_, Err(e)) => debug!("Could not check expiry of the {}: {:?}", check.name, e),
+            _ => {}
+        }
+    }
+}
+
+/// The latest expiry date of the keys that can sign with the gpg key `key`
+async fn gpg_key_expiry(key: &str) -> Result<Option<NaiveDateTime>> {
+    let output = Command::new("gpg")
+        .args(&["--batch", "--with-colons", "--fixed-list-mode", "--list-keys"])
+        .arg(key)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Running gpg")?;
+
+    if !output.status.success() {
+        return Err(anyhow!("gpg failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(parse_gpg_expiry(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Find the expiry date in the output of `gpg --with-colons --list-keys`
+///
+/// Signing works as long as one of the (not revoked) keys with the signing capability is valid,
+/// so the latest of their expiry dates is returned, or None if one of them does not expire.
+fn parse_gpg_expiry(out: &str) -> Option<NaiveDateTime> {
+    let expiries = out
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .filter(|fields| fields[0] == "pub" || fields[0] == "sub")
+        .filter(|fields| fields.get(1).map(|validity| *validity != "r").unwrap_or(false))
+        .filter(|fields| fields.get(11).map(|caps| caps.contains('s')).unwrap_or(false))
+        .map(|fields| fields.get(6).and_then(|exp| exp.parse::<i64>().ok()))
+        .collect::<Vec<Option<i64>>>();
+
+    if expiries.iter().any(Option::is_none) {
+        return None;
+    }
+
+    expiries
+        .into_iter()
+        .flatten()
+        .max()
+        .map(|timestamp| NaiveDateTime::from_timestamp(timestamp, 0))
+}
+
+/// The expiry date of the PEM encoded certificate `pem`
+async fn certificate_expiry(pem: &[u8]) -> Result<NaiveDateTime> {
+    let mut child = Command::new("openssl")
+        .args(&["x509", "-noout", "-enddate"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Running openssl")?;
+
+    {
+        let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for openssl"))?;
+        stdin.write_all(pem).await.context("Writing certificate to openssl")?;
+    }
+
+    let output = child.wait_with_output().await.context("Running openssl")?;
+    if !output.status.success() {
+        return Err(anyhow!("openssl failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    parse_openssl_enddate(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the output of `openssl x509 -enddate`, e.g. "notAfter=Mar  4 12:00:00 2022 GMT"
+fn parse_openssl_enddate(out: &str) -> Result<NaiveDateTime> {
+    let date = out
+        .trim()
+        .strip_prefix("notAfter=")
+        .ok_or_else(|| anyhow!("Unexpected output of openssl: {}", out.trim()))?
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    NaiveDateTime::parse_from_str(&date, "%b %d %H:%M:%S %Y GMT")
+        .with_context(|| anyhow!("Parsing expiry date of certificate: {}", date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gpg_expiry() {
+        let out = "tru::1:1615000000:0:3:1:5\n\
+                   pub:u:255:22:AAAAAAAAAAAAAAAA:1600000000:1700000000::u:::scSC::::::ed25519:::0:\n\
+                   fpr:::::::::0123456789ABCDEF0123456789ABCDEFAAAAAAAA:\n\
+                   uid:u::::1600000000::0000000000000000000000000000000000000000::Builder <builder@example.com>::::::::::0:\n\
+                   sub:u:255:22:BBBBBBBBBBBBBBBB:1600000000:1650000000:::::s::::::ed25519::\n\
+                   sub:u:255:18:CCCCCCCCCCCCCCCC:1600000000:1800000000:::::e::::::cv25519::\n";
+
+        // The encryption subkey does not count
+        assert_eq!(parse_gpg_expiry(out), Some(NaiveDateTime::from_timestamp(1_700_000_000, 0)));
+
+        let out = "pub:u:255:22:AAAAAAAAAAAAAAAA:1600000000:::u:::scSC::::::ed25519:::0:\n";
+        assert_eq!(parse_gpg_expiry(out), None);
+    }
+
+    #[test]
+    fn test_parse_openssl_enddate() {
+        let date = parse_openssl_enddate("notAfter=Mar  4 12:30:00 2022 GMT\n").unwrap();
+        assert_eq!(date.to_string(), "2022-03-04 12:30:00");
+
+        assert!(parse_openssl_enddate("foo").is_err());
+    }
+
+    #[test]
+    fn test_status() {
+        let now = NaiveDateTime::from_timestamp(1_600_000_000, 0);
+        let check = |expires| ExpiryCheck { name: String::from("key"), expires: Ok(expires) };
+
+        assert_eq!(check(None).status(&now, 30), ExpiryStatus::NoExpiry);
+        assert_eq!(check(Some(now - chrono::Duration::days(1))).status(&now, 30), ExpiryStatus::Expired);
+        assert_eq!(check(Some(now + chrono::Duration::days(10))).status(&now, 30), ExpiryStatus::ExpiresSoon);
+        assert_eq!(check(Some(now + chrono::Duration::days(40))).status(&now, 30), ExpiryStatus::Valid);
+    }
+}
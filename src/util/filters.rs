@@ -16,16 +16,22 @@ use resiter::Map;
 
 use crate::package::Package;
 use crate::package::PackageName;
+use crate::package::PackageVersion;
 use crate::package::PackageVersionConstraint;
 use crate::package::ParseDependency;
 
 /// Helper function to build a package filter based on some flags and the package version
+///
+/// If `version` is passed, only dependencies on `name` whose version constraint is matched by
+/// `version` are considered.
 pub fn build_package_filter_by_dependency_name(
     name: &PackageName,
+    version: Option<&PackageVersion>,
     check_build_dep: bool,
     check_runtime_dep: bool,
 ) -> impl filters::failable::filter::FailableFilter<Package, Error = Error> {
     let n = name.clone(); // clone, so we can move into closure
+    let v = version.cloned();
     let filter_build_dep = move |p: &Package| -> Result<bool> {
         trace!("Checking whether any build depenency of {:?} is '{}'", p, n);
         Ok({
@@ -35,7 +41,9 @@ pub fn build_package_filter_by_dependency_name(
                     .iter()
                     .inspect(|d| trace!("Checking {:?}", d))
                     .map(|d| d.parse_as_name_and_version())
-                    .map_ok(|(name, _)| name == n)
+                    .map_ok(|(name, constraint)| {
+                        name == n && v.as_ref().map(|v| constraint.matches(v)).unwrap_or(true)
+                    })
                     .collect::<Result<Vec<bool>>>()?
                     .into_iter()
                     .inspect(|b| trace!("found: {}", b))
@@ -44,6 +52,7 @@ pub fn build_package_filter_by_dependency_name(
     };
 
     let n = name.clone(); // clone, so we can move into closure
+    let v = version.cloned();
     let filter_rt_dep = move |p: &Package| -> Result<bool> {
         trace!(
             "Checking whether any runtime depenency of {:?} is '{}'",
@@ -57,7 +66,9 @@ pub fn build_package_filter_by_dependency_name(
                     .iter()
                     .inspect(|d| trace!("Checking {:?}", d))
                     .map(|d| d.parse_as_name_and_version())
-                    .map_ok(|(name, _)| name == n)
+                    .map_ok(|(name, constraint)| {
+                        name == n && v.as_ref().map(|v| constraint.matches(v)).unwrap_or(true)
+                    })
                     .collect::<Result<Vec<bool>>>()?
                     .into_iter()
                     .inspect(|b| trace!("found: {}", b))
@@ -120,7 +131,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, false);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, false);
 
         let found = repo
             .packages()
@@ -150,7 +161,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, false);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, false);
 
         let found = repo
             .packages()
@@ -180,7 +191,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, true);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, true);
 
         let found = repo
             .packages()
@@ -216,7 +227,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, false);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, false);
 
         let found = repo
             .packages()
@@ -245,7 +256,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, true);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, true);
 
         let found = repo
             .packages()
@@ -278,7 +289,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, true);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, true);
 
         let found = repo
             .packages()
@@ -333,7 +344,7 @@ mod tests {
 
         let repo = Repository::from(btree);
 
-        let f = build_package_filter_by_dependency_name(&pname("foo"), false, true);
+        let f = build_package_filter_by_dependency_name(&pname("foo"), None, false, true);
 
         let found = repo
             .packages()
@@ -371,4 +382,44 @@ mod tests {
             assert!(p.dependencies().build().is_empty());
         }
     }
+
+    #[test]
+    fn test_filter_by_dependency_version() {
+        let mut btree = BTreeMap::new();
+
+        {
+            let name = "a";
+            let vers = "1";
+            let mut pack = package(name, vers, "https://rust-lang.org", "123");
+            pack.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+                String::from("foo =2"),
+            )));
+            btree.insert((pname(name), pversion(vers)), pack);
+        }
+
+        {
+            let name = "b";
+            let vers = "2";
+            let mut pack = package(name, vers, "https://rust-lang.org", "123");
+            pack.set_dependencies(Dependencies::with_runtime_dependency(Dependency::from(
+                String::from("foo =4"),
+            )));
+            btree.insert((pname(name), pversion(vers)), pack);
+        }
+
+        let repo = Repository::from(btree);
+
+        let f = build_package_filter_by_dependency_name(&pname("foo"), Some(&pversion("4")), false, true);
+
+        let found = repo
+            .packages()
+            .map(|p| f.filter(p).map(|b| (b, p)))
+            .filter_ok(|(b, _)| *b)
+            .map_ok(|tpl| tpl.1)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].name(), pname("b"));
+    }
 }
@@ -0,0 +1,318 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Metrics of a running submit, in the Prometheus text format
+//!
+//! The metrics are collected for every submit. They are served via HTTP if `build --metrics-addr`
+//! is passed, which is only available if butido is built with the "metrics" feature.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use uuid::Uuid;
+
+/// The upper bounds (in seconds) of the buckets of the database write latency histogram
+const DB_WRITE_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// The upper bounds (in seconds) of the buckets of the build duration histograms
+const BUILD_DURATION_BUCKETS: &[f64] = &[60.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JobState {
+    Queued,
+    Running(Instant),
+    Succeeded,
+    Failed,
+}
+
+/// The metrics of a submit
+///
+/// Shared between the orchestrator, the scheduler and the HTTP server serving them.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The state of every job, with the name of its package
+    jobs: HashMap<Uuid, (String, JobState)>,
+    endpoint_containers: BTreeMap<String, u64>,
+    artifact_reuse_hits: u64,
+    db_write_duration: Histogram,
+    build_duration: BTreeMap<String, Histogram>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            jobs: HashMap::new(),
+            endpoint_containers: BTreeMap::new(),
+            artifact_reuse_hits: 0,
+            db_write_duration: Histogram::new(DB_WRITE_BUCKETS),
+            build_duration: BTreeMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn job_queued(&self, job: Uuid, package: &str) {
+        self.state().jobs.insert(job, (package.to_string(), JobState::Queued));
+    }
+
+    pub fn job_running(&self, job: &Uuid) {
+        self.set_job_state(job, JobState::Running(Instant::now()));
+    }
+
+    /// The job finished successfully, its run time is recorded for its package
+    pub fn job_succeeded(&self, job: &Uuid) {
+        let mut state = self.state();
+        if let Some((package, JobState::Running(since))) = state.jobs.get(job).cloned() {
+            state.build_duration
+                .entry(package)
+                .or_insert_with(|| Histogram::new(BUILD_DURATION_BUCKETS))
+                .observe(since.elapsed());
+        }
+        state.set_job_state(job, JobState::Succeeded);
+    }
+
+    /// The job was not run because artifacts could be reused
+    pub fn job_reused(&self, job: &Uuid) {
+        let mut state = self.state();
+        state.artifact_reuse_hits += 1;
+        state.set_job_state(job, JobState::Succeeded);
+    }
+
+    pub fn job_failed(&self, job: &Uuid) {
+        self.set_job_state(job, JobState::Failed);
+    }
+
+    pub fn container_started(&self, endpoint: &str) {
+        *self.state().endpoint_containers.entry(endpoint.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn container_finished(&self, endpoint: &str) {
+        if let Some(n) = self.state().endpoint_containers.get_mut(endpoint) {
+            *n = n.saturating_sub(1);
+        }
+    }
+
+    /// Run `f`, which writes to the database, and record how long it took
+    pub fn db_write<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let start = Instant::now();
+        let result = f();
+        self.state().db_write_duration.observe(start.elapsed());
+        result
+    }
+
+    /// The metrics in the Prometheus text format
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub fn render(&self) -> String {
+        let state = self.state();
+        let mut out = String::new();
+
+        header(&mut out, "butido_jobs", "gauge", "The jobs of the submit, by state");
+        for (label, in_state) in [
+            ("queued", (|s: &JobState| *s == JobState::Queued) as fn(&JobState) -> bool),
+            ("running", |s| matches!(s, JobState::Running(_))),
+            ("succeeded", |s| *s == JobState::Succeeded),
+            ("failed", |s| *s == JobState::Failed),
+        ].iter() {
+            let count = state.jobs.values().filter(|(_, s)| in_state(s)).count();
+            let _ = writeln!(out, "butido_jobs{{state=\"{}\"}} {}", label, count);
+        }
+
+        header(&mut out, "butido_endpoint_containers", "gauge", "The containers running on an endpoint");
+        for (endpoint, count) in state.endpoint_containers.iter() {
+            let _ = writeln!(out, "butido_endpoint_containers{{endpoint=\"{}\"}} {}", escape(endpoint), count);
+        }
+
+        header(&mut out, "butido_artifact_reuse_hits_total", "counter", "The jobs which reused existing artifacts instead of being run");
+        let _ = writeln!(out, "butido_artifact_reuse_hits_total {}", state.artifact_reuse_hits);
+
+        header(&mut out, "butido_db_write_duration_seconds", "histogram", "How long writes to the database took");
+        state.db_write_duration.render(&mut out, "butido_db_write_duration_seconds", None);
+
+        header(&mut out, "butido_build_duration_seconds", "histogram", "How long the successful jobs of a package ran");
+        for (package, histogram) in state.build_duration.iter() {
+            let label = format!("package=\"{}\"", escape(package));
+            histogram.render(&mut out, "butido_build_duration_seconds", Some(&label));
+        }
+
+        out
+    }
+
+    fn set_job_state(&self, job: &Uuid, job_state: JobState) {
+        self.state().set_job_state(job, job_state)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // The state is consistent after every single update, so a poisoned lock can be used
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn set_job_state(&mut self, job: &Uuid, job_state: JobState) {
+        if let Some((_, s)) = self.jobs.get_mut(job) {
+            *s = job_state;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+
+    /// The number of observations less than or equal to the bound with the same index
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, d: Duration) {
+        let secs = d.as_secs_f64();
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if secs <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    fn render(&self, out: &mut String, name: &str, label: Option<&str>) {
+        let prefix = label.map(|l| format!("{},", l)).unwrap_or_default();
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, prefix, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, prefix, self.count);
+
+        let labels = label.map(|l| format!("{{{}}}", l)).unwrap_or_default();
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn header(out: &mut String, name: &str, typ: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ);
+}
+
+/// Escape a label value
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serve the metrics on `addr` below `/metrics`, until the returned task is aborted
+#[cfg(feature = "metrics")]
+pub fn serve(metrics: std::sync::Arc<Metrics>, addr: std::net::SocketAddr) -> anyhow::Result<tokio::task::JoinHandle<()>> {
+    use anyhow::Context;
+    use hyper::Body;
+    use hyper::Method;
+    use hyper::Response;
+    use hyper::StatusCode;
+    use std::convert::Infallible;
+
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |request: hyper::Request<Body>| {
+                let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(Body::from(metrics.render()))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())
+                };
+
+                // safe because status and header are valid
+                async move { Ok::<_, Infallible>(response.unwrap()) }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)
+        .with_context(|| anyhow::anyhow!("Binding to {}", addr))?
+        .serve(make_service);
+
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    Ok(tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::warn!("Serving metrics failed: {}", e);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_jobs_and_reuse() {
+        let metrics = Metrics::default();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        metrics.job_queued(a, "foo");
+        metrics.job_queued(b, "bar");
+        metrics.job_queued(c, "baz");
+        metrics.job_running(&a);
+        metrics.job_succeeded(&a);
+        metrics.job_reused(&b);
+        metrics.container_started("ep1");
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("butido_jobs{state=\"queued\"} 1\n"));
+        assert!(rendered.contains("butido_jobs{state=\"succeeded\"} 2\n"));
+        assert!(rendered.contains("butido_artifact_reuse_hits_total 1\n"));
+        assert!(rendered.contains("butido_endpoint_containers{endpoint=\"ep1\"} 1\n"));
+        assert!(rendered.contains("butido_build_duration_seconds_count{package=\"foo\"} 1\n"));
+        assert!(!rendered.contains("package=\"bar\""));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[1.0, 10.0]);
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(5));
+        histogram.observe(Duration::from_secs(50));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "x", None);
+        assert_eq!(out, "x_bucket{le=\"1\"} 1\nx_bucket{le=\"10\"} 2\nx_bucket{le=\"+Inf\"} 3\nx_sum 55.5\nx_count 3\n");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
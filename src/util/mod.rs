@@ -39,13 +39,44 @@ impl AsRef<str> for EnvironmentVariableName {
     }
 }
 
+/// The name of an architecture jobs are built for, e.g. "x86_64" or "aarch64"
+#[derive(
+    parse_display::Display,
+    Serialize,
+    Deserialize,
+    Clone,
+    Debug,
+    Hash,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+)]
+#[serde(transparent)]
+#[display("{0}")]
+pub struct Architecture(String);
+
+impl From<String> for Architecture {
+    fn from(s: String) -> Architecture {
+        Architecture(s)
+    }
+}
+
+impl AsRef<str> for Architecture {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
 
 pub mod docker;
 pub mod env;
+pub mod expiry;
 pub mod filters;
 pub mod git;
+pub mod metrics;
 pub mod parser;
 pub mod progress;
+pub mod signing;
 
 pub fn stdout_is_pipe() -> bool {
     !atty::is(atty::Stream::Stdout)
@@ -29,6 +29,14 @@ impl ProgressBars {
         }
     }
 
+    /// The same progress bars, but hidden
+    pub fn hidden(&self) -> Self {
+        ProgressBars {
+            hide: true,
+            ..self.clone()
+        }
+    }
+
     pub fn bar(&self) -> ProgressBar {
         if self.hide {
             ProgressBar::hidden()
@@ -48,4 +56,15 @@ impl ProgressBars {
             bar
         }
     }
+
+    /// A line showing only its message
+    pub fn status_line(&self) -> ProgressBar {
+        if self.hide {
+            ProgressBar::hidden()
+        } else {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(ProgressStyle::default_spinner().template("{msg}"));
+            bar
+        }
+    }
 }
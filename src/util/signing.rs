@@ -0,0 +1,224 @@
+//
+// Copyright (c) 2020-2021 science+computing ag and other contributors
+//
+// This program and the accompanying materials are made
+// available under the terms of the Eclipse Public License 2.0
+// which is available at https://www.eclipse.org/legal/epl-2.0/
+//
+// SPDX-License-Identifier: EPL-2.0
+//
+
+//! Detached signatures of released artifacts, created with gpg or minisign
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::SigningConfig;
+use crate::config::SigningTool;
+use crate::job::SecretValue;
+
+/// The path of the signature of the file at `path`
+pub fn signature_path(tool: &SigningTool, path: &Path) -> PathBuf {
+    let mut p = path.as_os_str().to_os_string();
+    p.push(".");
+    p.push(tool.signature_extension());
+    PathBuf::from(p)
+}
+
+/// Whether the file at `path` is a signature of a file next to it
+pub fn is_signature_file(path: &Path) -> bool {
+    let is_signature_ext = path.extension()
+        .map(|ext| ext == OsStr::new("asc") || ext == OsStr::new("minisig"))
+        .unwrap_or(false);
+
+    is_signature_ext && path.with_extension("").is_file()
+}
+
+/// Sign the file at `path`, returning the path of the signature
+///
+/// If a password is configured, it is passed to the tool on stdin, so encrypted keys can be used.
+pub async fn sign(config: &SigningConfig, path: &Path) -> Result<PathBuf> {
+    let password = match config.password().as_ref() {
+        Some(p) => Some(p.fetch().await.context("Fetching the password of the signing key")?),
+        None => None,
+    };
+
+    let sig = signature_path(config.tool(), path);
+    let mut cmd = match config.tool() {
+        SigningTool::Gpg => {
+            let mut cmd = Command::new("gpg");
+            cmd.args(&["--batch", "--yes"]);
+            if password.is_some() {
+                cmd.args(&["--pinentry-mode", "loopback", "--passphrase-fd", "0"]);
+            }
+            cmd.args(&["--detach-sign", "--armor", "--local-user"])
+                .arg(config.key())
+                .arg("--output")
+                .arg(&sig)
+                .arg(path);
+            cmd
+        }
+        SigningTool::Minisign => {
+            let mut cmd = Command::new("minisign");
+            cmd.arg("-S")
+                .arg("-s")
+                .arg(config.key())
+                .arg("-m")
+                .arg(path)
+                .arg("-x")
+                .arg(&sig);
+            cmd
+        }
+    };
+
+    run(&mut cmd, config.tool(), password.as_ref())
+        .await
+        .with_context(|| anyhow!("Signing {}", path.display()))?;
+    Ok(sig)
+}
+
+/// Verify the signature `sig` of the file at `path`, which was created with `tool`
+///
+/// The signature has to be made with the key of `signing`. For gpg, the fingerprint of the
+/// signing key reported by gpg is compared to the configured `key`, any other key in the keyring
+/// is rejected. For minisign, the configured `public_key` is required.
+pub async fn verify(tool: &SigningTool, signing: Option<&SigningConfig>, path: &Path, sig: &Path) -> Result<()> {
+    let mut cmd = match tool {
+        SigningTool::Gpg => {
+            let mut cmd = Command::new("gpg");
+            cmd.args(&["--batch", "--status-fd", "1", "--verify"]).arg(sig).arg(path);
+            cmd
+        }
+        SigningTool::Minisign => {
+            let public_key = signing
+                .and_then(|signing| signing.public_key().as_ref())
+                .ok_or_else(|| anyhow!("No public key configured to verify minisign signatures"))?;
+
+            let mut cmd = Command::new("minisign");
+            cmd.arg("-V")
+                .arg("-p")
+                .arg(public_key)
+                .arg("-m")
+                .arg(path)
+                .arg("-x")
+                .arg(sig);
+            cmd
+        }
+    };
+
+    let output = run(&mut cmd, tool, None)
+        .await
+        .with_context(|| anyhow!("Verifying signature of {}", path.display()))?;
+
+    if *tool == SigningTool::Gpg {
+        let key = signing
+            .map(|signing| signing.key().as_str())
+            .ok_or_else(|| anyhow!("No key configured to verify gpg signatures"))?;
+        check_gpg_signer(&output, key).with_context(|| anyhow!("Verifying signature of {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Check that the gpg status output `status` (`--status-fd`) reports a valid signature made with
+/// `key`
+///
+/// `key` is a key ID or fingerprint, which has to match the end of the fingerprint of the signing
+/// key or of its primary key.
+fn check_gpg_signer(status: &str, key: &str) -> Result<()> {
+    let key = key.trim_start_matches("0x").replace(' ', "").to_uppercase();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Not a gpg key ID or fingerprint: {}", key));
+    }
+
+    let signers = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .map(|args| args.split_whitespace().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    if signers.is_empty() {
+        return Err(anyhow!("gpg did not report a valid signature"));
+    }
+
+    // The fingerprint of the primary key is the tenth argument, if the signing key is a subkey
+    for fingerprints in signers {
+        let is_key = fingerprints.iter()
+            .take(1)
+            .chain(fingerprints.get(9))
+            .any(|fpr| fpr.to_uppercase().ends_with(&key));
+
+        if !is_key {
+            return Err(anyhow!("Signed with key {}, not with the configured key {}", fingerprints.first().unwrap_or(&""), key));
+        }
+    }
+
+    Ok(())
+}
+
+/// Run `cmd`, with `password` on its stdin, and return its output
+async fn run(cmd: &mut Command, tool: &SigningTool, password: Option<&SecretValue>) -> Result<String> {
+    let mut child = cmd
+        .stdin(if password.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| anyhow!("Running {}", tool))?;
+
+    if let Some(password) = password {
+        let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin of {}", tool))?;
+        stdin.write_all(password.expose().as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+    }
+
+    let output = child.wait_with_output()
+        .await
+        .with_context(|| anyhow!("Waiting for {}", tool))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(anyhow!(
+            "{} failed ({}): {}",
+            tool,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_path() {
+        let p = Path::new("/releases/default/foo-1.0.tar.gz");
+        assert_eq!(signature_path(&SigningTool::Gpg, p), Path::new("/releases/default/foo-1.0.tar.gz.asc"));
+        assert_eq!(signature_path(&SigningTool::Minisign, p), Path::new("/releases/default/foo-1.0.tar.gz.minisig"));
+    }
+
+    #[test]
+    fn test_check_gpg_signer() {
+        let status = "[GNUPG:] NEWSIG\n\
+            [GNUPG:] GOODSIG 4B2A9F6C1D3E5F70 butido <butido@example.com>\n\
+            [GNUPG:] VALIDSIG 0123456789ABCDEF0123456789ABCDEF01234567 2021-03-24 1616580000 0 4 0 1 10 00 89ABCDEF0123456789ABCDEF0123456789ABCDEF\n";
+
+        // The signing (sub)key and its primary key, as fingerprint or key ID
+        assert!(check_gpg_signer(status, "0123456789ABCDEF0123456789ABCDEF01234567").is_ok());
+        assert!(check_gpg_signer(status, "0x89ABCDEF").is_ok());
+        assert!(check_gpg_signer(status, "0123 4567 89ab cdef 0123  4567 89ab cdef 0123 4567").is_ok());
+
+        assert!(check_gpg_signer(status, "0xDEADBEEF").is_err());
+        assert!(check_gpg_signer(status, "butido@example.com").is_err());
+        assert!(check_gpg_signer("[GNUPG:] BADSIG 4B2A9F6C1D3E5F70 butido\n", "0x89ABCDEF").is_err());
+    }
+}